@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use redis_rust::server::rdb;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = rdb::parse(data, 0);
+});
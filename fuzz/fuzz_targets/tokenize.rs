@@ -0,0 +1,15 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use redis_rust::{server::serde::tokenize, RedisValue};
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = BytesMut::from(data);
+
+    // --- drain every complete frame, the same way a connection would
+    while let Ok(Some(tok)) = tokenize(&buf, 0) {
+        let frame = buf.split_to(tok.1).freeze();
+        let _ = RedisValue::from_token(tok.0, &frame);
+    }
+});
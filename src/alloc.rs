@@ -0,0 +1,175 @@
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::Result;
+
+/// Allocator picked at build time, through the `jemalloc` and `mimalloc` features
+#[cfg(feature = "jemalloc")]
+pub type DefaultAllocator = tikv_jemallocator::Jemalloc;
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+pub type DefaultAllocator = mimalloc::MiMalloc;
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+pub type DefaultAllocator = std::alloc::System;
+
+/// `mem_allocator` in INFO
+#[cfg(feature = "jemalloc")]
+pub const ALLOCATOR_NAME: &str = "jemalloc-5.3.0";
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+pub const ALLOCATOR_NAME: &str = "mimalloc";
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+pub const ALLOCATOR_NAME: &str = "libc";
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// Wraps an allocator keeping count of the bytes in use, for `used_memory` and maxmemory.
+/// Only counts when installed as the `#[global_allocator]`
+pub struct TrackingAllocator<A> {
+    inner: A,
+}
+impl<A> TrackingAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+impl TrackingAllocator<DefaultAllocator> {
+    /// The build's allocator, tracked
+    pub const fn default_allocator() -> Self {
+        #[cfg(feature = "jemalloc")]
+        let inner = tikv_jemallocator::Jemalloc;
+        #[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+        let inner = mimalloc::MiMalloc;
+        #[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+        let inner = std::alloc::System;
+
+        Self::new(inner)
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            record_alloc(new_size);
+        }
+
+        new_ptr
+    }
+}
+
+fn record_alloc(size: usize) {
+    let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(allocated, Ordering::Relaxed);
+}
+
+/// Bytes currently allocated, None when `TrackingAllocator` isn't the global allocator
+pub fn allocated() -> Option<usize> {
+    is_tracking().then(|| ALLOCATED.load(Ordering::Relaxed))
+}
+
+/// Most bytes ever allocated at once, None when `TrackingAllocator` isn't the global allocator
+pub fn peak() -> Option<usize> {
+    is_tracking().then(|| PEAK.load(Ordering::Relaxed))
+}
+
+/// Whether allocations are being counted, anything the runtime did allocates
+fn is_tracking() -> bool {
+    PEAK.load(Ordering::Relaxed) > 0
+}
+
+/// What the allocator itself reports, the difference between these is fragmentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocatorStats {
+    /// bytes handed out to the program
+    pub allocated: usize,
+    /// bytes in the pages holding those allocations
+    pub active: usize,
+    /// bytes of physical memory mapped by the allocator
+    pub resident: usize,
+}
+
+/// Fresh allocator stats, only jemalloc exposes them
+#[cfg(feature = "jemalloc")]
+pub fn allocator_stats() -> Option<AllocatorStats> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // --- stats are cached by jemalloc until the epoch moves forward
+    epoch::advance().ok()?;
+    let res = AllocatorStats {
+        allocated: stats::allocated::read().ok()?,
+        active: stats::active::read().ok()?,
+        resident: stats::resident::read().ok()?,
+    };
+
+    Some(res)
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn allocator_stats() -> Option<AllocatorStats> {
+    None
+}
+
+/// Hands the dirty pages of every arena back to the OS, a no-op outside jemalloc
+#[cfg(feature = "jemalloc")]
+pub fn purge() -> Result<()> {
+    // --- MALLCTL_ARENAS_ALL, purge takes neither an old nor a new value
+    let name = b"arena.4096.purge\0";
+    let ret = unsafe {
+        tikv_jemalloc_sys::mallctl(
+            name.as_ptr().cast(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    anyhow::ensure!(ret == 0, "Failure purging allocator arenas: error {}", ret);
+
+    Ok(())
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn purge() -> Result<()> {
+    Ok(())
+}
+
+/// Resident set size of the process, as the OS sees it. Linux only
+pub fn rss() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<usize>()
+        .ok()?;
+
+    Some(kb * 1024)
+}
@@ -0,0 +1,69 @@
+use std::{fs::OpenOptions, path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+use redis_rust::server::aof;
+
+/// Verifies an append-only file, like redis-check-aof
+#[derive(Parser, Debug)]
+struct CheckArgs {
+    /// Truncate the file to the last valid command
+    #[arg(long)]
+    fix: bool,
+    /// AOF file to verify
+    file: PathBuf,
+}
+
+fn main() -> ExitCode {
+    let args = CheckArgs::parse();
+    let data = match std::fs::read(&args.file) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", args.file.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let report = aof::check(&data);
+    if report.rdb_preamble {
+        println!("The AOF appears to start with an RDB preamble");
+    }
+    println!("Commands: {}", report.commands);
+
+    let Some((offset, e)) = report.corruption else {
+        println!("AOF analyzed: size={}, ok_up_to={}", data.len(), data.len());
+        println!("AOF is valid");
+        return ExitCode::SUCCESS;
+    };
+
+    println!("0x{:>16x}: {}", offset, e);
+    println!(
+        "AOF analyzed: size={}, ok_up_to={}, diff={}",
+        data.len(),
+        report.valid_len,
+        data.len() - report.valid_len
+    );
+    if !args.fix {
+        println!("AOF is not valid. Use the --fix option to try fixing it.");
+        return ExitCode::FAILURE;
+    }
+
+    // --- an unreadable preamble can't be fixed by dropping trailing commands
+    if report.rdb_preamble && report.valid_len == 0 {
+        println!("The RDB preamble is corrupt, refusing to truncate");
+        return ExitCode::FAILURE;
+    }
+    let truncated = OpenOptions::new()
+        .write(true)
+        .open(&args.file)
+        .and_then(|file| file.set_len(report.valid_len as u64));
+    match truncated {
+        Ok(()) => {
+            println!("Successfully truncated AOF to {} bytes", report.valid_len);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to truncate AOF: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
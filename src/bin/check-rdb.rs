@@ -0,0 +1,75 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+use redis_rust::{
+    server::rdb::{self, RdbRecord},
+    RedisValue,
+};
+
+/// Verifies the structure of an RDB file, like redis-check-rdb
+#[derive(Parser, Debug)]
+struct CheckArgs {
+    /// RDB file to verify
+    file: PathBuf,
+}
+
+fn main() -> ExitCode {
+    let args = CheckArgs::parse();
+    let data = match std::fs::read(&args.file) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", args.file.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("[offset 0] Checking RDB file {}", args.file.display());
+    let report = rdb::check(&data, |range, record| {
+        println!("[offset {}] {}", range.start, describe(record));
+    });
+
+    if let Some(version) = report.version {
+        println!("RDB version: {}", version);
+    }
+    for (value_type, count) in &report.keys_by_type {
+        println!("keys of type {}: {}", value_type, count);
+    }
+
+    match report.corruption {
+        Some((offset, e)) => {
+            println!("--- RDB ERROR DETECTED ---");
+            println!("[offset {}] {}", offset, e);
+            ExitCode::FAILURE
+        }
+        None => {
+            println!("\\o/ RDB looks OK! \\o/");
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+fn describe(record: &RdbRecord) -> String {
+    match record {
+        RdbRecord::Aux { key, value } => format!("AUX {} = {}", show(key), show(value)),
+        RdbRecord::SelectDb(db) => format!("SELECTDB {}", db),
+        RdbRecord::ResizeDb { keys, expires } => {
+            format!("RESIZEDB keys={} expires={}", keys, expires)
+        }
+        RdbRecord::ExpireTime(ms) => format!("EXPIRETIME_MS {}", ms),
+        RdbRecord::Entry {
+            value_type, key, ..
+        }
+        | RdbRecord::UnsupportedEntry { value_type, key } => {
+            format!("{} {}", rdb::type_name(*value_type), show(key))
+        }
+        RdbRecord::Ignored(opcode) => format!("OPCODE {:#x} (skipped)", opcode),
+        RdbRecord::Eof => "EOF".to_string(),
+    }
+}
+
+fn show(value: &RedisValue) -> String {
+    match value {
+        RedisValue::BulkString(b) => format!("{:?}", String::from_utf8_lossy(b)),
+        other => format!("{:?}", other),
+    }
+}
@@ -0,0 +1,241 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use clap::Parser;
+use redis_rust::{
+    client::RedisClient,
+    repl::replica::{self, MasterConnector},
+    server::split,
+    RedisValue,
+};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use tokio::io::AsyncReadExt;
+
+const HISTORY_FILE: &str = ".redis-rust-cli-history";
+
+#[derive(Parser, Debug)]
+struct CliArgs {
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(short, long, default_value_t = 6379)]
+    port: u16,
+    /// Transfer raw RESP read from stdin to the server
+    #[arg(long)]
+    pipe: bool,
+    /// Fetch the server's dataset through a full sync and save it to this file
+    #[arg(long, value_name = "FILE")]
+    rdb: Option<PathBuf>,
+    /// Command to run instead of starting the interactive prompt, its arguments taken as
+    /// they are even when they start with a hyphen
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = CliArgs::parse();
+    let addr = format!("{}:{}", args.host, args.port);
+
+    if let Some(path) = args.rdb {
+        if let Err(e) = dump_rdb(&args.host, args.port, &path).await {
+            eprintln!("Failure fetching the RDB from {}: {}", addr, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut client = match RedisClient::connect(&addr).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Could not connect to redis-rust at {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    let res = if args.pipe {
+        pipe(client).await
+    } else if !args.command.is_empty() {
+        let cmd = args.command.into_iter().map(Bytes::from);
+        client
+            .command(cmd)
+            .await
+            .map(|reply| println!("{}", format_reply(&reply, 0)))
+    } else {
+        repl(client, &addr).await
+    };
+
+    // --- connected fine, the session failed later on, e.g. the server went away
+    if let Err(e) = res {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn repl(mut client: RedisClient, addr: &str) -> Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = std::env::var("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Ok(history) = &history {
+        let _ = editor.load_history(history);
+    }
+
+    let prompt = format!("{}> ", addr);
+    loop {
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        let args = match split_args(&line) {
+            Ok(args) if args.is_empty() => continue,
+            Ok(args) => args,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        let _ = editor.add_history_entry(line.as_str());
+
+        if args[0].eq_ignore_ascii_case(b"quit") || args[0].eq_ignore_ascii_case(b"exit") {
+            break;
+        }
+
+        let reply = client.command(args.into_iter().map(Bytes::from)).await?;
+        println!("{}", format_reply(&reply, 0));
+    }
+
+    if let Ok(history) = &history {
+        let _ = editor.save_history(history);
+    }
+
+    Ok(())
+}
+
+/// Remote backup mode, asks for a full sync like a replica and saves the transfer
+async fn dump_rdb(host: &str, port: u16, path: &Path) -> Result<()> {
+    let master_addr = format!("{} {}", host, port);
+    let rdb = replica::fetch_rdb(master_addr, &MasterConnector::default()).await?;
+    std::fs::write(path, &rdb)?;
+    eprintln!("Transfer finished with success after {} bytes", rdb.len());
+
+    Ok(())
+}
+
+/// Mass insertion mode, stdin is expected to already be RESP encoded
+async fn pipe(mut client: RedisClient) -> Result<()> {
+    let mut data = vec![];
+    tokio::io::stdin().read_to_end(&mut data).await?;
+
+    // --- an ECHO with a random marker tells us when the last reply arrived
+    let marker = Bytes::from(replica::gen_uuid());
+    let echo = format!(
+        "*2\r\n$4\r\nECHO\r\n${}\r\n{}\r\n",
+        marker.len(),
+        String::from_utf8_lossy(&marker)
+    );
+    client.write_raw(&data).await?;
+    client.write_raw(echo.as_bytes()).await?;
+    eprintln!("All data transferred. Waiting for the last reply...");
+
+    let (mut replies, mut errors) = (0, 0);
+    loop {
+        match client.read_reply().await? {
+            RedisValue::BulkString(b) if b == marker => break,
+            RedisValue::SimpleError(e) => {
+                errors += 1;
+                eprintln!("{}", String::from_utf8_lossy(&e));
+            }
+            _ => {}
+        }
+        replies += 1;
+    }
+
+    eprintln!("Last reply received from server.");
+    println!("errors: {}, replies: {}", errors, replies);
+
+    Ok(())
+}
+
+/// Splits a prompt line into arguments, honoring quotes like redis-cli does
+fn split_args(line: &str) -> Result<Vec<Vec<u8>>> {
+    let line = line.as_bytes();
+    let Some(args) = split::split_args(line) else {
+        bail!("Invalid argument(s)");
+    };
+
+    Ok(args.into_iter().map(|arg| arg.into_bytes(line)).collect())
+}
+
+/// Renders a reply the way redis-cli does in a terminal
+fn format_reply(reply: &RedisValue, indent: usize) -> String {
+    match reply {
+        RedisValue::SimpleString(s) => String::from_utf8_lossy(s).to_string(),
+        RedisValue::SimpleError(e) => format!("(error) {}", String::from_utf8_lossy(e)),
+        RedisValue::Integer(i) => format!("(integer) {}", i),
+        RedisValue::BulkString(b) | RedisValue::Json(b) => quote(b),
+        RedisValue::Counter(n) => quote(n.to_string().as_bytes()),
+        RedisValue::NullBulkString | RedisValue::NullArray => String::from("(nil)"),
+        RedisValue::TimeSeries(_) => String::from("(time series)"),
+        RedisValue::Bloom(_) => String::from("(bloom filter)"),
+        RedisValue::List(_) => String::from("(list)"),
+        RedisValue::Hash(_) => String::from("(hash)"),
+        RedisValue::Set(_) => String::from("(set)"),
+        RedisValue::SortedSet(_) => String::from("(sorted set)"),
+        RedisValue::Stream(_) => String::from("(stream)"),
+        RedisValue::Replies(replies) => replies
+            .iter()
+            .map(|reply| format_reply(reply, indent))
+            .collect::<Vec<String>>()
+            .join("\n"),
+        RedisValue::Double(n) => format!("(double) {}", String::from_utf8_lossy(n)),
+        RedisValue::Boolean(b) => format!("({})", b),
+        RedisValue::BigNumber(n) => format!("(big number) {}", String::from_utf8_lossy(n)),
+        RedisValue::Map(pairs) => format_reply(
+            &RedisValue::Array(
+                pairs
+                    .iter()
+                    .flat_map(|(key, value)| [key.clone(), value.clone()])
+                    .collect(),
+            ),
+            indent,
+        ),
+        RedisValue::Push(items) => format_reply(&RedisValue::Array(items.clone()), indent),
+        RedisValue::Array(arr) if arr.is_empty() => String::from("(empty array)"),
+        RedisValue::Array(arr) => {
+            let width = arr.len().to_string().len();
+            arr.iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let prefix = format!("{:>width$}) ", i + 1, width = width);
+                    let pad = if i == 0 { 0 } else { indent };
+                    format!(
+                        "{}{}{}",
+                        " ".repeat(pad),
+                        prefix,
+                        format_reply(item, indent + prefix.len())
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n")
+        }
+    }
+}
+
+fn quote(data: &[u8]) -> String {
+    let mut res = String::from("\"");
+    for &b in data {
+        match b {
+            b'\\' => res.push_str("\\\\"),
+            b'"' => res.push_str("\\\""),
+            b'\n' => res.push_str("\\n"),
+            b'\r' => res.push_str("\\r"),
+            b'\t' => res.push_str("\\t"),
+            0x07 => res.push_str("\\a"),
+            0x08 => res.push_str("\\b"),
+            b if b.is_ascii_graphic() || b == b' ' => res.push(b as char),
+            b => res.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    res.push('"');
+    res
+}
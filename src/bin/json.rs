@@ -0,0 +1,74 @@
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use redis_rust::server::{db::Db, json, rdb};
+
+/// Converts RDB files to and from the JSON dataset format
+#[derive(Parser, Debug)]
+struct JsonArgs {
+    #[command(subcommand)]
+    command: JsonCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum JsonCommand {
+    /// Prints the dataset of an RDB file as JSON
+    DumpJson {
+        /// RDB file to read
+        rdb: PathBuf,
+        /// File to write the JSON to instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Writes the dataset of a JSON file as an RDB file
+    LoadJson {
+        /// JSON file to read
+        json: PathBuf,
+        /// RDB file to write
+        rdb: PathBuf,
+    },
+}
+
+fn main() -> ExitCode {
+    let args = JsonArgs::parse();
+    let res = match args.command {
+        JsonCommand::DumpJson { rdb, output } => dump_json(&rdb, output.as_ref()),
+        JsonCommand::LoadJson { json, rdb } => load_json(&json, &rdb),
+    };
+
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn dump_json(rdb_path: &Path, output: Option<&PathBuf>) -> Result<()> {
+    let data = std::fs::read(rdb_path)?;
+    // --- every key is kept, an offline dump isn't the place to drop expired ones
+    let dataset = rdb::parse(&data, 0)?;
+    let dump = json::dump(&dataset)?;
+    match output {
+        Some(output) => std::fs::write(output, dump)?,
+        None => println!("{}", dump),
+    }
+
+    Ok(())
+}
+
+fn load_json(json_path: &Path, rdb_path: &Path) -> Result<()> {
+    let dump = std::fs::read_to_string(json_path)?;
+    let dataset = json::load(&dump, 0)?;
+    let data = rdb::serialize(&dataset, None)?;
+    std::fs::write(rdb_path, data)?;
+    let keys = dataset.values().map(Db::len).sum::<usize>();
+    println!("Wrote {} keys to {}", keys, rdb_path.display());
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use bytes::Bytes;
+use clap::Parser;
+use redis_rust::server::record;
+
+/// Feeds a log written with `--record-commands` back into a server
+#[derive(Parser, Debug)]
+struct ReplayArgs {
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(short, long, default_value_t = 6379)]
+    port: u16,
+    /// How many times faster than recorded to go, 0 sends commands without pauses
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+    /// Command log to replay
+    file: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = ReplayArgs::parse();
+    if !(args.speed >= 0.0 && args.speed.is_finite()) {
+        eprintln!("Invalid speed: {}", args.speed);
+        return ExitCode::FAILURE;
+    }
+    let commands = match std::fs::read(&args.file)
+        .map_err(anyhow::Error::from)
+        .and_then(|data| record::parse_log(Bytes::from(data)))
+    {
+        Ok(commands) => commands,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", args.file.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let addr = match tokio::net::lookup_host((args.host.as_str(), args.port))
+        .await
+        .map(|mut addrs| addrs.next())
+    {
+        Ok(Some(addr)) => addr,
+        Ok(None) => {
+            eprintln!("No address found for {}", args.host);
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("Cannot resolve {}: {}", args.host, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("Replaying {} commands to {}", commands.len(), addr);
+    match record::replay(commands, addr, args.speed).await {
+        Ok(report) => {
+            println!(
+                "Replayed {} commands, {} errors",
+                report.commands, report.errors
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Replay failed: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{bail, Result};
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use crate::server::{
+    handler::RedisValue,
+    serde::{ProtocolLimits, RespParser},
+};
+
+/// Async client speaking RESP to a redis server
+pub struct RedisClient {
+    stream: TcpStream,
+    buffer: BytesMut,
+    /// state of the reply being parsed from `buffer`
+    parser: RespParser,
+}
+impl RedisClient {
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+
+        Ok(Self {
+            stream,
+            buffer: BytesMut::with_capacity(512),
+            parser: RespParser::default(),
+        })
+    }
+
+    /// Sends a raw command, e.g. `["SET", "foo", "bar"]`, and returns the reply as is
+    pub async fn command<I, T>(&mut self, cmd: I) -> Result<RedisValue>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<Bytes>,
+    {
+        let request = build_request(cmd);
+        self.stream.write_all(&request.serialize()?).await?;
+
+        self.read_reply().await
+    }
+
+    pub fn pipeline(&mut self) -> Pipeline<'_> {
+        Pipeline {
+            client: self,
+            commands: vec![],
+        }
+    }
+
+    pub async fn ping(&mut self) -> Result<()> {
+        match self.command(["PING"]).await? {
+            RedisValue::SimpleString(_) => Ok(()),
+            other => unexpected_reply(other),
+        }
+    }
+
+    pub async fn get(&mut self, key: impl Into<Bytes>) -> Result<Option<Bytes>> {
+        match self
+            .command([Bytes::from_static(b"GET"), key.into()])
+            .await?
+        {
+            RedisValue::BulkString(value) => Ok(Some(value)),
+            RedisValue::NullBulkString => Ok(None),
+            other => unexpected_reply(other),
+        }
+    }
+
+    pub async fn set(&mut self, key: impl Into<Bytes>, value: impl Into<Bytes>) -> Result<()> {
+        let cmd = [Bytes::from_static(b"SET"), key.into(), value.into()];
+        match self.command(cmd).await? {
+            RedisValue::SimpleString(_) => Ok(()),
+            other => unexpected_reply(other),
+        }
+    }
+
+    /// Deletes the given keys, returning how many of them existed
+    pub async fn del<I, T>(&mut self, keys: I) -> Result<i64>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<Bytes>,
+    {
+        let cmd =
+            std::iter::once(Bytes::from_static(b"DEL")).chain(keys.into_iter().map(Into::into));
+        match self.command(cmd).await? {
+            RedisValue::Integer(deleted) => Ok(deleted),
+            other => unexpected_reply(other),
+        }
+    }
+
+    /// Writes already-encoded protocol data, replies have to be read back with `read_reply`
+    pub async fn write_raw(&mut self, data: &[u8]) -> Result<()> {
+        self.stream.write_all(data).await?;
+
+        Ok(())
+    }
+
+    /// Reads from the stream until a complete reply is buffered
+    pub async fn read_reply(&mut self) -> Result<RedisValue> {
+        loop {
+            // --- replies are trusted, whatever their size
+            if let Some(tok) = self
+                .parser
+                .parse(&self.buffer, &ProtocolLimits::unbounded())?
+            {
+                let data = self.buffer.split_to(tok.1).freeze();
+                return Ok(RedisValue::from_token(tok.0, &data));
+            }
+
+            let bytes_read = self.stream.read_buf(&mut self.buffer).await?;
+            if bytes_read == 0 {
+                bail!("Connection closed by server");
+            }
+        }
+    }
+}
+
+/// Batch of commands sent in a single write, with replies read back in order
+pub struct Pipeline<'a> {
+    client: &'a mut RedisClient,
+    commands: Vec<RedisValue>,
+}
+impl Pipeline<'_> {
+    pub fn cmd<I, T>(&mut self, cmd: I) -> &mut Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<Bytes>,
+    {
+        self.commands.push(build_request(cmd));
+        self
+    }
+
+    pub async fn execute(self) -> Result<Vec<RedisValue>> {
+        let count = self.commands.len();
+        let mut raw_data = BytesMut::new();
+        for cmd in self.commands {
+            raw_data.extend_from_slice(&cmd.serialize()?);
+        }
+        self.client.stream.write_all(&raw_data).await?;
+
+        let mut replies = Vec::with_capacity(count);
+        for _ in 0..count {
+            replies.push(self.client.read_reply().await?);
+        }
+
+        Ok(replies)
+    }
+}
+
+fn build_request<I, T>(cmd: I) -> RedisValue
+where
+    I: IntoIterator<Item = T>,
+    T: Into<Bytes>,
+{
+    RedisValue::Array(
+        cmd.into_iter()
+            .map(|m| RedisValue::BulkString(m.into()))
+            .collect(),
+    )
+}
+
+fn unexpected_reply<T>(reply: RedisValue) -> Result<T> {
+    match reply {
+        RedisValue::SimpleError(e) => bail!("{}", String::from_utf8_lossy(&e)),
+        other => bail!("Unexpected reply from server: {:?}", other),
+    }
+}
@@ -0,0 +1,74 @@
+use core::str;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+use crate::server::{
+    clock::{Clock, SystemClock},
+    commands::{execute, CommandContext},
+    events::KeyspaceEvent,
+    handler::RedisValue,
+    server::RedisServer,
+    session::Session,
+};
+
+/// In-process handle to a server, running commands directly against the store without
+/// sockets. A handle is one logical connection, a clone opens another one on the same server
+pub struct Redis {
+    server: Arc<RedisServer>,
+    session: Session,
+}
+impl Clone for Redis {
+    fn clone(&self) -> Self {
+        Self {
+            server: Arc::clone(&self.server),
+            session: self.server.new_session(),
+        }
+    }
+}
+impl Redis {
+    pub fn open_in_memory() -> Self {
+        Redis::open_in_memory_with_clock(Arc::new(SystemClock))
+    }
+
+    /// In-memory instance reading time from the given clock, so expiry can be driven by hand
+    pub fn open_in_memory_with_clock(clock: Arc<dyn Clock>) -> Self {
+        let server = RedisServer::in_memory(clock);
+        let session = server.new_session();
+
+        Self { server, session }
+    }
+
+    /// Runs a command given as its name followed by its arguments, e.g. `["SET", "foo", "bar"]`
+    pub async fn execute<I, T>(&mut self, cmd: I) -> Result<RedisValue>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<Bytes>,
+    {
+        let mut parts = cmd.into_iter().map(Into::into);
+        let name = parts
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Empty command"))?;
+        let args: Vec<Bytes> = parts.collect();
+
+        let mut ctx = CommandContext {
+            args: &args,
+            server: &self.server,
+            session: &mut self.session,
+        };
+
+        execute(str::from_utf8(&name)?, &mut ctx).await
+    }
+
+    /// Writes, deletions and expirations from now on, whichever handle or connection
+    /// caused them
+    pub fn keyspace_events(&self) -> broadcast::Receiver<KeyspaceEvent> {
+        self.server.keyspace_events.subscribe()
+    }
+
+    pub fn server(&self) -> &Arc<RedisServer> {
+        &self.server
+    }
+}
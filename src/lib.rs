@@ -0,0 +1,368 @@
+use std::num::NonZeroUsize;
+
+use clap::{Parser, ValueEnum};
+
+pub mod alloc;
+pub mod client;
+pub mod embedded;
+pub mod repl;
+pub mod server;
+
+pub use client::RedisClient;
+pub use embedded::Redis;
+pub use server::{
+    builder::ServerBuilder,
+    handler::{RedisConnectionHandler, RedisValue},
+    serde::{Protocol, ProtocolError, ProtocolLimits},
+    server::RedisServer,
+};
+pub use tokio_util::sync::CancellationToken;
+
+/// Later flags override earlier ones, so the command line wins over the config file
+#[derive(Parser, Debug, Default)]
+#[command(args_override_self = true)]
+pub struct Args {
+    /// redis.conf-style file to read settings from, flags given next to it taking precedence
+    pub config_file: Option<String>,
+    /// arguments the server was started with, still taking precedence when the config
+    /// file is read again
+    #[arg(skip)]
+    pub command_line: Vec<String>,
+    #[arg(long)]
+    pub dir: Option<String>,
+    #[arg(long)]
+    pub dbfilename: Option<String>,
+    /// number of databases SELECT can switch between, 16 unless given
+    #[arg(long)]
+    pub databases: Option<usize>,
+    /// snapshotting points as "<seconds> <changes> ...", "" disables them
+    #[arg(long)]
+    pub save: Option<String>,
+    /// log every write to an append-only file and load the dataset from it (yes/no)
+    #[arg(long, value_parser = clap::builder::BoolishValueParser::new())]
+    pub appendonly: Option<bool>,
+    /// name of the append-only file, within `dir`
+    #[arg(long)]
+    pub appendfilename: Option<String>,
+    /// when the append-only file is synced to disk: always, everysec or no
+    #[arg(long)]
+    pub appendfsync: Option<String>,
+    /// what a SIGTERM does with the dataset: any of default, save, nosave, now and force
+    #[arg(long)]
+    pub shutdown_on_sigterm: Option<String>,
+    /// what a SIGINT does with the dataset, same flags as `shutdown-on-sigterm`
+    #[arg(long)]
+    pub shutdown_on_sigint: Option<String>,
+    /// seconds a shutdown gives replicas to catch up with the replication stream
+    #[arg(long)]
+    pub shutdown_timeout: Option<u64>,
+    /// dataset size limit, e.g. "100mb", 0 means no limit
+    #[arg(long)]
+    pub maxmemory: Option<String>,
+    /// per-database dataset limits as "<db> <bytes> ...", a database past its own limit
+    /// evicting its keys even while the server is under maxmemory
+    #[arg(long)]
+    pub maxmemory_db: Option<String>,
+    /// which keys get evicted once maxmemory is reached, e.g. allkeys-lfu
+    #[arg(long)]
+    pub maxmemory_policy: Option<String>,
+    /// how slowly the LFU counter grows with accesses
+    #[arg(long)]
+    pub lfu_log_factor: Option<u32>,
+    /// minutes without access for the LFU counter to decay by one, 0 disables decay
+    #[arg(long)]
+    pub lfu_decay_time: Option<u64>,
+    /// how expired keys are removed: lazy (when accessed) or precise (right when they expire)
+    #[arg(long)]
+    pub expiry_mode: Option<String>,
+    /// how many times per second background jobs run, between 1 and 500
+    #[arg(long)]
+    pub hz: Option<u64>,
+    #[arg(long)]
+    pub port: Option<usize>,
+    /// addresses to listen on, 127.0.0.1 unless given. "*" stands for every IPv4
+    /// interface, and a "-" prefix marks an address that may be unavailable
+    #[arg(long, num_args = 1..)]
+    pub bind: Vec<String>,
+    /// path of a unix socket to listen on as well
+    #[arg(long)]
+    pub unixsocket: Option<String>,
+    /// permissions of the unix socket file, in octal e.g. "700"
+    #[arg(long)]
+    pub unixsocketperm: Option<String>,
+    #[arg(long)]
+    pub replicaof: Option<String>,
+    /// seconds without anything from the master before a replica drops the link
+    #[arg(long)]
+    pub repl_timeout: Option<u64>,
+    /// address a replica advertises to its master instead of the one it connects from
+    #[arg(long)]
+    pub replica_announce_ip: Option<String>,
+    /// port a replica advertises to its master instead of the one it listens on
+    #[arg(long)]
+    pub replica_announce_port: Option<u16>,
+    /// whether a replica keeps answering reads while its master link is down (yes/no)
+    #[arg(long, value_parser = clap::builder::BoolishValueParser::new())]
+    pub replica_serve_stale_data: Option<bool>,
+    /// whether a replica refuses writes from its clients, only taking them from its master
+    /// (yes/no)
+    #[arg(long, value_parser = clap::builder::BoolishValueParser::new())]
+    pub replica_read_only: Option<bool>,
+    /// seconds before probing idle connections for dead peers, 0 disables keepalive
+    #[arg(long)]
+    pub tcp_keepalive: Option<u64>,
+    /// connections open at the same time across all clients, memcached and HTTP ones
+    /// included, 0 for no limit
+    #[arg(long)]
+    pub maxclients: Option<usize>,
+    /// connections a single IP may keep open at the same time, 0 for no limit
+    #[arg(long)]
+    pub max_clients_per_ip: Option<usize>,
+    /// new connections a single IP may open per second, 0 for no limit
+    #[arg(long)]
+    pub max_connection_rate_per_ip: Option<u64>,
+    /// send small replies right away instead of batching them (Nagle's algorithm)
+    #[arg(long)]
+    pub tcp_nodelay: Option<bool>,
+    /// tokio scheduler to run the server on
+    #[arg(long, value_enum)]
+    pub runtime: Option<RuntimeFlavor>,
+    /// worker threads of the multi-thread runtime, defaults to one per core
+    #[arg(long)]
+    pub worker_threads: Option<NonZeroUsize>,
+    /// upper bound on threads for blocking work such as RDB encoding
+    #[arg(long)]
+    pub max_blocking_threads: Option<NonZeroUsize>,
+    /// largest bulk string a client may send, in bytes
+    #[arg(long)]
+    pub proto_max_bulk_len: Option<usize>,
+    /// largest number of elements in a client request
+    #[arg(long)]
+    pub proto_max_multibulk_len: Option<usize>,
+    /// largest amount of unparsed data buffered per client, in bytes
+    #[arg(long)]
+    pub client_query_buffer_limit: Option<usize>,
+    /// pending output limits as "<class> <hard> <soft> <soft seconds> ...", classes
+    /// being normal, replica and pubsub
+    #[arg(long)]
+    pub client_output_buffer_limit: Option<String>,
+    /// work a connection does back to back before giving other clients a turn, a command
+    /// counting as one plus one per key it scans, 0 never gives way
+    #[arg(long)]
+    pub client_fairness_budget: Option<usize>,
+    /// makes a command reachable only under a new name, an empty name disables it.
+    /// May be repeated
+    #[arg(long, num_args = 2, value_names = ["COMMAND", "NEW_NAME"])]
+    pub rename_command: Vec<String>,
+    /// turns on what this server has that Redis doesn't: SET's IFEQ option and DELIFEQ
+    #[arg(long)]
+    pub enable_extensions: bool,
+    /// answer CLUSTER commands as the single node of a cluster owning every hash slot
+    #[arg(long)]
+    pub cluster_enabled: bool,
+    /// run as a Sentinel: no dataset, only the commands needed to watch the
+    /// `--supervise-master` and answer clients looking for it
+    #[arg(long)]
+    pub sentinel: bool,
+    /// "<host> <port>" of a master to watch, failing over to one of the
+    /// `--supervise-replica` servers when it goes down
+    #[arg(long)]
+    pub supervise_master: Option<String>,
+    /// name the supervised master is looked up by, "mymaster" by default
+    #[arg(long)]
+    pub supervise_master_name: Option<String>,
+    /// "<host> <port>" of a replica the supervisor may promote or repoint. May be repeated
+    #[arg(long)]
+    pub supervise_replica: Vec<String>,
+    /// "<host> <port>" of another sentinel watching the same master. May be repeated
+    #[arg(long)]
+    pub sentinel_peer: Vec<String>,
+    /// how long the supervised master has to be unreachable to be considered down
+    #[arg(long)]
+    pub down_after_milliseconds: Option<u64>,
+    /// observers that have to agree the supervised master is down before failing over
+    #[arg(long)]
+    pub quorum: Option<usize>,
+    /// file every accepted command is appended to, for `replay`
+    #[arg(long)]
+    pub record_commands: Option<String>,
+    /// file audited commands are logged to, or "syslog"
+    #[arg(long)]
+    pub audit_log: Option<String>,
+    /// which commands get audited, any of write and admin separated by commas
+    #[arg(long)]
+    pub audit_commands: Option<String>,
+    /// password clients have to AUTH with before running commands, the default user's
+    #[arg(long)]
+    pub requirepass: Option<String>,
+    /// how many denied commands and authentication failures ACL LOG keeps
+    #[arg(long)]
+    pub acllog_max_len: Option<usize>,
+    /// microseconds a command has to run for to get into SLOWLOG, 0 logs every command and
+    /// a negative value none
+    #[arg(long, allow_negative_numbers = true)]
+    pub slowlog_log_slower_than: Option<i64>,
+    /// how many slow commands SLOWLOG keeps
+    #[arg(long)]
+    pub slowlog_max_len: Option<usize>,
+    /// seconds a client may stay idle before it's disconnected, 0 for never
+    #[arg(long)]
+    pub timeout: Option<u64>,
+    /// milliseconds a script may run before other clients get -BUSY replies and SCRIPT
+    /// KILL may stop it
+    #[arg(long)]
+    pub busy_reply_threshold: Option<u64>,
+    /// whether scripts replicate the writes they made rather than themselves as EVAL
+    /// (yes/no)
+    #[arg(long, value_parser = clap::builder::BoolishValueParser::new())]
+    pub lua_replicate_commands: Option<bool>,
+    /// keyspace events to publish over pub/sub, as Redis' flags: "K" and "E" for the
+    /// keyspace and keyevent channels, then classes such as "g$x" or "A" for all
+    #[arg(long)]
+    pub notify_keyspace_events: Option<String>,
+    /// most fields a hash keeps in a listpack before turning into a hash table
+    #[arg(long)]
+    pub hash_max_listpack_entries: Option<usize>,
+    /// longest field or value, in bytes, a hash keeps in a listpack
+    #[arg(long)]
+    pub hash_max_listpack_value: Option<usize>,
+    /// most integers a set of them keeps in an intset
+    #[arg(long)]
+    pub set_max_intset_entries: Option<usize>,
+    /// most members a set keeps in a listpack before turning into a hash table
+    #[arg(long)]
+    pub set_max_listpack_entries: Option<usize>,
+    /// longest member, in bytes, a set keeps in a listpack
+    #[arg(long)]
+    pub set_max_listpack_value: Option<usize>,
+    /// most members a sorted set keeps in a listpack
+    #[arg(long)]
+    pub zset_max_listpack_entries: Option<usize>,
+    /// longest member, in bytes, a sorted set keeps in a listpack
+    #[arg(long)]
+    pub zset_max_listpack_value: Option<usize>,
+    /// most elements of a list listpack when positive, -1 to -5 for 4 to 64 kb of them
+    #[arg(long, allow_negative_numbers = true)]
+    pub list_max_listpack_size: Option<i64>,
+    /// port of a second listener speaking the memcached text protocol
+    #[cfg(feature = "memcached")]
+    #[arg(long)]
+    pub memcached_port: Option<u16>,
+    /// port of the HTTP gateway
+    #[cfg(feature = "http")]
+    #[arg(long)]
+    pub http_port: Option<u16>,
+    /// agree on writes and reads with the `--raft-peer` servers through a Raft log, instead of
+    /// replicating asynchronously. Experimental
+    #[cfg(feature = "raft")]
+    #[arg(long)]
+    pub raft: bool,
+    /// "<host> <port>" of another member of the raft cluster. May be repeated
+    #[cfg(feature = "raft")]
+    #[arg(long)]
+    pub raft_peer: Vec<String>,
+    /// secret shared by the members of the raft cluster, which they authenticate to each
+    /// other with. Required along with `--raft`
+    #[cfg(feature = "raft")]
+    #[arg(long)]
+    pub raft_secret: Option<String>,
+    /// milliseconds without a leader before a raft node runs for election, randomized up
+    /// to twice as much
+    #[cfg(feature = "raft")]
+    #[arg(long)]
+    pub raft_election_timeout: Option<u64>,
+    /// file under `--dir` keeping the raft node ID, term and vote across restarts
+    #[cfg(feature = "raft")]
+    #[arg(long)]
+    pub raft_state_file: Option<String>,
+    /// entries applied after which a raft node snapshots the dataset and drops them from
+    /// its log, 0 keeping the whole log
+    #[cfg(feature = "raft")]
+    #[arg(long)]
+    pub raft_snapshot_entries: Option<usize>,
+    /// port taking TLS client connections, next to the plain `--port`
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    pub tls_port: Option<u16>,
+    /// PEM certificate chain presented on the TLS port
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    pub tls_cert_file: Option<String>,
+    /// PEM private key of `--tls-cert-file`
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    pub tls_key_file: Option<String>,
+    /// PEM certificates a TLS master's certificate is checked against
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    pub tls_ca_cert_file: Option<String>,
+    /// connect to the master over TLS, on its TLS port
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    pub tls_replication: bool,
+    /// whether TLS clients have to present a certificate signed by `--tls-ca-cert-file`:
+    /// no, optional or yes
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    pub tls_auth_clients: Option<String>,
+    /// name of the client certificate that is the ACL user the connection runs as, without
+    /// AUTH: off, CN or SAN
+    #[cfg(feature = "tls")]
+    #[arg(long)]
+    pub tls_auth_clients_user: Option<String>,
+    /// shared library adding custom commands. May be repeated
+    #[cfg(feature = "plugins")]
+    #[arg(long)]
+    pub load_plugin: Vec<String>,
+}
+
+impl Args {
+    /// Command line arguments on top of the settings of the config file they name, if any
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_from(std::env::args())
+    }
+
+    /// Same as `load`, with the program name and arguments given
+    pub fn load_from(cli: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let cli = cli.into_iter().collect::<Vec<_>>();
+        let mut args = Self::parse_from(&cli);
+        args.command_line = cli.iter().skip(1).cloned().collect();
+        let Some(path) = &args.config_file else {
+            return Ok(args);
+        };
+        let file_flags = server::config::config_file_flags(path)?;
+
+        let mut res = Self::parse_from(
+            cli.iter()
+                .take(1)
+                .chain(file_flags.iter())
+                .chain(cli.iter().skip(1)),
+        );
+        res.command_line = args.command_line;
+
+        Ok(res)
+    }
+
+    /// The config file read again, with the arguments given at startup on top. Invalid
+    /// settings are errors rather than exiting like at startup
+    pub fn reload(path: &str, command_line: &[String]) -> anyhow::Result<Self> {
+        let file_flags = server::config::config_file_flags(path)?;
+
+        let mut res = Self::try_parse_from(
+            std::iter::once("redis-rust")
+                .chain(file_flags.iter().map(String::as_str))
+                .chain(command_line.iter().map(String::as_str)),
+        )?;
+        res.command_line = command_line.to_vec();
+
+        Ok(res)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuntimeFlavor {
+    /// everything on the main thread
+    CurrentThread,
+    /// work-stealing pool of worker threads
+    MultiThread,
+}
@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+
+/// Circular buffer holding the tail of the replication stream, so a replica that
+/// briefly lost its link can resume with PSYNC instead of a full resync.
+/// Offsets follow Redis: the first byte ever fed has offset 1
+#[derive(Debug)]
+pub struct ReplBacklog {
+    buffer: VecDeque<u8>,
+    capacity: usize,
+    /// total number of bytes fed, i.e. the offset of the last byte held
+    offset: usize,
+    /// database the stream has selected at `offset`, `None` when unknown so the next
+    /// write goes out behind a SELECT
+    stream_db: Option<usize>,
+}
+impl Default for ReplBacklog {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_SIZE)
+    }
+}
+impl ReplBacklog {
+    /// `repl-backlog-size` default
+    pub const DEFAULT_SIZE: usize = 1024 * 1024;
+
+    pub fn new(capacity: usize) -> Self {
+        Self::starting_at(capacity, 0)
+    }
+
+    /// Empty backlog picking up the stream at `offset`, e.g. after a full resync
+    pub fn starting_at(capacity: usize, offset: usize) -> Self {
+        Self {
+            buffer: VecDeque::new(),
+            capacity,
+            offset,
+            stream_db: None,
+        }
+    }
+
+    /// Appends data sent to replicas, dropping the oldest bytes past the capacity
+    pub fn feed(&mut self, data: &[u8]) {
+        self.offset += data.len();
+
+        let data = &data[data.len().saturating_sub(self.capacity)..];
+        let overflow = (self.buffer.len() + data.len()).saturating_sub(self.capacity);
+        self.buffer.drain(..overflow);
+        self.buffer.extend(data);
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn stream_db(&self) -> Option<usize> {
+        self.stream_db
+    }
+
+    /// Records the database the stream switched to, by a SELECT fed or read from the master
+    pub fn set_stream_db(&mut self, db: Option<usize>) {
+        self.stream_db = db;
+    }
+
+    /// Everything from `from` up to the current offset, or `None` if part of that
+    /// range was already dropped. `from` is the offset of the first byte wanted
+    pub fn range_from(&self, from: usize) -> Option<Vec<u8>> {
+        let first_held = self.offset + 1 - self.buffer.len();
+        if from < first_held || from > self.offset + 1 {
+            return None;
+        }
+
+        let res = self.buffer.range(from - first_held..).copied().collect();
+
+        Some(res)
+    }
+}
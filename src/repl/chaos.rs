@@ -0,0 +1,76 @@
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+
+/// Faults injected into the replica side of replication, armed through DEBUG subcommands.
+/// Only built with the `chaos` feature, so partial resyncs and failovers can be exercised
+/// in tests
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    /// milliseconds to wait before applying each frame from the master
+    link_latency: AtomicU64,
+    /// milliseconds to wait before answering a GETACK
+    ack_delay: AtomicU64,
+    /// bytes of the incoming stream still to be corrupted
+    corrupt_bytes: AtomicUsize,
+}
+impl FaultInjector {
+    pub fn set_link_latency(&self, ms: u64) {
+        self.link_latency.store(ms, Ordering::Relaxed);
+    }
+
+    pub fn set_ack_delay(&self, ms: u64) {
+        self.ack_delay.store(ms, Ordering::Relaxed);
+    }
+
+    /// Corrupts the next `n` bytes received from the master, on top of any still pending
+    pub fn corrupt_next(&self, n: usize) {
+        self.corrupt_bytes.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Disarms every fault
+    pub fn reset(&self) {
+        self.set_link_latency(0);
+        self.set_ack_delay(0);
+        self.corrupt_bytes.store(0, Ordering::Relaxed);
+    }
+
+    pub async fn link_latency(&self) {
+        sleep_ms(self.link_latency.load(Ordering::Relaxed)).await;
+    }
+
+    pub async fn ack_delay(&self) {
+        sleep_ms(self.ack_delay.load(Ordering::Relaxed)).await;
+    }
+
+    /// Flips the bits of as many leading bytes of a frame as are still due to be
+    /// corrupted, `None` when the frame goes through untouched
+    pub fn corrupt(&self, frame: &[u8]) -> Option<Bytes> {
+        let mut taken = 0;
+        let _ = self
+            .corrupt_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pending| {
+                taken = pending.min(frame.len());
+                Some(pending - taken)
+            });
+        if taken == 0 {
+            return None;
+        }
+
+        let mut res = BytesMut::from(frame);
+        for byte in res[..taken].iter_mut() {
+            *byte = !*byte;
+        }
+
+        Some(res.freeze())
+    }
+}
+
+async fn sleep_ms(ms: u64) {
+    if ms > 0 {
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+    }
+}
@@ -1,17 +1,188 @@
-use super::replica::gen_uuid;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{futures::Notified, Notify};
+
+use crate::server::rdb::ReplInfo;
+
+use super::{backlog::ReplBacklog, replica::gen_uuid};
 
 #[derive(Clone, Debug)]
 pub struct RedisMasterContext {
     /// master replication ID
     pub master_replid: String,
-    /// offset into the circluar replication buffer
-    pub master_repl_offset: usize,
+    /// tail of the replication stream, its offset is the master replication offset
+    pub backlog: Arc<Mutex<ReplBacklog>>,
+    /// replication ID this server had before its last promotion
+    pub master_replid2: Option<String>,
+    /// first offset not valid for `master_replid2` anymore
+    pub second_repl_offset: Option<usize>,
+}
+impl Default for RedisMasterContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl RedisMasterContext {
     pub fn new() -> Self {
         Self {
             master_replid: gen_uuid(),
-            master_repl_offset: 0,
+            backlog: Arc::new(Mutex::new(ReplBacklog::default())),
+            master_replid2: None,
+            second_repl_offset: None,
         }
     }
+
+    /// Master resuming the replication history its dataset was saved with, so its
+    /// replicas can partially resync after a restart
+    pub fn restored(repl_info: &ReplInfo) -> Self {
+        Self {
+            master_replid: repl_info.replid.clone(),
+            backlog: Arc::new(Mutex::new(ReplBacklog::starting_at(
+                ReplBacklog::DEFAULT_SIZE,
+                repl_info.offset,
+            ))),
+            master_replid2: None,
+            second_repl_offset: None,
+        }
+    }
+
+    pub fn repl_offset(&self) -> usize {
+        self.backlog.lock().unwrap().offset()
+    }
+
+    /// Whether a replica asking to resume `replid` at `offset` shares our history
+    pub fn can_continue(&self, replid: &[u8], offset: usize) -> bool {
+        let is_replid2 = self.master_replid2.as_deref().map(str::as_bytes) == Some(replid)
+            && self
+                .second_repl_offset
+                .is_some_and(|second| offset <= second);
+
+        replid == self.master_replid.as_bytes() || is_replid2
+    }
+}
+
+/// Where a replica says it can be reached, from its REPLCONF listening-port and
+/// ip-address, falling back to the address of its connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplicaEndpoint {
+    pub ip: String,
+    pub port: u16,
+    /// offset the replica was synced to, then the last one it acknowledged
+    pub offset: usize,
+    /// capabilities from its REPLCONF capa, e.g. `eof` and `psync2`
+    pub capa: Vec<String>,
+}
+
+/// A replica that went through PSYNC on one of our connections
+#[derive(Debug)]
+struct ConnectedReplica {
+    endpoint: ReplicaEndpoint,
+    /// replication stream its connection didn't send yet, e.g. writes made while the
+    /// replica was receiving the RDB payload of its full resync
+    pending: Vec<u8>,
+    /// wakes the connection up to send `pending`
+    notify: Arc<Notify>,
+}
+
+/// Replicas that went through PSYNC on one of our connections
+#[derive(Debug, Default)]
+pub struct ConnectedReplicas {
+    /// by client ID
+    replicas: Mutex<BTreeMap<u64, ConnectedReplica>>,
+    /// wakes up WAIT every time a replica acknowledges an offset
+    acks: Notify,
+}
+impl ConnectedReplicas {
+    /// Starts buffering the replication stream for a replica. Register with the backlog
+    /// locked, so everything fed after the offset the replica syncs to reaches it once
+    pub fn register(&self, client_id: u64, endpoint: ReplicaEndpoint) -> Arc<Notify> {
+        let notify = Arc::new(Notify::new());
+        let replica = ConnectedReplica {
+            endpoint,
+            pending: Vec::new(),
+            notify: Arc::clone(&notify),
+        };
+        self.replicas.lock().unwrap().insert(client_id, replica);
+
+        notify
+    }
+
+    pub fn remove(&self, client_id: u64) {
+        self.replicas.lock().unwrap().remove(&client_id);
+    }
+
+    /// Records the offset a replica processed the stream up to, `REPLCONF ACK`
+    pub fn ack(&self, client_id: u64, offset: usize) {
+        if let Some(replica) = self.replicas.lock().unwrap().get_mut(&client_id) {
+            replica.endpoint.offset = replica.endpoint.offset.max(offset);
+        }
+        self.acks.notify_waiters();
+    }
+
+    /// Number of replicas that acknowledged the stream up to `offset`
+    pub fn acked_count(&self, offset: usize) -> usize {
+        self.replicas
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|replica| replica.endpoint.offset >= offset)
+            .count()
+    }
+
+    /// Resolves on the next acknowledgement of any replica. Enable it before checking
+    /// `acked_count` so an ACK in between isn't missed
+    pub fn next_ack(&self) -> Notified<'_> {
+        self.acks.notified()
+    }
+
+    /// Connected replicas, oldest connection first
+    pub fn list(&self) -> Vec<ReplicaEndpoint> {
+        self.replicas
+            .lock()
+            .unwrap()
+            .values()
+            .map(|replica| replica.endpoint.clone())
+            .collect()
+    }
+
+    /// Appends a command run against database `db` to the replication stream, i.e.
+    /// `backlog` and the pending buffer of every replica, behind a SELECT when the stream
+    /// has another database selected. `None` for commands about no database
+    pub fn propagate(&self, backlog: &Mutex<ReplBacklog>, db: Option<usize>, data: &[u8]) {
+        let mut backlog = backlog.lock().unwrap();
+        let mut replicas = self.replicas.lock().unwrap();
+        if let Some(db) = db.filter(|db| backlog.stream_db() != Some(*db)) {
+            let select = select_frame(db);
+            backlog.feed(&select);
+            backlog.set_stream_db(Some(db));
+            for replica in replicas.values_mut() {
+                replica.pending.extend_from_slice(&select);
+            }
+        }
+        backlog.feed(data);
+        for replica in replicas.values_mut() {
+            replica.pending.extend_from_slice(data);
+            replica.notify.notify_one();
+        }
+    }
+
+    /// Stream buffered for a replica since the last call, to be sent to it
+    pub fn take_pending(&self, client_id: u64) -> Vec<u8> {
+        self.replicas
+            .lock()
+            .unwrap()
+            .get_mut(&client_id)
+            .map(|replica| std::mem::take(&mut replica.pending))
+            .unwrap_or_default()
+    }
+}
+
+/// `SELECT <db>` as RESP, switching the database of the replication stream
+fn select_frame(db: usize) -> Vec<u8> {
+    let db = db.to_string();
+
+    format!("*2\r\n$6\r\nSELECT\r\n${}\r\n{}\r\n", db.len(), db).into_bytes()
 }
@@ -1,9 +1,19 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use master::RedisMasterContext;
-use replica::RedisReplicaContext;
+use replica::{gen_uuid, MasterConnector, MasterLink, RedisReplicaContext, ReplicaAnnounce};
+
+use crate::server::rdb::ReplInfo;
 
+pub mod backlog;
+#[cfg(feature = "chaos")]
+pub mod chaos;
 pub mod master;
+#[cfg(feature = "raft")]
+pub mod raft;
 pub mod replica;
+pub mod supervisor;
 
 #[derive(Clone, Debug)]
 pub enum ServerContext {
@@ -11,11 +21,28 @@ pub enum ServerContext {
     Replica(RedisReplicaContext),
 }
 impl ServerContext {
-    pub async fn new(replica_of: Option<String>, port: usize) -> Result<Self> {
-        let server_context = match replica_of {
-            None => Self::Master(RedisMasterContext::new()),
-            Some(master_addr) => {
-                Self::Replica(RedisReplicaContext::connect(port, master_addr).await?)
+    /// Sets up the replication role, picking up the history the local dataset was saved
+    /// with if any. For replicas this also returns the link to the master
+    pub async fn new(
+        replica_of: Option<String>,
+        announce: ReplicaAnnounce,
+        connector: MasterConnector,
+        repl_info: Option<ReplInfo>,
+    ) -> Result<(Self, Option<MasterLink>)> {
+        let server_context = match (replica_of, repl_info) {
+            (None, None) => (Self::Master(RedisMasterContext::new()), None),
+            (None, Some(repl_info)) => {
+                (Self::Master(RedisMasterContext::restored(&repl_info)), None)
+            }
+            (Some(master_addr), repl_info) => {
+                let (ctx, link) = RedisReplicaContext::connect(
+                    &announce,
+                    master_addr,
+                    &connector,
+                    repl_info.as_ref(),
+                )
+                .await?;
+                (Self::Replica(ctx), Some(link))
             }
         };
 
@@ -26,10 +53,67 @@ impl ServerContext {
         matches!(self, Self::Master(_))
     }
 
+    /// Replication history the current dataset belongs to, saved along with snapshots
+    pub fn repl_info(&self) -> ReplInfo {
+        let (replid, backlog) = match self {
+            Self::Master(ctx) => (&ctx.master_replid, &ctx.backlog),
+            Self::Replica(ctx) => (&ctx.master_replid, &ctx.backlog),
+        };
+        let backlog = backlog.lock().unwrap();
+
+        ReplInfo {
+            replid: replid.clone(),
+            offset: backlog.offset(),
+            stream_db: backlog.stream_db().unwrap_or(0),
+        }
+    }
+
     pub fn get_master_replid(&self) -> &str {
         match self {
             Self::Master(ctx) => &ctx.master_replid,
             Self::Replica(ctx) => &ctx.master_replid,
         }
     }
+
+    /// Turns a replica into a master (REPLICAOF NO ONE). The old replication ID is kept
+    /// as replid2 so former siblings can still partially resync against us
+    pub fn promote(&mut self) {
+        let Self::Replica(replica) = self else {
+            return;
+        };
+        replica.stop_link.notify_one();
+
+        let master = RedisMasterContext {
+            master_replid: gen_uuid(),
+            master_replid2: Some(replica.master_replid.clone()),
+            second_repl_offset: Some(replica.processed_offset() + 1),
+            backlog: Arc::clone(&replica.backlog),
+        };
+        log::info!(
+            "Promoted to master, new replication ID {}, previous one {} valid up to offset {}",
+            master.master_replid,
+            replica.master_replid,
+            replica.processed_offset()
+        );
+        *self = Self::Master(master);
+    }
+
+    /// Starts a new replication history, forgetting the previous one (DEBUG CHANGE-REPL-ID)
+    pub fn change_replid(&mut self) {
+        let (replid, replid2, second_offset) = match self {
+            Self::Master(ctx) => (
+                &mut ctx.master_replid,
+                &mut ctx.master_replid2,
+                &mut ctx.second_repl_offset,
+            ),
+            Self::Replica(ctx) => (
+                &mut ctx.master_replid,
+                &mut ctx.master_replid2,
+                &mut ctx.second_repl_offset,
+            ),
+        };
+        *replid = gen_uuid();
+        *replid2 = None;
+        *second_offset = None;
+    }
 }
@@ -0,0 +1,1284 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use bytes::Bytes;
+use rand::{thread_rng, Rng};
+use sha1_smol::Sha1;
+use tokio::{
+    sync::{oneshot, Notify},
+    task::JoinSet,
+    time::Instant,
+};
+
+use crate::{
+    client::RedisClient,
+    server::{
+        commands::{execute, CommandContext},
+        rdb,
+        server::RedisServer,
+    },
+    RedisValue,
+};
+
+use super::{replica::gen_uuid, supervisor::NodeAddr};
+
+/// Members of the raft cluster and how fast they react to a lost leader
+#[derive(Clone, Debug)]
+pub struct RaftConfig {
+    /// every other member of the cluster
+    pub peers: Vec<NodeAddr>,
+    /// what members send with RAFT AUTH to be taken as one, `raft-secret`
+    pub secret: String,
+    /// lower bound of the randomized time without a leader before running for election
+    pub election_timeout: Duration,
+    /// file under `dir` keeping the node ID, current term and vote across restarts. The
+    /// log and the snapshot sit next to it, under the same name with the `log` and
+    /// `snapshot` extensions
+    pub state_file: String,
+    /// entries applied since the last snapshot that make the node take a new one and drop
+    /// them from its log, 0 never compacting it
+    pub snapshot_entries: usize,
+}
+impl RaftConfig {
+    pub const DEFAULT_ELECTION_TIMEOUT_MS: u64 = 1000;
+    pub const DEFAULT_STATE_FILE: &str = "raft.state";
+    pub const DEFAULT_SNAPSHOT_ENTRIES: usize = 10_000;
+}
+
+/// Entries sent at most in one APPENDENTRIES
+const MAX_ENTRIES_PER_APPEND: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RaftRole {
+    Follower,
+    Candidate,
+    Leader,
+}
+impl RaftRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Follower => "follower",
+            Self::Candidate => "candidate",
+            Self::Leader => "leader",
+        }
+    }
+}
+
+/// A command in the replicated log, empty for the no-op a new leader starts its term with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub term: u64,
+    /// database the submitting client had selected
+    pub db: usize,
+    pub command: Vec<Bytes>,
+}
+
+/// RAFT REQUESTVOTE <term> <candidate-id> <last-log-index> <last-log-term>
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoteRequest {
+    pub term: u64,
+    pub candidate: String,
+    pub last_log_index: usize,
+    pub last_log_term: u64,
+}
+impl VoteRequest {
+    pub fn to_command(&self) -> Vec<Bytes> {
+        vec![
+            Bytes::from_static(b"RAFT"),
+            Bytes::from_static(b"REQUESTVOTE"),
+            Bytes::from(self.term.to_string()),
+            Bytes::from(self.candidate.clone()),
+            Bytes::from(self.last_log_index.to_string()),
+            Bytes::from(self.last_log_term.to_string()),
+        ]
+    }
+
+    /// Parses the arguments following the subcommand
+    pub fn from_args(args: &[Bytes]) -> Result<Self> {
+        let [term, candidate, last_log_index, last_log_term] = args else {
+            bail!("ERR wrong number of arguments for 'raft requestvote' command");
+        };
+
+        let res = Self {
+            term: number(term)?,
+            candidate: String::from_utf8_lossy(candidate).into_owned(),
+            last_log_index: number(last_log_index)?,
+            last_log_term: number(last_log_term)?,
+        };
+
+        Ok(res)
+    }
+}
+
+/// RAFT APPENDENTRIES <term> <leader-host> <leader-port> <prev-log-index> <prev-log-term>
+/// <leader-commit> [<entry-term> <db> <argc> <arg> ...] ...
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AppendRequest {
+    pub term: u64,
+    /// where clients are redirected to
+    pub leader: NodeAddr,
+    pub prev_log_index: usize,
+    pub prev_log_term: u64,
+    pub leader_commit: usize,
+    pub entries: Vec<LogEntry>,
+}
+impl AppendRequest {
+    pub fn to_command(&self) -> Vec<Bytes> {
+        let mut res = vec![
+            Bytes::from_static(b"RAFT"),
+            Bytes::from_static(b"APPENDENTRIES"),
+            Bytes::from(self.term.to_string()),
+            Bytes::from(self.leader.0.clone()),
+            Bytes::from(self.leader.1.to_string()),
+            Bytes::from(self.prev_log_index.to_string()),
+            Bytes::from(self.prev_log_term.to_string()),
+            Bytes::from(self.leader_commit.to_string()),
+        ];
+        for entry in self.entries.iter() {
+            res.push(Bytes::from(entry.term.to_string()));
+            res.push(Bytes::from(entry.db.to_string()));
+            res.push(Bytes::from(entry.command.len().to_string()));
+            res.extend(entry.command.iter().cloned());
+        }
+
+        res
+    }
+
+    /// Parses the arguments following the subcommand
+    pub fn from_args(args: &[Bytes]) -> Result<Self> {
+        let [term, host, port, prev_log_index, prev_log_term, leader_commit, entries @ ..] = args
+        else {
+            bail!("ERR wrong number of arguments for 'raft appendentries' command");
+        };
+
+        let mut parsed_entries = vec![];
+        let mut pos = 0;
+        while pos < entries.len() {
+            let (Some(term), Some(db), Some(argc)) =
+                (entries.get(pos), entries.get(pos + 1), entries.get(pos + 2))
+            else {
+                bail!("ERR syntax error");
+            };
+            // --- a huge argc mustn't wrap around into a range that looks valid
+            let argc: usize = number(argc)?;
+            let end = pos
+                .checked_add(3)
+                .and_then(|start| start.checked_add(argc))
+                .ok_or_else(|| anyhow!("ERR Protocol error: invalid entry length"))?;
+            let command = entries
+                .get(pos + 3..end)
+                .ok_or_else(|| anyhow!("ERR syntax error"))?;
+            parsed_entries.push(LogEntry {
+                term: number(term)?,
+                db: number(db)?,
+                command: command.to_vec(),
+            });
+            pos = end;
+        }
+
+        let res = Self {
+            term: number(term)?,
+            leader: (String::from_utf8_lossy(host).into_owned(), number(port)?),
+            prev_log_index: number(prev_log_index)?,
+            prev_log_term: number(prev_log_term)?,
+            leader_commit: number(leader_commit)?,
+            entries: parsed_entries,
+        };
+
+        Ok(res)
+    }
+}
+
+/// RAFT INSTALLSNAPSHOT <term> <leader-host> <leader-port> <last-index> <last-term> <rdb>,
+/// sent to a follower needing entries the leader already compacted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotRequest {
+    pub term: u64,
+    pub leader: NodeAddr,
+    /// last entry the snapshot covers, and its term
+    pub last_index: usize,
+    pub last_term: u64,
+    /// RDB image of the dataset once that entry was applied
+    pub data: Bytes,
+}
+impl SnapshotRequest {
+    pub fn to_command(&self) -> Vec<Bytes> {
+        vec![
+            Bytes::from_static(b"RAFT"),
+            Bytes::from_static(b"INSTALLSNAPSHOT"),
+            Bytes::from(self.term.to_string()),
+            Bytes::from(self.leader.0.clone()),
+            Bytes::from(self.leader.1.to_string()),
+            Bytes::from(self.last_index.to_string()),
+            Bytes::from(self.last_term.to_string()),
+            self.data.clone(),
+        ]
+    }
+
+    /// Parses the arguments following the subcommand
+    pub fn from_args(args: &[Bytes]) -> Result<Self> {
+        let [term, host, port, last_index, last_term, data] = args else {
+            bail!("ERR wrong number of arguments for 'raft installsnapshot' command");
+        };
+
+        let res = Self {
+            term: number(term)?,
+            leader: (String::from_utf8_lossy(host).into_owned(), number(port)?),
+            last_index: number(last_index)?,
+            last_term: number(last_term)?,
+            data: data.clone(),
+        };
+
+        Ok(res)
+    }
+}
+
+/// What the leader sends a peer to bring it up to date
+enum PeerMessage {
+    Append(AppendRequest),
+    Snapshot(SnapshotRequest),
+}
+impl PeerMessage {
+    fn term(&self) -> u64 {
+        match self {
+            Self::Append(req) => req.term,
+            Self::Snapshot(req) => req.term,
+        }
+    }
+
+    fn to_command(&self) -> Vec<Bytes> {
+        match self {
+            Self::Append(req) => req.to_command(),
+            Self::Snapshot(req) => req.to_command(),
+        }
+    }
+}
+
+fn number<T: std::str::FromStr>(arg: &[u8]) -> Result<T> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| anyhow!("ERR value is not an integer or out of range"))
+}
+
+/// What a node knows about the cluster and its log
+#[derive(Debug)]
+struct RaftState {
+    role: RaftRole,
+    current_term: u64,
+    voted_for: Option<String>,
+    /// address of the leader of `current_term`, once heard from
+    leader: Option<NodeAddr>,
+    /// entries past the snapshot, entry N being at N - `snapshot_index` - 1
+    log: Vec<LogEntry>,
+    /// last entry the snapshot covers, and its term, 0 before the first one
+    snapshot_index: usize,
+    snapshot_term: u64,
+    /// the log file, entries are synced to it before the node counts them as stored
+    log_file: fs::File,
+    /// highest entry known to be stored by a majority
+    commit_index: usize,
+    /// highest entry applied to the dataset
+    last_applied: usize,
+    election_deadline: Instant,
+    /// per peer, next entry to send and highest one known to be replicated
+    next_index: Vec<usize>,
+    match_index: Vec<usize>,
+    /// clients waiting for the outcome of the entry at an index, in the term it was
+    /// appended in
+    waiters: HashMap<usize, (u64, oneshot::Sender<RedisValue>)>,
+}
+impl RaftState {
+    fn last_index(&self) -> usize {
+        self.snapshot_index + self.log.len()
+    }
+
+    fn last_log_term(&self) -> u64 {
+        self.log
+            .last()
+            .map_or(self.snapshot_term, |entry| entry.term)
+    }
+
+    /// Term of an entry, `None` when it's past the log or compacted into the snapshot
+    fn term_at(&self, index: usize) -> Option<u64> {
+        match index.checked_sub(self.snapshot_index)? {
+            0 => Some(self.snapshot_term),
+            offset => self.log.get(offset - 1).map(|entry| entry.term),
+        }
+    }
+
+    fn entry(&self, index: usize) -> Option<&LogEntry> {
+        self.log.get(index.checked_sub(self.snapshot_index + 1)?)
+    }
+
+    /// Puts entries in the log from `index` on, in place of whatever was there from that
+    /// index. They are appended to the log file and synced first, a failure leaving both
+    /// the file and the log as they were
+    fn store(&mut self, index: usize, entries: Vec<LogEntry>) -> Result<()> {
+        let mut buf = vec![];
+        for (i, entry) in entries.iter().enumerate() {
+            encode_entry(&mut buf, index + i, entry);
+        }
+        let len = self.log_file.metadata()?.len();
+        if let Err(e) = self
+            .log_file
+            .write_all(&buf)
+            .and_then(|_| self.log_file.sync_data())
+        {
+            let _ = self.log_file.set_len(len);
+            return Err(e.into());
+        }
+
+        // --- entries replaced were never committed, their clients learn they're lost
+        self.log.truncate(index - self.snapshot_index - 1);
+        self.waiters.retain(|waiting, _| *waiting < index);
+        self.log.extend(entries);
+
+        Ok(())
+    }
+
+    /// Follows whoever runs `term`, forgetting the vote of an older term
+    fn step_down(&mut self, term: u64) {
+        if term > self.current_term {
+            self.current_term = term;
+            self.voted_for = None;
+            self.leader = None;
+        }
+        self.role = RaftRole::Follower;
+    }
+}
+
+/// Node ID, term and vote as the state file holds them, one per line. The vote line is
+/// empty when the node hasn't voted in the term
+fn read_state_file(path: &Path) -> Result<Option<(String, u64, Option<String>)>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failure reading {}", path.display())),
+    };
+    let mut lines = text.lines();
+    let (Some(id), Some(term), voted_for) = (lines.next(), lines.next(), lines.next()) else {
+        bail!("Invalid raft state file {}", path.display());
+    };
+    let term = term
+        .parse()
+        .with_context(|| format!("Invalid term in {}", path.display()))?;
+    let voted_for = voted_for
+        .filter(|voted_for| !voted_for.is_empty())
+        .map(str::to_string);
+
+    Ok(Some((id.to_string(), term, voted_for)))
+}
+
+/// Appends an entry the way the log file holds it: "<index> <term> <db> <argc>" on a line,
+/// then each argument as its length on a line followed by its bytes and a newline
+fn encode_entry(buf: &mut Vec<u8>, index: usize, entry: &LogEntry) {
+    let header = format!(
+        "{} {} {} {}\n",
+        index,
+        entry.term,
+        entry.db,
+        entry.command.len()
+    );
+    buf.extend_from_slice(header.as_bytes());
+    for arg in entry.command.iter() {
+        buf.extend_from_slice(format!("{}\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg);
+        buf.push(b'\n');
+    }
+}
+
+/// Entry at the start of `buf` with its index, and the bytes it takes. `None` when the
+/// record is cut short or garbled
+fn decode_entry(buf: &[u8]) -> Option<(usize, LogEntry, usize)> {
+    let mut pos = 0;
+    let header = std::str::from_utf8(read_line(buf, &mut pos)?).ok()?;
+    let [index, term, db, argc] = header.split(' ').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let (index, term, db, argc): (usize, u64, usize, usize) = (
+        index.parse().ok()?,
+        term.parse().ok()?,
+        db.parse().ok()?,
+        argc.parse().ok()?,
+    );
+
+    let mut command = vec![];
+    for _ in 0..argc {
+        let len: usize = std::str::from_utf8(read_line(buf, &mut pos)?)
+            .ok()?
+            .parse()
+            .ok()?;
+        let end = pos.checked_add(len)?;
+        let arg = buf.get(pos..end)?;
+        if buf.get(end) != Some(&b'\n') {
+            return None;
+        }
+        command.push(Bytes::copy_from_slice(arg));
+        pos = end + 1;
+    }
+
+    Some((index, LogEntry { term, db, command }, pos))
+}
+
+/// Line starting at `pos`, which moves past it
+fn read_line<'a>(buf: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let len = buf.get(*pos..)?.iter().position(|b| *b == b'\n')?;
+    let res = &buf[*pos..*pos + len];
+    *pos += len + 1;
+
+    Some(res)
+}
+
+/// Entries of the log file past the snapshot, and the file opened for appending. A later
+/// record for an index replaces the entry there and those after it, the way the log was
+/// changed. A record cut short by a crash is dropped from the file
+fn open_log(path: &Path, snapshot_index: usize) -> Result<(Vec<LogEntry>, fs::File)> {
+    let buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e).with_context(|| format!("Failure reading {}", path.display())),
+    };
+
+    let mut log: Vec<LogEntry> = vec![];
+    let mut pos = 0;
+    while let Some((index, entry, len)) = decode_entry(&buf[pos..]) {
+        pos += len;
+        // --- already part of the snapshot
+        if index <= snapshot_index {
+            continue;
+        }
+        let offset = index - snapshot_index - 1;
+        ensure!(
+            offset <= log.len(),
+            "Entry {} of {} doesn't follow the ones before",
+            index,
+            path.display()
+        );
+        log.truncate(offset);
+        log.push(entry);
+    }
+
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failure opening {}", path.display()))?;
+    if pos < buf.len() {
+        log::warn!(
+            "Dropping the incomplete entry at the end of {}",
+            path.display()
+        );
+        file.set_len(pos as u64)?;
+    }
+
+    Ok((log, file))
+}
+
+/// Index and term of the last entry the snapshot file covers, and the file right after
+/// them, where the RDB image of the dataset up to that entry starts
+fn read_snapshot(path: &Path) -> Result<Option<(usize, u64, BufReader<fs::File>)>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failure reading {}", path.display())),
+    };
+    let mut reader = BufReader::new(file);
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let parsed = header
+        .trim_end()
+        .split_once(' ')
+        .and_then(|(index, term)| Some((index.parse().ok()?, term.parse().ok()?)));
+    let Some((index, term)) = parsed else {
+        bail!("Invalid raft snapshot {}", path.display());
+    };
+
+    Ok(Some((index, term, reader)))
+}
+
+/// Writes a snapshot file and syncs it, replacing the previous one at once
+fn write_snapshot(path: &Path, index: usize, term: u64, data: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("snapshot.tmp");
+    let mut file =
+        fs::File::create(&tmp).with_context(|| format!("Failure creating {}", tmp.display()))?;
+    writeln!(file, "{} {}", index, term)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path).with_context(|| format!("Failure writing {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Experimental strongly consistent mode: writes and reads of keys are appended to a log
+/// replicated with Raft, and only run once a majority of the cluster stored them. The
+/// node ID, term and vote are saved to the state file before the node answers a vote, so a
+/// restarted node never votes twice in a term, and entries are synced to the log file
+/// before the node acknowledges them. Every `snapshot_entries` applied entries the dataset
+/// is saved to the snapshot file and the log up to there dropped. A restarted node starts
+/// from its snapshot and applies the rest of its log again as it learns it's committed
+#[derive(Debug)]
+pub struct RaftNode {
+    config: RaftConfig,
+    /// identifies the node in elections
+    id: String,
+    /// where clients can reach this node
+    addr: NodeAddr,
+    /// where the node ID, term and vote are saved
+    state_file: PathBuf,
+    /// where the log past the snapshot is kept
+    log_path: PathBuf,
+    /// where the dataset up to the snapshot is kept
+    snapshot_path: PathBuf,
+    state: Mutex<RaftState>,
+    /// held while a committed entry or a snapshot is applied to the dataset, so that
+    /// they never interleave
+    applying: tokio::sync::Mutex<()>,
+    /// wakes up the replication to peers
+    replicate: Notify,
+    /// wakes up the application of committed entries
+    committed: Notify,
+}
+impl RaftNode {
+    /// Node resuming the term, vote, snapshot and log of its files, when there are some.
+    /// The dataset is expected to start as the snapshot, see `snapshot_data`
+    pub fn new(config: RaftConfig, addr: NodeAddr, state_file: PathBuf) -> Result<Self> {
+        let (id, current_term, voted_for) = match read_state_file(&state_file)? {
+            Some(saved) => saved,
+            None => (gen_uuid(), 0, None),
+        };
+        let log_path = state_file.with_extension("log");
+        let snapshot_path = state_file.with_extension("snapshot");
+        let (snapshot_index, snapshot_term) = match read_snapshot(&snapshot_path)? {
+            Some((index, term, _)) => (index, term),
+            None => (0, 0),
+        };
+        let (log, log_file) = open_log(&log_path, snapshot_index)?;
+        let peers = config.peers.len();
+        let state = RaftState {
+            role: RaftRole::Follower,
+            current_term,
+            voted_for,
+            leader: None,
+            log,
+            snapshot_index,
+            snapshot_term,
+            log_file,
+            // --- what the snapshot covers is committed, the rest is learned again
+            commit_index: snapshot_index,
+            last_applied: snapshot_index,
+            election_deadline: Instant::now() + random_timeout(config.election_timeout),
+            next_index: vec![1; peers],
+            match_index: vec![0; peers],
+            waiters: HashMap::new(),
+        };
+
+        let node = Self {
+            config,
+            id,
+            addr,
+            state_file,
+            log_path,
+            snapshot_path,
+            state: Mutex::new(state),
+            applying: tokio::sync::Mutex::new(()),
+            replicate: Notify::new(),
+            committed: Notify::new(),
+        };
+        node.save_state(&node.state.lock().unwrap())?;
+
+        Ok(node)
+    }
+
+    /// Writes the term and vote to the state file and syncs it, replacing the previous
+    /// one at once so a crash never leaves half of it
+    fn save_state(&self, state: &RaftState) -> Result<()> {
+        let path = &self.state_file;
+        let tmp = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)
+            .with_context(|| format!("Failure creating {}", tmp.display()))?;
+        writeln!(
+            file,
+            "{}\n{}\n{}",
+            self.id,
+            state.current_term,
+            state.voted_for.as_deref().unwrap_or("")
+        )?;
+        file.sync_all()?;
+        fs::rename(&tmp, path).with_context(|| format!("Failure writing {}", path.display()))?;
+
+        Ok(())
+    }
+
+    /// Replaces the log file with the entries kept in memory, once a snapshot took over
+    /// the ones before them
+    fn rewrite_log(&self, state: &mut RaftState) -> Result<()> {
+        let path = &self.log_path;
+        let tmp = path.with_extension("log.tmp");
+        let mut buf = vec![];
+        for (i, entry) in state.log.iter().enumerate() {
+            encode_entry(&mut buf, state.snapshot_index + 1 + i, entry);
+        }
+        let mut file = fs::File::create(&tmp)
+            .with_context(|| format!("Failure creating {}", tmp.display()))?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path).with_context(|| format!("Failure writing {}", path.display()))?;
+        state.log_file = fs::OpenOptions::new().append(true).open(path)?;
+
+        Ok(())
+    }
+
+    /// RDB image of the dataset the snapshot covers, for the server to start from
+    pub fn snapshot_data(&self) -> Result<Option<Vec<u8>>> {
+        let Some((_, _, mut reader)) = read_snapshot(&self.snapshot_path)? else {
+            return Ok(None);
+        };
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+
+        Ok(Some(data))
+    }
+
+    /// Role, term, leader address and commit index, `RAFT STATE`
+    pub fn state(&self) -> (RaftRole, u64, Option<NodeAddr>, usize) {
+        let state = self.state.lock().unwrap();
+
+        (
+            state.role,
+            state.current_term,
+            state.leader.clone(),
+            state.commit_index,
+        )
+    }
+
+    /// Whether a connection sending RAFT AUTH with this secret is a member. Digests are
+    /// compared rather than the secrets themselves, like passwords are
+    pub fn check_secret(&self, secret: &[u8]) -> bool {
+        Sha1::from(secret).digest() == Sha1::from(self.config.secret.as_bytes()).digest()
+    }
+
+    /// Number of other members, `replicate_to` takes their position
+    pub fn peers(&self) -> usize {
+        self.config.peers.len()
+    }
+
+    fn heartbeat_period(&self) -> Duration {
+        self.config.election_timeout / 4
+    }
+
+    /// Appends a command to the log and waits for it to be committed and applied, with
+    /// its reply. Only the leader takes commands, others redirect to it
+    pub async fn submit(&self, db: usize, cmd: &str, args: &[Bytes]) -> RedisValue {
+        let applied = {
+            let mut state = self.state.lock().unwrap();
+            if state.role != RaftRole::Leader {
+                let err = match &state.leader {
+                    Some((host, port)) => format!("NOTLEADER {}:{}", host, port),
+                    None => "CLUSTERDOWN No raft leader elected yet".to_string(),
+                };
+                return RedisValue::SimpleError(Bytes::from(err));
+            }
+            let term = state.current_term;
+            let index = state.last_index() + 1;
+            let entry = LogEntry {
+                term,
+                db,
+                command: [Bytes::from(cmd.to_string())]
+                    .into_iter()
+                    .chain(args.iter().cloned())
+                    .collect(),
+            };
+            if let Err(e) = state.store(index, vec![entry]) {
+                log::error!("Failure saving to the raft log: {:#}", e);
+                return RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR Failure saving the command to the raft log",
+                ));
+            }
+            let (tx, rx) = oneshot::channel();
+            state.waiters.insert(index, (term, tx));
+            self.advance_commit(&mut state);
+            rx
+        };
+        self.replicate.notify_waiters();
+
+        // --- dropped when a new leader overwrote the entry
+        applied.await.unwrap_or_else(|_| {
+            RedisValue::SimpleError(Bytes::from_static(
+                b"TRYAGAIN The command was lost to a leader change",
+            ))
+        })
+    }
+
+    /// Term and whether the vote is granted. Both are saved before replying, a vote that
+    /// can't be saved is refused
+    pub fn request_vote(&self, req: VoteRequest) -> (u64, bool) {
+        let mut state = self.state.lock().unwrap();
+        let saved = (state.current_term, state.voted_for.clone());
+        if req.term > state.current_term {
+            state.step_down(req.term);
+        }
+        let up_to_date =
+            (req.last_log_term, req.last_log_index) >= (state.last_log_term(), state.last_index());
+        let granted = req.term == state.current_term
+            && state
+                .voted_for
+                .as_ref()
+                .is_none_or(|voted_for| *voted_for == req.candidate)
+            && up_to_date;
+        if granted {
+            state.voted_for = Some(req.candidate);
+        }
+        if (state.current_term, state.voted_for.clone()) != saved {
+            if let Err(e) = self.save_state(&state) {
+                log::error!("Failure saving the raft state, refusing the vote: {:#}", e);
+                // --- back to the vote of the term before this request, none in a newer one
+                if granted {
+                    state.voted_for = saved.1.filter(|_| state.current_term == saved.0);
+                }
+                return (state.current_term, false);
+            }
+        }
+        if granted {
+            state.election_deadline = Instant::now() + random_timeout(self.config.election_timeout);
+        }
+
+        (state.current_term, granted)
+    }
+
+    /// Follows the sender of a message from the leader of `term`, unless that term is
+    /// behind ours, returning whether it isn't
+    fn hear_from_leader(&self, state: &mut RaftState, term: u64, leader: NodeAddr) -> bool {
+        if term < state.current_term {
+            return false;
+        }
+        let newer = term > state.current_term;
+        state.step_down(term);
+        if newer {
+            if let Err(e) = self.save_state(state) {
+                log::error!("Failure saving the raft state: {:#}", e);
+            }
+        }
+        state.leader = Some(leader);
+        state.election_deadline = Instant::now() + random_timeout(self.config.election_timeout);
+
+        true
+    }
+
+    /// Term, whether the entries were stored, and the index up to which the log matches
+    /// the leader's. On a mismatch that index is where the leader should retry from
+    pub fn append_entries(&self, mut req: AppendRequest) -> (u64, bool, usize) {
+        let mut state = self.state.lock().unwrap();
+        if !self.hear_from_leader(&mut state, req.term, req.leader.clone()) {
+            return (state.current_term, false, 0);
+        }
+
+        // --- entries up to the snapshot were committed, they match whatever is sent
+        let matched = req.prev_log_index + req.entries.len();
+        if req.prev_log_index < state.snapshot_index {
+            if matched <= state.snapshot_index {
+                return (state.current_term, true, matched);
+            }
+            req.entries
+                .drain(..state.snapshot_index - req.prev_log_index);
+            req.prev_log_index = state.snapshot_index;
+            req.prev_log_term = state.snapshot_term;
+        }
+        if state.term_at(req.prev_log_index) != Some(req.prev_log_term) {
+            let hint = state.last_index().min(req.prev_log_index.saturating_sub(1));
+            return (state.current_term, false, hint);
+        }
+        // --- entries past the ones sent may be stale, they aren't known to match. From
+        // --- the first one that's missing or conflicting on, the ones sent replace ours
+        let conflict =
+            req.entries.iter().enumerate().position(|(i, entry)| {
+                state.term_at(req.prev_log_index + 1 + i) != Some(entry.term)
+            });
+        if let Some(skipped) = conflict {
+            let index = req.prev_log_index + 1 + skipped;
+            if let Err(e) = state.store(index, req.entries.split_off(skipped)) {
+                log::error!("Failure saving to the raft log: {:#}", e);
+                return (state.current_term, false, index - 1);
+            }
+        }
+        if req.leader_commit.min(matched) > state.commit_index {
+            state.commit_index = req.leader_commit.min(matched);
+            self.committed.notify_one();
+        }
+
+        (state.current_term, true, matched)
+    }
+
+    /// Replaces the dataset and the log up to the leader's snapshot, for a follower
+    /// missing entries the leader no longer has. Term, whether the snapshot went in, and
+    /// the last entry it covers
+    pub async fn install_snapshot(
+        &self,
+        server: &RedisServer,
+        req: SnapshotRequest,
+    ) -> (u64, bool, usize) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if !self.hear_from_leader(&mut state, req.term, req.leader.clone()) {
+                return (state.current_term, false, 0);
+            }
+            if req.last_index <= state.commit_index {
+                return (state.current_term, true, req.last_index);
+            }
+        }
+
+        let _applying = self.applying.lock().await;
+        let installed = async {
+            let data = req.data.clone();
+            let now = server.clock.now();
+            let dataset = tokio::task::spawn_blocking(move || rdb::parse(&data, now)).await??;
+            let (path, data) = (self.snapshot_path.clone(), req.data.clone());
+            let (index, term) = (req.last_index, req.last_term);
+            tokio::task::spawn_blocking(move || write_snapshot(&path, index, term, &data))
+                .await??;
+            server.replace_dataset(dataset).await?;
+            anyhow::Ok(())
+        };
+        let mut res = installed.await;
+
+        let mut state = self.state.lock().unwrap();
+        if res.is_ok() {
+            // --- entries past the snapshot stay when the log agrees with it up to there
+            match state.term_at(req.last_index) == Some(req.last_term) {
+                true => {
+                    let compacted = req.last_index - state.snapshot_index;
+                    state.log.drain(..compacted);
+                }
+                false => state.log.clear(),
+            }
+            state.waiters.retain(|waiting, _| *waiting > req.last_index);
+            state.snapshot_index = req.last_index;
+            state.snapshot_term = req.last_term;
+            state.commit_index = state.commit_index.max(req.last_index);
+            state.last_applied = req.last_index;
+            res = self.rewrite_log(&mut state);
+        }
+        if let Err(e) = res {
+            log::error!("Failure installing the raft snapshot: {:#}", e);
+            return (state.current_term, false, 0);
+        }
+
+        (state.current_term, true, req.last_index)
+    }
+
+    /// Commits the highest entry of the current term a majority stored
+    fn advance_commit(&self, state: &mut RaftState) {
+        let mut matched = state.match_index.clone();
+        matched.push(state.last_index());
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let majority_index = matched[matched.len() / 2];
+        if majority_index > state.commit_index
+            && state.term_at(majority_index) == Some(state.current_term)
+        {
+            state.commit_index = majority_index;
+            self.committed.notify_one();
+        }
+    }
+
+    /// Runs elections whenever the leader goes quiet, meant to be spawned next to the
+    /// server along with `replicate_to` for every peer and `apply_committed`
+    pub async fn run_elections(self: Arc<Self>) {
+        loop {
+            let (role, deadline) = {
+                let state = self.state.lock().unwrap();
+                (state.role, state.election_deadline)
+            };
+            if role == RaftRole::Leader {
+                tokio::time::sleep(self.heartbeat_period()).await;
+            } else if Instant::now() < deadline {
+                tokio::time::sleep_until(deadline).await;
+            } else {
+                self.run_for_election().await;
+            }
+        }
+    }
+
+    async fn run_for_election(&self) {
+        let req = {
+            let mut state = self.state.lock().unwrap();
+            state.current_term += 1;
+            state.role = RaftRole::Candidate;
+            state.voted_for = Some(self.id.clone());
+            state.leader = None;
+            state.election_deadline = Instant::now() + random_timeout(self.config.election_timeout);
+            // --- the vote for itself counts like any other, it must be saved first
+            if let Err(e) = self.save_state(&state) {
+                log::error!("Failure saving the raft state, not running: {:#}", e);
+                state.role = RaftRole::Follower;
+                return;
+            }
+            VoteRequest {
+                term: state.current_term,
+                candidate: self.id.clone(),
+                last_log_index: state.last_index(),
+                last_log_term: state.last_log_term(),
+            }
+        };
+        log::info!("Raft election for term {}", req.term);
+
+        let mut requests = JoinSet::new();
+        for peer in self.config.peers.iter() {
+            let (peer, cmd, timeout) = (peer.clone(), req.to_command(), self.heartbeat_period());
+            let secret = self.config.secret.clone();
+            requests.spawn(async move {
+                let request = async {
+                    let mut client = connect_peer(&peer, &secret).await?;
+                    client.command(cmd).await
+                };
+                tokio::time::timeout(timeout, request).await?
+            });
+        }
+        let voters = self.config.peers.len() + 1;
+        let majority = voters / 2 + 1;
+        let mut votes = 1;
+        loop {
+            if votes >= majority {
+                self.become_leader(req.term);
+                return;
+            }
+            let Some(reply) = requests.join_next().await else {
+                return;
+            };
+            let Ok(Ok(RedisValue::Array(reply))) = reply else {
+                continue;
+            };
+            let (Some(RedisValue::Integer(term)), Some(RedisValue::Integer(granted))) =
+                (reply.first(), reply.get(1))
+            else {
+                continue;
+            };
+
+            let mut state = self.state.lock().unwrap();
+            if *term as u64 > state.current_term {
+                state.step_down(*term as u64);
+                if let Err(e) = self.save_state(&state) {
+                    log::error!("Failure saving the raft state: {:#}", e);
+                }
+            }
+            if state.role != RaftRole::Candidate || state.current_term != req.term {
+                return;
+            }
+            votes += usize::from(*granted == 1);
+        }
+    }
+
+    fn become_leader(&self, term: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.role != RaftRole::Candidate || state.current_term != term {
+            return;
+        }
+        // --- entries of previous terms only commit along with one of the current term
+        let next_index = state.last_index() + 1;
+        let noop = LogEntry {
+            term,
+            db: 0,
+            command: vec![],
+        };
+        if let Err(e) = state.store(next_index, vec![noop]) {
+            log::error!("Failure saving to the raft log, not leading: {:#}", e);
+            state.role = RaftRole::Follower;
+            return;
+        }
+        log::info!("Raft leader for term {}", term);
+        state.role = RaftRole::Leader;
+        state.leader = Some(self.addr.clone());
+        state.next_index.fill(next_index);
+        state.match_index.fill(0);
+        self.advance_commit(&mut state);
+        drop(state);
+
+        self.replicate.notify_waiters();
+    }
+
+    /// Keeps a peer's log in line with ours while leading, sending heartbeats when
+    /// there is nothing new
+    pub async fn replicate_to(self: Arc<Self>, peer: usize) {
+        let addr = &self.config.peers[peer];
+        let mut client = None;
+        loop {
+            // --- the entries to send, or the term to send the snapshot in when the peer
+            // --- needs entries that were compacted
+            let message = {
+                let state = self.state.lock().unwrap();
+                let next_index = state.next_index[peer];
+                match state.role {
+                    RaftRole::Leader if next_index <= state.snapshot_index => {
+                        Some(Err(state.current_term))
+                    }
+                    RaftRole::Leader => Some(Ok(AppendRequest {
+                        term: state.current_term,
+                        leader: self.addr.clone(),
+                        prev_log_index: next_index - 1,
+                        prev_log_term: state.term_at(next_index - 1).unwrap_or(0),
+                        leader_commit: state.commit_index,
+                        entries: state
+                            .log
+                            .iter()
+                            .skip(next_index - state.snapshot_index - 1)
+                            .take(MAX_ENTRIES_PER_APPEND)
+                            .cloned()
+                            .collect(),
+                    })),
+                    _ => None,
+                }
+            };
+            let message = match message {
+                Some(Ok(req)) => PeerMessage::Append(req),
+                Some(Err(term)) => match self.snapshot_request(term) {
+                    Ok(req) => PeerMessage::Snapshot(req),
+                    Err(e) => {
+                        log::error!("Failure reading the raft snapshot: {:#}", e);
+                        tokio::time::sleep(self.heartbeat_period()).await;
+                        continue;
+                    }
+                },
+                None => {
+                    client = None;
+                    let _ =
+                        tokio::time::timeout(self.heartbeat_period(), self.replicate.notified())
+                            .await;
+                    continue;
+                }
+            };
+
+            // --- a snapshot may take longer to get across than a heartbeat period
+            let timeout = match message {
+                PeerMessage::Append(_) => self.heartbeat_period(),
+                PeerMessage::Snapshot(_) => self.config.election_timeout,
+            };
+            let reply = match send_message(
+                &mut client,
+                addr,
+                &self.config.secret,
+                message.to_command(),
+                timeout,
+            )
+            .await
+            {
+                Ok(reply) => reply,
+                Err(e) => {
+                    log::debug!("Failure replicating to {}:{}: {}", addr.0, addr.1, e);
+                    client = None;
+                    tokio::time::sleep(self.heartbeat_period()).await;
+                    continue;
+                }
+            };
+
+            let caught_up = {
+                let mut state = self.state.lock().unwrap();
+                let (term, success, matched) = reply;
+                if term > state.current_term {
+                    state.step_down(term);
+                    if let Err(e) = self.save_state(&state) {
+                        log::error!("Failure saving the raft state: {:#}", e);
+                    }
+                    continue;
+                }
+                if state.role != RaftRole::Leader || state.current_term != message.term() {
+                    continue;
+                }
+                match (&message, success) {
+                    (_, true) => {
+                        state.match_index[peer] = state.match_index[peer].max(matched);
+                        state.next_index[peer] = matched + 1;
+                        self.advance_commit(&mut state);
+                    }
+                    (PeerMessage::Append(req), false) => {
+                        state.next_index[peer] = (matched + 1).min(req.prev_log_index).max(1);
+                    }
+                    (PeerMessage::Snapshot(_), false) => {}
+                }
+                let idle = match &message {
+                    PeerMessage::Append(req) => success && req.entries.is_empty(),
+                    // --- a snapshot that didn't go in is sent again after a pause
+                    PeerMessage::Snapshot(_) => !success,
+                };
+                idle || state.next_index[peer] > state.last_index()
+            };
+            if caught_up {
+                let _ =
+                    tokio::time::timeout(self.heartbeat_period(), self.replicate.notified()).await;
+            }
+        }
+    }
+
+    /// INSTALLSNAPSHOT for the snapshot file as it is now
+    fn snapshot_request(&self, term: u64) -> Result<SnapshotRequest> {
+        let Some((last_index, last_term, mut reader)) = read_snapshot(&self.snapshot_path)? else {
+            bail!("The log was compacted but there is no snapshot");
+        };
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+
+        let res = SnapshotRequest {
+            term,
+            leader: self.addr.clone(),
+            last_index,
+            last_term,
+            data: Bytes::from(data),
+        };
+
+        Ok(res)
+    }
+
+    /// Saves the dataset, as it is once the entry at `index` was applied, to the snapshot
+    /// file and drops the log up to that entry
+    async fn compact(&self, server: &RedisServer, index: usize) -> Result<()> {
+        let term = self
+            .state
+            .lock()
+            .unwrap()
+            .term_at(index)
+            .ok_or_else(|| anyhow!("Entry {} isn't in the log", index))?;
+        let dataset = server.snapshot().await;
+        let path = self.snapshot_path.clone();
+        tokio::task::spawn_blocking(move || {
+            write_snapshot(&path, index, term, &rdb::serialize(&dataset, None)?)
+        })
+        .await??;
+
+        let mut state = self.state.lock().unwrap();
+        let compacted = index - state.snapshot_index;
+        state.log.drain(..compacted);
+        state.snapshot_index = index;
+        state.snapshot_term = term;
+        self.rewrite_log(&mut state)
+    }
+
+    /// Runs committed entries against the dataset, in log order, handing the replies to
+    /// the clients that submitted them
+    pub async fn apply_committed(self: Arc<Self>, server: Arc<RedisServer>) {
+        let mut session = server.new_session();
+        // --- committed entries are applied as is, like the stream of a master
+        session.is_master_link = true;
+        loop {
+            self.committed.notified().await;
+            loop {
+                let _applying = self.applying.lock().await;
+                // --- between entries, the dataset being what the ones applied made it
+                let due = {
+                    let state = self.state.lock().unwrap();
+                    let applied = state.last_applied;
+                    (self.config.snapshot_entries > 0
+                        && applied - state.snapshot_index >= self.config.snapshot_entries)
+                        .then_some(applied)
+                };
+                if let Some(applied) = due {
+                    if let Err(e) = self.compact(&server, applied).await {
+                        log::error!("Failure compacting the raft log: {:#}", e);
+                    }
+                }
+                let (index, entry, waiter) = {
+                    let mut state = self.state.lock().unwrap();
+                    if state.last_applied >= state.commit_index {
+                        break;
+                    }
+                    state.last_applied += 1;
+                    let index = state.last_applied;
+                    let entry = state
+                        .entry(index)
+                        .cloned()
+                        .expect("Committed entries past the snapshot are in the log");
+                    (index, entry, state.waiters.remove(&index))
+                };
+                let Some((cmd, args)) = entry.command.split_first() else {
+                    continue;
+                };
+
+                let cmd = String::from_utf8_lossy(cmd).into_owned();
+                // --- an index past the configured ones can only come from a misconfigured
+                // --- member, the entry fails there rather than landing in another database
+                if entry.db >= server.databases.len() {
+                    log::error!(
+                        "Failure applying raft entry {}: database {} isn't configured",
+                        index,
+                        entry.db
+                    );
+                    if let Some((_, waiter)) = waiter {
+                        let _ = waiter.send(RedisValue::SimpleError(Bytes::from_static(
+                            b"ERR DB index is out of range",
+                        )));
+                    }
+                    continue;
+                }
+                session.db = entry.db;
+                let mut ctx = CommandContext {
+                    args,
+                    server: &server,
+                    session: &mut session,
+                };
+                let res = execute(&cmd, &mut ctx).await.unwrap_or_else(|e| {
+                    log::error!("Failure applying raft entry {}: {}", index, e);
+                    RedisValue::SimpleError(Bytes::from(format!("ERR {}", e)))
+                });
+                if let Some((term, waiter)) = waiter {
+                    if term == entry.term {
+                        let _ = waiter.send(res);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn random_timeout(base: Duration) -> Duration {
+    base.mul_f64(1.0 + thread_rng().gen::<f64>())
+}
+
+/// Connection to another member, authenticated with the cluster's secret
+async fn connect_peer(node: &NodeAddr, secret: &str) -> Result<RedisClient> {
+    let mut client = RedisClient::connect((node.0.as_str(), node.1)).await?;
+    let auth = [
+        Bytes::from_static(b"RAFT"),
+        Bytes::from_static(b"AUTH"),
+        Bytes::from(secret.to_string()),
+    ];
+    match client.command(auth).await? {
+        RedisValue::SimpleString(_) => Ok(client),
+        RedisValue::SimpleError(e) => bail!("{}", String::from_utf8_lossy(&e)),
+        other => bail!("Unexpected reply to RAFT AUTH: {:?}", other),
+    }
+}
+
+/// APPENDENTRIES or INSTALLSNAPSHOT over a connection kept across calls, opened again
+/// after failures. Both get the term, whether it went through and an index back
+async fn send_message(
+    client: &mut Option<RedisClient>,
+    node: &NodeAddr,
+    secret: &str,
+    cmd: Vec<Bytes>,
+    timeout: Duration,
+) -> Result<(u64, bool, usize)> {
+    let request = async {
+        if client.is_none() {
+            *client = Some(connect_peer(node, secret).await?);
+        }
+        let client = client.as_mut().expect("Connected above");
+        client.command(cmd).await
+    };
+    let reply = tokio::time::timeout(timeout, request).await??;
+
+    let RedisValue::Array(reply) = reply else {
+        bail!("Unexpected reply to a raft message: {:?}", reply);
+    };
+    let [RedisValue::Integer(term), RedisValue::Integer(success), RedisValue::Integer(matched)] =
+        reply[..]
+    else {
+        bail!("Unexpected reply to a raft message: {:?}", reply);
+    };
+    ensure!(term >= 0 && matched >= 0, "Negative term or index");
+
+    Ok((term as u64, success == 1, matched as usize))
+}
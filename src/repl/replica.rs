@@ -1,27 +1,158 @@
-use anyhow::{ensure, Result};
+use core::str;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, ensure, Result};
 use bytes::Bytes;
 use rand::{thread_rng, Rng};
-use tokio::net::TcpStream;
+use tokio::{
+    net::{lookup_host, TcpStream},
+    sync::Notify,
+    time::timeout,
+};
+
+use crate::server::{
+    commands::{execute, CommandContext},
+    handler::{AsyncStream, RedisConnectionHandler, RedisValue},
+    net::SocketOptions,
+    rdb::ReplInfo,
+    server::{RedisServer, RedisServerConfig},
+    session::Session,
+};
+
+use super::{backlog::ReplBacklog, ServerContext};
+#[cfg(feature = "tls")]
+use tokio_rustls::{rustls::pki_types::ServerName, TlsConnector};
 
-use crate::server::handler::{RedisConnectionHandler, RedisValue};
+/// Pause between attempts to get a lost master link back
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long each address of the master gets to accept the connection, so an address the
+/// master's name no longer points to doesn't hold up the next one
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Clone, Debug)]
 pub struct RedisReplicaContext {
     /// master replication ID
     pub master_replid: String,
-    /// offset into the circluar backlog buffer
-    pub master_repl_offset: usize,
-    /// offset of the replica into circular backlog buffer
-    pub slave_repl_offset: usize,
+    /// name or IP the master was given as, resolved again on every connection attempt
+    pub master_host: String,
+    pub master_port: u16,
+    /// address `master_host` resolved to for the current link
+    pub master_addr: SocketAddr,
+    /// whether the link to the master is still up and being followed
+    pub link_up: Arc<AtomicBool>,
+    /// master's command stream as applied here, its offset is the replica offset.
+    /// Kept so sub-replicas can resume from us once we get promoted
+    pub backlog: Arc<Mutex<ReplBacklog>>,
     /// backup repl ID
     pub master_replid2: Option<String>,
     /// backup repl offset
     pub second_repl_offset: Option<usize>,
+    /// tells the task following the master to drop the link
+    pub stop_link: Arc<Notify>,
+}
+
+/// Connection to the master, right after the PSYNC handshake
+pub struct MasterLink {
+    pub handler: RedisConnectionHandler,
+    /// dataset sent for a full resync, `None` when continuing from the local one
+    pub rdb: Option<Vec<u8>>,
+}
+
+/// How the master answered PSYNC
+#[derive(Debug, PartialEq, Eq)]
+enum PsyncReply {
+    FullResync {
+        replid: String,
+        offset: usize,
+    },
+    /// the master only names its replication ID if it changed
+    Continue {
+        replid: Option<String>,
+    },
+}
+
+/// How a replica presents itself to its master during the handshake
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplicaAnnounce {
+    /// address to be reached at, `None` to let the master use the connection's
+    pub ip: Option<String>,
+    pub port: usize,
+}
+impl ReplicaAnnounce {
+    /// What the configuration says to announce, for a replica listening on `port`
+    pub fn new(config: &RedisServerConfig, port: usize) -> Self {
+        Self {
+            ip: config.replica_announce_ip.clone(),
+            port: config.replica_announce_port.map_or(port, usize::from),
+        }
+    }
+}
+
+/// How a replica opens its link to the master
+#[derive(Clone, Default)]
+pub struct MasterConnector {
+    pub socket_options: SocketOptions,
+    /// client side of TLS, for a master reached on its TLS port, `tls-replication`
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConnector>,
+}
+impl MasterConnector {
+    /// What the server is configured with
+    pub fn new(server: &RedisServer) -> Self {
+        Self {
+            socket_options: server.config.socket_options,
+            #[cfg(feature = "tls")]
+            tls: server.tls.connector.clone(),
+        }
+    }
+
+    /// Connects to the master, returning the stream along with its address
+    async fn connect(&self, host: &str, port: u16) -> Result<(Box<dyn AsyncStream>, SocketAddr)> {
+        let stream = connect_to_master(host, port).await?;
+        let addr = stream.peer_addr()?;
+        self.socket_options.apply(&stream)?;
+        // --- the master's certificate has to be for the name or IP it was given as
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let name = ServerName::try_from(host.to_string())?;
+            let stream = tls
+                .connect(name, stream)
+                .await
+                .map_err(|e| anyhow!("TLS handshake with master {} failed: {}", addr, e))?;
+            return Ok((Box::new(stream), addr));
+        }
+
+        Ok((Box::new(stream), addr))
+    }
 }
+
 impl RedisReplicaContext {
-    pub async fn connect(server_port: usize, master_addr: String) -> Result<Self> {
-        let master_addr = master_addr.replace(" ", ":");
-        let stream = TcpStream::connect(master_addr).await?;
+    /// Performs the replication handshake, returning the context along with the link to
+    /// the master. With the history of a saved dataset the master is asked to continue
+    /// from it, otherwise it sends its whole dataset
+    pub async fn connect(
+        announce: &ReplicaAnnounce,
+        master_addr: String,
+        connector: &MasterConnector,
+        repl_info: Option<&ReplInfo>,
+    ) -> Result<(Self, MasterLink)> {
+        let Some((master_host, master_port)) = master_addr.split_once(' ') else {
+            anyhow::bail!(
+                "Expected '<host> <port>' for the master, got '{}'",
+                master_addr
+            );
+        };
+        let master_port: u16 = master_port.trim().parse()?;
+        let (stream, master_addr) = connector.connect(master_host, master_port).await?;
         let mut handler = RedisConnectionHandler::new(stream);
 
         // --- handshake 1, replica pings master
@@ -37,7 +168,7 @@ impl RedisReplicaContext {
         let replconf_req = RedisValue::Array(vec![
             RedisValue::BulkString(Bytes::from_static(b"REPLCONF")),
             RedisValue::BulkString(Bytes::from_static(b"listening-port")),
-            RedisValue::BulkString(Bytes::from(format!("{}", server_port))),
+            RedisValue::BulkString(Bytes::from(format!("{}", announce.port))),
         ]);
         handler.write(replconf_req).await?;
         let replconf_res = handler.read_and_parse().await?;
@@ -46,6 +177,21 @@ impl RedisReplicaContext {
             "REPLCONF handshakes expects 'OK' from master"
         );
 
+        // --- otherwise the master goes by the address the connection comes from
+        if let Some(ip) = &announce.ip {
+            let replconf_req = RedisValue::Array(vec![
+                RedisValue::BulkString(Bytes::from_static(b"REPLCONF")),
+                RedisValue::BulkString(Bytes::from_static(b"ip-address")),
+                RedisValue::BulkString(Bytes::from(ip.clone())),
+            ]);
+            handler.write(replconf_req).await?;
+            let replconf_res = handler.read_and_parse().await?;
+            ensure!(
+                replconf_res == Some(RedisValue::SimpleString(Bytes::from_static(b"OK"))),
+                "REPLCONF handshakes expects 'OK' from master"
+            );
+        }
+
         let replconf_req = RedisValue::Array(vec![
             RedisValue::BulkString(Bytes::from_static(b"REPLCONF")),
             RedisValue::BulkString(Bytes::from_static(b"capa")),
@@ -58,33 +204,383 @@ impl RedisReplicaContext {
             "REPLCONF handshakes expects 'OK' from master"
         );
 
-        // --- handshake 3, replica sends PSYNC
+        // --- handshake 3, replica sends PSYNC, resuming right after the saved offset
+        let (psync_replid, psync_offset) = match repl_info {
+            Some(info) => (info.replid.clone(), (info.offset + 1).to_string()),
+            None => ("?".to_string(), "-1".to_string()),
+        };
         let psync_req = RedisValue::Array(vec![
             RedisValue::BulkString(Bytes::from_static(b"PSYNC")),
-            RedisValue::BulkString(Bytes::from_static(b"?")),
-            RedisValue::BulkString(Bytes::from_static(b"-1")),
+            RedisValue::BulkString(Bytes::from(psync_replid)),
+            RedisValue::BulkString(Bytes::from(psync_offset)),
         ]);
         handler.write(psync_req).await?;
-        handler
-            .read_and_parse()
-            .await
-            .expect("Failure reading result from initial PSYNC");
-        let file_data = handler
-            .read_rdb_file()
-            .await
-            .expect("Failure reading RDB file");
-        log::info!("File data: {:?}", file_data);
-
-        Ok(Self {
-            master_replid: gen_uuid(),
-            master_repl_offset: 0,
-            slave_repl_offset: 0,
-            master_replid2: None,
-            second_repl_offset: None,
-        })
+        handler.skip_newlines().await?;
+        let psync_reply = match handler.read_and_parse().await? {
+            Some(RedisValue::SimpleString(reply)) => parse_psync_reply(&reply)?,
+            other => anyhow::bail!("Unexpected PSYNC reply from master: {:?}", other),
+        };
+
+        let mut master_replid2 = None;
+        let mut second_repl_offset = None;
+        let (master_replid, offset, stream_db, rdb) = match (psync_reply, repl_info) {
+            (PsyncReply::FullResync { replid, offset }, _) => {
+                let (rdb, diskless) = handler.read_rdb_transfer().await?;
+                log::info!("Received {} bytes of RDB data from master", rdb.len());
+                // --- after a diskless sync Redis holds the command stream back until an ACK
+                if diskless {
+                    handler.write(ack(offset)).await?;
+                }
+                // --- the image tells which database the stream starts in, see `load_rdb`
+                (replid, offset, None, Some(rdb))
+            }
+            (PsyncReply::Continue { replid }, Some(info)) => {
+                log::info!(
+                    "Partial resync with master, continuing from offset {}",
+                    info.offset
+                );
+                // --- the master switched histories since, ours stays valid up to here
+                if let Some(replid) = replid.as_ref().filter(|id| **id != info.replid) {
+                    log::info!("Master replication ID changed to {}", replid);
+                    master_replid2 = Some(info.replid.clone());
+                    second_repl_offset = Some(info.offset + 1);
+                }
+                let replid = replid.unwrap_or(info.replid.clone());
+                (replid, info.offset, Some(info.stream_db), None)
+            }
+            (PsyncReply::Continue { .. }, None) => {
+                anyhow::bail!("Master accepted a partial resync that was never asked for")
+            }
+        };
+
+        let mut backlog = ReplBacklog::starting_at(ReplBacklog::DEFAULT_SIZE, offset);
+        backlog.set_stream_db(stream_db);
+        let ctx = Self {
+            master_replid,
+            master_host: master_host.to_string(),
+            master_port,
+            master_addr,
+            link_up: Arc::new(AtomicBool::new(true)),
+            backlog: Arc::new(Mutex::new(backlog)),
+            master_replid2,
+            second_repl_offset,
+            stop_link: Arc::new(Notify::new()),
+        };
+
+        Ok((ctx, MasterLink { handler, rdb }))
+    }
+
+    /// Bytes of the master's replication stream processed so far
+    pub fn processed_offset(&self) -> usize {
+        self.backlog.lock().unwrap().offset()
+    }
+}
+
+/// Connects to the master, looking its host up anew so a name that moved to another IP,
+/// as in Kubernetes, is followed. Each address it resolves to is tried in turn
+async fn connect_to_master(host: &str, port: u16) -> Result<TcpStream> {
+    let mut last_error = anyhow!("Master host '{}' doesn't resolve to any address", host);
+    for addr in lookup_host((host, port)).await? {
+        match timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => last_error = anyhow!("Failure connecting to {}: {}", addr, e),
+            Err(_) => last_error = anyhow!("Timed out connecting to {}", addr),
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Fetches the dataset of a master the way a new replica would, for `redis-cli --rdb`
+/// style backups. The link is dropped as soon as the transfer is done
+pub async fn fetch_rdb(master_addr: String, connector: &MasterConnector) -> Result<Vec<u8>> {
+    let (_, link) =
+        RedisReplicaContext::connect(&ReplicaAnnounce::default(), master_addr, connector, None)
+            .await?;
+    let res = link
+        .rdb
+        .ok_or_else(|| anyhow!("Master continued a replication instead of sending its dataset"))?;
+
+    Ok(res)
+}
+
+/// Parses `FULLRESYNC <replid> <offset>` or `CONTINUE [<replid>]`
+fn parse_psync_reply(reply: &[u8]) -> Result<PsyncReply> {
+    let reply = str::from_utf8(reply)?;
+    let parts = reply.split(' ').collect::<Vec<_>>();
+
+    let res = match parts.as_slice() {
+        ["FULLRESYNC", replid, offset] => PsyncReply::FullResync {
+            replid: replid.to_string(),
+            offset: offset.parse()?,
+        },
+        ["CONTINUE"] => PsyncReply::Continue { replid: None },
+        ["CONTINUE", replid] => PsyncReply::Continue {
+            replid: Some(replid.to_string()),
+        },
+        _ => return Err(anyhow!("Malformed PSYNC reply: '{}'", reply)),
+    };
+
+    Ok(res)
+}
+
+/// Starts following another master at runtime (REPLICAOF <host> <port>), asking it to
+/// continue from the current dataset first. The server stays as it was if the new master
+/// can't be reached. Boxed as Send since the command it comes from is itself run by the
+/// replication link it starts
+pub fn switch_master(
+    server: Arc<RedisServer>,
+    master_addr: String,
+) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    Box::pin(async move {
+        let handler = match connect_master(&server, master_addr.clone(), None).await {
+            Ok(handler) => handler,
+            Err(e) => {
+                log::error!("Failure connecting to master {}: {}", master_addr, e);
+                return;
+            }
+        };
+        log::info!("Now replicating from {}", master_addr);
+
+        follow_master(server, handler).await;
+    })
+}
+
+/// Does the handshake with a master, continuing from the current dataset when it can,
+/// then installs the new replica context and loads the dataset the master sent. When
+/// `replacing` a lost link, gives up if the replication role changed meanwhile
+async fn connect_master(
+    server: &RedisServer,
+    master_addr: String,
+    replacing: Option<&RedisReplicaContext>,
+) -> Result<RedisConnectionHandler> {
+    let repl_info = server.server_context.read().unwrap().repl_info();
+    let port = server.local_addr().map_or(0, |addr| addr.port() as usize);
+    let (ctx, link) = RedisReplicaContext::connect(
+        &ReplicaAnnounce::new(&server.config, port),
+        master_addr,
+        &MasterConnector::new(server),
+        Some(&repl_info),
+    )
+    .await?;
+
+    {
+        let mut server_context = server.server_context.write().unwrap();
+        if let Some(replacing) = replacing {
+            ensure!(
+                is_same_link(&server_context, replacing),
+                "Replication role changed while reconnecting"
+            );
+            if replacing.master_addr != ctx.master_addr {
+                log::info!(
+                    "Master {} moved from {} to {}",
+                    ctx.master_host,
+                    replacing.master_addr,
+                    ctx.master_addr
+                );
+            }
+        }
+        *server_context = ServerContext::Replica(ctx);
+    }
+    if let Some(rdb) = link.rdb {
+        if let Err(e) = server.load_rdb(rdb).await {
+            log::error!("Failure loading RDB received from master: {}", e);
+        }
+    }
+
+    Ok(link.handler)
+}
+
+/// Whether the server still follows the master through this replica context
+fn is_same_link(server_context: &ServerContext, replica: &RedisReplicaContext) -> bool {
+    matches!(server_context, ServerContext::Replica(current)
+        if Arc::ptr_eq(&current.link_up, &replica.link_up))
+}
+
+/// Applies the master's command stream to the local dataset. Every frame counts towards
+/// the replica offset, including PINGs and GETACKs. Once the link is lost the same master
+/// is retried every second, until it answers or the server gets promoted or repointed
+pub async fn follow_master(server: Arc<RedisServer>, mut handler: RedisConnectionHandler) {
+    loop {
+        let ServerContext::Replica(replica) = server.server_context.read().unwrap().clone() else {
+            return;
+        };
+        apply_master_stream(&server, &replica, handler).await;
+        replica.link_up.store(false, Ordering::Relaxed);
+
+        let master_addr = format!("{} {}", replica.master_host, replica.master_port);
+        handler = loop {
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+            if !is_same_link(&server.server_context.read().unwrap(), &replica) {
+                return;
+            }
+            match connect_master(&server, master_addr.clone(), Some(&replica)).await {
+                Ok(handler) => break handler,
+                Err(e) => log::warn!("Failure reconnecting to master {}: {}", master_addr, e),
+            }
+        };
+        log::info!("Reconnected to master {}", master_addr);
+    }
+}
+
+async fn apply_master_stream(
+    server: &RedisServer,
+    replica: &RedisReplicaContext,
+    mut handler: RedisConnectionHandler,
+) {
+    // --- a resumed stream goes on in the database it was in when the link dropped
+    let mut session = Session {
+        is_master_link: true,
+        db: replica.backlog.lock().unwrap().stream_db().unwrap_or(0),
+        ..server.new_session()
+    };
+    // --- commands between MULTI and EXEC, applied together once the EXEC arrives
+    let mut transaction: Option<Vec<(String, Vec<Bytes>)>> = None;
+
+    loop {
+        let repl_timeout = server.config.live.read().unwrap().repl_timeout;
+        let frame = tokio::select! {
+            frame = timeout(repl_timeout, handler.read_frame()) => frame,
+            _ = replica.stop_link.notified() => {
+                log::info!("Dropping the link to the master");
+                return;
+            }
+        };
+        let request = match frame {
+            Err(_) => {
+                log::warn!("MASTER timeout: no data nor PING received, dropping the link");
+                return;
+            }
+            Ok(frame) => frame,
+        };
+        let request = match request {
+            Ok(Some((request, _))) => request,
+            Ok(None) => {
+                log::warn!("Master closed the replication link");
+                return;
+            }
+            Err(e) => {
+                log::error!("Failure reading from master: {}", e);
+                return;
+            }
+        };
+        // --- commands re-serialize to the exact bytes the master sent
+        let raw = match request.clone().serialize() {
+            Ok(raw) => raw,
+            Err(e) => {
+                log::error!("Invalid frame from master: {}", e);
+                return;
+            }
+        };
+        #[cfg(feature = "chaos")]
+        let (request, raw) = match server.faults.corrupt(&raw) {
+            Some(corrupted) => match reparse(corrupted) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    log::error!("Failure reading from master: {}", e);
+                    return;
+                }
+            },
+            None => (request, raw),
+        };
+        #[cfg(feature = "chaos")]
+        server.faults.link_latency().await;
+
+        let is_command = matches!(&request, RedisValue::Array(arr)
+            if !arr.is_empty() && arr.iter().all(|v| matches!(v, RedisValue::BulkString(_))));
+        if !is_command {
+            log::warn!("Ignoring invalid frame from master: {:?}", request);
+            replica.backlog.lock().unwrap().feed(&raw);
+            continue;
+        }
+
+        let (cmd, args) = request.get_cmd_and_args();
+        let cmd = String::from_utf8_lossy(&cmd).to_uppercase();
+        let is_getack = cmd == "REPLCONF"
+            && args
+                .first()
+                .is_some_and(|sub| sub.eq_ignore_ascii_case(b"GETACK"));
+
+        match cmd.as_str() {
+            // --- the ACK reports the offset before the GETACK itself
+            "REPLCONF" if is_getack => {
+                let offset = {
+                    let mut backlog = replica.backlog.lock().unwrap();
+                    let offset = backlog.offset();
+                    backlog.feed(&raw);
+                    offset
+                };
+                #[cfg(feature = "chaos")]
+                server.faults.ack_delay().await;
+                if let Err(e) = handler.write(ack(offset)).await {
+                    log::error!("Failure sending REPLCONF ACK to master: {}", e);
+                    return;
+                }
+                continue;
+            }
+            // --- keepalive, nothing to apply
+            "PING" => {}
+            "MULTI" => transaction = Some(vec![]),
+            "EXEC" => match transaction.take() {
+                Some(commands) => {
+                    for (cmd, args) in commands {
+                        apply_command(server, &mut session, &cmd, &args).await;
+                    }
+                }
+                None => log::warn!("Ignoring EXEC without MULTI from master"),
+            },
+            _ => match transaction.as_mut() {
+                Some(commands) => commands.push((cmd, args)),
+                None => apply_command(server, &mut session, &cmd, &args).await,
+            },
+        }
+
+        let mut backlog = replica.backlog.lock().unwrap();
+        backlog.feed(&raw);
+        backlog.set_stream_db(Some(session.db));
     }
 }
 
+/// Runs a command from the master's stream. Replies are never sent back, failures and
+/// commands this server doesn't know are only logged, the dataset then drifts from the
+/// master's
+async fn apply_command(server: &RedisServer, session: &mut Session, cmd: &str, args: &[Bytes]) {
+    let mut ctx = CommandContext {
+        args,
+        server,
+        session,
+    };
+    match execute(cmd, &mut ctx).await {
+        Ok(RedisValue::SimpleError(e)) => log::warn!(
+            "Master sent '{}' which failed here: {}",
+            cmd,
+            String::from_utf8_lossy(&e)
+        ),
+        Ok(_) => {}
+        Err(e) => log::error!("Failure applying '{}' from master: {}", cmd, e),
+    }
+}
+
+/// `REPLCONF ACK <offset>`, telling the master how much of its stream got processed
+fn ack(offset: usize) -> RedisValue {
+    RedisValue::Array(vec![
+        RedisValue::BulkString(Bytes::from_static(b"REPLCONF")),
+        RedisValue::BulkString(Bytes::from_static(b"ACK")),
+        RedisValue::BulkString(Bytes::from(offset.to_string())),
+    ])
+}
+
+/// Parses a frame again after the fault injector corrupted it
+#[cfg(feature = "chaos")]
+fn reparse(raw: Bytes) -> Result<(RedisValue, Bytes)> {
+    let token = crate::server::serde::tokenize(&bytes::BytesMut::from(&raw[..]), 0)?;
+    let Some(token) = token.filter(|token| token.1 == raw.len()) else {
+        anyhow::bail!("Corrupted frame from master doesn't parse anymore");
+    };
+
+    Ok((RedisValue::from_token(token.0, &raw), raw))
+}
+
 pub fn gen_uuid() -> String {
     const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
     let mut rng = thread_rng();
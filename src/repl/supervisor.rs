@@ -0,0 +1,517 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use rand::{thread_rng, Rng};
+
+use crate::{client::RedisClient, server::pubsub::PubSub, RedisValue};
+
+/// A server's address as host and port
+pub type NodeAddr = (String, u16);
+
+/// What the failover supervisor watches and how eagerly it fails over
+#[derive(Clone, Debug)]
+pub struct SupervisorConfig {
+    /// name clients look the master up by, e.g. with SENTINEL GET-MASTER-ADDR-BY-NAME
+    pub name: String,
+    pub master: NodeAddr,
+    pub replicas: Vec<NodeAddr>,
+    /// other supervisors (sentinels) watching the same master
+    pub peers: Vec<NodeAddr>,
+    /// how long the master has to be unreachable before it is considered down
+    pub down_after: Duration,
+    /// observers that have to agree the master is down: the supervisor itself, each
+    /// replica that lost its link to the master and each peer that can't reach it
+    pub quorum: usize,
+}
+impl SupervisorConfig {
+    pub const DEFAULT_NAME: &'static str = "mymaster";
+    pub const DEFAULT_DOWN_AFTER_MS: u64 = 30_000;
+    pub const DEFAULT_QUORUM: usize = 1;
+}
+
+/// Parses a "<host> <port>" node address
+pub fn parse_node_addr(addr: &str) -> Result<NodeAddr> {
+    let Some((host, port)) = addr.trim().split_once(' ') else {
+        bail!("Expected '<host> <port>', got '{}'", addr);
+    };
+    let port = port
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid port in '{}'", addr))?;
+
+    Ok((host.to_string(), port))
+}
+
+/// The supervised master as last seen, shared between the supervisor and SENTINEL commands
+#[derive(Clone, Debug)]
+pub struct MonitoredMaster {
+    pub name: String,
+    pub addr: NodeAddr,
+    pub replicas: Vec<NodeAddr>,
+    pub quorum: usize,
+    pub down_after: Duration,
+    pub num_other_sentinels: usize,
+    /// unreachable for `down_after`
+    pub sdown: bool,
+    /// down as agreed by the quorum
+    pub odown: bool,
+    /// highest election epoch seen
+    pub current_epoch: u64,
+    /// epoch the current master got promoted in, 0 for the configured one
+    pub config_epoch: u64,
+    /// run ID voted for as failover leader, in `leader_epoch`
+    pub leader: Option<String>,
+    pub leader_epoch: u64,
+}
+impl MonitoredMaster {
+    /// Votes for `run_id` to lead the failover of `epoch`, once per epoch and first come
+    /// first served. Returns whoever got the vote for the latest epoch
+    pub fn vote(&mut self, run_id: &str, epoch: u64) -> (Option<String>, u64) {
+        self.current_epoch = self.current_epoch.max(epoch);
+        if epoch > self.leader_epoch {
+            self.leader = Some(run_id.to_string());
+            self.leader_epoch = epoch;
+        }
+
+        (self.leader.clone(), self.leader_epoch)
+    }
+
+    /// Comma separated state flags, as in SENTINEL MASTERS
+    pub fn flags(&self) -> String {
+        let mut flags = vec!["master"];
+        if self.sdown {
+            flags.push("s_down");
+        }
+        if self.odown {
+            flags.push("o_down");
+        }
+
+        flags.join(",")
+    }
+}
+
+/// Identity and shared state of a supervisor, kept by the server for SENTINEL commands
+#[derive(Clone, Debug)]
+pub struct SupervisorHandle {
+    /// identifies this supervisor in leader elections
+    pub run_id: String,
+    pub master: Arc<Mutex<MonitoredMaster>>,
+}
+impl SupervisorHandle {
+    pub fn new(config: &SupervisorConfig, run_id: String) -> Self {
+        let master = MonitoredMaster {
+            name: config.name.clone(),
+            addr: config.master.clone(),
+            replicas: config.replicas.clone(),
+            quorum: config.quorum,
+            down_after: config.down_after,
+            num_other_sentinels: config.peers.len(),
+            sdown: false,
+            odown: false,
+            current_epoch: 0,
+            config_epoch: 0,
+            leader: None,
+            leader_epoch: 0,
+        };
+
+        Self {
+            run_id,
+            master: Arc::new(Mutex::new(master)),
+        }
+    }
+}
+
+/// Minimal built-in Sentinel: probes the master every tick, promotes the most up to date
+/// replica with `REPLICAOF NO ONE` once the master has been down for long enough, and
+/// points every other known server, the old master included, at whoever is master. With
+/// peers, the quorum includes them and the failover is led by the one elected for the epoch
+pub struct Supervisor {
+    config: SupervisorConfig,
+    handle: SupervisorHandle,
+    /// where events like +switch-master are published, on a channel named after them
+    events: Arc<PubSub>,
+    last_seen: Instant,
+    /// no failover attempts before then, after losing an election
+    next_election: Instant,
+}
+impl Supervisor {
+    pub fn new(config: SupervisorConfig, handle: SupervisorHandle, events: Arc<PubSub>) -> Self {
+        Self {
+            config,
+            handle,
+            events,
+            last_seen: Instant::now(),
+            next_election: Instant::now(),
+        }
+    }
+
+    /// Ticks forever, meant to be spawned next to the server it runs in
+    pub async fn run(mut self, period: Duration) {
+        let (name, (host, port)) = self.snapshot();
+        log::info!("+monitor master {} {} {}", name, host, port);
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.tick(period).await;
+        }
+    }
+
+    /// Logs an event and publishes it for clients subscribed to its channel, the way
+    /// Sentinel announces `+sdown` or `+switch-master`
+    fn event(&self, kind: &str, details: String) {
+        log::warn!("{} {}", kind, details);
+        self.events
+            .publish(&Bytes::from(kind.to_string()), &Bytes::from(details));
+    }
+
+    fn snapshot(&self) -> (String, NodeAddr) {
+        let master = self.handle.master.lock().unwrap();
+
+        (master.name.clone(), master.addr.clone())
+    }
+
+    fn replicas(&self) -> Vec<NodeAddr> {
+        self.handle.master.lock().unwrap().replicas.clone()
+    }
+
+    /// One round of checks, probes give up after `probe_timeout`
+    pub async fn tick(&mut self, probe_timeout: Duration) {
+        self.adopt_newer_config(probe_timeout).await;
+        let (name, master) = self.snapshot();
+
+        if probe(&master, probe_timeout).await.is_some() {
+            self.last_seen = Instant::now();
+            {
+                let mut state = self.handle.master.lock().unwrap();
+                if state.sdown {
+                    self.event(
+                        "-sdown",
+                        format!("master {} {} {}", name, master.0, master.1),
+                    );
+                }
+                state.sdown = false;
+                state.odown = false;
+            }
+            self.reconfigure_replicas(probe_timeout).await;
+            return;
+        }
+        if self.last_seen.elapsed() < self.config.down_after {
+            return;
+        }
+
+        if !std::mem::replace(&mut self.handle.master.lock().unwrap().sdown, true) {
+            self.event(
+                "+sdown",
+                format!("master {} {} {}", name, master.0, master.1),
+            );
+        }
+        let mut infos = vec![];
+        for replica in self.replicas() {
+            if let Some(info) = probe(&replica, probe_timeout).await {
+                infos.push((replica, info));
+            }
+        }
+
+        // --- the supervisor itself plus every replica and peer that lost the master
+        let mut observers = 1 + infos
+            .iter()
+            .filter(|(_, info)| {
+                follows(info, &master) && field(info, "master_link_status") == "down"
+            })
+            .count();
+        let epoch = self.handle.master.lock().unwrap().current_epoch;
+        for peer in self.config.peers.iter() {
+            if let Ok((true, _)) = self
+                .ask_peer(peer, &master, epoch, "*", probe_timeout)
+                .await
+            {
+                observers += 1;
+            }
+        }
+        if observers < self.config.quorum {
+            return;
+        }
+        if !std::mem::replace(&mut self.handle.master.lock().unwrap().odown, true) {
+            self.event(
+                "+odown",
+                format!(
+                    "master {} {} {} #quorum {}/{}",
+                    name, master.0, master.1, observers, self.config.quorum
+                ),
+            );
+        }
+
+        if Instant::now() < self.next_election {
+            return;
+        }
+        let Some(epoch) = self.elect_leader(&master, probe_timeout).await else {
+            // --- randomized so split votes don't repeat in lockstep
+            let backoff = self
+                .config
+                .down_after
+                .mul_f64(1.0 + thread_rng().gen::<f64>());
+            self.next_election = Instant::now() + backoff;
+            return;
+        };
+        self.failover(infos, epoch, probe_timeout).await;
+    }
+
+    /// Asks every peer for its vote in a new epoch, the epoch if we got a majority. A
+    /// lonely supervisor leads every failover
+    async fn elect_leader(&self, master: &NodeAddr, probe_timeout: Duration) -> Option<u64> {
+        let run_id = &self.handle.run_id;
+        let (epoch, voted) = {
+            let mut state = self.handle.master.lock().unwrap();
+            let epoch = state.current_epoch + 1;
+            let (leader, _) = state.vote(run_id, epoch);
+            (epoch, leader.as_ref() == Some(run_id))
+        };
+        if self.config.peers.is_empty() {
+            return Some(epoch);
+        }
+        self.event("+new-epoch", epoch.to_string());
+
+        let mut votes = usize::from(voted);
+        for peer in self.config.peers.iter() {
+            let vote = self
+                .ask_peer(peer, master, epoch, run_id, probe_timeout)
+                .await;
+            if let Ok((_, Some((leader, leader_epoch)))) = vote {
+                if leader == *run_id && leader_epoch == epoch {
+                    votes += 1;
+                }
+            }
+        }
+
+        let voters = self.config.peers.len() + 1;
+        let majority = voters / 2 + 1;
+        if votes < majority.max(self.config.quorum) {
+            self.event(
+                "-failover-abort-not-elected",
+                format!("epoch {} votes {}", epoch, votes),
+            );
+            return None;
+        }
+        self.event(
+            "+elected-leader",
+            format!("epoch {} votes {}", epoch, votes),
+        );
+
+        Some(epoch)
+    }
+
+    /// SENTINEL IS-MASTER-DOWN-BY-ADDR on a peer, whether it sees the master as down and
+    /// who it voted for when asked for a vote
+    async fn ask_peer(
+        &self,
+        peer: &NodeAddr,
+        master: &NodeAddr,
+        epoch: u64,
+        run_id: &str,
+        probe_timeout: Duration,
+    ) -> Result<(bool, Option<(String, u64)>)> {
+        let cmd = [
+            "SENTINEL".to_string(),
+            "IS-MASTER-DOWN-BY-ADDR".to_string(),
+            master.0.clone(),
+            master.1.to_string(),
+            epoch.to_string(),
+            run_id.to_string(),
+        ];
+        let RedisValue::Array(reply) = request(peer, cmd, probe_timeout).await? else {
+            bail!("Unexpected reply to IS-MASTER-DOWN-BY-ADDR");
+        };
+        let (
+            Some(RedisValue::Integer(down)),
+            Some(RedisValue::BulkString(leader)),
+            Some(RedisValue::Integer(leader_epoch)),
+        ) = (reply.first(), reply.get(1), reply.get(2))
+        else {
+            bail!("Unexpected reply to IS-MASTER-DOWN-BY-ADDR");
+        };
+
+        let leader = match &leader[..] {
+            b"*" => None,
+            leader => Some((
+                String::from_utf8_lossy(leader).into_owned(),
+                *leader_epoch as u64,
+            )),
+        };
+
+        Ok((*down == 1, leader))
+    }
+
+    /// Switches to the master a peer promoted, told apart by a newer config epoch
+    async fn adopt_newer_config(&self, probe_timeout: Duration) {
+        let (name, current) = self.snapshot();
+        for peer in self.config.peers.iter() {
+            let cmd = ["SENTINEL".to_string(), "MASTER".to_string(), name.clone()];
+            let Ok(RedisValue::Array(reply)) = request(peer, cmd, probe_timeout).await else {
+                continue;
+            };
+            let reply = reply
+                .chunks(2)
+                .filter_map(|pair| match pair {
+                    [RedisValue::BulkString(k), RedisValue::BulkString(v)] => Some((
+                        String::from_utf8_lossy(k).into_owned(),
+                        String::from_utf8_lossy(v).into_owned(),
+                    )),
+                    _ => None,
+                })
+                .collect::<Info>();
+            let (Ok(port), Ok(config_epoch)) = (
+                field(&reply, "port").parse::<u16>(),
+                field(&reply, "config-epoch").parse::<u64>(),
+            ) else {
+                continue;
+            };
+            let addr = (field(&reply, "ip").to_string(), port);
+
+            let mut state = self.handle.master.lock().unwrap();
+            if config_epoch > state.config_epoch && addr != current {
+                self.event(
+                    "+config-update-from",
+                    format!("sentinel {}:{}", peer.0, peer.1),
+                );
+                self.event(
+                    "+switch-master",
+                    format!("{} {} {} {} {}", name, current.0, current.1, addr.0, addr.1),
+                );
+                state.replicas.retain(|replica| *replica != addr);
+                state.replicas.push(current.clone());
+                state.addr = addr;
+                state.config_epoch = config_epoch;
+                state.current_epoch = state.current_epoch.max(config_epoch);
+                state.sdown = false;
+                state.odown = false;
+                return;
+            }
+        }
+    }
+
+    async fn failover(&mut self, infos: Vec<(NodeAddr, Info)>, epoch: u64, timeout: Duration) {
+        let (name, master) = self.snapshot();
+        let Some((promoted, _)) = infos
+            .into_iter()
+            .filter(|(_, info)| follows(info, &master))
+            .max_by_key(|(_, info)| field(info, "slave_repl_offset").parse::<u64>().unwrap_or(0))
+        else {
+            self.event(
+                "-failover-abort-no-good-slave",
+                format!("master {} {} {}", name, master.0, master.1),
+            );
+            return;
+        };
+
+        if let Err(e) = send(&promoted, ["REPLICAOF", "NO", "ONE"], timeout).await {
+            log::error!("Failure promoting {}:{}: {}", promoted.0, promoted.1, e);
+            return;
+        }
+        self.event(
+            "+switch-master",
+            format!(
+                "{} {} {} {} {}",
+                name, master.0, master.1, promoted.0, promoted.1
+            ),
+        );
+
+        // --- the old master is repointed once it comes back
+        {
+            let mut state = self.handle.master.lock().unwrap();
+            state.replicas.retain(|replica| *replica != promoted);
+            state.replicas.push(master);
+            state.addr = promoted;
+            state.config_epoch = epoch;
+            state.sdown = false;
+            state.odown = false;
+        }
+        self.last_seen = Instant::now();
+        self.reconfigure_replicas(timeout).await;
+    }
+
+    /// Points reachable replicas that follow someone else, or nobody, at the master
+    async fn reconfigure_replicas(&self, probe_timeout: Duration) {
+        let (_, master) = self.snapshot();
+        let cmd = [
+            "REPLICAOF".to_string(),
+            master.0.clone(),
+            master.1.to_string(),
+        ];
+        for replica in self.replicas() {
+            let Some(info) = probe(&replica, probe_timeout).await else {
+                continue;
+            };
+            if follows(&info, &master) {
+                continue;
+            }
+
+            match send(&replica, cmd.clone(), probe_timeout).await {
+                Ok(()) => self.event(
+                    "+slave-reconf-sent",
+                    format!("{}:{} to {}:{}", replica.0, replica.1, master.0, master.1),
+                ),
+                Err(e) => log::error!("Failure repointing {}:{}: {}", replica.0, replica.1, e),
+            }
+        }
+    }
+}
+
+type Info = HashMap<String, String>;
+
+fn field<'a>(info: &'a Info, name: &str) -> &'a str {
+    info.get(name).map_or("", String::as_str)
+}
+
+fn follows(info: &Info, master: &NodeAddr) -> bool {
+    field(info, "role") == "slave"
+        && field(info, "master_host") == master.0
+        && field(info, "master_port") == master.1.to_string()
+}
+
+/// INFO replication of a node, `None` when it can't be reached in time
+async fn probe(node: &NodeAddr, timeout: Duration) -> Option<Info> {
+    let Ok(RedisValue::BulkString(info)) = request(node, ["INFO", "replication"], timeout).await
+    else {
+        return None;
+    };
+
+    let res = String::from_utf8_lossy(&info)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+        .collect();
+
+    Some(res)
+}
+
+/// Sends a single command to a node on a new connection
+pub(super) async fn request<I, T>(node: &NodeAddr, cmd: I, timeout: Duration) -> Result<RedisValue>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<Bytes>,
+{
+    let request = async {
+        let mut client = RedisClient::connect((node.0.as_str(), node.1)).await?;
+        client.command(cmd).await
+    };
+
+    tokio::time::timeout(timeout, request).await?
+}
+
+async fn send<I, T>(node: &NodeAddr, cmd: I, timeout: Duration) -> Result<()>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<Bytes>,
+{
+    match request(node, cmd, timeout).await? {
+        RedisValue::SimpleString(_) => Ok(()),
+        RedisValue::SimpleError(e) => bail!("{}", String::from_utf8_lossy(&e)),
+        other => bail!("Unexpected reply: {:?}", other),
+    }
+}
@@ -0,0 +1,545 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::{Mutex, RwLock},
+};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use sha1_smol::Sha1;
+
+use super::{commands::command_spec, handler::RedisValue, session::Session};
+
+/// User every connection starts as, the only one `requirepass` applies to
+pub const DEFAULT_USER: &str = "default";
+
+/// Commands that change how the server runs or look into other clients
+const ADMIN_COMMANDS: &[&str] = &[
+    "CONFIG",
+    "CLIENT",
+    "MONITOR",
+    "SLOWLOG",
+    "ACL",
+    "REPLICAOF",
+    "SLAVEOF",
+    "SENTINEL",
+    "DEBUG",
+    "SAVE",
+    "BGSAVE",
+    "SHUTDOWN",
+];
+const PUBSUB_COMMANDS: &[&str] = &[
+    "SUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "PUBLISH",
+];
+const CONNECTION_COMMANDS: &[&str] = &[
+    "AUTH", "HELLO", "PING", "ECHO", "SELECT", "COMMAND", "QUIT", "RESET",
+];
+const TRANSACTION_COMMANDS: &[&str] = &["MULTI", "EXEC", "DISCARD", "WATCH", "UNWATCH"];
+
+/// Denials of the same kind, by the same user, on the same object are counted in one
+/// entry as long as they come within this many ms of each other
+const GROUPING_WINDOW: u64 = 60_000;
+
+/// Why a client was turned away
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AclDenial {
+    /// wrong credentials
+    Auth,
+    /// command the user may not run
+    Command,
+    /// key outside the user's key patterns
+    Key,
+    /// channel outside the user's channel patterns
+    Channel,
+}
+impl AclDenial {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auth => "auth",
+            Self::Command => "command",
+            Self::Key => "key",
+            Self::Channel => "channel",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AclLogEntry {
+    /// times the denial happened within the grouping window
+    pub count: u64,
+    pub reason: AclDenial,
+    /// command, key or channel that was denied, the user name for `Auth`
+    pub object: String,
+    pub username: String,
+    /// the client as CLIENT INFO describes it
+    pub client_info: String,
+    pub entry_id: u64,
+    /// unix time in ms
+    pub created: u64,
+    pub updated: u64,
+}
+impl AclLogEntry {
+    /// Flat array of field names and values, the RESP2 form of the map ACL LOG replies with
+    pub fn to_value(&self, now: u64) -> RedisValue {
+        let age = now.saturating_sub(self.created) as f64 / 1000.0;
+        let fields = [
+            ("count", RedisValue::Integer(self.count as i64)),
+            ("reason", bulk(self.reason.as_str())),
+            ("context", bulk("toplevel")),
+            ("object", bulk(&self.object)),
+            ("username", bulk(&self.username)),
+            ("age-seconds", bulk(&format!("{:.3}", age))),
+            ("client-info", bulk(&self.client_info)),
+            ("entry-id", RedisValue::Integer(self.entry_id as i64)),
+            (
+                "timestamp-created",
+                RedisValue::Integer(self.created as i64),
+            ),
+            (
+                "timestamp-last-updated",
+                RedisValue::Integer(self.updated as i64),
+            ),
+        ];
+
+        RedisValue::Array(
+            fields
+                .into_iter()
+                .flat_map(|(name, value)| [bulk(name), value])
+                .collect(),
+        )
+    }
+}
+
+fn bulk(s: &str) -> RedisValue {
+    RedisValue::BulkString(Bytes::from(s.to_string()))
+}
+
+/// Recent authentication failures and permission denials, newest first and capped at
+/// `acllog-max-len` entries
+pub struct AclLog {
+    entries: Mutex<VecDeque<AclLogEntry>>,
+    next_id: Mutex<u64>,
+    max_len: usize,
+}
+impl AclLog {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::new()),
+            next_id: Mutex::new(0),
+            max_len,
+        }
+    }
+
+    pub fn record(
+        &self,
+        now: u64,
+        reason: AclDenial,
+        object: &str,
+        username: &str,
+        session: &Session,
+    ) {
+        let mut entries = self.entries.lock().unwrap();
+        let similar = entries.iter_mut().find(|entry| {
+            entry.reason == reason
+                && entry.object == object
+                && entry.username == username
+                && now.saturating_sub(entry.updated) < GROUPING_WINDOW
+        });
+        if let Some(entry) = similar {
+            entry.count += 1;
+            entry.updated = now;
+            entry.client_info = session.client_info();
+            return;
+        }
+        if self.max_len == 0 {
+            return;
+        }
+
+        let entry_id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id - 1
+        };
+        entries.push_front(AclLogEntry {
+            count: 1,
+            reason,
+            object: object.to_string(),
+            username: username.to_string(),
+            client_info: session.client_info(),
+            entry_id,
+            created: now,
+            updated: now,
+        });
+        entries.truncate(self.max_len);
+    }
+
+    /// Up to `count` of the most recent entries
+    pub fn entries(&self, count: usize) -> Vec<AclLogEntry> {
+        let entries = self.entries.lock().unwrap();
+
+        entries.iter().take(count).cloned().collect()
+    }
+
+    pub fn reset(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Groups of commands ACL rules allow or deny at once, e.g. "+@read"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AclCategory {
+    All,
+    /// commands reading the dataset
+    Read,
+    /// commands changing it
+    Write,
+    Admin,
+    Pubsub,
+    Connection,
+    Transaction,
+}
+impl AclCategory {
+    fn parse(name: &str) -> Option<Self> {
+        let res = match name.to_lowercase().as_str() {
+            "all" => Self::All,
+            "read" => Self::Read,
+            "write" => Self::Write,
+            "admin" => Self::Admin,
+            "pubsub" => Self::Pubsub,
+            "connection" => Self::Connection,
+            "transaction" => Self::Transaction,
+            _ => return None,
+        };
+
+        Some(res)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Admin => "admin",
+            Self::Pubsub => "pubsub",
+            Self::Connection => "connection",
+            Self::Transaction => "transaction",
+        }
+    }
+
+    /// Whether the command, by its uppercased name, is part of the category
+    pub fn contains(&self, cmd: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Read => {
+                command_spec(cmd).is_some_and(|spec| !spec.write)
+                    && [
+                        Self::Admin,
+                        Self::Pubsub,
+                        Self::Connection,
+                        Self::Transaction,
+                    ]
+                    .iter()
+                    .all(|other| !other.contains(cmd))
+            }
+            Self::Write => command_spec(cmd).is_some_and(|spec| spec.write),
+            Self::Admin => ADMIN_COMMANDS.contains(&cmd),
+            Self::Pubsub => PUBSUB_COMMANDS.contains(&cmd),
+            Self::Connection => CONNECTION_COMMANDS.contains(&cmd),
+            Self::Transaction => TRANSACTION_COMMANDS.contains(&cmd),
+        }
+    }
+}
+
+/// What a `+` or `-` rule of a user applies to
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum CommandRule {
+    Category(AclCategory),
+    /// uppercased command name
+    Command(String),
+}
+impl CommandRule {
+    fn matches(&self, cmd: &str) -> bool {
+        match self {
+            Self::Category(category) => category.contains(cmd),
+            Self::Command(name) => name == cmd,
+        }
+    }
+}
+
+/// Who may connect with which passwords, and run what
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AclUser {
+    /// whether the user may authenticate at all, "on" or "off"
+    pub enabled: bool,
+    /// any password is accepted, "nopass"
+    pub nopass: bool,
+    /// SHA-1 digests of the passwords, in hex
+    passwords: BTreeSet<String>,
+    /// command rules in the order they were given, the last one matching a command
+    /// deciding whether it may run
+    commands: Vec<(bool, CommandRule)>,
+}
+impl Default for AclUser {
+    /// What ACL SETUSER starts a new user from: disabled, without passwords nor commands
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            nopass: false,
+            passwords: BTreeSet::new(),
+            commands: vec![],
+        }
+    }
+}
+impl AclUser {
+    /// The default user: enabled, allowed everything, and asking for `requirepass` when set
+    fn new_default(requirepass: Option<&str>) -> Self {
+        Self {
+            enabled: true,
+            nopass: requirepass.is_none(),
+            passwords: requirepass.into_iter().map(password_hash).collect(),
+            commands: vec![(true, CommandRule::Category(AclCategory::All))],
+        }
+    }
+
+    /// Applies one ACL SETUSER rule, e.g. "on", ">secret" or "-@write"
+    fn apply(&mut self, rule: &str) -> Result<()> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allcommands" => self.commands = vec![(true, CommandRule::Category(AclCategory::All))],
+            "nocommands" => self.commands.clear(),
+            // --- every user sees every key and channel, the patterns are there for clients
+            // setting them as a matter of course
+            "~*" | "allkeys" | "&*" | "allchannels" => {}
+            "reset" => *self = Self::default(),
+            _ => {
+                let (kind, name) = rule.split_at(rule.chars().next().map_or(0, char::len_utf8));
+                match kind {
+                    ">" => {
+                        self.nopass = false;
+                        self.passwords.insert(password_hash(name));
+                    }
+                    "<" => {
+                        if !self.passwords.remove(&password_hash(name)) {
+                            bail!("no such password");
+                        }
+                    }
+                    "+" | "-" => {
+                        let allow = kind == "+";
+                        let rule = match name.strip_prefix('@') {
+                            Some(category) => match AclCategory::parse(category) {
+                                Some(category) => CommandRule::Category(category),
+                                None => bail!("Unknown command or category name in ACL"),
+                            },
+                            None if command_spec(&name.to_uppercase()).is_some() => {
+                                CommandRule::Command(name.to_uppercase())
+                            }
+                            None => bail!("Unknown command or category name in ACL"),
+                        };
+                        // --- "+@all" and "-@all" override whatever came before
+                        if rule == CommandRule::Category(AclCategory::All) {
+                            self.commands.clear();
+                        }
+                        self.commands.push((allow, rule));
+                    }
+                    _ => bail!("Syntax error"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn check_password(&self, password: &[u8]) -> bool {
+        self.nopass || self.passwords.contains(&password_hash_bytes(password))
+    }
+
+    /// Whether the user may run the command, by its uppercased name
+    pub fn permits(&self, cmd: &str) -> bool {
+        self.commands
+            .iter()
+            .rev()
+            .find(|(_, rule)| rule.matches(cmd))
+            .is_some_and(|(allow, _)| *allow)
+    }
+
+    /// Command rules as ACL LIST and ACL GETUSER show them, e.g. "+@all -flushall"
+    fn commands_str(&self) -> String {
+        let starts_with_all = matches!(
+            self.commands.first(),
+            Some((_, CommandRule::Category(AclCategory::All)))
+        );
+        let mut rules = match starts_with_all {
+            true => vec![],
+            false => vec!["-@all".to_string()],
+        };
+        for (allow, rule) in self.commands.iter() {
+            let sign = if *allow { '+' } else { '-' };
+            rules.push(match rule {
+                CommandRule::Category(category) => format!("{}@{}", sign, category.as_str()),
+                CommandRule::Command(name) => format!("{}{}", sign, name.to_lowercase()),
+            });
+        }
+
+        rules.join(" ")
+    }
+
+    fn flags(&self) -> Vec<&'static str> {
+        let mut res = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            res.push("nopass");
+        }
+
+        res
+    }
+
+    /// The user as one line of ACL LIST, e.g. "user default on nopass ~* &* +@all"
+    pub fn describe(&self, name: &str) -> String {
+        let mut res = format!("user {}", name);
+        for flag in self.flags() {
+            res.push(' ');
+            res.push_str(flag);
+        }
+        for password in self.passwords.iter() {
+            res.push_str(" #");
+            res.push_str(password);
+        }
+        res.push_str(" ~* &* ");
+        res.push_str(&self.commands_str());
+
+        res
+    }
+
+    /// Flat array of field names and values, the RESP2 form of the map ACL GETUSER
+    /// replies with
+    pub fn to_value(&self) -> RedisValue {
+        let fields = [
+            (
+                "flags",
+                RedisValue::Array(self.flags().into_iter().map(bulk).collect()),
+            ),
+            (
+                "passwords",
+                RedisValue::Array(self.passwords.iter().map(|hash| bulk(hash)).collect()),
+            ),
+            ("commands", bulk(&self.commands_str())),
+            ("keys", bulk("~*")),
+            ("channels", bulk("&*")),
+        ];
+
+        RedisValue::Array(
+            fields
+                .into_iter()
+                .flat_map(|(name, value)| [bulk(name), value])
+                .collect(),
+        )
+    }
+}
+
+fn password_hash(password: &str) -> String {
+    password_hash_bytes(password.as_bytes())
+}
+
+fn password_hash_bytes(password: &[u8]) -> String {
+    Sha1::from(password).digest().to_string()
+}
+
+/// Users connections may authenticate as, set up by ACL SETUSER. The default user is
+/// always there
+pub struct AclUsers {
+    users: RwLock<BTreeMap<String, AclUser>>,
+}
+impl AclUsers {
+    pub fn new(requirepass: Option<&str>) -> Self {
+        let users = BTreeMap::from([(DEFAULT_USER.to_string(), AclUser::new_default(requirepass))]);
+
+        Self {
+            users: RwLock::new(users),
+        }
+    }
+
+    /// Gives the default user `requirepass` as its only password, or no password at all
+    /// when `None`, the way CONFIG SET requirepass does
+    pub fn set_requirepass(&self, requirepass: Option<&str>) {
+        let mut users = self.users.write().unwrap();
+        let user = users.entry(DEFAULT_USER.to_string()).or_default();
+        user.nopass = requirepass.is_none();
+        user.passwords = requirepass.into_iter().map(password_hash).collect();
+    }
+
+    /// Whether new connections are the default user right away, without AUTH
+    pub fn default_open(&self) -> bool {
+        self.users
+            .read()
+            .unwrap()
+            .get(DEFAULT_USER)
+            .is_some_and(|user| user.enabled && user.nopass)
+    }
+
+    /// Whether the user exists, is enabled and has that password
+    pub fn authenticate(&self, username: &str, password: &[u8]) -> bool {
+        self.users
+            .read()
+            .unwrap()
+            .get(username)
+            .is_some_and(|user| user.enabled && user.check_password(password))
+    }
+
+    /// Whether the user exists and may log in
+    pub fn is_enabled(&self, username: &str) -> bool {
+        self.users
+            .read()
+            .unwrap()
+            .get(username)
+            .is_some_and(|user| user.enabled)
+    }
+
+    /// Whether the user may run the command, by its uppercased name
+    pub fn permits(&self, username: &str, cmd: &str) -> bool {
+        self.users
+            .read()
+            .unwrap()
+            .get(username)
+            .is_some_and(|user| user.permits(cmd))
+    }
+
+    /// Creates the user or changes an existing one. Rules apply in order and all or none
+    /// of them do, the error naming the first that didn't parse
+    pub fn set_user(&self, username: &str, rules: &[String]) -> Result<()> {
+        let mut users = self.users.write().unwrap();
+        let mut user = users.get(username).cloned().unwrap_or_default();
+        for rule in rules {
+            if let Err(e) = user.apply(rule) {
+                bail!("ERR Error in ACL SETUSER modifier '{}': {}", rule, e);
+            }
+        }
+        users.insert(username.to_string(), user);
+
+        Ok(())
+    }
+
+    pub fn get_user(&self, username: &str) -> Option<AclUser> {
+        self.users.read().unwrap().get(username).cloned()
+    }
+
+    /// Every user as ACL LIST shows them, by name
+    pub fn list(&self) -> Vec<String> {
+        self.users
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, user)| user.describe(name))
+            .collect()
+    }
+}
@@ -0,0 +1,347 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use bytes::BytesMut;
+
+use super::{
+    commands::{execute, CommandContext},
+    handler::RedisValue,
+    rdb::{self, ReplInfo},
+    serde::{tokenize, RESPRaw, RESPToken},
+    server::RedisServer,
+    session::Session,
+};
+
+/// When appended writes are flushed to disk, `appendfsync`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// after every write
+    Always,
+    /// once a second, a crash loses at most the last second of writes
+    #[default]
+    Everysec,
+    /// whenever the OS gets to it
+    No,
+}
+impl FromStr for AppendFsync {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let res = match s.to_lowercase().as_str() {
+            "always" => Self::Always,
+            "everysec" => Self::Everysec,
+            "no" => Self::No,
+            _ => bail!("Invalid appendfsync policy: '{}'", s),
+        };
+
+        Ok(res)
+    }
+}
+impl AppendFsync {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::Everysec => "everysec",
+            Self::No => "no",
+        }
+    }
+}
+
+/// File every write is appended to as a RESP command, `--appendonly`. It only gets opened
+/// once the dataset has been loaded from it
+pub struct AppendOnlyFile {
+    path: PathBuf,
+    fsync: AppendFsync,
+    file: Mutex<Option<OpenAof>>,
+    /// when the file was last synced, unix time in ms
+    last_sync: AtomicU64,
+}
+/// The file commands get appended to
+struct OpenAof {
+    file: File,
+    /// database the commands appended last run against, `None` until one went in
+    selected_db: Option<usize>,
+}
+
+impl AppendOnlyFile {
+    pub fn new(path: PathBuf, fsync: AppendFsync) -> Self {
+        Self {
+            path,
+            fsync,
+            file: Mutex::new(None),
+            last_sync: AtomicU64::new(0),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts a new file holding the dataset as an RDB preamble. It replaces the previous
+    /// file only once fully written
+    pub fn create(&self, preamble: &[u8]) -> Result<()> {
+        let temp = self.path.with_extension("aof.tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(preamble)?;
+        file.sync_all()?;
+        std::fs::rename(&temp, &self.path)?;
+
+        self.reopen(preamble.len() as u64)
+    }
+
+    /// Appends to the existing file from `len` on, dropping whatever torn write follows
+    pub fn reopen(&self, len: u64) -> Result<()> {
+        let file = OpenOptions::new().append(true).open(&self.path)?;
+        file.set_len(len)?;
+        *self.file.lock().unwrap() = Some(OpenAof {
+            file,
+            selected_db: None,
+        });
+
+        Ok(())
+    }
+
+    /// Appends a command run against database `db`, behind a SELECT when the file has
+    /// another database selected. `None` for commands about no database
+    pub fn append(&self, db: Option<usize>, data: &[u8]) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let Some(aof) = file.as_mut() else {
+            bail!("The append only file isn't open yet");
+        };
+        if let Some(db) = db.filter(|db| aof.selected_db != Some(*db)) {
+            let index = db.to_string();
+            write!(
+                aof.file,
+                "*2\r\n$6\r\nSELECT\r\n${}\r\n{}\r\n",
+                index.len(),
+                index
+            )?;
+            aof.selected_db = Some(db);
+        }
+        aof.file.write_all(data)?;
+        if self.fsync == AppendFsync::Always {
+            aof.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    /// Syncs the file once a second under `everysec`, run by the cron
+    pub fn sync_if_due(&self, now: u64) -> Result<()> {
+        if self.fsync != AppendFsync::Everysec
+            || now.saturating_sub(self.last_sync.load(Ordering::Relaxed)) < 1000
+        {
+            return Ok(());
+        }
+        self.last_sync.store(now, Ordering::Relaxed);
+        if let Some(aof) = self.file.lock().unwrap().as_ref() {
+            aof.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RedisServer {
+    /// Rebuilds the dataset from the append-only file, its RDB preamble and then the
+    /// commands after it. Without a file yet, the RDB file is loaded and becomes the
+    /// preamble of a new one. Returns the replication history the dataset was saved with
+    pub async fn load_aof(&self, aof: &AppendOnlyFile) -> Result<Option<ReplInfo>> {
+        let data = match std::fs::read(aof.path()) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let repl_info = self.load_rdbfile().await?;
+                let dataset = self.snapshot().await;
+                let preamble_info = repl_info.clone();
+                let preamble = tokio::task::spawn_blocking(move || {
+                    rdb::serialize(&dataset, preamble_info.as_ref())
+                })
+                .await??;
+                aof.create(&preamble)?;
+                log::info!("Append only file created from the RDB file");
+                return Ok(repl_info);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let report = check(&data);
+        if let Some((offset, e)) = report.corruption {
+            ensure!(
+                report.truncated,
+                "Bad file format reading the append only file at offset {}: {}. Fix it with check-aof --fix",
+                offset,
+                e
+            );
+            log::warn!(
+                "The append only file is truncated at offset {}, loading the {} bytes before",
+                offset,
+                report.valid_len
+            );
+        }
+        let buf = BytesMut::from(&data[..report.valid_len]);
+
+        let mut pos = 0;
+        let mut repl_info = None;
+        if report.rdb_preamble {
+            pos = rdb::walk(&buf, |_, _| Ok(()))? + 8;
+            let preamble = buf[..pos].to_vec();
+            let now = self.clock.now();
+            let (dataset, saved_info) =
+                tokio::task::spawn_blocking(move || rdb::parse_with_repl_info(&preamble, now))
+                    .await??;
+            self.replace_dataset(dataset).await?;
+            repl_info = saved_info;
+        }
+
+        let mut session = Session {
+            is_aof_client: true,
+            ..self.new_session()
+        };
+        let frames = buf.clone().freeze();
+        while let Some(RESPToken(token, next_pos)) = tokenize(&buf, pos)? {
+            pos = next_pos;
+            let (cmd, args) = RedisValue::from_token(token, &frames).get_cmd_and_args();
+            let cmd = String::from_utf8_lossy(&cmd).to_uppercase();
+            // --- transactions of a file written by Redis, applied command by command
+            if matches!(cmd.as_str(), "MULTI" | "EXEC") {
+                continue;
+            }
+            let mut ctx = CommandContext {
+                args: &args,
+                server: self,
+                session: &mut session,
+            };
+            match execute(&cmd, &mut ctx).await {
+                Ok(RedisValue::SimpleError(e)) => log::warn!(
+                    "Append only file command '{}' failed: {}",
+                    cmd,
+                    String::from_utf8_lossy(&e)
+                ),
+                Ok(_) => {}
+                Err(e) => log::error!(
+                    "Failure applying '{}' from the append only file: {}",
+                    cmd,
+                    e
+                ),
+            }
+        }
+        aof.reopen(report.valid_len as u64)?;
+        log::info!(
+            "DB loaded from append only file: {} commands",
+            report.commands
+        );
+
+        Ok(repl_info)
+    }
+}
+
+/// Outcome of `check`
+#[derive(Debug, Default)]
+pub struct AofCheck {
+    /// whether the file starts with an RDB image before the command stream
+    pub rdb_preamble: bool,
+    pub commands: usize,
+    /// length of the longest prefix made only of complete commands and transactions,
+    /// what `--fix` truncates the file to
+    pub valid_len: usize,
+    /// offset of the first corrupt byte and what is wrong with it
+    pub corruption: Option<(usize, anyhow::Error)>,
+    /// whether the corruption is only the end of the file cut short, e.g. by a crash
+    /// in the middle of a write
+    pub truncated: bool,
+}
+
+/// Verifies an append-only file: an optional RDB preamble followed by RESP commands,
+/// each an array of bulk strings. A transaction left open at the end counts as corrupt
+pub fn check(buf: &[u8]) -> AofCheck {
+    let mut report = AofCheck::default();
+
+    let mut pos = 0;
+    if buf.starts_with(b"REDIS") {
+        report.rdb_preamble = true;
+        match rdb::walk(buf, |_, _| Ok(())) {
+            // --- the preamble ends with its 8 byte checksum
+            Ok(end) if end + 8 <= buf.len() => pos = end + 8,
+            Ok(end) => {
+                report.corruption = Some((end, anyhow!("Missing RDB preamble checksum")));
+                return report;
+            }
+            Err(e) => {
+                report.corruption = Some((0, e.context("Invalid RDB preamble")));
+                return report;
+            }
+        }
+    }
+    report.valid_len = pos;
+
+    let buf = BytesMut::from(buf);
+    let mut multi_start = None;
+    while pos < buf.len() {
+        let cmd_start = pos;
+        let (cmd, next_pos) = match parse_command(&buf, pos) {
+            Ok(Some(command)) => command,
+            Ok(None) => {
+                report.corruption = Some((cmd_start, anyhow!("Truncated command")));
+                report.truncated = true;
+                return report;
+            }
+            Err(e) => {
+                report.corruption = Some((cmd_start, e));
+                return report;
+            }
+        };
+        report.commands += 1;
+        pos = next_pos;
+
+        match cmd.to_ascii_uppercase().as_slice() {
+            b"MULTI" if multi_start.is_some() => {
+                report.corruption = Some((cmd_start, anyhow!("Nested MULTI")));
+                return report;
+            }
+            b"MULTI" => multi_start = Some(cmd_start),
+            b"EXEC" | b"DISCARD" => multi_start = None,
+            _ => {}
+        }
+        if multi_start.is_none() {
+            report.valid_len = pos;
+        }
+    }
+
+    if let Some(multi_start) = multi_start {
+        report.corruption = Some((multi_start, anyhow!("Unterminated MULTI")));
+        report.truncated = true;
+    }
+
+    report
+}
+
+/// Tokenizes the command at `pos`, returning its name and where the next one starts.
+/// `None` for a command cut short by the end of the buffer
+fn parse_command(buf: &BytesMut, pos: usize) -> Result<Option<(Vec<u8>, usize)>> {
+    let Some(token) = tokenize(buf, pos)? else {
+        return Ok(None);
+    };
+    let RESPRaw::Array(args) = token.0 else {
+        return Err(anyhow!("Commands must be arrays"));
+    };
+
+    let mut name = None;
+    for arg in args {
+        let RESPRaw::BulkString(tok) = arg else {
+            return Err(anyhow!("Command arguments must be bulk strings"));
+        };
+        name.get_or_insert_with(|| tok.as_slice(buf).to_vec());
+    }
+    let name = name.ok_or_else(|| anyhow!("Empty command"))?;
+
+    Ok(Some((name, token.1)))
+}
@@ -0,0 +1,166 @@
+use std::{
+    borrow::Cow,
+    fs::{File, OpenOptions},
+    io::Write,
+    os::unix::net::UnixDatagram,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use serde::Serialize;
+
+use super::{acl::AclCategory, handler::RedisValue, session::Session};
+
+/// Socket of the local syslog daemon
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// Facility `auth` and severity `notice`, as `<PRI>` in syslog messages
+const SYSLOG_PRIORITY: u8 = 4 * 8 + 5;
+
+/// Kinds of commands the audit log can cover, `--audit-commands`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditCategory {
+    Write,
+    Admin,
+}
+impl FromStr for AuditCategory {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let res = match s.trim().to_lowercase().as_str() {
+            "write" => Self::Write,
+            "admin" => Self::Admin,
+            _ => bail!("Invalid audit category: '{}'", s),
+        };
+
+        Ok(res)
+    }
+}
+impl AuditCategory {
+    /// Parses a comma separated list, e.g. "write,admin"
+    pub fn parse_list(s: &str) -> Result<Vec<Self>> {
+        s.split(',').map(str::parse).collect()
+    }
+
+    fn includes(&self, cmd: &str) -> bool {
+        match self {
+            Self::Write => AclCategory::Write.contains(cmd),
+            Self::Admin => AclCategory::Admin.contains(cmd),
+        }
+    }
+}
+
+/// Where audit entries go, `--audit-log`: a file path or "syslog"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuditTarget {
+    File(String),
+    Syslog,
+}
+impl FromStr for AuditTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let res = match s {
+            "" => bail!("Empty audit log target"),
+            "syslog" => Self::Syslog,
+            path => Self::File(path.to_string()),
+        };
+
+        Ok(res)
+    }
+}
+
+enum AuditSink {
+    File(Mutex<File>),
+    Syslog(UnixDatagram),
+}
+
+/// Records who ran which write and admin commands, one JSON object per entry. Only the
+/// first argument is kept (the key or subcommand), values and secrets stay out of the log
+pub struct AuditLog {
+    sink: AuditSink,
+    categories: Vec<AuditCategory>,
+}
+impl AuditLog {
+    pub fn open(target: &AuditTarget, categories: Vec<AuditCategory>) -> Result<Self> {
+        let sink = match target {
+            AuditTarget::File(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                AuditSink::File(Mutex::new(file))
+            }
+            AuditTarget::Syslog => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(SYSLOG_SOCKET)?;
+                AuditSink::Syslog(socket)
+            }
+        };
+
+        Ok(Self { sink, categories })
+    }
+
+    /// Whether commands of this name are audited
+    pub fn covers(&self, cmd: &str) -> bool {
+        self.categories
+            .iter()
+            .any(|category| category.includes(cmd))
+    }
+
+    /// Logs a command that ran, with how it went. Failures are only logged, an audit sink
+    /// going away doesn't stop the server
+    pub fn record(
+        &self,
+        time: u64,
+        session: &Session,
+        cmd: &str,
+        args: &[Bytes],
+        res: &Result<RedisValue>,
+    ) {
+        let ok = matches!(res, Ok(reply) if !matches!(reply, RedisValue::SimpleError(_)));
+        let entry = AuditEntry {
+            time,
+            client_id: session.id,
+            addr: session
+                .addr
+                .map(|addr| addr.to_string())
+                .unwrap_or_default(),
+            user: session.user(),
+            command: cmd,
+            target: args.first().map(|arg| String::from_utf8_lossy(arg)),
+            result: if ok { "ok" } else { "error" },
+        };
+        let entry = match serde_json::to_string(&entry) {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::error!("Failure serializing audit entry: {}", e);
+                return;
+            }
+        };
+
+        let written = match &self.sink {
+            AuditSink::File(file) => file
+                .lock()
+                .unwrap()
+                .write_all(format!("{}\n", entry).as_bytes()),
+            AuditSink::Syslog(socket) => socket
+                .send(format!("<{}>redis-rust: {}", SYSLOG_PRIORITY, entry).as_bytes())
+                .map(|_| ()),
+        };
+        if let Err(e) = written {
+            log::error!("Failure writing audit entry: {}", e);
+        }
+    }
+}
+
+/// One line of the audit log, serialized as a JSON object in this field order
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    time: u64,
+    client_id: u64,
+    addr: String,
+    user: &'a str,
+    command: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<Cow<'a, str>>,
+    result: &'static str,
+}
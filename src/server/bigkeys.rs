@@ -0,0 +1,154 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::{ensure, Result};
+use bytes::Bytes;
+
+use super::{
+    handler::RedisValue,
+    keyspace::Keyspace,
+    memory::entry_size,
+    server::{Database, Expires, RedisAccessStore, RedisServer},
+};
+
+/// Keys looked at between two turns given to the other tasks
+const SCAN_BATCH: usize = 1000;
+/// Keys reported per type and as hottest when MEMORY ANALYZE doesn't say
+pub const DEFAULT_COUNT: usize = 10;
+
+/// Outcome of a key analysis
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyReport {
+    /// live keys looked at
+    pub scanned: usize,
+    /// largest keys of each type with their size in bytes, largest first
+    pub biggest: BTreeMap<&'static str, Vec<(Bytes, usize)>>,
+    /// most frequently accessed keys with their LFU counter, hottest first
+    pub hottest: Vec<(Bytes, u8)>,
+}
+
+/// The key analysis running in the background, if any, and the last one to finish
+#[derive(Debug, Default)]
+pub struct KeyAnalysis {
+    in_progress: AtomicBool,
+    last_report: Mutex<Option<Arc<KeyReport>>>,
+}
+impl KeyAnalysis {
+    pub fn in_progress(&self) -> bool {
+        self.in_progress.load(Ordering::SeqCst)
+    }
+
+    pub fn last_report(&self) -> Option<Arc<KeyReport>> {
+        self.last_report.lock().unwrap().clone()
+    }
+}
+
+impl RedisServer {
+    /// Looks for the `count` largest keys of each type and the `count` most accessed
+    /// ones of a database from a background task (MEMORY ANALYZE), over a snapshot of it
+    /// so clients don't wait on it. Only one analysis runs at a time
+    pub async fn analyze_keys(&self, db: &Database, count: usize) -> Result<()> {
+        ensure!(
+            !self.key_analysis.in_progress.swap(true, Ordering::SeqCst),
+            "Key analysis already in progress"
+        );
+        let main_store = db.main_store.lock().await.clone();
+        let expire_store = db.expire_store.lock().await.clone();
+
+        let access_store = Arc::clone(&db.access_store);
+        let analysis = Arc::clone(&self.key_analysis);
+        let now = self.clock.now();
+        let lfu_decay_time = self.config.live.read().unwrap().lfu_decay_time;
+        tokio::spawn(async move {
+            let report = scan_keys(
+                &main_store,
+                &expire_store,
+                &access_store,
+                now,
+                lfu_decay_time,
+                count,
+            )
+            .await;
+            log::info!("Key analysis done, {} keys scanned", report.scanned);
+            *analysis.last_report.lock().unwrap() = Some(Arc::new(report));
+            analysis.in_progress.store(false, Ordering::SeqCst);
+        });
+        log::info!("Key analysis started");
+
+        Ok(())
+    }
+}
+
+/// Walks the snapshot a batch at a time, letting the other tasks run in between
+async fn scan_keys(
+    main_store: &Keyspace,
+    expire_store: &Expires,
+    access_store: &RedisAccessStore,
+    now: u64,
+    lfu_decay_time: u64,
+    count: usize,
+) -> KeyReport {
+    let mut biggest: BTreeMap<&'static str, BinaryHeap<Reverse<(usize, Bytes)>>> = BTreeMap::new();
+    let mut hottest = BinaryHeap::new();
+    let mut scanned = 0;
+
+    let mut entries = main_store.iter().peekable();
+    while entries.peek().is_some() {
+        // --- access metadata is live, it is only held for a batch
+        let access = access_store.lock().await;
+        for (key, value) in entries.by_ref().take(SCAN_BATCH) {
+            let RedisValue::BulkString(name) = key else {
+                continue;
+            };
+            if expire_store
+                .get(key)
+                .is_some_and(|timestamp| *timestamp < now)
+            {
+                continue;
+            }
+            scanned += 1;
+            let sizes = biggest.entry(value.type_name()).or_default();
+            keep_top(sizes, (entry_size(key, value), name.clone()), count);
+            if let Some(access) = access.get(key) {
+                let frequency = access.frequency(now, lfu_decay_time);
+                keep_top(&mut hottest, (frequency, name.clone()), count);
+            }
+        }
+        drop(access);
+        tokio::task::yield_now().await;
+    }
+
+    KeyReport {
+        scanned,
+        biggest: biggest
+            .into_iter()
+            .map(|(type_name, sizes)| (type_name, into_ranking(sizes)))
+            .collect(),
+        hottest: into_ranking(hottest),
+    }
+}
+
+/// Adds an item to a heap holding the `count` largest items seen
+fn keep_top<T: Ord>(heap: &mut BinaryHeap<Reverse<T>>, item: T, count: usize) {
+    heap.push(Reverse(item));
+    if heap.len() > count {
+        heap.pop();
+    }
+}
+
+/// Items of a `keep_top` heap, largest first
+fn into_ranking<N>(heap: BinaryHeap<Reverse<(N, Bytes)>>) -> Vec<(Bytes, N)>
+where
+    (N, Bytes): Ord,
+{
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((measure, key))| (key, measure))
+        .collect()
+}
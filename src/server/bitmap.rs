@@ -0,0 +1,101 @@
+/// BITOP operations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+impl BitOp {
+    /// The operation named by an uppercase keyword
+    pub fn parse(keyword: &[u8]) -> Option<Self> {
+        let res = match keyword {
+            b"AND" => Self::And,
+            b"OR" => Self::Or,
+            b"XOR" => Self::Xor,
+            b"NOT" => Self::Not,
+            _ => return None,
+        };
+
+        Some(res)
+    }
+
+    /// Combines the sources byte by byte, shorter ones padded with zeros up to the longest.
+    /// NOT takes the first source alone
+    pub fn apply(self, sources: &[&[u8]]) -> Vec<u8> {
+        let len = sources.iter().map(|source| source.len()).max().unwrap_or(0);
+        let byte = |source: &[u8], i: usize| source.get(i).copied().unwrap_or(0);
+        (0..len)
+            .map(|i| match self {
+                Self::Not => !byte(sources[0], i),
+                Self::And => sources
+                    .iter()
+                    .fold(0xff, |acc, source| acc & byte(source, i)),
+                Self::Or => sources.iter().fold(0, |acc, source| acc | byte(source, i)),
+                Self::Xor => sources.iter().fold(0, |acc, source| acc ^ byte(source, i)),
+            })
+            .collect()
+    }
+}
+
+/// The inclusive range `start..=end` of a `len` long sequence, negative positions counting
+/// from its end. None when it selects nothing
+pub fn range(len: i64, start: i64, end: i64) -> Option<(usize, usize)> {
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+    if end < 0 || start > end {
+        return None;
+    }
+
+    Some((start as usize, end as usize))
+}
+
+/// Bit `offset` of a string, the most significant bit of each byte coming first
+pub fn get_bit(bytes: &[u8], offset: usize) -> bool {
+    bytes
+        .get(offset / 8)
+        .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+/// Sets bit `offset`, the string already long enough, replying its previous value
+pub fn set_bit(bytes: &mut [u8], offset: usize, bit: bool) -> bool {
+    let mask = 0x80 >> (offset % 8);
+    let byte = &mut bytes[offset / 8];
+    let old = *byte & mask != 0;
+    match bit {
+        true => *byte |= mask,
+        false => *byte &= !mask,
+    }
+
+    old
+}
+
+/// How many bits are set between bit offsets `first` and `last`, both inclusive and
+/// within the string
+pub fn count(bytes: &[u8], first: usize, last: usize) -> u64 {
+    (first / 8..=last / 8)
+        .map(|i| (bytes[i] & mask(i, first, last)).count_ones() as u64)
+        .sum()
+}
+
+/// Offset of the first bit equal to `bit` between bit offsets `first` and `last`, both
+/// inclusive and within the string
+pub fn position(bytes: &[u8], bit: bool, first: usize, last: usize) -> Option<usize> {
+    (first / 8..=last / 8).find_map(|i| {
+        let byte = if bit { bytes[i] } else { !bytes[i] };
+        let found = byte & mask(i, first, last);
+        (found != 0).then(|| i * 8 + found.leading_zeros() as usize)
+    })
+}
+
+/// The bits of byte `i` falling between bit offsets `first` and `last`
+fn mask(i: usize, first: usize, last: usize) -> u8 {
+    let from = first.max(i * 8) - i * 8;
+    let to = last.min(i * 8 + 7) - i * 8;
+
+    (0xff >> from) & (0xff << (7 - to))
+}
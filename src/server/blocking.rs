@@ -0,0 +1,262 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tokio::sync::oneshot;
+
+use super::{commands::End, handler::RedisValue};
+
+/// Reply to a blocking command ended with `CLIENT UNBLOCK <id> ERROR`
+pub const UNBLOCKED_ERROR: &[u8] = b"UNBLOCKED client unblocked via CLIENT UNBLOCK";
+
+/// Why a blocked client got back control
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Wakeup {
+    /// a write made this key worth retrying the blocking command on
+    KeyReady(RedisValue),
+    /// a push handed an element of the list at this key straight to the blocked BLPOP or
+    /// BRPOP, the element already being off the list
+    Popped { key: RedisValue, element: Bytes },
+    /// the block timeout went by
+    Timeout,
+    /// released by `CLIENT UNBLOCK`, `error` asks for an `UNBLOCKED_ERROR` reply instead
+    /// of the one a timeout gets
+    Unblocked { error: bool },
+}
+
+struct Waiter {
+    /// tells apart successive blocks of the same client
+    seq: u64,
+    /// database the keys are in
+    db: usize,
+    keys: Vec<RedisValue>,
+    /// end of the list a BLPOP or BRPOP pops from, `None` for the other blocking commands
+    pops: Option<End>,
+    wake: oneshot::Sender<Wakeup>,
+}
+
+#[derive(Default)]
+struct Registry {
+    last_seq: u64,
+    /// by client ID, a client blocks on one command at a time
+    waiters: HashMap<u64, Waiter>,
+    /// client IDs per database and key, in the order they blocked
+    by_key: HashMap<(usize, RedisValue), VecDeque<u64>>,
+}
+impl Registry {
+    /// Drops a waiter from every index, handing back its wake channel if it was still there
+    fn remove(&mut self, id: u64) -> Option<oneshot::Sender<Wakeup>> {
+        let waiter = self.waiters.remove(&id)?;
+        for key in waiter.keys {
+            let key = (waiter.db, key);
+            if let Some(queue) = self.by_key.get_mut(&key) {
+                queue.retain(|waiting| *waiting != id);
+                if queue.is_empty() {
+                    self.by_key.remove(&key);
+                }
+            }
+        }
+
+        Some(waiter.wake)
+    }
+
+    /// Same as `remove`, as long as the client is still in the block that got `seq`
+    fn remove_block(&mut self, id: u64, seq: u64) {
+        if self
+            .waiters
+            .get(&id)
+            .is_some_and(|waiter| waiter.seq == seq)
+        {
+            self.remove(id);
+        }
+    }
+}
+
+/// Every connection parked in a blocking command (BLPOP, XREAD BLOCK, WAIT, ...). Blocking
+/// commands register here and write commands signal the keys they touched, so all of them
+/// share the same wakeup order and timeout handling
+#[derive(Clone, Default)]
+pub struct BlockedClients {
+    registry: Arc<Mutex<Registry>>,
+}
+impl BlockedClients {
+    /// Parks a client on the given keys of a database, an empty list for waits that
+    /// aren't about keys
+    pub fn block(&self, id: u64, db: usize, keys: Vec<RedisValue>) -> BlockedClient {
+        self.park(id, db, keys, None)
+    }
+
+    /// Parks a client popping from the lists at the given keys, see `serve_pops`
+    pub fn block_pop(&self, id: u64, db: usize, keys: Vec<RedisValue>, end: End) -> BlockedClient {
+        self.park(id, db, keys, Some(end))
+    }
+
+    fn park(&self, id: u64, db: usize, keys: Vec<RedisValue>, pops: Option<End>) -> BlockedClient {
+        let (wake, woken) = oneshot::channel();
+        let mut registry = self.registry.lock().unwrap();
+        registry.remove(id);
+        registry.last_seq += 1;
+        let seq = registry.last_seq;
+        for key in keys.iter() {
+            registry
+                .by_key
+                .entry((db, key.clone()))
+                .or_default()
+                .push_back(id);
+        }
+        registry.waiters.insert(
+            id,
+            Waiter {
+                seq,
+                db,
+                keys,
+                pops,
+                wake,
+            },
+        );
+
+        BlockedClient {
+            id,
+            seq,
+            woken,
+            clients: self.clone(),
+        }
+    }
+
+    /// Wakes the client that has been blocked on the key the longest. Commands that leave
+    /// the key ready for more after serving it are expected to signal again. Clients in
+    /// BLPOP and BRPOP are left to `serve_pops`
+    pub fn signal_key_ready(&self, db: usize, key: &RedisValue) -> bool {
+        let mut registry = self.registry.lock().unwrap();
+        let Some(id) = registry.by_key.get(&(db, key.clone())).and_then(|queue| {
+            queue
+                .iter()
+                .copied()
+                .find(|id| registry.waiters[id].pops.is_none())
+        }) else {
+            return false;
+        };
+        let wake = registry.remove(id);
+        drop(registry);
+
+        wake.is_some_and(|wake| wake.send(Wakeup::KeyReady(key.clone())).is_ok())
+    }
+
+    /// Wakes every client blocked on the key, for writes all of them can be served by at
+    /// once, e.g. a stream entry each of its readers gets. Returns how many were woken,
+    /// BLPOPs and BRPOPs being left to `serve_pops`
+    pub fn signal_key_ready_all(&self, db: usize, key: &RedisValue) -> usize {
+        let mut registry = self.registry.lock().unwrap();
+        let ids = registry
+            .by_key
+            .get(&(db, key.clone()))
+            .cloned()
+            .unwrap_or_default();
+        let ids = ids
+            .into_iter()
+            .filter(|id| registry.waiters[id].pops.is_none())
+            .collect::<Vec<_>>();
+        let wakes = ids
+            .into_iter()
+            .filter_map(|id| registry.remove(id))
+            .collect::<Vec<_>>();
+        drop(registry);
+
+        wakes
+            .into_iter()
+            .filter_map(|wake| wake.send(Wakeup::KeyReady(key.clone())).ok())
+            .count()
+    }
+
+    /// Hands elements of the list at the key to the clients popping from it, longest
+    /// waiting first, for as long as `pop` takes one off the list for the end each pops
+    /// from. Returns the ends popped from, in order
+    pub fn serve_pops(
+        &self,
+        db: usize,
+        key: &RedisValue,
+        mut pop: impl FnMut(End) -> Option<Bytes>,
+    ) -> Vec<End> {
+        let mut registry = self.registry.lock().unwrap();
+        let waiting = registry
+            .by_key
+            .get(&(db, key.clone()))
+            .cloned()
+            .unwrap_or_default();
+        let mut res = vec![];
+        for id in waiting {
+            let Some(end) = registry.waiters.get(&id).and_then(|waiter| waiter.pops) else {
+                continue;
+            };
+            let Some(element) = pop(end) else {
+                break;
+            };
+            // --- registered waiters still hold their receiver, the send can't fail
+            if let Some(wake) = registry.remove(id) {
+                let _ = wake.send(Wakeup::Popped {
+                    key: key.clone(),
+                    element,
+                });
+            }
+            res.push(end);
+        }
+
+        res
+    }
+
+    /// Releases a blocked client by ID no matter what it waits on, false if it isn't blocked
+    pub fn unblock(&self, id: u64, error: bool) -> bool {
+        let wake = self.registry.lock().unwrap().remove(id);
+
+        wake.is_some_and(|wake| wake.send(Wakeup::Unblocked { error }).is_ok())
+    }
+
+    /// Number of clients currently blocked, `blocked_clients` in INFO
+    pub fn len(&self) -> usize {
+        self.registry.lock().unwrap().waiters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A client's spot in `BlockedClients`, given up when dropped (e.g. the connection closed)
+pub struct BlockedClient {
+    id: u64,
+    seq: u64,
+    woken: oneshot::Receiver<Wakeup>,
+    clients: BlockedClients,
+}
+impl BlockedClient {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Waits for a wakeup, forever when no timeout is given
+    pub async fn wait(mut self, timeout: Option<Duration>) -> Wakeup {
+        let Some(timeout) = timeout else {
+            return (&mut self.woken).await.unwrap_or(Wakeup::Timeout);
+        };
+
+        match tokio::time::timeout(timeout, &mut self.woken).await {
+            Ok(wakeup) => wakeup.unwrap_or(Wakeup::Timeout),
+            // --- a wakeup may have raced the timeout, it wins if it got through
+            Err(_) => {
+                let mut registry = self.clients.registry.lock().unwrap();
+                registry.remove_block(self.id, self.seq);
+                drop(registry);
+                self.woken.try_recv().unwrap_or(Wakeup::Timeout)
+            }
+        }
+    }
+}
+impl Drop for BlockedClient {
+    fn drop(&mut self) {
+        let mut registry = self.clients.registry.lock().unwrap();
+        registry.remove_block(self.id, self.seq);
+    }
+}
@@ -0,0 +1,195 @@
+use std::hash::{Hash, Hasher};
+
+use anyhow::{ensure, Result};
+
+/// Error rate of filters created by BF.ADD and BF.MADD
+pub const DEFAULT_ERROR_RATE: f64 = 0.01;
+/// Capacity of filters created by BF.ADD and BF.MADD
+pub const DEFAULT_CAPACITY: u64 = 100;
+/// How much larger each new layer of a scaling filter is than the previous one
+pub const DEFAULT_EXPANSION: u32 = 2;
+
+/// Error rate of each new layer relative to the previous one, so that the rate of the
+/// whole chain stays within the one asked for
+const TIGHTENING_RATIO: f64 = 0.5;
+/// Largest layer, in bytes, a capacity and error rate may ask for
+const MAX_LAYER_SIZE: usize = 512 * 1024 * 1024;
+
+/// A fixed size Bloom filter, one link of a `BloomFilter`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BloomLayer {
+    pub bits: Vec<u8>,
+    pub hashes: u32,
+    /// items it takes before the error rate goes past the one it was sized for
+    pub capacity: u64,
+    /// items added so far
+    pub count: u64,
+}
+impl BloomLayer {
+    /// Refused rather than allocated when it would take more than `MAX_LAYER_SIZE`
+    fn new(capacity: u64, error_rate: f64) -> Result<Self> {
+        // --- optimal size and number of hashes for the capacity and error rate
+        let bits_per_item = -error_rate.ln() / std::f64::consts::LN_2.powi(2);
+        let bits = (capacity as f64 * bits_per_item).ceil();
+        ensure!(
+            bits.is_finite() && bits <= (MAX_LAYER_SIZE * 8) as f64,
+            "ERR insufficient memory to create filter"
+        );
+        let bits = (bits as usize).max(8);
+        let hashes = (std::f64::consts::LN_2 * bits_per_item).ceil().max(1.0) as u32;
+
+        Ok(Self {
+            bits: vec![0; bits.div_ceil(8)],
+            hashes,
+            capacity,
+            count: 0,
+        })
+    }
+
+    /// Bits of an item, `hashes` of them derived from its two hashes
+    fn positions(&self, (h1, h2): (u64, u64)) -> impl Iterator<Item = usize> + '_ {
+        let bits = self.bits.len() as u64 * 8;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits) as usize)
+    }
+
+    fn contains(&self, hash: (u64, u64)) -> bool {
+        self.positions(hash)
+            .all(|pos| self.bits[pos / 8] & (1 << (pos % 8)) != 0)
+    }
+
+    fn insert(&mut self, hash: (u64, u64)) {
+        let positions = self.positions(hash).collect::<Vec<_>>();
+        for pos in positions {
+            self.bits[pos / 8] |= 1 << (pos % 8);
+        }
+        self.count += 1;
+    }
+}
+
+/// Scalable Bloom filter: a chain of layers, a new one added each time the last one is
+/// full, `expansion` times as large and with a tighter error rate
+#[derive(Clone, Debug)]
+pub struct BloomFilter {
+    /// error rate of the first layer, roughly the one of the whole filter
+    pub error_rate: f64,
+    /// 0 for a filter that refuses items once full, NONSCALING
+    pub expansion: u32,
+    layers: Vec<BloomLayer>,
+}
+// --- compared bit for bit so filters can be held by the stores like any other value
+impl PartialEq for BloomFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.error_rate.to_bits() == other.error_rate.to_bits()
+            && self.expansion == other.expansion
+            && self.layers == other.layers
+    }
+}
+impl Eq for BloomFilter {}
+impl Hash for BloomFilter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.error_rate.to_bits().hash(state);
+        self.expansion.hash(state);
+        self.layers.hash(state);
+    }
+}
+impl BloomFilter {
+    pub fn new(error_rate: f64, capacity: u64, expansion: u32) -> Result<Self> {
+        ensure!(
+            error_rate > 0.0 && error_rate < 1.0,
+            "ERR (0 < error rate range < 1)"
+        );
+        ensure!(capacity > 0, "ERR (capacity should be larger than 0)");
+
+        let res = Self {
+            error_rate,
+            expansion,
+            layers: vec![BloomLayer::new(capacity, error_rate)?],
+        };
+
+        Ok(res)
+    }
+
+    /// Filter read back from a file
+    pub fn from_parts(error_rate: f64, expansion: u32, layers: Vec<BloomLayer>) -> Result<Self> {
+        ensure!(!layers.is_empty(), "Bloom filter without layers");
+        ensure!(
+            layers
+                .iter()
+                .all(|layer| !layer.bits.is_empty() && layer.hashes > 0),
+            "Invalid Bloom filter layer"
+        );
+
+        Ok(Self {
+            error_rate,
+            expansion,
+            layers,
+        })
+    }
+
+    pub fn layers(&self) -> &[BloomLayer] {
+        &self.layers
+    }
+
+    /// Adds an item, returns whether it is new, as far as the filter can tell. Refused
+    /// when the filter is full and doesn't scale
+    pub fn add(&mut self, item: &[u8]) -> Result<bool> {
+        let hash = hash(item);
+        if self.layers.iter().any(|layer| layer.contains(hash)) {
+            return Ok(false);
+        }
+
+        let last = self.layers.last().expect("Filters have at least a layer");
+        if last.count >= last.capacity {
+            ensure!(self.expansion > 0, "ERR non scaling filter is full");
+            let capacity = last.capacity.saturating_mul(self.expansion as u64);
+            let error_rate = self.error_rate * TIGHTENING_RATIO.powi(self.layers.len() as i32);
+            self.layers.push(BloomLayer::new(capacity, error_rate)?);
+        }
+        self.layers
+            .last_mut()
+            .expect("Filters have at least a layer")
+            .insert(hash);
+
+        Ok(true)
+    }
+
+    /// Whether the item may have been added, false positives being possible
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let hash = hash(item);
+        self.layers.iter().any(|layer| layer.contains(hash))
+    }
+
+    /// Items the filter takes before adding a layer, or refusing them
+    pub fn capacity(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.capacity).sum()
+    }
+
+    /// Items added
+    pub fn len(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.count).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes taken by the bits of all layers
+    pub fn size(&self) -> usize {
+        self.layers.iter().map(|layer| layer.bits.len()).sum()
+    }
+}
+
+/// Two hashes of an item to derive its bits from. FNV-1a mixed with splitmix64: filters
+/// are saved to disk, so unlike std's hasher the result must never change
+fn hash(item: &[u8]) -> (u64, u64) {
+    let h1 = item.iter().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    let mut h2 = h1.wrapping_add(0x9e3779b97f4a7c15);
+    h2 = (h2 ^ (h2 >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h2 = (h2 ^ (h2 >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h2 ^= h2 >> 31;
+
+    // --- a step of 0 would give every hash the same bit
+    (h1, h2 | 1)
+}
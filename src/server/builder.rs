@@ -0,0 +1,87 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use crate::Args;
+
+use super::{
+    clock::{Clock, SystemClock},
+    server::RedisServer,
+    snapshot::{MemoryStorage, SnapshotStorage},
+};
+
+/// Programmatic configuration of a server to embed in another program, e.g. a test suite
+/// starting one with `RedisServer::builder().port(0).in_memory().build()`
+pub struct ServerBuilder {
+    args: Args,
+    clock: Arc<dyn Clock>,
+    /// where snapshots go, `dir`/`dbfilename` unless set
+    snapshot_storage: Option<Arc<dyn SnapshotStorage>>,
+}
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::from_args(Args::default())
+    }
+}
+impl ServerBuilder {
+    /// Starts from settings given the way the command line gives them, for those without a
+    /// method here
+    pub fn from_args(args: Args) -> Self {
+        Self {
+            args,
+            clock: Arc::new(SystemClock),
+            snapshot_storage: None,
+        }
+    }
+
+    /// Port to listen on, 0 for any free one, `RedisServer::local_addr` telling which
+    pub fn port(mut self, port: u16) -> Self {
+        self.args.port = Some(port as usize);
+        self
+    }
+
+    /// One more address to listen on, 127.0.0.1 unless any is given
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.args.bind.push(addr.into());
+        self
+    }
+
+    /// Replicates the server listening at that address
+    pub fn replica_of(mut self, master: SocketAddr) -> Self {
+        self.args.replicaof = Some(format!("{} {}", master.ip(), master.port()));
+        self
+    }
+
+    /// Never touches the disk: no snapshot points, no append-only file, and SAVE or
+    /// SHUTDOWN keeping the snapshot in memory
+    pub fn in_memory(mut self) -> Self {
+        self.args.save = Some(String::new());
+        self.args.appendonly = Some(false);
+        self.snapshot_storage = Some(Arc::new(MemoryStorage::default()));
+        self
+    }
+
+    /// Reads time from the given clock, e.g. a `MockClock` driving expiry by hand
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Loads and saves snapshots through the given storage
+    pub fn snapshot_storage(mut self, storage: Arc<dyn SnapshotStorage>) -> Self {
+        self.snapshot_storage = Some(storage);
+        self
+    }
+
+    /// Binds the listeners and loads the dataset, ready for `RedisServer::serve`
+    pub async fn build(self) -> anyhow::Result<Arc<RedisServer>> {
+        match self.snapshot_storage {
+            Some(storage) => RedisServer::init_with_storage(self.args, self.clock, storage).await,
+            None => RedisServer::init_with_clock(self.args, self.clock).await,
+        }
+    }
+}
+
+impl RedisServer {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+}
@@ -0,0 +1,117 @@
+use std::{collections::BTreeMap, net::SocketAddr, sync::Mutex};
+
+use tokio::sync::oneshot;
+
+/// What CLIENT LIST and CLIENT INFO tell about a connection
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientSummary {
+    pub id: u64,
+    pub addr: Option<SocketAddr>,
+    /// when the connection was accepted, unix time in ms
+    pub connected_at: u64,
+    /// `CLIENT SETNAME`
+    pub name: Option<String>,
+    /// command the connection ran last, lowercased
+    pub last_command: Option<String>,
+    /// when it last sent a command, unix time in ms
+    pub last_interaction: u64,
+    /// database it has SELECTed
+    pub db: usize,
+    /// channels it is subscribed to
+    pub channels: usize,
+    /// patterns it is subscribed to
+    pub patterns: usize,
+    /// user it runs commands as
+    pub user: String,
+    /// client library the connection announced, `CLIENT SETINFO LIB-NAME`
+    pub lib_name: Option<String>,
+    /// its version, `CLIENT SETINFO LIB-VER`
+    pub lib_ver: Option<String>,
+    /// identity in the certificate it presented over TLS
+    pub tls_peer: Option<String>,
+}
+impl ClientSummary {
+    /// One line of CLIENT LIST, fields named the way Redis names them
+    pub fn describe(&self, now: u64) -> String {
+        format!(
+            "id={} addr={} name={} age={} idle={} db={} sub={} psub={} cmd={} user={} tls-peer={} lib-name={} lib-ver={}",
+            self.id,
+            self.addr.map(|addr| addr.to_string()).unwrap_or_default(),
+            self.name.as_deref().unwrap_or_default(),
+            now.saturating_sub(self.connected_at) / 1000,
+            now.saturating_sub(self.last_interaction.max(self.connected_at)) / 1000,
+            self.db,
+            self.channels,
+            self.patterns,
+            self.last_command.as_deref().unwrap_or("NULL"),
+            self.user,
+            self.tls_peer.as_deref().unwrap_or_default(),
+            self.lib_name.as_deref().unwrap_or_default(),
+            self.lib_ver.as_deref().unwrap_or_default(),
+        )
+    }
+}
+
+/// A connection being served, and how to tell it to hang up
+#[derive(Debug)]
+struct RegisteredClient {
+    summary: ClientSummary,
+    /// fired by CLIENT KILL, taken once it was
+    kill: Option<oneshot::Sender<()>>,
+}
+
+/// Client connections being served, by client ID
+#[derive(Debug, Default)]
+pub struct ConnectedClients(Mutex<BTreeMap<u64, RegisteredClient>>);
+impl ConnectedClients {
+    /// Adds a connection, returning what resolves once it gets killed
+    pub fn register(&self, client: ClientSummary) -> oneshot::Receiver<()> {
+        let (kill, killed) = oneshot::channel();
+        self.0.lock().unwrap().insert(
+            client.id,
+            RegisteredClient {
+                summary: client,
+                kill: Some(kill),
+            },
+        );
+
+        killed
+    }
+
+    /// Refreshes what is known about a registered client, others are left out
+    pub fn update(&self, client: ClientSummary) {
+        if let Some(entry) = self.0.lock().unwrap().get_mut(&client.id) {
+            entry.summary = client;
+        }
+    }
+
+    pub fn remove(&self, id: u64) {
+        self.0.lock().unwrap().remove(&id);
+    }
+
+    /// Connected clients, oldest connection first
+    pub fn list(&self) -> Vec<ClientSummary> {
+        self.0
+            .lock()
+            .unwrap()
+            .values()
+            .map(|client| client.summary.clone())
+            .collect()
+    }
+
+    /// Tells the clients the filter picks to hang up, returning their IDs. Each connection
+    /// closes once done with the command it may be running
+    pub fn kill(&self, filter: impl Fn(&ClientSummary) -> bool) -> Vec<u64> {
+        let mut clients = self.0.lock().unwrap();
+
+        clients
+            .values_mut()
+            .filter(|client| filter(&client.summary))
+            .filter_map(|client| {
+                let kill = client.kill.take()?;
+                let _ = kill.send(());
+                Some(client.summary.id)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,61 @@
+use rand::Rng;
+
+/// Hash slots keys are spread over, all of them served here
+pub const SLOTS: u16 = 16384;
+
+/// CRC-16/XMODEM lookup table, the hash Redis Cluster assigns slots with
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// This server as the single node of its cluster, `--cluster-enabled`
+#[derive(Debug)]
+pub struct ClusterNode {
+    /// 40 hex characters naming the node, CLUSTER MYID
+    pub id: String,
+}
+impl ClusterNode {
+    pub fn with_random_id() -> Self {
+        let mut rng = rand::thread_rng();
+        let id = (0..40)
+            .map(|_| char::from_digit(rng.gen_range(0..16), 16).unwrap())
+            .collect();
+
+        Self { id }
+    }
+}
+
+/// Slot of a key. Only the part between the first `{` and the `}` after it is hashed
+/// when it isn't empty, so keys sharing that tag share a slot
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tag = key.iter().position(|byte| *byte == b'{').and_then(|open| {
+        let rest = &key[open + 1..];
+        rest.iter()
+            .position(|byte| *byte == b'}')
+            .filter(|close| *close > 0)
+            .map(|close| &rest[..close])
+    });
+
+    crc16(tag.unwrap_or(key)) % SLOTS
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, byte| {
+        (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize]
+    })
+}
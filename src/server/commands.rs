@@ -1,270 +1,9820 @@
 use core::str;
 use std::{
+    collections::{BTreeSet, HashMap, HashSet},
     fmt::Display,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{atomic::Ordering, Arc, LazyLock},
+    time::Instant,
 };
 
-use anyhow::{bail, Result};
+use anyhow::{bail, ensure, Context, Result};
 use bytes::Bytes;
-use tokio::{fs::File, io::AsyncReadExt};
+use rand::seq::{IteratorRandom, SliceRandom};
+use tokio::sync::{mpsc, MutexGuard};
 
-use crate::repl::ServerContext;
+use crate::{
+    alloc,
+    repl::{
+        master::ReplicaEndpoint, replica::switch_master, supervisor::MonitoredMaster, ServerContext,
+    },
+};
+
+use super::{
+    acl::{AclCategory, AclDenial, DEFAULT_USER},
+    bigkeys::{KeyReport, DEFAULT_COUNT},
+    bitmap::{self, BitOp},
+    blocking::{Wakeup, UNBLOCKED_ERROR},
+    bloom::{BloomFilter, DEFAULT_CAPACITY, DEFAULT_ERROR_RATE, DEFAULT_EXPANSION},
+    cluster,
+    db::Db,
+    digest,
+    document::{JsonPath, SetMode},
+    encoding::{HashFields, ListItems, SetMembers},
+    eviction::KeyAccess,
+    expiry::ExpiryMode,
+    glob::glob_match,
+    handler::{RedisConnectionHandler, RedisValue},
+    hyperloglog::HyperLogLog,
+    json,
+    keyspace::Keyspace,
+    lolwut::Schotter,
+    memory::{
+        self, entry_size, hash_field_size, list_item_size, set_member_size, stream_entry_size,
+        zset_member_size,
+    },
+    persistence::ShutdownFlags,
+    plugins::CommandFuture,
+    rdb,
+    scripting::{self, ReplTargets},
+    search::IndexDefinition,
+    serde::Protocol,
+    server::{Database, Dataset, Expires, RedisServer},
+    session::{Session, Transaction},
+    stream::{NewId, Stream, StreamId},
+    timeseries::{Aggregation, Rule, Sample, TimeSeries},
+    zset::{format_score, parse_score, ScoreBound, SortedSet},
+};
+
+pub struct CommandContext<'a> {
+    /// arguments after the command name, borrowed from the parsed request
+    pub args: &'a [Bytes],
+    pub server: &'a RedisServer,
+    pub session: &'a mut Session,
+}
+impl<'a> CommandContext<'a> {
+    /// Database the connection has SELECTed
+    pub fn db(&self) -> &'a Database {
+        &self.server.databases[self.session.db]
+    }
+
+    /// Argument as a key or value of the stores, sharing the request's buffer
+    pub fn arg_value(&self, pos: usize) -> Option<RedisValue> {
+        self.args.get(pos).cloned().map(RedisValue::BulkString)
+    }
+
+    /// Argument parsed as an integer, `None` when missing or not a number
+    pub fn arg_integer(&self, pos: usize) -> Option<i64> {
+        self.args.get(pos).and_then(|arg| parse_integer(arg))
+    }
+
+    /// Argument as an uppercased keyword such as a subcommand or an option
+    pub fn arg_keyword(&self, pos: usize) -> Option<Vec<u8>> {
+        self.args.get(pos).map(|arg| arg.to_ascii_uppercase())
+    }
+
+    /// Argument as text, invalid UTF-8 replaced
+    pub fn arg_str(&self, pos: usize) -> Option<String> {
+        self.args
+            .get(pos)
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+    }
+
+    /// Whether the extensions may run: with `--enable-extensions`, and whatever it says for
+    /// the master's stream and the append-only file, whose writes already ran once
+    pub fn extensions(&self) -> bool {
+        self.server.config.extensions || self.session.is_master_link || self.session.is_aof_client
+    }
+}
+
+/// Commands that can grow the dataset, refused when over `maxmemory` with nothing left to
+/// evict (Redis' denyoom flag)
+const DENYOOM_COMMANDS: &[&str] = &[
+    "SET",
+    "SETEX",
+    "PSETEX",
+    "SETNX",
+    "MSET",
+    "MSETNX",
+    "APPEND",
+    "SETRANGE",
+    "SETBIT",
+    "BITOP",
+    "COPY",
+    "PFADD",
+    "PFMERGE",
+    "LPUSH",
+    "RPUSH",
+    "LINSERT",
+    "LSET",
+    "SORT",
+    "HSET",
+    "SADD",
+    "ZADD",
+    "XADD",
+    "INCR",
+    "DECR",
+    "INCRBY",
+    "DECRBY",
+    "INCRBYFLOAT",
+    "JSON.SET",
+    "TS.CREATE",
+    "TS.ADD",
+    "TS.INCRBY",
+    "BF.RESERVE",
+    "BF.ADD",
+    "BF.MADD",
+];
+
+/// Writes notifying keyspace events about the key they take first
+const KEYSPACE_EVENT_COMMANDS: &[&str] = &[
+    "JSON.SET",
+    "JSON.DEL",
+    "TS.CREATE",
+    "TS.ADD",
+    "TS.INCRBY",
+    "TS.CREATERULE",
+    "TS.DELETERULE",
+    "BF.RESERVE",
+    "BF.ADD",
+    "BF.MADD",
+];
+
+/// Whether a command goes through the log in raft mode: every write, so that all members
+/// apply them in the same order, and the reads of keys, to make them linearizable.
+/// Commands running without the exec lock can't wait in the log, see `execute`
+#[cfg(feature = "raft")]
+fn goes_through_raft(spec: &CommandSpec) -> bool {
+    !spec.unlocked && (spec.write || spec.first_key > 0)
+}
+
+/// The only commands a RESP2 connection in subscribe mode may issue
+const SUBSCRIBE_MODE_COMMANDS: &[&str] = &[
+    "SUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "PING",
+    "QUIT",
+    "RESET",
+];
+
+/// What a Sentinel serves, everything else is unknown in Sentinel mode
+const SENTINEL_MODE_COMMANDS: &[&str] = &[
+    "PING",
+    "AUTH",
+    "HELLO",
+    "INFO",
+    "COMMAND",
+    "ROLE",
+    "CLIENT",
+    "SENTINEL",
+    "SHUTDOWN",
+    "SUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PSUBSCRIBE",
+    "PUNSUBSCRIBE",
+];
+
+/// What a connection may run before it authenticated, never refused by ACL rules either
+const NO_AUTH_COMMANDS: &[&str] = &["AUTH", "HELLO", "QUIT", "RESET"];
+
+/// Commands of this server's own, unknown unless `--enable-extensions` is given
+const EXTENSION_COMMANDS: &[&str] = &["DELIFEQ"];
+
+/// Commands run right away in a transaction rather than queued
+const TRANSACTION_COMMANDS: &[&str] = &["MULTI", "EXEC", "DISCARD", "WATCH", "QUIT", "RESET"];
+
+/// Commands running scripts, which may or may not write
+const SCRIPT_COMMANDS: &[&str] = &["EVAL", "EVALSHA"];
+
+/// Commands whose keys aren't at fixed positions, COMMAND's movablekeys flag
+const MOVABLE_KEYS_COMMANDS: &[&str] = &["LMPOP", "SORT", "XREAD", "EVAL", "EVALSHA"];
+
+/// Commands a script can't run, on top of those skipping the exec lock
+const NO_SCRIPT_COMMANDS: &[&str] = &[
+    "MULTI",
+    "DISCARD",
+    "WATCH",
+    "SCRIPT",
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "MONITOR",
+    "QUIT",
+    "RESET",
+];
+
+/// What may still run while the dataset is loading, the rest gets -LOADING
+const LOADING_OK_COMMANDS: &[&str] = &[
+    "PING",
+    "AUTH",
+    "HELLO",
+    "QUIT",
+    "RESET",
+    "SELECT",
+    "INFO",
+    "COMMAND",
+    "ROLE",
+    "CONFIG",
+    "CLIENT",
+    "REPLICAOF",
+    "SLAVEOF",
+    "SENTINEL",
+    "CLUSTER",
+    "SHUTDOWN",
+];
+
+/// What a replica still serves while its master link is down and `replica-serve-stale-data`
+/// is off, the rest gets -MASTERDOWN
+const STALE_OK_COMMANDS: &[&str] = &[
+    "PING",
+    "AUTH",
+    "HELLO",
+    "QUIT",
+    "RESET",
+    "SELECT",
+    "INFO",
+    "COMMAND",
+    "ROLE",
+    "CONFIG",
+    "CLIENT",
+    "ACL",
+    "REPLICAOF",
+    "SLAVEOF",
+    "REPLCONF",
+    "CLUSTER",
+    "SHUTDOWN",
+];
+
+/// `rename-command` table, commands reachable under another name or not at all
+#[derive(Clone, Debug, Default)]
+pub struct CommandRenames {
+    /// new name -> original name
+    aliases: HashMap<String, String>,
+    /// original names that no longer dispatch
+    hidden: HashSet<String>,
+}
+impl CommandRenames {
+    /// Builds the table from (command, new name) pairs, an empty new name disables the
+    /// command altogether
+    pub fn from_pairs(pairs: &[String]) -> Result<Self> {
+        ensure!(
+            pairs.len().is_multiple_of(2),
+            "Command renames come in <command> <new name> pairs"
+        );
+
+        let mut renames = Self::default();
+        for pair in pairs.chunks(2) {
+            let (cmd, new_name) = (pair[0].to_uppercase(), pair[1].to_uppercase());
+            ensure!(
+                renames.hidden.insert(cmd.clone()),
+                "Command '{}' renamed more than once",
+                cmd
+            );
+            if new_name.is_empty() {
+                continue;
+            }
+            ensure!(
+                renames.aliases.insert(new_name.clone(), cmd).is_none(),
+                "Name '{}' given to more than one command",
+                new_name
+            );
+        }
+
+        Ok(renames)
+    }
+
+    /// Name a command goes by, `None` when it was disabled
+    pub fn current_name<'a>(&'a self, cmd: &'a str) -> Option<&'a str> {
+        if !self.hidden.contains(cmd) {
+            return Some(cmd);
+        }
+
+        self.aliases
+            .iter()
+            .find(|(_, original)| *original == cmd)
+            .map(|(alias, _)| alias.as_str())
+    }
+
+    /// Command an uppercased name dispatches to, `None` when it was renamed away
+    pub fn resolve<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        if let Some(original) = self.aliases.get(name) {
+            return Some(original);
+        }
+        if self.hidden.contains(name) {
+            return None;
+        }
+
+        Some(name)
+    }
+}
+
+/// Runs a single command against the server and returns the reply to send back
+pub async fn execute(cmd: &str, ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let name = cmd.to_uppercase();
+    let refused = match ctx.server.config.command_renames.resolve(&name) {
+        // --- EXEC runs what got past the checks when it was queued, not what scripts call
+        Some(cmd) if ctx.session.in_exec && !ctx.session.in_script => Ok(cmd.to_string()),
+        Some(cmd) => match refusal(cmd, ctx).await {
+            Some(refusal) => Err(refusal),
+            None => Ok(cmd.to_string()),
+        },
+        None => Err(unknown_command(&name, ctx.args)),
+    };
+    let cmd = match refused {
+        Ok(cmd) => cmd,
+        Err(refusal) => {
+            // --- a command refused while queueing spoils the whole transaction
+            if let Some(transaction) = ctx.session.transaction.as_mut() {
+                transaction.aborted = true;
+            }
+            return Ok(refusal);
+        }
+    };
+    if let Some(transaction) = ctx.session.transaction.as_mut() {
+        if !TRANSACTION_COMMANDS.contains(&cmd.as_str()) {
+            transaction.commands.push((cmd, ctx.args.to_vec()));
+            return Ok(RedisValue::SimpleString(Bytes::from_static(b"QUEUED")));
+        }
+    }
+
+    // --- committed entries are applied on the same path as the master's stream
+    #[cfg(feature = "raft")]
+    if let Some(raft) = ctx.server.raft.as_ref() {
+        match command_spec(&cmd) {
+            _ if ctx.session.is_master_link || ctx.session.is_aof_client => {}
+            // --- they replicate on their own once unblocked, past the log
+            Some(spec) if spec.write && spec.unlocked => {
+                return Ok(RedisValue::SimpleError(Bytes::from(format!(
+                    "ERR '{}' is not supported in raft mode",
+                    cmd.to_lowercase()
+                ))));
+            }
+            Some(spec) if goes_through_raft(spec) => {
+                return Ok(raft.submit(ctx.session.db, &cmd, ctx.args).await);
+            }
+            _ => {}
+        }
+    }
+
+    // --- EXEC and scripts already hold it exclusively, blocking commands would hold it
+    // while they wait
+    let spec = command_spec(&cmd);
+    let unlocked = spec.is_some_and(|spec| spec.unlocked);
+    let propagated = spec.is_some_and(CommandSpec::propagated);
+    let exec_guard = match ctx.session.in_exec || ctx.session.in_script || unlocked {
+        true => None,
+        // --- a script past `busy-reply-threshold` keeps it, only SCRIPT KILL gets by
+        false => tokio::select! {
+            biased;
+            guard = ctx.server.exec_lock.read() => Some(guard),
+            () = ctx.server.running_script.overrun() => match cmd == "SCRIPT"
+                && ctx.arg_keyword(0).is_some_and(|sub_cmd| sub_cmd == b"KILL")
+            {
+                true => None,
+                false => return Ok(busy_script()),
+            },
+        },
+    };
+    let fence = match propagated {
+        true => Some(ctx.server.replication_fence.read().await),
+        false => None,
+    };
+    // --- monitors don't see their own commands, nor passwords
+    if ctx.session.monitor.is_none() && !NO_AUTH_COMMANDS.contains(&cmd.as_str()) {
+        let client = match (ctx.session.addr, ctx.session.is_master_link) {
+            (Some(addr), _) => addr.to_string(),
+            (None, true) => "master".to_string(),
+            (None, false) => "local".to_string(),
+        };
+        let now = ctx.server.clock.now();
+        let name = cmd.to_lowercase();
+        ctx.server.monitors.feed(now, &client, &name, ctx.args);
+    }
+    let started = Instant::now();
+    let res = dispatch(&cmd, ctx).await;
+    let duration = started.elapsed().as_micros() as u64;
+    ctx.server.stats.record_command();
+    // --- blocking commands would count the time they waited, AUTH would log passwords
+    let (slower_than, max_len) = {
+        let live = ctx.server.config.live.read().unwrap();
+        (live.slowlog_log_slower_than, live.slowlog_max_len)
+    };
+    if !unlocked
+        && !NO_AUTH_COMMANDS.contains(&cmd.as_str())
+        && slower_than >= 0
+        && duration >= slower_than as u64
+    {
+        let now = ctx.server.clock.now();
+        ctx.server
+            .slowlog
+            .record(now, duration, &cmd, ctx.args, ctx.session, max_len);
+    }
+    let propagate_after = std::mem::take(&mut ctx.session.propagate_after);
+    if propagated
+        && !ctx.session.is_aof_client
+        && !matches!(res, Ok(RedisValue::SimpleError(_)) | Err(_))
+    {
+        if let Ok(reply) = &res {
+            let now = ctx.server.clock.now();
+            let replicated = replicated_args(&cmd, ctx.args, reply, now);
+            // --- everywhere, unless a script narrowed it down with `redis.set_repl`
+            let targets = ctx.session.repl_targets;
+            for (name, args) in replicated.into_iter().chain(propagate_after) {
+                if targets.aof {
+                    append_to_aof(ctx.server, Some(ctx.session.db), name, &args)?;
+                }
+                if targets.replicas {
+                    propagate(ctx.server, Some(ctx.session.db), name, &args)?;
+                }
+            }
+        }
+    }
+    // --- messages reach the subscribers of replicas too, without being part of the dataset
+    // --- nor of any database
+    if cmd == "PUBLISH" && !ctx.session.is_aof_client && res.is_ok() {
+        propagate(ctx.server, None, &cmd, ctx.args)?;
+    }
+    drop(fence);
+    drop(exec_guard);
+    // --- a null reply is a write that didn't happen, e.g. SET NX on an existing key
+    if KEYSPACE_EVENT_COMMANDS.contains(&cmd.as_str())
+        && !matches!(
+            res,
+            Ok(RedisValue::SimpleError(_) | RedisValue::NullBulkString) | Err(_)
+        )
+    {
+        if let Some(key) = ctx.args.first() {
+            let key = RedisValue::BulkString(key.clone());
+            ctx.server
+                .key_changed(ctx.session.db, &cmd.to_lowercase(), &key);
+        }
+    }
+    if let Some(audit) = ctx.server.audit.as_ref().filter(|audit| audit.covers(&cmd)) {
+        let now = ctx.server.clock.now();
+        audit.record(now, ctx.session, &cmd, ctx.args, &res);
+    }
+
+    res
+}
+
+/// Error a command gets instead of running, e.g. while the dataset is loading
+async fn refusal(cmd: &str, ctx: &CommandContext<'_>) -> Option<RedisValue> {
+    if command_spec(cmd).is_none() && ctx.server.custom_commands.get(cmd).is_none() {
+        return Some(unknown_command(cmd, ctx.args));
+    }
+    if ctx.session.auth_pending && !NO_AUTH_COMMANDS.contains(&cmd) {
+        return Some(RedisValue::SimpleError(Bytes::from_static(
+            b"NOAUTH Authentication required.",
+        )));
+    }
+    if ctx.session.protocol == Protocol::Resp2
+        && ctx.session.in_subscribe_mode()
+        && !SUBSCRIBE_MODE_COMMANDS.contains(&cmd)
+    {
+        return Some(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+            cmd.to_lowercase()
+        ))));
+    }
+    if ctx.server.config.sentinel && !SENTINEL_MODE_COMMANDS.contains(&cmd) {
+        return Some(unknown_command(cmd, ctx.args));
+    }
+    if !ctx.extensions() && EXTENSION_COMMANDS.contains(&cmd) {
+        return Some(unknown_command(cmd, ctx.args));
+    }
+    if command_spec(cmd).is_some_and(|spec| !spec.accepts(ctx.args.len())) {
+        return Some(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for '{}' command",
+            cmd.to_lowercase()
+        ))));
+    }
+    // --- the master's stream and the append-only file ran their commands' checks already
+    let user = ctx.session.user();
+    if !ctx.session.is_master_link
+        && !ctx.session.is_aof_client
+        && !NO_AUTH_COMMANDS.contains(&cmd)
+        && !ctx.server.acl_users.permits(user, cmd)
+    {
+        let now = ctx.server.clock.now();
+        let cmd = cmd.to_lowercase();
+        ctx.server
+            .acl_log
+            .record(now, AclDenial::Command, &cmd, user, ctx.session);
+        return Some(RedisValue::SimpleError(Bytes::from(format!(
+            "NOPERM User {} has no permissions to run the '{}' command",
+            user, cmd
+        ))));
+    }
+    // --- the master link is what loads the dataset on a full resync
+    if ctx.server.is_loading()
+        && !ctx.session.is_master_link
+        && !ctx.session.is_aof_client
+        && !LOADING_OK_COMMANDS.contains(&cmd)
+    {
+        return Some(RedisValue::SimpleError(Bytes::from_static(
+            b"LOADING Redis is loading the dataset in memory",
+        )));
+    }
+    if !ctx
+        .server
+        .config
+        .live
+        .read()
+        .unwrap()
+        .replica_serve_stale_data
+        && !STALE_OK_COMMANDS.contains(&cmd)
+        && is_master_link_down(ctx.server)
+    {
+        return Some(RedisValue::SimpleError(Bytes::from_static(
+            b"MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'.",
+        )));
+    }
+    // --- scripts go through, the writes they call are refused one by one
+    if command_spec(cmd).is_some_and(|spec| spec.write)
+        && !SCRIPT_COMMANDS.contains(&cmd)
+        && !ctx.session.is_master_link
+        && !ctx.session.is_aof_client
+        && ctx.server.config.live.read().unwrap().replica_read_only
+        && is_replica(ctx.server)
+    {
+        return Some(RedisValue::SimpleError(Bytes::from_static(
+            b"READONLY You can't write against a read only replica.",
+        )));
+    }
+    // --- the master's stream is applied as is, it already passed the check there
+    if !ctx.session.is_master_link
+        && !ctx.session.is_aof_client
+        && !ctx.server.evict_to_fit(ctx.session.db).await
+        && DENYOOM_COMMANDS.contains(&cmd)
+    {
+        return Some(RedisValue::SimpleError(Bytes::from_static(
+            b"OOM command not allowed when used memory > 'maxmemory'.",
+        )));
+    }
+
+    None
+}
+
+/// What Redis replies to a command it doesn't have
+fn unknown_command(cmd: &str, args: &[Bytes]) -> RedisValue {
+    let args = args
+        .iter()
+        .map(|arg| format!("'{}' ", String::from_utf8_lossy(arg)))
+        .collect::<String>();
+
+    RedisValue::SimpleError(Bytes::from(format!(
+        "ERR unknown command '{}', with args beginning with: {}",
+        cmd.to_lowercase(),
+        args
+    )))
+}
+
+/// Appends a write to the replication stream of a master. Replicas keep their master's
+/// stream as it was received. `db` is the database the write ran against, `None` for
+/// commands about no database such as REPLCONF GETACK
+pub(super) fn propagate(
+    server: &RedisServer,
+    db: Option<usize>,
+    cmd: &str,
+    args: &[Bytes],
+) -> Result<()> {
+    let ServerContext::Master(master) = &*server.server_context.read().unwrap() else {
+        return Ok(());
+    };
+    server
+        .replicas
+        .propagate(&master.backlog, db, &command_frame(cmd, args)?);
+
+    Ok(())
+}
+
+/// Sends the deletion of keys of a database removed in the background, expired or
+/// evicted rather than deleted by a command, to replicas and the append-only file
+pub(super) fn propagate_deletions(
+    server: &RedisServer,
+    db: usize,
+    keys: &[RedisValue],
+) -> Result<()> {
+    for key in keys {
+        let RedisValue::BulkString(key) = key else {
+            continue;
+        };
+        let args = [key.clone()];
+        append_to_aof(server, Some(db), "DEL", &args)?;
+        propagate(server, Some(db), "DEL", &args)?;
+    }
+
+    Ok(())
+}
+
+/// Logs a write to the append-only file, when there is one, see `propagate` for `db`. A
+/// failed write is only logged, the command already went through
+fn append_to_aof(server: &RedisServer, db: Option<usize>, cmd: &str, args: &[Bytes]) -> Result<()> {
+    let Some(aof) = server.aof.as_ref() else {
+        return Ok(());
+    };
+    if let Err(e) = aof.append(db, &command_frame(cmd, args)?) {
+        log::error!("Failure writing the append only file: {}", e);
+    }
+
+    Ok(())
+}
+
+/// A command as RESP, the way replicas and the append-only file get it
+fn command_frame(cmd: &str, args: &[Bytes]) -> Result<Bytes> {
+    let command = RedisValue::Array(
+        std::iter::once(Bytes::from(cmd.to_string()))
+            .chain(args.iter().cloned())
+            .map(RedisValue::BulkString)
+            .collect(),
+    );
+
+    command.serialize()
+}
+
+/// Command and arguments a write goes to replicas and the append-only file as, pinning
+/// down what the master picked on its own and would come out differently when applied
+/// later, e.g. the entry ID of `XADD key *` or the deadline of `SET key value EX 10`.
+/// `None` for a conditional write that didn't happen and has nothing to replicate
+fn replicated_args<'a>(
+    cmd: &'a str,
+    args: &[Bytes],
+    reply: &RedisValue,
+    now: u64,
+) -> Option<(&'a str, Vec<Bytes>)> {
+    let mut res = args.to_vec();
+    if let ("XADD", RedisValue::BulkString(id)) = (cmd, reply) {
+        res[1] = id.clone();
+    }
+    // --- samples at the current time go out at the timestamp the master gave them
+    if let ("TS.ADD", RedisValue::Integer(timestamp)) = (cmd, reply) {
+        res[1] = Bytes::from(timestamp.to_string());
+    }
+    if let ("TS.INCRBY", RedisValue::Integer(timestamp)) = (cmd, reply) {
+        res.truncate(2);
+        res.push(Bytes::from_static(b"TIMESTAMP"));
+        res.push(Bytes::from(timestamp.to_string()));
+    }
+    // --- relative and second based TTLs all go out as PEXPIREAT, the way Redis does
+    let expire_time = match cmd {
+        "EXPIRE" => Some(ExpireTime::Seconds),
+        "PEXPIRE" => Some(ExpireTime::Millis),
+        "EXPIREAT" => Some(ExpireTime::UnixSeconds),
+        _ => None,
+    };
+    let deadline = expire_time
+        .zip(res.get(1).and_then(|amount| parse_integer(amount)))
+        .and_then(|(time, amount)| time.deadline(amount, now));
+    if let Some(deadline) = deadline {
+        res[1] = Bytes::from(deadline.to_string());
+        return Some(("PEXPIREAT", res));
+    }
+    // --- same for SETEX and PSETEX, sent as the SET they amount to
+    let expire_time = match cmd {
+        "SETEX" => Some(ExpireTime::Seconds),
+        "PSETEX" => Some(ExpireTime::Millis),
+        _ => None,
+    };
+    let deadline = expire_time
+        .zip(res.get(1).and_then(|amount| parse_integer(amount)))
+        .and_then(|(time, amount)| time.deadline(amount, now));
+    if let (Some(deadline), [key, _, value]) = (deadline, res.as_slice()) {
+        let args = vec![
+            key.clone(),
+            value.clone(),
+            Bytes::from_static(b"PXAT"),
+            Bytes::from(deadline.to_string()),
+        ];
+        return Some(("SET", args));
+    }
+    // --- GETEX goes out as the TTL change it made, GETDEL as the deletion
+    if cmd == "GETEX" {
+        let expire_time = match res.get(1).map(|option| option.to_ascii_uppercase()) {
+            Some(option) if option == b"EX" => Some(ExpireTime::Seconds),
+            Some(option) if option == b"PX" => Some(ExpireTime::Millis),
+            Some(option) if option == b"EXAT" => Some(ExpireTime::UnixSeconds),
+            Some(option) if option == b"PXAT" => Some(ExpireTime::UnixMillis),
+            Some(option) if option == b"PERSIST" => return Some(("PERSIST", res[..1].to_vec())),
+            _ => None,
+        };
+        let deadline = expire_time
+            .zip(res.get(2).and_then(|amount| parse_integer(amount)))
+            .and_then(|(time, amount)| time.deadline(amount, now));
+        if let Some(deadline) = deadline {
+            return Some((
+                "PEXPIREAT",
+                vec![res[0].clone(), Bytes::from(deadline.to_string())],
+            ));
+        }
+    }
+    if cmd == "GETDEL" {
+        return Some(("DEL", res));
+    }
+    // --- INCRBYFLOAT as the value it came to, rounding being up to the platform
+    if let ("INCRBYFLOAT", RedisValue::BulkString(value)) = (cmd, reply) {
+        return Some((
+            "SET",
+            vec![
+                res[0].clone(),
+                value.clone(),
+                Bytes::from_static(b"KEEPTTL"),
+            ],
+        ));
+    }
+    // --- LMPOP goes out as the pop it made from the one list it took elements from
+    if let ("LMPOP", RedisValue::Array(popped)) = (cmd, reply) {
+        let end = res
+            .first()
+            .and_then(|numkeys| parse_integer(numkeys))
+            .and_then(|numkeys| res.get(numkeys as usize + 1));
+        if let ([RedisValue::BulkString(key), RedisValue::Array(elements)], Some(end)) =
+            (popped.as_slice(), end)
+        {
+            let name = match end.eq_ignore_ascii_case(b"LEFT") {
+                true => "LPOP",
+                false => "RPOP",
+            };
+            return Some((
+                name,
+                vec![key.clone(), Bytes::from(elements.len().to_string())],
+            ));
+        }
+    }
+    // --- SPOP goes out as the removal of the members it drew
+    match (cmd, reply) {
+        ("SPOP", RedisValue::BulkString(member)) => {
+            return Some(("SREM", vec![res[0].clone(), member.clone()]));
+        }
+        ("SPOP", RedisValue::Array(members)) if !members.is_empty() => {
+            let members = members.iter().filter_map(|member| match member {
+                RedisValue::BulkString(member) => Some(member.clone()),
+                _ => None,
+            });
+            return Some(("SREM", res[..1].iter().cloned().chain(members).collect()));
+        }
+        _ => {}
+    }
+    // --- the extensions go out as the plain writes they made, replicas and restarts may
+    // not have them enabled, and not at all when their condition didn't hold
+    match (cmd, reply) {
+        ("DELIFEQ", RedisValue::Integer(1)) => return Some(("DEL", res[..1].to_vec())),
+        ("DELIFEQ", _) => return None,
+        _ => {}
+    }
+    if cmd == "SET" {
+        let now = now as i64;
+        let mut pos = 2;
+        while pos < res.len() {
+            let option = res[pos].to_ascii_uppercase();
+            if option == b"IFEQ" {
+                // --- with GET the reply is the old value, which matched when it was written
+                let written = match reply {
+                    RedisValue::SimpleString(_) => true,
+                    RedisValue::BulkString(old) => res.get(pos + 1) == Some(old),
+                    _ => false,
+                };
+                if !written {
+                    return None;
+                }
+                res.drain(pos..(pos + 2).min(res.len()));
+                continue;
+            }
+            let value = res.get(pos + 1).and_then(|value| parse_integer(value));
+            let deadline = match option.as_slice() {
+                b"EX" => value.map(|secs| now.saturating_add(secs.saturating_mul(1000))),
+                b"PX" => value.map(|ms| now.saturating_add(ms)),
+                b"EXAT" => value.map(|secs| secs.saturating_mul(1000)),
+                b"PXAT" => None,
+                _ => {
+                    pos += 1;
+                    continue;
+                }
+            };
+            if let Some(deadline) = deadline {
+                res[pos] = Bytes::from_static(b"PXAT");
+                res[pos + 1] = Bytes::from(deadline.to_string());
+            }
+            pos += 2;
+        }
+    }
+
+    Some((cmd, res))
+}
+
+/// Runs a command, built-in or custom, once it got past the checks of `execute`
+async fn dispatch(cmd: &str, ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if let Some(spec) = command_spec(cmd) {
+        return (spec.handler)(ctx).await;
+    }
+    match ctx.server.custom_commands.get(cmd) {
+        Some(custom) => custom.call(ctx).await,
+        None => Ok(unknown_command(cmd, ctx.args)),
+    }
+}
+
+/// What a built-in command runs, resolving to its reply
+type Handler = for<'a, 'b> fn(&'a mut CommandContext<'b>) -> CommandFuture<'a>;
+
+/// `max_args` of the commands taking any number of keys, fields or options
+const MANY: usize = usize::MAX;
+
+/// A built-in command: what it runs and what `execute` checks and does around it
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// fewest arguments it takes, its name left out
+    pub min_args: usize,
+    /// most arguments it takes, `MANY` for no limit
+    pub max_args: usize,
+    /// changes the dataset: replicated, logged to the append-only file and audited as a
+    /// write
+    pub write: bool,
+    /// runs without the exec lock: EXEC takes it for itself, the blocking commands would
+    /// hold it while they wait. Writes among them take their locks and replicate on their
+    /// own
+    pub unlocked: bool,
+    /// argument of the first key, 1 for the one after the name and 0 when there are none
+    pub first_key: i64,
+    /// argument of the last key, negative from the end
+    pub last_key: i64,
+    /// arguments from one key to the next
+    pub key_step: i64,
+    handler: Handler,
+}
+impl CommandSpec {
+    const fn read(name: &'static str, min_args: usize, max_args: usize, handler: Handler) -> Self {
+        Self {
+            name,
+            min_args,
+            max_args,
+            write: false,
+            unlocked: false,
+            first_key: 1,
+            last_key: 1,
+            key_step: 1,
+            handler,
+        }
+    }
+
+    const fn write(name: &'static str, min_args: usize, max_args: usize, handler: Handler) -> Self {
+        Self {
+            write: true,
+            ..Self::read(name, min_args, max_args, handler)
+        }
+    }
+
+    const fn unlocked(self) -> Self {
+        Self {
+            unlocked: true,
+            ..self
+        }
+    }
+
+    /// Where the keys are when not only in the first argument, (0, 0, 0) for none
+    const fn keys(self, first_key: i64, last_key: i64, key_step: i64) -> Self {
+        Self {
+            first_key,
+            last_key,
+            key_step,
+            ..self
+        }
+    }
+
+    /// Arguments counting the name, negative when that's the fewest it takes, as COMMAND
+    /// reports it
+    pub fn arity(&self) -> i64 {
+        match self.min_args == self.max_args {
+            true => self.min_args as i64 + 1,
+            false => -(self.min_args as i64 + 1),
+        }
+    }
+
+    /// Whether `execute` replicates the command once it ran, see `replicated_args`
+    pub fn propagated(&self) -> bool {
+        self.write && !self.unlocked
+    }
+
+    /// Whether the command takes that many arguments
+    pub fn accepts(&self, args: usize) -> bool {
+        (self.min_args..=self.max_args).contains(&args)
+    }
+}
+
+/// Built-in commands, RAFT aside as it only exists with the raft feature
+const COMMAND_TABLE: &[CommandSpec] = &[
+    CommandSpec::read("PING", 0, 1, |ctx| Box::pin(ping(ctx))).keys(0, 0, 0),
+    CommandSpec::read("ECHO", 1, 1, |ctx| Box::pin(echo(ctx))).keys(0, 0, 0),
+    CommandSpec::read("AUTH", 1, 2, |ctx| Box::pin(auth(ctx))).keys(0, 0, 0),
+    CommandSpec::read("HELLO", 0, MANY, |ctx| Box::pin(hello(ctx))).keys(0, 0, 0),
+    CommandSpec::read("QUIT", 0, MANY, |ctx| Box::pin(quit(ctx))).keys(0, 0, 0),
+    CommandSpec::read("RESET", 0, 0, |ctx| Box::pin(reset(ctx))).keys(0, 0, 0),
+    CommandSpec::read("INFO", 0, MANY, |ctx| Box::pin(info(ctx))).keys(0, 0, 0),
+    CommandSpec::read("COMMAND", 0, MANY, |ctx| Box::pin(command(ctx))).keys(0, 0, 0),
+    CommandSpec::write("SET", 2, MANY, |ctx| Box::pin(set(ctx))),
+    CommandSpec::write("SETEX", 3, 3, |ctx| Box::pin(setex(ctx, "setex", b"EX"))),
+    CommandSpec::write("PSETEX", 3, 3, |ctx| Box::pin(setex(ctx, "psetex", b"PX"))),
+    CommandSpec::write("SETNX", 2, 2, |ctx| Box::pin(setnx(ctx))),
+    CommandSpec::read("GET", 1, 1, |ctx| Box::pin(get(ctx))),
+    CommandSpec::write("GETEX", 1, MANY, |ctx| Box::pin(getex(ctx))),
+    CommandSpec::write("GETDEL", 1, 1, |ctx| Box::pin(getdel(ctx))),
+    CommandSpec::read("GETRANGE", 3, 3, |ctx| Box::pin(getrange(ctx))),
+    CommandSpec::read("SUBSTR", 3, 3, |ctx| Box::pin(getrange(ctx))),
+    CommandSpec::read("MGET", 1, MANY, |ctx| Box::pin(mget(ctx))).keys(1, -1, 1),
+    CommandSpec::write("MSET", 2, MANY, |ctx| Box::pin(mset(ctx, "mset", false))).keys(1, -1, 2),
+    CommandSpec::write("MSETNX", 2, MANY, |ctx| Box::pin(mset(ctx, "msetnx", true))).keys(1, -1, 2),
+    CommandSpec::write("APPEND", 2, 2, |ctx| Box::pin(append(ctx))),
+    CommandSpec::read("STRLEN", 1, 1, |ctx| Box::pin(strlen(ctx))),
+    CommandSpec::write("SETRANGE", 3, 3, |ctx| Box::pin(setrange(ctx))),
+    CommandSpec::write("SETBIT", 3, 3, |ctx| Box::pin(setbit(ctx))),
+    CommandSpec::read("GETBIT", 2, 2, |ctx| Box::pin(getbit(ctx))),
+    CommandSpec::read("BITCOUNT", 1, 4, |ctx| Box::pin(bitcount(ctx))),
+    CommandSpec::read("BITPOS", 2, 5, |ctx| Box::pin(bitpos(ctx))),
+    CommandSpec::write("BITOP", 3, MANY, |ctx| Box::pin(bitop(ctx))).keys(2, -1, 1),
+    CommandSpec::write("PFADD", 1, MANY, |ctx| Box::pin(pfadd(ctx))),
+    CommandSpec::read("PFCOUNT", 1, MANY, |ctx| Box::pin(pfcount(ctx))).keys(1, -1, 1),
+    CommandSpec::write("PFMERGE", 1, MANY, |ctx| Box::pin(pfmerge(ctx))).keys(1, -1, 1),
+    CommandSpec::write("DEL", 1, MANY, |ctx| Box::pin(del(ctx))).keys(1, -1, 1),
+    CommandSpec::write("INCR", 1, 1, |ctx| Box::pin(incr(ctx))),
+    CommandSpec::write("DECR", 1, 1, |ctx| Box::pin(decr(ctx))),
+    CommandSpec::write("INCRBY", 2, 2, |ctx| Box::pin(incrby(ctx))),
+    CommandSpec::write("DECRBY", 2, 2, |ctx| Box::pin(decrby(ctx))),
+    CommandSpec::write("INCRBYFLOAT", 2, 2, |ctx| Box::pin(incrbyfloat(ctx))),
+    CommandSpec::write("LPUSH", 2, MANY, |ctx| {
+        Box::pin(push(ctx, "lpush", End::Head))
+    }),
+    CommandSpec::write("RPUSH", 2, MANY, |ctx| {
+        Box::pin(push(ctx, "rpush", End::Tail))
+    }),
+    CommandSpec::write("LPOP", 1, 2, |ctx| Box::pin(pop(ctx, "lpop", End::Head))),
+    CommandSpec::write("RPOP", 1, 2, |ctx| Box::pin(pop(ctx, "rpop", End::Tail))),
+    CommandSpec::write("BLPOP", 2, MANY, |ctx| {
+        Box::pin(blocking_pop(ctx, "blpop", End::Head))
+    })
+    .unlocked()
+    .keys(1, -2, 1),
+    CommandSpec::write("BRPOP", 2, MANY, |ctx| {
+        Box::pin(blocking_pop(ctx, "brpop", End::Tail))
+    })
+    .unlocked()
+    .keys(1, -2, 1),
+    CommandSpec::read("LRANGE", 3, 3, |ctx| Box::pin(lrange(ctx))),
+    CommandSpec::read("LLEN", 1, 1, |ctx| Box::pin(llen(ctx))),
+    CommandSpec::write("LINSERT", 4, 4, |ctx| Box::pin(linsert(ctx))),
+    CommandSpec::write("LSET", 3, 3, |ctx| Box::pin(lset(ctx))),
+    CommandSpec::write("LREM", 3, 3, |ctx| Box::pin(lrem(ctx))),
+    CommandSpec::write("LTRIM", 3, 3, |ctx| Box::pin(ltrim(ctx))),
+    CommandSpec::write("LMPOP", 3, MANY, |ctx| Box::pin(lmpop(ctx))).keys(0, 0, 0),
+    CommandSpec::read("LPOS", 2, MANY, |ctx| Box::pin(lpos(ctx))),
+    CommandSpec::write("SORT", 1, MANY, |ctx| Box::pin(sort(ctx, false))),
+    CommandSpec::read("SORT_RO", 1, MANY, |ctx| Box::pin(sort(ctx, true))),
+    CommandSpec::write("HSET", 3, MANY, |ctx| Box::pin(hset(ctx))),
+    CommandSpec::read("HGET", 2, 2, |ctx| Box::pin(hget(ctx))),
+    CommandSpec::write("HDEL", 2, MANY, |ctx| Box::pin(hdel(ctx))),
+    CommandSpec::read("HGETALL", 1, 1, |ctx| Box::pin(hgetall(ctx))),
+    CommandSpec::read("HLEN", 1, 1, |ctx| Box::pin(hlen(ctx))),
+    CommandSpec::read("HEXISTS", 2, 2, |ctx| Box::pin(hexists(ctx))),
+    CommandSpec::write("SADD", 2, MANY, |ctx| Box::pin(sadd(ctx))),
+    CommandSpec::write("SREM", 2, MANY, |ctx| Box::pin(srem(ctx))),
+    CommandSpec::read("SMEMBERS", 1, 1, |ctx| Box::pin(smembers(ctx))),
+    CommandSpec::read("SISMEMBER", 2, 2, |ctx| Box::pin(sismember(ctx))),
+    CommandSpec::read("SMISMEMBER", 2, MANY, |ctx| Box::pin(smismember(ctx))),
+    CommandSpec::read("SRANDMEMBER", 1, 2, |ctx| Box::pin(srandmember(ctx))),
+    CommandSpec::write("SPOP", 1, 2, |ctx| Box::pin(spop(ctx))),
+    CommandSpec::read("SINTER", 1, MANY, |ctx| {
+        Box::pin(set_algebra(ctx, "sinter", SetOperation::Intersection))
+    })
+    .keys(1, -1, 1),
+    CommandSpec::read("SUNION", 1, MANY, |ctx| {
+        Box::pin(set_algebra(ctx, "sunion", SetOperation::Union))
+    })
+    .keys(1, -1, 1),
+    CommandSpec::read("SDIFF", 1, MANY, |ctx| {
+        Box::pin(set_algebra(ctx, "sdiff", SetOperation::Difference))
+    })
+    .keys(1, -1, 1),
+    CommandSpec::write("ZADD", 3, MANY, |ctx| Box::pin(zadd(ctx))),
+    CommandSpec::write("ZREM", 2, MANY, |ctx| Box::pin(zrem(ctx))),
+    CommandSpec::read("ZRANGE", 3, MANY, |ctx| Box::pin(zrange(ctx))),
+    CommandSpec::read("ZRANGEBYSCORE", 3, MANY, |ctx| Box::pin(zrangebyscore(ctx))),
+    CommandSpec::read("ZSCORE", 2, 2, |ctx| Box::pin(zscore(ctx))),
+    CommandSpec::read("ZRANK", 2, MANY, |ctx| Box::pin(zrank(ctx))),
+    CommandSpec::write("XADD", 4, MANY, |ctx| Box::pin(xadd(ctx))),
+    CommandSpec::read("XRANGE", 3, MANY, |ctx| Box::pin(xrange(ctx))),
+    CommandSpec::read("XLEN", 1, 1, |ctx| Box::pin(xlen(ctx))),
+    CommandSpec::read("XREAD", 3, MANY, |ctx| Box::pin(xread(ctx)))
+        .unlocked()
+        .keys(0, 0, 0),
+    CommandSpec::read("EXISTS", 1, MANY, |ctx| Box::pin(exists(ctx))).keys(1, -1, 1),
+    CommandSpec::read("TYPE", 1, 1, |ctx| Box::pin(type_(ctx))),
+    CommandSpec::write("JSON.SET", 3, MANY, |ctx| Box::pin(json_set(ctx))),
+    CommandSpec::read("JSON.GET", 1, MANY, |ctx| Box::pin(json_get(ctx))),
+    CommandSpec::write("JSON.DEL", 1, 2, |ctx| Box::pin(json_del(ctx))),
+    CommandSpec::write("FT.CREATE", 1, MANY, |ctx| Box::pin(ft_create(ctx))).keys(0, 0, 0),
+    CommandSpec::read("FT.SEARCH", 2, MANY, |ctx| Box::pin(ft_search(ctx))).keys(0, 0, 0),
+    CommandSpec::write("FT.DROPINDEX", 1, MANY, |ctx| Box::pin(ft_dropindex(ctx))).keys(0, 0, 0),
+    CommandSpec::read("FT._LIST", 0, 0, |ctx| Box::pin(ft_list(ctx))).keys(0, 0, 0),
+    CommandSpec::write("TS.CREATE", 1, MANY, |ctx| Box::pin(ts_create(ctx))),
+    CommandSpec::write("TS.ADD", 3, MANY, |ctx| Box::pin(ts_add(ctx))),
+    CommandSpec::write("TS.INCRBY", 2, MANY, |ctx| Box::pin(ts_incrby(ctx))),
+    CommandSpec::read("TS.GET", 1, MANY, |ctx| Box::pin(ts_get(ctx))),
+    CommandSpec::read("TS.RANGE", 3, MANY, |ctx| Box::pin(ts_range(ctx))),
+    CommandSpec::write("TS.CREATERULE", 5, MANY, |ctx| Box::pin(ts_createrule(ctx))).keys(1, 2, 1),
+    CommandSpec::write("TS.DELETERULE", 2, 2, |ctx| Box::pin(ts_deleterule(ctx))).keys(1, 2, 1),
+    CommandSpec::write("BF.RESERVE", 3, MANY, |ctx| Box::pin(bf_reserve(ctx))),
+    CommandSpec::write("BF.ADD", 2, 2, |ctx| Box::pin(bf_add(ctx))),
+    CommandSpec::write("BF.MADD", 2, MANY, |ctx| Box::pin(bf_madd(ctx))),
+    CommandSpec::read("BF.EXISTS", 2, 2, |ctx| Box::pin(bf_exists(ctx))),
+    CommandSpec::read("BF.MEXISTS", 2, MANY, |ctx| Box::pin(bf_mexists(ctx))),
+    CommandSpec::read("BF.INFO", 1, 2, |ctx| Box::pin(bf_info(ctx))),
+    CommandSpec::write("DELIFEQ", 2, 2, |ctx| Box::pin(delifeq(ctx))),
+    CommandSpec::write("EXPIRE", 2, MANY, |ctx| {
+        Box::pin(expire(ctx, "expire", ExpireTime::Seconds))
+    }),
+    CommandSpec::write("PEXPIRE", 2, MANY, |ctx| {
+        Box::pin(expire(ctx, "pexpire", ExpireTime::Millis))
+    }),
+    CommandSpec::write("EXPIREAT", 2, MANY, |ctx| {
+        Box::pin(expire(ctx, "expireat", ExpireTime::UnixSeconds))
+    }),
+    CommandSpec::write("PEXPIREAT", 2, MANY, |ctx| {
+        Box::pin(expire(ctx, "pexpireat", ExpireTime::UnixMillis))
+    }),
+    CommandSpec::read("TTL", 1, 1, |ctx| Box::pin(ttl(ctx, "ttl", false))),
+    CommandSpec::read("PTTL", 1, 1, |ctx| Box::pin(ttl(ctx, "pttl", true))),
+    CommandSpec::write("PERSIST", 1, 1, |ctx| Box::pin(persist(ctx))),
+    CommandSpec::read("KEYS", 1, 1, |ctx| Box::pin(keys(ctx))).keys(0, 0, 0),
+    CommandSpec::read("SELECT", 1, 1, |ctx| Box::pin(select(ctx))).keys(0, 0, 0),
+    CommandSpec::write("SWAPDB", 2, 2, |ctx| Box::pin(swapdb(ctx))).keys(0, 0, 0),
+    CommandSpec::write("FLUSHDB", 0, 1, |ctx| Box::pin(flush(ctx, false))).keys(0, 0, 0),
+    CommandSpec::write("FLUSHALL", 0, 1, |ctx| Box::pin(flush(ctx, true))).keys(0, 0, 0),
+    CommandSpec::write("RENAME", 2, 2, |ctx| Box::pin(rename(ctx, false))).keys(1, 2, 1),
+    CommandSpec::write("RENAMENX", 2, 2, |ctx| Box::pin(rename(ctx, true))).keys(1, 2, 1),
+    CommandSpec::write("COPY", 2, MANY, |ctx| Box::pin(copy(ctx))).keys(1, 2, 1),
+    CommandSpec::write("MOVE", 2, 2, |ctx| Box::pin(move_key(ctx))).keys(1, 1, 1),
+    CommandSpec::read("RANDOMKEY", 0, 0, |ctx| Box::pin(randomkey(ctx))).keys(0, 0, 0),
+    CommandSpec::read("DBSIZE", 0, 0, |ctx| Box::pin(dbsize(ctx))).keys(0, 0, 0),
+    CommandSpec::read("MULTI", 0, 0, |ctx| Box::pin(multi(ctx))).keys(0, 0, 0),
+    CommandSpec::read("EXEC", 0, 0, |ctx| Box::pin(exec(ctx)))
+        .unlocked()
+        .keys(0, 0, 0),
+    CommandSpec::read("DISCARD", 0, 0, |ctx| Box::pin(discard(ctx))).keys(0, 0, 0),
+    CommandSpec::read("WATCH", 1, MANY, |ctx| Box::pin(watch(ctx))).keys(1, -1, 1),
+    CommandSpec::read("UNWATCH", 0, 0, |ctx| Box::pin(unwatch(ctx))).keys(0, 0, 0),
+    CommandSpec::write("EVAL", 2, MANY, |ctx| Box::pin(eval(ctx)))
+        .unlocked()
+        .keys(0, 0, 0),
+    CommandSpec::write("EVALSHA", 2, MANY, |ctx| Box::pin(evalsha(ctx)))
+        .unlocked()
+        .keys(0, 0, 0),
+    CommandSpec::read("SCRIPT", 1, MANY, |ctx| Box::pin(script(ctx))).keys(0, 0, 0),
+    CommandSpec::read("SUBSCRIBE", 1, MANY, |ctx| Box::pin(subscribe(ctx, false))).keys(0, 0, 0),
+    CommandSpec::read("UNSUBSCRIBE", 0, MANY, |ctx| {
+        Box::pin(unsubscribe(ctx, false))
+    })
+    .keys(0, 0, 0),
+    CommandSpec::read("PSUBSCRIBE", 1, MANY, |ctx| Box::pin(subscribe(ctx, true))).keys(0, 0, 0),
+    CommandSpec::read("PUNSUBSCRIBE", 0, MANY, |ctx| {
+        Box::pin(unsubscribe(ctx, true))
+    })
+    .keys(0, 0, 0),
+    CommandSpec::read("PUBLISH", 2, 2, |ctx| Box::pin(publish(ctx))).keys(0, 0, 0),
+    CommandSpec::read("MONITOR", 0, 0, |ctx| Box::pin(monitor(ctx))).keys(0, 0, 0),
+    CommandSpec::read("SLOWLOG", 1, 2, |ctx| Box::pin(slowlog(ctx))).keys(0, 0, 0),
+    CommandSpec::read("SCAN", 1, MANY, |ctx| Box::pin(scan(ctx))).keys(0, 0, 0),
+    // --- ACKs keep coming in while a shutdown holds off commands to wait for them
+    CommandSpec::read("REPLCONF", 0, MANY, |ctx| Box::pin(replconf(ctx)))
+        .unlocked()
+        .keys(0, 0, 0),
+    CommandSpec::read("WAIT", 2, 2, |ctx| Box::pin(wait(ctx)))
+        .unlocked()
+        .keys(0, 0, 0),
+    CommandSpec::read("CONFIG", 1, MANY, |ctx| Box::pin(config(ctx))).keys(0, 0, 0),
+    CommandSpec::read("CLIENT", 1, MANY, |ctx| Box::pin(client(ctx))).keys(0, 0, 0),
+    CommandSpec::read("ACL", 1, MANY, |ctx| Box::pin(acl(ctx))).keys(0, 0, 0),
+    CommandSpec::read("REPLICAOF", 2, 2, |ctx| Box::pin(replicaof(ctx))).keys(0, 0, 0),
+    CommandSpec::read("SLAVEOF", 2, 2, |ctx| Box::pin(replicaof(ctx))).keys(0, 0, 0),
+    CommandSpec::read("ROLE", 0, 0, |ctx| Box::pin(role(ctx))).keys(0, 0, 0),
+    CommandSpec::read("SENTINEL", 1, MANY, |ctx| Box::pin(sentinel(ctx))).keys(0, 0, 0),
+    CommandSpec::read("CLUSTER", 1, MANY, |ctx| Box::pin(cluster(ctx))).keys(0, 0, 0),
+    CommandSpec::read("DEBUG", 1, MANY, |ctx| Box::pin(debug(ctx))).keys(0, 0, 0),
+    CommandSpec::read("LOLWUT", 0, MANY, |ctx| Box::pin(lolwut(ctx))).keys(0, 0, 0),
+    CommandSpec::read("OBJECT", 1, MANY, |ctx| Box::pin(object(ctx))).keys(2, 2, 1),
+    CommandSpec::read("MEMORY", 1, MANY, |ctx| Box::pin(memory(ctx))).keys(0, 0, 0),
+    CommandSpec::read("SAVE", 0, 0, |ctx| Box::pin(save(ctx))).keys(0, 0, 0),
+    CommandSpec::read("BGSAVE", 0, 1, |ctx| Box::pin(bgsave(ctx))).keys(0, 0, 0),
+    CommandSpec::read("LASTSAVE", 0, 0, |ctx| Box::pin(lastsave(ctx))).keys(0, 0, 0),
+    CommandSpec::read("SHUTDOWN", 0, MANY, |ctx| Box::pin(shutdown(ctx)))
+        .unlocked()
+        .keys(0, 0, 0),
+];
+
+/// Built-in commands by uppercased name
+static COMMANDS: LazyLock<HashMap<&'static str, &'static CommandSpec>> = LazyLock::new(|| {
+    #[allow(unused_mut)]
+    let mut res = COMMAND_TABLE
+        .iter()
+        .map(|spec| (spec.name, spec))
+        .collect::<HashMap<_, _>>();
+    #[cfg(feature = "raft")]
+    res.insert("RAFT", &RAFT_COMMAND);
+
+    res
+});
+
+#[cfg(feature = "raft")]
+static RAFT_COMMAND: CommandSpec =
+    CommandSpec::read("RAFT", 1, MANY, |ctx| Box::pin(raft(ctx))).keys(0, 0, 0);
+
+/// The built-in command going by an uppercased name, `None` for custom and unknown ones
+pub fn command_spec(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.get(name).copied()
+}
+
+impl RedisValue {
+    /// Splits a request, an array of bulk strings, into the command name and its arguments
+    pub fn get_cmd_and_args(self) -> (Bytes, Vec<Bytes>) {
+        let request = match self {
+            RedisValue::Array(arr) => arr,
+            _ => panic!("Incoming array should be an array"),
+        };
+
+        let mut parts = request.into_iter().map(|part| match part {
+            RedisValue::BulkString(b) => b,
+            _ => panic!("Request items should be bulk strings"),
+        });
+        let cmd = parts.next().unwrap();
+        let args = parts.collect();
+
+        (cmd, args)
+    }
+}
+
+fn get_argument(pos: usize, args: &[Bytes]) -> &Bytes {
+    args.get(pos).expect("No key specified for SET command")
+}
+
+pub async fn ping(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let message = ctx.arg_value(0);
+
+    let subscribed = ctx.session.protocol == Protocol::Resp2 && ctx.session.in_subscribe_mode();
+    let res = match (subscribed, message) {
+        // --- subscribed RESP2 connections reply with a pub/sub style array
+        (true, message) => RedisValue::Array(vec![
+            RedisValue::BulkString(Bytes::from_static(b"pong")),
+            message.unwrap_or(RedisValue::BulkString(Bytes::new())),
+        ]),
+        (false, Some(message)) => message,
+        (false, None) => RedisValue::SimpleString(Bytes::from_static(b"PONG")),
+    };
+
+    Ok(res)
+}
+
+/// QUIT: replies OK, then the connection is closed
+pub async fn quit(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    ctx.session.quitting = true;
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// RESET: puts the connection back the way it connected: out of its transaction, its
+/// subscriptions and MONITOR, its WATCHes and name forgotten, in RESP2 as the default
+/// user on database 0
+pub async fn reset(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    ctx.session.transaction = None;
+    forget_watched(ctx);
+    for channel in std::mem::take(&mut ctx.session.channels) {
+        ctx.server.pubsub.unsubscribe(ctx.session.id, &channel);
+    }
+    for pattern in std::mem::take(&mut ctx.session.patterns) {
+        ctx.server.pubsub.punsubscribe(ctx.session.id, &pattern);
+    }
+    ctx.session.monitor = None;
+    ctx.session.protocol = Protocol::Resp2;
+    ctx.session.name = None;
+    ctx.session.db = 0;
+    ctx.session.user = None;
+    ctx.session.auth_pending = !ctx.server.acl_users.default_open();
+    ctx.session.no_evict = false;
+    ctx.session.no_touch = false;
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"RESET"));
+
+    Ok(res)
+}
+
+pub async fn echo(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let res = RedisValue::BulkString(get_argument(0, ctx.args).clone());
+
+    Ok(res)
+}
+
+/// AUTH [username] password: runs the connection's commands as that user from then on,
+/// the password alone being the default user's
+pub async fn auth(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let args = ctx.args;
+    let (username, password) = match args {
+        [password] => {
+            let default = ctx.server.acl_users.get_user(DEFAULT_USER);
+            if default.is_some_and(|user| user.nopass) {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR AUTH <password> called without any password configured for the default user. Are you sure your client is configured correctly?",
+                )));
+            }
+            (DEFAULT_USER.to_string(), password)
+        }
+        [username, password] => (String::from_utf8_lossy(username).into_owned(), password),
+        _ => unreachable!("Arity is checked before dispatch"),
+    };
+    let res = match authenticate(ctx, username, password) {
+        Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+        Err(refusal) => refusal,
+    };
+
+    Ok(res)
+}
+
+/// Switches the connection to the user when the password is right, otherwise logs the
+/// failure for ACL LOG
+fn authenticate(
+    ctx: &mut CommandContext<'_>,
+    username: String,
+    password: &[u8],
+) -> Result<(), RedisValue> {
+    if !ctx.server.acl_users.authenticate(&username, password) {
+        let now = ctx.server.clock.now();
+        ctx.server
+            .acl_log
+            .record(now, AclDenial::Auth, "AUTH", &username, ctx.session);
+        return Err(RedisValue::SimpleError(Bytes::from_static(
+            b"WRONGPASS invalid username-password pair or user is disabled.",
+        )));
+    }
+    ctx.session.user = Some(username);
+    ctx.session.auth_pending = false;
+
+    Ok(())
+}
+
+/// HELLO [protover [AUTH username password]]: switches the connection to RESP2 or RESP3,
+/// replying with what the server is. AUTH authenticates on the way, connections that
+/// still have to can't HELLO otherwise
+pub async fn hello(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let protocol = match ctx.args.first() {
+        None => ctx.session.protocol,
+        Some(_) => match ctx.arg_integer(0) {
+            Some(2) => Protocol::Resp2,
+            Some(3) => Protocol::Resp3,
+            Some(_) => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"NOPROTO unsupported protocol version",
+                )))
+            }
+            None => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR Protocol version is not an integer or out of range",
+                )))
+            }
+        },
+    };
+    let mut pos = 1;
+    let mut name = None;
+    while pos < ctx.args.len() {
+        match ctx.arg_keyword(pos).as_deref() {
+            Some(b"AUTH") if ctx.args.len() >= pos + 3 => {
+                let username = String::from_utf8_lossy(&ctx.args[pos + 1]).into_owned();
+                let args = ctx.args;
+                if let Err(refusal) = authenticate(ctx, username, &args[pos + 2]) {
+                    return Ok(refusal);
+                }
+                pos += 3;
+            }
+            Some(b"SETNAME") if ctx.args.len() >= pos + 2 => {
+                match client_name(&ctx.args[pos + 1]) {
+                    Ok(value) => name = Some(value),
+                    Err(refusal) => return Ok(refusal),
+                }
+                pos += 2;
+            }
+            _ => {
+                return Ok(RedisValue::SimpleError(Bytes::from(format!(
+                    "ERR Syntax error in HELLO option '{}'",
+                    String::from_utf8_lossy(&ctx.args[pos])
+                ))))
+            }
+        }
+    }
+    if ctx.session.auth_pending {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time",
+        )));
+    }
+    ctx.session.protocol = protocol;
+    if let Some(name) = name {
+        ctx.session.name = name;
+        ctx.server.clients.update(ctx.session.summary());
+    }
+
+    let mode = match ctx.server.config.sentinel {
+        true => "sentinel",
+        false => "standalone",
+    };
+    let role = match *ctx.server.server_context.read().unwrap() {
+        ServerContext::Master(_) => "master",
+        ServerContext::Replica(_) => "replica",
+    };
+    let fields = [
+        (
+            "server",
+            RedisValue::BulkString(Bytes::from_static(b"redis")),
+        ),
+        (
+            "version",
+            RedisValue::BulkString(Bytes::from_static(env!("CARGO_PKG_VERSION").as_bytes())),
+        ),
+        ("proto", RedisValue::Integer(protocol.version())),
+        ("id", RedisValue::Integer(ctx.session.id as i64)),
+        (
+            "mode",
+            RedisValue::BulkString(Bytes::from_static(mode.as_bytes())),
+        ),
+        (
+            "role",
+            RedisValue::BulkString(Bytes::from_static(role.as_bytes())),
+        ),
+        ("modules", RedisValue::Array(vec![])),
+    ];
+    let res = RedisValue::Map(
+        fields
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    RedisValue::BulkString(Bytes::from_static(name.as_bytes())),
+                    value,
+                )
+            })
+            .collect(),
+    );
+
+    Ok(res)
+}
+
+/// Options of SET, past the key and the value
+#[derive(Debug, Default)]
+struct SetOptions {
+    /// unix time in ms the key expires at, EX, PX, EXAT or PXAT
+    expire_at: Option<u64>,
+    /// the key keeps the TTL it had, KEEPTTL, instead of losing it
+    keep_ttl: bool,
+    condition: Option<SetCondition>,
+    /// reply with the value the key held, GET
+    get: bool,
+}
+
+/// When SET writes at all
+#[derive(Debug)]
+enum SetCondition {
+    /// only when the key doesn't exist
+    Nx,
+    /// only when the key exists
+    Xx,
+    /// only over a string equal to the one given, an extension
+    IfEq(Bytes),
+}
+
+/// SET key value [NX|XX] [GET] [EX s|PX ms|EXAT unix-s|PXAT unix-ms|KEEPTTL]
+pub async fn set(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(value)) = (ctx.arg_value(0), ctx.arg_value(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'set' command",
+        )));
+    };
+    let options = match parse_set_options(ctx) {
+        Ok(options) => options,
+        Err(e) => return Ok(e),
+    };
+
+    set_key(ctx, key, value, options).await
+}
+
+/// Writes a string the way SET does with the given options, replying like SET
+async fn set_key(
+    ctx: &CommandContext<'_>,
+    key: RedisValue,
+    value: RedisValue,
+    options: SetOptions,
+) -> Result<RedisValue> {
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let needs_string = options.get || matches!(options.condition, Some(SetCondition::IfEq(_)));
+    let (exists, old_string) = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(value) => match value.as_string() {
+            Some(old) => (true, Some(old)),
+            None if needs_string => return Ok(wrong_type()),
+            None => (true, None),
+        },
+        None => (false, None),
+    };
+    let allowed = match &options.condition {
+        None => true,
+        Some(SetCondition::Nx) => !exists,
+        Some(SetCondition::Xx) => exists,
+        Some(SetCondition::IfEq(expected)) => old_string.as_ref() == Some(expected),
+    };
+    // --- with GET the reply is the old value, written or not
+    let old_value = old_string.map_or(RedisValue::NullBulkString, RedisValue::BulkString);
+    if !allowed {
+        let res = if options.get {
+            old_value
+        } else {
+            RedisValue::NullBulkString
+        };
+        return Ok(res);
+    }
+
+    match options.expire_at {
+        Some(timeout) => {
+            expire_store.insert(key.clone(), timeout);
+            if ctx.server.config.expiry_mode == ExpiryMode::Precise {
+                ctx.db().expiry_timers.schedule(key.clone(), timeout);
+            }
+        }
+        None if !options.keep_ttl => {
+            expire_store.remove(&key);
+        }
+        None => {}
+    }
+    // --- notified from here, the reply doesn't tell whether the write happened
+    ctx.server.key_changed(ctx.session.db, "set", &key);
+    store_value(ctx, &mut main_store, key, value).await;
+
+    let res = if options.get {
+        old_value
+    } else {
+        RedisValue::SimpleString(Bytes::from_static(b"OK"))
+    };
+
+    Ok(res)
+}
+
+/// Options of a SET request, or the error to reply with
+fn parse_set_options(ctx: &CommandContext<'_>) -> Result<SetOptions, RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+
+    let mut options = SetOptions::default();
+    let mut pos = 2;
+    while let Some(option) = ctx.arg_keyword(pos) {
+        let has_ttl_option = options.expire_at.is_some() || options.keep_ttl;
+        match option.as_slice() {
+            b"NX" if options.condition.is_none() => options.condition = Some(SetCondition::Nx),
+            b"XX" if options.condition.is_none() => options.condition = Some(SetCondition::Xx),
+            b"IFEQ" if ctx.extensions() && options.condition.is_none() => {
+                let expected = ctx.args.get(pos + 1).ok_or_else(syntax_error)?;
+                options.condition = Some(SetCondition::IfEq(expected.clone()));
+                pos += 1;
+            }
+            b"GET" if !options.get => options.get = true,
+            b"KEEPTTL" if !has_ttl_option => options.keep_ttl = true,
+            b"EX" | b"PX" | b"EXAT" | b"PXAT" if !has_ttl_option => {
+                let amount = ctx.args.get(pos + 1).ok_or_else(syntax_error)?;
+                options.expire_at = Some(expire_option(ctx, &option, amount, "set")?);
+                pos += 1;
+            }
+            _ => return Err(syntax_error()),
+        }
+        pos += 1;
+    }
+
+    Ok(options)
+}
+
+/// Unix time in ms the EX, PX, EXAT or PXAT option of a command expires the key at, or
+/// the error to reply with
+fn expire_option(
+    ctx: &CommandContext<'_>,
+    option: &[u8],
+    amount: &Bytes,
+    name: &str,
+) -> Result<u64, RedisValue> {
+    let Some(amount) = parse_integer(amount) else {
+        return Err(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+    // --- deadlines have to fit in an i64 like in Redis, TTL and PTTL give them as one
+    let now = ctx.server.clock.now() as i64;
+    let expire_at = Some(amount)
+        .filter(|amount| *amount > 0)
+        .and_then(|amount| match option {
+            b"EX" => amount.checked_mul(1000)?.checked_add(now),
+            b"PX" => amount.checked_add(now),
+            b"EXAT" => amount.checked_mul(1000),
+            // --- absolute unix time in ms, how Redis 7 masters propagate relative expiries
+            _ => Some(amount),
+        })
+        .map(|expire_at| expire_at as u64);
+
+    expire_at.ok_or_else(|| {
+        RedisValue::SimpleError(Bytes::from(format!(
+            "ERR invalid expire time in '{}' command",
+            name
+        )))
+    })
+}
+
+/// SETEX key seconds value and PSETEX key ms value, SET with EX or PX
+async fn setex(ctx: &mut CommandContext<'_>, name: &str, unit: &[u8]) -> Result<RedisValue> {
+    let (Some(key), Some(amount), Some(value)) =
+        (ctx.arg_value(0), ctx.args.get(1), ctx.arg_value(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))));
+    };
+    let options = match expire_option(ctx, unit, amount, name) {
+        Ok(expire_at) => SetOptions {
+            expire_at: Some(expire_at),
+            ..Default::default()
+        },
+        Err(e) => return Ok(e),
+    };
+
+    set_key(ctx, key, value, options).await
+}
+
+/// SETNX key value: SET NX, replying 1 when the key got written and 0 when it existed
+pub async fn setnx(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(value)) = (ctx.arg_value(0), ctx.arg_value(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'setnx' command",
+        )));
+    };
+    let options = SetOptions {
+        condition: Some(SetCondition::Nx),
+        ..Default::default()
+    };
+
+    let res = match set_key(ctx, key, value, options).await? {
+        RedisValue::NullBulkString => RedisValue::Integer(0),
+        _ => RedisValue::Integer(1),
+    };
+
+    Ok(res)
+}
+
+/// GETEX key [EX s|PX ms|EXAT unix-s|PXAT unix-ms|PERSIST]: GET that also sets or
+/// removes the TTL of the key
+pub async fn getex(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let Some(key) = ctx.arg_value(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'getex' command",
+        )));
+    };
+    let mut expire_at = None;
+    let mut persist = false;
+    let mut pos = 1;
+    while let Some(option) = ctx.arg_keyword(pos) {
+        let has_ttl_option = expire_at.is_some() || persist;
+        match option.as_slice() {
+            b"PERSIST" if !has_ttl_option => persist = true,
+            b"EX" | b"PX" | b"EXAT" | b"PXAT" if !has_ttl_option => {
+                let Some(amount) = ctx.args.get(pos + 1) else {
+                    return Ok(syntax_error());
+                };
+                match expire_option(ctx, &option, amount, "getex") {
+                    Ok(deadline) => expire_at = Some(deadline),
+                    Err(e) => return Ok(e),
+                }
+                pos += 1;
+            }
+            _ => return Ok(syntax_error()),
+        }
+        pos += 1;
+    }
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+    let now = ctx.server.clock.now();
+    let value = get_live_value(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    );
+    record_read(ctx, &key, value.is_some()).await;
+    let Some(value) = value else {
+        return Ok(RedisValue::NullBulkString);
+    };
+    let Some(string) = value.as_string() else {
+        return Ok(wrong_type());
+    };
+    if let Some(deadline) = expire_at {
+        expire_store.insert(key.clone(), deadline);
+        if ctx.server.config.expiry_mode == ExpiryMode::Precise {
+            ctx.db().expiry_timers.schedule(key.clone(), deadline);
+        }
+        ctx.server.save_state.mark_dirty();
+        ctx.server.key_changed(ctx.session.db, "expire", &key);
+    } else if persist && expire_store.remove(&key).is_some() {
+        ctx.server.save_state.mark_dirty();
+        ctx.server.key_changed(ctx.session.db, "persist", &key);
+    }
+
+    let res = RedisValue::BulkString(string);
+
+    Ok(res)
+}
+
+/// GETDEL key: replies with the string a key holds and deletes it
+pub async fn getdel(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'getdel' command",
+        )));
+    };
+
+    let mut old = None;
+    let mut other_type = false;
+    ctx.server
+        .delete_key_if(ctx.db(), &key, |value| match value.as_string() {
+            Some(string) => {
+                old = Some(string);
+                true
+            }
+            None => {
+                other_type = true;
+                false
+            }
+        })
+        .await;
+    ctx.server.stats.record_lookup(old.is_some());
+    if other_type {
+        return Ok(wrong_type());
+    }
+
+    let res = old.map_or(RedisValue::NullBulkString, RedisValue::BulkString);
+
+    Ok(res)
+}
+
+/// DELIFEQ key value, deletes a string key only if it holds the given value. An extension,
+/// the delete counterpart of SET IFEQ
+pub async fn delifeq(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(expected)) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'delifeq' command",
+        )));
+    };
+
+    let mut other_type = false;
+    let deleted = ctx
+        .server
+        .delete_key_if(ctx.db(), &key, |value| match value.as_string() {
+            Some(current) => current == expected,
+            None => {
+                other_type = true;
+                false
+            }
+        })
+        .await;
+    if other_type {
+        return Ok(wrong_type());
+    }
+
+    let res = RedisValue::Integer(deleted as i64);
+
+    Ok(res)
+}
+
+/// What the time argument of EXPIRE and its siblings counts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExpireTime {
+    /// seconds from now, EXPIRE
+    Seconds,
+    /// ms from now, PEXPIRE
+    Millis,
+    /// unix time in seconds, EXPIREAT
+    UnixSeconds,
+    /// unix time in ms, PEXPIREAT
+    UnixMillis,
+}
+impl ExpireTime {
+    /// Unix time in ms the key expires at, `None` when it doesn't fit
+    fn deadline(self, amount: i64, now: u64) -> Option<i64> {
+        let now = i64::try_from(now).ok()?;
+        match self {
+            Self::Seconds => amount.checked_mul(1000)?.checked_add(now),
+            Self::Millis => amount.checked_add(now),
+            Self::UnixSeconds => amount.checked_mul(1000),
+            Self::UnixMillis => Some(amount),
+        }
+    }
+}
+
+/// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT key time [NX|XX|GT|LT]: sets the TTL of an
+/// existing key, replies 0 when the key is missing or the condition isn't met. A deadline
+/// already past deletes the key
+async fn expire(ctx: &CommandContext<'_>, name: &str, time: ExpireTime) -> Result<RedisValue> {
+    let (Some(key), Some(amount)) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))));
+    };
+    let Some(amount) = parse_integer(amount) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for pos in 2..ctx.args.len() {
+        match ctx.arg_keyword(pos).unwrap_or_default().as_slice() {
+            b"NX" => nx = true,
+            b"XX" => xx = true,
+            b"GT" => gt = true,
+            b"LT" => lt = true,
+            _ => {
+                return Ok(RedisValue::SimpleError(Bytes::from(format!(
+                    "ERR Unsupported option {}",
+                    ctx.arg_str(pos).unwrap_or_default()
+                ))))
+            }
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR NX and XX, GT or LT options at the same time are not compatible",
+        )));
+    }
+    if gt && lt {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR GT and LT options at the same time are not compatible",
+        )));
+    }
+    let now = ctx.server.clock.now();
+    let Some(deadline) = time.deadline(amount, now) else {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR invalid expire time in '{}' command",
+            name
+        ))));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+    if get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    )
+    .is_none()
+    {
+        return Ok(RedisValue::Integer(0));
+    }
+    // --- a key without a TTL counts as never expiring: GT fails on it, LT goes through
+    let current = expire_store.get(&key).map(|timestamp| *timestamp as i64);
+    let allowed = (!nx || current.is_none())
+        && (!xx || current.is_some())
+        && (!gt || current.is_some_and(|current| deadline > current))
+        && (!lt || current.is_none_or(|current| deadline < current));
+    if !allowed {
+        return Ok(RedisValue::Integer(0));
+    }
+    if deadline <= now as i64 {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server.delete_key(ctx.db(), &key).await;
+        return Ok(RedisValue::Integer(1));
+    }
+    let deadline = deadline as u64;
+    expire_store.insert(key.clone(), deadline);
+    if ctx.server.config.expiry_mode == ExpiryMode::Precise {
+        ctx.db().expiry_timers.schedule(key.clone(), deadline);
+    }
+    ctx.server.save_state.mark_dirty();
+    ctx.server.key_changed(ctx.session.db, "expire", &key);
+
+    let res = RedisValue::Integer(1);
+
+    Ok(res)
+}
+
+/// TTL and PTTL key: time left before the key expires, in seconds or ms. -1 for a key
+/// without a TTL, -2 for a missing one
+async fn ttl(ctx: &CommandContext<'_>, name: &str, millis: bool) -> Result<RedisValue> {
+    let (Some(key), None) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+    let now = ctx.server.clock.now();
+    let hit = get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    )
+    .is_some();
+    // --- like Redis, looking at the TTL doesn't count as an access to the key
+    ctx.server.stats.record_lookup(hit);
+    let left = match expire_store.get(&key) {
+        _ if !hit => -2,
+        None => -1,
+        Some(timestamp) if millis => timestamp.saturating_sub(now) as i64,
+        Some(timestamp) => (timestamp.saturating_sub(now) as i64 + 500) / 1000,
+    };
+    let res = RedisValue::Integer(left);
+
+    Ok(res)
+}
+
+/// PERSIST key: removes the TTL of a key, replies whether it had one
+pub async fn persist(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), None) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'persist' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+    let now = ctx.server.clock.now();
+    if get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    )
+    .is_none()
+        || expire_store.remove(&key).is_none()
+    {
+        return Ok(RedisValue::Integer(0));
+    }
+    ctx.server.save_state.mark_dirty();
+    ctx.server.key_changed(ctx.session.db, "persist", &key);
+
+    let res = RedisValue::Integer(1);
+
+    Ok(res)
+}
+
+/// Writes a value to the main store along with the bookkeeping every write does: access
+/// metadata, memory accounting, the dirty counter and clients blocked on the key
+async fn store_value(
+    ctx: &CommandContext<'_>,
+    main_store: &mut Keyspace,
+    key: RedisValue,
+    value: RedisValue,
+) {
+    // --- overwriting a key counts as an access, a new key starts with fresh metadata
+    let now = ctx.server.clock.now();
+    if main_store.contains_key(&key) {
+        if !ctx.session.no_touch {
+            touch(ctx, &key).await;
+        }
+    } else {
+        let mut access_store = ctx.db().access_store.lock().await;
+        access_store.insert(key.clone(), KeyAccess::new(now));
+    }
+    ctx.db().memory.add_entry(&key, &value);
+    ctx.db().search_indexes.update(&key, &value);
+    if let Some(old_value) = main_store.insert(key.clone(), value) {
+        ctx.db().memory.remove_entry(&key, &old_value);
+    }
+    ctx.server.save_state.mark_dirty();
+    ctx.server
+        .blocked_clients
+        .signal_key_ready(ctx.session.db, &key);
+}
+
+pub async fn get(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let key = &RedisValue::BulkString(get_argument(0, ctx.args).clone());
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let value = get_live_value(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        key,
+        now,
+    );
+    record_read(ctx, key, value.is_some()).await;
+    let res = match value {
+        Some(value) => value
+            .as_string()
+            .map_or_else(wrong_type, RedisValue::BulkString),
+        None => RedisValue::NullBulkString,
+    };
+
+    Ok(res)
+}
+
+/// DEL key [key ...]: removes the keys along with their TTL, replies how many existed
+pub async fn del(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.args.is_empty() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'del' command",
+        )));
+    }
+
+    let mut deleted = 0;
+    for key in ctx.args.iter().cloned().map(RedisValue::BulkString) {
+        if ctx.server.delete_key(ctx.db(), &key).await {
+            deleted += 1;
+        }
+    }
+    let res = RedisValue::Integer(deleted);
+
+    Ok(res)
+}
+
+/// EXISTS key [key ...]: how many of the keys exist, a key given twice counting twice
+pub async fn exists(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.args.is_empty() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'exists' command",
+        )));
+    }
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+    let now = ctx.server.clock.now();
+    let mut found = 0;
+    for key in ctx.args.iter().cloned().map(RedisValue::BulkString) {
+        // --- like Redis, checking a key doesn't count as an access to it
+        let hit = get_live_value_mut(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            &key,
+            now,
+        )
+        .is_some();
+        ctx.server.stats.record_lookup(hit);
+        found += hit as i64;
+    }
+    let res = RedisValue::Integer(found);
+
+    Ok(res)
+}
+
+/// TYPE key: type of the value held, "none" for a missing key
+pub async fn type_(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), None) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'type' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+    let now = ctx.server.clock.now();
+    let type_name = get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    )
+    .map_or("none", |value| value.type_name());
+    let res = RedisValue::SimpleString(Bytes::from_static(type_name.as_bytes()));
+
+    Ok(res)
+}
+
+pub async fn incr(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.args.len() != 1 {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'incr' command",
+        )));
+    }
+
+    add_to_counter(ctx, 1).await
+}
+
+pub async fn decr(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.args.len() != 1 {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'decr' command",
+        )));
+    }
+
+    add_to_counter(ctx, -1).await
+}
+
+pub async fn incrby(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.args.len() != 2 {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'incrby' command",
+        )));
+    }
+    let Some(increment) = ctx.arg_integer(1) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+
+    add_to_counter(ctx, increment).await
+}
+
+pub async fn decrby(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.args.len() != 2 {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'decrby' command",
+        )));
+    }
+    let Some(decrement) = ctx.arg_integer(1) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+    let Some(increment) = decrement.checked_neg() else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR decrement would overflow",
+        )));
+    };
+
+    add_to_counter(ctx, increment).await
+}
+
+/// Adds to the integer held by the string at the first argument, a missing key counting
+/// as 0. The result is kept as a `RedisValue::Counter`, the TTL stays
+async fn add_to_counter(ctx: &CommandContext<'_>, increment: i64) -> Result<RedisValue> {
+    let key = RedisValue::BulkString(get_argument(0, ctx.args).clone());
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let current = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Counter(n)) => Some(*n),
+        Some(RedisValue::BulkString(text)) => parse_canonical_integer(text),
+        Some(_) => return Ok(wrong_type()),
+        None => Some(0),
+    };
+    let Some(current) = current else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+    let Some(value) = current.checked_add(increment) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR increment or decrement would overflow",
+        )));
+    };
+    ctx.server.key_changed(ctx.session.db, "incrby", &key);
+    store_value(ctx, &mut main_store, key, RedisValue::Counter(value)).await;
+
+    let res = RedisValue::Integer(value);
+
+    Ok(res)
+}
+
+/// INCRBYFLOAT key increment: the result is stored as text, as Redis does
+pub async fn incrbyfloat(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.args.len() != 2 {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'incrbyfloat' command",
+        )));
+    }
+    let key = RedisValue::BulkString(get_argument(0, ctx.args).clone());
+    let Some(increment) = parse_float(get_argument(1, ctx.args)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not a valid float",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let current = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Counter(n)) => Some(*n as f64),
+        Some(RedisValue::BulkString(text)) => parse_float(text),
+        Some(_) => return Ok(wrong_type()),
+        None => Some(0.0),
+    };
+    let Some(current) = current else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not a valid float",
+        )));
+    };
+    let value = current + increment;
+    if !value.is_finite() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR increment would produce NaN or Infinity",
+        )));
+    }
+    let text = Bytes::from(value.to_string());
+    ctx.server.key_changed(ctx.session.db, "incrbyfloat", &key);
+    store_value(
+        ctx,
+        &mut main_store,
+        key,
+        RedisValue::BulkString(text.clone()),
+    )
+    .await;
+
+    let res = RedisValue::BulkString(text);
+
+    Ok(res)
+}
+
+/// End of a list a command works at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum End {
+    Head,
+    Tail,
+}
+impl End {
+    /// Command popping from this end, as pops made for blocked clients get replicated
+    fn pop_command(&self) -> &'static str {
+        match self {
+            End::Head => "LPOP",
+            End::Tail => "RPOP",
+        }
+    }
+}
+
+/// LPUSH and RPUSH key element [element ...]: replies the length of the list after the
+/// push, creating it when missing
+async fn push(ctx: &mut CommandContext<'_>, name: &str, end: End) -> Result<RedisValue> {
+    let (Some(key), Some(items)) = (
+        ctx.arg_value(0),
+        ctx.args.get(1..).filter(|items| !items.is_empty()),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::List(_)) => {
+            if !ctx.session.no_touch {
+                touch(ctx, &key).await;
+            }
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            let list = RedisValue::List(ListItems::default());
+            store_value(ctx, &mut main_store, key.clone(), list).await;
+        }
+    }
+
+    let Some(RedisValue::List(list)) = main_store.get_mut(&key) else {
+        unreachable!("The list was just looked up or created");
+    };
+    let limits = ctx.server.config.live.read().unwrap().encoding_limits;
+    // --- elements go in one at a time, LPUSH a b c leaves c first
+    for item in items {
+        ctx.db().memory.grow(list_item_size(item));
+        match end {
+            End::Head => list.push_front(item.clone(), &limits),
+            End::Tail => list.push_back(item.clone(), &limits),
+        }
+    }
+    let len = list.len();
+    ctx.server.save_state.mark_dirty();
+    ctx.server.key_changed(ctx.session.db, name, &key);
+
+    // --- clients blocked in BLPOP and BRPOP get the new elements first, longest waiting
+    // first. A master's pops come down its stream instead
+    if ctx.session.is_master_link || ctx.session.is_aof_client {
+        return Ok(RedisValue::Integer(len as i64));
+    }
+    let served = ctx
+        .server
+        .blocked_clients
+        .serve_pops(ctx.session.db, &key, |end| {
+            let element = match end {
+                End::Head => list.pop_front(),
+                End::Tail => list.pop_back(),
+            }?;
+            ctx.db().memory.shrink(list_item_size(&element));
+            Some(element)
+        });
+    let emptied = list.is_empty();
+    let RedisValue::BulkString(name) = &key else {
+        unreachable!("Keys are bulk strings");
+    };
+    for end in served {
+        ctx.session
+            .propagate_after
+            .push((end.pop_command(), vec![name.clone()]));
+        ctx.server
+            .key_changed(ctx.session.db, &end.pop_command().to_lowercase(), &key);
+    }
+    if emptied {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server
+            .delete_key_if(
+                ctx.db(),
+                &key,
+                |value| matches!(value, RedisValue::List(list) if list.is_empty()),
+            )
+            .await;
+    }
+
+    let res = RedisValue::Integer(len as i64);
+
+    Ok(res)
+}
+
+/// LPOP and RPOP key [count]: an element, or an array of up to `count` of them. A list
+/// left empty is deleted
+async fn pop(ctx: &CommandContext<'_>, name: &str, end: End) -> Result<RedisValue> {
+    let (Some(key), None) = (ctx.arg_value(0), ctx.args.get(2)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))));
+    };
+    let count = match ctx.args.get(1) {
+        None => None,
+        Some(_) => match ctx.arg_integer(1).and_then(|n| usize::try_from(n).ok()) {
+            Some(count) => Some(count),
+            None => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is out of range, must be positive",
+                )))
+            }
+        },
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let list = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Ok(wrong_type()),
+        // --- Redis replies a null array when given a count, the same nil to RESP2 clients
+        None => return Ok(RedisValue::NullBulkString),
+    };
+    let popped = (0..count.unwrap_or(1).min(list.len()))
+        .map_while(|_| match end {
+            End::Head => list.pop_front(),
+            End::Tail => list.pop_back(),
+        })
+        .collect::<Vec<_>>();
+    let emptied = list.is_empty();
+    for item in popped.iter() {
+        ctx.db().memory.shrink(list_item_size(item));
+    }
+    if !popped.is_empty() {
+        ctx.server.save_state.mark_dirty();
+        ctx.server.key_changed(ctx.session.db, name, &key);
+    }
+    if emptied {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server
+            .delete_key_if(
+                ctx.db(),
+                &key,
+                |value| matches!(value, RedisValue::List(list) if list.is_empty()),
+            )
+            .await;
+    }
+
+    let res = match count {
+        None => popped
+            .into_iter()
+            .next()
+            .map_or(RedisValue::NullBulkString, RedisValue::BulkString),
+        Some(_) => RedisValue::Array(popped.into_iter().map(RedisValue::BulkString).collect()),
+    };
+
+    Ok(res)
+}
+
+/// BLPOP and BRPOP key [key ...] timeout: pops from the first of the keys holding a list,
+/// or else waits up to `timeout` seconds, 0 for ever, for a push to hand an element over.
+/// Replies the key and the element, or a null array once the timeout went by
+async fn blocking_pop(ctx: &mut CommandContext<'_>, name: &str, end: End) -> Result<RedisValue> {
+    let Some((timeout, keys)) = ctx.args.split_last().filter(|(_, keys)| !keys.is_empty()) else {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))));
+    };
+    let timeout = match parse_float(timeout) {
+        Some(secs) if secs < 0.0 => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR timeout is negative",
+            )))
+        }
+        Some(secs) => (secs > 0.0).then(|| std::time::Duration::from_secs_f64(secs)),
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR timeout is not a float or out of range",
+            )))
+        }
+    };
+    let keys = keys
+        .iter()
+        .cloned()
+        .map(RedisValue::BulkString)
+        .collect::<Vec<_>>();
+
+    // --- BLPOP and BRPOP skip the exec lock in `execute` so they don't hold it while
+    // blocked, they only take it, and the fence, while popping right away
+    let exec_guard = match ctx.session.in_exec {
+        true => None,
+        false => Some(ctx.server.exec_lock.read().await),
+    };
+    let fence = ctx.server.replication_fence.read().await;
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let mut ready = None;
+    for key in keys.iter() {
+        match get_live_value_mut(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            key,
+            now,
+        ) {
+            Some(RedisValue::List(_)) => {
+                ready = Some(key);
+                break;
+            }
+            Some(_) => return Ok(wrong_type()),
+            None => {}
+        }
+    }
+    if let Some(key) = ready {
+        let Some(RedisValue::List(list)) = main_store.get_mut(key) else {
+            unreachable!("The list was just looked up");
+        };
+        let element = match end {
+            End::Head => list.pop_front(),
+            End::Tail => list.pop_back(),
+        }
+        .expect("Lists in the store are never empty");
+        let emptied = list.is_empty();
+        ctx.db().memory.shrink(list_item_size(&element));
+        ctx.server.save_state.mark_dirty();
+        if !ctx.session.is_aof_client {
+            let RedisValue::BulkString(name) = key else {
+                unreachable!("Keys are bulk strings");
+            };
+            let args = [name.clone()];
+            append_to_aof(ctx.server, Some(ctx.session.db), end.pop_command(), &args)?;
+            propagate(ctx.server, Some(ctx.session.db), end.pop_command(), &args)?;
+        }
+        ctx.server
+            .key_changed(ctx.session.db, &end.pop_command().to_lowercase(), key);
+        drop(expire_store);
+        drop(main_store);
+        if emptied {
+            ctx.server
+                .delete_key_if(
+                    ctx.db(),
+                    key,
+                    |value| matches!(value, RedisValue::List(list) if list.is_empty()),
+                )
+                .await;
+        }
+        drop(fence);
+        drop(exec_guard);
+        return Ok(RedisValue::Array(vec![
+            key.clone(),
+            RedisValue::BulkString(element),
+        ]));
+    }
+    // --- nothing blocks inside EXEC, the way Redis has it
+    if ctx.session.in_exec {
+        return Ok(RedisValue::NullArray);
+    }
+
+    // --- parked before the stores are released, a push can't slip in between
+    let blocked =
+        ctx.server
+            .blocked_clients
+            .block_pop(ctx.session.id, ctx.session.db, keys.clone(), end);
+    drop(expire_store);
+    drop(main_store);
+    drop(fence);
+    drop(exec_guard);
+    let res = match blocked.wait(timeout).await {
+        Wakeup::Popped { key, element } => {
+            RedisValue::Array(vec![key, RedisValue::BulkString(element)])
+        }
+        Wakeup::Unblocked { error: true } => {
+            RedisValue::SimpleError(Bytes::from_static(UNBLOCKED_ERROR))
+        }
+        Wakeup::Timeout | Wakeup::Unblocked { error: false } => RedisValue::NullArray,
+        Wakeup::KeyReady(_) => unreachable!("Pushes hand elements over rather than signal"),
+    };
+
+    Ok(res)
+}
+
+/// LRANGE key start stop: elements between two indexes, both included, negative ones
+/// counting from the tail
+pub async fn lrange(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(_), Some(_), None) = (
+        ctx.arg_value(0),
+        ctx.args.get(1),
+        ctx.args.get(2),
+        ctx.args.get(3),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'lrange' command",
+        )));
+    };
+    let (Some(start), Some(stop)) = (ctx.arg_integer(1), ctx.arg_integer(2)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let value = get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    );
+    let list = match value {
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            record_read(ctx, &key, false).await;
+            return Ok(RedisValue::Array(vec![]));
+        }
+    };
+    let len = list.len() as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    let items = if start > stop {
+        vec![]
+    } else {
+        list.range(start as usize..=stop as usize)
+            .cloned()
+            .map(RedisValue::BulkString)
+            .collect()
+    };
+    record_read(ctx, &key, true).await;
+
+    let res = RedisValue::Array(items);
+
+    Ok(res)
+}
+
+/// LLEN key: length of the list, 0 for a missing key
+pub async fn llen(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), None) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'llen' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let len = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::List(list)) => list.len(),
+        Some(_) => return Ok(wrong_type()),
+        None => 0,
+    };
+    let res = RedisValue::Integer(len as i64);
+
+    Ok(res)
+}
+
+/// LINSERT key BEFORE|AFTER pivot element: inserts next to the first occurrence of the
+/// pivot. Replies the new length, -1 when the pivot isn't there, 0 for a missing key
+pub async fn linsert(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(position), Some(pivot), Some(element)) = (
+        ctx.arg_value(0),
+        ctx.arg_keyword(1),
+        ctx.args.get(2),
+        ctx.args.get(3),
+    ) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let after = match position.as_slice() {
+        b"BEFORE" => false,
+        b"AFTER" => true,
+        _ => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR syntax error",
+            )))
+        }
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let list = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Ok(wrong_type()),
+        None => return Ok(RedisValue::Integer(0)),
+    };
+    let Some(index) = list.iter().position(|item| item == pivot) else {
+        return Ok(RedisValue::Integer(-1));
+    };
+    let limits = ctx.server.config.live.read().unwrap().encoding_limits;
+    list.insert(index + after as usize, element.clone(), &limits);
+    let len = list.len();
+    ctx.db().memory.grow(list_item_size(element));
+    ctx.server.save_state.mark_dirty();
+    ctx.server.key_changed(ctx.session.db, "linsert", &key);
+
+    let res = RedisValue::Integer(len as i64);
+
+    Ok(res)
+}
+
+/// LSET key index element: replaces the element at an index, negative ones counting from
+/// the tail
+pub async fn lset(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(element)) = (ctx.arg_value(0), ctx.args.get(2)) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let Some(index) = ctx.arg_integer(1) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let list = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR no such key",
+            )))
+        }
+    };
+    let len = list.len() as i64;
+    let index = if index < 0 { index + len } else { index };
+    let limits = ctx.server.config.live.read().unwrap().encoding_limits;
+    let Some(old) = usize::try_from(index)
+        .ok()
+        .and_then(|index| list.set(index, element.clone(), &limits))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR index out of range",
+        )));
+    };
+    ctx.db().memory.shrink(list_item_size(&old));
+    ctx.db().memory.grow(list_item_size(element));
+    ctx.server.save_state.mark_dirty();
+    ctx.server.key_changed(ctx.session.db, "lset", &key);
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// LREM key count element: removes occurrences of an element, the first `count` from the
+/// head, the last `-count` from the tail, or all of them for 0. Replies how many went
+pub async fn lrem(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(element)) = (ctx.arg_value(0), ctx.args.get(2)) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let Some(count) = ctx.arg_integer(1) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let list = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Ok(wrong_type()),
+        None => return Ok(RedisValue::Integer(0)),
+    };
+    let limit = match count {
+        0 => usize::MAX,
+        count => count.unsigned_abs() as usize,
+    };
+    // --- indexes of the matches, from the end the count starts at
+    let mut matches = list
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| *item == element)
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if count < 0 {
+        matches.reverse();
+    }
+    matches.truncate(limit);
+    matches.sort_unstable();
+    for index in matches.iter().rev() {
+        list.remove(*index);
+    }
+    let removed = matches.len();
+    let emptied = list.is_empty();
+    if removed > 0 {
+        ctx.db().memory.shrink(removed * list_item_size(element));
+        ctx.server.save_state.mark_dirty();
+        ctx.server.key_changed(ctx.session.db, "lrem", &key);
+    }
+    if emptied {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server
+            .delete_key_if(
+                ctx.db(),
+                &key,
+                |value| matches!(value, RedisValue::List(list) if list.is_empty()),
+            )
+            .await;
+    }
+
+    let res = RedisValue::Integer(removed as i64);
+
+    Ok(res)
+}
+
+/// LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]: index of the element,
+/// the RANK-th match from the head or, when negative, from the tail. With COUNT, an array
+/// of up to that many indexes, 0 for all of them. MAXLEN bounds the elements compared
+pub async fn lpos(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(element)) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let not_an_integer = || {
+        RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        ))
+    };
+
+    let mut rank = 1;
+    let mut count = None;
+    let mut max_len = 0;
+    let mut pos = 2;
+    while pos < ctx.args.len() {
+        let Some(value) = ctx.args.get(pos + 1) else {
+            return Ok(syntax_error());
+        };
+        let Some(value) = parse_integer(value) else {
+            return Ok(not_an_integer());
+        };
+        match ctx.arg_keyword(pos).as_deref() {
+            Some(b"RANK") if value == 0 => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR RANK can't be zero: use 1 to start from the first match, 2 from the \
+                    second ... or use negative to start from the end of the list",
+                )));
+            }
+            // --- its opposite wouldn't fit
+            Some(b"RANK") if value == i64::MIN => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is out of range, value must between -9223372036854775807 and \
+                    9223372036854775807",
+                )));
+            }
+            Some(b"RANK") => rank = value,
+            Some(b"COUNT") if value < 0 => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR COUNT can't be negative",
+                )));
+            }
+            Some(b"COUNT") => count = Some(value as usize),
+            Some(b"MAXLEN") if value < 0 => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR MAXLEN can't be negative",
+                )));
+            }
+            Some(b"MAXLEN") => max_len = value as usize,
+            _ => return Ok(syntax_error()),
+        }
+        pos += 2;
+    }
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let list = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            record_read(ctx, &key, false).await;
+            return Ok(match count {
+                Some(_) => RedisValue::Array(vec![]),
+                None => RedisValue::NullBulkString,
+            });
+        }
+    };
+    let max_len = match max_len {
+        0 => list.len(),
+        max_len => max_len.min(list.len()),
+    };
+    let limit = match count {
+        Some(0) => usize::MAX,
+        Some(count) => count,
+        None => 1,
+    };
+    // --- the matches past the first |RANK| - 1, walking from the end RANK starts at
+    let indexes: Box<dyn Iterator<Item = usize>> = match rank > 0 {
+        true => Box::new(0..max_len),
+        false => Box::new((list.len() - max_len..list.len()).rev()),
+    };
+    let matches = indexes
+        .filter(|index| list[*index] == element)
+        .skip(rank.unsigned_abs() as usize - 1)
+        .take(limit)
+        .map(|index| RedisValue::Integer(index as i64))
+        .collect::<Vec<_>>();
+    record_read(ctx, &key, true).await;
+
+    let res = match count {
+        Some(_) => RedisValue::Array(matches),
+        None => matches
+            .into_iter()
+            .next()
+            .unwrap_or(RedisValue::NullBulkString),
+    };
+
+    Ok(res)
+}
+
+/// LTRIM key start stop: keeps only the elements between two indexes, both included,
+/// negative ones counting from the tail. A list left empty is deleted
+pub async fn ltrim(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let (Some(start), Some(stop)) = (ctx.arg_integer(1), ctx.arg_integer(2)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let list = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::List(list)) => list,
+        Some(_) => return Ok(wrong_type()),
+        None => return Ok(RedisValue::SimpleString(Bytes::from_static(b"OK"))),
+    };
+    let len = list.len() as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    let (keep_from, keep_len) = match start > stop {
+        true => (0, 0),
+        false => (start as usize, (stop - start + 1) as usize),
+    };
+    let removed = list
+        .trim(keep_from..keep_from + keep_len)
+        .iter()
+        .map(|item| list_item_size(item))
+        .sum::<usize>();
+    let emptied = list.is_empty();
+    ctx.db().memory.shrink(removed);
+    ctx.server.save_state.mark_dirty();
+    ctx.server.key_changed(ctx.session.db, "ltrim", &key);
+    if emptied {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server
+            .delete_key_if(
+                ctx.db(),
+                &key,
+                |value| matches!(value, RedisValue::List(list) if list.is_empty()),
+            )
+            .await;
+    }
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// LMPOP numkeys key [key ...] LEFT|RIGHT [COUNT count]: pops up to `count` elements from
+/// the first of the keys holding a list. Replies the key and the elements, or nil when
+/// all the lists are empty
+pub async fn lmpop(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let numkeys = match ctx.arg_integer(0) {
+        Some(numkeys) if numkeys > 0 => numkeys as usize,
+        _ => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR numkeys should be greater than 0",
+            )))
+        }
+    };
+    let Some(keys) = ctx.args.get(1..1 + numkeys) else {
+        return Ok(syntax_error());
+    };
+    let end = match ctx.arg_keyword(1 + numkeys).as_deref() {
+        Some(b"LEFT") => End::Head,
+        Some(b"RIGHT") => End::Tail,
+        _ => return Ok(syntax_error()),
+    };
+    let count = match &ctx.args[2 + numkeys..] {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+            match parse_integer(count).filter(|count| *count > 0) {
+                Some(count) => count as usize,
+                None => {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR count should be greater than 0",
+                    )))
+                }
+            }
+        }
+        _ => return Ok(syntax_error()),
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    for key in keys.iter().cloned().map(RedisValue::BulkString) {
+        let list = match get_live_value_mut(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            &key,
+            now,
+        ) {
+            Some(RedisValue::List(list)) => list,
+            Some(_) => return Ok(wrong_type()),
+            None => continue,
+        };
+        let popped = (0..count.min(list.len()))
+            .map_while(|_| match end {
+                End::Head => list.pop_front(),
+                End::Tail => list.pop_back(),
+            })
+            .collect::<Vec<_>>();
+        let emptied = list.is_empty();
+        for item in popped.iter() {
+            ctx.db().memory.shrink(list_item_size(item));
+        }
+        ctx.server.save_state.mark_dirty();
+        ctx.server
+            .key_changed(ctx.session.db, &end.pop_command().to_lowercase(), &key);
+        if emptied {
+            drop(expire_store);
+            drop(main_store);
+            ctx.server
+                .delete_key_if(
+                    ctx.db(),
+                    &key,
+                    |value| matches!(value, RedisValue::List(list) if list.is_empty()),
+                )
+                .await;
+        }
+        return Ok(RedisValue::Array(vec![
+            key,
+            RedisValue::Array(popped.into_iter().map(RedisValue::BulkString).collect()),
+        ]));
+    }
+
+    let res = RedisValue::NullArray;
+
+    Ok(res)
+}
+
+/// SORT key [BY pattern] [LIMIT offset count] [GET pattern ...] [ASC|DESC] [ALPHA]
+/// [STORE destination]: elements of a list, set or sorted set as numbers in ascending
+/// order, or as strings with ALPHA. BY sorts them by the values a pattern points to, GET
+/// replies those values instead of the elements, see `sort_lookup`. STORE keeps the result
+/// as a list and replies its length. SORT_RO is SORT without STORE
+pub async fn sort(ctx: &mut CommandContext<'_>, read_only: bool) -> Result<RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let mut by = None;
+    let mut limit = None;
+    let mut gets = vec![];
+    let mut desc = false;
+    let mut alpha = false;
+    let mut store = None;
+    let mut pos = 1;
+    while pos < ctx.args.len() {
+        let option = ctx.arg_keyword(pos).expect("Within the arguments");
+        match (option.as_slice(), ctx.args.get(pos + 1)) {
+            (b"ASC", _) => desc = false,
+            (b"DESC", _) => desc = true,
+            (b"ALPHA", _) => alpha = true,
+            (b"BY", Some(pattern)) => {
+                by = Some(pattern.clone());
+                pos += 1;
+            }
+            (b"GET", Some(pattern)) => {
+                gets.push(pattern.clone());
+                pos += 1;
+            }
+            (b"STORE", Some(dest)) if !read_only => {
+                store = Some(RedisValue::BulkString(dest.clone()));
+                pos += 1;
+            }
+            (b"LIMIT", Some(_)) if pos + 2 < ctx.args.len() => {
+                let (Some(offset), Some(count)) =
+                    (ctx.arg_integer(pos + 1), ctx.arg_integer(pos + 2))
+                else {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR value is not an integer or out of range",
+                    )));
+                };
+                limit = Some((offset, count));
+                pos += 2;
+            }
+            _ => return Ok(syntax_error()),
+        }
+        pos += 1;
+    }
+    // --- a BY pattern without `*` leaves the elements in the order they are in
+    let sorted = by.as_ref().is_none_or(|pattern| pattern.contains(&b'*'));
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let elements = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::List(list)) => Some(list.iter().cloned().collect::<Vec<_>>()),
+        Some(RedisValue::Set(set)) => Some(set.iter().collect()),
+        Some(RedisValue::SortedSet(zset)) => {
+            Some(zset.iter().map(|(member, _)| member.clone()).collect())
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => None,
+    };
+    record_read(ctx, &key, elements.is_some()).await;
+    let mut elements = elements.unwrap_or_default();
+
+    if sorted {
+        let mut weighted = Vec::with_capacity(elements.len());
+        for element in elements {
+            let weight = match &by {
+                Some(pattern) => sort_lookup(
+                    ctx.server,
+                    ctx.db(),
+                    &mut main_store,
+                    &mut expire_store,
+                    pattern,
+                    &element,
+                    now,
+                ),
+                None => Some(element.clone()),
+            };
+            weighted.push((weight, element));
+        }
+        // --- ties go by the elements themselves, so replicas sort the same way
+        if alpha {
+            // --- missing values come first
+            weighted.sort_by(|(a_weight, a), (b_weight, b)| {
+                let order = a_weight.cmp(b_weight).then_with(|| a.cmp(b));
+                if desc {
+                    order.reverse()
+                } else {
+                    order
+                }
+            });
+            elements = weighted.into_iter().map(|(_, element)| element).collect();
+        } else {
+            let mut scored = Vec::with_capacity(weighted.len());
+            for (weight, element) in weighted {
+                let score = match weight {
+                    Some(weight) => match parse_score(&weight) {
+                        Some(score) => score,
+                        None => {
+                            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                                b"ERR One or more scores can't be converted into double",
+                            )))
+                        }
+                    },
+                    None => 0.0,
+                };
+                scored.push((score, element));
+            }
+            scored.sort_by(|(a_score, a), (b_score, b)| {
+                let order = a_score.total_cmp(b_score).then_with(|| a.cmp(b));
+                if desc {
+                    order.reverse()
+                } else {
+                    order
+                }
+            });
+            elements = scored.into_iter().map(|(_, element)| element).collect();
+        }
+    }
+    let (offset, count) = limit.unwrap_or((0, -1));
+    let count = usize::try_from(count).unwrap_or(usize::MAX);
+    let elements = elements
+        .into_iter()
+        .skip(offset.max(0) as usize)
+        .take(count)
+        .collect::<Vec<_>>();
+
+    let mut rows = Vec::with_capacity(elements.len() * gets.len().max(1));
+    for element in elements {
+        if gets.is_empty() {
+            rows.push(Some(element));
+            continue;
+        }
+        for pattern in gets.iter() {
+            rows.push(sort_lookup(
+                ctx.server,
+                ctx.db(),
+                &mut main_store,
+                &mut expire_store,
+                pattern,
+                &element,
+                now,
+            ));
+        }
+    }
+    let Some(dest) = store else {
+        let rows = rows
+            .into_iter()
+            .map(|row| row.map_or(RedisValue::NullBulkString, RedisValue::BulkString))
+            .collect();
+        return Ok(RedisValue::Array(rows));
+    };
+    // --- missing values are stored as empty strings
+    let len = rows.len();
+    if rows.is_empty() {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server.delete_key(ctx.db(), &dest).await;
+    } else {
+        let limits = ctx.server.config.live.read().unwrap().encoding_limits;
+        let mut list = ListItems::default();
+        for row in rows {
+            list.push_back(row.unwrap_or_default(), &limits);
+        }
+        expire_store.remove(&dest);
+        ctx.server.key_changed(ctx.session.db, "sortstore", &dest);
+        store_value(ctx, &mut main_store, dest, RedisValue::List(list)).await;
+    }
+
+    let res = RedisValue::Integer(len as i64);
+
+    Ok(res)
+}
+
+/// What a SORT pattern points to for an element: the string at the key the pattern names
+/// once its first `*` is replaced by the element, or a field of the hash there when the
+/// pattern ends with `->field`. `#` stands for the element itself
+fn sort_lookup(
+    server: &RedisServer,
+    db: &Database,
+    main_store: &mut Keyspace,
+    expire_store: &mut Expires,
+    pattern: &[u8],
+    element: &Bytes,
+    now: u64,
+) -> Option<Bytes> {
+    if pattern == b"#" {
+        return Some(element.clone());
+    }
+    let star = pattern.iter().position(|byte| *byte == b'*')?;
+    let arrow = pattern[star + 1..]
+        .windows(2)
+        .position(|window| window == b"->")
+        .map(|i| star + 1 + i)
+        .filter(|arrow| arrow + 2 < pattern.len());
+    let key_end = arrow.unwrap_or(pattern.len());
+    let key = RedisValue::BulkString(Bytes::from(
+        [&pattern[..star], element, &pattern[star + 1..key_end]].concat(),
+    ));
+    let value = get_live_value_mut(server, db, main_store, expire_store, &key, now)?;
+
+    match (value, arrow) {
+        (RedisValue::Hash(fields), Some(arrow)) => fields.get(&pattern[arrow + 2..]).cloned(),
+        (value, None) => value.as_string(),
+        _ => None,
+    }
+}
+
+/// HSET key field value [field value ...]: replies how many of the fields are new,
+/// creating the hash when missing
+pub async fn hset(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(pairs)) = (
+        ctx.arg_value(0),
+        ctx.args
+            .get(1..)
+            .filter(|pairs| !pairs.is_empty() && pairs.len() % 2 == 0),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'hset' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Hash(_)) => {
+            if !ctx.session.no_touch {
+                touch(ctx, &key).await;
+            }
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            let hash = RedisValue::Hash(HashFields::default());
+            store_value(ctx, &mut main_store, key.clone(), hash).await;
+        }
+    }
+
+    let Some(RedisValue::Hash(fields)) = main_store.get_mut(&key) else {
+        unreachable!("The hash was just looked up or created");
+    };
+    let limits = ctx.server.config.live.read().unwrap().encoding_limits;
+    let mut added = 0;
+    for pair in pairs.chunks_exact(2) {
+        ctx.db().memory.grow(hash_field_size(&pair[0], &pair[1]));
+        match fields.insert(pair[0].clone(), pair[1].clone(), &limits) {
+            Some(previous) => ctx.db().memory.shrink(hash_field_size(&pair[0], &previous)),
+            None => added += 1,
+        }
+    }
+    if let Some(hash) = main_store.get(&key) {
+        ctx.db().search_indexes.update(&key, hash);
+    }
+    ctx.server.save_state.mark_dirty();
+    ctx.server.key_changed(ctx.session.db, "hset", &key);
+
+    let res = RedisValue::Integer(added);
+
+    Ok(res)
+}
+
+/// HGET key field: value of the field, nil when it or the hash is missing
+pub async fn hget(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(field), None) = (ctx.arg_value(0), ctx.args.get(1), ctx.args.get(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'hget' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let value = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Hash(fields)) => fields.get(field).cloned(),
+        Some(_) => return Ok(wrong_type()),
+        None => None,
+    };
+    record_read(ctx, &key, value.is_some()).await;
+
+    let res = value.map_or(RedisValue::NullBulkString, RedisValue::BulkString);
+
+    Ok(res)
+}
+
+/// HDEL key field [field ...]: replies how many fields were removed. A hash left empty
+/// is deleted
+pub async fn hdel(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(names)) = (
+        ctx.arg_value(0),
+        ctx.args.get(1..).filter(|names| !names.is_empty()),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'hdel' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let fields = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Hash(fields)) => fields,
+        Some(_) => return Ok(wrong_type()),
+        None => return Ok(RedisValue::Integer(0)),
+    };
+    let mut removed = 0;
+    for name in names {
+        if let Some(value) = fields.remove(name) {
+            ctx.db().memory.shrink(hash_field_size(name, &value));
+            removed += 1;
+        }
+    }
+    let emptied = fields.is_empty();
+    if removed > 0 {
+        if let Some(hash) = main_store.get(&key) {
+            ctx.db().search_indexes.update(&key, hash);
+        }
+        ctx.server.save_state.mark_dirty();
+        ctx.server.key_changed(ctx.session.db, "hdel", &key);
+    }
+    if emptied {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server
+            .delete_key_if(
+                ctx.db(),
+                &key,
+                |value| matches!(value, RedisValue::Hash(fields) if fields.is_empty()),
+            )
+            .await;
+    }
+
+    let res = RedisValue::Integer(removed);
+
+    Ok(res)
+}
+
+/// HGETALL key: fields and their values, one after the other in a flat array
+pub async fn hgetall(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), None) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'hgetall' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let items = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Hash(fields)) => fields
+            .iter()
+            .map(|(field, value)| {
+                (
+                    RedisValue::BulkString(field.clone()),
+                    RedisValue::BulkString(value.clone()),
+                )
+            })
+            .collect(),
+        Some(_) => return Ok(wrong_type()),
+        None => vec![],
+    };
+    record_read(ctx, &key, !items.is_empty()).await;
+
+    let res = RedisValue::Map(items);
+
+    Ok(res)
+}
+
+/// HLEN key: number of fields, 0 for a missing key
+pub async fn hlen(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), None) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'hlen' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let len = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Hash(fields)) => fields.len(),
+        Some(_) => return Ok(wrong_type()),
+        None => 0,
+    };
+    let res = RedisValue::Integer(len as i64);
+
+    Ok(res)
+}
+
+/// HEXISTS key field: 1 when the hash has the field, 0 otherwise
+pub async fn hexists(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(field), None) = (ctx.arg_value(0), ctx.args.get(1), ctx.args.get(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'hexists' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let exists = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Hash(fields)) => fields.contains_key(field),
+        Some(_) => return Ok(wrong_type()),
+        None => false,
+    };
+    let res = RedisValue::Integer(exists as i64);
+
+    Ok(res)
+}
+
+/// SADD key member [member ...]: replies how many members were added, creating the set
+/// when missing
+pub async fn sadd(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(members)) = (
+        ctx.arg_value(0),
+        ctx.args.get(1..).filter(|members| !members.is_empty()),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'sadd' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Set(_)) => {
+            if !ctx.session.no_touch {
+                touch(ctx, &key).await;
+            }
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            let set = RedisValue::Set(SetMembers::default());
+            store_value(ctx, &mut main_store, key.clone(), set).await;
+        }
+    }
+
+    let Some(RedisValue::Set(set)) = main_store.get_mut(&key) else {
+        unreachable!("The set was just looked up or created");
+    };
+    let limits = ctx.server.config.live.read().unwrap().encoding_limits;
+    let mut added = 0;
+    for member in members {
+        if set.insert(member.clone(), &limits) {
+            ctx.db().memory.grow(set_member_size(member));
+            added += 1;
+        }
+    }
+    if added > 0 {
+        ctx.server.save_state.mark_dirty();
+        ctx.server.key_changed(ctx.session.db, "sadd", &key);
+    }
+
+    let res = RedisValue::Integer(added);
+
+    Ok(res)
+}
+
+/// SREM key member [member ...]: replies how many members were removed. A set left empty
+/// is deleted
+pub async fn srem(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(members)) = (
+        ctx.arg_value(0),
+        ctx.args.get(1..).filter(|members| !members.is_empty()),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'srem' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let set = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Set(set)) => set,
+        Some(_) => return Ok(wrong_type()),
+        None => return Ok(RedisValue::Integer(0)),
+    };
+    let mut removed = 0;
+    for member in members {
+        if set.remove(member) {
+            ctx.db().memory.shrink(set_member_size(member));
+            removed += 1;
+        }
+    }
+    let emptied = set.is_empty();
+    if removed > 0 {
+        ctx.server.save_state.mark_dirty();
+        ctx.server.key_changed(ctx.session.db, "srem", &key);
+    }
+    if emptied {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server
+            .delete_key_if(
+                ctx.db(),
+                &key,
+                |value| matches!(value, RedisValue::Set(set) if set.is_empty()),
+            )
+            .await;
+    }
+
+    let res = RedisValue::Integer(removed);
+
+    Ok(res)
+}
+
+/// SMEMBERS key: every member of the set, an empty array for a missing key
+pub async fn smembers(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), None) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'smembers' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let members = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Set(set)) => set.iter().map(RedisValue::BulkString).collect(),
+        Some(_) => return Ok(wrong_type()),
+        None => vec![],
+    };
+    record_read(ctx, &key, !members.is_empty()).await;
+
+    let res = RedisValue::Array(members);
+
+    Ok(res)
+}
+
+/// SISMEMBER key member: 1 when the set holds the member, 0 otherwise
+pub async fn sismember(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(member), None) = (ctx.arg_value(0), ctx.args.get(1), ctx.args.get(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'sismember' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let found = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Set(set)) => set.contains(member),
+        Some(_) => return Ok(wrong_type()),
+        None => false,
+    };
+    record_read(ctx, &key, found).await;
+
+    let res = RedisValue::Integer(found as i64);
+
+    Ok(res)
+}
+
+/// SMISMEMBER key member [member ...]: 1 or 0 for each member, whether the set holds it
+pub async fn smismember(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(members)) = (ctx.arg_value(0), ctx.args.get(1..)) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let set = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Set(set)) => Some(&*set),
+        Some(_) => return Ok(wrong_type()),
+        None => None,
+    };
+    let found = members
+        .iter()
+        .map(|member| RedisValue::Integer(set.is_some_and(|set| set.contains(member)) as i64))
+        .collect();
+    let hit = set.is_some();
+    record_read(ctx, &key, hit).await;
+
+    let res = RedisValue::Array(found);
+
+    Ok(res)
+}
+
+/// SRANDMEMBER key [count]: a random member, or with a count that many distinct ones, as
+/// many as the set holds. A negative count draws its opposite with repetitions, up to
+/// `proto-max-multibulk-len` of them
+pub async fn srandmember(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    // --- repeats are drawn one by one, no more of them than a request array may hold
+    let max_repeats = ctx.server.limits.max_multibulk_len as u64;
+    let count = match ctx.args.get(1).map(|count| parse_integer(count)) {
+        Some(Some(count)) if count >= 0 || count.unsigned_abs() <= max_repeats => Some(count),
+        Some(_) => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR value is out of range",
+            )))
+        }
+        None => None,
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let set = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Set(set)) => &*set,
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            record_read(ctx, &key, false).await;
+            return Ok(match count {
+                Some(_) => RedisValue::Array(vec![]),
+                None => RedisValue::NullBulkString,
+            });
+        }
+    };
+    let members = {
+        let mut rng = rand::thread_rng();
+        let members = match count {
+            None => set.iter().choose(&mut rng).into_iter().collect(),
+            Some(count) if count >= 0 => {
+                let count = (count as usize).min(set.len());
+                set.iter().choose_multiple(&mut rng, count)
+            }
+            Some(count) => {
+                let members = set.iter().collect::<Vec<_>>();
+                (0..count.unsigned_abs())
+                    .filter_map(|_| members.choose(&mut rng).cloned())
+                    .collect::<Vec<_>>()
+            }
+        };
+        members
+            .into_iter()
+            .map(RedisValue::BulkString)
+            .collect::<Vec<_>>()
+    };
+    record_read(ctx, &key, true).await;
+
+    let res = match count {
+        Some(_) => RedisValue::Array(members),
+        None => members
+            .into_iter()
+            .next()
+            .unwrap_or(RedisValue::NullBulkString),
+    };
+
+    Ok(res)
+}
+
+/// SPOP key [count]: removes and replies a random member, or with a count up to that many
+/// of them. A set left empty is deleted
+pub async fn spop(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let count = match ctx.args.get(1) {
+        Some(count) => match parse_integer(count).filter(|count| *count >= 0) {
+            Some(count) => Some(count as usize),
+            None => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is out of range, must be positive",
+                )))
+            }
+        },
+        None => None,
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let set = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Set(set)) => set,
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            return Ok(match count {
+                Some(_) => RedisValue::Array(vec![]),
+                None => RedisValue::NullBulkString,
+            })
+        }
+    };
+    let drawn = count.unwrap_or(1).min(set.len());
+    let popped = set.iter().choose_multiple(&mut rand::thread_rng(), drawn);
+    for member in &popped {
+        set.remove(member);
+        ctx.db().memory.shrink(set_member_size(member));
+    }
+    let emptied = set.is_empty();
+    if !popped.is_empty() {
+        ctx.server.save_state.mark_dirty();
+        ctx.server.key_changed(ctx.session.db, "spop", &key);
+    }
+    if emptied {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server
+            .delete_key_if(
+                ctx.db(),
+                &key,
+                |value| matches!(value, RedisValue::Set(set) if set.is_empty()),
+            )
+            .await;
+    }
+    let mut popped = popped.into_iter().map(RedisValue::BulkString);
+
+    let res = match count {
+        Some(_) => RedisValue::Array(popped.collect()),
+        None => popped.next().unwrap_or(RedisValue::NullBulkString),
+    };
+
+    Ok(res)
+}
+
+/// How SINTER, SUNION and SDIFF combine their sets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SetOperation {
+    Intersection,
+    Union,
+    /// members of the first set missing from all the others
+    Difference,
+}
+
+/// SINTER, SUNION and SDIFF key [key ...]: members of the sets combined, missing keys
+/// counting as empty sets. All the keys are read under the same lock, so no write lands
+/// halfway through
+async fn set_algebra(
+    ctx: &CommandContext<'_>,
+    name: &str,
+    operation: SetOperation,
+) -> Result<RedisValue> {
+    if ctx.args.is_empty() {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))));
+    }
+    let keys = ctx
+        .args
+        .iter()
+        .cloned()
+        .map(RedisValue::BulkString)
+        .collect::<Vec<_>>();
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    // --- expired keys dropped and types checked first, the sets are then only borrowed
+    for key in keys.iter() {
+        let hit = match get_live_value_mut(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            key,
+            now,
+        ) {
+            Some(RedisValue::Set(_)) => true,
+            Some(_) => return Ok(wrong_type()),
+            None => false,
+        };
+        record_read(ctx, key, hit).await;
+    }
+    let empty = SetMembers::default();
+    let sets = keys
+        .iter()
+        .map(|key| match main_store.get(key) {
+            Some(RedisValue::Set(set)) => set,
+            _ => &empty,
+        })
+        .collect::<Vec<_>>();
+    let (first, others) = sets.split_first().expect("At least one key");
+    let members = match operation {
+        SetOperation::Intersection => {
+            let smallest = sets.iter().min_by_key(|set| set.len()).unwrap_or(first);
+            smallest
+                .iter()
+                .filter(|member| sets.iter().all(|set| set.contains(member)))
+                .collect::<Vec<_>>()
+        }
+        SetOperation::Union => sets
+            .iter()
+            .flat_map(|set| set.iter())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect(),
+        SetOperation::Difference => first
+            .iter()
+            .filter(|member| others.iter().all(|set| !set.contains(member)))
+            .collect(),
+    };
+
+    let res = RedisValue::Array(members.into_iter().map(RedisValue::BulkString).collect());
+
+    Ok(res)
+}
+
+/// ZADD key [NX|XX] [GT|LT] [CH] score member [score member ...]: replies how many
+/// members were added, or added and moved with CH
+pub async fn zadd(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let Some(key) = ctx.arg_value(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'zadd' command",
+        )));
+    };
+    let (mut nx, mut xx, mut gt, mut lt, mut ch) = (false, false, false, false, false);
+    let mut pos = 1;
+    while let Some(option) = ctx.arg_keyword(pos) {
+        match option.as_slice() {
+            b"NX" => nx = true,
+            b"XX" => xx = true,
+            b"GT" => gt = true,
+            b"LT" => lt = true,
+            b"CH" => ch = true,
+            _ => break,
+        }
+        pos += 1;
+    }
+    if nx && xx {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR XX and NX options at the same time are not compatible",
+        )));
+    }
+    if (gt && lt) || (nx && (gt || lt)) {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR GT, LT, and/or NX options at the same time are not compatible",
+        )));
+    }
+    let pairs = &ctx.args[pos..];
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return Ok(syntax_error());
+    }
+    // --- every score checked before anything is written
+    let Some(members) = pairs
+        .chunks_exact(2)
+        .map(|pair| Some((parse_score(&pair[0])?, pair[1].clone())))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not a valid float",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::SortedSet(_)) => {
+            if !ctx.session.no_touch {
+                touch(ctx, &key).await;
+            }
+        }
+        Some(_) => return Ok(wrong_type()),
+        None if xx => return Ok(RedisValue::Integer(0)),
+        None => {
+            let zset = RedisValue::SortedSet(SortedSet::default());
+            store_value(ctx, &mut main_store, key.clone(), zset).await;
+        }
+    }
+
+    let Some(RedisValue::SortedSet(zset)) = main_store.get_mut(&key) else {
+        unreachable!("The sorted set was just looked up or created");
+    };
+    let (mut added, mut moved) = (0, 0);
+    for (score, member) in members {
+        match zset.score(&member) {
+            Some(_) if nx => {}
+            Some(current) if (gt && score <= current) || (lt && score >= current) => {}
+            Some(current) => {
+                if current != score {
+                    zset.insert(member, score);
+                    moved += 1;
+                }
+            }
+            None if xx => {}
+            None => {
+                ctx.db().memory.grow(zset_member_size(&member));
+                zset.insert(member, score);
+                added += 1;
+            }
+        }
+    }
+    if added + moved > 0 {
+        ctx.server.save_state.mark_dirty();
+        ctx.server.key_changed(ctx.session.db, "zadd", &key);
+    }
+
+    let res = RedisValue::Integer(if ch { added + moved } else { added });
+
+    Ok(res)
+}
+
+/// ZREM key member [member ...]: replies how many members were removed. A sorted set
+/// left empty is deleted
+pub async fn zrem(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(members)) = (
+        ctx.arg_value(0),
+        ctx.args.get(1..).filter(|members| !members.is_empty()),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'zrem' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let zset = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::SortedSet(zset)) => zset,
+        Some(_) => return Ok(wrong_type()),
+        None => return Ok(RedisValue::Integer(0)),
+    };
+    let mut removed = 0;
+    for member in members {
+        if zset.remove(member).is_some() {
+            ctx.db().memory.shrink(zset_member_size(member));
+            removed += 1;
+        }
+    }
+    let emptied = zset.is_empty();
+    if removed > 0 {
+        ctx.server.save_state.mark_dirty();
+        ctx.server.key_changed(ctx.session.db, "zrem", &key);
+    }
+    if emptied {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server
+            .delete_key_if(
+                ctx.db(),
+                &key,
+                |value| matches!(value, RedisValue::SortedSet(zset) if zset.is_empty()),
+            )
+            .await;
+    }
+
+    let res = RedisValue::Integer(removed);
+
+    Ok(res)
+}
+
+/// Members of a ZRANGE or ZRANGEBYSCORE reply, each followed by its score with WITHSCORES.
+/// RESP3 connections get a [member, score] pair per member instead, the score a double
+fn zset_members<'a>(
+    members: impl Iterator<Item = (&'a Bytes, f64)>,
+    with_scores: bool,
+    protocol: Protocol,
+) -> RedisValue {
+    let res = match (with_scores, protocol) {
+        (true, Protocol::Resp3) => members
+            .map(|(member, score)| {
+                RedisValue::Array(vec![
+                    RedisValue::BulkString(member.clone()),
+                    RedisValue::Double(format_score(score)),
+                ])
+            })
+            .collect(),
+        (true, Protocol::Resp2) => members
+            .flat_map(|(member, score)| {
+                [
+                    RedisValue::BulkString(member.clone()),
+                    RedisValue::BulkString(format_score(score)),
+                ]
+            })
+            .collect(),
+        (false, _) => members
+            .map(|(member, _)| RedisValue::BulkString(member.clone()))
+            .collect(),
+    };
+
+    RedisValue::Array(res)
+}
+
+/// ZRANGE key start stop [WITHSCORES]: members between two ranks, both included, lowest
+/// score first. Negative ranks count from the highest score
+pub async fn zrange(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(_), Some(_)) = (ctx.arg_value(0), ctx.args.get(1), ctx.args.get(2)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'zrange' command",
+        )));
+    };
+    let with_scores = match (ctx.arg_keyword(3).as_deref(), ctx.args.get(4)) {
+        (None, _) => false,
+        (Some(b"WITHSCORES"), None) => true,
+        _ => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR syntax error",
+            )))
+        }
+    };
+    let (Some(start), Some(stop)) = (ctx.arg_integer(1), ctx.arg_integer(2)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let zset = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::SortedSet(zset)) => &*zset,
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            record_read(ctx, &key, false).await;
+            return Ok(RedisValue::Array(vec![]));
+        }
+    };
+    let len = zset.len() as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    let count = if start > stop { 0 } else { stop - start + 1 };
+    let res = zset_members(
+        zset.iter().skip(start as usize).take(count as usize),
+        with_scores,
+        ctx.session.protocol,
+    );
+    record_read(ctx, &key, true).await;
+
+    Ok(res)
+}
+
+/// ZRANGEBYSCORE key min max [WITHSCORES] [LIMIT offset count]: members with a score
+/// within a range, lowest first
+pub async fn zrangebyscore(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let (Some(key), Some(min), Some(max)) = (ctx.arg_value(0), ctx.args.get(1), ctx.args.get(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'zrangebyscore' command",
+        )));
+    };
+    let mut with_scores = false;
+    let mut limit = None;
+    let mut pos = 3;
+    while let Some(option) = ctx.arg_keyword(pos) {
+        match option.as_slice() {
+            b"WITHSCORES" => with_scores = true,
+            b"LIMIT" => {
+                if ctx.args.get(pos + 2).is_none() {
+                    return Ok(syntax_error());
+                }
+                let (Some(offset), Some(count)) =
+                    (ctx.arg_integer(pos + 1), ctx.arg_integer(pos + 2))
+                else {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR value is not an integer or out of range",
+                    )));
+                };
+                limit = Some((offset, count));
+                pos += 2;
+            }
+            _ => return Ok(syntax_error()),
+        }
+        pos += 1;
+    }
+    let (Some(min), Some(max)) = (ScoreBound::parse(min), ScoreBound::parse(max)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR min or max is not a float",
+        )));
+    };
+    // --- a negative offset selects nothing, a negative count everything past the offset
+    let (offset, count) = match limit {
+        None => (0, usize::MAX),
+        Some((offset, _)) if offset < 0 => (0, 0),
+        Some((offset, count)) => (
+            offset as usize,
+            usize::try_from(count).unwrap_or(usize::MAX),
+        ),
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let zset = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::SortedSet(zset)) => &*zset,
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            record_read(ctx, &key, false).await;
+            return Ok(RedisValue::Array(vec![]));
+        }
+    };
+    let res = zset_members(
+        zset.range_by_score(min, max).skip(offset).take(count),
+        with_scores,
+        ctx.session.protocol,
+    );
+    record_read(ctx, &key, true).await;
+
+    Ok(res)
+}
+
+/// ZSCORE key member: score of the member, nil when it or the sorted set is missing
+pub async fn zscore(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(member), None) = (ctx.arg_value(0), ctx.args.get(1), ctx.args.get(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'zscore' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let score = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::SortedSet(zset)) => zset.score(member),
+        Some(_) => return Ok(wrong_type()),
+        None => None,
+    };
+    record_read(ctx, &key, score.is_some()).await;
+
+    let res = score.map_or(RedisValue::NullBulkString, |score| {
+        RedisValue::Double(format_score(score))
+    });
+
+    Ok(res)
+}
+
+/// ZRANK key member: position of the member by score, lowest first, nil when it or the
+/// sorted set is missing
+pub async fn zrank(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(member), None) = (ctx.arg_value(0), ctx.args.get(1), ctx.args.get(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'zrank' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let rank = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::SortedSet(zset)) => zset.rank(member),
+        Some(_) => return Ok(wrong_type()),
+        None => None,
+    };
+    record_read(ctx, &key, rank.is_some()).await;
+
+    let res = rank.map_or(RedisValue::NullBulkString, |rank| {
+        RedisValue::Integer(rank as i64)
+    });
+
+    Ok(res)
+}
+
+/// Reply to a stream ID that doesn't parse
+fn invalid_stream_id() -> RedisValue {
+    RedisValue::SimpleError(Bytes::from_static(
+        b"ERR Invalid stream ID specified as stream command argument",
+    ))
+}
+
+/// XADD key <* | ms-* | ms-seq> field value [field value ...]: appends an entry, creating
+/// the stream when missing, and replies its ID
+pub async fn xadd(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(id), Some(pairs)) = (
+        ctx.arg_value(0),
+        ctx.args.get(1),
+        ctx.args
+            .get(2..)
+            .filter(|pairs| !pairs.is_empty() && pairs.len().is_multiple_of(2)),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'xadd' command",
+        )));
+    };
+    let Some(id) = NewId::parse(id) else {
+        return Ok(invalid_stream_id());
+    };
+    if id == NewId::Explicit(StreamId::MIN) {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR The ID specified in XADD must be greater than 0-0",
+        )));
+    }
+    let fields = pairs
+        .chunks_exact(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect::<Vec<_>>();
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Stream(_)) => {
+            if !ctx.session.no_touch {
+                touch(ctx, &key).await;
+            }
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            let stream = RedisValue::Stream(Stream::default());
+            store_value(ctx, &mut main_store, key.clone(), stream).await;
+        }
+    }
+
+    let Some(RedisValue::Stream(stream)) = main_store.get_mut(&key) else {
+        unreachable!("The stream was just looked up or created");
+    };
+    let size = stream_entry_size(&fields);
+    // --- only an ID at or before the last one fails, never on a new stream
+    let id = match stream.add(id, now, fields) {
+        Ok(id) => id,
+        Err(e) => return Ok(RedisValue::SimpleError(Bytes::from(e.to_string()))),
+    };
+    ctx.db().memory.grow(size);
+    ctx.server.save_state.mark_dirty();
+    ctx.server
+        .blocked_clients
+        .signal_key_ready_all(ctx.session.db, &key);
+    ctx.server.key_changed(ctx.session.db, "xadd", &key);
+
+    let res = RedisValue::BulkString(Bytes::from(id.to_string()));
+
+    Ok(res)
+}
+
+/// XRANGE key start end [COUNT count]: entries with an ID between the two, both
+/// included, oldest first. "-" and "+" stand for the first and last possible IDs
+pub async fn xrange(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(start), Some(end)) = (ctx.arg_value(0), ctx.args.get(1), ctx.args.get(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'xrange' command",
+        )));
+    };
+    let count = match (ctx.arg_keyword(3).as_deref(), ctx.args.get(5)) {
+        (None, _) => None,
+        (Some(b"COUNT"), None) if ctx.args.get(4).is_some() => match ctx.arg_integer(4) {
+            // --- Redis takes a negative count as 0
+            Some(count) => Some(usize::try_from(count).unwrap_or(0)),
+            None => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is not an integer or out of range",
+                )))
+            }
+        },
+        _ => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR syntax error",
+            )))
+        }
+    };
+    let (Some(start), Some(end)) = (
+        StreamId::parse_bound(start, false),
+        StreamId::parse_bound(end, true),
+    ) else {
+        return Ok(invalid_stream_id());
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let stream = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Stream(stream)) => &*stream,
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            record_read(ctx, &key, false).await;
+            return Ok(RedisValue::Array(vec![]));
+        }
+    };
+    let res = stream_entries(stream.range(start..=end).take(count.unwrap_or(usize::MAX)));
+    record_read(ctx, &key, true).await;
+
+    Ok(res)
+}
+
+/// Entries of an XRANGE or XREAD reply, each its ID then its fields and values
+fn stream_entries<'a>(
+    entries: impl Iterator<Item = (&'a StreamId, &'a Vec<(Bytes, Bytes)>)>,
+) -> RedisValue {
+    RedisValue::Array(
+        entries
+            .map(|(id, fields)| {
+                RedisValue::Array(vec![
+                    RedisValue::BulkString(Bytes::from(id.to_string())),
+                    RedisValue::Array(
+                        fields
+                            .iter()
+                            .flat_map(|(field, value)| [field.clone(), value.clone()])
+                            .map(RedisValue::BulkString)
+                            .collect(),
+                    ),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// XREAD [COUNT count] [BLOCK ms] STREAMS key [key ...] id [id ...]: entries past the
+/// given IDs, "$" standing for the last one of the stream. Nothing to read replies a null
+/// array, right away or once BLOCK times out, 0 blocking for as long as it takes
+pub async fn xread(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let mut count = None;
+    let mut block = None;
+    let mut pos = 0;
+    let streams = loop {
+        let Some(option) = ctx.arg_keyword(pos) else {
+            return Ok(syntax_error());
+        };
+        match option.as_slice() {
+            b"STREAMS" => break &ctx.args[pos + 1..],
+            b"COUNT" => match ctx.arg_integer(pos + 1) {
+                Some(n) => count = Some(usize::try_from(n).unwrap_or(0)),
+                None if ctx.args.get(pos + 1).is_none() => return Ok(syntax_error()),
+                None => {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR value is not an integer or out of range",
+                    )))
+                }
+            },
+            b"BLOCK" => match ctx.arg_integer(pos + 1) {
+                Some(ms) if ms < 0 => {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR timeout is negative",
+                    )))
+                }
+                Some(ms) => block = Some(ms as u64),
+                None if ctx.args.get(pos + 1).is_none() => return Ok(syntax_error()),
+                None => {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR timeout is not an integer or out of range",
+                    )))
+                }
+            },
+            _ => return Ok(syntax_error()),
+        }
+        pos += 2;
+    };
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.",
+        )));
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+    let keys = keys
+        .iter()
+        .cloned()
+        .map(RedisValue::BulkString)
+        .collect::<Vec<_>>();
+    let Some(ids) = ids
+        .iter()
+        .map(|id| match id.as_ref() {
+            b"$" => Some(None),
+            id => StreamId::parse_bound(id, false).map(Some),
+        })
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Ok(invalid_stream_id());
+    };
+    let deadline = block
+        .filter(|ms| *ms > 0)
+        .map(|ms| tokio::time::Instant::now() + std::time::Duration::from_millis(ms));
+
+    let mut ids = ids;
+    loop {
+        let mut main_store = ctx.db().main_store.lock().await;
+        let mut expire_store = ctx.db().expire_store.lock().await;
+
+        let now = ctx.server.clock.now();
+        let mut replies = vec![];
+        for (key, id) in keys.iter().zip(ids.iter_mut()) {
+            let stream = match get_live_value_mut(
+                ctx.server,
+                ctx.db(),
+                &mut main_store,
+                &mut expire_store,
+                key,
+                now,
+            ) {
+                Some(RedisValue::Stream(stream)) => Some(&*stream),
+                Some(_) => return Ok(wrong_type()),
+                None => None,
+            };
+            // --- "$" is the last ID when the command first runs, kept while it blocks
+            let after = *id.get_or_insert(stream.map_or(StreamId::MIN, |stream| stream.last_id()));
+            let Some(stream) = stream else {
+                continue;
+            };
+            let mut entries = stream
+                .after(after)
+                .take(count.unwrap_or(usize::MAX))
+                .peekable();
+            if entries.peek().is_some() {
+                replies.push(RedisValue::Array(vec![
+                    key.clone(),
+                    stream_entries(entries),
+                ]));
+            }
+        }
+        if !replies.is_empty() {
+            return Ok(RedisValue::Array(replies));
+        }
+        // --- nothing blocks inside EXEC, the way Redis has it
+        if block.is_none() || ctx.session.in_exec {
+            return Ok(RedisValue::NullArray);
+        }
+
+        // --- parked before the stores are released, an XADD can't slip in between
+        let blocked =
+            ctx.server
+                .blocked_clients
+                .block(ctx.session.id, ctx.session.db, keys.clone());
+        drop(expire_store);
+        drop(main_store);
+        let timeout = deadline
+            .map(|deadline| deadline.saturating_duration_since(tokio::time::Instant::now()));
+        match blocked.wait(timeout).await {
+            Wakeup::KeyReady(_) => continue,
+            Wakeup::Popped { .. } => unreachable!("Only BLPOP and BRPOP get elements handed over"),
+            Wakeup::Unblocked { error: true } => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(UNBLOCKED_ERROR)))
+            }
+            Wakeup::Timeout | Wakeup::Unblocked { error: false } => {
+                return Ok(RedisValue::NullArray)
+            }
+        }
+    }
+}
+
+/// XLEN key: number of entries, 0 for a missing key
+pub async fn xlen(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), None) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'xlen' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let len = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Stream(stream)) => stream.len(),
+        Some(_) => return Ok(wrong_type()),
+        None => 0,
+    };
+    let res = RedisValue::Integer(len as i64);
+
+    Ok(res)
+}
+
+/// GETRANGE, also served under its legacy name SUBSTR
+pub async fn getrange(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let key = &RedisValue::BulkString(get_argument(0, ctx.args).clone());
+    let (Some(start), Some(end)) = (
+        parse_integer(get_argument(1, ctx.args)),
+        parse_integer(get_argument(2, ctx.args)),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let value = get_live_value(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        key,
+        now,
+    );
+    record_read(ctx, key, value.is_some()).await;
+    let value = match value.map(|value| value.as_string()) {
+        Some(Some(b)) => b,
+        Some(None) => return Ok(wrong_type()),
+        None => Bytes::new(),
+    };
+
+    // --- negative offsets count from the end, both ends are inclusive
+    let len = value.len() as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 {
+        (len + end).max(0)
+    } else {
+        end.min(len - 1)
+    };
+
+    let res = if len == 0 || start > end {
+        RedisValue::BulkString(Bytes::new())
+    } else {
+        RedisValue::BulkString(value.slice(start as usize..=end as usize))
+    };
+
+    Ok(res)
+}
+
+/// MGET key [key ...]: the value of each key, nil for missing keys and other types
+pub async fn mget(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let mut values = Vec::with_capacity(ctx.args.len());
+    for key in ctx.args.iter().cloned().map(RedisValue::BulkString) {
+        let value = get_live_value(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            &key,
+            now,
+        );
+        record_read(ctx, &key, value.is_some()).await;
+        values.push(
+            value
+                .and_then(|value| value.as_string())
+                .map_or(RedisValue::NullBulkString, RedisValue::BulkString),
+        );
+    }
+
+    let res = RedisValue::Array(values);
+
+    Ok(res)
+}
+
+/// MSET and MSETNX key value [key value ...]: sets every key, dropping their TTL. With
+/// `only_new` nothing is set unless all keys are missing, MSETNX replying whether they
+/// were
+pub async fn mset(ctx: &mut CommandContext<'_>, name: &str, only_new: bool) -> Result<RedisValue> {
+    if !ctx.args.len().is_multiple_of(2) {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))));
+    }
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    if only_new {
+        for key in ctx
+            .args
+            .iter()
+            .step_by(2)
+            .cloned()
+            .map(RedisValue::BulkString)
+        {
+            if get_live_value_mut(
+                ctx.server,
+                ctx.db(),
+                &mut main_store,
+                &mut expire_store,
+                &key,
+                now,
+            )
+            .is_some()
+            {
+                return Ok(RedisValue::Integer(0));
+            }
+        }
+    }
+    for pair in ctx.args.chunks(2) {
+        let key = RedisValue::BulkString(pair[0].clone());
+        expire_store.remove(&key);
+        ctx.server.key_changed(ctx.session.db, "set", &key);
+        store_value(
+            ctx,
+            &mut main_store,
+            key,
+            RedisValue::BulkString(pair[1].clone()),
+        )
+        .await;
+    }
+
+    let res = match only_new {
+        true => RedisValue::Integer(1),
+        false => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+    };
+
+    Ok(res)
+}
+
+/// APPEND key value: adds to the end of a string, creating it when missing. Replies the
+/// new length
+pub async fn append(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(suffix)) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let old = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(value) => match value.as_string() {
+            Some(old) => old,
+            None => return Ok(wrong_type()),
+        },
+        None => Bytes::new(),
+    };
+    if old.len() + suffix.len() > ctx.server.limits.max_bulk_len {
+        return Ok(string_too_long());
+    }
+    let mut value = Vec::with_capacity(old.len() + suffix.len());
+    value.extend_from_slice(&old);
+    value.extend_from_slice(suffix);
+    let len = value.len();
+    ctx.server.key_changed(ctx.session.db, "append", &key);
+    store_value(
+        ctx,
+        &mut main_store,
+        key,
+        RedisValue::BulkString(Bytes::from(value)),
+    )
+    .await;
+
+    let res = RedisValue::Integer(len as i64);
+
+    Ok(res)
+}
+
+/// STRLEN key: length of a string, 0 for a missing key
+pub async fn strlen(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let value = get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    )
+    .map(|value| value.as_string());
+    record_read(ctx, &key, value.is_some()).await;
+
+    let res = match value {
+        Some(Some(value)) => RedisValue::Integer(value.len() as i64),
+        Some(None) => wrong_type(),
+        None => RedisValue::Integer(0),
+    };
+
+    Ok(res)
+}
+
+/// SETRANGE key offset value: overwrites part of a string, padding it with zero bytes up
+/// to the offset. A missing key is created unless there is nothing to write. Replies the
+/// new length
+pub async fn setrange(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(patch)) = (ctx.arg_value(0), ctx.args.get(2)) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let offset = match ctx.arg_integer(1) {
+        Some(offset) if offset >= 0 => offset as usize,
+        Some(_) => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR offset is out of range",
+            )))
+        }
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR value is not an integer or out of range",
+            )))
+        }
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let old = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(value) => match value.as_string() {
+            Some(old) => Some(old),
+            None => return Ok(wrong_type()),
+        },
+        None => None,
+    };
+    if patch.is_empty() {
+        let len = old.map_or(0, |old| old.len());
+        return Ok(RedisValue::Integer(len as i64));
+    }
+    if offset.saturating_add(patch.len()) > ctx.server.limits.max_bulk_len {
+        return Ok(string_too_long());
+    }
+    let mut value = old.map_or_else(Vec::new, |old| old.to_vec());
+    if value.len() < offset + patch.len() {
+        value.resize(offset + patch.len(), 0);
+    }
+    value[offset..offset + patch.len()].copy_from_slice(patch);
+    let len = value.len();
+    ctx.server.key_changed(ctx.session.db, "setrange", &key);
+    store_value(
+        ctx,
+        &mut main_store,
+        key,
+        RedisValue::BulkString(Bytes::from(value)),
+    )
+    .await;
+
+    let res = RedisValue::Integer(len as i64);
+
+    Ok(res)
+}
+
+/// SETBIT key offset 0|1: sets a bit of a string, zero padding it up to the offset when
+/// shorter. Replies the bit's previous value
+pub async fn setbit(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let Some(offset) = bit_offset(ctx, 1) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR bit offset is not an integer or out of range",
+        )));
+    };
+    let bit = match ctx.arg_integer(2) {
+        Some(0) => false,
+        Some(1) => true,
+        _ => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR bit is not an integer or out of range",
+            )))
+        }
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let mut value = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(value) => match value.as_string() {
+            Some(old) => old.to_vec(),
+            None => return Ok(wrong_type()),
+        },
+        None => Vec::new(),
+    };
+    if value.len() <= offset / 8 {
+        value.resize(offset / 8 + 1, 0);
+    }
+    let old = bitmap::set_bit(&mut value, offset, bit);
+    ctx.server.key_changed(ctx.session.db, "setbit", &key);
+    store_value(
+        ctx,
+        &mut main_store,
+        key,
+        RedisValue::BulkString(Bytes::from(value)),
+    )
+    .await;
+
+    let res = RedisValue::Integer(old as i64);
+
+    Ok(res)
+}
+
+/// GETBIT key offset: a bit of a string, 0 past its end or for a missing key
+pub async fn getbit(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let Some(offset) = bit_offset(ctx, 1) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR bit offset is not an integer or out of range",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let value = get_live_value(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    );
+    record_read(ctx, &key, value.is_some()).await;
+    let value = match value.map(|value| value.as_string()) {
+        Some(Some(b)) => b,
+        Some(None) => return Ok(wrong_type()),
+        None => Bytes::new(),
+    };
+
+    let res = RedisValue::Integer(bitmap::get_bit(&value, offset) as i64);
+
+    Ok(res)
+}
+
+/// BITCOUNT key [start end [BYTE|BIT]]: how many bits of a string are set, within a range
+/// of bytes or bits when given, negative positions counting from the end
+pub async fn bitcount(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    if ctx.args.len() == 2 {
+        return Ok(syntax_error());
+    }
+    let range = match ctx.args.len() {
+        1 => None,
+        _ => match (ctx.arg_integer(1), ctx.arg_integer(2)) {
+            (Some(start), Some(end)) => Some((start, end)),
+            _ => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is not an integer or out of range",
+                )))
+            }
+        },
+    };
+    let Some(in_bits) = bit_unit(ctx, 3) else {
+        return Ok(syntax_error());
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let value = get_live_value(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    );
+    record_read(ctx, &key, value.is_some()).await;
+    let value = match value.map(|value| value.as_string()) {
+        Some(Some(b)) => b,
+        Some(None) => return Ok(wrong_type()),
+        None => Bytes::new(),
+    };
+
+    let bits = bit_span(value.len(), range, in_bits);
+    let res = RedisValue::Integer(
+        bits.map_or(0, |(first, last)| bitmap::count(&value, first, last) as i64),
+    );
+
+    Ok(res)
+}
+
+/// BITPOS key bit [start [end [BYTE|BIT]]]: offset of the first bit set to 0 or 1, within a
+/// range when given. A string is taken as followed by zeros unless the range has an end
+pub async fn bitpos(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let bit = match ctx.arg_integer(1) {
+        Some(0) => false,
+        Some(1) => true,
+        _ => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR The bit argument must be 1 or 0.",
+            )))
+        }
+    };
+    let bounds: Option<Vec<i64>> = (2..ctx.args.len().min(4))
+        .map(|pos| ctx.arg_integer(pos))
+        .collect();
+    let Some(bounds) = bounds else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+    let Some(in_bits) = bit_unit(ctx, 4) else {
+        return Ok(syntax_error());
+    };
+    let end_given = bounds.len() == 2;
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let value = get_live_value(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    );
+    record_read(ctx, &key, value.is_some()).await;
+    let value = match value.map(|value| value.as_string()) {
+        Some(Some(b)) => b,
+        Some(None) => return Ok(wrong_type()),
+        // --- a missing key is all zeros
+        None => return Ok(RedisValue::Integer(if bit { -1 } else { 0 })),
+    };
+
+    let range = match bounds[..] {
+        [] => None,
+        [start] => Some((start, -1)),
+        [start, end] => Some((start, end)),
+        _ => unreachable!("At most two bounds are taken"),
+    };
+    let res = match bit_span(value.len(), range, in_bits) {
+        Some((first, last)) => match bitmap::position(&value, bit, first, last) {
+            Some(pos) => pos as i64,
+            None if !bit && !end_given => value.len() as i64 * 8,
+            None => -1,
+        },
+        None => -1,
+    };
+    let res = RedisValue::Integer(res);
+
+    Ok(res)
+}
+
+/// BITOP AND|OR|XOR|NOT destkey key [key ...]: stores the bitwise combination of strings,
+/// missing keys and shorter strings padded with zeros. Replies the length of the result,
+/// an empty one deleting the destination
+pub async fn bitop(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let (Some(op), Some(dest)) = (ctx.arg_keyword(0), ctx.arg_value(1)) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let Some(op) = BitOp::parse(&op) else {
+        return Ok(syntax_error());
+    };
+    if op == BitOp::Not && ctx.args.len() != 3 {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR BITOP NOT must be called with a single source key.",
+        )));
+    }
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let mut sources = Vec::with_capacity(ctx.args.len() - 2);
+    for key in ctx.args[2..].iter().cloned().map(RedisValue::BulkString) {
+        let value = get_live_value(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            &key,
+            now,
+        );
+        record_read(ctx, &key, value.is_some()).await;
+        match value.map(|value| value.as_string()) {
+            Some(Some(b)) => sources.push(b),
+            Some(None) => return Ok(wrong_type()),
+            None => sources.push(Bytes::new()),
+        }
+    }
+    let sources: Vec<&[u8]> = sources.iter().map(|source| &source[..]).collect();
+    let value = op.apply(&sources);
+    let len = value.len();
+    if value.is_empty() {
+        drop(expire_store);
+        drop(main_store);
+        ctx.server.delete_key(ctx.db(), &dest).await;
+    } else {
+        expire_store.remove(&dest);
+        ctx.server.key_changed(ctx.session.db, "set", &dest);
+        store_value(
+            ctx,
+            &mut main_store,
+            dest,
+            RedisValue::BulkString(Bytes::from(value)),
+        )
+        .await;
+    }
+
+    let res = RedisValue::Integer(len as i64);
+
+    Ok(res)
+}
+
+/// Argument as a bit offset, None when not a number, negative or past the longest string
+/// allowed
+fn bit_offset(ctx: &CommandContext<'_>, pos: usize) -> Option<usize> {
+    let offset = usize::try_from(ctx.arg_integer(pos)?).ok()?;
+
+    (offset / 8 < ctx.server.limits.max_bulk_len).then_some(offset)
+}
+
+/// Whether the range of BITCOUNT and BITPOS counts bits, from the BYTE|BIT argument at
+/// `pos` when given. None for anything else
+fn bit_unit(ctx: &CommandContext<'_>, pos: usize) -> Option<bool> {
+    match ctx.arg_keyword(pos).as_deref() {
+        None | Some(b"BYTE") => Some(false),
+        Some(b"BIT") => Some(true),
+        Some(_) => None,
+    }
+}
+
+/// Inclusive bit offsets of a `len` bytes long string a range selects, the whole string
+/// without one
+fn bit_span(len: usize, range: Option<(i64, i64)>, in_bits: bool) -> Option<(usize, usize)> {
+    let (start, end) = range.unwrap_or((0, -1));
+    if in_bits {
+        return bitmap::range(len as i64 * 8, start, end);
+    }
+    let (start, end) = bitmap::range(len as i64, start, end)?;
+
+    Some((start * 8, end * 8 + 7))
+}
+
+/// PFADD key [element ...]: adds elements to a HyperLogLog, creating it when missing.
+/// Replies 1 when its estimate changed, 0 otherwise
+pub async fn pfadd(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let value = get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    );
+    let (mut hll, mut changed) = match hyperloglog_of(value.as_deref()) {
+        Ok(Some(hll)) => (hll, false),
+        Ok(None) => (HyperLogLog::default(), true),
+        Err(reply) => return Ok(reply),
+    };
+    for element in ctx.args[1..].iter() {
+        changed |= hll.add(element);
+    }
+    if changed {
+        ctx.server.key_changed(ctx.session.db, "pfadd", &key);
+        store_value(
+            ctx,
+            &mut main_store,
+            key,
+            RedisValue::BulkString(Bytes::from(hll.to_bytes())),
+        )
+        .await;
+    }
+
+    let res = RedisValue::Integer(changed as i64);
+
+    Ok(res)
+}
+
+/// PFCOUNT key [key ...]: estimated number of distinct elements added to a HyperLogLog,
+/// or to any of them for several keys. Missing keys count as empty
+pub async fn pfcount(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let mut union = HyperLogLog::default();
+    for key in ctx.args.iter().cloned().map(RedisValue::BulkString) {
+        let value = get_live_value(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            &key,
+            now,
+        );
+        record_read(ctx, &key, value.is_some()).await;
+        match hyperloglog_of(value.as_ref()) {
+            Ok(Some(hll)) => union.merge(&hll),
+            Ok(None) => {}
+            Err(reply) => return Ok(reply),
+        }
+    }
+
+    let res = RedisValue::Integer(union.count() as i64);
+
+    Ok(res)
+}
+
+/// PFMERGE destkey [sourcekey ...]: stores the union of HyperLogLogs, the destination's
+/// own registers included when it exists
+pub async fn pfmerge(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(dest) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let mut union = HyperLogLog::default();
+    for key in ctx.args.iter().cloned().map(RedisValue::BulkString) {
+        let value = get_live_value(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            &key,
+            now,
+        );
+        match hyperloglog_of(value.as_ref()) {
+            Ok(Some(hll)) => union.merge(&hll),
+            Ok(None) => {}
+            Err(reply) => return Ok(reply),
+        }
+    }
+    ctx.server.key_changed(ctx.session.db, "pfadd", &dest);
+    store_value(
+        ctx,
+        &mut main_store,
+        dest,
+        RedisValue::BulkString(Bytes::from(union.to_bytes())),
+    )
+    .await;
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// HyperLogLog a value holds, `None` for a missing key and an error reply for anything
+/// but a string in one of the HyperLogLog encodings
+fn hyperloglog_of(value: Option<&RedisValue>) -> Result<Option<HyperLogLog>, RedisValue> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let Some(bytes) = value.as_string() else {
+        return Err(wrong_type());
+    };
+
+    match HyperLogLog::parse(&bytes) {
+        Some(hll) => Ok(Some(hll)),
+        None => Err(RedisValue::SimpleError(Bytes::from_static(
+            b"WRONGTYPE Key is not a valid HyperLogLog string value.",
+        ))),
+    }
+}
+
+/// Refusal of a write that would grow a string past `proto-max-bulk-len`
+fn string_too_long() -> RedisValue {
+    RedisValue::SimpleError(Bytes::from_static(
+        b"ERR string exceeds maximum allowed size (proto-max-bulk-len)",
+    ))
+}
+
+/// JSON.SET key path value [NX|XX]. New keys are only created at the root, missing
+/// paths only by adding a key to an existing object. Replies nil when nothing was set
+pub async fn json_set(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(path), Some(value)) = (ctx.arg_value(0), ctx.arg_str(1), ctx.args.get(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'json.set' command",
+        )));
+    };
+    let mode = match ctx.arg_keyword(3).as_deref() {
+        None => SetMode::Always,
+        Some(b"NX") => SetMode::Missing,
+        Some(b"XX") => SetMode::Exists,
+        Some(_) => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR syntax error",
+            )))
+        }
+    };
+    let path = match JsonPath::parse(&path) {
+        Ok(path) => path,
+        Err(e) => return Ok(RedisValue::SimpleError(Bytes::from(format!("ERR {}", e)))),
+    };
+    let value: serde_json::Value = match serde_json::from_slice(value) {
+        Ok(value) => value,
+        Err(e) => return Ok(RedisValue::SimpleError(Bytes::from(format!("ERR {}", e)))),
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let document = match get_live_value(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Json(text)) => {
+            let mut document = serde_json::from_slice(&text)?;
+            if !path.set(&mut document, value, mode) {
+                return Ok(RedisValue::NullBulkString);
+            }
+            document
+        }
+        Some(_) => return Ok(wrong_type()),
+        None if !path.is_root() => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR new objects must be created at the root",
+            )))
+        }
+        None if mode == SetMode::Exists => return Ok(RedisValue::NullBulkString),
+        None => value,
+    };
+    // --- the TTL of the key is kept, as with any partial update
+    let document = RedisValue::Json(Bytes::from(serde_json::to_vec(&document)?));
+    store_value(ctx, &mut main_store, key, document).await;
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// JSON.GET key [path ...]. Without a path the whole document, with several an object
+/// of each path's result
+pub async fn json_get(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'json.get' command",
+        )));
+    };
+    let mut paths = vec![];
+    for pos in 1..ctx.args.len() {
+        let text = ctx.arg_str(pos).unwrap_or_default();
+        match JsonPath::parse(&text) {
+            Ok(path) => paths.push((text, path)),
+            Err(e) => return Ok(RedisValue::SimpleError(Bytes::from(format!("ERR {}", e)))),
+        }
+    }
+
+    let value = {
+        let mut main_store = ctx.db().main_store.lock().await;
+        let mut expire_store = ctx.db().expire_store.lock().await;
+        let now = ctx.server.clock.now();
+        get_live_value(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            &key,
+            now,
+        )
+    };
+    record_read(ctx, &key, value.is_some()).await;
+    let text = match value {
+        Some(RedisValue::Json(text)) => text,
+        Some(_) => return Ok(wrong_type()),
+        None => return Ok(RedisValue::NullBulkString),
+    };
+    if paths.is_empty() {
+        return Ok(RedisValue::BulkString(text));
+    }
+
+    let document = serde_json::from_slice(&text)?;
+    let mut results = serde_json::Map::new();
+    for (text, path) in paths.iter() {
+        let Some(result) = path.get(&document) else {
+            return Ok(RedisValue::SimpleError(Bytes::from(format!(
+                "ERR Path '{}' does not exist",
+                text
+            ))));
+        };
+        results.insert(text.clone(), result);
+    }
+    let res = match paths.len() {
+        1 => results.into_iter().next().map(|(_, result)| result),
+        _ => Some(serde_json::Value::Object(results)),
+    };
+    let res = RedisValue::BulkString(Bytes::from(serde_json::to_vec(&res)?));
+
+    Ok(res)
+}
+
+/// JSON.DEL key [path], the root by default which deletes the key. Replies how many
+/// values were removed
+pub async fn json_del(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'json.del' command",
+        )));
+    };
+    let path = match ctx.arg_str(1).map(|path| JsonPath::parse(&path)) {
+        None => JsonPath::root(),
+        Some(Ok(path)) => path,
+        Some(Err(e)) => return Ok(RedisValue::SimpleError(Bytes::from(format!("ERR {}", e)))),
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let deleted = match get_live_value(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Json(_)) if path.is_root() => {
+            drop((main_store, expire_store));
+            usize::from(ctx.server.delete_key(ctx.db(), &key).await)
+        }
+        Some(RedisValue::Json(text)) => {
+            let mut document = serde_json::from_slice(&text)?;
+            let deleted = path.delete(&mut document);
+            if deleted > 0 {
+                let document = RedisValue::Json(Bytes::from(serde_json::to_vec(&document)?));
+                store_value(ctx, &mut main_store, key, document).await;
+            }
+            deleted
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => 0,
+    };
+
+    let res = RedisValue::Integer(deleted as i64);
+
+    Ok(res)
+}
+
+/// FT.CREATE index [ON JSON|HASH] [PREFIX count prefix ...] SCHEMA identifier [AS name]
+/// NUMERIC|TAG ...
+pub async fn ft_create(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(name) = ctx.arg_str(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'ft.create' command",
+        )));
+    };
+    let definition = match IndexDefinition::from_args(&ctx.args[1..]) {
+        Ok(definition) => definition,
+        Err(e) => return Ok(RedisValue::SimpleError(Bytes::from(e.to_string()))),
+    };
+
+    let main_store = ctx.db().main_store.lock().await;
+    let res = match ctx
+        .db()
+        .search_indexes
+        .create(&name, definition, &main_store)
+    {
+        Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+        Err(e) => RedisValue::SimpleError(Bytes::from(e.to_string())),
+    };
+
+    Ok(res)
+}
+
+/// FT.SEARCH index query [NOCONTENT] [LIMIT offset count]. Replies the number of
+/// matches, then the key of each match, followed by its document unless NOCONTENT
+pub async fn ft_search(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(name), Some(query)) = (ctx.arg_str(0), ctx.arg_str(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'ft.search' command",
+        )));
+    };
+    let mut no_content = false;
+    let (mut offset, mut count) = (0, 10);
+    let mut pos = 2;
+    while let Some(option) = ctx.arg_keyword(pos) {
+        match option.as_slice() {
+            b"NOCONTENT" => no_content = true,
+            b"LIMIT" => match (ctx.arg_integer(pos + 1), ctx.arg_integer(pos + 2)) {
+                (Some(limit_offset), Some(limit_count))
+                    if limit_offset >= 0 && limit_count >= 0 =>
+                {
+                    (offset, count) = (limit_offset as usize, limit_count as usize);
+                    pos += 2;
+                }
+                _ => {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR value is not an integer or out of range",
+                    )))
+                }
+            },
+            _ => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR syntax error",
+                )))
+            }
+        }
+        pos += 1;
+    }
+    let keys = match ctx.db().search_indexes.search(&name, &query) {
+        Ok(keys) => keys,
+        Err(e) => return Ok(RedisValue::SimpleError(Bytes::from(e.to_string()))),
+    };
+
+    // --- keys past their TTL are still indexed until something notices they expired
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+    let now = ctx.server.clock.now();
+    let mut matches = vec![];
+    for key in keys {
+        let key = RedisValue::BulkString(key);
+        if let Some(document) = get_live_value(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            &key,
+            now,
+        ) {
+            matches.push((key, document));
+        }
+    }
+
+    let mut res = vec![RedisValue::Integer(matches.len() as i64)];
+    for (key, document) in matches.into_iter().skip(offset).take(count) {
+        res.push(key);
+        if !no_content {
+            match document {
+                RedisValue::Json(text) => res.push(RedisValue::Array(vec![
+                    RedisValue::BulkString(Bytes::from_static(b"$")),
+                    RedisValue::BulkString(text),
+                ])),
+                // --- hashes come as their fields and values, like HGETALL
+                RedisValue::Hash(hash) => res.push(RedisValue::Array(
+                    hash.iter()
+                        .flat_map(|(field, value)| [field.clone(), value.clone()])
+                        .map(RedisValue::BulkString)
+                        .collect(),
+                )),
+                _ => {}
+            }
+        }
+    }
+    let res = RedisValue::Array(res);
+
+    Ok(res)
+}
+
+pub async fn ft_dropindex(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(name) = ctx.arg_str(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'ft.dropindex' command",
+        )));
+    };
+
+    let res = match ctx.db().search_indexes.drop_index(&name) {
+        true => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+        false => RedisValue::SimpleError(Bytes::from(format!("ERR {}: no such index", name))),
+    };
+
+    Ok(res)
+}
+
+pub async fn ft_list(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let res = RedisValue::Array(
+        ctx.db()
+            .search_indexes
+            .names()
+            .into_iter()
+            .map(|name| RedisValue::BulkString(Bytes::from(name)))
+            .collect(),
+    );
+
+    Ok(res)
+}
+
+/// TS.CREATE key [RETENTION ms]
+pub async fn ts_create(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'ts.create' command",
+        )));
+    };
+    let retention = match parse_retention(ctx, 1) {
+        Ok(retention) => retention,
+        Err(e) => return Ok(RedisValue::SimpleError(Bytes::from(e.to_string()))),
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    if get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    )
+    .is_some()
+    {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR TSDB: key already exists",
+        )));
+    }
+    let series = TimeSeries::new(retention.unwrap_or(0));
+    store_value(
+        ctx,
+        &mut main_store,
+        key,
+        RedisValue::TimeSeries(Box::new(series)),
+    )
+    .await;
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// TS.ADD key timestamp|* value [RETENTION ms], creating the series when missing.
+/// Replies the timestamp of the sample
+pub async fn ts_add(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(timestamp), Some(value)) =
+        (ctx.arg_value(0), ctx.arg_str(1), ctx.arg_str(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'ts.add' command",
+        )));
+    };
+    let timestamp = match timestamp.as_str() {
+        "*" => Some(ctx.server.clock.now()),
+        timestamp => timestamp.parse().ok(),
+    };
+    let Some(timestamp) = timestamp else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR TSDB: invalid timestamp",
+        )));
+    };
+    let Some(value) = parse_sample_value(&value) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR TSDB: invalid value",
+        )));
+    };
+    let retention = match parse_retention(ctx, 3) {
+        Ok(retention) => retention,
+        Err(e) => return Ok(RedisValue::SimpleError(Bytes::from(e.to_string()))),
+    };
+
+    change_series(ctx, key, retention, |series| {
+        let closed = series.add(Sample { timestamp, value })?;
+        Ok((timestamp, closed))
+    })
+    .await
+}
+
+/// TS.INCRBY key value [TIMESTAMP ts]: a sample of the newest value plus `value`, at the
+/// current time unless given. A series starts at 0
+pub async fn ts_incrby(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(increment)) = (ctx.arg_value(0), ctx.arg_str(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'ts.incrby' command",
+        )));
+    };
+    let Some(increment) = parse_sample_value(&increment) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR TSDB: invalid increment",
+        )));
+    };
+    let timestamp = match ctx.arg_keyword(2).as_deref() {
+        None => ctx.server.clock.now(),
+        Some(b"TIMESTAMP") => match ctx.arg_integer(3) {
+            Some(timestamp) if timestamp >= 0 => timestamp as u64,
+            _ => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR TSDB: invalid timestamp",
+                )))
+            }
+        },
+        Some(_) => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR syntax error",
+            )))
+        }
+    };
+
+    change_series(ctx, key, None, |series| match series.last() {
+        Some(last) if last.timestamp > timestamp => bail!(
+            "ERR TSDB: timestamp must be equal to or higher than the maximum existing timestamp"
+        ),
+        Some(last) if last.timestamp == timestamp => {
+            series.update_last(last.value + increment);
+            Ok((timestamp, vec![]))
+        }
+        last => {
+            let closed = series.add(Sample {
+                timestamp,
+                value: last.map_or(0.0, |last| last.value) + increment,
+            })?;
+            Ok((timestamp, closed))
+        }
+    })
+    .await
+}
+
+/// Runs `change` on the time series at key, creating it when missing, then adds the
+/// buckets it closed to the destinations of its rules. `change` returns the timestamp
+/// written along with those. Series are changed in place, a new sample doesn't copy the
+/// ones there
+async fn change_series(
+    ctx: &CommandContext<'_>,
+    key: RedisValue,
+    retention: Option<u64>,
+    change: impl FnOnce(&mut TimeSeries) -> Result<(u64, Vec<(Bytes, Sample)>)>,
+) -> Result<RedisValue> {
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::TimeSeries(_)) => {
+            if !ctx.session.no_touch {
+                touch(ctx, &key).await;
+            }
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            let series = TimeSeries::new(retention.unwrap_or(0));
+            store_value(
+                ctx,
+                &mut main_store,
+                key.clone(),
+                RedisValue::TimeSeries(Box::new(series)),
+            )
+            .await;
+        }
+    }
+
+    let Some(value) = main_store.get_mut(&key) else {
+        unreachable!("The series was just looked up or created");
+    };
+    ctx.db().memory.remove_entry(&key, value);
+    let RedisValue::TimeSeries(series) = value else {
+        unreachable!("The value was just checked to be a series");
+    };
+    let changed = change(series);
+    ctx.db().memory.add_entry(&key, value);
+    let (timestamp, closed) = match changed {
+        Ok(changed) => changed,
+        Err(e) => return Ok(RedisValue::SimpleError(Bytes::from(e.to_string()))),
+    };
+
+    // --- destinations aren't sources of their own, rules don't chain
+    for (dest, sample) in closed {
+        let dest = RedisValue::BulkString(dest);
+        let Some(value @ RedisValue::TimeSeries(_)) = main_store.get_mut(&dest) else {
+            continue;
+        };
+        ctx.db().memory.remove_entry(&dest, value);
+        if let RedisValue::TimeSeries(series) = value {
+            let _ = series.add(sample);
+        }
+        ctx.db().memory.add_entry(&dest, value);
+    }
+    ctx.server.save_state.mark_dirty();
+
+    let res = RedisValue::Integer(timestamp as i64);
+
+    Ok(res)
+}
+
+/// TS.GET key, the newest sample
+pub async fn ts_get(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'ts.get' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let last = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::TimeSeries(series)) => series.last(),
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR TSDB: the key does not exist",
+            )))
+        }
+    };
+    record_read(ctx, &key, true).await;
+
+    let res = match last {
+        Some(last) => sample_reply(last),
+        None => RedisValue::Array(vec![]),
+    };
+
+    Ok(res)
+}
+
+/// TS.RANGE key from|- to|+ [AGGREGATION type bucket] [COUNT n]
+pub async fn ts_range(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(from), Some(to)) = (ctx.arg_value(0), ctx.arg_str(1), ctx.arg_str(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'ts.range' command",
+        )));
+    };
+    let from = match from.as_str() {
+        "-" => Some(0),
+        from => from.parse().ok(),
+    };
+    let to = match to.as_str() {
+        "+" => Some(u64::MAX),
+        to => to.parse().ok(),
+    };
+    let (Some(from), Some(to)) = (from, to) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR TSDB: invalid timestamp",
+        )));
+    };
+    let mut aggregation = None;
+    let mut count = usize::MAX;
+    let mut pos = 3;
+    while let Some(option) = ctx.arg_keyword(pos) {
+        match option.as_slice() {
+            b"AGGREGATION" => {
+                let parsed = ctx
+                    .arg_str(pos + 1)
+                    .and_then(|name| Aggregation::parse(&name));
+                match (parsed, ctx.arg_integer(pos + 2)) {
+                    (Some(parsed), Some(bucket)) if bucket > 0 => {
+                        aggregation = Some((parsed, bucket as u64));
+                    }
+                    _ => {
+                        return Ok(RedisValue::SimpleError(Bytes::from_static(
+                            b"ERR TSDB: invalid aggregation",
+                        )))
+                    }
+                }
+                pos += 3;
+            }
+            b"COUNT" => {
+                let Some(parsed) = ctx.arg_integer(pos + 1).filter(|count| *count >= 0) else {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR TSDB: invalid COUNT",
+                    )));
+                };
+                count = parsed as usize;
+                pos += 2;
+            }
+            _ => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR syntax error",
+                )))
+            }
+        }
+    }
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let samples = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::TimeSeries(series)) => match aggregation {
+            Some((aggregation, bucket)) => series.aggregate(from, to, aggregation, bucket),
+            None => series.range(from, to).take(count).copied().collect(),
+        },
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR TSDB: the key does not exist",
+            )))
+        }
+    };
+    record_read(ctx, &key, true).await;
+
+    let res = RedisValue::Array(samples.into_iter().take(count).map(sample_reply).collect());
+
+    Ok(res)
+}
+
+/// TS.CREATERULE source dest AGGREGATION type bucket
+pub async fn ts_createrule(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(source), Some(dest), Some(b"AGGREGATION"), Some(aggregation), Some(bucket)) = (
+        ctx.arg_value(0),
+        ctx.arg_value(1),
+        ctx.arg_keyword(2).as_deref(),
+        ctx.arg_str(3).and_then(|name| Aggregation::parse(&name)),
+        ctx.arg_integer(4).filter(|bucket| *bucket > 0),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR TSDB: invalid arguments, expected source dest AGGREGATION type bucket",
+        )));
+    };
+    if source == dest {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR TSDB: the source key and destination key should be different",
+        )));
+    }
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &dest,
+        now,
+    ) {
+        Some(RedisValue::TimeSeries(_)) => {}
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR TSDB: the key does not exist",
+            )))
+        }
+    }
+    let RedisValue::BulkString(dest) = dest else {
+        unreachable!("Arguments are bulk strings");
+    };
+    match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &source,
+        now,
+    ) {
+        Some(RedisValue::TimeSeries(series)) => {
+            if series.rules.iter().any(|rule| rule.dest == dest) {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR TSDB: the destination key already has a rule",
+                )));
+            }
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR TSDB: the key does not exist",
+            )))
+        }
+    }
+
+    // --- rules count towards the size of their source series
+    let Some(value) = main_store.get_mut(&source) else {
+        unreachable!("The series was just looked up");
+    };
+    ctx.db().memory.remove_entry(&source, value);
+    let RedisValue::TimeSeries(series) = value else {
+        unreachable!("The value was just checked to be a series");
+    };
+    series.rules.push(Rule {
+        dest,
+        aggregation,
+        bucket_duration: bucket as u64,
+        bucket_start: None,
+    });
+    ctx.db().memory.add_entry(&source, value);
+    ctx.server.save_state.mark_dirty();
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// TS.DELETERULE source dest
+pub async fn ts_deleterule(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(source), Some(dest)) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'ts.deleterule' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &source,
+        now,
+    ) {
+        Some(RedisValue::TimeSeries(series)) => {
+            if !series.rules.iter().any(|rule| rule.dest == dest) {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR TSDB: compaction rule does not exist",
+                )));
+            }
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR TSDB: the key does not exist",
+            )))
+        }
+    }
+
+    // --- rules count towards the size of their source series
+    let Some(value) = main_store.get_mut(&source) else {
+        unreachable!("The series was just looked up");
+    };
+    ctx.db().memory.remove_entry(&source, value);
+    let RedisValue::TimeSeries(series) = value else {
+        unreachable!("The value was just checked to be a series");
+    };
+    series.rules.retain(|rule| rule.dest != dest);
+    ctx.db().memory.add_entry(&source, value);
+    ctx.server.save_state.mark_dirty();
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// RETENTION option at `pos`, if given
+fn parse_retention(ctx: &CommandContext<'_>, pos: usize) -> Result<Option<u64>> {
+    match ctx.arg_keyword(pos).as_deref() {
+        None => Ok(None),
+        Some(b"RETENTION") => match ctx.arg_integer(pos + 1) {
+            Some(retention) if retention >= 0 => Ok(Some(retention as u64)),
+            _ => bail!("ERR TSDB: invalid RETENTION"),
+        },
+        Some(_) => bail!("ERR syntax error"),
+    }
+}
+
+fn parse_sample_value(value: &str) -> Option<f64> {
+    value.parse().ok().filter(|value: &f64| value.is_finite())
+}
+
+/// A sample as TS.GET and TS.RANGE reply it: timestamp, then value as text
+fn sample_reply(sample: Sample) -> RedisValue {
+    RedisValue::Array(vec![
+        RedisValue::Integer(sample.timestamp as i64),
+        RedisValue::BulkString(Bytes::from(sample.value.to_string())),
+    ])
+}
+
+/// BF.RESERVE key error_rate capacity [EXPANSION n] [NONSCALING]
+pub async fn bf_reserve(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(error_rate), Some(capacity)) = (
+        ctx.arg_value(0),
+        ctx.arg_str(1).and_then(|rate| rate.parse::<f64>().ok()),
+        ctx.arg_integer(2),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'bf.reserve' command",
+        )));
+    };
+    let mut expansion = DEFAULT_EXPANSION;
+    let mut pos = 3;
+    while let Some(option) = ctx.arg_keyword(pos) {
+        match option.as_slice() {
+            b"NONSCALING" => expansion = 0,
+            b"EXPANSION" => {
+                let Some(parsed) = ctx.arg_integer(pos + 1).filter(|expansion| *expansion > 0)
+                else {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR bad expansion",
+                    )));
+                };
+                expansion = parsed.min(u32::MAX as i64) as u32;
+                pos += 1;
+            }
+            _ => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR syntax error",
+                )))
+            }
+        }
+        pos += 1;
+    }
+    let filter = match BloomFilter::new(error_rate, capacity.max(0) as u64, expansion) {
+        Ok(filter) => filter,
+        Err(e) => return Ok(RedisValue::SimpleError(Bytes::from(e.to_string()))),
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    if get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    )
+    .is_some()
+    {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR item exists",
+        )));
+    }
+    store_value(
+        ctx,
+        &mut main_store,
+        key,
+        RedisValue::Bloom(Box::new(filter)),
+    )
+    .await;
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// BF.ADD key item, 1 when the item is new
+pub async fn bf_add(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(item)) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'bf.add' command",
+        )));
+    };
+
+    let res = match add_to_filter(ctx, key, std::slice::from_ref(item)).await? {
+        RedisValue::Array(mut added) => added.remove(0),
+        other => other,
+    };
+
+    Ok(res)
+}
+
+/// BF.MADD key item [item ...], whether each item is new
+pub async fn bf_madd(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0).filter(|_| ctx.args.len() > 1) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'bf.madd' command",
+        )));
+    };
+
+    add_to_filter(ctx, key, &ctx.args[1..]).await
+}
+
+/// Adds items to the filter at key, created with the default error rate and capacity
+/// when missing. Replies an array of what each item gave, a full filter failing the rest
+async fn add_to_filter(
+    ctx: &CommandContext<'_>,
+    key: RedisValue,
+    items: &[Bytes],
+) -> Result<RedisValue> {
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Bloom(_)) => {
+            if !ctx.session.no_touch {
+                touch(ctx, &key).await;
+            }
+        }
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            let filter = BloomFilter::new(DEFAULT_ERROR_RATE, DEFAULT_CAPACITY, DEFAULT_EXPANSION)?;
+            store_value(
+                ctx,
+                &mut main_store,
+                key.clone(),
+                RedisValue::Bloom(Box::new(filter)),
+            )
+            .await;
+        }
+    }
+
+    let Some(value) = main_store.get_mut(&key) else {
+        unreachable!("The filter was just looked up or created");
+    };
+    ctx.db().memory.remove_entry(&key, value);
+    let RedisValue::Bloom(filter) = value else {
+        unreachable!("The value was just checked to be a filter");
+    };
+    let mut added = vec![];
+    for item in items {
+        added.push(match filter.add(item) {
+            Ok(new) => RedisValue::Integer(new as i64),
+            Err(e) => RedisValue::SimpleError(Bytes::from(e.to_string())),
+        });
+    }
+    ctx.db().memory.add_entry(&key, value);
+    ctx.server.save_state.mark_dirty();
+
+    let res = RedisValue::Array(added);
+
+    Ok(res)
+}
+
+/// BF.EXISTS key item, 1 when the item may have been added
+pub async fn bf_exists(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(key), Some(item)) = (ctx.arg_value(0), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'bf.exists' command",
+        )));
+    };
+
+    let res = match check_filter(ctx, key, std::slice::from_ref(item)).await? {
+        RedisValue::Array(mut found) => found.remove(0),
+        other => other,
+    };
+
+    Ok(res)
+}
+
+/// BF.MEXISTS key item [item ...]
+pub async fn bf_mexists(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0).filter(|_| ctx.args.len() > 1) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'bf.mexists' command",
+        )));
+    };
+
+    check_filter(ctx, key, &ctx.args[1..]).await
+}
+
+/// Whether each item may be in the filter at key, none of them when it's missing
+async fn check_filter(
+    ctx: &CommandContext<'_>,
+    key: RedisValue,
+    items: &[Bytes],
+) -> Result<RedisValue> {
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let found = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Bloom(filter)) => items.iter().map(|item| filter.contains(item)).collect(),
+        Some(_) => return Ok(wrong_type()),
+        None => vec![false; items.len()],
+    };
+    let exists = main_store.contains_key(&key);
+    drop((main_store, expire_store));
+    record_read(ctx, &key, exists).await;
+
+    let res = RedisValue::Array(
+        found
+            .into_iter()
+            .map(|found| RedisValue::Integer(found as i64))
+            .collect(),
+    );
+
+    Ok(res)
+}
+
+/// BF.INFO key, the sizing and fill of a filter
+pub async fn bf_info(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'bf.info' command",
+        )));
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let filter = match get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &key,
+        now,
+    ) {
+        Some(RedisValue::Bloom(filter)) => filter,
+        Some(_) => return Ok(wrong_type()),
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR not found",
+            )))
+        }
+    };
+    let fields = [
+        ("Capacity", filter.capacity() as i64),
+        ("Size", filter.size() as i64),
+        ("Number of filters", filter.layers().len() as i64),
+        ("Number of items inserted", filter.len() as i64),
+        ("Expansion rate", filter.expansion as i64),
+    ];
+
+    let res = RedisValue::Array(
+        fields
+            .into_iter()
+            .flat_map(|(name, value)| {
+                [
+                    RedisValue::SimpleString(Bytes::from_static(name.as_bytes())),
+                    RedisValue::Integer(value),
+                ]
+            })
+            .collect(),
+    );
+
+    Ok(res)
+}
+
+/// Reply to a command used on a key holding another type of value
+fn wrong_type() -> RedisValue {
+    RedisValue::SimpleError(Bytes::from_static(
+        b"WRONGTYPE Operation against a key holding the wrong kind of value",
+    ))
+}
+
+/// Returns the value stored at key, lazily removing it if it has expired
+fn get_live_value(
+    server: &RedisServer,
+    db: &Database,
+    main_store: &mut Keyspace,
+    expire_store: &mut Expires,
+    key: &RedisValue,
+    now: u64,
+) -> Option<RedisValue> {
+    get_live_value_mut(server, db, main_store, expire_store, key, now).map(|value| value.clone())
+}
+
+/// `get_live_value` without the copy, for values changed in place
+fn get_live_value_mut<'a>(
+    server: &RedisServer,
+    db: &Database,
+    main_store: &'a mut Keyspace,
+    expire_store: &mut Expires,
+    key: &RedisValue,
+    now: u64,
+) -> Option<&'a mut RedisValue> {
+    let timestamp = expire_store.get(key).copied().unwrap_or(u64::MAX);
+
+    if timestamp < now {
+        let val = main_store.remove(key)?;
+        db.memory.remove_entry(key, &val);
+        db.search_indexes.remove(key);
+        server.stats.record_expired_key();
+        server.key_changed(db.index, "expired", key);
+        expire_store.remove(key);
+        None
+    } else {
+        main_store.get_mut(key)
+    }
+}
+
+/// Bookkeeping after a read lookup: keyspace hit/miss stats and the key's access metadata
+async fn record_read(ctx: &CommandContext<'_>, key: &RedisValue, hit: bool) {
+    ctx.server.stats.record_lookup(hit);
+
+    if !hit {
+        ctx.db().access_store.lock().await.remove(key);
+    } else if !ctx.session.no_touch {
+        touch(ctx, key).await;
+    }
+}
+
+/// Records an access to an existing key, see `KeyAccess::touch`
+async fn touch(ctx: &CommandContext<'_>, key: &RedisValue) {
+    let now = ctx.server.clock.now();
+    let (lfu_log_factor, lfu_decay_time) = {
+        let live = ctx.server.config.live.read().unwrap();
+        (live.lfu_log_factor, live.lfu_decay_time)
+    };
+
+    ctx.db()
+        .access_store
+        .lock()
+        .await
+        .entry(key.clone())
+        .or_insert_with(|| KeyAccess::new(now))
+        .touch(now, lfu_log_factor, lfu_decay_time);
+}
+
+fn parse_integer(arg: &[u8]) -> Option<i64> {
+    str::from_utf8(arg).ok()?.parse().ok()
+}
+
+/// Integer a string holds when it is written the way Redis would write it back: no sign
+/// but a minus, no leading zeros or spaces
+fn parse_canonical_integer(arg: &[u8]) -> Option<i64> {
+    parse_integer(arg).filter(|n| n.to_string().as_bytes() == arg)
+}
+
+/// Finite float a string holds, no spaces around it
+fn parse_float(arg: &[u8]) -> Option<f64> {
+    str::from_utf8(arg)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|value: &f64| value.is_finite())
+}
+
+/// MULTI: starts a transaction, commands are queued until EXEC or DISCARD
+pub async fn multi(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if !ctx.args.is_empty() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'multi' command",
+        )));
+    }
+    if ctx.session.transaction.is_some() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR MULTI calls can not be nested",
+        )));
+    }
+    ctx.session.transaction = Some(Transaction::default());
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// EXEC: runs the commands queued since MULTI with nothing else running in between,
+/// replying with the array of their replies, or a null array without running any when a
+/// WATCHed key changed. Their writes reach replicas and the
+/// append-only file wrapped in MULTI and EXEC, so they get applied as one there too
+pub async fn exec(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if !ctx.args.is_empty() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'exec' command",
+        )));
+    }
+    let Some(transaction) = ctx.session.transaction.take() else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR EXEC without MULTI",
+        )));
+    };
+    if transaction.aborted {
+        forget_watched(ctx);
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"EXECABORT Transaction discarded because of previous errors.",
+        )));
+    }
+
+    let exec_guard = tokio::select! {
+        biased;
+        guard = ctx.server.exec_lock.write() => guard,
+        () = ctx.server.running_script.overrun() => {
+            forget_watched(ctx);
+            return Ok(busy_script());
+        }
+    };
+    // --- checked under the lock, no write can land between the check and the commands
+    let dirty = ctx.server.watched_keys.changed(&ctx.session.watched);
+    forget_watched(ctx);
+    if dirty {
+        return Ok(RedisValue::NullArray);
+    }
+    // --- blocking commands and scripts replicate their writes on their own, inside the block
+    let writes = transaction
+        .commands
+        .iter()
+        .any(|(cmd, _)| command_spec(cmd).is_some_and(|spec| spec.write));
+    // --- the block starts in the database of the connection, SELECT going out ahead of it
+    if writes {
+        append_to_aof(ctx.server, Some(ctx.session.db), "MULTI", &[])?;
+        propagate(ctx.server, Some(ctx.session.db), "MULTI", &[])?;
+    }
+    ctx.session.in_exec = true;
+    let mut replies = vec![];
+    for (cmd, args) in &transaction.commands {
+        let mut ctx = CommandContext {
+            args,
+            server: ctx.server,
+            session: ctx.session,
+        };
+        replies.push(Box::pin(execute(cmd, &mut ctx)).await);
+    }
+    ctx.session.in_exec = false;
+    if writes {
+        append_to_aof(ctx.server, None, "EXEC", &[])?;
+        propagate(ctx.server, None, "EXEC", &[])?;
+    }
+    drop(exec_guard);
+
+    // --- SUBSCRIBE and friends add one element per channel, the way Redis does
+    let mut res = vec![];
+    for reply in replies {
+        match reply? {
+            RedisValue::Replies(replies) => res.extend(replies),
+            reply => res.push(reply),
+        }
+    }
+    let res = RedisValue::Array(res);
+
+    Ok(res)
+}
+
+/// DISCARD: drops the commands queued since MULTI
+pub async fn discard(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if !ctx.args.is_empty() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'discard' command",
+        )));
+    }
+    if ctx.session.transaction.take().is_none() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR DISCARD without MULTI",
+        )));
+    }
+    forget_watched(ctx);
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// WATCH key [key ...]: makes the next EXEC fail if any of the keys changes before it
+pub async fn watch(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.args.is_empty() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'watch' command",
+        )));
+    }
+    if ctx.session.transaction.is_some() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR WATCH inside MULTI is not allowed",
+        )));
+    }
+    let db = ctx.session.db;
+    for key in ctx.args.iter().cloned().map(RedisValue::BulkString) {
+        if ctx
+            .session
+            .watched
+            .iter()
+            .any(|(watched_db, watched, _)| *watched_db == db && *watched == key)
+        {
+            continue;
+        }
+        let version = ctx.server.watched_keys.watch(ctx.session.id, db, &key);
+        ctx.session.watched.push((db, key, version));
+    }
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// UNWATCH: forgets the keys WATCHed so far
+pub async fn unwatch(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if !ctx.args.is_empty() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'unwatch' command",
+        )));
+    }
+    forget_watched(ctx);
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// Drops the connection's WATCHed keys, as EXEC, DISCARD and UNWATCH do
+fn forget_watched(ctx: &mut CommandContext<'_>) {
+    ctx.session.watched.clear();
+    ctx.server.watched_keys.unwatch_all(ctx.session.id);
+}
+
+/// EVAL script numkeys [key ...] [arg ...]: runs a Lua script, atomically, the keys it
+/// takes in KEYS and the other arguments in ARGV. The script is kept for EVALSHA
+pub async fn eval(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(body) = ctx.args.first().cloned() else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let sha = scripting::sha1_hex(&body);
+    if !ctx.server.scripts.contains(&sha) {
+        if let Err(reply) = scripting::check(&body) {
+            return Ok(reply);
+        }
+        ctx.server.scripts.insert(body.clone());
+    }
+
+    let res = run_script(ctx, sha, body).await?;
+
+    Ok(res)
+}
+
+/// EVALSHA sha1 numkeys [key ...] [arg ...]: EVAL of a script EVAL ran or SCRIPT LOAD
+/// loaded before
+pub async fn evalsha(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(sha) = ctx.arg_str(0).map(|sha| sha.to_lowercase()) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let Some(body) = ctx.server.scripts.get(&sha) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"NOSCRIPT No matching script. Please use EVAL.",
+        )));
+    };
+
+    let res = run_script(ctx, sha, body).await?;
+
+    Ok(res)
+}
+
+/// Runs a script under the exec lock, taken exclusively the way EXEC takes it. Its writes
+/// are replicated one by one, in a MULTI/EXEC block, or with `lua-replicate-commands` off
+/// the script itself is, as an EVAL of its body
+async fn run_script(ctx: &mut CommandContext<'_>, sha: String, body: Bytes) -> Result<RedisValue> {
+    let numkeys = match ctx.arg_integer(1) {
+        Some(numkeys) if numkeys < 0 => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR Number of keys can't be negative",
+            )))
+        }
+        Some(numkeys) if numkeys as usize > ctx.args.len() - 2 => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR Number of keys can't be greater than number of args",
+            )))
+        }
+        Some(numkeys) => numkeys as usize,
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR value is not an integer or out of range",
+            )))
+        }
+    };
+    let keys = ctx.args[2..2 + numkeys].to_vec();
+    let argv = ctx.args[2 + numkeys..].to_vec();
+
+    // --- inside EXEC the lock is already held
+    let exec_guard = match ctx.session.in_exec {
+        true => None,
+        false => tokio::select! {
+            biased;
+            guard = ctx.server.exec_lock.write() => Some(guard),
+            () = ctx.server.running_script.overrun() => return Ok(busy_script()),
+        },
+    };
+    let (calls, mut requests) = mpsc::unbounded_channel();
+    let (threshold, effects) = {
+        let live = ctx.server.config.live.read().unwrap();
+        (live.busy_reply_threshold, live.lua_replicate_commands)
+    };
+    let running = Arc::clone(&ctx.server.running_script);
+    running.start(threshold);
+    let verbatim = [body.clone()]
+        .into_iter()
+        .chain(ctx.args[1..].iter().cloned())
+        .collect::<Vec<_>>();
+    let script = tokio::task::spawn_blocking(move || {
+        scripting::run(&sha, &body, keys, argv, calls, effects, running)
+    });
+    ctx.session.in_script = true;
+    // --- scripts get RESP2 replies whatever the connection speaks, as in Redis, and a
+    // --- SELECT they make doesn't outlive them
+    let protocol = std::mem::replace(&mut ctx.session.protocol, Protocol::Resp2);
+    let db = ctx.session.db;
+    let mut wrapped = ReplTargets::NONE;
+    let mut wrote = false;
+    let mut failure = None;
+    while let Some(call) = requests.recv().await {
+        let repl = match effects {
+            true => call.repl,
+            false => ReplTargets::NONE,
+        };
+        let reply = match script_call(ctx, &call.args, repl, &mut wrapped, &mut wrote).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        };
+        // --- the script only goes away early when it panicked
+        let _ = call.reply.send(reply);
+    }
+    ctx.session.in_script = false;
+    ctx.session.protocol = protocol;
+    ctx.session.db = db;
+    if wrapped.aof {
+        append_to_aof(ctx.server, None, "EXEC", &[])?;
+    }
+    if wrapped.replicas {
+        propagate(ctx.server, None, "EXEC", &[])?;
+    }
+    // --- EVALSHA goes out as EVAL, replicas may not have the script
+    if !effects && wrote && !ctx.session.is_aof_client {
+        append_to_aof(ctx.server, Some(ctx.session.db), "EVAL", &verbatim)?;
+        propagate(ctx.server, Some(ctx.session.db), "EVAL", &verbatim)?;
+    }
+    let res = match failure {
+        Some(e) => Err(e),
+        None => script.await.map_err(anyhow::Error::from),
+    };
+    ctx.server.running_script.finish();
+    drop(exec_guard);
+    let res = res?;
+
+    Ok(res)
+}
+
+/// A command of a running script, refused when it can't run from one. Its write goes to
+/// the `repl` targets, MULTI going out to each ahead of the first write it gets unless
+/// EXEC sent it; `wrapped` tells where it went
+async fn script_call(
+    ctx: &mut CommandContext<'_>,
+    args: &[Bytes],
+    repl: ReplTargets,
+    wrapped: &mut ReplTargets,
+    wrote: &mut bool,
+) -> Result<RedisValue> {
+    let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+    let spec = ctx
+        .server
+        .config
+        .command_renames
+        .resolve(&name)
+        .and_then(command_spec);
+    if NO_SCRIPT_COMMANDS.contains(&name.as_str()) || spec.is_some_and(|spec| spec.unlocked) {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR This Redis command is not allowed from script",
+        )));
+    }
+    if spec.is_some_and(CommandSpec::propagated) {
+        if !ctx.session.in_exec && !ctx.session.is_aof_client {
+            if repl.aof && !wrapped.aof {
+                append_to_aof(ctx.server, Some(ctx.session.db), "MULTI", &[])?;
+                wrapped.aof = true;
+            }
+            if repl.replicas && !wrapped.replicas {
+                propagate(ctx.server, Some(ctx.session.db), "MULTI", &[])?;
+                wrapped.replicas = true;
+            }
+        }
+        ctx.server.running_script.record_write();
+        *wrote = true;
+    }
+
+    let mut ctx = CommandContext {
+        args: &args[1..],
+        server: ctx.server,
+        session: ctx.session,
+    };
+    ctx.session.repl_targets = repl;
+    let res = Box::pin(execute(&name, &mut ctx)).await;
+    ctx.session.repl_targets = ReplTargets::ALL;
+    let res = res?;
+
+    Ok(res)
+}
+
+/// Reply to the commands a script past `busy-reply-threshold` holds off
+fn busy_script() -> RedisValue {
+    RedisValue::SimpleError(Bytes::from_static(
+        b"BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN \
+          NOSAVE.",
+    ))
+}
+
+/// SCRIPT LOAD script | EXISTS sha1 [sha1 ...] | FLUSH [ASYNC|SYNC] | KILL: manages the
+/// scripts EVALSHA can run, or stops the one running if it didn't write yet
+pub async fn script(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(sub_cmd) = ctx.arg_keyword(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let res = match (sub_cmd.as_slice(), &ctx.args[1..]) {
+        (b"LOAD", [body]) => match scripting::check(body) {
+            Ok(()) => RedisValue::BulkString(Bytes::from(ctx.server.scripts.insert(body.clone()))),
+            Err(reply) => reply,
+        },
+        (b"EXISTS", shas) if !shas.is_empty() => RedisValue::Array(
+            shas.iter()
+                .map(|sha| {
+                    let sha = String::from_utf8_lossy(sha).to_lowercase();
+                    RedisValue::Integer(ctx.server.scripts.contains(&sha) as i64)
+                })
+                .collect(),
+        ),
+        (b"FLUSH", []) => {
+            ctx.server.scripts.flush();
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        (b"FLUSH", [mode])
+            if mode.eq_ignore_ascii_case(b"ASYNC") || mode.eq_ignore_ascii_case(b"SYNC") =>
+        {
+            ctx.server.scripts.flush();
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        (b"KILL", []) => match ctx.server.running_script.kill() {
+            Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+            Err(reply) => reply,
+        },
+        (b"LOAD" | b"EXISTS" | b"FLUSH" | b"KILL", _) => {
+            RedisValue::SimpleError(Bytes::from(format!(
+                "ERR wrong number of arguments for 'script|{}' command",
+                String::from_utf8_lossy(&sub_cmd).to_lowercase()
+            )))
+        }
+        _ => RedisValue::SimpleError(Bytes::from(format!(
+            "ERR unknown subcommand '{}'",
+            String::from_utf8_lossy(&ctx.args[0])
+        ))),
+    };
+
+    Ok(res)
+}
+
+/// SUBSCRIBE channel [channel ...], or PSUBSCRIBE pattern [pattern ...]: gets the
+/// connection the messages published to the channels, or to any channel matching the
+/// glob patterns. Replies with one confirmation per channel, telling how many
+/// subscriptions the connection now has
+pub async fn subscribe(ctx: &mut CommandContext<'_>, patterns: bool) -> Result<RedisValue> {
+    let name = if patterns { "psubscribe" } else { "subscribe" };
+    if ctx.args.is_empty() {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))));
+    }
+    let inbox = ctx.session.inbox();
+    let mut replies = vec![];
+    for channel in ctx.args {
+        let subscribed = match patterns {
+            true => &mut ctx.session.patterns,
+            false => &mut ctx.session.channels,
+        };
+        // --- subscribing twice is fine, messages still come once
+        if subscribed.insert(channel.clone()) {
+            match patterns {
+                true => ctx
+                    .server
+                    .pubsub
+                    .psubscribe(ctx.session.id, channel.clone(), &inbox),
+                false => ctx
+                    .server
+                    .pubsub
+                    .subscribe(ctx.session.id, channel.clone(), &inbox),
+            }
+        }
+        replies.push(subscription_reply(name, Some(channel), ctx.session));
+    }
+
+    let res = RedisValue::Replies(replies);
+
+    Ok(res)
+}
+
+/// UNSUBSCRIBE [channel ...], or PUNSUBSCRIBE [pattern ...]: stops getting the messages
+/// of the channels or patterns given, of all of them when none is. Replies with one
+/// confirmation per channel, telling how many subscriptions the connection has left
+pub async fn unsubscribe(ctx: &mut CommandContext<'_>, patterns: bool) -> Result<RedisValue> {
+    let name = if patterns {
+        "punsubscribe"
+    } else {
+        "unsubscribe"
+    };
+    let subscribed = match patterns {
+        true => &ctx.session.patterns,
+        false => &ctx.session.channels,
+    };
+    let channels = match ctx.args.is_empty() {
+        true => subscribed.iter().cloned().collect(),
+        false => ctx.args.to_vec(),
+    };
+    // --- nothing to unsubscribe from still gets a confirmation, with no channel
+    if channels.is_empty() {
+        let res = RedisValue::Replies(vec![subscription_reply(name, None, ctx.session)]);
+        return Ok(res);
+    }
+    let mut replies = vec![];
+    for channel in &channels {
+        let subscribed = match patterns {
+            true => &mut ctx.session.patterns,
+            false => &mut ctx.session.channels,
+        };
+        if subscribed.remove(channel) {
+            match patterns {
+                true => ctx.server.pubsub.punsubscribe(ctx.session.id, channel),
+                false => ctx.server.pubsub.unsubscribe(ctx.session.id, channel),
+            }
+        }
+        replies.push(subscription_reply(name, Some(channel), ctx.session));
+    }
+
+    let res = RedisValue::Replies(replies);
+
+    Ok(res)
+}
+
+/// Confirmation of a (P)(UN)SUBSCRIBE: its kind, the channel and the subscriptions left
+fn subscription_reply(
+    kind: &'static str,
+    channel: Option<&Bytes>,
+    session: &Session,
+) -> RedisValue {
+    RedisValue::Push(vec![
+        RedisValue::BulkString(Bytes::from_static(kind.as_bytes())),
+        channel.map_or(RedisValue::NullBulkString, |channel| {
+            RedisValue::BulkString(channel.clone())
+        }),
+        RedisValue::Integer(session.subscriptions() as i64),
+    ])
+}
+
+/// PUBLISH channel message: sends the message to the subscribers of the channel, replying
+/// with how many got it
+pub async fn publish(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let [channel, message] = ctx.args else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'publish' command",
+        )));
+    };
+    let receivers = ctx.server.pubsub.publish(channel, message);
+
+    let res = RedisValue::Integer(receivers as i64);
+
+    Ok(res)
+}
+
+/// KEYS pattern: the live keys matching a glob pattern, see `glob_match`
+pub async fn keys(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(pattern), None) = (ctx.args.first(), ctx.args.get(1)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'keys' command",
+        )));
+    };
+    let main_store_lock = ctx.db().main_store.lock().await;
+    let expire_store_lock = ctx.db().expire_store.lock().await;
+
+    let mut res = vec![];
+    let now = ctx.server.clock.now();
+
+    for key in main_store_lock.keys() {
+        // --- if expired, skip it
+        let expire_key = expire_store_lock.get(key);
+        if expire_key.is_some_and(|&k| k < now) {
+            continue;
+        }
+        if !matches!(key, RedisValue::BulkString(name) if glob_match(pattern, name)) {
+            continue;
+        }
+
+        res.push(key.clone());
+    }
+    // --- the scan held the stores all along, so it weighs on the fairness budget
+    ctx.session.charge(main_store_lock.len());
+
+    let res = RedisValue::Array(res);
+
+    Ok(res)
+}
+
+/// Parses a database index, `Err` with the reply to send for one that isn't configured
+fn database_index(
+    ctx: &CommandContext<'_>,
+    arg: &[u8],
+    not_integer: &'static [u8],
+) -> Result<usize, RedisValue> {
+    match parse_integer(arg) {
+        Some(index) if (0..ctx.server.databases.len() as i64).contains(&index) => {
+            Ok(index as usize)
+        }
+        Some(_) => Err(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR DB index is out of range",
+        ))),
+        None => Err(RedisValue::SimpleError(Bytes::from_static(not_integer))),
+    }
+}
+
+/// SELECT index: switches the database the connection's commands run against
+pub async fn select(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let res = match database_index(
+        ctx,
+        &ctx.args[0],
+        b"ERR value is not an integer or out of range",
+    ) {
+        Ok(index) => {
+            ctx.session.db = index;
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        Err(e) => e,
+    };
+
+    Ok(res)
+}
+
+/// SWAPDB index1 index2: exchanges what two databases hold, clients connected to one of
+/// them seeing the other's keys right away
+pub async fn swapdb(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let first = match database_index(ctx, &ctx.args[0], b"ERR invalid first DB index") {
+        Ok(index) => index,
+        Err(e) => return Ok(e),
+    };
+    let second = match database_index(ctx, &ctx.args[1], b"ERR invalid second DB index") {
+        Ok(index) => index,
+        Err(e) => return Ok(e),
+    };
+
+    ctx.server.swap_databases(first, second).await;
+    ctx.server.save_state.mark_dirty();
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// FLUSHDB and FLUSHALL [ASYNC|SYNC]: deletes every key of the selected database, or of
+/// all of them. With ASYNC the old keys are freed in the background
+pub async fn flush(ctx: &mut CommandContext<'_>, all: bool) -> Result<RedisValue> {
+    let lazy = match ctx.arg_keyword(0).as_deref() {
+        None | Some(b"SYNC") => false,
+        Some(b"ASYNC") => true,
+        Some(_) => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR syntax error",
+            )))
+        }
+    };
+
+    let old = match all {
+        true => ctx.server.replace_dataset(Dataset::new()).await?,
+        false => {
+            let old = ctx.server.replace_database(ctx.db(), Db::new()).await;
+            Dataset::from([(ctx.session.db, old)])
+        }
+    };
+    ctx.server.save_state.mark_dirty();
+    // --- dropping a large map takes a while, each value being freed in turn
+    if lazy {
+        tokio::task::spawn_blocking(move || drop(old));
+    }
+
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// MOVE key db: moves a key along with its TTL to another database, replying whether it
+/// did. Nothing moves when the key is already there
+pub async fn move_key(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(key) = ctx.arg_value(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let target = match database_index(
+        ctx,
+        &ctx.args[1],
+        b"ERR value is not an integer or out of range",
+    ) {
+        Ok(index) => index,
+        Err(e) => return Ok(e),
+    };
+    if target == ctx.session.db {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR source and destination objects are the same",
+        )));
+    }
+
+    let source = ctx.db();
+    let target = &ctx.server.databases[target];
+    let mut stores = lock_databases(source, target).await;
+    let [(source_main, source_expire), (target_main, target_expire)] = &mut stores;
+
+    let now = ctx.server.clock.now();
+    if get_live_value_mut(ctx.server, source, source_main, source_expire, &key, now).is_none()
+        || get_live_value_mut(ctx.server, target, target_main, target_expire, &key, now).is_some()
+    {
+        return Ok(RedisValue::Integer(0));
+    }
+
+    let Some(value) = source_main.remove(&key) else {
+        unreachable!("The key was just looked up");
+    };
+    let ttl = source_expire.remove(&key);
+    source.search_indexes.remove(&key);
+    source.memory.remove_entry(&key, &value);
+    let access = source.access_store.lock().await.remove(&key);
+    target.search_indexes.update(&key, &value);
+    target.memory.add_entry(&key, &value);
+    target_main.insert(key.clone(), value);
+    if let Some(access) = access {
+        target.access_store.lock().await.insert(key.clone(), access);
+    }
+    if let Some(deadline) = ttl {
+        target_expire.insert(key.clone(), deadline);
+        if ctx.server.config.expiry_mode == ExpiryMode::Precise {
+            target.expiry_timers.schedule(key.clone(), deadline);
+        }
+    }
+    drop(stores);
+    ctx.server.save_state.mark_dirty();
+    ctx.server.key_changed(source.index, "move_from", &key);
+    ctx.server.key_changed(target.index, "move_to", &key);
+    ctx.server
+        .blocked_clients
+        .signal_key_ready(target.index, &key);
+
+    let res = RedisValue::Integer(1);
+
+    Ok(res)
+}
+
+/// Main and expire stores of two different databases, locked in index order the way
+/// everything locking several databases does
+async fn lock_databases<'a>(
+    first: &'a Database,
+    second: &'a Database,
+) -> [(MutexGuard<'a, Keyspace>, MutexGuard<'a, Expires>); 2] {
+    let (low, high) = match first.index < second.index {
+        true => (first, second),
+        false => (second, first),
+    };
+    let low_stores = (low.main_store.lock().await, low.expire_store.lock().await);
+    let high_stores = (high.main_store.lock().await, high.expire_store.lock().await);
+
+    match first.index < second.index {
+        true => [low_stores, high_stores],
+        false => [high_stores, low_stores],
+    }
+}
+
+/// RENAME and RENAMENX source destination: moves a value along with its TTL, replacing
+/// the destination unless `only_new`. RENAMENX replies whether it moved it
+pub async fn rename(ctx: &mut CommandContext<'_>, only_new: bool) -> Result<RedisValue> {
+    let (Some(source), Some(dest)) = (ctx.arg_value(0), ctx.arg_value(1)) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    if get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &source,
+        now,
+    )
+    .is_none()
+    {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR no such key",
+        )));
+    }
+    let dest_exists = get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &dest,
+        now,
+    )
+    .is_some();
+    if only_new && dest_exists {
+        return Ok(RedisValue::Integer(0));
+    }
+    if source == dest {
+        let res = match only_new {
+            true => RedisValue::Integer(0),
+            false => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+        };
+        return Ok(res);
+    }
+
+    let Some(value) = main_store.remove(&source) else {
+        unreachable!("The source was just looked up");
+    };
+    ctx.db().memory.remove_entry(&source, &value);
+    ctx.db().search_indexes.remove(&source);
+    ctx.db().access_store.lock().await.remove(&source);
+    let ttl = expire_store.remove(&source);
+    expire_store.remove(&dest);
+    store_value(ctx, &mut main_store, dest.clone(), value).await;
+    if let Some(deadline) = ttl {
+        set_deadline(ctx, &mut expire_store, &dest, deadline);
+    }
+    ctx.server
+        .key_changed(ctx.session.db, "rename_from", &source);
+    ctx.server.key_changed(ctx.session.db, "rename_to", &dest);
+
+    let res = match only_new {
+        true => RedisValue::Integer(1),
+        false => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+    };
+
+    Ok(res)
+}
+
+/// COPY source destination [DB index] [REPLACE]: copies a value along with its TTL,
+/// replying whether it did. An existing destination is only overwritten with REPLACE
+pub async fn copy(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(source), Some(dest)) = (ctx.arg_value(0), ctx.arg_value(1)) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let mut replace = false;
+    let mut target = ctx.session.db;
+    let mut pos = 2;
+    while pos < ctx.args.len() {
+        match ctx.arg_keyword(pos).as_deref() {
+            Some(b"REPLACE") => replace = true,
+            Some(b"DB") if pos + 1 < ctx.args.len() => {
+                target = match database_index(
+                    ctx,
+                    &ctx.args[pos + 1],
+                    b"ERR value is not an integer or out of range",
+                ) {
+                    Ok(index) => index,
+                    Err(e) => return Ok(e),
+                };
+                pos += 1;
+            }
+            _ => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR syntax error",
+                )))
+            }
+        }
+        pos += 1;
+    }
+    if target != ctx.session.db {
+        return copy_to_database(ctx, source, dest, target, replace).await;
+    }
+    if source == dest {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR source and destination objects are the same",
+        )));
+    }
+
+    let mut main_store = ctx.db().main_store.lock().await;
+    let mut expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let Some(value) = get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &source,
+        now,
+    )
+    .cloned() else {
+        return Ok(RedisValue::Integer(0));
+    };
+    let dest_exists = get_live_value_mut(
+        ctx.server,
+        ctx.db(),
+        &mut main_store,
+        &mut expire_store,
+        &dest,
+        now,
+    )
+    .is_some();
+    if dest_exists && !replace {
+        return Ok(RedisValue::Integer(0));
+    }
+
+    store_value(ctx, &mut main_store, dest.clone(), value).await;
+    expire_store.remove(&dest);
+    if let Some(deadline) = expire_store.get(&source).copied() {
+        set_deadline(ctx, &mut expire_store, &dest, deadline);
+    }
+    ctx.server.key_changed(ctx.session.db, "copy_to", &dest);
+
+    let res = RedisValue::Integer(1);
+
+    Ok(res)
+}
+
+/// COPY with a DB other than the selected one, both databases locked in index order
+async fn copy_to_database(
+    ctx: &mut CommandContext<'_>,
+    source: RedisValue,
+    dest: RedisValue,
+    target: usize,
+    replace: bool,
+) -> Result<RedisValue> {
+    let source_db = ctx.db();
+    let target_db = &ctx.server.databases[target];
+    let mut stores = lock_databases(source_db, target_db).await;
+    let [(source_main, source_expire), (target_main, target_expire)] = &mut stores;
+
+    let now = ctx.server.clock.now();
+    let Some(value) = get_live_value_mut(
+        ctx.server,
+        source_db,
+        source_main,
+        source_expire,
+        &source,
+        now,
+    )
+    .cloned() else {
+        return Ok(RedisValue::Integer(0));
+    };
+    let dest_exists = get_live_value_mut(
+        ctx.server,
+        target_db,
+        target_main,
+        target_expire,
+        &dest,
+        now,
+    )
+    .is_some();
+    if dest_exists && !replace {
+        return Ok(RedisValue::Integer(0));
+    }
+    let ttl = source_expire.get(&source).copied();
+
+    // --- storing goes through the selected database's bookkeeping, so it switches over
+    // --- for the time of the write
+    let selected = ctx.session.db;
+    ctx.session.db = target;
+    store_value(ctx, target_main, dest.clone(), value).await;
+    target_expire.remove(&dest);
+    if let Some(deadline) = ttl {
+        set_deadline(ctx, target_expire, &dest, deadline);
+    }
+    ctx.session.db = selected;
+    drop(stores);
+    ctx.server.key_changed(target, "copy_to", &dest);
+
+    let res = RedisValue::Integer(1);
+
+    Ok(res)
+}
+
+/// Gives a key the absolute expire time in ms, timer included in precise expiry mode
+fn set_deadline(
+    ctx: &CommandContext<'_>,
+    expire_store: &mut Expires,
+    key: &RedisValue,
+    deadline: u64,
+) {
+    expire_store.insert(key.clone(), deadline);
+    if ctx.server.config.expiry_mode == ExpiryMode::Precise {
+        ctx.db().expiry_timers.schedule(key.clone(), deadline);
+    }
+}
+
+/// RANDOMKEY: a key drawn uniformly among the live ones, nil when there are none
+pub async fn randomkey(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let main_store = ctx.db().main_store.lock().await;
+    let expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let key = main_store
+        .keys()
+        .filter(|key| {
+            expire_store
+                .get(*key)
+                .is_none_or(|timestamp| *timestamp >= now)
+        })
+        .choose(&mut rand::thread_rng());
+    // --- the draw went through the whole keyspace, like KEYS
+    ctx.session.charge(main_store.len());
+
+    let res = key.cloned().unwrap_or(RedisValue::NullBulkString);
+
+    Ok(res)
+}
+
+/// DBSIZE: number of keys, those past their TTL left out
+pub async fn dbsize(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let main_store = ctx.db().main_store.lock().await;
+    let expire_store = ctx.db().expire_store.lock().await;
+
+    let now = ctx.server.clock.now();
+    let expired = expire_store
+        .iter()
+        .filter(|(key, timestamp)| **timestamp < now && main_store.contains_key(*key))
+        .count();
+
+    let res = RedisValue::Integer((main_store.len() - expired) as i64);
+
+    Ok(res)
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count]: the next keys of an iteration over the
+/// keyspace and the cursor to go on with, 0 once it is done. Cursors are positions in the
+/// order `Keyspace::scan` walks, so keys there all along come up exactly once whatever is
+/// written in between. A call only visits the COUNT or so keys from its cursor on
+pub async fn scan(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let syntax_error = || RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error"));
+    let Some(cursor) = ctx.args.first() else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'scan' command",
+        )));
+    };
+    let Some(cursor) = str::from_utf8(cursor)
+        .ok()
+        .and_then(|c| c.parse::<u64>().ok())
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR invalid cursor",
+        )));
+    };
+    let mut pattern = None;
+    let mut count = 10;
+    let mut pos = 1;
+    while let Some(option) = ctx.arg_keyword(pos) {
+        let Some(value) = ctx.args.get(pos + 1) else {
+            return Ok(syntax_error());
+        };
+        match option.as_slice() {
+            b"MATCH" => pattern = Some(value),
+            b"COUNT" => match parse_integer(value) {
+                Some(n) if n >= 1 => count = n as usize,
+                Some(_) => return Ok(syntax_error()),
+                None => {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR value is not an integer or out of range",
+                    )))
+                }
+            },
+            _ => return Ok(syntax_error()),
+        }
+        pos += 2;
+    }
+
+    let main_store = ctx.db().main_store.lock().await;
+    let expire_store = ctx.db().expire_store.lock().await;
+    let now = ctx.server.clock.now();
+    let (batch, next_cursor) = main_store.scan(cursor, count);
+    // --- only the keys visited count, not the size of the keyspace
+    ctx.session.charge(batch.len());
+    let keys = batch
+        .into_iter()
+        .filter(|key| {
+            expire_store
+                .get(*key)
+                .is_none_or(|timestamp| *timestamp >= now)
+        })
+        .filter(|key| match (pattern, key) {
+            (Some(pattern), RedisValue::BulkString(name)) => glob_match(pattern, name),
+            (Some(_), _) => false,
+            (None, _) => true,
+        })
+        .cloned()
+        .collect();
+
+    let res = RedisValue::Array(vec![
+        RedisValue::BulkString(Bytes::from(next_cursor.to_string())),
+        RedisValue::Array(keys),
+    ]);
+
+    Ok(res)
+}
+
+/// CONFIG GET pattern [pattern ...], CONFIG SET parameter value [parameter value ...] and
+/// CONFIG RELOAD, reading the config file again
+pub async fn config(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(sub_cmd) = ctx.arg_keyword(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+
+    let res = match sub_cmd.as_slice() {
+        b"GET" if ctx.args.len() < 2 => RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'config|get' command",
+        )),
+        b"GET" => {
+            let patterns = (1..ctx.args.len())
+                .filter_map(|pos| ctx.arg_str(pos))
+                .collect::<Vec<_>>();
+            RedisValue::Map(
+                ctx.server
+                    .config_get(&patterns)
+                    .into_iter()
+                    .map(|(name, value)| {
+                        (
+                            RedisValue::BulkString(Bytes::from(name)),
+                            RedisValue::BulkString(Bytes::from(value)),
+                        )
+                    })
+                    .collect(),
+            )
+        }
+        b"SET" => {
+            let pairs = ctx.args[1..]
+                .chunks(2)
+                .map(|pair| {
+                    let [name, value] = pair else {
+                        return None;
+                    };
+                    Some((
+                        String::from_utf8_lossy(name).into_owned(),
+                        String::from_utf8_lossy(value).into_owned(),
+                    ))
+                })
+                .collect::<Option<Vec<_>>>();
+            match pairs.filter(|pairs| !pairs.is_empty()) {
+                Some(pairs) => match ctx.server.config_set(&pairs) {
+                    Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+                    Err(e) => RedisValue::SimpleError(Bytes::from(e.to_string())),
+                },
+                None => RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR wrong number of arguments for 'config|set' command",
+                )),
+            }
+        }
+        b"RELOAD" if ctx.args.len() == 1 => match ctx.server.config_reload() {
+            Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+            Err(e) => RedisValue::SimpleError(Bytes::from(e.to_string())),
+        },
+        _ => RedisValue::SimpleError(Bytes::from(format!(
+            "ERR unknown subcommand '{}'",
+            String::from_utf8_lossy(&ctx.args[0])
+        ))),
+    };
+
+    Ok(res)
+}
+
+/// MONITOR: streams every command other clients run to this connection, until it
+/// disconnects
+pub async fn monitor(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.session.monitor.is_none() {
+        ctx.session.monitor = Some(ctx.server.monitors.attach());
+    }
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// SLOWLOG GET [count], LEN and RESET. GET replies with the 10 most recent entries unless
+/// told otherwise, all of them for -1
+pub async fn slowlog(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(sub_cmd) = ctx.arg_keyword(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let res = match (sub_cmd.as_slice(), ctx.args.get(1)) {
+        (b"GET", None) => RedisValue::Array(
+            ctx.server
+                .slowlog
+                .entries(10)
+                .iter()
+                .map(|entry| entry.to_value())
+                .collect(),
+        ),
+        (b"GET", Some(_)) => match ctx.arg_integer(1) {
+            Some(count) if count >= -1 => {
+                let count = usize::try_from(count).unwrap_or(usize::MAX);
+                RedisValue::Array(
+                    ctx.server
+                        .slowlog
+                        .entries(count)
+                        .iter()
+                        .map(|entry| entry.to_value())
+                        .collect(),
+                )
+            }
+            _ => RedisValue::SimpleError(Bytes::from_static(
+                b"ERR count should be greater than or equal to -1",
+            )),
+        },
+        (b"LEN", None) => RedisValue::Integer(ctx.server.slowlog.len() as i64),
+        (b"RESET", None) => {
+            ctx.server.slowlog.reset();
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        (b"LEN" | b"RESET", Some(_)) => RedisValue::SimpleError(Bytes::from(format!(
+            "ERR wrong number of arguments for 'slowlog|{}' command",
+            String::from_utf8_lossy(&sub_cmd).to_lowercase()
+        ))),
+        _ => RedisValue::SimpleError(Bytes::from(format!(
+            "ERR unknown subcommand '{}'",
+            String::from_utf8_lossy(&ctx.args[0])
+        ))),
+    };
+
+    Ok(res)
+}
+
+pub async fn client(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(sub_cmd) = ctx.arg_keyword(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'client' command",
+        )));
+    };
+    match sub_cmd.as_slice() {
+        b"UNBLOCK" => return client_unblock(ctx).await,
+        b"SETINFO" => return client_setinfo(ctx).await,
+        b"KILL" => return client_kill(ctx).await,
+        b"ID" => return Ok(RedisValue::Integer(ctx.session.id as i64)),
+        b"SETNAME" => {
+            let (Some(value), None) = (ctx.args.get(1), ctx.args.get(2)) else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR wrong number of arguments for 'client|setname' command",
+                )));
+            };
+            ctx.session.name = match client_name(value) {
+                Ok(name) => name,
+                Err(refusal) => return Ok(refusal),
+            };
+            ctx.server.clients.update(ctx.session.summary());
+            return Ok(RedisValue::SimpleString(Bytes::from_static(b"OK")));
+        }
+        b"GETNAME" => {
+            return Ok(match &ctx.session.name {
+                Some(name) => RedisValue::BulkString(Bytes::from(name.clone())),
+                None => RedisValue::NullBulkString,
+            })
+        }
+        b"INFO" => {
+            let now = ctx.server.clock.now();
+            let line = ctx.session.summary().describe(now);
+            return Ok(RedisValue::BulkString(Bytes::from(line + "\n")));
+        }
+        b"LIST" => {
+            let now = ctx.server.clock.now();
+            let lines = ctx
+                .server
+                .clients
+                .list()
+                .iter()
+                .map(|client| client.describe(now) + "\n")
+                .collect::<String>();
+            return Ok(RedisValue::BulkString(Bytes::from(lines)));
+        }
+        _ => {}
+    }
+
+    let flag = match sub_cmd.as_slice() {
+        b"NO-EVICT" => &mut ctx.session.no_evict,
+        b"NO-TOUCH" => &mut ctx.session.no_touch,
+        _ => {
+            return Ok(RedisValue::SimpleError(Bytes::from(format!(
+                "ERR unknown subcommand '{}'",
+                String::from_utf8_lossy(&sub_cmd)
+            ))))
+        }
+    };
+    let res = match ctx.args.get(1) {
+        Some(on) if on.eq_ignore_ascii_case(b"ON") => {
+            *flag = true;
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        Some(off) if off.eq_ignore_ascii_case(b"OFF") => {
+            *flag = false;
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        _ => RedisValue::SimpleError(Bytes::from_static(b"ERR syntax error")),
+    };
+
+    Ok(res)
+}
+
+/// ACL SETUSER, GETUSER, LIST, WHOAMI and LOG. LOG [count|RESET] replies with the most
+/// recent denials, 10 unless told otherwise
+pub async fn acl(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(sub_cmd) = ctx.arg_keyword(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    match sub_cmd.as_slice() {
+        b"LOG" => {}
+        b"SETUSER" => return acl_setuser(ctx).await,
+        b"GETUSER" => {
+            let Some(username) = ctx.arg_str(1).filter(|_| ctx.args.len() == 2) else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR wrong number of arguments for 'acl|getuser' command",
+                )));
+            };
+            let res = match ctx.server.acl_users.get_user(&username) {
+                Some(user) => user.to_value(),
+                None => RedisValue::NullBulkString,
+            };
+            return Ok(res);
+        }
+        b"LIST" => {
+            let lines = ctx.server.acl_users.list();
+            return Ok(RedisValue::Array(
+                lines
+                    .into_iter()
+                    .map(|line| RedisValue::BulkString(Bytes::from(line)))
+                    .collect(),
+            ));
+        }
+        b"WHOAMI" => {
+            let user = ctx.session.user().to_string();
+            return Ok(RedisValue::BulkString(Bytes::from(user)));
+        }
+        _ => {
+            return Ok(RedisValue::SimpleError(Bytes::from(format!(
+                "ERR unknown subcommand '{}'",
+                String::from_utf8_lossy(&sub_cmd)
+            ))))
+        }
+    }
+
+    let count = match (ctx.arg_keyword(1), ctx.args.get(2)) {
+        (None, None) => 10,
+        (Some(reset), None) if reset == b"RESET" => {
+            ctx.server.acl_log.reset();
+            return Ok(RedisValue::SimpleString(Bytes::from_static(b"OK")));
+        }
+        (Some(_), None) => match ctx.arg_integer(1).and_then(|n| usize::try_from(n).ok()) {
+            Some(count) => count,
+            None => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is out of range, must be positive",
+                )))
+            }
+        },
+        _ => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR syntax error",
+            )))
+        }
+    };
+    let now = ctx.server.clock.now();
+    let res = RedisValue::Array(
+        ctx.server
+            .acl_log
+            .entries(count)
+            .iter()
+            .map(|entry| entry.to_value(now))
+            .collect(),
+    );
+
+    Ok(res)
+}
+
+/// ACL SETUSER username [rule ...]: creates the user or applies the rules to it, e.g. "on",
+/// ">password" or "+@read". Nothing changes when one of the rules is wrong
+async fn acl_setuser(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(username) = ctx.arg_str(1) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'acl|setuser' command",
+        )));
+    };
+    let rules = (2..ctx.args.len())
+        .filter_map(|pos| ctx.arg_str(pos))
+        .collect::<Vec<_>>();
+    let res = match ctx.server.acl_users.set_user(&username, &rules) {
+        Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+        Err(e) => RedisValue::SimpleError(Bytes::from(e.to_string())),
+    };
+
+    Ok(res)
+}
+
+/// CLIENT SETINFO <LIB-NAME|LIB-VER> <value>: the client library behind the connection,
+/// as drivers announce it on connect, shown by CLIENT LIST and CLIENT INFO
+async fn client_setinfo(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(attr), Some(value), None) = (ctx.arg_keyword(1), ctx.args.get(2), ctx.args.get(3))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'client|setinfo' command",
+        )));
+    };
+    let (name, field) = match attr.as_slice() {
+        b"LIB-NAME" => ("lib-name", &mut ctx.session.lib_name),
+        b"LIB-VER" => ("lib-ver", &mut ctx.session.lib_ver),
+        _ => {
+            return Ok(RedisValue::SimpleError(Bytes::from(format!(
+                "ERR Unrecognized option '{}'",
+                String::from_utf8_lossy(&ctx.args[1])
+            ))))
+        }
+    };
+    // --- CLIENT LIST is space separated, one client per line
+    if !value.iter().all(|b| (b'!'..=b'~').contains(b)) {
+        return Ok(RedisValue::SimpleError(Bytes::from(format!(
+            "ERR {} cannot contain spaces, newlines or special characters.",
+            name
+        ))));
+    }
+
+    *field = (!value.is_empty()).then(|| String::from_utf8_lossy(value).into_owned());
+    ctx.server.clients.update(ctx.session.summary());
+    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
+
+    Ok(res)
+}
+
+/// Name CLIENT SETNAME and HELLO SETNAME give a connection, none when empty
+fn client_name(value: &[u8]) -> Result<Option<String>, RedisValue> {
+    // --- CLIENT LIST is space separated, one client per line
+    if !value.iter().all(|b| (b'!'..=b'~').contains(b)) {
+        return Err(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR Client names cannot contain spaces, newlines or special characters.",
+        )));
+    }
+    let res = (!value.is_empty()).then(|| String::from_utf8_lossy(value).into_owned());
+
+    Ok(res)
+}
+
+/// CLIENT KILL ip:port, or CLIENT KILL [ID id] [ADDR ip:port] [USER username] [SKIPME yes|no]:
+/// closes the connections matching, replying OK for the old form and how many otherwise.
+/// Clients blocked in a command are woken up with an -UNBLOCKED error first
+async fn client_kill(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let mut id = None;
+    let mut addr = None;
+    let mut user = None;
+    let mut skip_me = true;
+    let old_form = ctx.args.len() == 2;
+    if old_form {
+        addr = ctx.arg_str(1);
+    } else if ctx.args.len().is_multiple_of(2) {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR syntax error",
+        )));
+    }
+    for pos in (1..ctx.args.len()).step_by(2).filter(|_| !old_form) {
+        let value = &ctx.args[pos + 1];
+        match ctx.arg_keyword(pos).as_deref() {
+            Some(b"ID") => match ctx
+                .arg_integer(pos + 1)
+                .and_then(|id| u64::try_from(id).ok())
+            {
+                Some(value) => id = Some(value),
+                None => {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR client-id should be greater than 0",
+                    )))
+                }
+            },
+            Some(b"ADDR") => addr = ctx.arg_str(pos + 1),
+            Some(b"USER") => user = ctx.arg_str(pos + 1),
+            Some(b"SKIPME") if value.eq_ignore_ascii_case(b"YES") => skip_me = true,
+            Some(b"SKIPME") if value.eq_ignore_ascii_case(b"NO") => skip_me = false,
+            _ => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR syntax error",
+                )))
+            }
+        }
+    }
+
+    let own_id = ctx.session.id;
+    let killed = ctx.server.clients.kill(|client| {
+        id.is_none_or(|id| client.id == id)
+            && addr
+                .as_deref()
+                .is_none_or(|addr| client.addr.is_some_and(|a| a.to_string() == addr))
+            && user.as_deref().is_none_or(|user| client.user == user)
+            && !(skip_me && client.id == own_id)
+    });
+    for id in &killed {
+        ctx.server.blocked_clients.unblock(*id, true);
+    }
+    let res = match (old_form, killed.len()) {
+        (true, 0) => RedisValue::SimpleError(Bytes::from_static(b"ERR No such client")),
+        (true, _) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+        (false, count) => RedisValue::Integer(count as i64),
+    };
+
+    Ok(res)
+}
+
+/// CLIENT UNBLOCK <id> [TIMEOUT|ERROR]: ends the blocking command a client is stuck in,
+/// as if it timed out or with an -UNBLOCKED error
+async fn client_unblock(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(_), mode, None) = (ctx.args.get(1), ctx.args.get(2), ctx.args.get(3)) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'client|unblock' command",
+        )));
+    };
+    let Some(id) = ctx.arg_integer(1).and_then(|id| u64::try_from(id).ok()) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+    let error = match mode {
+        None => false,
+        Some(mode) if mode.eq_ignore_ascii_case(b"TIMEOUT") => false,
+        Some(mode) if mode.eq_ignore_ascii_case(b"ERROR") => true,
+        Some(_) => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR CLIENT UNBLOCK reason should be TIMEOUT or ERROR",
+            )))
+        }
+    };
+
+    let unblocked = ctx.server.blocked_clients.unblock(id, error);
+    let res = RedisValue::Integer(unblocked as i64);
+
+    Ok(res)
+}
+
+pub async fn save(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let res = match ctx.server.save().await {
+        Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+        Err(e) => {
+            log::error!("Failure saving the DB: {:#}", e);
+            RedisValue::SimpleError(Bytes::from_static(b"ERR"))
+        }
+    };
+
+    Ok(res)
+}
+
+pub async fn bgsave(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let res = match ctx.server.bgsave().await {
+        Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"Background saving started")),
+        Err(e) => RedisValue::SimpleError(Bytes::from(format!("ERR {}", e))),
+    };
+
+    Ok(res)
+}
+
+/// LASTSAVE: unix time in seconds of the last successful save, the server start if none
+pub async fn lastsave(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let last_save = ctx.server.save_state.last_save.load(Ordering::Relaxed);
+    let res = RedisValue::Integer((last_save / 1000) as i64);
+
+    Ok(res)
+}
+
+/// SHUTDOWN [NOSAVE|SAVE] [NOW] [FORCE]: see `RedisServer::shutdown`. The connection
+/// gets its reply before it is closed with the others
+pub async fn shutdown(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    // --- EXEC holds the lock the shutdown waits on
+    if ctx.session.in_exec {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR Command not allowed inside a transaction",
+        )));
+    }
+    let mut flags = ShutdownFlags::default();
+    for arg in ctx.args.iter() {
+        let flag = arg.to_ascii_uppercase();
+        match flag.as_slice() {
+            b"SAVE" => flags.save = Some(true),
+            b"NOSAVE" => flags.save = Some(false),
+            b"FORCE" => flags.force = true,
+            b"NOW" => flags.now = true,
+            _ => {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR syntax error",
+                )))
+            }
+        }
+    }
+
+    // --- a script past `busy-reply-threshold` holds the lock the shutdown waits on, only
+    // NOSAVE may stop it
+    let shutdown = tokio::select! {
+        biased;
+        shutdown = ctx.server.shutdown(flags) => shutdown,
+        () = ctx.server.running_script.overrun() => match flags.save {
+            Some(false) => {
+                ctx.server.running_script.force_kill();
+                ctx.server.shutdown(flags).await
+            }
+            _ => return Ok(busy_script()),
+        },
+    };
+    let res = match shutdown {
+        Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+        Err(e) => {
+            log::error!("{:#}", e);
+            RedisValue::SimpleError(Bytes::from_static(
+                b"ERR Errors trying to SHUTDOWN. Check logs.",
+            ))
+        }
+    };
+
+    Ok(res)
+}
 
-use super::{
-    handler::{RedisConnectionHandler, RedisValue},
-    server::RedisServer,
-};
+/// Sections INFO reports, in the order it reports them
+const INFO_SECTIONS: &[&str] = &[
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "replication",
+    "sentinel",
+    "cluster",
+    "keyspace",
+];
 
-pub fn now() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64
+/// INFO [section ...]: the sections asked for, the default ones without any. "all" and
+/// "everything" are the default sections too, there are no others
+pub async fn info(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let mut wanted = (0..ctx.args.len())
+        .filter_map(|pos| ctx.arg_str(pos))
+        .map(|section| section.to_lowercase())
+        .collect::<Vec<_>>();
+    if wanted.is_empty() {
+        wanted.push("default".to_string());
+    }
+    // --- sentinels have no dataset to report on
+    let defaults: &[&str] = match ctx.server.config.sentinel {
+        true => &["server", "clients", "stats", "sentinel"],
+        false => &[
+            "server",
+            "clients",
+            "memory",
+            "persistence",
+            "stats",
+            "replication",
+            "cluster",
+            "keyspace",
+        ],
+    };
+    let selected = |section: &str| {
+        wanted.iter().any(|name| match name.as_str() {
+            "default" | "all" | "everything" => defaults.contains(&section),
+            name => name == section,
+        })
+    };
+
+    let mut sections = vec![];
+    for &section in INFO_SECTIONS.iter().filter(|section| selected(section)) {
+        let fields = match section {
+            "server" => info_server(ctx.server),
+            "clients" => info_clients(ctx.server),
+            "memory" => info_memory(ctx.server),
+            "persistence" => info_persistence(ctx.server),
+            "stats" => info_stats(ctx.server),
+            "replication" => info_replication(ctx.server),
+            "sentinel" if ctx.server.config.sentinel => info_sentinel(ctx.server),
+            "cluster" => vec![format_info(
+                "cluster_enabled",
+                &(ctx.server.cluster.is_some() as u8),
+            )],
+            "keyspace" => info_keyspace(ctx.server).await,
+            _ => continue,
+        };
+        let title = section[..1].to_uppercase() + &section[1..];
+        let fields = fields
+            .iter()
+            .map(|field| format!("{}\r\n", field))
+            .collect::<String>();
+        sections.push(format!("# {}\r\n{}", title, fields));
+    }
+
+    let res = RedisValue::BulkString(Bytes::from(sections.join("\r\n")));
+
+    Ok(res)
 }
 
-pub struct CommandContext<'a> {
-    pub args: &'a Vec<RedisValue>,
-    pub server: &'a RedisServer,
-    pub handler: &'a mut RedisConnectionHandler,
+fn info_server(server: &RedisServer) -> Vec<String> {
+    let mode = match (server.config.sentinel, server.cluster.is_some()) {
+        (true, _) => "sentinel",
+        (false, true) => "cluster",
+        (false, false) => "standalone",
+    };
+    let now = server.clock.now();
+    let uptime = now.saturating_sub(server.started_at) / 1000;
+    let port = server.local_addr().map_or(0, |addr| addr.port());
+
+    vec![
+        format_info("redis_version", &env!("CARGO_PKG_VERSION")),
+        format_info("redis_mode", &mode),
+        format_info("os", &std::env::consts::OS),
+        format_info("arch_bits", &(usize::BITS)),
+        format_info("process_id", &std::process::id()),
+        format_info("tcp_port", &port),
+        format_info("server_time_usec", &(now * 1000)),
+        format_info("uptime_in_seconds", &uptime),
+        format_info("uptime_in_days", &(uptime / (24 * 60 * 60))),
+        format_info("hz", &server.config.hz),
+    ]
 }
 
-impl RedisValue {
-    pub fn get_cmd_and_args(self) -> (Bytes, Vec<RedisValue>) {
-        let request = match self {
-            RedisValue::Array(arr) => arr,
-            _ => panic!("Incoming array should be an array"),
+fn info_clients(server: &RedisServer) -> Vec<String> {
+    let clients = server.clients.list();
+    let pubsub_clients = clients
+        .iter()
+        .filter(|client| client.channels + client.patterns > 0)
+        .count();
+
+    vec![
+        format_info("connected_clients", &clients.len()),
+        format_info("blocked_clients", &server.blocked_clients.len()),
+        format_info("pubsub_clients", &pubsub_clients),
+    ]
+}
+
+fn info_sentinel(server: &RedisServer) -> Vec<String> {
+    let mut res = vec![format_info(
+        "sentinel_masters",
+        &server.supervisor.iter().count(),
+    )];
+    for (i, supervisor) in server.supervisor.iter().enumerate() {
+        let master = supervisor.master.lock().unwrap();
+        let status = match master.odown {
+            true => "odown",
+            false => "ok",
         };
+        res.push(format!(
+            "master{}:name={},status={},address={}:{},slaves={},sentinels={}",
+            i,
+            master.name,
+            status,
+            master.addr.0,
+            master.addr.1,
+            master.replicas.len(),
+            master.num_other_sentinels + 1
+        ));
+    }
 
-        let cmd = request.first().unwrap().clone().unpack_bulk_str().unwrap();
-        let args = request.into_iter().skip(1).collect();
+    res
+}
 
-        (cmd, args)
+fn info_memory(server: &RedisServer) -> Vec<String> {
+    let used = server.memory.used();
+    let mut res = vec![
+        format_info("used_memory", &used),
+        format_info("used_memory_peak", &server.memory.peak()),
+        format_info("used_memory_dataset", &server.memory.dataset()),
+    ];
+    if let Some(rss) = alloc::rss() {
+        res.push(format_info("used_memory_rss", &rss));
+        res.push(format_info(
+            "mem_fragmentation_ratio",
+            &format_ratio(rss, used),
+        ));
+        res.push(format_info(
+            "mem_fragmentation_bytes",
+            &(rss as i64 - used as i64),
+        ));
+    }
+    if let Some(allocator) = alloc::allocator_stats() {
+        res.push(format_info("allocator_allocated", &allocator.allocated));
+        res.push(format_info("allocator_active", &allocator.active));
+        res.push(format_info("allocator_resident", &allocator.resident));
+        res.push(format_info(
+            "allocator_frag_ratio",
+            &format_ratio(allocator.active, allocator.allocated),
+        ));
     }
+    let live = server.config.live.read().unwrap();
+    res.extend([
+        format_info("mem_allocator", &alloc::ALLOCATOR_NAME),
+        format_info("maxmemory", &live.maxmemory),
+        format_info("maxmemory_policy", &live.maxmemory_policy.as_str()),
+    ]);
 
-    fn unpack_bulk_str(&self) -> Result<Bytes> {
-        match self {
-            RedisValue::BulkString(b) => Ok(b.clone()),
-            _ => bail!("Should be a bulk string"),
+    res
+}
+
+fn info_persistence(server: &RedisServer) -> Vec<String> {
+    let save_state = &server.save_state;
+    let bgsave_status = match save_state.last_bgsave_ok.load(Ordering::Relaxed) {
+        true => "ok",
+        false => "err",
+    };
+
+    // --- the append-only file is only started at load, from an RDB preamble, and never
+    // --- rewritten, so no rewrite is ever in progress
+    vec![
+        format_info("loading", &u8::from(server.is_loading())),
+        format_info(
+            "rdb_changes_since_last_save",
+            &save_state.dirty.load(Ordering::Relaxed),
+        ),
+        format_info(
+            "rdb_bgsave_in_progress",
+            &u8::from(server.bgsave_in_progress.load(Ordering::Relaxed)),
+        ),
+        format_info(
+            "rdb_last_save_time",
+            &(save_state.last_save.load(Ordering::Relaxed) / 1000),
+        ),
+        format_info("rdb_last_bgsave_status", &bgsave_status),
+        format_info("aof_enabled", &(server.aof.is_some() as u8)),
+        format_info("aof_rewrite_in_progress", &0),
+    ]
+}
+
+fn info_stats(server: &RedisServer) -> Vec<String> {
+    vec![
+        format_info(
+            "total_connections_received",
+            &server.stats.total_connections_received(),
+        ),
+        format_info(
+            "total_commands_processed",
+            &server.stats.total_commands_processed(),
+        ),
+        format_info(
+            "instantaneous_ops_per_sec",
+            &server.stats.instantaneous_ops_per_sec(),
+        ),
+        format_info(
+            "total_net_input_bytes",
+            &server.stats.total_net_input_bytes(),
+        ),
+        format_info(
+            "total_net_output_bytes",
+            &server.stats.total_net_output_bytes(),
+        ),
+        format_info("rejected_connections", &server.stats.rejected_connections()),
+        format_info("expired_keys", &server.stats.expired_keys()),
+        format_info("evicted_keys", &server.stats.evicted_keys()),
+        format_info("keyspace_hits", &server.stats.keyspace_hits()),
+        format_info("keyspace_misses", &server.stats.keyspace_misses()),
+        format_info(
+            "client_output_buffer_limit_disconnections",
+            &server.stats.client_output_buffer_limit_disconnections(),
+        ),
+        format_info(
+            "client_fairness_yields",
+            &server.stats.client_fairness_yields(),
+        ),
+    ]
+}
+
+/// A line per database, those empty left out like Redis leaves them out
+async fn info_keyspace(server: &RedisServer) -> Vec<String> {
+    let mut lines = vec![];
+    for db in &server.databases {
+        let main_store = db.main_store.lock().await;
+        let expire_store = db.expire_store.lock().await;
+
+        let now = server.clock.now();
+        let ttls = expire_store
+            .iter()
+            .filter(|(key, timestamp)| **timestamp >= now && main_store.contains_key(*key))
+            .map(|(_, timestamp)| timestamp - now)
+            .collect::<Vec<_>>();
+        let expired = expire_store
+            .iter()
+            .filter(|(key, timestamp)| **timestamp < now && main_store.contains_key(*key))
+            .count();
+        let keys = main_store.len() - expired;
+        if keys == 0 {
+            continue;
         }
+        let avg_ttl = match ttls.len() {
+            0 => 0,
+            // --- summed wider, a few TTLs far in the future overflow u64
+            count => (ttls.iter().map(|ttl| *ttl as u128).sum::<u128>() / count as u128) as u64,
+        };
+
+        lines.push(format!(
+            "db{}:keys={},expires={},avg_ttl={}",
+            db.index,
+            keys,
+            ttls.len(),
+            avg_ttl
+        ));
     }
+
+    lines
 }
 
-fn get_argument(pos: usize, args: &Vec<RedisValue>) -> &RedisValue {
-    args.get(pos).expect("No key specified for SET command")
+fn info_replication(server: &RedisServer) -> Vec<String> {
+    let server_context = server.server_context.read().unwrap();
+    let (role, master_replid, offset, master_replid2, second_offset) = match &*server_context {
+        ServerContext::Master(master) => (
+            "master",
+            &master.master_replid,
+            master.repl_offset(),
+            &master.master_replid2,
+            master.second_repl_offset,
+        ),
+        // --- a replica's stream offset mirrors what it has applied from its master
+        ServerContext::Replica(replica) => (
+            "slave",
+            &replica.master_replid,
+            replica.processed_offset(),
+            &replica.master_replid2,
+            replica.second_repl_offset,
+        ),
+    };
+
+    let mut res = vec![format_info("role", &role)];
+    if let ServerContext::Replica(replica) = &*server_context {
+        let link_status = match replica.link_up.load(Ordering::Relaxed) {
+            true => "up",
+            false => "down",
+        };
+        res.extend([
+            format_info("master_host", &replica.master_host),
+            format_info("master_port", &replica.master_port),
+            format_info("master_link_status", &link_status),
+        ]);
+    }
+    let replicas = server.replicas.list();
+    res.push(format_info("connected_slaves", &replicas.len()));
+    for (i, replica) in replicas.iter().enumerate() {
+        res.push(format_info(
+            &format!("slave{}", i),
+            &format!(
+                "ip={},port={},state=online,offset={}",
+                replica.ip, replica.port, replica.offset
+            ),
+        ));
+    }
+    res.extend([
+        format_info("master_replid", master_replid),
+        format_info("master_repl_offset", &offset),
+    ]);
+    if !server_context.is_master() {
+        res.push(format_info("slave_repl_offset", &offset));
+    }
+    res.extend([
+        format_info("master_replid2", &master_replid2.as_deref().unwrap_or("")),
+        format_info(
+            "second_repl_offset",
+            &second_offset.map_or(-1, |m| m as i64),
+        ),
+    ]);
+
+    res
 }
 
-pub async fn ping(ctx: &mut CommandContext<'_>) -> Result<usize> {
-    let res = RedisValue::SimpleString(Bytes::from_static(b"PONG"));
-    let bytes = ctx.handler.write(res).await?;
+/// ROLE: `master` with its offset and replicas, or `slave` with where its master is,
+/// the state of the link and the offset processed so far
+pub async fn role(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.server.config.sentinel {
+        let names = ctx
+            .server
+            .supervisor
+            .iter()
+            .map(|supervisor| supervisor.master.lock().unwrap().name.clone())
+            .map(|name| RedisValue::BulkString(Bytes::from(name)))
+            .collect();
+        return Ok(RedisValue::Array(vec![
+            RedisValue::BulkString(Bytes::from_static(b"sentinel")),
+            RedisValue::Array(names),
+        ]));
+    }
 
-    Ok(bytes)
+    let server_context = ctx.server.server_context.read().unwrap().clone();
+
+    let res = match server_context {
+        ServerContext::Master(master) => RedisValue::Array(vec![
+            RedisValue::BulkString(Bytes::from_static(b"master")),
+            RedisValue::Integer(master.repl_offset() as i64),
+            RedisValue::Array(
+                ctx.server
+                    .replicas
+                    .list()
+                    .into_iter()
+                    .map(|replica| {
+                        RedisValue::Array(vec![
+                            RedisValue::BulkString(Bytes::from(replica.ip)),
+                            RedisValue::BulkString(Bytes::from(replica.port.to_string())),
+                            RedisValue::BulkString(Bytes::from(replica.offset.to_string())),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ]),
+        ServerContext::Replica(replica) => {
+            let state = match replica.link_up.load(Ordering::Relaxed) {
+                true => "connected",
+                false => "connect",
+            };
+            RedisValue::Array(vec![
+                RedisValue::BulkString(Bytes::from_static(b"slave")),
+                RedisValue::BulkString(Bytes::from(replica.master_host.clone())),
+                RedisValue::Integer(replica.master_port as i64),
+                RedisValue::BulkString(Bytes::from_static(state.as_bytes())),
+                RedisValue::Integer(replica.processed_offset() as i64),
+            ])
+        }
+    };
+
+    Ok(res)
 }
 
-pub async fn echo(ctx: &mut CommandContext<'_>) -> Result<usize> {
-    let res = ctx.args.first().unwrap().clone();
-    let bytes = ctx.handler.write(res).await?;
+/// CLUSTER INFO | MYID | SLOTS | SHARDS | KEYSLOT key: this server as the single node of
+/// a cluster owning every hash slot, enough for cluster clients to route to it
+pub async fn cluster(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(node) = ctx.server.cluster.as_ref() else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR This instance has cluster support disabled",
+        )));
+    };
+    let Some(sub_cmd) = ctx.arg_keyword(0) else {
+        unreachable!("Arity is checked before dispatch");
+    };
+    let bulk = |s: &str| RedisValue::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    // --- where clients reach this node, the address it listens on
+    let addr = ctx.server.local_addr();
+    let ip = match addr.map(|addr| addr.ip()) {
+        Some(ip) if !ip.is_unspecified() => ip.to_string(),
+        _ => "127.0.0.1".to_string(),
+    };
+    let port = addr.map_or(0, |addr| addr.port() as i64);
+    let slots = || {
+        vec![
+            RedisValue::Integer(0),
+            RedisValue::Integer(cluster::SLOTS as i64 - 1),
+        ]
+    };
 
-    Ok(bytes)
+    let res = match sub_cmd.as_slice() {
+        b"INFO" => {
+            let slots = cluster::SLOTS.to_string();
+            let fields = [
+                ("cluster_state", "ok"),
+                ("cluster_slots_assigned", &slots),
+                ("cluster_slots_ok", &slots),
+                ("cluster_slots_pfail", "0"),
+                ("cluster_slots_fail", "0"),
+                ("cluster_known_nodes", "1"),
+                ("cluster_size", "1"),
+                ("cluster_current_epoch", "1"),
+                ("cluster_my_epoch", "1"),
+                ("cluster_stats_messages_sent", "0"),
+                ("cluster_stats_messages_received", "0"),
+                ("total_cluster_links_buffer_limit_exceeded", "0"),
+            ];
+            let info = fields
+                .iter()
+                .map(|(key, value)| format!("{}\r\n", format_info(key, value)))
+                .collect::<String>();
+            RedisValue::BulkString(Bytes::from(info))
+        }
+        b"MYID" => bulk(&node.id),
+        b"SLOTS" => {
+            let mut range = slots();
+            range.push(RedisValue::Array(vec![
+                bulk(&ip),
+                RedisValue::Integer(port),
+                bulk(&node.id),
+                RedisValue::Map(vec![]),
+            ]));
+            RedisValue::Array(vec![RedisValue::Array(range)])
+        }
+        b"SHARDS" => {
+            let offset = match &*ctx.server.server_context.read().unwrap() {
+                ServerContext::Master(master) => master.repl_offset(),
+                ServerContext::Replica(replica) => replica.processed_offset(),
+            };
+            let node = RedisValue::Map(vec![
+                (bulk("id"), bulk(&node.id)),
+                (bulk("port"), RedisValue::Integer(port)),
+                (bulk("ip"), bulk(&ip)),
+                (bulk("endpoint"), bulk(&ip)),
+                (bulk("role"), bulk("master")),
+                (
+                    bulk("replication-offset"),
+                    RedisValue::Integer(offset as i64),
+                ),
+                (bulk("health"), bulk("online")),
+            ]);
+            RedisValue::Array(vec![RedisValue::Map(vec![
+                (bulk("slots"), RedisValue::Array(slots())),
+                (bulk("nodes"), RedisValue::Array(vec![node])),
+            ])])
+        }
+        b"KEYSLOT" => match (ctx.args.get(1), ctx.args.get(2)) {
+            (Some(key), None) => RedisValue::Integer(cluster::key_slot(key) as i64),
+            _ => RedisValue::SimpleError(Bytes::from_static(
+                b"ERR wrong number of arguments for 'cluster|keyslot' command",
+            )),
+        },
+        _ => RedisValue::SimpleError(Bytes::from(format!(
+            "ERR unknown subcommand '{}'",
+            String::from_utf8_lossy(&sub_cmd)
+        ))),
+    };
+
+    Ok(res)
+}
+
+/// COMMAND [COUNT | INFO [name ...] | DOCS [name ...]]: the built-in commands as clients
+/// such as redis-cli look them up on connect, under the names `rename-command` gave them
+pub async fn command(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let server = ctx.server;
+    // --- the commands asked for by name, `None` for unknown ones, or every one of them
+    let named = |names: &[Bytes]| match names.is_empty() {
+        true => available_commands(server).into_iter().map(Some).collect(),
+        false => names
+            .iter()
+            .map(|name| {
+                let name = String::from_utf8_lossy(name).to_uppercase();
+                let spec = server
+                    .config
+                    .command_renames
+                    .resolve(&name)
+                    .filter(|cmd| available(server, cmd))
+                    .and_then(command_spec)?;
+                Some((name, spec))
+            })
+            .collect::<Vec<_>>(),
+    };
+
+    let res = match ctx.arg_keyword(0).as_deref() {
+        None => RedisValue::Array(
+            available_commands(server)
+                .iter()
+                .map(|(name, spec)| command_info(name, spec))
+                .collect(),
+        ),
+        Some(b"COUNT") if ctx.args.len() == 1 => {
+            RedisValue::Integer(available_commands(server).len() as i64)
+        }
+        Some(b"COUNT") => RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'command|count' command",
+        )),
+        Some(b"INFO") => RedisValue::Array(
+            named(&ctx.args[1..])
+                .into_iter()
+                .map(|command| match command {
+                    Some((name, spec)) => command_info(&name, spec),
+                    None => RedisValue::NullBulkString,
+                })
+                .collect(),
+        ),
+        // --- no summaries nor arguments to hand out, the group is what there is
+        Some(b"DOCS") => RedisValue::Map(
+            named(&ctx.args[1..])
+                .into_iter()
+                .flatten()
+                .map(|(name, spec)| {
+                    let docs = RedisValue::Map(vec![(
+                        RedisValue::BulkString(Bytes::from_static(b"group")),
+                        RedisValue::BulkString(Bytes::from_static(
+                            command_group(spec.name).as_bytes(),
+                        )),
+                    )]);
+                    (
+                        RedisValue::BulkString(Bytes::from(name.to_lowercase())),
+                        docs,
+                    )
+                })
+                .collect(),
+        ),
+        Some(sub_cmd) => RedisValue::SimpleError(Bytes::from(format!(
+            "ERR unknown subcommand '{}'",
+            String::from_utf8_lossy(sub_cmd)
+        ))),
+    };
+
+    Ok(res)
 }
 
-pub async fn set(ctx: &mut CommandContext<'_>) -> Result<usize> {
-    let key = get_argument(0, ctx.args).clone();
-    let value = get_argument(1, ctx.args).clone();
+/// Whether clients of this server can run a built-in command at all, by its original name
+fn available(server: &RedisServer, cmd: &str) -> bool {
+    (!server.config.sentinel || SENTINEL_MODE_COMMANDS.contains(&cmd))
+        && (server.config.extensions || !EXTENSION_COMMANDS.contains(&cmd))
+}
 
-    let mut main_store = ctx.server.main_store.lock().await;
-    let mut expire_store = ctx.server.expire_store.lock().await;
+/// The built-in commands clients can run, by the name they go by, sorted
+fn available_commands(server: &RedisServer) -> Vec<(String, &'static CommandSpec)> {
+    let mut res = COMMANDS
+        .values()
+        .filter(|spec| available(server, spec.name))
+        .filter_map(|spec| {
+            let name = server.config.command_renames.current_name(spec.name)?;
+            Some((name.to_string(), *spec))
+        })
+        .collect::<Vec<_>>();
+    res.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-    if let Some(cmd_arg) = ctx.args.get(2) {
-        let cmd_as_str = str::from_utf8(&cmd_arg.clone().unpack_bulk_str().unwrap())
-            .unwrap()
-            .to_uppercase();
-        let timeout = match cmd_as_str.as_str() {
-            "PX" => {
-                let timeout_value_raw = get_argument(3, ctx.args);
-                let timeout_value: u64 =
-                    str::from_utf8(&timeout_value_raw.unpack_bulk_str().unwrap())
-                        .unwrap()
-                        .parse()
-                        .unwrap();
-                SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64
-                    + timeout_value
-            }
-            _ => panic!("Invalid command argument for SET: '{}'", cmd_as_str),
-        };
-        expire_store.insert(key.clone(), timeout);
+    res
+}
+
+/// A command as COMMAND INFO describes it: name, arity, flags, first key, last key, key
+/// step, ACL categories, then tips, key specifications and subcommands, left empty
+fn command_info(name: &str, spec: &CommandSpec) -> RedisValue {
+    let status = |s: &str| RedisValue::SimpleString(Bytes::from(s.to_string()));
+    let mut flags = vec![];
+    match spec.write {
+        true => flags.push("write"),
+        false if spec.first_key > 0 => flags.push("readonly"),
+        false => {}
+    }
+    let listed: [(&str, &[&str]); 5] = [
+        ("denyoom", DENYOOM_COMMANDS),
+        ("loading", LOADING_OK_COMMANDS),
+        ("stale", STALE_OK_COMMANDS),
+        ("no_auth", NO_AUTH_COMMANDS),
+        ("movablekeys", MOVABLE_KEYS_COMMANDS),
+    ];
+    flags.extend(
+        listed
+            .iter()
+            .filter(|(_, commands)| commands.contains(&spec.name))
+            .map(|(flag, _)| *flag),
+    );
+    if AclCategory::Admin.contains(spec.name) {
+        flags.push("admin");
+    }
+    if AclCategory::Pubsub.contains(spec.name) {
+        flags.push("pubsub");
     }
-    main_store.insert(key, value);
+    if NO_SCRIPT_COMMANDS.contains(&spec.name) || spec.unlocked {
+        flags.push("noscript");
+    }
+    let categories = [
+        AclCategory::Read,
+        AclCategory::Write,
+        AclCategory::Admin,
+        AclCategory::Pubsub,
+        AclCategory::Connection,
+        AclCategory::Transaction,
+    ]
+    .iter()
+    .filter(|category| category.contains(spec.name))
+    .map(|category| status(&format!("@{}", category.as_str())))
+    .collect();
 
-    let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
-    let bytes = ctx.handler.write(res).await?;
+    RedisValue::Array(vec![
+        RedisValue::BulkString(Bytes::from(name.to_lowercase())),
+        RedisValue::Integer(spec.arity()),
+        RedisValue::Array(flags.into_iter().map(status).collect()),
+        RedisValue::Integer(spec.first_key),
+        RedisValue::Integer(spec.last_key),
+        RedisValue::Integer(spec.key_step),
+        RedisValue::Array(categories),
+        RedisValue::Array(vec![]),
+        RedisValue::Array(vec![]),
+        RedisValue::Array(vec![]),
+    ])
+}
 
-    Ok(bytes)
+/// Group COMMAND DOCS files a command under, as Redis' documentation does
+fn command_group(cmd: &str) -> &'static str {
+    match cmd {
+        "SET" | "SETEX" | "PSETEX" | "SETNX" | "GET" | "GETEX" | "GETDEL" | "GETRANGE"
+        | "SUBSTR" | "MGET" | "MSET" | "MSETNX" | "APPEND" | "STRLEN" | "SETRANGE" | "INCR"
+        | "DECR" | "INCRBY" | "DECRBY" | "INCRBYFLOAT" | "DELIFEQ" => "string",
+        "SETBIT" | "GETBIT" | "BITCOUNT" | "BITPOS" | "BITOP" => "bitmap",
+        "PFADD" | "PFCOUNT" | "PFMERGE" => "hyperloglog",
+        "LPUSH" | "RPUSH" | "LPOP" | "RPOP" | "BLPOP" | "BRPOP" | "LRANGE" | "LLEN" | "LINSERT"
+        | "LSET" | "LREM" | "LTRIM" | "LMPOP" | "LPOS" => "list",
+        "HSET" | "HGET" | "HDEL" | "HGETALL" | "HLEN" | "HEXISTS" => "hash",
+        "SADD" | "SREM" | "SMEMBERS" | "SISMEMBER" | "SMISMEMBER" | "SRANDMEMBER" | "SPOP"
+        | "SINTER" | "SUNION" | "SDIFF" => "set",
+        "ZADD" | "ZREM" | "ZRANGE" | "ZRANGEBYSCORE" | "ZSCORE" | "ZRANK" => "sorted-set",
+        "XADD" | "XRANGE" | "XLEN" | "XREAD" => "stream",
+        "PING" | "ECHO" | "AUTH" | "HELLO" | "SELECT" | "CLIENT" | "QUIT" | "RESET" => "connection",
+        "MULTI" | "EXEC" | "DISCARD" | "WATCH" | "UNWATCH" => "transactions",
+        "EVAL" | "EVALSHA" | "SCRIPT" => "scripting",
+        "SUBSCRIBE" | "UNSUBSCRIBE" | "PSUBSCRIBE" | "PUNSUBSCRIBE" | "PUBLISH" => "pubsub",
+        "INFO" | "COMMAND" | "SWAPDB" | "FLUSHDB" | "FLUSHALL" | "DBSIZE" | "MONITOR"
+        | "SLOWLOG" | "REPLCONF" | "CONFIG" | "ACL" | "REPLICAOF" | "SLAVEOF" | "ROLE"
+        | "DEBUG" | "LOLWUT" | "MEMORY" | "SAVE" | "BGSAVE" | "LASTSAVE" | "SHUTDOWN" => "server",
+        "CLUSTER" | "RAFT" => "cluster",
+        "SENTINEL" => "sentinel",
+        // --- JSON., TS., BF. and FT., modules in Redis
+        _ if cmd.contains('.') => "module",
+        _ => "generic",
+    }
 }
 
-pub async fn get(ctx: &mut CommandContext<'_>) -> Result<usize> {
-    let key = get_argument(0, ctx.args);
+/// SENTINEL subcommands, answered from the failover supervisor's view of its master
+pub async fn sentinel(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(sub_cmd) = ctx.arg_keyword(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'sentinel' command",
+        )));
+    };
+    let supervisor = ctx.server.supervisor.as_ref();
+    // --- the supervised master, when the name argument matches it
+    let by_name = |pos: usize| -> Option<MonitoredMaster> {
+        let name = ctx.args.get(pos)?;
+
+        supervisor
+            .map(|supervisor| supervisor.master.lock().unwrap().clone())
+            .filter(|master| master.name.as_bytes() == &name[..])
+    };
+    let no_such_master =
+        || RedisValue::SimpleError(Bytes::from_static(b"ERR No such master with that name"));
+
+    let res = match sub_cmd.as_slice() {
+        b"MYID" => match supervisor {
+            Some(supervisor) => RedisValue::BulkString(Bytes::from(supervisor.run_id.clone())),
+            None => RedisValue::SimpleError(Bytes::from_static(b"ERR No master is monitored")),
+        },
+        b"MASTERS" => RedisValue::Array(
+            supervisor
+                .iter()
+                .map(|supervisor| sentinel_master_fields(&supervisor.master.lock().unwrap()))
+                .collect(),
+        ),
+        b"MASTER" => match by_name(1) {
+            Some(master) => sentinel_master_fields(&master),
+            None => no_such_master(),
+        },
+        b"GET-MASTER-ADDR-BY-NAME" => match by_name(1) {
+            Some(master) => RedisValue::Array(vec![
+                RedisValue::BulkString(Bytes::from(master.addr.0)),
+                RedisValue::BulkString(Bytes::from(master.addr.1.to_string())),
+            ]),
+            None => RedisValue::NullBulkString,
+        },
+        b"REPLICAS" | b"SLAVES" => match by_name(1) {
+            Some(master) => RedisValue::Array(
+                master
+                    .replicas
+                    .iter()
+                    .map(|(host, port)| {
+                        sentinel_fields(&[
+                            ("name", format!("{}:{}", host, port)),
+                            ("ip", host.clone()),
+                            ("port", port.to_string()),
+                            ("flags", "slave".to_string()),
+                        ])
+                    })
+                    .collect(),
+            ),
+            None => no_such_master(),
+        },
+        b"IS-MASTER-DOWN-BY-ADDR" => return sentinel_is_master_down(ctx).await,
+        _ => RedisValue::SimpleError(Bytes::from(format!(
+            "ERR Unknown sentinel subcommand '{}'",
+            String::from_utf8_lossy(&sub_cmd)
+        ))),
+    };
 
-    let mut main_store = ctx.server.main_store.lock().await;
-    let mut expire_store = ctx.server.expire_store.lock().await;
+    Ok(res)
+}
 
-    let res = match main_store.get(&key) {
-        Some(val) => {
-            let timestamp = expire_store.get(key).unwrap_or(&u64::MAX);
+/// SENTINEL IS-MASTER-DOWN-BY-ADDR <ip> <port> <current-epoch> <runid>, a runid other
+/// than * also asks for our vote as failover leader in that epoch
+async fn sentinel_is_master_down(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(ip), Some(_), Some(_), Some(run_id)) = (
+        ctx.arg_str(1),
+        ctx.args.get(2),
+        ctx.args.get(3),
+        ctx.arg_str(4),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'sentinel is-master-down-by-addr' command",
+        )));
+    };
+    let (Some(port), Some(epoch)) = (
+        ctx.arg_integer(2).and_then(|port| u16::try_from(port).ok()),
+        ctx.arg_integer(3)
+            .and_then(|epoch| u64::try_from(epoch).ok()),
+    ) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+    let addr = (ip, port);
 
-            if *timestamp < now() {
-                main_store.remove(key);
-                expire_store.remove(key);
-                RedisValue::NullBulkString
+    let (down, leader, leader_epoch) = match &ctx.server.supervisor {
+        Some(supervisor) => {
+            let mut master = supervisor.master.lock().unwrap();
+            if master.addr != addr {
+                (false, None, 0)
+            } else if run_id == "*" {
+                (master.sdown, None, 0)
             } else {
-                val.clone()
+                let (leader, leader_epoch) = master.vote(&run_id, epoch);
+                (master.sdown, leader, leader_epoch)
             }
         }
-        None => RedisValue::NullBulkString,
+        None => (false, None, 0),
     };
-    let bytes = ctx.handler.write(res).await?;
 
-    Ok(bytes)
+    let res = RedisValue::Array(vec![
+        RedisValue::Integer(i64::from(down)),
+        RedisValue::BulkString(Bytes::from(leader.unwrap_or("*".to_string()))),
+        RedisValue::Integer(leader_epoch as i64),
+    ]);
+
+    Ok(res)
 }
 
-pub async fn keys(ctx: &mut CommandContext<'_>) -> Result<usize> {
-    let _pattern = str::from_utf8(&get_argument(0, ctx.args).unpack_bulk_str().unwrap()).unwrap();
-    let main_store_lock = ctx.server.main_store.lock().await;
-    let expire_store_lock = ctx.server.expire_store.lock().await;
+fn sentinel_master_fields(master: &MonitoredMaster) -> RedisValue {
+    sentinel_fields(&[
+        ("name", master.name.clone()),
+        ("ip", master.addr.0.clone()),
+        ("port", master.addr.1.to_string()),
+        ("flags", master.flags()),
+        ("num-slaves", master.replicas.len().to_string()),
+        (
+            "num-other-sentinels",
+            master.num_other_sentinels.to_string(),
+        ),
+        ("quorum", master.quorum.to_string()),
+        (
+            "down-after-milliseconds",
+            master.down_after.as_millis().to_string(),
+        ),
+        ("config-epoch", master.config_epoch.to_string()),
+    ])
+}
 
-    let mut res = vec![];
+/// Field/value pairs as the flat array Sentinel replies with
+fn sentinel_fields(fields: &[(&str, String)]) -> RedisValue {
+    RedisValue::Array(
+        fields
+            .iter()
+            .flat_map(|(name, value)| {
+                [
+                    RedisValue::BulkString(Bytes::from(name.to_string())),
+                    RedisValue::BulkString(Bytes::from(value.clone())),
+                ]
+            })
+            .collect(),
+    )
+}
 
-    for key in main_store_lock.keys() {
-        // --- if expired, skip it
-        let expire_key = expire_store_lock.get(key);
-        if expire_key.is_some_and(|&k| k < now()) {
-            continue;
+/// REPLICAOF NO ONE promotes a replica, REPLICAOF <host> <port> drops the current master
+/// if any and follows the given one from a background task
+pub async fn replicaof(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let (Some(host), Some(port), None) = (ctx.args.first(), ctx.args.get(1), ctx.args.get(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'replicaof' command",
+        )));
+    };
+    let is_no_one = host.eq_ignore_ascii_case(b"NO") && port.eq_ignore_ascii_case(b"ONE");
+
+    if is_no_one {
+        ctx.server.server_context.write().unwrap().promote();
+        return Ok(RedisValue::SimpleString(Bytes::from_static(b"OK")));
+    }
+
+    let host = String::from_utf8_lossy(host).into_owned();
+    let Some(port) = parse_integer(port).and_then(|port| u16::try_from(port).ok()) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR Invalid master port",
+        )));
+    };
+    let Some(server) = ctx.server.handle() else {
+        bail!("Server is shutting down");
+    };
+
+    let res = {
+        let server_context = ctx.server.server_context.read().unwrap();
+        match &*server_context {
+            ServerContext::Replica(replica)
+                if replica.master_host == host
+                    && replica.master_port == port
+                    && replica.link_up.load(Ordering::Relaxed) =>
+            {
+                RedisValue::SimpleString(Bytes::from_static(
+                    b"OK Already connected to specified master",
+                ))
+            }
+            server_context => {
+                if let ServerContext::Replica(replica) = server_context {
+                    replica.stop_link.notify_one();
+                }
+                tokio::spawn(switch_master(server, format!("{} {}", host, port)));
+                RedisValue::SimpleString(Bytes::from_static(b"OK"))
+            }
         }
+    };
 
-        res.push(key.clone());
+    Ok(res)
+}
+
+/// OBJECT ENCODING, REFCOUNT, IDLETIME and FREQ key: how a value is held and how
+/// recently and often it was accessed, null for a missing key
+pub async fn object(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.arg_keyword(0).as_deref() == Some(b"HELP") {
+        let lines = [
+            "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "ENCODING <key>",
+            "    Return the kind of internal representation used in order to store the value",
+            "    associated with a <key>.",
+            "FREQ <key>",
+            "    Return the access frequency index of the <key>. The returned integer is",
+            "    proportional to the logarithm of the recent access frequency of the key.",
+            "IDLETIME <key>",
+            "    Return the idle time of the <key>, that is the approximated number of",
+            "    seconds elapsed since the last access to the key.",
+            "REFCOUNT <key>",
+            "    Return the number of references of the value associated with the specified",
+            "    <key>.",
+        ];
+        let res = RedisValue::Array(
+            lines
+                .iter()
+                .map(|line| RedisValue::SimpleString(Bytes::from_static(line.as_bytes())))
+                .collect(),
+        );
+        return Ok(res);
     }
+    let (Some(sub_cmd), Some(key), None) = (ctx.arg_keyword(0), ctx.arg_value(1), ctx.args.get(2))
+    else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'object' command",
+        )));
+    };
+    let key = &key;
 
-    let res = RedisValue::Array(res);
-    let bytes = ctx.handler.write(res).await?;
+    // --- introspection never counts as an access
+    let limits = ctx.server.config.live.read().unwrap().encoding_limits;
+    let encoding = {
+        let mut main_store = ctx.db().main_store.lock().await;
+        let mut expire_store = ctx.db().expire_store.lock().await;
+        let now = ctx.server.clock.now();
+        get_live_value_mut(
+            ctx.server,
+            ctx.db(),
+            &mut main_store,
+            &mut expire_store,
+            key,
+            now,
+        )
+        .map(|value| value.encoding(&limits))
+    };
+    let Some(encoding) = encoding else {
+        return Ok(RedisValue::NullBulkString);
+    };
 
-    Ok(bytes)
+    let res = match sub_cmd.as_slice() {
+        b"ENCODING" => RedisValue::BulkString(Bytes::from_static(encoding.as_bytes())),
+        // --- values are never shared between keys
+        b"REFCOUNT" => RedisValue::Integer(1),
+        b"IDLETIME"
+            if ctx
+                .server
+                .config
+                .live
+                .read()
+                .unwrap()
+                .maxmemory_policy
+                .is_lfu() =>
+        {
+            RedisValue::SimpleError(Bytes::from_static(
+                b"ERR An LFU maxmemory policy is selected, idle time not tracked. Please note \
+                that when switching between policies at runtime LRU and LFU data will take \
+                some time to adjust.",
+            ))
+        }
+        b"IDLETIME" => {
+            let now = ctx.server.clock.now();
+            let access_store = ctx.db().access_store.lock().await;
+            let last_access = access_store
+                .get(key)
+                .map_or(now, |access| access.last_access);
+            RedisValue::Integer((now.saturating_sub(last_access) / 1000) as i64)
+        }
+        b"FREQ"
+            if !ctx
+                .server
+                .config
+                .live
+                .read()
+                .unwrap()
+                .maxmemory_policy
+                .is_lfu() =>
+        {
+            RedisValue::SimpleError(Bytes::from_static(
+                b"ERR An LFU maxmemory policy is not selected, access frequency not tracked. \
+                Please note that when switching between policies at runtime LRU and LFU data \
+                will take some time to adjust.",
+            ))
+        }
+        b"FREQ" => {
+            let now = ctx.server.clock.now();
+            let access_store = ctx.db().access_store.lock().await;
+            let frequency = access_store
+                .get(key)
+                .copied()
+                .unwrap_or_else(|| KeyAccess::new(now))
+                .frequency(now, ctx.server.config.live.read().unwrap().lfu_decay_time);
+            RedisValue::Integer(frequency as i64)
+        }
+        _ => RedisValue::SimpleError(Bytes::from(format!(
+            "ERR unknown subcommand '{}'",
+            String::from_utf8_lossy(&sub_cmd)
+        ))),
+    };
+
+    Ok(res)
 }
 
-pub async fn config(ctx: &mut CommandContext<'_>) -> Result<usize> {
-    let sub_cmd = str::from_utf8(&get_argument(0, ctx.args).unpack_bulk_str().unwrap())
-        .unwrap()
-        .to_uppercase();
+pub async fn memory(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(sub_cmd) = ctx.arg_keyword(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'memory' command",
+        )));
+    };
 
-    let res = match sub_cmd.as_str() {
-        "GET" => {
-            if ctx.server.config.is_none() {
-                RedisValue::SimpleError(Bytes::from_static(b"No config object exists"))
-            } else {
-                let mut resp: Vec<RedisValue> = Vec::new();
-                let config = ctx.server.config.as_ref().unwrap();
-
-                for arg in ctx.args.iter().skip(1) {
-                    let raw_key = arg.clone().unpack_bulk_str().unwrap();
-                    let key = String::from(str::from_utf8(&raw_key).unwrap());
-
-                    match key.as_str() {
-                        "dir" => resp.extend([
-                            RedisValue::BulkString(Bytes::from(key)),
-                            RedisValue::BulkString(Bytes::from(config.dir.clone())),
-                        ]),
-                        "dbfilename" => resp.extend([
-                            RedisValue::BulkString(Bytes::from(key)),
-                            RedisValue::BulkString(Bytes::from(config.dbfilename.clone())),
-                        ]),
-                        _ => continue,
-                    }
+    let res = match sub_cmd.as_slice() {
+        // --- MEMORY USAGE key [SAMPLES count], every element is counted whatever the samples
+        b"USAGE" => {
+            let Some(key) = ctx.arg_value(1) else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR wrong number of arguments for 'memory|usage' command",
+                )));
+            };
+            match (
+                ctx.arg_keyword(2).as_deref(),
+                ctx.arg_integer(3),
+                ctx.args.get(4),
+            ) {
+                (None, _, _) => {}
+                (Some(b"SAMPLES"), Some(samples), None) if samples >= 0 => {}
+                _ => {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR syntax error",
+                    )))
+                }
+            }
+            let mut main_store = ctx.db().main_store.lock().await;
+            let mut expire_store = ctx.db().expire_store.lock().await;
+            let now = ctx.server.clock.now();
+            match get_live_value_mut(
+                ctx.server,
+                ctx.db(),
+                &mut main_store,
+                &mut expire_store,
+                &key,
+                now,
+            ) {
+                Some(value) => RedisValue::Integer(entry_size(&key, value) as i64),
+                None => RedisValue::NullBulkString,
+            }
+        }
+        b"PURGE" => match alloc::purge() {
+            Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+            Err(e) => RedisValue::SimpleError(Bytes::from(format!("ERR {}", e))),
+        },
+        b"STATS" => {
+            let memory = &ctx.server.memory;
+            let mut stats = vec![
+                ("peak.allocated", memory.peak().to_string()),
+                ("total.allocated", memory.used().to_string()),
+                ("dataset.bytes", memory.dataset().to_string()),
+            ];
+            if let Some(allocator) = alloc::allocator_stats() {
+                stats.extend([
+                    ("allocator.allocated", allocator.allocated.to_string()),
+                    ("allocator.active", allocator.active.to_string()),
+                    ("allocator.resident", allocator.resident.to_string()),
+                    (
+                        "allocator.fragmentation.ratio",
+                        format_ratio(allocator.active, allocator.allocated),
+                    ),
+                ]);
+            }
+            if let Some(rss) = alloc::rss() {
+                stats.extend([
+                    ("rss", rss.to_string()),
+                    ("fragmentation", format_ratio(rss, memory.used())),
+                    (
+                        "fragmentation.bytes",
+                        (rss as i64 - memory.used() as i64).to_string(),
+                    ),
+                ]);
+            }
+            RedisValue::Array(
+                stats
+                    .into_iter()
+                    .flat_map(|(name, value)| {
+                        [
+                            RedisValue::BulkString(Bytes::from_static(name.as_bytes())),
+                            RedisValue::BulkString(Bytes::from(value)),
+                        ]
+                    })
+                    .collect(),
+            )
+        }
+        // --- MEMORY ANALYZE [COUNT n], then MEMORY BIGKEYS and MEMORY HOTKEYS once done
+        b"ANALYZE" => {
+            let count = match (ctx.arg_keyword(1).as_deref(), ctx.arg_integer(2)) {
+                (None, _) => DEFAULT_COUNT,
+                (Some(b"COUNT"), Some(count)) if count > 0 => count as usize,
+                _ => {
+                    return Ok(RedisValue::SimpleError(Bytes::from_static(
+                        b"ERR syntax error",
+                    )))
                 }
-                RedisValue::Array(resp)
+            };
+            match ctx.server.analyze_keys(ctx.db(), count).await {
+                Ok(()) => RedisValue::SimpleString(Bytes::from_static(b"Key analysis started")),
+                Err(e) => RedisValue::SimpleError(Bytes::from(format!("ERR {}", e))),
             }
         }
+        b"BIGKEYS" => match key_report(ctx.server) {
+            Ok(report) => RedisValue::Array(
+                report
+                    .biggest
+                    .iter()
+                    .flat_map(|(type_name, keys)| {
+                        keys.iter().map(|(key, size)| {
+                            RedisValue::Array(vec![
+                                RedisValue::BulkString(Bytes::from_static(type_name.as_bytes())),
+                                RedisValue::BulkString(key.clone()),
+                                RedisValue::Integer(*size as i64),
+                            ])
+                        })
+                    })
+                    .collect(),
+            ),
+            Err(e) => e,
+        },
+        b"HOTKEYS" => match key_report(ctx.server) {
+            Ok(report) => RedisValue::Array(
+                report
+                    .hottest
+                    .iter()
+                    .map(|(key, frequency)| {
+                        RedisValue::Array(vec![
+                            RedisValue::BulkString(key.clone()),
+                            RedisValue::Integer(*frequency as i64),
+                        ])
+                    })
+                    .collect(),
+            ),
+            Err(e) => e,
+        },
         _ => RedisValue::SimpleError(Bytes::from(format!(
-            "Invalid sub command for 'CONFIG': '{}'",
-            sub_cmd
+            "ERR unknown subcommand '{}'",
+            String::from_utf8_lossy(&sub_cmd)
         ))),
     };
-    let bytes = ctx.handler.write(res).await?;
 
-    Ok(bytes)
+    Ok(res)
+}
+
+/// Report of the last key analysis, or the error telling why there is none
+fn key_report(server: &RedisServer) -> Result<Arc<KeyReport>, RedisValue> {
+    if let Some(report) = server.key_analysis.last_report() {
+        return Ok(report);
+    }
+    let message: &[u8] = if server.key_analysis.in_progress() {
+        b"ERR key analysis in progress, try again later"
+    } else {
+        b"ERR no key analysis yet, run MEMORY ANALYZE first"
+    };
+
+    Err(RedisValue::SimpleError(Bytes::from_static(message)))
 }
 
-pub async fn info(ctx: &mut CommandContext<'_>) -> Result<usize> {
-    let info_data = match &ctx.server.server_context {
-        ServerContext::Master(master) => {
-            let role = format_info("role", &"master");
-            let repl_id = format_info("master_replid", &master.master_replid);
-            let repl_offset = format_info("master_repl_offset", &master.master_repl_offset);
-            vec![role, repl_id, repl_offset].join("\r\n")
+/// LOLWUT [VERSION version] [columns [squares per row [squares per column]]]: computer art
+/// and the version of the server. There is one drawing whatever the version asked for
+pub async fn lolwut(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let not_an_integer = || {
+        RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        ))
+    };
+    let mut pos = 0;
+    if ctx.arg_keyword(0).as_deref() == Some(b"VERSION") {
+        if ctx.arg_integer(1).is_none() {
+            return Ok(not_an_integer());
         }
-        ServerContext::Replica(replica) => {
-            let role = format_info("role", &"slave");
-            let master_replid = format_info("master_replid", &replica.master_replid);
-            let master_repl_offset = format_info("master_repl_offset", &replica.master_repl_offset);
-            let slave_repl_offset = format_info("slave_repl_offset", &replica.slave_repl_offset);
-            let master_replid2 = format_info(
-                "master_replid2",
-                &replica.master_replid2.as_ref().unwrap_or(&"".to_string()),
-            );
-            let second_repl_offset = format_info(
-                "second_repl_offset",
-                &replica.second_repl_offset.map_or(-1, |m| m as i32),
-            );
+        pos = 2;
+    }
+    let mut schotter = Schotter::default();
+    let sizes = [
+        (&mut schotter.cols, 1000),
+        (&mut schotter.squares_per_row, 200),
+        (&mut schotter.squares_per_col, 200),
+    ];
+    for (size, max) in sizes {
+        let Some(arg) = ctx.args.get(pos) else {
+            break;
+        };
+        let Some(n) = parse_integer(arg) else {
+            return Ok(not_an_integer());
+        };
+        *size = n.clamp(1, max) as usize;
+        pos += 1;
+    }
+    if pos < ctx.args.len() {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR syntax error",
+        )));
+    }
 
-            vec![
-                role,
-                master_replid,
-                master_repl_offset,
-                slave_repl_offset,
-                master_replid2,
-                second_repl_offset,
-            ]
-            .join("\r\n")
+    let res = RedisValue::BulkString(Bytes::from(format!(
+        "{}\nGeorg Nees - schotter, plotter on paper, 1968. Redis ver. {}\n",
+        schotter.render(),
+        env!("CARGO_PKG_VERSION")
+    )));
+
+    Ok(res)
+}
+
+/// DEBUG: introspection and knobs for tests, not meant for applications
+pub async fn debug(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    let Some(sub_cmd) = ctx.arg_keyword(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'debug' command",
+        )));
+    };
+
+    let res = match sub_cmd.as_slice() {
+        b"CHANGE-REPL-ID" => {
+            ctx.server.server_context.write().unwrap().change_replid();
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        b"DIGEST" => {
+            let dataset = ctx.server.snapshot().await;
+            let now = ctx.server.clock.now();
+            let digest = digest::dataset_digest(&dataset, now);
+            RedisValue::SimpleString(Bytes::from(digest::to_hex(&digest)))
+        }
+        b"DIGEST-VALUE" => {
+            let main_store = ctx.db().main_store.lock().await;
+            RedisValue::Array(
+                (1..ctx.args.len())
+                    .map(|pos| {
+                        let value = ctx.arg_value(pos).and_then(|key| main_store.get(&key));
+                        let digest = digest::value_digest(value);
+                        RedisValue::SimpleString(Bytes::from(digest::to_hex(&digest)))
+                    })
+                    .collect(),
+            )
+        }
+        b"OBJECT" => {
+            let Some(key) = ctx.arg_value(1) else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR wrong number of arguments for 'debug|object' command",
+                )));
+            };
+            let mut main_store = ctx.db().main_store.lock().await;
+            let mut expire_store = ctx.db().expire_store.lock().await;
+            let now = ctx.server.clock.now();
+            let Some(value) = get_live_value_mut(
+                ctx.server,
+                ctx.db(),
+                &mut main_store,
+                &mut expire_store,
+                &key,
+                now,
+            ) else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR no such key",
+                )));
+            };
+            let limits = ctx.server.config.live.read().unwrap().encoding_limits;
+            let encoding = value.encoding(&limits);
+            let serialized_len = rdb::serialized_len(value)?;
+            let last_access = ctx
+                .db()
+                .access_store
+                .lock()
+                .await
+                .get(&key)
+                .map_or(now, |access| access.last_access);
+            // --- the LRU clock of Redis, seconds on 24 bits
+            RedisValue::SimpleString(Bytes::from(format!(
+                "Value at:0x0 refcount:1 encoding:{} serializedlength:{} lru:{} \
+                 lru_seconds_idle:{}",
+                encoding,
+                serialized_len,
+                (last_access / 1000) & 0xff_ffff,
+                now.saturating_sub(last_access) / 1000
+            )))
+        }
+        b"SLEEP" => {
+            let Some(secs) = ctx
+                .arg_str(1)
+                .and_then(|secs| secs.parse::<f64>().ok())
+                .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is not a valid float",
+                )));
+            };
+            // --- only this client waits, the others keep being served
+            tokio::time::sleep(std::time::Duration::from_secs_f64(secs)).await;
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        b"SET-ACTIVE-EXPIRE" => {
+            let Some(enabled @ (0 | 1)) = ctx.arg_integer(1) else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is not an integer or out of range",
+                )));
+            };
+            ctx.server
+                .active_expire
+                .store(enabled == 1, Ordering::Relaxed);
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        // --- lists aren't split into nodes here, so there is no threshold to move
+        b"QUICKLIST-PACKED-THRESHOLD" => {
+            if ctx
+                .arg_str(1)
+                .and_then(|size| memory::parse_memory_size(&size).ok())
+                .is_none()
+            {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR argument must be a memory value",
+                )));
+            }
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
         }
+        // --- no JVM heap to map
+        b"JMAP" => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+        b"DUMP-JSON" => {
+            let dataset = ctx.server.snapshot().await;
+            match json::dump(&dataset) {
+                Ok(dump) => RedisValue::BulkString(Bytes::from(dump)),
+                Err(e) => RedisValue::SimpleError(Bytes::from(format!("ERR {}", e))),
+            }
+        }
+        b"LOAD-JSON" => {
+            let Some(dump) = ctx.arg_str(1) else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR wrong number of arguments for 'debug|load-json' command",
+                )));
+            };
+            match json::load(&dump, ctx.server.clock.now()) {
+                Ok(dataset) => match ctx.server.replace_dataset(dataset).await {
+                    Ok(_) => RedisValue::SimpleString(Bytes::from_static(b"OK")),
+                    Err(e) => RedisValue::SimpleError(Bytes::from(format!(
+                        "ERR Error loading the JSON dataset: {}",
+                        e
+                    ))),
+                },
+                Err(e) => RedisValue::SimpleError(Bytes::from(format!(
+                    "ERR Error loading the JSON dataset: {}",
+                    e
+                ))),
+            }
+        }
+        #[cfg(feature = "chaos")]
+        b"REPL-LATENCY" | b"REPL-ACK-DELAY" | b"REPL-CORRUPT" => {
+            let Some(amount) = ctx.arg_integer(1).and_then(|n| u64::try_from(n).ok()) else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is not an integer or out of range",
+                )));
+            };
+            let faults = &ctx.server.faults;
+            match sub_cmd.as_slice() {
+                b"REPL-LATENCY" => faults.set_link_latency(amount),
+                b"REPL-ACK-DELAY" => faults.set_ack_delay(amount),
+                _ => faults.corrupt_next(amount as usize),
+            }
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        #[cfg(feature = "chaos")]
+        b"REPL-FAULTS-RESET" => {
+            ctx.server.faults.reset();
+            RedisValue::SimpleString(Bytes::from_static(b"OK"))
+        }
+        #[cfg(feature = "chaos")]
+        b"DROP-MASTER-LINK" => match &*ctx.server.server_context.read().unwrap() {
+            ServerContext::Replica(replica) => {
+                replica.stop_link.notify_one();
+                RedisValue::SimpleString(Bytes::from_static(b"OK"))
+            }
+            ServerContext::Master(_) => {
+                RedisValue::SimpleError(Bytes::from_static(b"ERR Not a replica"))
+            }
+        },
+        _ => RedisValue::SimpleError(Bytes::from(format!(
+            "ERR unknown subcommand '{}'",
+            String::from_utf8_lossy(&sub_cmd)
+        ))),
     };
 
-    let res = RedisValue::BulkString(Bytes::from(info_data));
-    let bytes = ctx.handler.write(res).await?;
+    Ok(res)
+}
+
+/// RAFT AUTH | REQUESTVOTE | APPENDENTRIES | INSTALLSNAPSHOT | STATE: messages between
+/// the members of a raft cluster, and what this member knows about it. Members
+/// authenticate with the cluster's secret before sending votes, entries or snapshots
+#[cfg(feature = "raft")]
+pub async fn raft(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    use crate::repl::raft::{AppendRequest, SnapshotRequest, VoteRequest};
 
-    Ok(bytes)
+    let Some(raft) = ctx.server.raft.as_ref() else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR This instance has raft support disabled",
+        )));
+    };
+    let Some(sub_cmd) = ctx.arg_keyword(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'raft' command",
+        )));
+    };
+
+    if matches!(
+        sub_cmd.as_slice(),
+        b"REQUESTVOTE" | b"APPENDENTRIES" | b"INSTALLSNAPSHOT"
+    ) && !ctx.session.is_raft_peer
+    {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"NOAUTH Raft members must authenticate with RAFT AUTH first",
+        )));
+    }
+
+    let res = match sub_cmd.as_slice() {
+        b"AUTH" => match ctx.args.get(1..) {
+            Some([secret]) if raft.check_secret(secret) => {
+                ctx.session.is_raft_peer = true;
+                RedisValue::SimpleString(Bytes::from_static(b"OK"))
+            }
+            Some([_]) => {
+                RedisValue::SimpleError(Bytes::from_static(b"WRONGPASS invalid raft secret"))
+            }
+            _ => RedisValue::SimpleError(Bytes::from_static(
+                b"ERR wrong number of arguments for 'raft auth' command",
+            )),
+        },
+        b"REQUESTVOTE" => match VoteRequest::from_args(&ctx.args[1..]) {
+            Ok(req) => {
+                let (term, granted) = raft.request_vote(req);
+                RedisValue::Array(vec![
+                    RedisValue::Integer(term as i64),
+                    RedisValue::Integer(granted as i64),
+                ])
+            }
+            Err(e) => RedisValue::SimpleError(Bytes::from(e.to_string())),
+        },
+        b"APPENDENTRIES" => match AppendRequest::from_args(&ctx.args[1..]) {
+            Ok(req) => {
+                let (term, success, matched) = raft.append_entries(req);
+                RedisValue::Array(vec![
+                    RedisValue::Integer(term as i64),
+                    RedisValue::Integer(success as i64),
+                    RedisValue::Integer(matched as i64),
+                ])
+            }
+            Err(e) => RedisValue::SimpleError(Bytes::from(e.to_string())),
+        },
+        b"INSTALLSNAPSHOT" => match SnapshotRequest::from_args(&ctx.args[1..]) {
+            Ok(req) => {
+                let (term, success, last_index) = raft.install_snapshot(ctx.server, req).await;
+                RedisValue::Array(vec![
+                    RedisValue::Integer(term as i64),
+                    RedisValue::Integer(success as i64),
+                    RedisValue::Integer(last_index as i64),
+                ])
+            }
+            Err(e) => RedisValue::SimpleError(Bytes::from(e.to_string())),
+        },
+        b"STATE" => {
+            let (role, term, leader, commit_index) = raft.state();
+            RedisValue::Array(vec![
+                RedisValue::BulkString(Bytes::from_static(role.as_str().as_bytes())),
+                RedisValue::Integer(term as i64),
+                match leader {
+                    Some((host, port)) => {
+                        RedisValue::BulkString(Bytes::from(format!("{}:{}", host, port)))
+                    }
+                    None => RedisValue::NullBulkString,
+                },
+                RedisValue::Integer(commit_index as i64),
+            ])
+        }
+        _ => RedisValue::SimpleError(Bytes::from(format!(
+            "ERR unknown subcommand '{}'",
+            String::from_utf8_lossy(&sub_cmd)
+        ))),
+    };
+
+    Ok(res)
 }
 
-pub async fn replconf(ctx: &mut CommandContext<'_>) -> Result<usize> {
+/// REPLCONF <option> <value> ...: what a replica tells about itself during the handshake,
+/// listening-port, ip-address and capa, then `ACK <offset>` as it processes the stream.
+/// GETACK is answered by replicas on their link to the master, anywhere else it does nothing
+pub async fn replconf(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if !ctx.args.len().is_multiple_of(2) {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR syntax error",
+        )));
+    }
+    for (pos, option) in ctx.args.iter().enumerate().step_by(2) {
+        if option.eq_ignore_ascii_case(b"listening-port") {
+            let Some(port) = ctx
+                .arg_integer(pos + 1)
+                .and_then(|port| u16::try_from(port).ok())
+            else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is not an integer or out of range",
+                )));
+            };
+            ctx.session.replica_listening_port = Some(port);
+        } else if option.eq_ignore_ascii_case(b"ip-address") {
+            ctx.session.replica_announced_ip = ctx.arg_str(pos + 1);
+        } else if option.eq_ignore_ascii_case(b"capa") {
+            let capa = String::from_utf8_lossy(&ctx.args[pos + 1]).to_lowercase();
+            if !ctx.session.replica_capa.contains(&capa) {
+                ctx.session.replica_capa.push(capa);
+            }
+        } else if option.eq_ignore_ascii_case(b"ack") {
+            let Some(offset) = ctx
+                .arg_integer(pos + 1)
+                .and_then(|offset| usize::try_from(offset).ok())
+            else {
+                return Ok(RedisValue::SimpleError(Bytes::from_static(
+                    b"ERR value is not an integer or out of range",
+                )));
+            };
+            ctx.server.replicas.ack(ctx.session.id, offset);
+        } else if !option.eq_ignore_ascii_case(b"getack") {
+            return Ok(RedisValue::SimpleError(Bytes::from(format!(
+                "ERR Unrecognized REPLCONF option: {}",
+                String::from_utf8_lossy(option)
+            ))));
+        }
+    }
     let res = RedisValue::SimpleString(Bytes::from_static(b"OK"));
-    let bytes = ctx.handler.write(res).await?;
 
-    Ok(bytes)
+    Ok(res)
+}
+
+/// WAIT numreplicas timeout: blocks until that many replicas acknowledged every write made
+/// so far, or the timeout in ms goes by, 0 waiting for as long as it takes. Replies how many
+/// replicas did
+pub async fn wait(ctx: &mut CommandContext<'_>) -> Result<RedisValue> {
+    if ctx.args.len() != 2 {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'wait' command",
+        )));
+    }
+    let Some(numreplicas) = ctx.arg_integer(0) else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR value is not an integer or out of range",
+        )));
+    };
+    let timeout = match ctx.arg_integer(1) {
+        Some(ms) if ms < 0 => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR timeout is negative",
+            )))
+        }
+        Some(ms) => ms as u64,
+        None => {
+            return Ok(RedisValue::SimpleError(Bytes::from_static(
+                b"ERR timeout is not an integer or out of range",
+            )))
+        }
+    };
+    let ServerContext::Master(master) = ctx.server.server_context.read().unwrap().clone() else {
+        return Ok(RedisValue::SimpleError(Bytes::from_static(
+            b"ERR WAIT cannot be used with replica instances.",
+        )));
+    };
+
+    let offset = master.repl_offset();
+    let replicas = &ctx.server.replicas;
+    let enough = |count: usize| i64::try_from(count).unwrap_or(i64::MAX) >= numreplicas;
+    if ctx.session.in_exec {
+        return Ok(RedisValue::Integer(replicas.acked_count(offset) as i64));
+    }
+    if !enough(replicas.acked_count(offset)) {
+        // --- replicas only ACK when asked, the replies come back on their links
+        propagate(
+            ctx.server,
+            None,
+            "REPLCONF",
+            &[Bytes::from_static(b"GETACK"), Bytes::from_static(b"*")],
+        )?;
+    }
+    let deadline = (timeout > 0)
+        .then(|| tokio::time::Instant::now() + std::time::Duration::from_millis(timeout));
+    loop {
+        let next_ack = replicas.next_ack();
+        tokio::pin!(next_ack);
+        next_ack.as_mut().enable();
+        let count = replicas.acked_count(offset);
+        if enough(count) {
+            return Ok(RedisValue::Integer(count as i64));
+        }
+
+        let blocked = ctx
+            .server
+            .blocked_clients
+            .block(ctx.session.id, ctx.session.db, vec![]);
+        let timeout = deadline
+            .map(|deadline| deadline.saturating_duration_since(tokio::time::Instant::now()));
+        let wakeup = tokio::select! {
+            _ = &mut next_ack => continue,
+            wakeup = blocked.wait(timeout) => wakeup,
+        };
+        let res = match wakeup {
+            Wakeup::Unblocked { error: true } => {
+                RedisValue::SimpleError(Bytes::from_static(UNBLOCKED_ERROR))
+            }
+            _ => RedisValue::Integer(replicas.acked_count(offset) as i64),
+        };
+
+        return Ok(res);
+    }
 }
 
-pub async fn psync(ctx: &mut CommandContext<'_>) -> Result<usize> {
+/// PSYNC <replid> <offset>: resumes from the backlog with +CONTINUE when the requested
+/// history is still held, otherwise falls back to a full resync
+pub async fn psync(
+    ctx: &mut CommandContext<'_>,
+    handler: &mut RedisConnectionHandler,
+) -> Result<usize> {
+    let (Some(replid), Some(_)) = (ctx.args.first(), ctx.args.get(1)) else {
+        let err = RedisValue::SimpleError(Bytes::from_static(
+            b"ERR wrong number of arguments for 'psync' command",
+        ));
+        return handler.write(err).await;
+    };
+    let offset = match ctx.arg_integer(1) {
+        Some(offset) => offset,
+        None => {
+            let err = RedisValue::SimpleError(Bytes::from_static(
+                b"ERR value is not an integer or out of range",
+            ));
+            return handler.write(err).await;
+        }
+    };
+
+    let server_context = ctx.server.server_context.read().unwrap().clone();
+    let master_replid = server_context.get_master_replid();
+    let backlog = match &server_context {
+        ServerContext::Master(master) => &master.backlog,
+        ServerContext::Replica(replica) => &replica.backlog,
+    };
+    let ip = match (&ctx.session.replica_announced_ip, ctx.session.addr) {
+        (Some(ip), _) => ip.clone(),
+        (None, Some(addr)) => addr.ip().to_string(),
+        (None, None) => String::new(),
+    };
+    // --- no write gets in between the snapshot and the offset it is sent at
+    // --- a transaction is either all in the snapshot or all in the stream after it
+    let exec_guard = ctx.server.exec_lock.read().await;
+    let fence = ctx.server.replication_fence.write().await;
+    let dataset = ctx.server.snapshot().await;
+    let (repl_offset, stream_db, backlog_data) = {
+        let mut backlog = backlog.lock().unwrap();
+        // --- `PSYNC ? -1` never matches, it explicitly asks for a full resync
+        let backlog_data = match (&server_context, usize::try_from(offset)) {
+            (ServerContext::Master(master), Ok(offset)) if master.can_continue(replid, offset) => {
+                backlog.range_from(offset)
+            }
+            _ => None,
+        };
+        // --- a master starts the stream after the snapshot with a SELECT, a replica passes
+        // --- on its master's stream and the database it is at
+        let stream_db = match (&server_context, &backlog_data) {
+            (ServerContext::Master(_), None) => {
+                backlog.set_stream_db(None);
+                0
+            }
+            _ => backlog.stream_db().unwrap_or(0),
+        };
+        // --- writes made from here on are buffered until the sync payload is sent
+        let endpoint = ReplicaEndpoint {
+            ip,
+            port: ctx.session.replica_listening_port.unwrap_or(0),
+            offset: backlog.offset(),
+            capa: ctx.session.replica_capa.clone(),
+        };
+        ctx.session.replication_feed = Some(ctx.server.replicas.register(ctx.session.id, endpoint));
+        (backlog.offset(), stream_db, backlog_data)
+    };
+    drop(fence);
+    drop(exec_guard);
+    ctx.session.is_replica = true;
+
+    if let Some(backlog_data) = backlog_data {
+        log::info!(
+            "Partial resync accepted, sending {} bytes of backlog",
+            backlog_data.len()
+        );
+        let res = RedisValue::SimpleString(Bytes::from(format!("CONTINUE {}", master_replid)));
+        let bytes = handler.write(res).await? + handler.write_raw(&backlog_data).await?;
+
+        return Ok(bytes);
+    }
+
     let res = RedisValue::SimpleString(Bytes::from(format!(
-        "FULLRESYNC {} 0",
-        ctx.server.server_context.get_master_replid()
+        "FULLRESYNC {} {}",
+        master_replid, repl_offset
     )));
-    ctx.handler
+    // --- failures end the replica's connection, `handle_session` logs why
+    handler
         .write(res)
         .await
-        .expect("Failed to write initial FULLRESYNC");
+        .context("Failure sending FULLRESYNC")?;
 
     // --- send rdb dump over the wire for fullsync
-    let mut file = File::open("empty.rdb").await?;
-    let mut buf = vec![];
-    file.read_to_end(&mut buf)
-        .await
-        .expect("Failed to read rdb file");
-
-    let file_header = format!("${}\r\n", buf.len());
-    let raw_data = &[file_header.as_bytes(), &buf].concat();
-    let bytes = ctx
-        .handler
+    let repl_info = rdb::ReplInfo {
+        replid: master_replid.to_string(),
+        offset: repl_offset,
+        stream_db,
+    };
+    let rdb =
+        tokio::task::spawn_blocking(move || rdb::serialize(&dataset, Some(&repl_info))).await??;
+    let file_header = format!("${}\r\n", rdb.len());
+    let raw_data = &[file_header.as_bytes(), &rdb].concat();
+    let bytes = handler
         .write_raw(raw_data)
         .await
-        .expect("Failed to write file");
+        .context("Failure sending the RDB file")?;
 
     Ok(bytes)
 }
@@ -272,3 +9822,27 @@ pub async fn psync(ctx: &mut CommandContext<'_>) -> Result<usize> {
 fn format_info<V: Display>(key: &str, value: &V) -> String {
     format!("{}:{}", key, value)
 }
+
+/// Whether this server replicates another one
+fn is_replica(server: &RedisServer) -> bool {
+    matches!(
+        &*server.server_context.read().unwrap(),
+        ServerContext::Replica(_)
+    )
+}
+
+/// Whether this is a replica that lost the link to its master
+fn is_master_link_down(server: &RedisServer) -> bool {
+    match &*server.server_context.read().unwrap() {
+        ServerContext::Replica(replica) => !replica.link_up.load(Ordering::Relaxed),
+        ServerContext::Master(_) => false,
+    }
+}
+
+/// Ratio between two memory amounts the way Redis prints them, e.g. "1.25"
+fn format_ratio(numerator: usize, denominator: usize) -> String {
+    match denominator {
+        0 => "0.00".to_string(),
+        _ => format!("{:.2}", numerator as f64 / denominator as f64),
+    }
+}
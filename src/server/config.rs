@@ -0,0 +1,585 @@
+use std::{fs, time::Duration};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::{ArgAction, CommandFactory};
+
+use crate::Args;
+
+use super::{
+    encoding::EncodingLimits,
+    events::KeyspaceNotifications,
+    eviction::MaxmemoryPolicy,
+    glob::glob_match,
+    memory::{parse_memory_size, DatabaseQuotas},
+    output::OutputBufferLimits,
+    persistence::parse_save_points,
+    server::{RedisServer, RedisServerConfig},
+    split::split_args,
+};
+
+/// Parameters CONFIG SET may change, the rest of the configuration is fixed at startup
+const LIVE_PARAMS: &[&str] = &[
+    "save",
+    "maxmemory",
+    "maxmemory-db",
+    "maxmemory-policy",
+    "lfu-log-factor",
+    "lfu-decay-time",
+    "repl-timeout",
+    "replica-serve-stale-data",
+    "replica-read-only",
+    "client-output-buffer-limit",
+    "client-fairness-budget",
+    "requirepass",
+    "notify-keyspace-events",
+    "slowlog-log-slower-than",
+    "slowlog-max-len",
+    "timeout",
+    "busy-reply-threshold",
+    "lua-replicate-commands",
+    "hash-max-listpack-entries",
+    "hash-max-listpack-value",
+    "set-max-intset-entries",
+    "set-max-listpack-entries",
+    "set-max-listpack-value",
+    "zset-max-listpack-entries",
+    "zset-max-listpack-value",
+    "list-max-listpack-size",
+];
+
+/// Old names still accepted for a parameter, as (alias, name)
+const ALIASES: &[(&str, &str)] = &[
+    ("slaveof", "replicaof"),
+    ("slave-serve-stale-data", "replica-serve-stale-data"),
+    ("slave-read-only", "replica-read-only"),
+    ("slave-announce-ip", "replica-announce-ip"),
+    ("slave-announce-port", "replica-announce-port"),
+    ("hash-max-ziplist-entries", "hash-max-listpack-entries"),
+    ("hash-max-ziplist-value", "hash-max-listpack-value"),
+    ("zset-max-ziplist-entries", "zset-max-listpack-entries"),
+    ("zset-max-ziplist-value", "zset-max-listpack-value"),
+    ("list-max-ziplist-size", "list-max-listpack-size"),
+    ("lua-time-limit", "busy-reply-threshold"),
+];
+
+/// Directives adding to what earlier lines of the config file set instead of replacing it,
+/// an empty value starting over
+const CUMULATIVE_DIRECTIVES: &[&str] = &["save", "maxmemory-db", "client-output-buffer-limit"];
+
+/// What CONFIG SET can change while the server runs
+#[derive(Clone, Debug)]
+pub struct LiveConfig {
+    /// `save` points as (seconds, changes), empty when snapshotting is disabled
+    pub save_points: Vec<(u64, u64)>,
+    /// bytes, 0 when unlimited
+    pub maxmemory: usize,
+    /// per-database quotas, `maxmemory-db`
+    pub maxmemory_db: DatabaseQuotas,
+    pub maxmemory_policy: MaxmemoryPolicy,
+    pub lfu_log_factor: u32,
+    /// minutes, `lfu-decay-time`
+    pub lfu_decay_time: u64,
+    /// silence from the master after which a replica drops the link, `repl-timeout`
+    pub repl_timeout: Duration,
+    /// whether reads are served while the master link is down, `replica-serve-stale-data`
+    pub replica_serve_stale_data: bool,
+    /// whether a replica refuses writes from its clients, `replica-read-only`
+    pub replica_read_only: bool,
+    pub client_output_buffer_limits: OutputBufferLimits,
+    /// work units before a connection yields to the others, `client-fairness-budget`
+    pub client_fairness_budget: usize,
+    /// password of the default user, `requirepass`
+    pub requirepass: Option<String>,
+    /// keyspace events published over pub/sub, `notify-keyspace-events`
+    pub notify_keyspace_events: KeyspaceNotifications,
+    /// microseconds, negative when SLOWLOG is disabled, `slowlog-log-slower-than`
+    pub slowlog_log_slower_than: i64,
+    /// entries SLOWLOG keeps, `slowlog-max-len`
+    pub slowlog_max_len: usize,
+    /// seconds a client may stay silent before being disconnected, 0 for never, `timeout`
+    pub timeout: u64,
+    /// running time after which a script gets other clients -BUSY replies and may be
+    /// stopped with SCRIPT KILL, `busy-reply-threshold`
+    pub busy_reply_threshold: Duration,
+    /// whether scripts replicate the writes they made rather than themselves,
+    /// `lua-replicate-commands`
+    pub lua_replicate_commands: bool,
+    /// sizes under which collections stay compact, `*-max-listpack-*` and
+    /// `set-max-intset-entries`
+    pub encoding_limits: EncodingLimits,
+}
+impl Default for LiveConfig {
+    fn default() -> Self {
+        Self {
+            save_points: vec![(3600, 1), (300, 100), (60, 10000)],
+            maxmemory: 0,
+            maxmemory_db: DatabaseQuotas::default(),
+            maxmemory_policy: MaxmemoryPolicy::default(),
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            repl_timeout: Duration::from_secs(60),
+            replica_serve_stale_data: true,
+            replica_read_only: true,
+            client_output_buffer_limits: OutputBufferLimits::default(),
+            client_fairness_budget: 1000,
+            requirepass: None,
+            notify_keyspace_events: KeyspaceNotifications::default(),
+            slowlog_log_slower_than: 10_000,
+            slowlog_max_len: 128,
+            timeout: 0,
+            busy_reply_threshold: Duration::from_millis(5000),
+            lua_replicate_commands: true,
+            encoding_limits: EncodingLimits::default(),
+        }
+    }
+}
+impl LiveConfig {
+    /// Changes a parameter from its redis.conf form, one of `LIVE_PARAMS`
+    fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        match name {
+            "save" => self.save_points = parse_save_points(value)?,
+            "maxmemory" => self.maxmemory = parse_memory_size(value)?,
+            "maxmemory-db" => self.maxmemory_db = value.parse()?,
+            "maxmemory-policy" => self.maxmemory_policy = value.parse()?,
+            "lfu-log-factor" => self.lfu_log_factor = parse_number(value)?,
+            "lfu-decay-time" => self.lfu_decay_time = parse_number(value)?,
+            // --- a zero timeout would drop the link right away
+            "repl-timeout" => {
+                self.repl_timeout = Duration::from_secs(parse_number::<u64>(value)?.max(1))
+            }
+            "replica-serve-stale-data" => self.replica_serve_stale_data = parse_yes_no(value)?,
+            "replica-read-only" => self.replica_read_only = parse_yes_no(value)?,
+            "client-output-buffer-limit" => self.client_output_buffer_limits = value.parse()?,
+            "client-fairness-budget" => self.client_fairness_budget = parse_number(value)?,
+            "requirepass" => {
+                self.requirepass = Some(value.to_string()).filter(|password| !password.is_empty())
+            }
+            "notify-keyspace-events" => self.notify_keyspace_events = value.parse()?,
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than = parse_number(value)?,
+            "slowlog-max-len" => self.slowlog_max_len = parse_number(value)?,
+            "timeout" => self.timeout = parse_number(value)?,
+            "busy-reply-threshold" => {
+                self.busy_reply_threshold = Duration::from_millis(parse_number(value)?)
+            }
+            "lua-replicate-commands" => self.lua_replicate_commands = parse_yes_no(value)?,
+            "hash-max-listpack-entries" => {
+                self.encoding_limits.hash_max_listpack_entries = parse_number(value)?
+            }
+            "hash-max-listpack-value" => {
+                self.encoding_limits.hash_max_listpack_value = parse_number(value)?
+            }
+            "set-max-intset-entries" => {
+                self.encoding_limits.set_max_intset_entries = parse_number(value)?
+            }
+            "set-max-listpack-entries" => {
+                self.encoding_limits.set_max_listpack_entries = parse_number(value)?
+            }
+            "set-max-listpack-value" => {
+                self.encoding_limits.set_max_listpack_value = parse_number(value)?
+            }
+            "zset-max-listpack-entries" => {
+                self.encoding_limits.zset_max_listpack_entries = parse_number(value)?
+            }
+            "zset-max-listpack-value" => {
+                self.encoding_limits.zset_max_listpack_value = parse_number(value)?
+            }
+            "list-max-listpack-size" => {
+                self.encoding_limits.list_max_listpack_size = parse_number(value)?
+            }
+            _ => bail!("can't set immutable config"),
+        }
+
+        Ok(())
+    }
+
+    /// Current values, as CONFIG GET shows them
+    fn params(&self) -> Vec<(&'static str, String)> {
+        let save = self
+            .save_points
+            .iter()
+            .map(|(seconds, changes)| format!("{} {}", seconds, changes))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        vec![
+            ("save", save),
+            ("maxmemory", self.maxmemory.to_string()),
+            ("maxmemory-db", self.maxmemory_db.to_string()),
+            (
+                "maxmemory-policy",
+                self.maxmemory_policy.as_str().to_string(),
+            ),
+            ("lfu-log-factor", self.lfu_log_factor.to_string()),
+            ("lfu-decay-time", self.lfu_decay_time.to_string()),
+            ("repl-timeout", self.repl_timeout.as_secs().to_string()),
+            (
+                "replica-serve-stale-data",
+                yes_no(self.replica_serve_stale_data),
+            ),
+            ("replica-read-only", yes_no(self.replica_read_only)),
+            (
+                "client-output-buffer-limit",
+                self.client_output_buffer_limits.to_string(),
+            ),
+            (
+                "client-fairness-budget",
+                self.client_fairness_budget.to_string(),
+            ),
+            ("requirepass", self.requirepass.clone().unwrap_or_default()),
+            (
+                "notify-keyspace-events",
+                self.notify_keyspace_events.to_string(),
+            ),
+            (
+                "slowlog-log-slower-than",
+                self.slowlog_log_slower_than.to_string(),
+            ),
+            ("slowlog-max-len", self.slowlog_max_len.to_string()),
+            ("timeout", self.timeout.to_string()),
+            (
+                "busy-reply-threshold",
+                self.busy_reply_threshold.as_millis().to_string(),
+            ),
+            (
+                "lua-replicate-commands",
+                yes_no(self.lua_replicate_commands),
+            ),
+            (
+                "hash-max-listpack-entries",
+                self.encoding_limits.hash_max_listpack_entries.to_string(),
+            ),
+            (
+                "hash-max-listpack-value",
+                self.encoding_limits.hash_max_listpack_value.to_string(),
+            ),
+            (
+                "set-max-intset-entries",
+                self.encoding_limits.set_max_intset_entries.to_string(),
+            ),
+            (
+                "set-max-listpack-entries",
+                self.encoding_limits.set_max_listpack_entries.to_string(),
+            ),
+            (
+                "set-max-listpack-value",
+                self.encoding_limits.set_max_listpack_value.to_string(),
+            ),
+            (
+                "zset-max-listpack-entries",
+                self.encoding_limits.zset_max_listpack_entries.to_string(),
+            ),
+            (
+                "zset-max-listpack-value",
+                self.encoding_limits.zset_max_listpack_value.to_string(),
+            ),
+            (
+                "list-max-listpack-size",
+                self.encoding_limits.list_max_listpack_size.to_string(),
+            ),
+        ]
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T> {
+    value
+        .parse()
+        .ok()
+        .with_context(|| format!("argument couldn't be parsed into an integer: '{}'", value))
+}
+
+fn parse_yes_no(value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => bail!("argument must be 'yes' or 'no'"),
+    }
+}
+
+fn yes_no(flag: bool) -> String {
+    match flag {
+        true => "yes".to_string(),
+        false => "no".to_string(),
+    }
+}
+
+/// Name a parameter goes by, its alias resolved
+fn canonical_name(name: &str) -> &str {
+    ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, name)| name)
+}
+
+impl RedisServerConfig {
+    /// Parameters set for good at startup, the listening ports aside
+    fn fixed_params(&self) -> Vec<(&'static str, String)> {
+        #[allow(unused_mut)]
+        let mut res = vec![
+            ("bind", self.bind.join(" ")),
+            ("unixsocket", self.unixsocket.clone().unwrap_or_default()),
+            (
+                "unixsocketperm",
+                format!("{:o}", self.unixsocketperm.unwrap_or(0)),
+            ),
+            ("dir", self.dir.clone()),
+            ("dbfilename", self.dbfilename.clone()),
+            ("databases", self.databases.to_string()),
+            ("appendonly", yes_no(self.appendonly)),
+            ("appendfilename", self.appendfilename.clone()),
+            ("appendfsync", self.appendfsync.as_str().to_string()),
+            ("hz", self.hz.to_string()),
+            ("acllog-max-len", self.acllog_max_len.to_string()),
+            (
+                "replica-announce-ip",
+                self.replica_announce_ip.clone().unwrap_or_default(),
+            ),
+            (
+                "replica-announce-port",
+                self.replica_announce_port.unwrap_or(0).to_string(),
+            ),
+            ("tcp-keepalive", self.socket_options.keepalive.to_string()),
+            ("maxclients", self.connection_limits.max_total.to_string()),
+        ];
+        #[cfg(feature = "tls")]
+        res.extend([
+            (
+                "tls-cert-file",
+                self.tls.cert_file.clone().unwrap_or_default(),
+            ),
+            (
+                "tls-key-file",
+                self.tls.key_file.clone().unwrap_or_default(),
+            ),
+            (
+                "tls-ca-cert-file",
+                self.tls.ca_cert_file.clone().unwrap_or_default(),
+            ),
+            ("tls-replication", yes_no(self.tls.replication)),
+            (
+                "tls-auth-clients",
+                self.tls.auth_clients.as_str().to_string(),
+            ),
+            (
+                "tls-auth-clients-user",
+                self.tls.auth_clients_user.as_str().to_string(),
+            ),
+        ]);
+
+        res
+    }
+}
+
+impl RedisServer {
+    /// Every parameter CONFIG GET knows about with its current value
+    pub fn config_params(&self) -> Vec<(&'static str, String)> {
+        let port = self.local_addr().map_or(0, |addr| addr.port());
+        let mut res = vec![("port", port.to_string())];
+        res.extend(self.config.fixed_params());
+        #[cfg(feature = "tls")]
+        res.push((
+            "tls-port",
+            self.tls_addr().map_or(0, |addr| addr.port()).to_string(),
+        ));
+        res.extend(self.config.live.read().unwrap().params());
+
+        res
+    }
+
+    /// Parameters matching any of the glob patterns, under the name or alias that matched
+    pub fn config_get(&self, patterns: &[String]) -> Vec<(String, String)> {
+        let params = self.config_params();
+        let mut res: Vec<(String, String)> = vec![];
+        for pattern in patterns.iter().map(|pattern| pattern.to_lowercase()) {
+            let matches = |name: &str| glob_match(pattern.as_bytes(), name.as_bytes());
+            let aliases = ALIASES.iter().filter(|(alias, _)| matches(alias));
+            let found = params
+                .iter()
+                .filter(|(name, _)| matches(name))
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .chain(aliases.filter_map(|(alias, name)| {
+                    let (_, value) = params.iter().find(|(param, _)| param == name)?;
+                    Some((alias.to_string(), value.clone()))
+                }));
+            for (name, value) in found {
+                if !res.iter().any(|(known, _)| *known == name) {
+                    res.push((name, value));
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Changes parameters at runtime, all of them or none when one is unknown or its value
+    /// is wrong
+    pub fn config_set(&self, pairs: &[(String, String)]) -> Result<()> {
+        let params = self.config_params();
+        let mut live = self.config.live.read().unwrap().clone();
+        for (name, value) in pairs {
+            let param = canonical_name(&name.to_lowercase()).to_string();
+            ensure!(
+                params.iter().any(|(known, _)| *known == param),
+                "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                name
+            );
+            ensure!(
+                LIVE_PARAMS.contains(&param.as_str()),
+                "ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config",
+                name
+            );
+            if let Err(e) = live.set(&param, value) {
+                bail!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                    name,
+                    e
+                );
+            }
+        }
+        self.acl_users.set_requirepass(live.requirepass.as_deref());
+        *self.config.live.write().unwrap() = live;
+
+        Ok(())
+    }
+}
+
+impl RedisServer {
+    /// Reads the config file again, for CONFIG RELOAD and SIGHUP. The parameters CONFIG SET
+    /// could change take their new values, the others only once the server restarts.
+    /// Logs which ones are which
+    pub fn config_reload(&self) -> Result<()> {
+        let Some(path) = &self.config.config_file else {
+            bail!("ERR The server is running without a config file");
+        };
+        let reloaded = Args::reload(path, &self.config.command_line)
+            .and_then(|args| RedisServerConfig::from_args(&args))
+            .map_err(|e| anyhow!("ERR Failure reloading the config file {}: {}", path, e))?;
+
+        let fixed = self.config.fixed_params();
+        let restart = reloaded
+            .fixed_params()
+            .into_iter()
+            .filter(|param| !fixed.contains(param))
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        let live = reloaded.live.into_inner().unwrap();
+        let mut current = self.config.live.write().unwrap();
+        let params = current.params();
+        let changed = live
+            .params()
+            .into_iter()
+            .filter(|param| !params.contains(param))
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        if changed.contains(&"requirepass") {
+            self.acl_users.set_requirepass(live.requirepass.as_deref());
+        }
+        *current = live;
+
+        if !changed.is_empty() {
+            log::warn!("Config reload applied: {}", changed.join(", "));
+        }
+        if !restart.is_empty() {
+            log::warn!(
+                "Config reload left out until a restart: {}",
+                restart.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Command line flags equivalent to a redis.conf-style file: one directive per line, its
+/// arguments separated by spaces and quoted when they have some, `#` starting comments.
+/// Directives this server doesn't have are skipped with a warning
+pub fn config_file_flags(path: &str) -> Result<Vec<String>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failure reading the config file {}", path))?;
+    let command = Args::command();
+
+    let mut res = vec![];
+    // --- values of cumulative directives, only turned into flags once all lines are read
+    let mut cumulative: Vec<(String, Vec<String>)> = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words = split_config_line(line)
+            .with_context(|| format!("Malformed line {} of {}", number + 1, path))?;
+        let Some((directive, values)) = words.split_first() else {
+            continue;
+        };
+        let name = canonical_name(&directive.to_lowercase()).to_string();
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(name.as_str()))
+        else {
+            log::warn!(
+                "Skipping unknown directive '{}' on line {} of {}",
+                directive,
+                number + 1,
+                path
+            );
+            continue;
+        };
+
+        if CUMULATIVE_DIRECTIVES.contains(&name.as_str()) {
+            let value = values.join(" ");
+            match cumulative.iter_mut().find(|(known, _)| *known == name) {
+                Some((_, known)) if value.is_empty() => known.clear(),
+                Some((_, known)) => known.push(value),
+                None => cumulative.push((name, vec![value])),
+            }
+            continue;
+        }
+        match arg.get_action() {
+            ArgAction::SetTrue => {
+                ensure!(
+                    values.len() == 1,
+                    "Wrong number of arguments on line {} of {}",
+                    number + 1,
+                    path
+                );
+                if parse_yes_no(&values[0])? {
+                    res.push(format!("--{}", name));
+                }
+            }
+            // --- flags taking several values get them apart, the rest as a single one
+            _ if arg
+                .get_num_args()
+                .is_some_and(|range| range.max_values() > 1) =>
+            {
+                res.push(format!("--{}", name));
+                res.extend(values.iter().cloned());
+            }
+            _ => res.push(format!("--{}={}", name, values.join(" "))),
+        }
+    }
+    for (name, values) in cumulative {
+        let values = values.into_iter().filter(|value| !value.is_empty());
+        res.push(format!(
+            "--{}={}",
+            name,
+            values.collect::<Vec<_>>().join(" ")
+        ));
+    }
+
+    Ok(res)
+}
+
+/// Words of a config file line, as Redis splits them, see `split_args`. Escapes give
+/// bytes, the words they end up in must still be UTF-8
+fn split_config_line(line: &str) -> Result<Vec<String>> {
+    let line = line.as_bytes();
+    let Some(words) = split_args(line) else {
+        bail!("Unbalanced quotes");
+    };
+
+    words
+        .into_iter()
+        .map(|word| {
+            String::from_utf8(word.into_bytes(line)).context("Escapes making invalid UTF-8")
+        })
+        .collect()
+}
@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Bounds on client connections, overall and per IP, 0 meaning unlimited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// connections open at the same time from anywhere, `maxclients`
+    pub max_total: usize,
+    /// connections open at the same time, `max-clients-per-ip`
+    pub max_clients: usize,
+    /// connections accepted within a second, `max-connection-rate-per-ip`
+    pub max_rate: u64,
+}
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_total: 10_000,
+            max_clients: 0,
+            max_rate: 0,
+        }
+    }
+}
+
+/// Why a connection was turned away
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionRefusal {
+    MaxClients,
+    TooManyClients,
+    TooFast,
+}
+impl ConnectionRefusal {
+    /// Why the connection is closed, as told to the client in its own protocol
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::MaxClients => "max number of clients reached",
+            Self::TooManyClients => "max number of clients from this IP reached",
+            Self::TooFast => "connection rate limit from this IP reached",
+        }
+    }
+
+    /// Error sent to a RESP client before closing the connection
+    pub fn message(&self) -> Vec<u8> {
+        format!("-ERR {}\r\n", self.reason()).into_bytes()
+    }
+}
+
+#[derive(Debug, Default)]
+struct IpState {
+    /// connections currently open
+    open: usize,
+    /// second the rate is being counted for, unix time in seconds
+    window: u64,
+    /// connections accepted within `window`
+    accepted: u64,
+}
+
+/// Tracks the connections of every client IP against `ConnectionLimits`
+#[derive(Debug, Default)]
+pub struct ConnectionLimiter {
+    limits: ConnectionLimits,
+    ips: Mutex<HashMap<IpAddr, IpState>>,
+    /// connections currently open, from any IP or the unix socket
+    open: AtomicUsize,
+}
+impl ConnectionLimiter {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits,
+            ips: Mutex::default(),
+            open: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts a new connection with no IP to go by, e.g. over the unix socket, against
+    /// `maxclients` only
+    pub fn admit_local(self: &Arc<Self>) -> Result<ConnectionPermit, ConnectionRefusal> {
+        self.reserve()?;
+
+        let res = ConnectionPermit {
+            limiter: Arc::clone(self),
+            ip: None,
+        };
+
+        Ok(res)
+    }
+
+    /// Counts a new connection from `ip`, the permit keeps it open until dropped
+    pub fn admit(
+        self: &Arc<Self>,
+        ip: IpAddr,
+        now: u64,
+    ) -> Result<ConnectionPermit, ConnectionRefusal> {
+        let mut ips = self.ips.lock().unwrap();
+        let state = ips.entry(ip).or_default();
+        let second = now / 1000;
+        if state.window != second {
+            state.window = second;
+            state.accepted = 0;
+        }
+        // --- refused attempts count too, a flood stays refused until it slows down
+        state.accepted += 1;
+        if self.limits.max_rate > 0 && state.accepted > self.limits.max_rate {
+            return Err(ConnectionRefusal::TooFast);
+        }
+        if self.limits.max_clients > 0 && state.open >= self.limits.max_clients {
+            return Err(ConnectionRefusal::TooManyClients);
+        }
+        self.reserve()?;
+        state.open += 1;
+
+        let res = ConnectionPermit {
+            limiter: Arc::clone(self),
+            ip: Some(ip),
+        };
+
+        Ok(res)
+    }
+
+    /// Forgets IPs with no open connection and nothing counted in the current second
+    pub fn prune(&self, now: u64) {
+        let second = now / 1000;
+        self.ips
+            .lock()
+            .unwrap()
+            .retain(|_, state| state.open > 0 || state.window == second);
+    }
+
+    /// Takes one of the `maxclients` slots if any is left
+    fn reserve(&self) -> Result<(), ConnectionRefusal> {
+        let max = self.limits.max_total;
+        self.open
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |open| {
+                (max == 0 || open < max).then_some(open + 1)
+            })
+            .map_err(|_| ConnectionRefusal::MaxClients)?;
+
+        Ok(())
+    }
+
+    fn release(&self, ip: Option<IpAddr>) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+        let Some(ip) = ip else {
+            return;
+        };
+        if let Some(state) = self.ips.lock().unwrap().get_mut(&ip) {
+            state.open -= 1;
+        }
+    }
+}
+
+/// An admitted connection, counted against its IP for as long as it is held
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    /// `None` for connections only counted against `maxclients`
+    ip: Option<IpAddr>,
+}
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
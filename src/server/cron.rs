@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use tokio::time::{interval, Interval, MissedTickBehavior};
+
+use super::server::RedisServer;
+
+/// Bounds on `hz`, same as Redis
+pub const MIN_HZ: u64 = 1;
+pub const MAX_HZ: u64 = 500;
+
+impl RedisServer {
+    /// Ticks at the configured `hz` rate, driving `cron`
+    pub fn cron_interval(&self) -> Interval {
+        let mut cron = interval(Duration::from_millis(1000 / self.config.hz));
+        // --- a slow tick shouldn't be followed by a burst of catch-up runs
+        cron.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        cron
+    }
+
+    /// Periodic housekeeping, the equivalent of Redis' serverCron. Every job that has to
+    /// run in the background hooks in here rather than owning a timer of its own
+    pub async fn cron(&self) {
+        self.autosave().await;
+        self.active_expire_cycle().await;
+        self.connection_limiter.prune(self.clock.now());
+        self.stats.sample_ops(self.clock.now());
+        if let Some(recorder) = &self.recorder {
+            if let Err(e) = recorder.flush() {
+                log::error!("Failure writing the command log: {}", e);
+            }
+        }
+        if let Some(aof) = &self.aof {
+            if let Err(e) = aof.sync_if_due(self.clock.now()) {
+                log::error!("Failure syncing the append only file: {}", e);
+            }
+        }
+    }
+}
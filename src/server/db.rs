@@ -0,0 +1,78 @@
+use bytes::Bytes;
+
+use super::{handler::RedisValue, keyspace::Keyspace, server::Expires};
+
+/// Contents of a database as a whole, the way they are saved, loaded and swapped in: keys,
+/// always strings, each with its value and the time it expires at, read and written as one
+/// entry. The entries stay in the persistent maps the live stores are made of, so taking
+/// a database out of its stores or putting one in is O(1) and a snapshot shares its
+/// structure with the data it was taken from
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Db {
+    keyspace: Keyspace,
+    expires: Expires,
+}
+impl Db {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A database holding what its main and expire stores hold
+    pub fn from_stores(keyspace: Keyspace, expires: Expires) -> Self {
+        Self { keyspace, expires }
+    }
+
+    /// Main and expire stores to install the database in
+    pub fn into_stores(self) -> (Keyspace, Expires) {
+        (self.keyspace, self.expires)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keyspace.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyspace.is_empty()
+    }
+
+    /// Keys with an expire time
+    pub fn volatile_len(&self) -> usize {
+        self.expires.len()
+    }
+
+    /// Value of a key and its absolute expire time in ms, expired or not
+    pub fn get(&self, key: &Bytes) -> Option<(&RedisValue, Option<u64>)> {
+        let key = RedisValue::BulkString(key.clone());
+        let value = self.keyspace.get(&key)?;
+
+        Some((value, self.expires.get(&key).copied()))
+    }
+
+    /// Sets a key to a value expiring at `expires_at`, never for `None`, replacing both
+    pub fn insert(&mut self, key: Bytes, value: RedisValue, expires_at: Option<u64>) {
+        let key = RedisValue::BulkString(key);
+        match expires_at {
+            Some(expires_at) => self.expires.insert(key.clone(), expires_at),
+            None => self.expires.remove(&key),
+        };
+        self.keyspace.insert(key, value);
+    }
+
+    /// Removes a key, replying its value and expire time
+    pub fn remove(&mut self, key: &Bytes) -> Option<(RedisValue, Option<u64>)> {
+        let key = RedisValue::BulkString(key.clone());
+        let value = self.keyspace.remove(&key)?;
+
+        Some((value, self.expires.remove(&key)))
+    }
+
+    /// Keys with their values and expire times, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &RedisValue, Option<u64>)> {
+        self.keyspace.iter().map(|(key, value)| {
+            let RedisValue::BulkString(name) = key else {
+                unreachable!("Keys are bulk strings");
+            };
+            (name, value, self.expires.get(key).copied())
+        })
+    }
+}
@@ -0,0 +1,131 @@
+use sha1_smol::Sha1;
+
+use super::{handler::RedisValue, rdb, server::Dataset, zset::format_score};
+
+/// SHA1 digest as DEBUG DIGEST computes it
+pub type Digest = [u8; 20];
+
+/// Digest of the whole dataset, the same for any two servers holding the same keys,
+/// values and TTLs in the same databases whatever the order they were written in (DEBUG
+/// DIGEST). Keys past their TTL are left out, replicas don't show them either
+pub fn dataset_digest(dataset: &Dataset, now: u64) -> Digest {
+    let mut res = [0; 20];
+    for (db, contents) in dataset {
+        let mut live_keys = contents
+            .iter()
+            .filter(|(_, _, expires_at)| expires_at.is_none_or(|timestamp| timestamp >= now));
+        let Some(first) = live_keys.next() else {
+            continue;
+        };
+
+        // --- the database number goes first, the way Redis mixes in each non empty one
+        mix_digest(&mut res, &(*db as u32).to_be_bytes());
+        for (key, value, expires_at) in std::iter::once(first).chain(live_keys) {
+            let mut digest = [0; 20];
+            mix_digest(&mut digest, key);
+            mix_value(&mut digest, value);
+            // --- only whether a key has a TTL counts, deadlines drift between servers
+            if expires_at.is_some() {
+                xor_digest(&mut digest, b"!!expire!!");
+            }
+            // --- xor makes the order keys come in irrelevant
+            xor_bytes(&mut res, &digest);
+        }
+    }
+
+    res
+}
+
+/// Digest of a single value, zeroes for a missing key (DEBUG DIGEST-VALUE)
+pub fn value_digest(value: Option<&RedisValue>) -> Digest {
+    let mut res = [0; 20];
+    if let Some(value) = value {
+        mix_value(&mut res, value);
+    }
+
+    res
+}
+
+/// Digest as DEBUG DIGEST replies it, 40 hex digits
+pub fn to_hex(digest: &Digest) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn mix_value(digest: &mut Digest, value: &RedisValue) {
+    if let RedisValue::List(items) = value {
+        for item in items {
+            mix_digest(digest, item);
+        }
+        return;
+    }
+    // --- members xored in, their order doesn't count
+    if let RedisValue::Set(members) = value {
+        let mut members_digest = [0; 20];
+        for member in members {
+            xor_digest(&mut members_digest, &member);
+        }
+        mix_digest(digest, &members_digest);
+        return;
+    }
+    if let RedisValue::SortedSet(zset) = value {
+        let mut members_digest = [0; 20];
+        for (member, score) in zset.iter() {
+            let mut member_digest = [0; 20];
+            mix_digest(&mut member_digest, member);
+            mix_digest(&mut member_digest, &format_score(score));
+            xor_bytes(&mut members_digest, &member_digest);
+        }
+        mix_digest(digest, &members_digest);
+        return;
+    }
+    if let RedisValue::Stream(stream) = value {
+        for (id, fields) in stream.entries() {
+            mix_digest(digest, id.to_string().as_bytes());
+            for (field, value) in fields {
+                mix_digest(digest, field);
+                mix_digest(digest, value);
+            }
+        }
+        mix_digest(digest, stream.last_id().to_string().as_bytes());
+        return;
+    }
+    // --- each field digested on its own and xored in, the way Redis does for hashes
+    if let RedisValue::Hash(fields) = value {
+        let mut fields_digest = [0; 20];
+        for (field, value) in fields {
+            let mut field_digest = [0; 20];
+            mix_digest(&mut field_digest, field);
+            mix_digest(&mut field_digest, value);
+            xor_bytes(&mut fields_digest, &field_digest);
+        }
+        mix_digest(digest, &fields_digest);
+        return;
+    }
+    match value.as_string() {
+        Some(data) => mix_digest(digest, &data),
+        // --- module types: what they are saved as, behind the type name
+        None => {
+            mix_digest(digest, value.type_name().as_bytes());
+            if let Some(data) = rdb::module_data(value) {
+                mix_digest(digest, &data);
+            }
+        }
+    }
+}
+
+/// Xors the SHA1 of `data` into the digest
+fn xor_digest(digest: &mut Digest, data: &[u8]) {
+    xor_bytes(digest, &Sha1::from(data).digest().bytes());
+}
+
+/// Xors `data` in and hashes the result, making the outcome depend on the order of calls
+fn mix_digest(digest: &mut Digest, data: &[u8]) {
+    xor_digest(digest, data);
+    *digest = Sha1::from(&digest[..]).digest().bytes();
+}
+
+fn xor_bytes(digest: &mut Digest, other: &Digest) {
+    for (byte, other) in digest.iter_mut().zip(other) {
+        *byte ^= other;
+    }
+}
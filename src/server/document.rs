@@ -0,0 +1,234 @@
+use anyhow::{bail, ensure, Result};
+use serde_json::Value;
+
+/// Step of a JSONPath
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Selector {
+    /// `.name` or `['name']`
+    Key(String),
+    /// `[n]`, negative indexes count from the end
+    Index(i64),
+    /// `.*` or `[*]`
+    Wildcard,
+}
+
+/// Path into a JSON document, as taken by the JSON.* commands. `$`-paths select every
+/// match, legacy paths such as `.a.b` only the first one. Recursive descent isn't supported
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonPath {
+    selectors: Vec<Selector>,
+    legacy: bool,
+}
+impl JsonPath {
+    /// The whole document
+    pub fn root() -> Self {
+        Self {
+            selectors: vec![],
+            legacy: true,
+        }
+    }
+
+    pub fn parse(path: &str) -> Result<Self> {
+        let (legacy, mut rest) = match path.strip_prefix('$') {
+            Some(rest) => (false, rest),
+            None => (true, path.strip_prefix('.').unwrap_or(path)),
+        };
+        // --- legacy paths may start right with a key name, as in "a.b"
+        let mut selectors = vec![];
+        if legacy && !rest.is_empty() && !rest.starts_with('[') {
+            rest = parse_key(rest, &mut selectors);
+        }
+
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                ensure!(
+                    !after.starts_with('.'),
+                    "recursive descent is not supported in '{}'",
+                    path
+                );
+                ensure!(!after.is_empty(), "invalid path '{}'", path);
+                rest = parse_key(after, &mut selectors);
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let Some(end) = after.find(']') else {
+                    bail!("invalid path '{}'", path);
+                };
+                let inner = after[..end].trim();
+                let selector = match inner {
+                    "*" => Selector::Wildcard,
+                    _ if inner.len() >= 2
+                        && (inner.starts_with('\'') && inner.ends_with('\'')
+                            || inner.starts_with('"') && inner.ends_with('"')) =>
+                    {
+                        Selector::Key(inner[1..inner.len() - 1].to_string())
+                    }
+                    _ => match inner.parse() {
+                        Ok(index) => Selector::Index(index),
+                        Err(_) => bail!("invalid path '{}'", path),
+                    },
+                };
+                selectors.push(selector);
+                rest = &after[end + 1..];
+            } else {
+                bail!("invalid path '{}'", path);
+            }
+        }
+
+        Ok(Self { selectors, legacy })
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.selectors.is_empty()
+    }
+
+    /// Every match, as JSON pointers in document order
+    pub fn find(&self, doc: &Value) -> Vec<String> {
+        find(doc, &self.selectors)
+    }
+
+    /// What JSON.GET replies for the path: an array of every match for `$`-paths, the
+    /// first match for legacy ones, `None` when a legacy path matches nothing
+    pub fn get(&self, doc: &Value) -> Option<Value> {
+        let mut matches = self
+            .find(doc)
+            .into_iter()
+            .filter_map(|pointer| doc.pointer(&pointer).cloned());
+
+        match self.legacy {
+            true => matches.next(),
+            false => Some(Value::Array(matches.collect())),
+        }
+    }
+
+    /// Sets `value` at every match. Without matches the value is added under the last
+    /// key of the path to the objects its parent path matches. Returns whether anything
+    /// changed, `mode` allowing only updates (`Exists`) or only additions (`Missing`)
+    pub fn set(&self, doc: &mut Value, value: Value, mode: SetMode) -> bool {
+        let matches = self.find(doc);
+        if !matches.is_empty() {
+            if mode == SetMode::Missing {
+                return false;
+            }
+            for pointer in matches {
+                if let Some(target) = doc.pointer_mut(&pointer) {
+                    *target = value.clone();
+                }
+            }
+            return true;
+        }
+
+        let (Some(Selector::Key(key)), false) = (self.selectors.last(), mode == SetMode::Exists)
+        else {
+            return false;
+        };
+        let parents = find(doc, &self.selectors[..self.selectors.len() - 1]);
+        let mut res = false;
+        for pointer in parents {
+            if let Some(Value::Object(parent)) = doc.pointer_mut(&pointer) {
+                parent.insert(key.clone(), value.clone());
+                res = true;
+            }
+        }
+
+        res
+    }
+
+    /// Removes every match, returns how many were removed. The root can't be removed
+    /// from within the document
+    pub fn delete(&self, doc: &mut Value) -> usize {
+        let mut res = 0;
+        // --- later array elements go first so earlier indexes stay valid
+        for pointer in self.find(doc).into_iter().rev() {
+            let Some((parent, last)) = pointer.rsplit_once('/') else {
+                continue;
+            };
+            let removed = match doc.pointer_mut(parent) {
+                Some(Value::Object(object)) => object.remove(&unescape(last)).is_some(),
+                Some(Value::Array(array)) => match last.parse::<usize>() {
+                    Ok(index) if index < array.len() => {
+                        array.remove(index);
+                        true
+                    }
+                    _ => false,
+                },
+                _ => false,
+            };
+            res += usize::from(removed);
+        }
+
+        res
+    }
+}
+
+/// Which JSON.SET calls go through, `NX` and `XX`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetMode {
+    Always,
+    /// XX, only update existing values
+    Exists,
+    /// NX, only add missing values
+    Missing,
+}
+
+/// Reads a key name up to the next `.` or `[`, returns what follows it
+fn parse_key<'a>(path: &'a str, selectors: &mut Vec<Selector>) -> &'a str {
+    let end = path.find(['.', '[']).unwrap_or(path.len());
+    let selector = match &path[..end] {
+        "*" => Selector::Wildcard,
+        key => Selector::Key(key.to_string()),
+    };
+    selectors.push(selector);
+
+    &path[end..]
+}
+
+fn find(doc: &Value, selectors: &[Selector]) -> Vec<String> {
+    let mut current = vec![(String::new(), doc)];
+    for selector in selectors {
+        let mut next = vec![];
+        for (pointer, value) in current {
+            match (selector, value) {
+                (Selector::Key(key), Value::Object(object)) => {
+                    if let Some(child) = object.get(key) {
+                        next.push((format!("{}/{}", pointer, escape(key)), child));
+                    }
+                }
+                (Selector::Index(index), Value::Array(array)) => {
+                    let index = match *index {
+                        index if index < 0 => {
+                            array.len().checked_sub(index.unsigned_abs() as usize)
+                        }
+                        index => Some(index as usize),
+                    };
+                    if let Some((index, child)) =
+                        index.and_then(|index| Some((index, array.get(index)?)))
+                    {
+                        next.push((format!("{}/{}", pointer, index), child));
+                    }
+                }
+                (Selector::Wildcard, Value::Object(object)) => {
+                    for (key, child) in object {
+                        next.push((format!("{}/{}", pointer, escape(key)), child));
+                    }
+                }
+                (Selector::Wildcard, Value::Array(array)) => {
+                    for (index, child) in array.iter().enumerate() {
+                        next.push((format!("{}/{}", pointer, index), child));
+                    }
+                }
+                _ => {}
+            }
+        }
+        current = next;
+    }
+
+    current.into_iter().map(|(pointer, _)| pointer).collect()
+}
+
+/// Escapes a key for use in a JSON pointer (RFC 6901)
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
@@ -0,0 +1,581 @@
+use std::{
+    collections::{btree_map, btree_set, vec_deque, BTreeMap, BTreeSet, VecDeque},
+    hash::{Hash, Hasher},
+    ops::{Deref, Range},
+    slice,
+};
+
+use bytes::Bytes;
+
+/// Bytes a list listpack may take for each negative `list-max-listpack-size`, -1 to -5
+const LIST_LISTPACK_BYTES: [usize; 5] = [4096, 8192, 16384, 32768, 65536];
+/// Bytes a list listpack may take when `list-max-listpack-size` counts entries instead
+const LIST_LISTPACK_SAFETY_BYTES: usize = 8192;
+/// Listpack header and terminator
+const LISTPACK_OVERHEAD: usize = 7;
+
+/// Sizes under which collections keep their compact encoding, the `*-max-listpack-*` and
+/// `set-max-intset-entries` parameters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncodingLimits {
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+    pub set_max_intset_entries: usize,
+    pub set_max_listpack_entries: usize,
+    pub set_max_listpack_value: usize,
+    pub zset_max_listpack_entries: usize,
+    pub zset_max_listpack_value: usize,
+    /// entries when positive, -1 to -5 for 4kb to 64kb
+    pub list_max_listpack_size: i64,
+}
+impl Default for EncodingLimits {
+    fn default() -> Self {
+        Self {
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            set_max_intset_entries: 512,
+            set_max_listpack_entries: 128,
+            set_max_listpack_value: 64,
+            zset_max_listpack_entries: 128,
+            zset_max_listpack_value: 64,
+            list_max_listpack_size: -2,
+        }
+    }
+}
+impl EncodingLimits {
+    /// Whether a list of `len` items, `bytes` of listpack entries, fits in a single
+    /// listpack or needs a quicklist of them
+    fn list_fits(&self, len: usize, bytes: usize) -> bool {
+        let (max_len, max_bytes) = match self.list_max_listpack_size {
+            size if size >= 0 => (size as usize, LIST_LISTPACK_SAFETY_BYTES),
+            size => {
+                let level = (size.unsigned_abs() as usize).min(LIST_LISTPACK_BYTES.len());
+                (usize::MAX, LIST_LISTPACK_BYTES[level - 1])
+            }
+        };
+
+        len <= max_len && bytes + LISTPACK_OVERHEAD <= max_bytes
+    }
+
+    /// Whether a sorted set fits in a listpack, or needs a skiplist
+    pub fn zset_fits<'a>(&self, len: usize, mut members: impl Iterator<Item = &'a [u8]>) -> bool {
+        len <= self.zset_max_listpack_entries
+            && members.all(|member| member.len() <= self.zset_max_listpack_value)
+    }
+}
+
+/// Bytes a string of `len` takes in a listpack: its encoding, the data, its back length
+fn listpack_entry_size(len: usize) -> usize {
+    let header = match len {
+        0..=63 => 1,
+        64..=4095 => 2,
+        _ => 5,
+    };
+    let entry = header + len;
+    let backlen = match entry {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    };
+
+    entry + backlen
+}
+
+/// Items of a list, in one deque whatever their size as it's already as flat as a
+/// listpack. What is kept is the encoding Redis would hold them in: a single listpack
+/// while within `list-max-listpack-size`, a quicklist once the list outgrows it. Like
+/// hashes and sets, a list never goes back to a listpack
+#[derive(Clone, Debug, Default)]
+pub struct ListItems {
+    items: VecDeque<Bytes>,
+    /// bytes the items take as listpack entries, only kept up while in a listpack
+    listpack_bytes: usize,
+    quicklist: bool,
+}
+impl Deref for ListItems {
+    type Target = VecDeque<Bytes>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.items
+    }
+}
+impl ListItems {
+    pub fn push_front(&mut self, item: Bytes, limits: &EncodingLimits) {
+        let size = item.len();
+        self.items.push_front(item);
+        self.grown(size, limits);
+    }
+
+    pub fn push_back(&mut self, item: Bytes, limits: &EncodingLimits) {
+        let size = item.len();
+        self.items.push_back(item);
+        self.grown(size, limits);
+    }
+
+    /// Inserts an item before the one at `index`, at the tail when it's the length
+    pub fn insert(&mut self, index: usize, item: Bytes, limits: &EncodingLimits) {
+        let size = item.len();
+        self.items.insert(index, item);
+        self.grown(size, limits);
+    }
+
+    /// Replaces the item at `index`, replying the previous one. `None` when out of range
+    pub fn set(&mut self, index: usize, item: Bytes, limits: &EncodingLimits) -> Option<Bytes> {
+        let size = item.len();
+        let res = std::mem::replace(self.items.get_mut(index)?, item);
+        self.removed(&res);
+        self.grown(size, limits);
+
+        Some(res)
+    }
+
+    pub fn pop_front(&mut self) -> Option<Bytes> {
+        let res = self.items.pop_front()?;
+        self.removed(&res);
+
+        Some(res)
+    }
+
+    pub fn pop_back(&mut self) -> Option<Bytes> {
+        let res = self.items.pop_back()?;
+        self.removed(&res);
+
+        Some(res)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Bytes> {
+        let res = self.items.remove(index)?;
+        self.removed(&res);
+
+        Some(res)
+    }
+
+    /// Keeps only the items in `keep`, replying the others
+    pub fn trim(&mut self, keep: Range<usize>) -> Vec<Bytes> {
+        let tail = self.items.split_off(keep.end.min(self.items.len()));
+        let res = self
+            .items
+            .drain(..keep.start.min(self.items.len()))
+            .chain(tail)
+            .collect::<Vec<_>>();
+        for item in res.iter() {
+            self.removed(item);
+        }
+
+        res
+    }
+
+    /// As OBJECT ENCODING reports it
+    pub fn encoding(&self) -> &'static str {
+        match self.quicklist {
+            false => "listpack",
+            true => "quicklist",
+        }
+    }
+
+    /// Accounts for an item of `size` bytes that just went in, moving to a quicklist when
+    /// the list outgrew a listpack
+    fn grown(&mut self, size: usize, limits: &EncodingLimits) {
+        if self.quicklist {
+            return;
+        }
+        self.listpack_bytes += listpack_entry_size(size);
+        self.quicklist = !limits.list_fits(self.items.len(), self.listpack_bytes);
+    }
+
+    fn removed(&mut self, item: &[u8]) {
+        if !self.quicklist {
+            self.listpack_bytes -= listpack_entry_size(item.len());
+        }
+    }
+}
+/// Items as they are loaded, in a listpack when they fit Redis' default limits
+impl FromIterator<Bytes> for ListItems {
+    fn from_iter<T: IntoIterator<Item = Bytes>>(iter: T) -> Self {
+        let limits = EncodingLimits::default();
+        let mut res = Self::default();
+        for item in iter {
+            res.push_back(item, &limits);
+        }
+
+        res
+    }
+}
+impl<const N: usize> From<[Bytes; N]> for ListItems {
+    fn from(items: [Bytes; N]) -> Self {
+        items.into_iter().collect()
+    }
+}
+/// Same items, whatever the encodings
+impl PartialEq for ListItems {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+impl Eq for ListItems {}
+impl Hash for ListItems {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.items.hash(state);
+    }
+}
+impl<'a> IntoIterator for &'a ListItems {
+    type Item = &'a Bytes;
+    type IntoIter = vec_deque::Iter<'a, Bytes>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+/// Fields and values of a hash: pairs sorted by field in one vector while the hash is
+/// within `hash-max-listpack-*`, a tree once it outgrows them. Like in Redis, it never
+/// goes back to pairs
+#[derive(Clone, Debug)]
+pub enum HashFields {
+    Listpack(Vec<(Bytes, Bytes)>),
+    Table(BTreeMap<Bytes, Bytes>),
+}
+impl Default for HashFields {
+    fn default() -> Self {
+        Self::Listpack(vec![])
+    }
+}
+impl HashFields {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Listpack(pairs) => pairs.len(),
+            Self::Table(fields) => fields.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        match self {
+            Self::Listpack(pairs) => pairs
+                .binary_search_by(|(known, _)| known[..].cmp(field))
+                .ok()
+                .map(|pos| &pairs[pos].1),
+            Self::Table(fields) => fields.get(field),
+        }
+    }
+
+    pub fn contains_key(&self, field: &[u8]) -> bool {
+        self.get(field).is_some()
+    }
+
+    /// Sets a field, replying its previous value. Moves to a tree when the hash gets more
+    /// fields, or a longer field or value, than `limits` allow in a listpack
+    pub fn insert(&mut self, field: Bytes, value: Bytes, limits: &EncodingLimits) -> Option<Bytes> {
+        let pairs = match self {
+            Self::Listpack(pairs) => pairs,
+            Self::Table(fields) => return fields.insert(field, value),
+        };
+        let fits = field.len() <= limits.hash_max_listpack_value
+            && value.len() <= limits.hash_max_listpack_value;
+        let res = match pairs.binary_search_by(|(known, _)| known.cmp(&field)) {
+            Ok(pos) => Some(std::mem::replace(&mut pairs[pos].1, value)),
+            Err(pos) => {
+                pairs.insert(pos, (field, value));
+                None
+            }
+        };
+        if !fits || pairs.len() > limits.hash_max_listpack_entries {
+            *self = Self::Table(std::mem::take(pairs).into_iter().collect());
+        }
+
+        res
+    }
+
+    pub fn remove(&mut self, field: &[u8]) -> Option<Bytes> {
+        match self {
+            Self::Listpack(pairs) => pairs
+                .binary_search_by(|(known, _)| known[..].cmp(field))
+                .ok()
+                .map(|pos| pairs.remove(pos).1),
+            Self::Table(fields) => fields.remove(field),
+        }
+    }
+
+    /// Fields and their values, ordered by field whatever the encoding
+    pub fn iter(&self) -> HashIter<'_> {
+        match self {
+            Self::Listpack(pairs) => HashIter::Listpack(pairs.iter()),
+            Self::Table(fields) => HashIter::Table(fields.iter()),
+        }
+    }
+
+    /// As OBJECT ENCODING reports it
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::Listpack(_) => "listpack",
+            Self::Table(_) => "hashtable",
+        }
+    }
+}
+/// Fields as they are loaded, in a listpack when they fit Redis' default limits
+impl FromIterator<(Bytes, Bytes)> for HashFields {
+    fn from_iter<T: IntoIterator<Item = (Bytes, Bytes)>>(iter: T) -> Self {
+        let limits = EncodingLimits::default();
+        let mut res = Self::default();
+        for (field, value) in iter {
+            res.insert(field, value, &limits);
+        }
+
+        res
+    }
+}
+impl<const N: usize> From<[(Bytes, Bytes); N]> for HashFields {
+    fn from(pairs: [(Bytes, Bytes); N]) -> Self {
+        pairs.into_iter().collect()
+    }
+}
+/// Same fields and values, whatever the encodings
+impl PartialEq for HashFields {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+impl Eq for HashFields {}
+impl Hash for HashFields {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for pair in self.iter() {
+            pair.hash(state);
+        }
+    }
+}
+impl<'a> IntoIterator for &'a HashFields {
+    type Item = (&'a Bytes, &'a Bytes);
+    type IntoIter = HashIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub enum HashIter<'a> {
+    Listpack(slice::Iter<'a, (Bytes, Bytes)>),
+    Table(btree_map::Iter<'a, Bytes, Bytes>),
+}
+impl<'a> Iterator for HashIter<'a> {
+    type Item = (&'a Bytes, &'a Bytes);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Listpack(pairs) => pairs.next().map(|(field, value)| (field, value)),
+            Self::Table(fields) => fields.next(),
+        }
+    }
+}
+
+/// Members of a set: sorted integers while they all are and within
+/// `set-max-intset-entries`, sorted members in one vector within `set-max-listpack-*`,
+/// a tree past that. Like in Redis, a set never goes back to a more compact encoding
+#[derive(Clone, Debug)]
+pub enum SetMembers {
+    Intset(Vec<i64>),
+    Listpack(Vec<Bytes>),
+    Table(BTreeSet<Bytes>),
+}
+impl Default for SetMembers {
+    fn default() -> Self {
+        Self::Intset(vec![])
+    }
+}
+impl SetMembers {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Intset(ints) => ints.len(),
+            Self::Listpack(members) => members.len(),
+            Self::Table(members) => members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Self::Intset(ints) => {
+                intset_member(member).is_some_and(|n| ints.binary_search(&n).is_ok())
+            }
+            Self::Listpack(members) => members
+                .binary_search_by(|known| known[..].cmp(member))
+                .is_ok(),
+            Self::Table(members) => members.contains(member),
+        }
+    }
+
+    /// Adds a member, replying whether it is new. Moves to a listpack when a member isn't
+    /// an integer, and to a tree when the set outgrows the encoding it's in
+    pub fn insert(&mut self, member: Bytes, limits: &EncodingLimits) -> bool {
+        match self {
+            Self::Intset(ints) => match intset_member(&member) {
+                Some(n) => {
+                    let Err(pos) = ints.binary_search(&n) else {
+                        return false;
+                    };
+                    ints.insert(pos, n);
+                    if ints.len() > limits.set_max_intset_entries {
+                        *self = Self::Table(self.iter().collect());
+                    }
+                    true
+                }
+                None => {
+                    // --- the longest integers are at either end
+                    let longest = [ints.first(), ints.last()]
+                        .into_iter()
+                        .flatten()
+                        .map(|n| n.to_string().len())
+                        .chain([member.len()])
+                        .max()
+                        .unwrap_or_default();
+                    let fits = ints.len() < limits.set_max_listpack_entries
+                        && longest <= limits.set_max_listpack_value;
+                    *self = match fits {
+                        // --- listpacks are looked up by bytes, not by integer value
+                        true => {
+                            let mut members = self.iter().collect::<Vec<_>>();
+                            members.sort();
+                            Self::Listpack(members)
+                        }
+                        false => Self::Table(self.iter().collect()),
+                    };
+                    self.insert(member, limits)
+                }
+            },
+            Self::Listpack(members) => {
+                let Err(pos) = members.binary_search(&member) else {
+                    return false;
+                };
+                if members.len() < limits.set_max_listpack_entries
+                    && member.len() <= limits.set_max_listpack_value
+                {
+                    members.insert(pos, member);
+                } else {
+                    let mut members = std::mem::take(members).into_iter().collect::<BTreeSet<_>>();
+                    members.insert(member);
+                    *self = Self::Table(members);
+                }
+                true
+            }
+            Self::Table(members) => members.insert(member),
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            Self::Intset(ints) => match intset_member(member).map(|n| ints.binary_search(&n)) {
+                Some(Ok(pos)) => {
+                    ints.remove(pos);
+                    true
+                }
+                _ => false,
+            },
+            Self::Listpack(members) => {
+                match members.binary_search_by(|known| known[..].cmp(member)) {
+                    Ok(pos) => {
+                        members.remove(pos);
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+            Self::Table(members) => members.remove(member),
+        }
+    }
+
+    /// Members in the order of the encoding: integers by value, the others as bytes
+    pub fn iter(&self) -> SetIter<'_> {
+        match self {
+            Self::Intset(ints) => SetIter::Intset(ints.iter()),
+            Self::Listpack(members) => SetIter::Listpack(members.iter()),
+            Self::Table(members) => SetIter::Table(members.iter()),
+        }
+    }
+
+    /// As OBJECT ENCODING reports it
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::Intset(_) => "intset",
+            Self::Listpack(_) => "listpack",
+            Self::Table(_) => "hashtable",
+        }
+    }
+}
+/// Members as they are loaded, in the most compact encoding Redis' default limits allow
+impl FromIterator<Bytes> for SetMembers {
+    fn from_iter<T: IntoIterator<Item = Bytes>>(iter: T) -> Self {
+        let limits = EncodingLimits::default();
+        let mut res = Self::default();
+        for member in iter {
+            res.insert(member, &limits);
+        }
+
+        res
+    }
+}
+impl<const N: usize> From<[Bytes; N]> for SetMembers {
+    fn from(members: [Bytes; N]) -> Self {
+        members.into_iter().collect()
+    }
+}
+/// Same members, whatever the encodings
+impl PartialEq for SetMembers {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|member| other.contains(&member))
+    }
+}
+impl Eq for SetMembers {}
+/// Only the size, as equal sets may be in encodings listing their members in other orders
+impl Hash for SetMembers {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+    }
+}
+impl<'a> IntoIterator for &'a SetMembers {
+    type Item = Bytes;
+    type IntoIter = SetIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub enum SetIter<'a> {
+    Intset(slice::Iter<'a, i64>),
+    Listpack(slice::Iter<'a, Bytes>),
+    Table(btree_set::Iter<'a, Bytes>),
+}
+impl Iterator for SetIter<'_> {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Intset(ints) => ints.next().map(|n| Bytes::from(n.to_string())),
+            Self::Listpack(members) => members.next().cloned(),
+            Self::Table(members) => members.next().cloned(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Intset(ints) => ints.size_hint(),
+            Self::Listpack(members) => members.size_hint(),
+            Self::Table(members) => members.size_hint(),
+        }
+    }
+}
+
+/// The integer a member stands for when an intset can hold it: written the way Redis
+/// writes integers, without sign, padding or leading zeros it would lose
+fn intset_member(member: &[u8]) -> Option<i64> {
+    let n = std::str::from_utf8(member).ok()?.parse::<i64>().ok()?;
+
+    (n.to_string().as_bytes() == member).then_some(n)
+}
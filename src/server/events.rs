@@ -0,0 +1,159 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+use super::handler::RedisValue;
+
+/// Events a subscriber may fall behind by before missing some, see `RecvError::Lagged`
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Classes of keyspace events, by the flag turning them on: generic, string, list, set,
+/// hash, sorted set, expired, evicted, stream, module, key miss and new key
+const EVENT_CLASSES: &str = "g$lshzxetdmn";
+/// Classes "A" stands for, all but key misses and new keys
+const ALL_CLASSES: u16 = (1 << 10) - 1;
+
+/// A change to the dataset, named the way Redis keyspace notifications name them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyspaceEvent {
+    /// the lowercase command that wrote the key, e.g. "set" or "json.del", or "del" and
+    /// "expired" for keys going away
+    pub event: String,
+    /// database the key is in
+    pub db: usize,
+    pub key: Bytes,
+}
+
+/// Keyspace events for in-process subscribers, who react to writes without polling
+/// or going through pub/sub
+#[derive(Debug)]
+pub struct KeyspaceEvents {
+    sender: broadcast::Sender<KeyspaceEvent>,
+}
+impl Default for KeyspaceEvents {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENTS_CAPACITY);
+
+        Self { sender }
+    }
+}
+impl KeyspaceEvents {
+    /// Receiver of the events from now on. One that falls more than 1024 events behind
+    /// skips the oldest ones
+    pub fn subscribe(&self) -> broadcast::Receiver<KeyspaceEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn notify(&self, db: usize, event: &str, key: &RedisValue) {
+        // --- nothing is built while nobody listens, the common case
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let RedisValue::BulkString(key) = key else {
+            return;
+        };
+        let _ = self.sender.send(KeyspaceEvent {
+            event: event.to_string(),
+            db,
+            key: key.clone(),
+        });
+    }
+}
+
+/// Which keyspace events get published over pub/sub, `notify-keyspace-events`: "K" for
+/// `__keyspace@<db>__:<key>` channels, "E" for `__keyevent@<db>__:<event>` ones, and the
+/// classes of events to publish, e.g. "KEx" for expirations. Off by default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyspaceNotifications {
+    keyspace: bool,
+    keyevent: bool,
+    /// one bit per flag of `EVENT_CLASSES`
+    classes: u16,
+}
+impl FromStr for KeyspaceNotifications {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut res = Self::default();
+        for flag in s.chars() {
+            match flag {
+                'K' => res.keyspace = true,
+                'E' => res.keyevent = true,
+                'A' => res.classes |= ALL_CLASSES,
+                _ => match EVENT_CLASSES.find(flag) {
+                    Some(class) => res.classes |= 1 << class,
+                    None => bail!("Invalid notify-keyspace-events flags: '{}'", s),
+                },
+            }
+        }
+
+        Ok(res)
+    }
+}
+impl fmt::Display for KeyspaceNotifications {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut classes = self.classes;
+        if classes & ALL_CLASSES == ALL_CLASSES {
+            f.write_str("A")?;
+            classes &= !ALL_CLASSES;
+        }
+        for (class, flag) in EVENT_CLASSES.chars().enumerate() {
+            if classes & (1 << class) != 0 {
+                write!(f, "{}", flag)?;
+            }
+        }
+        if self.keyspace {
+            f.write_str("K")?;
+        }
+        if self.keyevent {
+            f.write_str("E")?;
+        }
+
+        Ok(())
+    }
+}
+impl KeyspaceNotifications {
+    /// Whether anything gets published at all, nothing does without K or E
+    pub fn enabled(&self) -> bool {
+        (self.keyspace || self.keyevent) && self.classes != 0
+    }
+
+    /// Channels an event on a key of a database is published to, with the message each gets
+    pub fn channels(&self, db: usize, event: &str, key: &Bytes) -> Vec<(Bytes, Bytes)> {
+        let class = EVENT_CLASSES.find(event_class(event)).unwrap();
+        if self.classes & (1 << class) == 0 {
+            return vec![];
+        }
+        let mut res = vec![];
+        if self.keyspace {
+            let mut channel = format!("__keyspace@{}__:", db).into_bytes();
+            channel.extend_from_slice(key);
+            res.push((Bytes::from(channel), Bytes::from(event.to_string())));
+        }
+        if self.keyevent {
+            let channel = format!("__keyevent@{}__:{}", db, event);
+            res.push((Bytes::from(channel), key.clone()));
+        }
+
+        res
+    }
+}
+
+/// Flag of the class an event belongs to, see `EVENT_CLASSES`
+fn event_class(event: &str) -> char {
+    match event {
+        "set" | "setrange" | "append" | "incrby" | "incrbyfloat" => '$',
+        "lpush" | "rpush" | "lpop" | "rpop" => 'l',
+        "sadd" | "srem" => 's',
+        "hset" | "hdel" => 'h',
+        "zadd" | "zrem" => 'z',
+        "xadd" => 't',
+        "expired" => 'x',
+        "evicted" => 'e',
+        // --- json.set, ts.add, bf.add and the like, as Redis modules name their events
+        event if event.contains('.') => 'd',
+        _ => 'g',
+    }
+}
@@ -0,0 +1,286 @@
+use std::{collections::HashMap, str::FromStr, sync::Mutex};
+
+use anyhow::{bail, Result};
+use rand::{seq::SliceRandom, Rng};
+
+use super::{
+    commands::propagate_deletions,
+    handler::RedisValue,
+    server::{Expires, RedisServer},
+};
+use crate::repl::ServerContext;
+
+/// LFU counter given to new keys, so they aren't evicted before getting a chance to be read
+pub const LFU_INIT_VAL: u8 = 5;
+
+/// Keys drawn to pick each one to evict, Redis' default `maxmemory-samples`
+const MAXMEMORY_SAMPLES: usize = 5;
+
+/// Which keys get evicted once `maxmemory` is reached, `maxmemory-policy`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MaxmemoryPolicy {
+    #[default]
+    NoEviction,
+    AllKeysLru,
+    VolatileLru,
+    AllKeysLfu,
+    VolatileLfu,
+    AllKeysRandom,
+    VolatileRandom,
+    VolatileTtl,
+}
+impl FromStr for MaxmemoryPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let res = match s.to_lowercase().as_str() {
+            "noeviction" => Self::NoEviction,
+            "allkeys-lru" => Self::AllKeysLru,
+            "volatile-lru" => Self::VolatileLru,
+            "allkeys-lfu" => Self::AllKeysLfu,
+            "volatile-lfu" => Self::VolatileLfu,
+            "allkeys-random" => Self::AllKeysRandom,
+            "volatile-random" => Self::VolatileRandom,
+            "volatile-ttl" => Self::VolatileTtl,
+            _ => bail!("Invalid maxmemory policy: '{}'", s),
+        };
+
+        Ok(res)
+    }
+}
+impl MaxmemoryPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::VolatileLru => "volatile-lru",
+            Self::AllKeysLfu => "allkeys-lfu",
+            Self::VolatileLfu => "volatile-lfu",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileRandom => "volatile-random",
+            Self::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    pub fn is_lfu(&self) -> bool {
+        matches!(self, Self::AllKeysLfu | Self::VolatileLfu)
+    }
+
+    /// Whether only keys with a TTL may be evicted
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            Self::VolatileLru | Self::VolatileLfu | Self::VolatileRandom | Self::VolatileTtl
+        )
+    }
+
+    /// How good an eviction candidate a key is, the highest goes first. Keys never
+    /// accessed since the dataset was loaded count as the least used
+    fn score(
+        &self,
+        key: &RedisValue,
+        expire_store: &Expires,
+        access_store: &HashMap<RedisValue, KeyAccess>,
+        now: u64,
+        lfu_decay_time: u64,
+    ) -> u64 {
+        let access = access_store.get(key);
+        match self {
+            Self::AllKeysLru | Self::VolatileLru => {
+                access.map_or(u64::MAX, |access| now.saturating_sub(access.last_access))
+            }
+            Self::AllKeysLfu | Self::VolatileLfu => {
+                let frequency = access.map_or(0, |access| access.frequency(now, lfu_decay_time));
+                u8::MAX as u64 - frequency as u64
+            }
+            Self::VolatileTtl => u64::MAX - expire_store.get(key).copied().unwrap_or(u64::MAX),
+            Self::NoEviction | Self::AllKeysRandom | Self::VolatileRandom => 0,
+        }
+    }
+}
+
+/// Keys eviction candidates are drawn from: the keyspace as it was when the pool was last
+/// filled, or its keys with a TTL for volatile policies. Keys written since only show up
+/// once it is used up and filled again, they would be the last ones to go anyway
+#[derive(Debug, Default)]
+pub struct EvictionPool(Mutex<Vec<RedisValue>>);
+impl EvictionPool {
+    /// Up to `count` keys drawn at random, `keys` filling the pool when it is empty. None
+    /// means there is nothing left to evict
+    fn sample(&self, keys: impl FnOnce() -> Vec<RedisValue>, count: usize) -> Vec<RedisValue> {
+        let mut pool = self.0.lock().unwrap();
+        if pool.is_empty() {
+            *pool = keys();
+        }
+        let mut rng = rand::thread_rng();
+
+        (0..count.min(pool.len()))
+            .map(|_| {
+                let pos = rng.gen_range(0..pool.len());
+                pool.swap_remove(pos)
+            })
+            .collect()
+    }
+
+    /// Empties the pool, for a database whose keys were all replaced
+    pub fn reset(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl RedisServer {
+    /// Evicts keys as `maxmemory-policy` says until memory use is back under `maxmemory`
+    /// and database `db`, the one about to be written to, under its `maxmemory-db` quota,
+    /// returning whether they are. Each key to go is the best of a few drawn at random, the
+    /// way Redis approximates LRU and LFU. Evictions reach replicas as DEL, replicas never
+    /// evict on their own
+    pub async fn evict_to_fit(&self, db: usize) -> bool {
+        let (maxmemory, quota, policy, lfu_decay_time) = {
+            let live = self.config.live.read().unwrap();
+            (
+                live.maxmemory,
+                live.maxmemory_db.get(db),
+                live.maxmemory_policy,
+                live.lfu_decay_time,
+            )
+        };
+        let over_maxmemory = || maxmemory != 0 && self.memory.used() > maxmemory;
+        let over_quota = || quota != 0 && self.databases[db].memory.used() > quota;
+        if !over_maxmemory() && !over_quota() {
+            return true;
+        }
+        if policy == MaxmemoryPolicy::NoEviction
+            || matches!(
+                *self.server_context.read().unwrap(),
+                ServerContext::Replica(_)
+            )
+        {
+            return false;
+        }
+
+        let exec_guard = self.exec_lock.read().await;
+        let fence = self.replication_fence.read().await;
+        // --- the best of the keys drawn from every database goes, all of them locked in
+        // --- index order
+        let mut stores = Vec::with_capacity(self.databases.len());
+        for db in &self.databases {
+            let main_store = db.main_store.lock().await;
+            let expire_store = db.expire_store.lock().await;
+            let access_store = db.access_store.lock().await;
+            stores.push((main_store, expire_store, access_store));
+        }
+        let now = self.clock.now();
+        let mut evicted = vec![vec![]; self.databases.len()];
+        while over_maxmemory() || over_quota() {
+            // --- past maxmemory any database may give up a key, past its quota only the
+            // --- database itself
+            let candidates = match over_maxmemory() {
+                true => 0..self.databases.len(),
+                false => db..db + 1,
+            };
+            let mut sample = vec![];
+            for (db, (main_store, expire_store, _)) in self.databases[candidates.clone()]
+                .iter()
+                .zip(&stores[candidates])
+            {
+                let keys = db.eviction_pool.sample(
+                    || match policy.is_volatile() {
+                        true => expire_store.keys().cloned().collect(),
+                        false => main_store.keys().cloned().collect(),
+                    },
+                    MAXMEMORY_SAMPLES,
+                );
+                sample.extend(keys.into_iter().map(|key| (db.index, key)));
+            }
+            if sample.is_empty() {
+                break;
+            }
+            // --- ties, e.g. every key under the random policies, go to any database
+            sample.shuffle(&mut rand::thread_rng());
+            let victim = sample
+                .into_iter()
+                .filter(|(index, key)| stores[*index].0.contains_key(key))
+                .max_by_key(|(index, key)| {
+                    let (_, expire_store, access_store) = &stores[*index];
+                    policy.score(key, expire_store, access_store, now, lfu_decay_time)
+                });
+            let Some((index, key)) = victim else {
+                continue;
+            };
+            let (main_store, expire_store, access_store) = &mut stores[index];
+            let Some((key, value)) = main_store.remove_with_key(&key) else {
+                continue;
+            };
+            expire_store.remove(&key);
+            access_store.remove(&key);
+            self.databases[index].memory.remove_entry(&key, &value);
+            self.databases[index].search_indexes.remove(&key);
+            self.stats.record_evicted_key();
+            self.key_changed(index, "evicted", &key);
+            evicted[index].push(key);
+        }
+        let res = !over_maxmemory() && !over_quota();
+        drop(stores);
+        for (index, keys) in evicted.iter().enumerate() {
+            if let Err(e) = propagate_deletions(self, index, keys) {
+                log::error!("Failure propagating evicted keys: {}", e);
+            }
+        }
+        drop(fence);
+        drop(exec_guard);
+
+        res
+    }
+}
+
+/// Access metadata kept per key to pick eviction candidates
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyAccess {
+    /// last access time in ms, for LRU
+    pub last_access: u64,
+    /// logarithmic access counter, for LFU
+    lfu_counter: u8,
+    /// last time the LFU counter was decremented, in minutes
+    lfu_decrement_time: u64,
+}
+impl KeyAccess {
+    pub fn new(now: u64) -> Self {
+        Self {
+            last_access: now,
+            lfu_counter: LFU_INIT_VAL,
+            lfu_decrement_time: now / 60_000,
+        }
+    }
+
+    /// Records an access: refreshes the LRU clock and, with decreasing probability the
+    /// higher it already is, bumps the LFU counter (`lfu-log-factor`)
+    pub fn touch(&mut self, now: u64, lfu_log_factor: u32, lfu_decay_time: u64) {
+        let counter = self.frequency(now, lfu_decay_time);
+        self.lfu_counter = log_incr(counter, lfu_log_factor);
+        self.lfu_decrement_time = now / 60_000;
+        self.last_access = now;
+    }
+
+    /// LFU counter after decaying one step per `lfu-decay-time` minutes without access
+    pub fn frequency(&self, now: u64, lfu_decay_time: u64) -> u8 {
+        let elapsed = (now / 60_000).saturating_sub(self.lfu_decrement_time);
+        let periods = elapsed.checked_div(lfu_decay_time).unwrap_or(0);
+
+        self.lfu_counter
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+}
+
+fn log_incr(counter: u8, lfu_log_factor: u32) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    let p = 1.0 / (base * lfu_log_factor as f64 + 1.0);
+    if rand::thread_rng().gen::<f64>() < p {
+        counter + 1
+    } else {
+        counter
+    }
+}
@@ -0,0 +1,374 @@
+use std::{
+    str::FromStr,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use tokio::time::{interval, MissedTickBehavior};
+
+use super::{
+    commands::propagate_deletions,
+    handler::RedisValue,
+    keyspace::Keyspace,
+    server::{Database, Expires, RedisServer},
+};
+use crate::repl::ServerContext;
+
+/// How expired keys get removed, `expiry-mode`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExpiryMode {
+    /// when a command runs into them
+    #[default]
+    Lazy,
+    /// also within a tick of their deadline, tracked in a timer wheel
+    Precise,
+}
+impl FromStr for ExpiryMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let res = match s.to_lowercase().as_str() {
+            "lazy" => Self::Lazy,
+            "precise" => Self::Precise,
+            _ => bail!("Invalid expiry mode: '{}'", s),
+        };
+
+        Ok(res)
+    }
+}
+impl ExpiryMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lazy => "lazy",
+            Self::Precise => "precise",
+        }
+    }
+}
+
+/// How often timers are checked, keys go away at most this long after expiring
+const TICK: Duration = Duration::from_millis(1);
+
+/// Keys with a TTL the active expiry cycle checks per round, as in Redis
+const ACTIVE_EXPIRE_SAMPLE: usize = 20;
+/// Rounds go on while more than this percentage of the keys checked had expired
+const ACTIVE_EXPIRE_STALE_PERCENT: usize = 10;
+/// Share of a cron tick the active expiry cycle may take, in percent
+const ACTIVE_EXPIRE_CPU_PERCENT: u64 = 25;
+
+/// Bits of the deadline each level of the wheel covers
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 6;
+/// Time covered by the whole wheel, ~2 years of milliseconds. Deadlines past the end of the
+/// current span are parked at its edge and placed again once it comes around
+const MAX_SPAN: u64 = 1 << (SLOT_BITS * LEVELS as u32);
+
+struct Timer {
+    key: RedisValue,
+    deadline: u64,
+}
+
+/// Hierarchical timer wheel over millisecond deadlines. Level `n` has 64 slots of 64^n ms
+/// each, timers move down a level every time their slot comes around until they fire.
+/// Insertion is O(1) and advancing only visits occupied slots
+pub struct TimerWheel {
+    /// time the wheel has been advanced to
+    elapsed: u64,
+    levels: Vec<Vec<Vec<Timer>>>,
+    len: usize,
+}
+impl TimerWheel {
+    pub fn new(now: u64) -> Self {
+        Self {
+            elapsed: now,
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            len: 0,
+        }
+    }
+
+    /// Number of timers pending, including stale ones
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a timer for the key, due once the wheel reaches `deadline`
+    pub fn insert(&mut self, key: RedisValue, deadline: u64) {
+        self.len += 1;
+        self.place(Timer { key, deadline });
+    }
+
+    /// Advances the wheel to `now`, returning the keys whose timers are due
+    pub fn advance(&mut self, now: u64) -> Vec<RedisValue> {
+        let mut res = vec![];
+        while let Some((level, slot, slot_start)) = self.next_slot() {
+            if slot_start > now {
+                break;
+            }
+
+            // --- a millisecond slot is done with once drained, move past it so timers parked
+            // --- at the edge of the span get placed in the next one
+            self.elapsed = match level {
+                0 => slot_start + 1,
+                _ => slot_start,
+            };
+            for timer in std::mem::take(&mut self.levels[level][slot]) {
+                if timer.deadline <= now {
+                    self.len -= 1;
+                    res.push(timer.key);
+                } else {
+                    self.place(timer);
+                }
+            }
+        }
+        self.elapsed = self.elapsed.max(now);
+
+        res
+    }
+
+    fn place(&mut self, timer: Timer) {
+        // --- due or too far ahead, either way it belongs to a slot we can reach
+        let at = timer
+            .deadline
+            .clamp(self.elapsed, self.elapsed | (MAX_SPAN - 1));
+        let level = level_for(self.elapsed, at);
+        let slot = slot_for(at, level);
+        self.levels[level][slot].push(timer);
+    }
+
+    /// First occupied slot from `elapsed` on, the lowest level winning ties
+    fn next_slot(&self) -> Option<(usize, usize, u64)> {
+        (0..LEVELS)
+            .filter_map(|level| {
+                let slot_span = 1u64 << (SLOT_BITS * level as u32);
+                let level_span = slot_span << SLOT_BITS;
+                let level_start = self.elapsed - self.elapsed % level_span;
+                let current = slot_for(self.elapsed, level);
+                (current..SLOTS)
+                    .find(|slot| !self.levels[level][*slot].is_empty())
+                    .map(|slot| {
+                        let slot_start = level_start + slot as u64 * slot_span;
+                        (level, slot, slot_start.max(self.elapsed))
+                    })
+            })
+            .min_by_key(|(level, _, slot_start)| (*slot_start, *level))
+    }
+}
+
+/// Level whose slots tell `elapsed` and `deadline` apart: the highest 6 bit group they
+/// differ in
+fn level_for(elapsed: u64, deadline: u64) -> usize {
+    let masked = (elapsed ^ deadline) | (SLOTS as u64 - 1);
+    let significant = 63 - masked.leading_zeros();
+
+    (significant / SLOT_BITS) as usize
+}
+
+fn slot_for(deadline: u64, level: usize) -> usize {
+    ((deadline >> (SLOT_BITS * level as u32)) as usize) & (SLOTS - 1)
+}
+
+/// Deadlines of keys with a TTL, for `ExpiryMode::Precise`. Timers aren't removed when a TTL
+/// changes or a key goes away, the expire store is checked again when they fire
+pub struct ExpiryTimers {
+    wheel: Mutex<TimerWheel>,
+}
+impl ExpiryTimers {
+    pub fn new(now: u64) -> Self {
+        Self {
+            wheel: Mutex::new(TimerWheel::new(now)),
+        }
+    }
+
+    /// Keys count as expired once their timestamp is in the past, see `get_live_value`
+    pub fn schedule(&self, key: RedisValue, expire_time: u64) {
+        self.wheel.lock().unwrap().insert(key, expire_time + 1);
+    }
+
+    pub fn due(&self, now: u64) -> Vec<RedisValue> {
+        self.wheel.lock().unwrap().advance(now)
+    }
+
+    pub fn len(&self) -> usize {
+        self.wheel.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Exchanges the timers of two databases, along with the keys they hold (SWAPDB)
+    pub fn swap(&self, other: &ExpiryTimers) {
+        let mut wheel = self.wheel.lock().unwrap();
+        let mut other_wheel = other.wheel.lock().unwrap();
+        std::mem::swap(&mut *wheel, &mut *other_wheel);
+    }
+}
+
+/// Keys with a TTL the active expiry cycle has yet to check in its current pass, the way
+/// Redis walks its expires dictionary with a cursor
+#[derive(Debug, Default)]
+pub struct ExpireCursor(Mutex<Vec<RedisValue>>);
+impl ExpireCursor {
+    /// Up to `count` keys to check next, a new pass starting once the last one is done.
+    /// Fewer keys than asked for mean the pass just ended
+    fn next(&self, expire_store: &Expires, count: usize) -> Vec<RedisValue> {
+        let mut pending = self.0.lock().unwrap();
+        if pending.is_empty() {
+            *pending = expire_store.keys().cloned().collect();
+        }
+        let at = pending.len().saturating_sub(count);
+
+        pending.split_off(at)
+    }
+
+    /// Starts the next pass over, for a database whose keys were all replaced
+    pub fn reset(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl RedisServer {
+    /// Expires keys as their timers fire, for as long as the server runs
+    pub async fn run_expiry(self: Arc<Self>) {
+        let mut tick = interval(TICK);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+            self.expire_due_keys().await;
+        }
+    }
+
+    /// Removes the keys whose timers fired, as long as they really expired. With active
+    /// expiry off the timers are left to fire once it is back on
+    pub async fn expire_due_keys(&self) {
+        if !self.active_expire.load(Ordering::Relaxed) {
+            return;
+        }
+        let now = self.clock.now();
+        for db in &self.databases {
+            let due = db.expiry_timers.due(now);
+            if due.is_empty() {
+                continue;
+            }
+
+            let exec_guard = self.exec_lock.read().await;
+            let fence = self.replication_fence.read().await;
+            let mut main_store = db.main_store.lock().await;
+            let mut expire_store = db.expire_store.lock().await;
+            let expired = due
+                .into_iter()
+                .filter(|key| self.remove_expired(db, &mut main_store, &mut expire_store, key, now))
+                .collect::<Vec<_>>();
+            drop(expire_store);
+            drop(main_store);
+            if let Err(e) = propagate_deletions(self, db.index, &expired) {
+                log::error!("Failure propagating expired keys: {}", e);
+            }
+            drop(fence);
+            drop(exec_guard);
+        }
+    }
+
+    /// Active expiry for `ExpiryMode::Lazy`, run from `cron`: checks keys with a TTL 20 at
+    /// a time and removes the expired ones, going on while more than 10% of them were,
+    /// for at most a quarter of a cron tick. Databases are gone through in turn, a cycle
+    /// out of time leaving the rest to the next one. Replicas leave it to their master,
+    /// whose deletions reach them as DEL
+    pub async fn active_expire_cycle(&self) {
+        if self.config.expiry_mode == ExpiryMode::Precise
+            || !self.active_expire.load(Ordering::Relaxed)
+            || matches!(
+                *self.server_context.read().unwrap(),
+                ServerContext::Replica(_)
+            )
+        {
+            return;
+        }
+        let started = Instant::now();
+        let budget = Duration::from_millis(1000 / self.config.hz * ACTIVE_EXPIRE_CPU_PERCENT / 100);
+
+        // --- cron runs in the accept loop, which a script holding the lock for long would
+        // stall, the next tick tries again
+        let Ok(exec_guard) = self.exec_lock.try_read() else {
+            return;
+        };
+        let fence = self.replication_fence.read().await;
+        let first = self.expire_next_db.load(Ordering::Relaxed);
+        for offset in 0..self.databases.len() {
+            let db = &self.databases[(first + offset) % self.databases.len()];
+            let expired = self.expire_cycle_db(db, started, budget).await;
+            if let Err(e) = propagate_deletions(self, db.index, &expired) {
+                log::error!("Failure propagating expired keys: {}", e);
+            }
+            if started.elapsed() >= budget {
+                let next = (db.index + 1) % self.databases.len();
+                self.expire_next_db.store(next, Ordering::Relaxed);
+                break;
+            }
+        }
+        drop(fence);
+        drop(exec_guard);
+    }
+
+    /// One database's share of `active_expire_cycle`, returning the keys it removed
+    async fn expire_cycle_db(
+        &self,
+        db: &Database,
+        started: Instant,
+        budget: Duration,
+    ) -> Vec<RedisValue> {
+        let mut main_store = db.main_store.lock().await;
+        let mut expire_store = db.expire_store.lock().await;
+        let mut expired = vec![];
+        loop {
+            let now = self.clock.now();
+            let sample = db.expire_cursor.next(&expire_store, ACTIVE_EXPIRE_SAMPLE);
+            let checked = sample.len();
+            let before = expired.len();
+            expired.extend(sample.into_iter().filter(|key| {
+                self.remove_expired(db, &mut main_store, &mut expire_store, key, now)
+            }));
+            let stale = expired.len() - before;
+            if checked < ACTIVE_EXPIRE_SAMPLE
+                || stale * 100 <= checked * ACTIVE_EXPIRE_STALE_PERCENT
+                || started.elapsed() >= budget
+            {
+                return expired;
+            }
+        }
+    }
+
+    /// Removes a key whose TTL is past, returning whether it did
+    fn remove_expired(
+        &self,
+        db: &Database,
+        main_store: &mut Keyspace,
+        expire_store: &mut Expires,
+        key: &RedisValue,
+        now: u64,
+    ) -> bool {
+        if expire_store
+            .get(key)
+            .is_none_or(|timestamp| *timestamp >= now)
+        {
+            return false;
+        }
+        expire_store.remove(key);
+        let Some(value) = main_store.remove(key) else {
+            return false;
+        };
+        db.memory.remove_entry(key, &value);
+        db.search_indexes.remove(key);
+        self.stats.record_expired_key();
+        self.key_changed(db.index, "expired", key);
+
+        true
+    }
+}
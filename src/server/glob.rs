@@ -0,0 +1,71 @@
+/// Whether `text` matches a glob-style pattern, the way KEYS and SCAN MATCH take them:
+/// `*` any run of bytes, `?` any one byte, `[abc]`, `[^abc]` and `[a-z]` one byte of a
+/// class, `\` making the byte after it literal
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // --- where to go on from after the last star, should what follows it fail to match
+    let mut backtrack = None;
+    while t < text.len() {
+        let next = match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, t));
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(pattern, p + 1, text[t]),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(p + 2),
+            Some(byte) => (*byte == text[t]).then_some(p + 1),
+            None => None,
+        };
+        match (next, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                t += 1;
+            }
+            // --- the star takes one more byte and the rest is tried again
+            (None, Some((star_p, star_t))) => {
+                backtrack = Some((star_p, star_t + 1));
+                p = star_p;
+                t = star_t + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|byte| *byte == b'*')
+}
+
+/// Matches a byte against the class starting right after a `[`, returning where the
+/// pattern goes on past its `]`. An unterminated class runs to the end of the pattern
+fn match_class(pattern: &[u8], mut p: usize, byte: u8) -> Option<usize> {
+    let negated = pattern.get(p) == Some(&b'^');
+    if negated {
+        p += 1;
+    }
+    let mut matched = false;
+    while let Some(&first) = pattern.get(p) {
+        match first {
+            b']' => {
+                p += 1;
+                break;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                matched |= pattern[p + 1] == byte;
+                p += 2;
+            }
+            // --- a reversed range counts as written the right way round
+            _ if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() => {
+                let last = pattern[p + 2];
+                matched |= (first.min(last)..=first.max(last)).contains(&byte);
+                p += 3;
+            }
+            _ => {
+                matched |= first == byte;
+                p += 1;
+            }
+        }
+    }
+
+    (matched != negated).then_some(p)
+}
@@ -2,20 +2,43 @@ use core::str;
 
 use anyhow::{bail, ensure, Result};
 use bytes::{Bytes, BytesMut};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::server::serde::{get_next_word, RespParser};
 
-use crate::server::serde::{get_next_word, tokenize};
+use super::{
+    bloom::BloomFilter,
+    encoding::{EncodingLimits, HashFields, ListItems, SetMembers},
+    serde::{ProtocolError, ProtocolLimits, RESPRaw, RESPToken},
+    stream::Stream,
+    timeseries::TimeSeries,
+    zset::SortedSet,
+};
 
-use super::serde::{RESPRaw, RESPToken};
+/// Any bidirectional byte stream a connection can be served over (TCP, unix sockets,
+/// TLS, in-memory duplex pipes, ...)
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
 
 pub struct RedisConnectionHandler {
-    stream: TcpStream,
+    stream: Box<dyn AsyncStream>,
     buffer: BytesMut,
+    /// state of the frame being parsed from `buffer`
+    parser: RespParser,
+    /// frame parsed ahead by `has_buffered_frame`, not handed out yet
+    ready: Option<RESPToken>,
+    /// replies queued while more pipelined requests are buffered, sent in one write
+    output: BytesMut,
+    limits: ProtocolLimits,
+    /// bytes written since the last `take_written`
+    written: usize,
 }
 
+/// Length of the delimiter closing a diskless RDB transfer
+const RDB_EOF_MARK_LEN: usize = 40;
+/// Longest string Redis embeds in its object header
+const MAX_EMBSTR_LEN: usize = 44;
+
 /// Fundamental type returned by the parser, ready to be consumed by the executor
 pub type RESPResult = Result<Option<RedisValue>>;
 
@@ -25,15 +48,108 @@ pub enum RedisValue {
     BulkString(Bytes),
     Array(Vec<RedisValue>),
     NullBulkString,
+    /// "*-1", the null array of XREAD BLOCK timing out
+    NullArray,
     SimpleError(Bytes),
+    Integer(i64),
+    /// JSON document stored by JSON.SET, as compact text. Only ever held by the stores,
+    /// it goes on the wire as a bulk string
+    Json(Bytes),
+    /// Time series of TS.ADD, only ever held by the stores and never sent as is
+    TimeSeries(Box<TimeSeries>),
+    /// Bloom filter of BF.ADD, only ever held by the stores and never sent as is
+    Bloom(Box<BloomFilter>),
+    /// String holding an integer, as INCR and friends leave it so counters aren't parsed
+    /// again on every update. Only ever held by the stores, it goes on the wire as a bulk
+    /// string
+    Counter(i64),
+    /// List of LPUSH and RPUSH, only ever held by the stores and never sent as is
+    List(ListItems),
+    /// Fields and values of HSET, only ever held by the stores and never sent as is
+    Hash(HashFields),
+    /// Members of SADD, only ever held by the stores and never sent as is
+    Set(SetMembers),
+    /// Members and scores of ZADD, only ever held by the stores and never sent as is
+    SortedSet(SortedSet),
+    /// Entries of XADD, only ever held by the stores and never sent as is
+    Stream(Stream),
+    /// Replies sent one after the other, as SUBSCRIBE gives one per channel. Never held
+    /// by the stores
+    Replies(Vec<RedisValue>),
+    /// Field value pairs, a flat array of them in RESP2
+    Map(Vec<(RedisValue, RedisValue)>),
+    /// Floating point number as its text, e.g. from `format_score`. A bulk string in RESP2
+    Double(Bytes),
+    /// An integer 1 or 0 in RESP2
+    Boolean(bool),
+    /// Integer too large for an i64 as its digits, a bulk string in RESP2
+    BigNumber(Bytes),
+    /// Out of band data such as pub/sub messages, an array in RESP2
+    Push(Vec<RedisValue>),
 }
 
 impl RedisValue {
-    fn from_token(tok: RESPRaw, buf: &Bytes) -> RedisValue {
+    /// Name of the type of a stored value, as Redis' TYPE reports it
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RedisValue::Json(_) => "ReJSON-RL",
+            RedisValue::TimeSeries(_) => "TSDB-TYPE",
+            RedisValue::Bloom(_) => "MBbloom--",
+            RedisValue::List(_) => "list",
+            RedisValue::Hash(_) => "hash",
+            RedisValue::Set(_) => "set",
+            RedisValue::SortedSet(_) => "zset",
+            RedisValue::Stream(_) => "stream",
+            _ => "string",
+        }
+    }
+
+    /// Encoding Redis would hold a stored value in, as OBJECT ENCODING reports it. Hashes,
+    /// sets and lists keep their own, sorted sets are measured against `limits` as their
+    /// storage doesn't change with their size
+    pub fn encoding(&self, limits: &EncodingLimits) -> &'static str {
+        match self {
+            RedisValue::Counter(_) => "int",
+            RedisValue::BulkString(data)
+                if data.len() <= 20
+                    && str::from_utf8(data).is_ok_and(|s| s.parse::<i64>().is_ok()) =>
+            {
+                "int"
+            }
+            RedisValue::BulkString(data) if data.len() <= MAX_EMBSTR_LEN => "embstr",
+            RedisValue::List(items) => items.encoding(),
+            RedisValue::Hash(fields) => fields.encoding(),
+            RedisValue::Set(members) => members.encoding(),
+            RedisValue::SortedSet(zset)
+                if limits.zset_fits(zset.len(), zset.iter().map(|(member, _)| &member[..])) =>
+            {
+                "listpack"
+            }
+            RedisValue::SortedSet(_) => "skiplist",
+            RedisValue::Stream(_) => "stream",
+            _ => "raw",
+        }
+    }
+
+    /// Contents of a stored string, whether held as is or as a counter. `None` for the
+    /// other types
+    pub fn as_string(&self) -> Option<Bytes> {
+        match self {
+            RedisValue::BulkString(data) => Some(data.clone()),
+            RedisValue::Counter(n) => Some(Bytes::from(n.to_string())),
+            _ => None,
+        }
+    }
+
+    pub fn from_token(tok: RESPRaw, buf: &Bytes) -> RedisValue {
         match tok {
-            RESPRaw::SimpleString(str) => RedisValue::SimpleString(str.as_bytes(&buf)),
-            RESPRaw::BulkString(bulk_str) => RedisValue::BulkString(bulk_str.as_bytes(&buf)),
+            RESPRaw::SimpleString(str) => RedisValue::SimpleString(str.as_bytes(buf)),
+            RESPRaw::SimpleError(err) => RedisValue::SimpleError(err.as_bytes(buf)),
+            RESPRaw::Integer(i) => RedisValue::Integer(i),
+            RESPRaw::BulkString(bulk_str) => RedisValue::BulkString(bulk_str.as_bytes(buf)),
+            RESPRaw::Unescaped(data) => RedisValue::BulkString(data),
             RESPRaw::NullBulkString(_) => RedisValue::NullBulkString,
+            RESPRaw::NullArray => RedisValue::NullArray,
             RESPRaw::Array(arr) => RedisValue::Array(
                 arr.into_iter()
                     .map(|m| RedisValue::from_token(m, buf))
@@ -44,82 +160,206 @@ impl RedisValue {
 }
 
 impl RedisConnectionHandler {
-    pub fn new(stream: TcpStream) -> Self {
+    /// Handler for a trusted peer, e.g. the master link of a replica
+    pub fn new(stream: impl AsyncStream + 'static) -> Self {
+        RedisConnectionHandler::with_limits(stream, ProtocolLimits::unbounded())
+    }
+
+    /// Handler for a client connection, enforcing the given protocol limits
+    pub fn with_limits(stream: impl AsyncStream + 'static, limits: ProtocolLimits) -> Self {
         Self {
-            stream,
+            stream: Box::new(stream),
             buffer: BytesMut::with_capacity(512),
+            parser: RespParser::default(),
+            ready: None,
+            output: BytesMut::new(),
+            limits,
+            written: 0,
         }
     }
 
-    fn _parse(&mut self, token: Option<RESPToken>) -> RESPResult {
-        token.map_or(Ok(None), |tok| {
-            let req_data = self.buffer.split_to(tok.1);
-            Ok(Some(RedisValue::from_token(tok.0, &req_data.freeze())))
-        })
+    fn _parse(&mut self, token: RESPToken) -> (RedisValue, usize) {
+        let req_data = self.buffer.split_to(token.1);
+        (RedisValue::from_token(token.0, &req_data.freeze()), token.1)
     }
 
+    /// Reads the `$<len>\r\n<payload>` RDB transfer of a full sync, across as many reads as needed.
+    /// Anything the master sends after the payload stays buffered for `read_and_parse`
     pub async fn read_rdb_file(&mut self) -> Result<Vec<u8>> {
-        // --- read stream data into the buffer
-        let bytes_read = self
-            .stream
-            .read_buf(&mut self.buffer)
-            .await
-            .expect("Failure reading from stream");
-        if bytes_read == 0 {
-            return Ok(vec![]);
+        let (res, _) = self.read_rdb_transfer().await?;
+
+        Ok(res)
+    }
+
+    /// Same as `read_rdb_file`, also telling whether the payload came in the diskless
+    /// `$EOF:<mark>\r\n<payload><mark>` form, streamed by a master that doesn't know its size
+    pub async fn read_rdb_transfer(&mut self) -> Result<(Vec<u8>, bool)> {
+        self.skip_newlines().await?;
+
+        // --- parse file size, once the whole header line is in
+        let (header, file_offset) = loop {
+            ensure!(self.buffer[0] == b'$', "Invalid format for FULLSYNC data");
+
+            if let Some((tok, file_offset)) = get_next_word(&self.buffer, 1) {
+                break (tok.as_slice(&self.buffer).to_vec(), file_offset);
+            }
+
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                bail!("Connection closed before receiving the RDB file header");
+            }
+        };
+        let _ = self.buffer.split_to(file_offset);
+
+        if let Some(mark) = header.strip_prefix(b"EOF:") {
+            ensure!(
+                mark.len() == RDB_EOF_MARK_LEN,
+                "Invalid EOF mark for diskless FULLSYNC data"
+            );
+            let res = self.read_until_mark(mark).await?;
+            return Ok((res, true));
         }
+        let file_size: usize = str::from_utf8(&header)?.parse()?;
 
-        // --- ensure correct format
-        ensure!(self.buffer[0] == b'$', "Invalid format for FULLSYNC data");
+        // --- keep reading until all data is present
+        while self.buffer.len() < file_size {
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                bail!(
+                    "Connection closed after {} of {} bytes of the RDB file",
+                    self.buffer.len(),
+                    file_size
+                );
+            }
+        }
+        let file_data = self.buffer.split_to(file_size).freeze();
 
-        // --- parse file size
-        let (tok, file_offset) = get_next_word(&self.buffer, 1).unwrap();
-        let raw_file_size = tok.as_slice(&self.buffer);
-        let file_size: usize = str::from_utf8(raw_file_size)?.parse()?;
-        let _ = self.buffer.split_to(file_offset).freeze();
+        Ok((file_data.to_vec(), false))
+    }
 
-        // --- ensure file size is correct/all data is present
-        ensure!(
-            self.buffer.len() == file_size,
-            "Expected RDB file for FULLSYNC to be {}, but got {}",
-            file_size,
-            self.buffer.len()
-        );
-        let file_data = self.buffer.split_to(file_size - 1).freeze();
+    /// Reads a payload of unknown size, terminated by `mark`
+    async fn read_until_mark(&mut self, mark: &[u8]) -> Result<Vec<u8>> {
+        let mut res = Vec::new();
+        loop {
+            if let Some(end) = self.buffer.windows(mark.len()).position(|w| w == mark) {
+                res.extend_from_slice(&self.buffer.split_to(end));
+                let _ = self.buffer.split_to(mark.len());
+                return Ok(res);
+            }
+            // --- everything but a possible start of the mark is payload
+            let complete = self.buffer.len().saturating_sub(mark.len() - 1);
+            res.extend_from_slice(&self.buffer.split_to(complete));
 
-        // --- flush out buffer
-        self.buffer.clear();
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                bail!(
+                    "Connection closed after {} bytes of the diskless RDB transfer",
+                    res.len() + self.buffer.len()
+                );
+            }
+        }
+    }
+
+    /// Drops the bare newlines a master sends to keep the link alive while it prepares a
+    /// full sync, waiting for the first byte of actual data
+    pub async fn skip_newlines(&mut self) -> Result<()> {
+        loop {
+            let newlines = self.buffer.iter().take_while(|b| **b == b'\n').count();
+            let _ = self.buffer.split_to(newlines);
+            if !self.buffer.is_empty() {
+                return Ok(());
+            }
 
-        Ok(file_data.to_vec())
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                bail!("Connection closed while waiting for the master");
+            }
+        }
     }
 
-    /// Reads from self.buffer and parses the message to a RedisValue
+    /// Reads from the stream until a complete frame is buffered and parses it to a RedisValue.
+    /// Returns `None` once the peer closes the connection
     pub async fn read_and_parse(&mut self) -> RESPResult {
-        let bytes_read = self
-            .stream
-            .read_buf(&mut self.buffer)
-            .await
-            .expect("Failure reading from stream");
-        if bytes_read == 0 {
-            return Ok(None);
+        let frame = self.read_frame().await?;
+
+        Ok(frame.map(|(value, _)| value))
+    }
+
+    /// Same as `read_and_parse`, also returning the number of bytes the frame took on the wire
+    pub async fn read_frame(&mut self) -> Result<Option<(RedisValue, usize)>> {
+        loop {
+            // --- a frame can already be buffered, left over from a previous read
+            let ready = match self.ready.take() {
+                Some(token) => Some(token),
+                None => self.parser.parse(&self.buffer, &self.limits)?,
+            };
+            if let Some(token) = ready {
+                return Ok(Some(self._parse(token)));
+            }
+
+            let bytes_read = self.stream.read_buf(&mut self.buffer).await?;
+            if bytes_read == 0 {
+                if !self.buffer.is_empty() {
+                    log::warn!("Connection closed with a partial frame buffered");
+                }
+                return Ok(None);
+            }
+
+            ensure!(
+                self.buffer.len() <= self.limits.max_query_buffer,
+                ProtocolError::QueryBufferLimit
+            );
+            log::trace!("{} bytes buffered", self.buffer.len());
+        }
+    }
+
+    /// Whether another complete request is already buffered, i.e. the client pipelines
+    pub fn has_buffered_frame(&mut self) -> bool {
+        if self.ready.is_none() {
+            // --- an invalid frame is left for `read_frame` to report
+            self.ready = self.parser.parse(&self.buffer, &self.limits).ok().flatten();
         }
 
-        log::info!("Parsing: {:?}", &self.buffer);
-        let token = tokenize(&self.buffer, 0).expect("Failure parsing request");
-        self._parse(token)
+        self.ready.is_some()
+    }
+
+    /// Adds encoded data to the queued output, sent along with the next write
+    pub fn queue_raw(&mut self, data: &[u8]) {
+        self.output.extend_from_slice(data);
+    }
+
+    /// Bytes queued and not written yet
+    pub fn queued_len(&self) -> usize {
+        self.output.len()
+    }
+
+    /// Writes whatever output is queued
+    pub async fn write_queued(&mut self) -> Result<usize> {
+        let data = self.output.split();
+        self.stream.write_all(&data).await?;
+        self.written += data.len();
+
+        Ok(data.len())
     }
 
     pub async fn write(&mut self, response: RedisValue) -> Result<usize> {
         let serialized_data = response.serialize()?;
-        let bytes = self.stream.write(serialized_data.as_bytes()).await?;
 
-        Ok(bytes)
+        self.write_raw(&serialized_data).await
     }
 
+    /// Writes data right away, after any queued output so replies keep their order
     pub async fn write_raw(&mut self, data: &[u8]) -> Result<usize> {
-        let bytes = self.stream.write(data).await?;
+        if self.output.is_empty() {
+            self.stream.write_all(data).await?;
+            self.written += data.len();
+            return Ok(data.len());
+        }
+
+        self.queue_raw(data);
+
+        self.write_queued().await
+    }
 
-        Ok(bytes)
+    /// Bytes written to the stream since the last call, for the traffic counters
+    pub fn take_written(&mut self) -> usize {
+        std::mem::take(&mut self.written)
     }
 
     pub async fn flush(&mut self) -> Result<()> {
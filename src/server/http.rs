@@ -0,0 +1,311 @@
+use core::str;
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use super::{
+    commands::{execute, CommandContext},
+    handler::RedisValue,
+    server::RedisServer,
+    session::Session,
+};
+
+/// Longest request line or header
+const MAX_LINE_LEN: usize = 8192;
+const MAX_HEADERS: usize = 64;
+/// Keys live under this path, e.g. `/keys/user%3A1`
+const KEYS_PATH: &str = "/keys/";
+
+/// What the gateway needs from an HTTP/1.1 request
+#[derive(Debug, Default)]
+struct HttpRequest {
+    method: String,
+    target: String,
+    content_length: Option<usize>,
+    chunked: bool,
+    /// `Connection: close`, or an HTTP/1.0 client not asking for keep-alive
+    close: bool,
+}
+
+struct HttpResponse {
+    status: &'static str,
+    body: Bytes,
+}
+impl HttpResponse {
+    fn new(status: &'static str, body: impl Into<Bytes>) -> Self {
+        Self {
+            status,
+            body: body.into(),
+        }
+    }
+
+    fn serialize(&self, close: bool) -> Vec<u8> {
+        let head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n{}\r\n",
+            self.status,
+            self.body.len(),
+            if close { "Connection: close\r\n" } else { "" }
+        );
+
+        [head.as_bytes(), &self.body].concat()
+    }
+}
+
+/// Reads a line up to "\n", `None` once the client is gone or the line is too long
+async fn read_line(reader: &mut (impl AsyncBufReadExt + Unpin)) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    let read = reader
+        .take(MAX_LINE_LEN as u64)
+        .read_until(b'\n', &mut line)
+        .await?;
+    let Some(line) = line.strip_suffix(b"\n").filter(|_| read > 0) else {
+        return Ok(None);
+    };
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+    Ok(Some(String::from_utf8_lossy(line).into_owned()))
+}
+
+/// Reads the request line and headers, `Err` holds the response for a malformed request
+async fn read_request(
+    reader: &mut (impl AsyncBufReadExt + Unpin),
+) -> Result<Option<Result<HttpRequest, HttpResponse>>> {
+    let bad_request = || Ok(Some(Err(HttpResponse::new("400 Bad Request", ""))));
+
+    let Some(request_line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+    let [method, target, version] = request_line.split(' ').collect::<Vec<_>>()[..] else {
+        return bad_request();
+    };
+    let mut request = HttpRequest {
+        method: method.to_string(),
+        target: target.to_string(),
+        close: version == "HTTP/1.0",
+        ..Default::default()
+    };
+    if !version.starts_with("HTTP/1.") {
+        return bad_request();
+    }
+
+    for _ in 0..MAX_HEADERS {
+        let Some(header) = read_line(reader).await? else {
+            return Ok(None);
+        };
+        if header.is_empty() {
+            return Ok(Some(Ok(request)));
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return bad_request();
+        };
+        let value = value.trim();
+        match name.to_lowercase().as_str() {
+            "content-length" => match value.parse() {
+                Ok(len) => request.content_length = Some(len),
+                Err(_) => return bad_request(),
+            },
+            "transfer-encoding" => request.chunked = value.eq_ignore_ascii_case("chunked"),
+            "connection" if value.eq_ignore_ascii_case("close") => request.close = true,
+            "connection" if value.eq_ignore_ascii_case("keep-alive") => request.close = false,
+            _ => {}
+        }
+    }
+
+    Ok(Some(Err(HttpResponse::new(
+        "431 Request Header Fields Too Large",
+        "",
+    ))))
+}
+
+/// The gateway can't AUTH, what every request gets while the server requires a password
+fn unauthorized() -> HttpResponse {
+    HttpResponse::new("401 Unauthorized", "NOAUTH Authentication required.")
+}
+
+/// Decodes `%XX` escapes, `None` for an invalid one
+fn percent_decode(s: &str) -> Option<Bytes> {
+    let mut res = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                res.push(u8::from_str_radix(str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => res.push(b),
+        }
+    }
+
+    Some(Bytes::from(res))
+}
+
+impl RedisServer {
+    /// Accepts HTTP clients on the gateway listener, for as long as the server runs.
+    /// They count against `maxclients` and the per IP limits like RESP clients do
+    pub async fn run_http(self: Arc<Self>) {
+        let Some(listener) = self.http_listener.as_ref() else {
+            return;
+        };
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    let permit = match self.admit(&stream, addr) {
+                        Ok(permit) => permit,
+                        Err(refusal) => {
+                            let response =
+                                HttpResponse::new("503 Service Unavailable", refusal.reason());
+                            self.refuse(stream, response.serialize(true));
+                            continue;
+                        }
+                    };
+                    let server = Arc::clone(&self);
+                    tokio::spawn(async move {
+                        if let Err(e) = server.handle_http(stream, addr).await {
+                            log::error!("Failure serving HTTP client: {}", e);
+                        }
+                        drop(permit);
+                    });
+                }
+                Err(e) => log::error!("{}", e),
+            }
+        }
+    }
+
+    /// Serves an HTTP client for as long as it keeps the connection alive
+    async fn handle_http(&self, stream: TcpStream, addr: SocketAddr) -> Result<()> {
+        let mut session = self.new_session();
+        session.auth_pending = !self.acl_users.default_open();
+        session.addr = Some(addr);
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        loop {
+            let request = match read_request(&mut reader).await? {
+                Some(Ok(request)) => request,
+                Some(Err(response)) => {
+                    writer.write_all(&response.serialize(true)).await?;
+                    return Ok(());
+                }
+                None => return Ok(()),
+            };
+
+            // --- a body that isn't read leaves the connection out of sync, close it. Nothing
+            // is read before the client may send it, and the buffer only grows with what
+            // actually arrives rather than with what Content-Length announces
+            let body = match (request.chunked, request.content_length) {
+                _ if session.auth_pending => Err(unauthorized()),
+                (true, _) => Err(HttpResponse::new("501 Not Implemented", "")),
+                (false, Some(len)) if len > self.limits.max_bulk_len => {
+                    Err(HttpResponse::new("413 Content Too Large", ""))
+                }
+                (false, Some(len)) => {
+                    let mut body = Vec::new();
+                    (&mut reader)
+                        .take(len as u64)
+                        .read_to_end(&mut body)
+                        .await?;
+                    if body.len() < len {
+                        return Ok(());
+                    }
+                    Ok(Bytes::from(body))
+                }
+                (false, None) => Ok(Bytes::new()),
+            };
+            let (response, close) = match body {
+                Ok(body) => (
+                    self.http_response(&mut session, &request, body).await?,
+                    request.close,
+                ),
+                Err(response) => (response, true),
+            };
+
+            writer.write_all(&response.serialize(close)).await?;
+            if close {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Maps a request onto the dataset:
+    ///
+    /// - `GET /keys/{key}` replies with the value, 404 when missing
+    /// - `PUT /keys/{key}[?px=<ms>]` sets the value to the body, optionally with a TTL
+    /// - `DELETE /keys/{key}` removes the key, 404 when missing
+    ///
+    /// Keys are percent-decoded. Error replies of the commands come back as 503 with the
+    /// error as the body. The gateway can't AUTH, every request gets a 401 while the
+    /// server requires a password, before its body is read
+    async fn http_response(
+        &self,
+        session: &mut Session,
+        request: &HttpRequest,
+        body: Bytes,
+    ) -> Result<HttpResponse> {
+        let (path, query) = request
+            .target
+            .split_once('?')
+            .unwrap_or((&request.target, ""));
+        let Some(key) = path
+            .strip_prefix(KEYS_PATH)
+            .filter(|key| !key.is_empty())
+            .and_then(percent_decode)
+        else {
+            return Ok(HttpResponse::new("404 Not Found", ""));
+        };
+
+        if session.auth_pending {
+            return Ok(unauthorized());
+        }
+
+        let res = match request.method.as_str() {
+            "GET" => match self.http_execute(session, "GET", vec![key]).await? {
+                RedisValue::BulkString(value) => HttpResponse::new("200 OK", value),
+                RedisValue::SimpleError(e) => HttpResponse::new("503 Service Unavailable", e),
+                _ => HttpResponse::new("404 Not Found", ""),
+            },
+            "PUT" => {
+                let mut args = vec![key, body];
+                for param in query.split('&').filter(|param| !param.is_empty()) {
+                    match param.split_once('=') {
+                        Some(("px", ms)) if ms.parse::<u64>().is_ok() => {
+                            args.extend([Bytes::from_static(b"PX"), Bytes::from(ms.to_string())])
+                        }
+                        _ => return Ok(HttpResponse::new("400 Bad Request", "")),
+                    }
+                }
+                match self.http_execute(session, "SET", args).await? {
+                    RedisValue::SimpleError(e) => HttpResponse::new("503 Service Unavailable", e),
+                    _ => HttpResponse::new("204 No Content", ""),
+                }
+            }
+            "DELETE" => match self.http_execute(session, "DEL", vec![key]).await? {
+                RedisValue::Integer(0) => HttpResponse::new("404 Not Found", ""),
+                RedisValue::SimpleError(e) => HttpResponse::new("503 Service Unavailable", e),
+                _ => HttpResponse::new("204 No Content", ""),
+            },
+            _ => HttpResponse::new("405 Method Not Allowed", ""),
+        };
+
+        Ok(res)
+    }
+
+    async fn http_execute(
+        &self,
+        session: &mut Session,
+        cmd: &str,
+        args: Vec<Bytes>,
+    ) -> Result<RedisValue> {
+        let mut ctx = CommandContext {
+            args: &args,
+            server: self,
+            session,
+        };
+
+        execute(cmd, &mut ctx).await
+    }
+}
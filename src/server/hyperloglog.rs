@@ -0,0 +1,209 @@
+/// Registers of a HyperLogLog, 2^14 of them as in Redis
+pub const REGISTERS: usize = 1 << INDEX_BITS;
+
+const INDEX_BITS: u32 = 14;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+/// Magic, encoding, 3 unused bytes and the cached cardinality
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * REGISTER_BITS).div_ceil(8);
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+/// Bits of the hash left once the register index is taken
+const Q: usize = 64 - INDEX_BITS as usize;
+
+/// Approximate set cardinality counter of PFADD, kept in a string the way Redis keeps it
+/// so it replicates, persists and GETs as one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Box<[u8; REGISTERS]>,
+}
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: Box::new([0; REGISTERS]),
+        }
+    }
+}
+impl HyperLogLog {
+    /// Reads a string in either of Redis' encodings, `None` when it isn't a HyperLogLog
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != b"HYLL" {
+            return None;
+        }
+        let mut res = Self::default();
+        let data = &bytes[HEADER_LEN..];
+        match bytes[4] {
+            DENSE if bytes.len() == DENSE_LEN => {
+                for (index, register) in res.registers.iter_mut().enumerate() {
+                    *register = dense_get(data, index);
+                }
+            }
+            SPARSE => {
+                // --- runs of zeros, ZERO on one byte, XZERO on two, and VAL runs of a value
+                let mut index = 0;
+                let mut pos = 0;
+                while pos < data.len() {
+                    let opcode = data[pos];
+                    let (value, run) = match opcode >> 6 {
+                        0b00 => (0, (opcode & 0x3f) as usize + 1),
+                        0b01 => {
+                            let low = *data.get(pos + 1)? as usize;
+                            pos += 1;
+                            (0, (((opcode & 0x3f) as usize) << 8 | low) + 1)
+                        }
+                        _ => (((opcode >> 2) & 0x1f) + 1, (opcode & 0x3) as usize + 1),
+                    };
+                    pos += 1;
+                    let registers = res.registers.get_mut(index..index + run)?;
+                    registers.fill(value);
+                    index += run;
+                }
+                if index != REGISTERS {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+
+        Some(res)
+    }
+
+    /// The dense encoding, its cached cardinality marked as stale
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = vec![0; DENSE_LEN];
+        res[..4].copy_from_slice(b"HYLL");
+        res[4] = DENSE;
+        res[HEADER_LEN - 1] = 0x80;
+        let data = &mut res[HEADER_LEN..];
+        for (index, register) in self.registers.iter().enumerate() {
+            dense_set(data, index, *register);
+        }
+
+        res
+    }
+
+    /// Adds an element, returning whether a register changed and with it the estimate
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let hash = murmur_hash64a(element, 0xadc8_3b19);
+        let index = (hash & (REGISTERS as u64 - 1)) as usize;
+        // --- the run of zeros is counted in the bits left, one always set ends it
+        let rest = (hash >> INDEX_BITS) | (1 << Q);
+        let count = (rest.trailing_zeros() + 1) as u8;
+        if count <= self.registers[index] {
+            return false;
+        }
+        self.registers[index] = count;
+
+        true
+    }
+
+    /// Takes in another one, the union of both then being counted
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, other) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// Estimated number of distinct elements added, with Redis' estimator
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let mut histogram = [0_u32; Q + 2];
+        for register in self.registers.iter() {
+            histogram[(*register as usize).min(Q + 1)] += 1;
+        }
+        let mut z = m * tau((m - histogram[Q + 1] as f64) / m);
+        for count in histogram[1..=Q].iter().rev() {
+            z += *count as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+
+        (0.721_347_520_444_481_7 * m * m / z).round() as u64
+    }
+}
+
+fn dense_get(data: &[u8], index: usize) -> u8 {
+    let bit = index * REGISTER_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let low = data[byte] >> shift;
+    let high = data
+        .get(byte + 1)
+        .map_or(0, |next| next.checked_shl(8 - shift as u32).unwrap_or(0));
+
+    (low | high) & REGISTER_MAX
+}
+
+fn dense_set(data: &mut [u8], index: usize, value: u8) {
+    let bit = index * REGISTER_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    data[byte] &= !(REGISTER_MAX << shift);
+    data[byte] |= value << shift;
+    if shift > 8 - REGISTER_BITS {
+        let spill = 8 - shift as u32;
+        data[byte + 1] &= !(REGISTER_MAX >> spill);
+        data[byte + 1] |= value >> spill;
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
+
+/// MurmurHash64A, the hash Redis picks registers with
+fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, byte) in rest.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+
+    h
+}
@@ -0,0 +1,278 @@
+use anyhow::{bail, ensure, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    handler::RedisValue,
+    rdb,
+    server::Dataset,
+    zset::{format_score, parse_score, SortedSet},
+};
+
+/// Version of the JSON dataset format, bumped on incompatible changes
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The dataset as JSON, for inspection, diffing and test fixtures:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "keys": [
+///     { "key": "greeting", "type": "string", "value": "hello", "expires_at": 1735689600000 },
+///     { "key": { "hex": "ff00" }, "type": "string", "value": "raw" },
+///     { "key": "queue", "type": "list", "value": ["a", "b"] },
+///     { "key": "user", "type": "hash", "value": [["name", "ada"]] },
+///     { "key": "tags", "type": "set", "value": ["x", "y"] },
+///     { "key": "board", "type": "zset", "value": [["ada", "1.5"], ["bob", "inf"]] },
+///     { "key": "greeting", "type": "string", "value": "hi", "db": 3 }
+///   ]
+/// }
+/// ```
+///
+/// Keys come sorted by database then name so dumps of the same data are identical, as
+/// do set members and hash fields. Keys, values and elements are JSON strings when they
+/// are valid UTF-8, `{"hex": ...}` objects otherwise. `expires_at` is a unix time in
+/// milliseconds, left out for keys without a TTL, as `db` is for keys of database 0.
+/// JSON documents have the type "json" and their text as value. Scores are text, as
+/// replies give them, so infinities fit. Streams, time series and Bloom filters
+/// ("stream", "timeseries" and "bloom") have their RDB encoding as value, in hex
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonDataset {
+    version: u32,
+    keys: Vec<JsonEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonEntry {
+    key: JsonBytes,
+    #[serde(rename = "type")]
+    value_type: String,
+    value: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "is_first_database")]
+    db: usize,
+}
+
+fn is_first_database(db: &usize) -> bool {
+    *db == 0
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum JsonBytes {
+    Text(String),
+    Binary { hex: String },
+}
+impl JsonBytes {
+    fn encode(data: &[u8]) -> Self {
+        match std::str::from_utf8(data) {
+            Ok(text) => Self::Text(text.to_string()),
+            Err(_) => Self::Binary {
+                hex: data.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            },
+        }
+    }
+
+    fn decode(self) -> Result<Bytes> {
+        let hex = match self {
+            Self::Text(text) => return Ok(Bytes::from(text)),
+            Self::Binary { hex } => hex,
+        };
+        ensure!(
+            hex.len() % 2 == 0 && hex.is_ascii(),
+            "Invalid hex string: '{}'",
+            hex
+        );
+        let res = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Bytes::from(res))
+    }
+}
+
+/// Converts the dataset to its JSON representation, see `JsonDataset`
+pub fn dump(dataset: &Dataset) -> Result<String> {
+    let mut keys = dataset
+        .iter()
+        .flat_map(|(db, contents)| {
+            contents.iter().map(move |(key, value, expires_at)| {
+                let (value_type, value) = encode_value(value)?;
+                Ok(JsonEntry {
+                    key: JsonBytes::encode(key),
+                    value_type: value_type.to_string(),
+                    value,
+                    expires_at,
+                    db: *db,
+                })
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    keys.sort_by(|a, b| (a.db, json_key_order(&a.key)).cmp(&(b.db, json_key_order(&b.key))));
+
+    let dataset = JsonDataset {
+        version: FORMAT_VERSION,
+        keys,
+    };
+    let res = serde_json::to_string_pretty(&dataset)?;
+
+    Ok(res)
+}
+
+fn json_key_order(key: &JsonBytes) -> (&str, bool) {
+    match key {
+        JsonBytes::Text(text) => (text, false),
+        JsonBytes::Binary { hex } => (hex, true),
+    }
+}
+
+/// Reads a dataset back from JSON, skipping keys that expired before `now` like loading
+/// an RDB file does
+pub fn load(json: &str, now: u64) -> Result<Dataset> {
+    let dataset: JsonDataset = serde_json::from_str(json)?;
+    ensure!(
+        dataset.version == FORMAT_VERSION,
+        "Unsupported JSON dataset version {}",
+        dataset.version
+    );
+
+    let mut res = Dataset::new();
+    for entry in dataset.keys {
+        if entry.expires_at.is_some_and(|expires_at| expires_at < now) {
+            continue;
+        }
+        let key = entry.key.decode()?;
+        let value = decode_value(&entry.value_type, entry.value)?;
+        let contents = res.entry(entry.db).or_default();
+        contents.insert(key, value, entry.expires_at);
+    }
+    res.retain(|_, contents| !contents.is_empty());
+
+    Ok(res)
+}
+
+/// The type name and JSON representation of a stored value
+fn encode_value(value: &RedisValue) -> Result<(&'static str, serde_json::Value)> {
+    let res = match value {
+        RedisValue::Json(document) => ("json", json_value(JsonBytes::encode(document))?),
+        RedisValue::List(items) => (
+            "list",
+            json_value(
+                items
+                    .iter()
+                    .map(|item| JsonBytes::encode(item))
+                    .collect::<Vec<_>>(),
+            )?,
+        ),
+        RedisValue::Hash(fields) => {
+            let mut fields = fields.iter().collect::<Vec<_>>();
+            fields.sort();
+            let fields = fields
+                .into_iter()
+                .map(|(field, value)| (JsonBytes::encode(field), JsonBytes::encode(value)))
+                .collect::<Vec<_>>();
+            ("hash", json_value(fields)?)
+        }
+        RedisValue::Set(members) => {
+            let mut members = members.iter().collect::<Vec<_>>();
+            members.sort();
+            let members = members
+                .iter()
+                .map(|member| JsonBytes::encode(member))
+                .collect::<Vec<_>>();
+            ("set", json_value(members)?)
+        }
+        RedisValue::SortedSet(zset) => {
+            let members = zset
+                .iter()
+                .map(|(member, score)| {
+                    (
+                        JsonBytes::encode(member),
+                        JsonBytes::encode(&format_score(score)),
+                    )
+                })
+                .collect::<Vec<_>>();
+            ("zset", json_value(members)?)
+        }
+        RedisValue::Stream(_) => ("stream", json_value(rdb_value(value)?)?),
+        RedisValue::TimeSeries(_) => ("timeseries", json_value(rdb_value(value)?)?),
+        RedisValue::Bloom(_) => ("bloom", json_value(rdb_value(value)?)?),
+        other => match other.as_string() {
+            Some(value) => ("string", json_value(JsonBytes::encode(&value))?),
+            None => bail!("Values of type {} can't be exported", other.type_name()),
+        },
+    };
+
+    Ok(res)
+}
+
+/// Reads back a value `encode_value` wrote
+fn decode_value(value_type: &str, value: serde_json::Value) -> Result<RedisValue> {
+    let res = match value_type {
+        "string" => RedisValue::BulkString(serde_json::from_value::<JsonBytes>(value)?.decode()?),
+        "json" => RedisValue::Json(serde_json::from_value::<JsonBytes>(value)?.decode()?),
+        "list" => RedisValue::List(
+            serde_json::from_value::<Vec<JsonBytes>>(value)?
+                .into_iter()
+                .map(JsonBytes::decode)
+                .collect::<Result<_>>()?,
+        ),
+        "hash" => RedisValue::Hash(
+            serde_json::from_value::<Vec<(JsonBytes, JsonBytes)>>(value)?
+                .into_iter()
+                .map(|(field, value)| Ok((field.decode()?, value.decode()?)))
+                .collect::<Result<_>>()?,
+        ),
+        "set" => RedisValue::Set(
+            serde_json::from_value::<Vec<JsonBytes>>(value)?
+                .into_iter()
+                .map(JsonBytes::decode)
+                .collect::<Result<_>>()?,
+        ),
+        "zset" => {
+            let mut zset = SortedSet::default();
+            for (member, score) in serde_json::from_value::<Vec<(JsonBytes, JsonBytes)>>(value)? {
+                let score = score.decode()?;
+                let Some(score) = parse_score(&score) else {
+                    bail!("Invalid score '{}'", String::from_utf8_lossy(&score));
+                };
+                zset.insert(member.decode()?, score);
+            }
+            RedisValue::SortedSet(zset)
+        }
+        "stream" | "timeseries" | "bloom" => {
+            let data = serde_json::from_value::<JsonBytes>(value)?.decode()?;
+            let value = rdb::decode_value(&data)?;
+            let expected = match value {
+                RedisValue::Stream(_) => "stream",
+                RedisValue::TimeSeries(_) => "timeseries",
+                RedisValue::Bloom(_) => "bloom",
+                _ => "",
+            };
+            ensure!(
+                expected == value_type,
+                "Value doesn't hold a {}",
+                value_type
+            );
+            value
+        }
+        value_type => bail!("Unsupported value type '{}'", value_type),
+    };
+
+    Ok(res)
+}
+
+/// RDB encoding of a value, always in hex as it's binary
+fn rdb_value(value: &RedisValue) -> Result<JsonBytes> {
+    let data = rdb::encode_value(value)?;
+
+    Ok(JsonBytes::Binary {
+        hex: data.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    })
+}
+
+fn json_value(value: impl Serialize) -> Result<serde_json::Value> {
+    Ok(serde_json::to_value(value)?)
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+use super::handler::RedisValue;
+
+/// Keys of a database with their values, along with the order SCAN walks them in. Reads
+/// go straight to the map, writes through here so both stay in step. Cloning is cheap,
+/// the two share their structure with the original until written to
+#[derive(Clone, Debug, Default)]
+pub struct Keyspace {
+    values: im::HashMap<RedisValue, RedisValue>,
+    /// keys by `scan_order`, more than one only when their positions collide
+    scan_index: im::OrdMap<u64, Vec<RedisValue>>,
+}
+impl Deref for Keyspace {
+    type Target = im::HashMap<RedisValue, RedisValue>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.values
+    }
+}
+/// Equal when they hold the same keys and values, the SCAN order following from the keys
+impl PartialEq for Keyspace {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+impl FromIterator<(RedisValue, RedisValue)> for Keyspace {
+    fn from_iter<I: IntoIterator<Item = (RedisValue, RedisValue)>>(iter: I) -> Self {
+        let mut res = Self::default();
+        for (key, value) in iter {
+            res.insert(key, value);
+        }
+
+        res
+    }
+}
+impl IntoIterator for Keyspace {
+    type Item = (RedisValue, RedisValue);
+    type IntoIter = im::hashmap::ConsumingIter<(RedisValue, RedisValue)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.into_iter()
+    }
+}
+impl<'a> IntoIterator for &'a Keyspace {
+    type Item = (&'a RedisValue, &'a RedisValue);
+    type IntoIter = im::hashmap::Iter<'a, RedisValue, RedisValue>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.iter()
+    }
+}
+impl Keyspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: RedisValue, value: RedisValue) -> Option<RedisValue> {
+        let previous = self.values.insert(key.clone(), value);
+        if previous.is_none() {
+            self.scan_index
+                .entry(scan_order(&key))
+                .or_default()
+                .push(key);
+        }
+
+        previous
+    }
+
+    pub fn remove(&mut self, key: &RedisValue) -> Option<RedisValue> {
+        self.remove_with_key(key).map(|(_, value)| value)
+    }
+
+    /// Removes a key, giving back the key as stored along with its value
+    pub fn remove_with_key(&mut self, key: &RedisValue) -> Option<(RedisValue, RedisValue)> {
+        let res = self.values.remove_with_key(key)?;
+        let order = scan_order(key);
+        if let Some(keys) = self.scan_index.get_mut(&order) {
+            keys.retain(|known| known != key);
+            if keys.is_empty() {
+                self.scan_index.remove(&order);
+            }
+        }
+
+        Some(res)
+    }
+
+    /// The value of a key to change in place, the key itself staying
+    pub fn get_mut(&mut self, key: &RedisValue) -> Option<&mut RedisValue> {
+        self.values.get_mut(key)
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.scan_index.clear();
+    }
+
+    /// Keys from position `cursor` of the SCAN order on, at least `count` of them unless
+    /// the keyspace runs out first. The keys sharing the position of the last one come
+    /// along so the next call doesn't skip them. Also gives the cursor to go on from, 0
+    /// once the iteration is over
+    pub fn scan(&self, cursor: u64, count: usize) -> (Vec<&RedisValue>, u64) {
+        let mut res = vec![];
+        for (order, keys) in self.scan_index.range(cursor..) {
+            res.extend(keys);
+            if res.len() >= count {
+                return (res, order.checked_add(1).unwrap_or_default());
+            }
+        }
+
+        (res, 0)
+    }
+}
+
+/// Position of a key in a SCAN iteration, the same for as long as the server runs
+fn scan_order(key: &RedisValue) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+
+    hasher.finish()
+}
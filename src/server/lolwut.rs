@@ -0,0 +1,158 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+/// Georg Nees' Schotter, as LOLWUT draws it: rows of squares getting more and more
+/// scattered and rotated from the top down
+pub struct Schotter {
+    /// terminal columns the drawing takes, two canvas pixels each
+    pub cols: usize,
+    pub squares_per_row: usize,
+    pub squares_per_col: usize,
+}
+impl Default for Schotter {
+    fn default() -> Self {
+        Self {
+            cols: 66,
+            squares_per_row: 8,
+            squares_per_col: 12,
+        }
+    }
+}
+impl Schotter {
+    /// The drawing in braille characters, one line of text per four rows of pixels
+    pub fn render(&self) -> String {
+        let width = self.cols * 2;
+        let padding = if width > 4 { 2 } else { 0 };
+        let side = (width - padding * 2) as f64 / self.squares_per_row as f64;
+        let height = (side * self.squares_per_col as f64) as usize + padding * 2;
+        let mut canvas = Canvas::new(width, height);
+
+        let mut rng = rand::thread_rng();
+        for y in 0..self.squares_per_col {
+            for x in 0..self.squares_per_row {
+                let mut cx = x as f64 * side + side / 2.0 + padding as f64;
+                let mut cy = y as f64 * side + side / 2.0 + padding as f64;
+                let mut angle = 0.0;
+                // --- the first two rows stay in place
+                if y > 1 {
+                    let disorder = y as f64 / self.squares_per_col as f64;
+                    let mut jitter = || {
+                        let amount = rng.gen::<f64>() * disorder;
+                        match rng.gen::<bool>() {
+                            true => -amount,
+                            false => amount,
+                        }
+                    };
+                    angle = jitter();
+                    cx += jitter() * side / 3.0;
+                    cy += jitter() * side / 3.0;
+                }
+                canvas.square(cx, cy, side, angle);
+            }
+        }
+
+        canvas.to_braille()
+    }
+}
+
+/// Pixels, on or off
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixels[y * self.width + x]
+    }
+
+    fn set(&mut self, x: i64, y: i64) {
+        if (0..self.width as i64).contains(&x) && (0..self.height as i64).contains(&y) {
+            self.pixels[y as usize * self.width + x as usize] = true;
+        }
+    }
+
+    /// Bresenham's line between two pixels
+    fn line(&mut self, (mut x0, mut y0): (i64, i64), (x1, y1): (i64, i64)) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let mut error = dx + dy;
+        loop {
+            self.set(x0, y0);
+            if (x0, y0) == (x1, y1) {
+                break;
+            }
+            // --- both steps are decided on the error before either moves
+            let doubled = 2 * error;
+            if doubled >= dy {
+                if x0 == x1 {
+                    break;
+                }
+                error += dy;
+                x0 += sx;
+            }
+            if doubled <= dx {
+                if y0 == y1 {
+                    break;
+                }
+                error += dx;
+                y0 += sy;
+            }
+        }
+    }
+
+    /// Outline of a square centered on (x, y), turned by `angle` radians
+    fn square(&mut self, x: f64, y: f64, side: f64, angle: f64) {
+        let radius = (side / 2.0_f64.sqrt()).round();
+        let corners = (0..4)
+            .map(|corner| {
+                let k = PI / 4.0 + angle + corner as f64 * PI / 2.0;
+                (
+                    (k.sin() * radius + x).round() as i64,
+                    (k.cos() * radius + y).round() as i64,
+                )
+            })
+            .collect::<Vec<_>>();
+        for corner in 0..4 {
+            self.line(corners[corner], corners[(corner + 1) % 4]);
+        }
+    }
+
+    /// Each 2x4 block of pixels as the braille character with those dots raised
+    fn to_braille(&self) -> String {
+        // --- dot bits by (x, y) in the block, in Unicode's braille order
+        const DOTS: [(usize, usize, u32); 8] = [
+            (0, 0, 0x01),
+            (0, 1, 0x02),
+            (0, 2, 0x04),
+            (1, 0, 0x08),
+            (1, 1, 0x10),
+            (1, 2, 0x20),
+            (0, 3, 0x40),
+            (1, 3, 0x80),
+        ];
+
+        let mut res = String::new();
+        for y in (0..self.height).step_by(4) {
+            for x in (0..self.width).step_by(2) {
+                let dots = DOTS
+                    .iter()
+                    .filter(|(dx, dy, _)| self.get(x + dx, y + dy))
+                    .fold(0, |dots, (_, _, bit)| dots | bit);
+                res.push(char::from_u32(0x2800 + dots).unwrap_or(' '));
+            }
+            res.push('\n');
+        }
+
+        res
+    }
+}
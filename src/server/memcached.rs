@@ -0,0 +1,345 @@
+use core::str;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use super::{
+    commands::{execute, CommandContext},
+    handler::RedisValue,
+    server::RedisServer,
+    session::Session,
+};
+
+/// Longest key memcached accepts
+const MAX_KEY_LEN: usize = 250;
+/// Longest command line, anything past it is a client error
+const MAX_LINE_LEN: usize = 2048;
+/// Expiration times up to 30 days are relative, larger ones are unix timestamps
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+/// Reply to every request but `version` and `quit` while the server requires a password,
+/// the text protocol has no way to authenticate
+const UNAUTHENTICATED: &[u8] = b"CLIENT_ERROR unauthenticated\r\n";
+
+/// Request of the memcached text protocol
+#[derive(Debug, PartialEq, Eq)]
+enum MemcachedRequest {
+    Get(Vec<Bytes>),
+    Set {
+        key: Bytes,
+        flags: u32,
+        exptime: i64,
+        len: usize,
+        noreply: bool,
+    },
+    Delete {
+        key: Bytes,
+        noreply: bool,
+    },
+    Version,
+    Quit,
+}
+
+/// Parses a command line, without its trailing "\r\n". `Err` holds the reply for a line
+/// that isn't a valid request
+fn parse_request(line: &[u8]) -> Result<MemcachedRequest, &'static [u8]> {
+    const BAD_FORMAT: &[u8] = b"CLIENT_ERROR bad command line format\r\n";
+
+    let mut parts = line
+        .split(|b| *b == b' ')
+        .filter(|part| !part.is_empty())
+        .map(Bytes::copy_from_slice);
+    let Some(cmd) = parts.next() else {
+        return Err(b"ERROR\r\n");
+    };
+    let args = parts.collect::<Vec<_>>();
+    if args
+        .iter()
+        .any(|arg| arg.len() > MAX_KEY_LEN || arg.iter().any(u8::is_ascii_control))
+    {
+        return Err(BAD_FORMAT);
+    }
+    let number = |arg: &Bytes| -> Option<i64> { str::from_utf8(arg).ok()?.parse().ok() };
+    let noreply = |arg: Option<&Bytes>| -> Result<bool, &'static [u8]> {
+        match arg {
+            None => Ok(false),
+            Some(arg) if arg.as_ref() == b"noreply" => Ok(true),
+            Some(_) => Err(BAD_FORMAT),
+        }
+    };
+
+    let res = match (cmd.as_ref(), args.as_slice()) {
+        (b"get", keys) if !keys.is_empty() => MemcachedRequest::Get(keys.to_vec()),
+        (b"set", [key, flags, exptime, len, rest @ ..]) if rest.len() <= 1 => {
+            let (Some(flags), Some(exptime), Some(len)) = (
+                number(flags).and_then(|flags| u32::try_from(flags).ok()),
+                number(exptime),
+                number(len).and_then(|len| usize::try_from(len).ok()),
+            ) else {
+                return Err(BAD_FORMAT);
+            };
+            MemcachedRequest::Set {
+                key: key.clone(),
+                flags,
+                exptime,
+                len,
+                noreply: noreply(rest.first())?,
+            }
+        }
+        (b"delete", [key, rest @ ..]) if rest.len() <= 1 => MemcachedRequest::Delete {
+            key: key.clone(),
+            noreply: noreply(rest.first())?,
+        },
+        (b"version", []) => MemcachedRequest::Version,
+        (b"quit", []) => MemcachedRequest::Quit,
+        _ => return Err(b"ERROR\r\n"),
+    };
+
+    Ok(res)
+}
+
+/// Flags memcached clients stored with their items. They are kept along with the value
+/// they were stored with, an item a Redis client wrote since reads back with flags 0
+#[derive(Debug, Default)]
+pub struct ItemFlags(Mutex<HashMap<Bytes, (u32, Bytes)>>);
+impl ItemFlags {
+    fn set(&self, key: Bytes, flags: u32, value: Bytes) {
+        let mut items = self.0.lock().unwrap();
+        match flags {
+            0 => items.remove(&key),
+            flags => items.insert(key, (flags, value)),
+        };
+    }
+
+    fn get(&self, key: &Bytes, value: &Bytes) -> u32 {
+        match self.0.lock().unwrap().get(key) {
+            Some((flags, stored)) if stored == value => *flags,
+            _ => 0,
+        }
+    }
+
+    fn remove(&self, key: &Bytes) {
+        self.0.lock().unwrap().remove(key);
+    }
+}
+
+/// Unix time in ms a memcached expiration time stands for, `None` when it doesn't expire.
+/// Negative times expire the item right away
+fn expire_at(exptime: i64, now: u64) -> Option<u64> {
+    match exptime {
+        0 => None,
+        ..=-1 => Some(0),
+        1..=MAX_RELATIVE_EXPTIME => Some(now + exptime as u64 * 1000),
+        _ => Some(exptime as u64 * 1000),
+    }
+}
+
+impl RedisServer {
+    /// Accepts memcached clients on the second listener, for as long as the server runs.
+    /// They count against `maxclients` and the per IP limits like RESP clients do
+    pub async fn run_memcached(self: Arc<Self>) {
+        let Some(listener) = self.memcached_listener.as_ref() else {
+            return;
+        };
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    let permit = match self.admit(&stream, addr) {
+                        Ok(permit) => permit,
+                        Err(refusal) => {
+                            let message = format!("SERVER_ERROR {}\r\n", refusal.reason());
+                            self.refuse(stream, message.into_bytes());
+                            continue;
+                        }
+                    };
+                    let server = Arc::clone(&self);
+                    tokio::spawn(async move {
+                        if let Err(e) = server.handle_memcached(stream, addr).await {
+                            log::error!("Failure serving memcached client: {}", e);
+                        }
+                        drop(permit);
+                    });
+                }
+                Err(e) => log::error!("{}", e),
+            }
+        }
+    }
+
+    /// Serves a memcached client until it disconnects or sends `quit`. Requests run as
+    /// the equivalent Redis commands, on the same dataset
+    async fn handle_memcached(&self, stream: TcpStream, addr: SocketAddr) -> Result<()> {
+        let mut session = self.new_session();
+        session.auth_pending = !self.acl_users.default_open();
+        session.addr = Some(addr);
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = (&mut reader)
+                .take(MAX_LINE_LEN as u64)
+                .read_until(b'\n', &mut line)
+                .await?;
+            if read == 0 {
+                return Ok(());
+            }
+            let Some(line) = line.strip_suffix(b"\n") else {
+                writer.write_all(b"CLIENT_ERROR line too long\r\n").await?;
+                return Ok(());
+            };
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+            let request = match parse_request(line) {
+                Ok(request) => request,
+                Err(reply) => {
+                    writer.write_all(reply).await?;
+                    continue;
+                }
+            };
+            let (reply, noreply) = match request {
+                MemcachedRequest::Get(keys) => {
+                    (self.memcached_get(&mut session, keys).await?, false)
+                }
+                MemcachedRequest::Set {
+                    key,
+                    flags,
+                    exptime,
+                    len,
+                    noreply,
+                } => {
+                    if len > self.limits.max_bulk_len {
+                        writer
+                            .write_all(b"SERVER_ERROR object too large for cache\r\n")
+                            .await?;
+                        return Ok(());
+                    }
+                    // --- the data block, followed by its own "\r\n"
+                    let mut data = vec![0; len + 2];
+                    reader.read_exact(&mut data).await?;
+                    if !data.ends_with(b"\r\n") {
+                        writer.write_all(b"CLIENT_ERROR bad data chunk\r\n").await?;
+                        return Ok(());
+                    }
+                    data.truncate(len);
+                    let reply = self
+                        .memcached_set(&mut session, key, flags, Bytes::from(data), exptime)
+                        .await?;
+                    (reply, noreply)
+                }
+                MemcachedRequest::Delete { key, noreply } => {
+                    (self.memcached_delete(&mut session, key).await?, noreply)
+                }
+                MemcachedRequest::Version => (
+                    format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")).into_bytes(),
+                    false,
+                ),
+                MemcachedRequest::Quit => return Ok(()),
+            };
+            if !noreply {
+                writer.write_all(&reply).await?;
+            }
+        }
+    }
+
+    async fn memcached_get(&self, session: &mut Session, keys: Vec<Bytes>) -> Result<Vec<u8>> {
+        if session.auth_pending {
+            return Ok(UNAUTHENTICATED.to_vec());
+        }
+        let mut res = Vec::new();
+        for key in keys {
+            let args = [key.clone()];
+            let mut ctx = CommandContext {
+                args: &args,
+                server: self,
+                session,
+            };
+            if let RedisValue::BulkString(value) = execute("GET", &mut ctx).await? {
+                res.extend(
+                    format!(
+                        "VALUE {} {} {}\r\n",
+                        String::from_utf8_lossy(&key),
+                        self.memcached_flags.get(&key, &value),
+                        value.len()
+                    )
+                    .into_bytes(),
+                );
+                res.extend_from_slice(&value);
+                res.extend_from_slice(b"\r\n");
+            }
+        }
+        res.extend_from_slice(b"END\r\n");
+
+        Ok(res)
+    }
+
+    async fn memcached_set(
+        &self,
+        session: &mut Session,
+        key: Bytes,
+        flags: u32,
+        value: Bytes,
+        exptime: i64,
+    ) -> Result<Vec<u8>> {
+        if session.auth_pending {
+            return Ok(UNAUTHENTICATED.to_vec());
+        }
+        let mut args = vec![key.clone(), value.clone()];
+        if let Some(expire_at) = expire_at(exptime, self.clock.now()) {
+            args.extend([
+                Bytes::from_static(b"PXAT"),
+                Bytes::from(expire_at.to_string()),
+            ]);
+        }
+        let mut ctx = CommandContext {
+            args: &args,
+            server: self,
+            session,
+        };
+
+        let res = match execute("SET", &mut ctx).await? {
+            RedisValue::SimpleError(e) => {
+                format!("SERVER_ERROR {}\r\n", String::from_utf8_lossy(&e)).into_bytes()
+            }
+            _ => {
+                self.memcached_flags.set(key, flags, value);
+                b"STORED\r\n".to_vec()
+            }
+        };
+
+        Ok(res)
+    }
+
+    async fn memcached_delete(&self, session: &mut Session, key: Bytes) -> Result<Vec<u8>> {
+        if session.auth_pending {
+            return Ok(UNAUTHENTICATED.to_vec());
+        }
+        let args = [key.clone()];
+        let mut ctx = CommandContext {
+            args: &args,
+            server: self,
+            session,
+        };
+
+        let res = match execute("DEL", &mut ctx).await? {
+            RedisValue::SimpleError(e) => {
+                format!("SERVER_ERROR {}\r\n", String::from_utf8_lossy(&e)).into_bytes()
+            }
+            RedisValue::Integer(0) => b"NOT_FOUND\r\n".to_vec(),
+            _ => {
+                self.memcached_flags.remove(&key);
+                b"DELETED\r\n".to_vec()
+            }
+        };
+
+        Ok(res)
+    }
+}
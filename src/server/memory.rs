@@ -0,0 +1,267 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{bail, ensure, Result};
+use bytes::Bytes;
+
+use crate::alloc;
+
+use super::handler::RedisValue;
+
+/// Fixed cost charged per key on top of its payload, for the hash table slot and metadata
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Approximate footprint of the dataset, maintained as keys are written and removed.
+/// Stands in for the real memory usage when allocations aren't tracked
+#[derive(Debug, Default)]
+pub struct MemoryUsage {
+    used: AtomicUsize,
+    peak: AtomicUsize,
+}
+impl MemoryUsage {
+    /// What `maxmemory` is compared against: the bytes allocated by the process when the
+    /// tracking allocator is installed, the dataset estimate otherwise
+    pub fn used(&self) -> usize {
+        alloc::allocated().unwrap_or_else(|| self.dataset())
+    }
+
+    /// Highest `used` so far
+    pub fn peak(&self) -> usize {
+        alloc::peak().unwrap_or_else(|| self.peak.load(Ordering::Relaxed))
+    }
+
+    /// Estimated size of the keys and values alone
+    pub fn dataset(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn add_entry(&self, key: &RedisValue, value: &RedisValue) {
+        self.grow(entry_size(key, value));
+    }
+
+    pub fn remove_entry(&self, key: &RedisValue, value: &RedisValue) {
+        self.shrink(entry_size(key, value));
+    }
+
+    /// Accounts for a value that grew in place, e.g. a list pushed to
+    pub fn grow(&self, size: usize) {
+        let used = self.used.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak.fetch_max(used, Ordering::Relaxed);
+    }
+
+    /// Accounts for a value that shrank in place
+    pub fn shrink(&self, size: usize) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(size))
+            });
+    }
+}
+
+/// One database's share of the dataset estimate, kept alongside the server total it also
+/// feeds. What `maxmemory-db` quotas are compared against
+#[derive(Debug)]
+pub struct DatabaseMemory {
+    used: AtomicUsize,
+    total: Arc<MemoryUsage>,
+}
+impl DatabaseMemory {
+    pub fn new(total: Arc<MemoryUsage>) -> Self {
+        Self {
+            used: AtomicUsize::new(0),
+            total,
+        }
+    }
+
+    /// Estimated size of the keys and values of the database
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn add_entry(&self, key: &RedisValue, value: &RedisValue) {
+        self.grow(entry_size(key, value));
+    }
+
+    pub fn remove_entry(&self, key: &RedisValue, value: &RedisValue) {
+        self.shrink(entry_size(key, value));
+    }
+
+    /// Accounts for a value that grew in place, e.g. a list pushed to
+    pub fn grow(&self, size: usize) {
+        self.used.fetch_add(size, Ordering::Relaxed);
+        self.total.grow(size);
+    }
+
+    /// Accounts for a value that shrank in place
+    pub fn shrink(&self, size: usize) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(size))
+            });
+        self.total.shrink(size);
+    }
+
+    /// Exchanges the shares of two databases whose contents were swapped, the total
+    /// staying the same
+    pub fn swap(&self, other: &Self) {
+        let used = self.used.swap(other.used(), Ordering::Relaxed);
+        other.used.store(used, Ordering::Relaxed);
+    }
+}
+
+/// `maxmemory-db`, the bytes each database may hold on its own, databases left out having
+/// no quota. A database past its quota evicts its own keys, whatever the others hold
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DatabaseQuotas(BTreeMap<usize, usize>);
+impl FromStr for DatabaseQuotas {
+    type Err = anyhow::Error;
+
+    /// Parses "<db> <bytes> ...", a 0 quota being the same as none
+    fn from_str(s: &str) -> Result<Self> {
+        let parts = s.split_whitespace().collect::<Vec<_>>();
+        ensure!(
+            parts.len() % 2 == 0,
+            "Database quotas come in <db> <bytes> pairs"
+        );
+
+        let mut quotas = BTreeMap::new();
+        for pair in parts.chunks(2) {
+            let index = pair[0].parse()?;
+            match parse_memory_size(pair[1])? {
+                0 => quotas.remove(&index),
+                quota => quotas.insert(index, quota),
+            };
+        }
+
+        Ok(Self(quotas))
+    }
+}
+impl Display for DatabaseQuotas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pairs = self
+            .0
+            .iter()
+            .map(|(index, quota)| format!("{} {}", index, quota))
+            .collect::<Vec<_>>();
+
+        write!(f, "{}", pairs.join(" "))
+    }
+}
+impl DatabaseQuotas {
+    /// Bytes the database may hold, 0 when it has no quota
+    pub fn get(&self, index: usize) -> usize {
+        self.0.get(&index).copied().unwrap_or(0)
+    }
+}
+
+pub fn entry_size(key: &RedisValue, value: &RedisValue) -> usize {
+    ENTRY_OVERHEAD + value_size(key) + value_size(value)
+}
+
+fn value_size(value: &RedisValue) -> usize {
+    match value {
+        RedisValue::BulkString(b)
+        | RedisValue::SimpleString(b)
+        | RedisValue::SimpleError(b)
+        | RedisValue::Json(b) => b.len(),
+        RedisValue::Array(arr) | RedisValue::Replies(arr) | RedisValue::Push(arr) => {
+            arr.iter().map(|v| 16 + value_size(v)).sum()
+        }
+        RedisValue::Map(pairs) => pairs
+            .iter()
+            .map(|(key, value)| 32 + value_size(key) + value_size(value))
+            .sum(),
+        RedisValue::Double(n) | RedisValue::BigNumber(n) => n.len(),
+        RedisValue::Boolean(_) => 8,
+        RedisValue::NullBulkString
+        | RedisValue::NullArray
+        | RedisValue::Integer(_)
+        | RedisValue::Counter(_) => 8,
+        RedisValue::TimeSeries(series) => {
+            16 * series.samples().len()
+                + series
+                    .rules
+                    .iter()
+                    .map(|rule| 48 + rule.dest.len())
+                    .sum::<usize>()
+        }
+        RedisValue::Bloom(filter) => 32 * filter.layers().len() + filter.size(),
+        RedisValue::List(items) => items.iter().map(|item| list_item_size(item)).sum(),
+        RedisValue::Hash(fields) => fields
+            .iter()
+            .map(|(field, value)| hash_field_size(field, value))
+            .sum(),
+        RedisValue::Set(members) => members.iter().map(|member| set_member_size(&member)).sum(),
+        RedisValue::SortedSet(zset) => zset
+            .iter()
+            .map(|(member, _)| zset_member_size(member))
+            .sum(),
+        RedisValue::Stream(stream) => stream
+            .entries()
+            .map(|(_, fields)| stream_entry_size(fields))
+            .sum(),
+    }
+}
+
+/// Share of a list element in the size of its list
+pub fn list_item_size(item: &[u8]) -> usize {
+    16 + item.len()
+}
+
+/// Share of a hash field and its value in the size of their hash
+pub fn hash_field_size(field: &[u8], value: &[u8]) -> usize {
+    32 + field.len() + value.len()
+}
+
+/// Share of a set member in the size of its set
+pub fn set_member_size(member: &[u8]) -> usize {
+    16 + member.len()
+}
+
+/// Share of a stream entry in the size of its stream
+pub fn stream_entry_size(fields: &[(Bytes, Bytes)]) -> usize {
+    32 + fields
+        .iter()
+        .map(|(field, value)| 16 + field.len() + value.len())
+        .sum::<usize>()
+}
+
+/// Share of a sorted set member and its score in the size of their sorted set, the
+/// member being held both by name and in score order
+pub fn zset_member_size(member: &[u8]) -> usize {
+    48 + 2 * member.len()
+}
+
+/// Parses a memory amount the way redis.conf does: "1024", "100mb", "1gb", "512k"
+pub fn parse_memory_size(value: &str) -> Result<usize> {
+    let value = value.trim().to_lowercase();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => bail!("Invalid memory unit: '{}'", unit),
+    };
+    let Some(res) = number.parse::<usize>()?.checked_mul(multiplier) else {
+        bail!("Memory amount too large: '{}'", value);
+    };
+
+    Ok(res)
+}
@@ -1,4 +1,58 @@
+pub mod acl;
+pub mod aof;
+pub mod audit;
+pub mod bigkeys;
+pub mod bitmap;
+pub mod blocking;
+pub mod bloom;
+pub mod builder;
+pub mod clients;
+pub mod clock;
+pub mod cluster;
 pub mod commands;
+pub mod config;
+pub mod connlimit;
+pub mod cron;
+pub mod db;
+pub mod digest;
+pub mod document;
+pub mod encoding;
+pub mod events;
+pub mod eviction;
+pub mod expiry;
+pub mod glob;
 pub mod handler;
-mod serde;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod hyperloglog;
+pub mod json;
+pub mod keyspace;
+pub mod lolwut;
+#[cfg(feature = "memcached")]
+pub mod memcached;
+pub mod memory;
+pub mod monitor;
+pub mod net;
+pub mod output;
+pub mod persistence;
+pub mod plugins;
+pub mod pubsub;
+pub mod rdb;
+pub mod record;
+pub mod scripting;
+pub mod search;
+pub mod serde;
+#[allow(clippy::module_inception)]
 pub mod server;
+pub mod session;
+pub mod slowlog;
+pub mod snapshot;
+pub mod split;
+pub mod stats;
+pub mod store;
+pub mod stream;
+pub mod timeseries;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod watch;
+pub mod zset;
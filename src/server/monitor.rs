@@ -0,0 +1,62 @@
+use std::fmt::Write;
+
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+/// Lines buffered for a monitor that fell behind, older ones are skipped past that
+const MONITOR_BACKLOG: usize = 1024;
+
+/// Connections in MONITOR mode, each fed a line for every command the others run
+#[derive(Debug)]
+pub struct Monitors(broadcast::Sender<Bytes>);
+impl Default for Monitors {
+    fn default() -> Self {
+        Monitors(broadcast::channel(MONITOR_BACKLOG).0)
+    }
+}
+impl Monitors {
+    /// Lines of the commands run from now on
+    pub fn attach(&self) -> broadcast::Receiver<Bytes> {
+        self.0.subscribe()
+    }
+
+    /// Hands a command to the monitors as Redis formats it, e.g.
+    /// `1339518083.107412 [0 127.0.0.1:60866] "set" "foo" "bar"`. Nothing is formatted
+    /// while nobody monitors
+    pub fn feed(&self, now: u64, client: &str, cmd: &str, args: &[Bytes]) {
+        if self.0.receiver_count() == 0 {
+            return;
+        }
+        let mut line = format!("{}.{:06} [0 {}]", now / 1000, now % 1000 * 1000, client);
+        for arg in [cmd.as_bytes()]
+            .into_iter()
+            .chain(args.iter().map(|arg| &arg[..]))
+        {
+            line.push(' ');
+            quote(&mut line, arg);
+        }
+        // --- only fails when the last monitor just left
+        let _ = self.0.send(Bytes::from(line));
+    }
+}
+
+/// Appends the argument double quoted, escaped the way redis-cli reads it back
+fn quote(line: &mut String, arg: &[u8]) {
+    line.push('"');
+    for &b in arg {
+        match b {
+            b'\\' => line.push_str("\\\\"),
+            b'"' => line.push_str("\\\""),
+            b'\n' => line.push_str("\\n"),
+            b'\r' => line.push_str("\\r"),
+            b'\t' => line.push_str("\\t"),
+            0x07 => line.push_str("\\a"),
+            0x08 => line.push_str("\\b"),
+            b' '..=b'~' => line.push(b as char),
+            _ => {
+                let _ = write!(line, "\\x{:02x}", b);
+            }
+        }
+    }
+    line.push('"');
+}
@@ -0,0 +1,179 @@
+use std::{
+    future::poll_fn, io, net::SocketAddr, os::unix::fs::PermissionsExt, task::Poll, time::Duration,
+};
+
+use anyhow::{Context, Result};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+/// Settings applied to every client connection and to the replica's link to its master
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// seconds of idle time before keepalive probes go out, 0 disables them, `tcp-keepalive`
+    pub keepalive: u64,
+    /// disables Nagle's algorithm so small replies aren't held back
+    pub nodelay: bool,
+}
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            keepalive: 300,
+            nodelay: true,
+        }
+    }
+}
+impl SocketOptions {
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+
+        let socket = SockRef::from(stream);
+        if self.keepalive == 0 {
+            return socket.set_keepalive(false);
+        }
+        // --- same as Redis, probe every third of the idle time so a dead peer is
+        // --- dropped roughly twice the configured time after going silent
+        let idle = Duration::from_secs(self.keepalive);
+        let interval = Duration::from_secs((self.keepalive / 3).max(1));
+        let keepalive = TcpKeepalive::new().with_time(idle).with_interval(interval);
+        socket.set_tcp_keepalive(&keepalive)
+    }
+}
+
+/// Sockets clients connect to, TCP on every `bind` address and a unix socket with
+/// `unixsocket`
+#[derive(Debug, Default)]
+pub struct Listeners {
+    pub tcp: Vec<TcpListener>,
+    pub unix: Option<UnixListener>,
+    /// on `tls-port`, same addresses as `tcp`
+    #[cfg(feature = "tls")]
+    pub tls: Vec<TcpListener>,
+}
+
+/// Client connection just accepted
+pub enum Accepted {
+    Tcp(TcpStream, SocketAddr),
+    Unix(UnixStream),
+    /// TCP connection still to go through the TLS handshake
+    #[cfg(feature = "tls")]
+    Tls(TcpStream, SocketAddr),
+}
+
+impl Listeners {
+    /// Listens on the port of every address, the same one for all when the first got an
+    /// ephemeral port, and on the unix socket if any. A stale socket file is replaced
+    pub async fn bind(
+        addrs: &[String],
+        port: u16,
+        unixsocket: Option<&str>,
+        unixsocketperm: Option<u32>,
+    ) -> Result<Self> {
+        let mut res = Listeners {
+            tcp: bind_tcp(addrs, port).await?,
+            ..Default::default()
+        };
+        if let Some(path) = unixsocket {
+            let _ = std::fs::remove_file(path);
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("Failure binding unix socket {}", path))?;
+            if let Some(mode) = unixsocketperm {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+            }
+            res.unix = Some(listener);
+        }
+
+        Ok(res)
+    }
+
+    /// Listens for TLS connections on `port` of every address as well
+    #[cfg(feature = "tls")]
+    pub async fn with_tls(self, addrs: &[String], port: u16) -> Result<Self> {
+        let res = Listeners {
+            tls: bind_tcp(addrs, port).await?,
+            ..self
+        };
+
+        Ok(res)
+    }
+
+    /// Address of the first TCP listener
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.tcp
+            .first()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// Address of the first TLS listener
+    #[cfg(feature = "tls")]
+    pub fn tls_addr(&self) -> Option<SocketAddr> {
+        self.tls
+            .first()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        #[cfg(feature = "tls")]
+        if !self.tls.is_empty() {
+            return false;
+        }
+        self.tcp.is_empty() && self.unix.is_none()
+    }
+
+    /// Next connection on any of the listeners, never resolves without listeners
+    pub async fn accept(&self) -> io::Result<Accepted> {
+        poll_fn(|cx| {
+            for listener in &self.tcp {
+                if let Poll::Ready(accepted) = listener.poll_accept(cx) {
+                    return Poll::Ready(accepted.map(|(stream, addr)| Accepted::Tcp(stream, addr)));
+                }
+            }
+            if let Some(listener) = &self.unix {
+                if let Poll::Ready(accepted) = listener.poll_accept(cx) {
+                    return Poll::Ready(accepted.map(|(stream, _)| Accepted::Unix(stream)));
+                }
+            }
+            #[cfg(feature = "tls")]
+            for listener in &self.tls {
+                if let Poll::Ready(accepted) = listener.poll_accept(cx) {
+                    return Poll::Ready(accepted.map(|(stream, addr)| Accepted::Tls(stream, addr)));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Listens on the port of every address, the same one for all when the first got an
+/// ephemeral port
+async fn bind_tcp(addrs: &[String], port: u16) -> Result<Vec<TcpListener>> {
+    let mut res: Vec<TcpListener> = vec![];
+    for addr in addrs {
+        // --- "-" marks an address that may not be available, e.g. IPv6 on some hosts
+        let (optional, addr) = match addr.strip_prefix('-') {
+            Some(addr) => (true, addr),
+            None => (false, addr.as_str()),
+        };
+        let port = res
+            .first()
+            .and_then(|bound| bound.local_addr().ok())
+            .map_or(port, |bound| bound.port());
+        match TcpListener::bind((bind_host(addr), port)).await {
+            Ok(listener) => res.push(listener),
+            Err(e) if optional => log::warn!("Skipping bind address {}: {}", addr, e),
+            Err(e) => return Err(e).with_context(|| format!("Failure binding {}:{}", addr, port)),
+        }
+    }
+
+    Ok(res)
+}
+
+/// Host to bind for a `bind` address, with Redis' "*" and "::*" for every IPv4 and IPv6
+/// interface
+pub fn bind_host(addr: &str) -> &str {
+    match addr.trim_start_matches('-') {
+        "*" => "0.0.0.0",
+        "::*" => "::",
+        addr => addr,
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use anyhow::{bail, ensure, Result};
+
+use super::{
+    handler::{RedisConnectionHandler, RedisValue},
+    memory::parse_memory_size,
+    serde::Protocol,
+};
+
+/// Kinds of connections, each with its own `client-output-buffer-limit`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientClass {
+    Normal,
+    Replica,
+    Pubsub,
+}
+impl FromStr for ClientClass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let res = match s.to_lowercase().as_str() {
+            "normal" => Self::Normal,
+            "replica" | "slave" => Self::Replica,
+            "pubsub" => Self::Pubsub,
+            _ => bail!("Invalid client class: '{}'", s),
+        };
+
+        Ok(res)
+    }
+}
+impl ClientClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Replica => "replica",
+            Self::Pubsub => "pubsub",
+        }
+    }
+}
+
+/// Bounds on the output a connection may have pending, 0 disables a limit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutputBufferLimit {
+    /// bytes past which the client is dropped right away
+    pub hard: usize,
+    /// bytes the client may stay over for at most `soft_seconds`
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+/// `client-output-buffer-limit`, one limit per client class
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputBufferLimits {
+    pub normal: OutputBufferLimit,
+    pub replica: OutputBufferLimit,
+    pub pubsub: OutputBufferLimit,
+}
+impl Default for OutputBufferLimits {
+    fn default() -> Self {
+        Self {
+            normal: OutputBufferLimit::default(),
+            replica: OutputBufferLimit {
+                hard: 256 * 1024 * 1024,
+                soft: 64 * 1024 * 1024,
+                soft_seconds: 60,
+            },
+            pubsub: OutputBufferLimit {
+                hard: 32 * 1024 * 1024,
+                soft: 8 * 1024 * 1024,
+                soft_seconds: 60,
+            },
+        }
+    }
+}
+impl FromStr for OutputBufferLimits {
+    type Err = anyhow::Error;
+
+    /// Parses "<class> <hard> <soft> <soft seconds> ...", classes left out keep their
+    /// defaults
+    fn from_str(s: &str) -> Result<Self> {
+        let parts = s.split_whitespace().collect::<Vec<_>>();
+        ensure!(
+            parts.len() % 4 == 0,
+            "Output buffer limits come in <class> <hard> <soft> <soft seconds> groups"
+        );
+
+        let mut limits = Self::default();
+        for group in parts.chunks(4) {
+            let limit = OutputBufferLimit {
+                hard: parse_memory_size(group[1])?,
+                soft: parse_memory_size(group[2])?,
+                soft_seconds: group[3].parse()?,
+            };
+            *limits.get_mut(group[0].parse()?) = limit;
+        }
+
+        Ok(limits)
+    }
+}
+impl Display for OutputBufferLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let classes = [
+            ClientClass::Normal,
+            ClientClass::Replica,
+            ClientClass::Pubsub,
+        ];
+        let groups = classes.map(|class| {
+            let limit = self.get(class);
+            format!(
+                "{} {} {} {}",
+                class.as_str(),
+                limit.hard,
+                limit.soft,
+                limit.soft_seconds
+            )
+        });
+
+        write!(f, "{}", groups.join(" "))
+    }
+}
+impl OutputBufferLimits {
+    pub fn get(&self, class: ClientClass) -> OutputBufferLimit {
+        match class {
+            ClientClass::Normal => self.normal,
+            ClientClass::Replica => self.replica,
+            ClientClass::Pubsub => self.pubsub,
+        }
+    }
+
+    fn get_mut(&mut self, class: ClientClass) -> &mut OutputBufferLimit {
+        match class {
+            ClientClass::Normal => &mut self.normal,
+            ClientClass::Replica => &mut self.replica,
+            ClientClass::Pubsub => &mut self.pubsub,
+        }
+    }
+}
+
+/// Queued replies are written out once they reach this size, even mid-pipeline
+pub const MAX_QUEUED_OUTPUT: usize = 64 * 1024;
+
+/// Sends a reply while enforcing the connection's output buffer limit. Replies to
+/// pipelined requests are queued and go out in a single write once the last buffered
+/// request got its reply. Output over the hard limit is never sent, output over the soft
+/// limit has `soft_seconds` to drain to the client. Returns false when the client has to
+/// be disconnected instead
+pub async fn write_limited(
+    handler: &mut RedisConnectionHandler,
+    reply: RedisValue,
+    protocol: Protocol,
+    limit: OutputBufferLimit,
+) -> Result<bool> {
+    handler.queue_raw(&reply.serialize_as(protocol)?);
+    let pending = handler.queued_len();
+    if limit.hard > 0 && pending > limit.hard {
+        return Ok(false);
+    }
+    if pending < MAX_QUEUED_OUTPUT && handler.has_buffered_frame() {
+        return Ok(true);
+    }
+    if limit.soft == 0 || pending <= limit.soft {
+        handler.write_queued().await?;
+        return Ok(true);
+    }
+
+    let soft_time = Duration::from_secs(limit.soft_seconds);
+    let res = match tokio::time::timeout(soft_time, handler.write_queued()).await {
+        Ok(written) => written.map(|_| true)?,
+        Err(_) => false,
+    };
+
+    Ok(res)
+}
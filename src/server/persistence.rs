@@ -0,0 +1,308 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use bytes::Bytes;
+
+use crate::repl::ServerContext;
+
+use super::{
+    commands::propagate,
+    db::Db,
+    rdb::{self, ReplInfo},
+    server::{Dataset, RedisServer},
+    snapshot::SnapshotStorage,
+};
+
+/// How a shutdown treats the dataset, from SHUTDOWN arguments or `shutdown-on-sigterm`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownFlags {
+    /// `Some(true)` for SAVE, `Some(false)` for NOSAVE, `None` to save only when
+    /// save points are configured
+    pub save: Option<bool>,
+    /// exit even if the final save fails
+    pub force: bool,
+    /// exit without waiting for lagging replicas
+    pub now: bool,
+}
+impl FromStr for ShutdownFlags {
+    type Err = anyhow::Error;
+
+    /// Parses the space separated `shutdown-on-sigterm` value, e.g. "nosave force"
+    fn from_str(s: &str) -> Result<Self> {
+        let mut flags = ShutdownFlags::default();
+        for flag in s.split_whitespace() {
+            match flag.to_lowercase().as_str() {
+                "default" => {}
+                "save" => flags.save = Some(true),
+                "nosave" => flags.save = Some(false),
+                "force" => flags.force = true,
+                "now" => flags.now = true,
+                _ => bail!("Invalid shutdown flag: '{}'", flag),
+            }
+        }
+
+        Ok(flags)
+    }
+}
+
+/// Seconds to wait before retrying an automatic BGSAVE that failed
+const BGSAVE_RETRY_DELAY: u64 = 5;
+
+/// Where the dataset stands relative to the last RDB written
+#[derive(Debug, Default)]
+pub struct SaveState {
+    /// writes since the last successful save, `rdb_changes_since_last_save`
+    pub dirty: AtomicU64,
+    /// ms timestamp of the last successful save, or of startup
+    pub last_save: AtomicU64,
+    pub last_bgsave_ok: AtomicBool,
+    /// ms timestamp of the last BGSAVE attempt
+    pub last_bgsave_try: AtomicU64,
+}
+impl SaveState {
+    pub fn new(now: u64) -> Self {
+        Self {
+            dirty: AtomicU64::new(0),
+            last_save: AtomicU64::new(now),
+            last_bgsave_ok: AtomicBool::new(true),
+            last_bgsave_try: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts a change to the dataset towards the save points
+    pub fn mark_dirty(&self) {
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a successful save of a snapshot taken when `dirty` was `dirty_at_snapshot`,
+    /// writes that came in while saving still count
+    fn saved(&self, dirty_at_snapshot: u64, now: u64) {
+        self.dirty.fetch_sub(dirty_at_snapshot, Ordering::Relaxed);
+        self.last_save.store(now, Ordering::Relaxed);
+    }
+}
+
+/// Encodes a snapshot off the async workers and hands it to the snapshot storage, which
+/// never clobbers the previous one with a failed save
+async fn write_rdb(
+    storage: Arc<dyn SnapshotStorage>,
+    dataset: Dataset,
+    repl_info: ReplInfo,
+) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let data = rdb::serialize(&dataset, Some(&repl_info))?;
+        storage.store(&data)
+    })
+    .await??;
+    log::info!("DB saved on disk");
+
+    Ok(())
+}
+
+/// Parses the `save` config value, pairs of "<seconds> <changes>"
+pub fn parse_save_points(value: &str) -> Result<Vec<(u64, u64)>> {
+    let numbers = value
+        .split_whitespace()
+        .map(|n| n.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid save point")?;
+    ensure!(
+        numbers.len() % 2 == 0,
+        "Save points come in <seconds> <changes> pairs"
+    );
+
+    let res = numbers.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+
+    Ok(res)
+}
+
+impl RedisServer {
+    /// Point-in-time copy of the dataset, empty databases left out. Cloning the persistent
+    /// maps is O(1), so the store locks are only held for an instant and writers carry on
+    /// during the dump. Every database is locked at once, in index order, so the copy
+    /// never sees a write to one and not the next
+    pub async fn snapshot(&self) -> Dataset {
+        let mut locked = Vec::with_capacity(self.databases.len());
+        for db in &self.databases {
+            let main_store = db.main_store.lock().await;
+            let expire_store = db.expire_store.lock().await;
+            locked.push((db.index, main_store, expire_store));
+        }
+
+        locked
+            .iter()
+            .filter(|(_, main_store, _)| !main_store.is_empty())
+            .map(|(index, main_store, expire_store)| {
+                let contents = Db::from_stores((*main_store).clone(), (*expire_store).clone());
+                (*index, contents)
+            })
+            .collect()
+    }
+
+    /// Writes the whole dataset to the configured RDB file, blocking the caller until done
+    pub async fn save(&self) -> Result<()> {
+        let dirty = self.save_state.dirty.load(Ordering::Relaxed);
+        let dataset = self.snapshot().await;
+        let repl_info = self.server_context.read().unwrap().repl_info();
+
+        write_rdb(Arc::clone(&self.snapshot_storage), dataset, repl_info).await?;
+        self.save_state.saved(dirty, self.clock.now());
+
+        Ok(())
+    }
+
+    /// Writes a snapshot of the dataset from a background task (BGSAVE). Only one
+    /// background save runs at a time
+    pub async fn bgsave(&self) -> Result<()> {
+        ensure!(
+            !self.bgsave_in_progress.swap(true, Ordering::SeqCst),
+            "Background save already in progress"
+        );
+        let dirty = self.save_state.dirty.load(Ordering::Relaxed);
+        let dataset = self.snapshot().await;
+        let repl_info = self.server_context.read().unwrap().repl_info();
+        self.save_state
+            .last_bgsave_try
+            .store(self.clock.now(), Ordering::Relaxed);
+
+        let storage = Arc::clone(&self.snapshot_storage);
+        let clock = Arc::clone(&self.clock);
+        let save_state = Arc::clone(&self.save_state);
+        let in_progress = Arc::clone(&self.bgsave_in_progress);
+        tokio::spawn(async move {
+            let res = write_rdb(storage, dataset, repl_info).await;
+            match &res {
+                Ok(()) => save_state.saved(dirty, clock.now()),
+                Err(e) => log::error!("Background saving error: {:#}", e),
+            }
+            save_state
+                .last_bgsave_ok
+                .store(res.is_ok(), Ordering::Relaxed);
+            in_progress.store(false, Ordering::SeqCst);
+        });
+        log::info!("Background saving started");
+
+        Ok(())
+    }
+
+    /// Starts a BGSAVE once a save point is reached: at least `changes` writes and
+    /// `seconds` elapsed since the last save. A failed BGSAVE is retried after a delay
+    pub async fn autosave(&self) {
+        if self.bgsave_in_progress.load(Ordering::SeqCst) {
+            return;
+        }
+        let now = self.clock.now();
+        let state = &self.save_state;
+        let dirty = state.dirty.load(Ordering::Relaxed);
+        let since_save = now.saturating_sub(state.last_save.load(Ordering::Relaxed));
+        let since_try = now.saturating_sub(state.last_bgsave_try.load(Ordering::Relaxed));
+        if !state.last_bgsave_ok.load(Ordering::Relaxed) && since_try <= BGSAVE_RETRY_DELAY * 1000 {
+            return;
+        }
+
+        let due = self
+            .config
+            .live
+            .read()
+            .unwrap()
+            .save_points
+            .iter()
+            .find(|(seconds, changes)| dirty >= *changes && since_save > seconds * 1000)
+            .copied();
+        let Some((seconds, changes)) = due else {
+            return;
+        };
+        log::info!("{} changes in {} seconds. Saving...", changes, seconds);
+        if let Err(e) = self.bgsave().await {
+            log::error!("Failure starting the automatic BGSAVE: {}", e);
+        }
+    }
+
+    /// Stops the server: lets the commands running finish and holds off the next ones,
+    /// gives replicas `shutdown-timeout` to catch up unless NOW, runs the final save, then
+    /// stops the accept loop and closes every client connection. Fails with the server
+    /// still running when the final save does
+    pub async fn shutdown(&self, flags: ShutdownFlags) -> Result<()> {
+        let exec_guard = self.exec_lock.write().await;
+        if !flags.now {
+            self.wait_for_replicas().await?;
+        }
+        self.prepare_shutdown(flags).await?;
+
+        self.shutdown_signal.notify_one();
+        let closed = self.clients.kill(|_| true);
+        log::info!("Closing {} client connections", closed.len());
+        drop(exec_guard);
+
+        Ok(())
+    }
+
+    /// Waits up to `shutdown-timeout` for every replica to acknowledge the whole stream
+    async fn wait_for_replicas(&self) -> Result<()> {
+        let offset = match &*self.server_context.read().unwrap() {
+            ServerContext::Master(master) => master.repl_offset(),
+            ServerContext::Replica(_) => return Ok(()),
+        };
+        let replicas = self.replicas.list().len();
+        if self.replicas.acked_count(offset) == replicas {
+            return Ok(());
+        }
+
+        log::info!("Waiting for replicas before shutting down.");
+        propagate(
+            self,
+            None,
+            "REPLCONF",
+            &[Bytes::from_static(b"GETACK"), Bytes::from_static(b"*")],
+        )?;
+        let caught_up = async {
+            loop {
+                let next_ack = self.replicas.next_ack();
+                tokio::pin!(next_ack);
+                next_ack.as_mut().enable();
+                if self.replicas.acked_count(offset) >= replicas {
+                    return;
+                }
+                next_ack.await;
+            }
+        };
+        if tokio::time::timeout(self.config.shutdown_timeout, caught_up)
+            .await
+            .is_err()
+        {
+            log::warn!("Lagging replica(s) didn't catch up before the shutdown timeout.");
+        }
+
+        Ok(())
+    }
+
+    /// Runs the final save a shutdown requires, failing if the server has to keep running
+    pub async fn prepare_shutdown(&self, flags: ShutdownFlags) -> Result<()> {
+        if let Some(recorder) = &self.recorder {
+            recorder.flush()?;
+        }
+        let save = flags
+            .save
+            .unwrap_or(!self.config.live.read().unwrap().save_points.is_empty());
+        if !save {
+            return Ok(());
+        }
+
+        log::info!("Saving the final RDB snapshot before exiting.");
+        match self.save().await {
+            Ok(()) => Ok(()),
+            // --- FORCE exits anyway, losing whatever was not saved
+            Err(e) if flags.force => {
+                log::warn!("Error trying to save the DB, exiting anyway: {}", e);
+                Ok(())
+            }
+            Err(e) => Err(e.context("Error trying to save the DB, can't exit")),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{ensure, Result};
+
+use super::{commands::CommandContext, handler::RedisValue};
+
+/// What a custom command runs, resolving to the reply sent back to the client
+pub type CommandFuture<'a> = Pin<Box<dyn Future<Output = Result<RedisValue>> + Send + 'a>>;
+
+/// A command added by a downstream crate. It gets the same context as built-in commands:
+/// its arguments, the stores through `ctx.server` and the client's session
+pub trait CustomCommand: Send + Sync {
+    fn call<'a>(&'a self, ctx: &'a mut CommandContext<'_>) -> CommandFuture<'a>;
+}
+
+/// Symbol a plugin library exports to register its commands, with the signature of
+/// `PluginEntryPoint`
+#[cfg(feature = "plugins")]
+pub const PLUGIN_ENTRY_POINT: &[u8] = b"redis_rust_plugin_register";
+
+/// Entry point of a plugin library. Rust types cross the library boundary as is, so
+/// plugins have to be built with the same compiler and version of this crate
+#[cfg(feature = "plugins")]
+pub type PluginEntryPoint = fn(&CommandRegistry) -> Result<()>;
+
+/// Custom commands by uppercased name. Built-in commands take precedence over them
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: RwLock<HashMap<String, Arc<dyn CustomCommand>>>,
+    /// plugin libraries loaded, kept open for as long as their commands may run
+    #[cfg(feature = "plugins")]
+    libraries: std::sync::Mutex<Vec<libloading::Library>>,
+}
+impl CommandRegistry {
+    /// Adds a command, meant to be called before the server starts taking clients
+    pub fn register(&self, name: &str, command: impl CustomCommand + 'static) -> Result<()> {
+        let name = name.to_uppercase();
+        let mut commands = self.commands.write().unwrap();
+        ensure!(
+            !commands.contains_key(&name),
+            "Command '{}' is already registered",
+            name
+        );
+        commands.insert(name, Arc::new(command));
+
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn CustomCommand>> {
+        self.commands.read().unwrap().get(name).cloned()
+    }
+
+    /// Loads a plugin library and lets it register its commands
+    #[cfg(feature = "plugins")]
+    pub fn load_plugin(&self, path: &str) -> Result<()> {
+        use anyhow::Context;
+
+        // --- SAFETY: running foreign initialization code is the point of a plugin, and
+        // --- the entry point signature is part of the plugin contract
+        let library = unsafe { libloading::Library::new(path) }
+            .with_context(|| format!("Failed loading plugin {:?}", path))?;
+        let entry_point = *unsafe { library.get::<PluginEntryPoint>(PLUGIN_ENTRY_POINT) }
+            .with_context(|| format!("Plugin {:?} has no registration entry point", path))?;
+        entry_point(self).with_context(|| format!("Plugin {:?} failed to register", path))?;
+        self.libraries.lock().unwrap().push(library);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+use super::{glob::glob_match, handler::RedisValue};
+
+/// Where a subscribed connection gets the messages published for it, each ready to be
+/// sent as is
+pub type Inbox = mpsc::UnboundedSender<RedisValue>;
+
+/// Subscribers of a channel or pattern, by client ID
+type Subscribers = HashMap<Bytes, HashMap<u64, Inbox>>;
+
+/// Channels and glob patterns connections SUBSCRIBEd and PSUBSCRIBEd to, PUBLISH fanning
+/// messages out to their inboxes
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: Mutex<Subscribers>,
+    patterns: Mutex<Subscribers>,
+}
+impl PubSub {
+    pub fn subscribe(&self, client_id: u64, channel: Bytes, inbox: &Inbox) {
+        add_subscriber(&self.channels, client_id, channel, inbox);
+    }
+
+    pub fn unsubscribe(&self, client_id: u64, channel: &[u8]) {
+        remove_subscriber(&self.channels, client_id, channel);
+    }
+
+    pub fn psubscribe(&self, client_id: u64, pattern: Bytes, inbox: &Inbox) {
+        add_subscriber(&self.patterns, client_id, pattern, inbox);
+    }
+
+    pub fn punsubscribe(&self, client_id: u64, pattern: &[u8]) {
+        remove_subscriber(&self.patterns, client_id, pattern);
+    }
+
+    /// Sends a message to the subscribers of the channel and of the patterns matching it,
+    /// returning how many got it
+    pub fn publish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        let mut res = 0;
+        if let Some(subscribers) = self.channels.lock().unwrap().get(channel) {
+            let message = RedisValue::Push(vec![
+                RedisValue::BulkString(Bytes::from_static(b"message")),
+                RedisValue::BulkString(channel.clone()),
+                RedisValue::BulkString(message.clone()),
+            ]);
+            res += deliver(subscribers, &message);
+        }
+        for (pattern, subscribers) in self.patterns.lock().unwrap().iter() {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            let message = RedisValue::Push(vec![
+                RedisValue::BulkString(Bytes::from_static(b"pmessage")),
+                RedisValue::BulkString(pattern.clone()),
+                RedisValue::BulkString(channel.clone()),
+                RedisValue::BulkString(message.clone()),
+            ]);
+            res += deliver(subscribers, &message);
+        }
+
+        res
+    }
+
+    /// Drops every subscription of a client, e.g. once it disconnected
+    pub fn forget(&self, client_id: u64) {
+        for subscriptions in [&self.channels, &self.patterns] {
+            subscriptions.lock().unwrap().retain(|_, subscribers| {
+                subscribers.remove(&client_id);
+                !subscribers.is_empty()
+            });
+        }
+    }
+}
+
+fn add_subscriber(subscriptions: &Mutex<Subscribers>, client_id: u64, name: Bytes, inbox: &Inbox) {
+    subscriptions
+        .lock()
+        .unwrap()
+        .entry(name)
+        .or_default()
+        .insert(client_id, inbox.clone());
+}
+
+fn remove_subscriber(subscriptions: &Mutex<Subscribers>, client_id: u64, name: &[u8]) {
+    let mut subscriptions = subscriptions.lock().unwrap();
+    if let Some(subscribers) = subscriptions.get_mut(name) {
+        subscribers.remove(&client_id);
+        if subscribers.is_empty() {
+            subscriptions.remove(name);
+        }
+    }
+}
+
+/// Hands the message to each subscriber still connected, returning how many
+fn deliver(subscribers: &HashMap<u64, Inbox>, message: &RedisValue) -> usize {
+    subscribers
+        .values()
+        .filter(|inbox| inbox.send(message.clone()).is_ok())
+        .count()
+}
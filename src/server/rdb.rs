@@ -0,0 +1,1913 @@
+use core::str;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+};
+
+use anyhow::{anyhow, bail, ensure, Result};
+use bytes::Bytes;
+
+use super::{
+    bloom::{BloomFilter, BloomLayer},
+    handler::RedisValue,
+    server::Dataset,
+    store::Store,
+    stream::{Stream, StreamId},
+    timeseries::{Aggregation, Rule, Sample, TimeSeries},
+    zset::SortedSet,
+};
+
+const LEN_ENCODING_MASK: u8 = 0b11000000;
+const LEN_DECODING_MASK: u8 = 0b00111111;
+
+const OPCODE_SLOT_INFO: u8 = 0xf4;
+const OPCODE_FUNCTION: u8 = 0xf5;
+const OPCODE_MODULE_AUX: u8 = 0xf7;
+const OPCODE_IDLE: u8 = 0xf8;
+const OPCODE_FREQ: u8 = 0xf9;
+const OPCODE_AUX: u8 = 0xfa;
+const OPCODE_RESIZEDB: u8 = 0xfb;
+const OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const OPCODE_EXPIRETIME: u8 = 0xfd;
+const OPCODE_SELECTDB: u8 = 0xfe;
+const OPCODE_EOF: u8 = 0xff;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_MODULE_2: u8 = 7;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+const TYPE_HASH_METADATA: u8 = 24;
+const TYPE_HASH_LISTPACK_EX: u8 = 25;
+
+/// Entries per listpack of a saved stream, Redis' default `stream-node-max-entries`
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+/// Flags of a stream listpack entry: deleted, or with the fields of the master entry
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+/// Closes a listpack
+const LISTPACK_END: u8 = 0xff;
+/// Closes a ziplist
+const ZIPLIST_END: u8 = 0xff;
+/// Kinds of quicklist nodes since v2: a single large item, or a listpack of small ones
+const QUICKLIST_NODE_PLAIN: usize = 1;
+const QUICKLIST_NODE_PACKED: usize = 2;
+
+/// CRC-64/Jones lookup table, the checksum Redis ends RDB files with
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            // --- the polynomial 0xad93d23594c935a9, reflected
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0x95ac_9329_ac4b_c9b5,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Opcode closing the data a module serialized
+const MODULE_OPCODE_EOF: usize = 0;
+const MODULE_OPCODE_SINT: usize = 1;
+const MODULE_OPCODE_UINT: usize = 2;
+const MODULE_OPCODE_FLOAT: usize = 3;
+const MODULE_OPCODE_DOUBLE: usize = 4;
+const MODULE_OPCODE_STRING: usize = 5;
+
+/// Module type JSON documents are saved under, RedisJSON's, so files move between the two
+const JSON_MODULE_NAME: &str = "ReJSON-RL";
+/// RedisJSON encoding version storing a document as its JSON text
+const JSON_MODULE_ENCVER: u64 = 3;
+/// Module type time series are saved under. The layout is this server's own, nothing
+/// like the chunks of RedisTimeSeries, so the name is too
+const TIMESERIES_MODULE_NAME: &str = "rrust-TSD";
+const TIMESERIES_MODULE_ENCVER: u64 = 1;
+/// Module type Bloom filters are saved under, a layout of this server's own as well
+const BLOOM_MODULE_NAME: &str = "rrust-BLM";
+const BLOOM_MODULE_ENCVER: u64 = 1;
+/// Characters a module type name is made of, 6 bits each in the module ID
+const MODULE_NAME_CHARSET: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Smallest valid RDB image (version 11, aux fields only, no keys), used for full
+/// resyncs so the server doesn't depend on an RDB file in its working directory
+pub const EMPTY_RDB: &[u8] = &[
+    0x52, 0x45, 0x44, 0x49, 0x53, 0x30, 0x30, 0x31, 0x31, 0xfa, 0x09, 0x72, 0x65, 0x64, 0x69, 0x73,
+    0x2d, 0x76, 0x65, 0x72, 0x05, 0x37, 0x2e, 0x32, 0x2e, 0x30, 0xfa, 0x0a, 0x72, 0x65, 0x64, 0x69,
+    0x73, 0x2d, 0x62, 0x69, 0x74, 0x73, 0xc0, 0x40, 0xfa, 0x05, 0x63, 0x74, 0x69, 0x6d, 0x65, 0xc2,
+    0x6d, 0x08, 0xbc, 0x65, 0xfa, 0x08, 0x75, 0x73, 0x65, 0x64, 0x2d, 0x6d, 0x65, 0x6d, 0xc2, 0xb0,
+    0xc4, 0x10, 0x00, 0xfa, 0x08, 0x61, 0x6f, 0x66, 0x2d, 0x62, 0x61, 0x73, 0x65, 0xc0, 0x00, 0xff,
+    0xf0, 0x6e, 0x3b, 0xfe, 0xc0, 0xff, 0x5a, 0xa2,
+];
+
+/// Files from this size up are decoded on several threads
+pub const PARALLEL_LOAD_MIN_SIZE: usize = 16 * 1024 * 1024;
+/// Entries decoded at a time by a thread of the parallel loader
+const PARALLEL_LOAD_BATCH: usize = 4096;
+
+/// Replication history a snapshot belongs to, kept in the `repl-id`, `repl-offset` and
+/// `repl-stream-db` aux fields so a restarted server can resume it with PSYNC
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplInfo {
+    pub replid: String,
+    /// replication offset the dataset matches
+    pub offset: usize,
+    /// database the replication stream had selected at that offset, for the commands
+    /// following it without a SELECT of their own
+    pub stream_db: usize,
+}
+
+/// Top level record of an RDB file, as found by `walk`
+#[derive(Debug, Clone, PartialEq)]
+pub enum RdbRecord {
+    Aux {
+        key: RedisValue,
+        value: RedisValue,
+    },
+    SelectDb(usize),
+    ResizeDb {
+        keys: usize,
+        expires: usize,
+    },
+    /// expire time in ms, applies to the entry that follows
+    ExpireTime(u64),
+    Entry {
+        value_type: u8,
+        key: RedisValue,
+        value: RedisValue,
+    },
+    /// key of a value the server can't hold (hashes with field TTLs, streams with
+    /// consumer groups...), its value is stepped over
+    UnsupportedEntry {
+        value_type: u8,
+        key: RedisValue,
+    },
+    /// record the server has no use for, e.g. functions, module data or eviction hints
+    Ignored(u8),
+    Eof,
+}
+
+/// Outcome of `check`
+#[derive(Debug, Default)]
+pub struct RdbCheck {
+    pub version: Option<u32>,
+    pub keys_by_type: BTreeMap<&'static str, usize>,
+    /// offset of the first corrupt record and what is wrong with it
+    pub corruption: Option<(usize, anyhow::Error)>,
+}
+
+/// Decodes the key space of an RDB file, dropping keys that expired before `now`.
+/// Never panics on malformed input, any structural problem is reported as an error instead
+pub fn parse(buf: &[u8], now: u64) -> Result<Dataset> {
+    let (stores, _) = parse_with_repl_info(buf, now)?;
+
+    Ok(stores)
+}
+
+/// Same as `parse`, also returning the replication history saved along with the data.
+/// Files of `PARALLEL_LOAD_MIN_SIZE` bytes or more are decoded on several threads
+pub fn parse_with_repl_info(buf: &[u8], now: u64) -> Result<(Dataset, Option<ReplInfo>)> {
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if buf.len() < PARALLEL_LOAD_MIN_SIZE || workers < 2 {
+        return parse_sequential(buf, now);
+    }
+
+    parse_parallel(buf, now, workers)
+}
+
+/// Decodes the whole file on the calling thread
+pub fn parse_sequential(buf: &[u8], now: u64) -> Result<(Dataset, Option<ReplInfo>)> {
+    let mut loader = Loader::default();
+    let end = walk(buf, |_, record| loader.load(record, now))?;
+    verify_checksum(buf, end)?;
+
+    Ok(loader.finish())
+}
+
+/// Decodes the file as a pipeline: the records are indexed first without decoding the
+/// entries, `workers` threads then decode batches of entries while the calling thread
+/// inserts them, in file order
+pub fn parse_parallel(buf: &[u8], now: u64, workers: usize) -> Result<(Dataset, Option<ReplInfo>)> {
+    // --- string entries stay undecoded, only their position is kept
+    let mut records = Vec::new();
+    let mut entries = Vec::new();
+    let end = walk_records(buf, false, |range, record| {
+        match record {
+            RdbRecord::Entry {
+                value_type: TYPE_STRING,
+                ..
+            } => {
+                entries.push(range.start + 1);
+                records.push(None);
+            }
+            record => records.push(Some(record)),
+        }
+        Ok(())
+    })?;
+    verify_checksum(buf, end)?;
+
+    let batches = entries.chunks(PARALLEL_LOAD_BATCH).collect::<Vec<_>>();
+    let next_batch = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..workers.min(batches.len()) {
+            let tx = tx.clone();
+            let (batches, next_batch) = (&batches, &next_batch);
+            scope.spawn(move || loop {
+                let i = next_batch.fetch_add(1, Ordering::Relaxed);
+                let Some(batch) = batches.get(i) else {
+                    return;
+                };
+                let decoded = batch
+                    .iter()
+                    .map(|pos| {
+                        let (key, next) = parse_rdb_string(buf, *pos)?;
+                        let (value, _) = parse_rdb_string(buf, next)?;
+                        Ok((key, value))
+                    })
+                    .collect::<Result<Vec<_>>>();
+                // --- the loader gave up on an error, nobody is waiting anymore
+                if tx.send((i, decoded)).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(tx);
+
+        // --- batches come back in whatever order they were decoded
+        let mut loader = Loader::default();
+        let mut pending = BTreeMap::new();
+        let mut batch = Vec::new().into_iter();
+        let mut batch_index = 0;
+        for record in records {
+            let record = match record {
+                Some(record) => record,
+                None => {
+                    let (key, value) = match batch.next() {
+                        Some(entry) => entry,
+                        None => {
+                            while !pending.contains_key(&batch_index) {
+                                let (i, decoded) = rx.recv()?;
+                                pending.insert(i, decoded);
+                            }
+                            batch = pending.remove(&batch_index).unwrap()?.into_iter();
+                            batch_index += 1;
+                            batch.next().expect("Batches should never be empty")
+                        }
+                    };
+                    RdbRecord::Entry {
+                        value_type: TYPE_STRING,
+                        key,
+                        value,
+                    }
+                }
+            };
+            loader.load(record, now)?;
+        }
+
+        Ok(loader.finish())
+    })
+}
+
+/// Builds the stores out of the records of a file, handed over in order
+#[derive(Default)]
+struct Loader {
+    dataset: Dataset,
+    // --- expire opcodes apply to the key/value pair that follows them
+    expire_time_in_ms: Option<u64>,
+    db: usize,
+    skipped_keys: usize,
+    repl_id: Option<String>,
+    repl_offset: Option<usize>,
+    repl_stream_db: Option<usize>,
+}
+impl Loader {
+    fn load(&mut self, record: RdbRecord, now: u64) -> Result<()> {
+        match record {
+            RdbRecord::SelectDb(selected) => self.db = selected,
+            RdbRecord::ExpireTime(expire_time) => self.expire_time_in_ms = Some(expire_time),
+            // --- not every value Redis holds exists here
+            RdbRecord::UnsupportedEntry { .. } => {
+                self.expire_time_in_ms = None;
+                self.skipped_keys += 1;
+            }
+            RdbRecord::Entry { key, value, .. } => {
+                let expire_time = self.expire_time_in_ms.take();
+                // --- if the key has expired already, skip persisting this
+                if expire_time.is_some_and(|expire_time| expire_time < now) {
+                    return Ok(());
+                }
+                let RedisValue::BulkString(key) = key else {
+                    bail!("Invalid key of type {}", key.type_name());
+                };
+                let contents = self.dataset.entry(self.db).or_default();
+                contents.insert(key, value, expire_time);
+            }
+            RdbRecord::Aux {
+                key: RedisValue::BulkString(key),
+                value: RedisValue::BulkString(value),
+            } => match key.as_ref() {
+                b"repl-id" => self.repl_id = Some(String::from_utf8_lossy(&value).into_owned()),
+                b"repl-offset" => self.repl_offset = str::from_utf8(&value)?.parse().ok(),
+                b"repl-stream-db" => self.repl_stream_db = str::from_utf8(&value)?.parse().ok(),
+                _ => {}
+            },
+            RdbRecord::Aux { .. }
+            | RdbRecord::ResizeDb { .. }
+            | RdbRecord::Ignored(_)
+            | RdbRecord::Eof => {}
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> (Dataset, Option<ReplInfo>) {
+        if self.skipped_keys > 0 {
+            log::warn!("Skipped {} keys of unsupported types", self.skipped_keys);
+        }
+        let repl_info = match (self.repl_id, self.repl_offset) {
+            (Some(replid), Some(offset)) => Some(ReplInfo {
+                replid,
+                offset,
+                stream_db: self.repl_stream_db.unwrap_or(0),
+            }),
+            _ => None,
+        };
+
+        (self.dataset, repl_info)
+    }
+}
+
+/// Walks the records of an RDB file in order, handing `visit` the byte range each one
+/// spans. Returns the offset right after the EOF opcode
+pub fn walk(buf: &[u8], visit: impl FnMut(Range<usize>, RdbRecord) -> Result<()>) -> Result<usize> {
+    walk_records(buf, true, visit)
+}
+
+/// Same as `walk`. Without `decode_entries` the key and value of string entries are left
+/// as null placeholders, to be decoded later from the range of the record
+fn walk_records(
+    buf: &[u8],
+    decode_entries: bool,
+    mut visit: impl FnMut(Range<usize>, RdbRecord) -> Result<()>,
+) -> Result<usize> {
+    ensure!(
+        slice_at(buf, 0, 5).is_ok_and(|magic| magic == b"REDIS"),
+        "Invalid RDB magic string"
+    );
+    // --- skip the magic string and the 4 digit version
+    let mut next_pos = 9;
+
+    loop {
+        let record_pos = next_pos;
+        let opcode = byte_at(buf, next_pos)?;
+        next_pos += 1;
+
+        let record = match opcode {
+            OPCODE_AUX => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                let (value, next) = parse_rdb_string(buf, next)?;
+                next_pos = next;
+                RdbRecord::Aux { key, value }
+            }
+            OPCODE_SELECTDB => {
+                let (db, next) = parse_length_encoding(buf, next_pos)?;
+                next_pos = next;
+                RdbRecord::SelectDb(db)
+            }
+            OPCODE_RESIZEDB => {
+                let (keys, next) = parse_length_encoding(buf, next_pos)?;
+                let (expires, next) = parse_length_encoding(buf, next)?;
+                next_pos = next;
+                RdbRecord::ResizeDb { keys, expires }
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let expire_time_in_ms = u64::from_le_bytes(
+                    slice_at(buf, next_pos, 8)?
+                        .try_into()
+                        .expect("Should be a slice of length 8"),
+                );
+                next_pos += 8;
+                RdbRecord::ExpireTime(expire_time_in_ms)
+            }
+            OPCODE_EXPIRETIME => {
+                let expire_time_in_s = u32::from_le_bytes(
+                    slice_at(buf, next_pos, 4)?
+                        .try_into()
+                        .expect("Should be a slice of length 4"),
+                );
+                next_pos += 4;
+                RdbRecord::ExpireTime(expire_time_in_s as u64 * 1000)
+            }
+            OPCODE_FUNCTION => {
+                let (_, next) = parse_rdb_string(buf, next_pos)?;
+                next_pos = next;
+                RdbRecord::Ignored(opcode)
+            }
+            OPCODE_MODULE_AUX => {
+                // --- module ID, when the data is loaded (and the same again), then the data
+                let (_, next) = parse_length_encoding(buf, next_pos)?;
+                let (_, next) = parse_length_encoding(buf, next)?;
+                let (_, next) = parse_length_encoding(buf, next)?;
+                next_pos = skip_module_data(buf, next)?;
+                RdbRecord::Ignored(opcode)
+            }
+            OPCODE_SLOT_INFO => {
+                let (_, next) = parse_length_encoding(buf, next_pos)?;
+                let (_, next) = parse_length_encoding(buf, next)?;
+                let (_, next) = parse_length_encoding(buf, next)?;
+                next_pos = next;
+                RdbRecord::Ignored(opcode)
+            }
+            OPCODE_IDLE => {
+                let (_, next) = parse_length_encoding(buf, next_pos)?;
+                next_pos = next;
+                RdbRecord::Ignored(opcode)
+            }
+            OPCODE_FREQ => {
+                byte_at(buf, next_pos)?;
+                next_pos += 1;
+                RdbRecord::Ignored(opcode)
+            }
+            OPCODE_EOF => RdbRecord::Eof,
+            TYPE_STRING if !decode_entries => {
+                next_pos = skip_rdb_string(buf, skip_rdb_string(buf, next_pos)?)?;
+                RdbRecord::Entry {
+                    value_type: opcode,
+                    key: RedisValue::NullBulkString,
+                    value: RedisValue::NullBulkString,
+                }
+            }
+            TYPE_STRING => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                let (value, next) = parse_rdb_string(buf, next)?;
+                next_pos = next;
+                RdbRecord::Entry {
+                    value_type: opcode,
+                    key,
+                    value,
+                }
+            }
+            // --- the plain encoding lists are saved with here, Redis' own are listpacks
+            TYPE_LIST => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                let (len, mut next) = parse_length_encoding(buf, next)?;
+                let mut items = VecDeque::new();
+                for _ in 0..len {
+                    let (item, after_item) = parse_rdb_string(buf, next)?;
+                    let RedisValue::BulkString(item) = item else {
+                        bail!("Invalid list element at offset {}", next);
+                    };
+                    items.push_back(item);
+                    next = after_item;
+                }
+                next_pos = next;
+                RdbRecord::Entry {
+                    value_type: opcode,
+                    key,
+                    value: RedisValue::List(items.into_iter().collect()),
+                }
+            }
+            // --- Redis' own lists: nodes that are ziplists, or listpacks and large items
+            // --- since v2
+            TYPE_LIST_QUICKLIST | TYPE_LIST_QUICKLIST_2 => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                let (len, mut next) = parse_length_encoding(buf, next)?;
+                let mut items = VecDeque::new();
+                for _ in 0..len {
+                    let mut container = QUICKLIST_NODE_PACKED;
+                    if opcode == TYPE_LIST_QUICKLIST_2 {
+                        (container, next) = parse_length_encoding(buf, next)?;
+                    }
+                    let (node, after_node) = parse_rdb_string(buf, next)?;
+                    let RedisValue::BulkString(node) = node else {
+                        bail!("Invalid quicklist node at offset {}", next);
+                    };
+                    match container {
+                        QUICKLIST_NODE_PLAIN => items.push_back(node),
+                        QUICKLIST_NODE_PACKED if opcode == TYPE_LIST_QUICKLIST => {
+                            items.extend(ziplist_elements(&node)?)
+                        }
+                        QUICKLIST_NODE_PACKED => items.extend(listpack_elements(&node)?),
+                        container => bail!(
+                            "Invalid quicklist container {} at offset {}",
+                            container,
+                            next
+                        ),
+                    }
+                    next = after_node;
+                }
+                next_pos = next;
+                RdbRecord::Entry {
+                    value_type: opcode,
+                    key,
+                    value: RedisValue::List(items.into_iter().collect()),
+                }
+            }
+            TYPE_SET => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                let (len, mut next) = parse_length_encoding(buf, next)?;
+                let mut members = vec![];
+                for _ in 0..len {
+                    let (member, after_member) = parse_rdb_string(buf, next)?;
+                    let RedisValue::BulkString(member) = member else {
+                        bail!("Invalid set member at offset {}", next);
+                    };
+                    members.push(member);
+                    next = after_member;
+                }
+                next_pos = next;
+                RdbRecord::Entry {
+                    value_type: opcode,
+                    key,
+                    value: RedisValue::Set(members.into_iter().collect()),
+                }
+            }
+            // --- scores as text behind a 1 byte length, 253 to 255 are NaN and infinities
+            TYPE_ZSET => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                let (len, mut next) = parse_length_encoding(buf, next)?;
+                let mut zset = SortedSet::default();
+                for _ in 0..len {
+                    let (member, after_member) = parse_rdb_string(buf, next)?;
+                    let RedisValue::BulkString(member) = member else {
+                        bail!("Invalid sorted set member at offset {}", next);
+                    };
+                    let (score, after_score) = match byte_at(buf, after_member)? {
+                        253 => bail!("NaN score at offset {}", after_member),
+                        254 => (f64::INFINITY, after_member + 1),
+                        255 => (f64::NEG_INFINITY, after_member + 1),
+                        score_len => {
+                            let text = slice_at(buf, after_member + 1, score_len as usize)?;
+                            let score = str::from_utf8(text)
+                                .ok()
+                                .and_then(|text| text.parse::<f64>().ok())
+                                .filter(|score| !score.is_nan())
+                                .ok_or_else(|| {
+                                    anyhow!("Invalid score at offset {}", after_member)
+                                })?;
+                            (score, after_member + 1 + score_len as usize)
+                        }
+                    };
+                    zset.insert(member, score);
+                    next = after_score;
+                }
+                next_pos = next;
+                RdbRecord::Entry {
+                    value_type: opcode,
+                    key,
+                    value: RedisValue::SortedSet(zset),
+                }
+            }
+            // --- binary scores, as Redis saves skiplist encoded sorted sets
+            TYPE_ZSET_2 => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                let (len, mut next) = parse_length_encoding(buf, next)?;
+                let mut zset = SortedSet::default();
+                for _ in 0..len {
+                    let (member, after_member) = parse_rdb_string(buf, next)?;
+                    let RedisValue::BulkString(member) = member else {
+                        bail!("Invalid sorted set member at offset {}", next);
+                    };
+                    let raw = slice_at(buf, after_member, 8)?
+                        .try_into()
+                        .expect("Should be 8 bytes");
+                    let score = f64::from_le_bytes(raw);
+                    ensure!(!score.is_nan(), "NaN score at offset {}", after_member);
+                    zset.insert(member, score);
+                    next = after_member + 8;
+                }
+                next_pos = next;
+                RdbRecord::Entry {
+                    value_type: opcode,
+                    key,
+                    value: RedisValue::SortedSet(zset),
+                }
+            }
+            TYPE_HASH => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                let (len, mut next) = parse_length_encoding(buf, next)?;
+                let mut fields = vec![];
+                for _ in 0..len {
+                    let (field, after_field) = parse_rdb_string(buf, next)?;
+                    let (value, after_value) = parse_rdb_string(buf, after_field)?;
+                    let (RedisValue::BulkString(field), RedisValue::BulkString(value)) =
+                        (field, value)
+                    else {
+                        bail!("Invalid hash field at offset {}", next);
+                    };
+                    fields.push((field, value));
+                    next = after_value;
+                }
+                next_pos = next;
+                RdbRecord::Entry {
+                    value_type: opcode,
+                    key,
+                    value: RedisValue::Hash(fields.into_iter().collect()),
+                }
+            }
+            // --- small collections, saved by Redis as a single blob
+            TYPE_LIST_ZIPLIST | TYPE_SET_INTSET | TYPE_SET_LISTPACK | TYPE_ZSET_ZIPLIST
+            | TYPE_ZSET_LISTPACK | TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                let (blob, next) = parse_rdb_string(buf, next)?;
+                let RedisValue::BulkString(blob) = blob else {
+                    bail!("Invalid {} blob at offset {}", type_name(opcode), next_pos);
+                };
+                let value = compact_value(&blob, opcode)
+                    .map_err(|e| anyhow!("{} in the value of the key at offset {}", e, next_pos))?;
+                next_pos = next;
+                RdbRecord::Entry {
+                    value_type: opcode,
+                    key,
+                    value,
+                }
+            }
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                next_pos = skip_stream(buf, next, opcode)?;
+                match parse_stream(buf, next, opcode)? {
+                    Some(stream) => RdbRecord::Entry {
+                        value_type: opcode,
+                        key,
+                        value: RedisValue::Stream(stream),
+                    },
+                    None => RdbRecord::UnsupportedEntry {
+                        value_type: opcode,
+                        key,
+                    },
+                }
+            }
+            TYPE_MODULE_2 => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                let (module_id, next) = parse_length_encoding(buf, next)?;
+                let start = next;
+                next_pos = skip_module_data(buf, next)?;
+                match parse_module_value(buf, module_id as u64, start)? {
+                    Some(value) => RdbRecord::Entry {
+                        value_type: opcode,
+                        key,
+                        value,
+                    },
+                    None => RdbRecord::UnsupportedEntry {
+                        value_type: opcode,
+                        key,
+                    },
+                }
+            }
+            value_type => {
+                let (key, next) = parse_rdb_string(buf, next_pos)?;
+                next_pos = skip_value(buf, next, value_type)?;
+                RdbRecord::UnsupportedEntry { value_type, key }
+            }
+        };
+
+        let is_eof = record == RdbRecord::Eof;
+        visit(record_pos..next_pos, record)?;
+        if is_eof {
+            return Ok(next_pos);
+        }
+    }
+}
+
+/// Strict verification of a whole RDB file, in the spirit of redis-check-rdb: on top
+/// of what the loader checks, the version must be numeric and the file must end with
+/// the 8 byte checksum right after EOF, nothing past it
+pub fn check(buf: &[u8], mut visit: impl FnMut(&Range<usize>, &RdbRecord)) -> RdbCheck {
+    let mut report = RdbCheck {
+        version: slice_at(buf, 5, 4)
+            .ok()
+            .and_then(|v| str::from_utf8(v).ok()?.parse().ok()),
+        ..Default::default()
+    };
+    if !slice_at(buf, 0, 5).is_ok_and(|magic| magic == b"REDIS") {
+        report.corruption = Some((0, anyhow!("Invalid RDB magic string")));
+        return report;
+    }
+    if report.version.is_none() {
+        report.corruption = Some((5, anyhow!("Invalid RDB version")));
+        return report;
+    }
+
+    // --- records are contiguous, a failure starts where the last good record ended
+    let mut record_end = 9;
+    let res = walk(buf, |range, record| {
+        visit(&range, &record);
+        if let RdbRecord::Entry { value_type, .. }
+        | RdbRecord::UnsupportedEntry { value_type, .. } = record
+        {
+            *report
+                .keys_by_type
+                .entry(type_name(value_type))
+                .or_default() += 1;
+        }
+        record_end = range.end;
+
+        Ok(())
+    });
+
+    report.corruption = match res {
+        Ok(end) if buf.len() < end + 8 => Some((end, anyhow!("Missing checksum after EOF"))),
+        Ok(end) if buf.len() > end + 8 => Some((
+            end + 8,
+            anyhow!(
+                "{} unexpected bytes after the checksum",
+                buf.len() - end - 8
+            ),
+        )),
+        Ok(end) => verify_checksum(buf, end).err().map(|e| (end, e)),
+        Err(e) => Some((record_end, e)),
+    };
+
+    report
+}
+
+/// Name of a value type, as reported by TYPE
+pub fn type_name(value_type: u8) -> &'static str {
+    match value_type {
+        TYPE_STRING => "string",
+        TYPE_LIST | TYPE_LIST_ZIPLIST | TYPE_LIST_QUICKLIST | TYPE_LIST_QUICKLIST_2 => "list",
+        TYPE_SET | TYPE_SET_INTSET | TYPE_SET_LISTPACK => "set",
+        TYPE_ZSET | TYPE_ZSET_2 | TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => "zset",
+        TYPE_HASH
+        | TYPE_HASH_ZIPMAP
+        | TYPE_HASH_ZIPLIST
+        | TYPE_HASH_LISTPACK
+        | TYPE_HASH_METADATA
+        | TYPE_HASH_LISTPACK_EX => "hash",
+        TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => "stream",
+        TYPE_MODULE_2 => "module",
+        _ => "unknown",
+    }
+}
+
+/// Encodes the dataset as an RDB image the loader can read back, or Redis can. Databases
+/// without keys are left out
+pub fn serialize(dataset: &Dataset, repl_info: Option<&ReplInfo>) -> Result<Vec<u8>> {
+    let mut buf = b"REDIS0011".to_vec();
+    let mut aux_fields = vec![
+        ("redis-ver", "7.2.0".to_string()),
+        ("redis-bits", "64".to_string()),
+    ];
+    if let Some(repl_info) = repl_info {
+        aux_fields.push(("repl-id", repl_info.replid.clone()));
+        aux_fields.push(("repl-offset", repl_info.offset.to_string()));
+        aux_fields.push(("repl-stream-db", repl_info.stream_db.to_string()));
+    }
+    for (key, value) in aux_fields {
+        buf.push(OPCODE_AUX);
+        write_rdb_string(&mut buf, key.as_bytes());
+        write_rdb_string(&mut buf, value.as_bytes());
+    }
+
+    for (db, contents) in dataset {
+        write_database(&mut buf, *db, contents)?;
+    }
+
+    buf.push(OPCODE_EOF);
+    buf.extend(crc64(&buf).to_le_bytes());
+
+    Ok(buf)
+}
+
+/// Writes the keys of a database behind its SELECTDB, nothing when it has none. Goes
+/// through `Store` so that a database kept by another engine saves the same way
+fn write_database(buf: &mut Vec<u8>, db: usize, contents: &dyn Store) -> Result<()> {
+    if contents.is_empty()? {
+        return Ok(());
+    }
+    buf.push(OPCODE_SELECTDB);
+    write_length_encoding(buf, db);
+    buf.push(OPCODE_RESIZEDB);
+    write_length_encoding(buf, contents.len()?);
+    write_length_encoding(buf, contents.volatile_len()?);
+
+    contents.for_each(&mut |key, value, expire_time| {
+        if let Some(expire_time) = expire_time {
+            buf.push(OPCODE_EXPIRETIME_MS);
+            buf.extend(expire_time.to_le_bytes());
+        }
+        write_entry(buf, key, value)
+    })
+}
+
+/// Bytes the value of a key takes in an RDB file, its type and key left out, as DEBUG
+/// OBJECT reports it
+pub fn serialized_len(value: &RedisValue) -> Result<usize> {
+    let mut buf = vec![];
+    write_entry(&mut buf, b"", value)?;
+
+    // --- the type, then the length of the empty key
+    Ok(buf.len() - 2)
+}
+
+/// A value as an RDB file holds it, its type first, with no key
+pub fn encode_value(value: &RedisValue) -> Result<Vec<u8>> {
+    let mut res = vec![];
+    write_entry(&mut res, b"", value)?;
+    // --- the length of the empty key
+    res.remove(1);
+
+    Ok(res)
+}
+
+/// Reads back a value written by `encode_value`
+pub fn decode_value(data: &[u8]) -> Result<RedisValue> {
+    let Some((&value_type, value_data)) = data.split_first() else {
+        bail!("Empty value");
+    };
+    let mut buf = b"REDIS0011".to_vec();
+    buf.extend([value_type, 0]);
+    buf.extend_from_slice(value_data);
+    buf.push(OPCODE_EOF);
+
+    let mut res = None;
+    let end = walk(&buf, |_, record| {
+        match record {
+            RdbRecord::Entry { value, .. } if res.is_none() => res = Some(value),
+            RdbRecord::Eof => {}
+            _ => bail!("Invalid {} value", type_name(value_type)),
+        }
+        Ok(())
+    })?;
+    ensure!(end == buf.len(), "Trailing bytes after the value");
+
+    res.ok_or_else(|| anyhow!("Invalid {} value", type_name(value_type)))
+}
+
+/// Writes a key and its value, behind the type of the value
+fn write_entry(buf: &mut Vec<u8>, key_data: &[u8], value: &RedisValue) -> Result<()> {
+    match value {
+        RedisValue::BulkString(_) | RedisValue::Counter(_) => {
+            buf.push(TYPE_STRING);
+            write_rdb_string(buf, key_data);
+            write_rdb_string(buf, &value.as_string().expect("Strings have contents"));
+        }
+        RedisValue::List(items) => {
+            buf.push(TYPE_LIST);
+            write_rdb_string(buf, key_data);
+            write_length_encoding(buf, items.len());
+            for item in items {
+                write_rdb_string(buf, item);
+            }
+        }
+        RedisValue::Set(members) => {
+            buf.push(TYPE_SET);
+            write_rdb_string(buf, key_data);
+            write_length_encoding(buf, members.len());
+            for member in members {
+                write_rdb_string(buf, &member);
+            }
+        }
+        RedisValue::SortedSet(zset) => {
+            buf.push(TYPE_ZSET_2);
+            write_rdb_string(buf, key_data);
+            write_length_encoding(buf, zset.len());
+            for (member, score) in zset.iter() {
+                write_rdb_string(buf, member);
+                buf.extend(score.to_le_bytes());
+            }
+        }
+        RedisValue::Stream(stream) => {
+            buf.push(TYPE_STREAM_LISTPACKS_3);
+            write_rdb_string(buf, key_data);
+            write_stream(buf, stream);
+        }
+        RedisValue::Hash(fields) => {
+            buf.push(TYPE_HASH);
+            write_rdb_string(buf, key_data);
+            write_length_encoding(buf, fields.len());
+            for (field, value) in fields {
+                write_rdb_string(buf, field);
+                write_rdb_string(buf, value);
+            }
+        }
+        // --- the layout RedisJSON saves documents with
+        RedisValue::Json(document) => {
+            buf.push(TYPE_MODULE_2);
+            write_rdb_string(buf, key_data);
+            write_length_encoding(
+                buf,
+                module_id(JSON_MODULE_NAME, JSON_MODULE_ENCVER) as usize,
+            );
+            write_module_string(buf, document);
+            write_length_encoding(buf, MODULE_OPCODE_EOF);
+        }
+        RedisValue::TimeSeries(series) => {
+            buf.push(TYPE_MODULE_2);
+            write_rdb_string(buf, key_data);
+            write_length_encoding(
+                buf,
+                module_id(TIMESERIES_MODULE_NAME, TIMESERIES_MODULE_ENCVER) as usize,
+            );
+            write_timeseries(buf, series);
+            write_length_encoding(buf, MODULE_OPCODE_EOF);
+        }
+        RedisValue::Bloom(filter) => {
+            buf.push(TYPE_MODULE_2);
+            write_rdb_string(buf, key_data);
+            write_length_encoding(
+                buf,
+                module_id(BLOOM_MODULE_NAME, BLOOM_MODULE_ENCVER) as usize,
+            );
+            write_bloom(buf, filter);
+            write_length_encoding(buf, MODULE_OPCODE_EOF);
+        }
+        _ => bail!(
+            "Only string, list, hash, set, sorted set, stream, JSON, time series and Bloom filter values can be saved"
+        ),
+    }
+
+    Ok(())
+}
+
+/// Module data a JSON document, time series or Bloom filter is saved as, `None` for
+/// other values
+pub fn module_data(value: &RedisValue) -> Option<Vec<u8>> {
+    let mut res = vec![];
+    match value {
+        RedisValue::Json(document) => write_module_string(&mut res, document),
+        RedisValue::TimeSeries(series) => write_timeseries(&mut res, series),
+        RedisValue::Bloom(filter) => write_bloom(&mut res, filter),
+        _ => return None,
+    }
+
+    Some(res)
+}
+
+fn write_rdb_string(buf: &mut Vec<u8>, data: &[u8]) {
+    write_length_encoding(buf, data.len());
+    buf.extend_from_slice(data);
+}
+
+fn write_length_encoding(buf: &mut Vec<u8>, len: usize) {
+    match len {
+        0..=0x3f => buf.push(len as u8),
+        0x40..=0x3fff => buf.extend([0b01000000 | (len >> 8) as u8, len as u8]),
+        0x4000..=0xffffffff => {
+            buf.push(0x80);
+            buf.extend((len as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(0x81);
+            buf.extend((len as u64).to_be_bytes());
+        }
+    }
+}
+
+fn byte_at(buf: &[u8], pos: usize) -> Result<u8> {
+    buf.get(pos)
+        .copied()
+        .ok_or_else(|| anyhow!("Unexpected end of RDB data at offset {}", pos))
+}
+
+fn slice_at(buf: &[u8], pos: usize, len: usize) -> Result<&[u8]> {
+    pos.checked_add(len)
+        .and_then(|end| buf.get(pos..end))
+        .ok_or_else(|| anyhow!("Unexpected end of RDB data at offset {}", pos))
+}
+
+fn parse_rdb_string(buf: &[u8], pos: usize) -> Result<(RedisValue, usize)> {
+    // --- special encoding, integers stored as little endian binary
+    let enconding_byte = byte_at(buf, pos)?;
+    if enconding_byte & LEN_ENCODING_MASK == LEN_ENCODING_MASK {
+        let (value, next_pos) = match enconding_byte & LEN_DECODING_MASK {
+            0 => (byte_at(buf, pos + 1)? as i8 as i64, pos + 2),
+            1 => {
+                let raw = slice_at(buf, pos + 1, 2)?
+                    .try_into()
+                    .expect("Should be 2 bytes");
+                (i16::from_le_bytes(raw) as i64, pos + 3)
+            }
+            2 => {
+                let raw = slice_at(buf, pos + 1, 4)?
+                    .try_into()
+                    .expect("Should be 4 bytes");
+                (i32::from_le_bytes(raw) as i64, pos + 5)
+            }
+            3 => {
+                let (compressed_len, next) = parse_length_encoding(buf, pos + 1)?;
+                let (len, next) = parse_length_encoding(buf, next)?;
+                let compressed = slice_at(buf, next, compressed_len)?;
+                let parsed = RedisValue::BulkString(Bytes::from(lzf_decompress(compressed, len)?));
+                return Ok((parsed, next + compressed_len));
+            }
+            encoding => bail!("Invalid string encoding: {}", encoding),
+        };
+        let parsed = RedisValue::BulkString(Bytes::from(value.to_string()));
+        return Ok((parsed, next_pos));
+    }
+
+    let (str_len, next_pos) = parse_length_encoding(buf, pos)?;
+
+    let raw_str = slice_at(buf, next_pos, str_len).map_err(|_| {
+        anyhow!(
+            "Buffer overflow when parsing string: needed {} bytes but got {}",
+            str_len,
+            buf.len().saturating_sub(next_pos)
+        )
+    })?;
+    let parsed = RedisValue::BulkString(Bytes::copy_from_slice(raw_str));
+    Ok((parsed, next_pos + str_len))
+}
+
+/// Steps over a string without decoding it, the end it reports is only checked to be
+/// within the buffer
+fn skip_rdb_string(buf: &[u8], pos: usize) -> Result<usize> {
+    let enconding_byte = byte_at(buf, pos)?;
+    let (len, next) = match enconding_byte & LEN_DECODING_MASK {
+        _ if enconding_byte & LEN_ENCODING_MASK != LEN_ENCODING_MASK => {
+            parse_length_encoding(buf, pos)?
+        }
+        0 => (1, pos + 1),
+        1 => (2, pos + 1),
+        2 => (4, pos + 1),
+        3 => parse_length_encoding(buf, pos + 1).and_then(|(compressed_len, next)| {
+            Ok((compressed_len, parse_length_encoding(buf, next)?.1))
+        })?,
+        encoding => bail!("Invalid string encoding: {}", encoding),
+    };
+
+    slice_at(buf, next, len).map(|_| next + len)
+}
+
+/// Expands an LZF compressed string, which Redis uses for values over 20 bytes
+fn lzf_decompress(data: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut res = Vec::with_capacity(len);
+    let mut pos = 0;
+    while pos < data.len() {
+        let ctrl = data[pos] as usize;
+        pos += 1;
+
+        // --- literal run of ctrl + 1 bytes
+        if ctrl < 32 {
+            res.extend_from_slice(slice_at(data, pos, ctrl + 1)?);
+            pos += ctrl + 1;
+            continue;
+        }
+
+        // --- back reference, the 3 high bits are the length and the rest the distance
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            run += byte_at(data, pos)? as usize;
+            pos += 1;
+        }
+        let distance = ((ctrl & 0x1f) << 8) + byte_at(data, pos)? as usize + 1;
+        pos += 1;
+        let start = res
+            .len()
+            .checked_sub(distance)
+            .ok_or_else(|| anyhow!("Invalid back reference in LZF data at offset {}", pos - 1))?;
+        // --- may overlap what it produces, copied one byte at a time
+        for i in start..start + run + 2 {
+            res.push(res[i]);
+        }
+    }
+    ensure!(
+        res.len() == len,
+        "LZF data expands to {} bytes instead of {}",
+        res.len(),
+        len
+    );
+
+    Ok(res)
+}
+
+/// Steps over the value of a key of a type only stored by Redis, returning the offset of
+/// the next record
+fn skip_value(buf: &[u8], pos: usize, value_type: u8) -> Result<usize> {
+    let res = match value_type {
+        TYPE_HASH_ZIPMAP => parse_rdb_string(buf, pos)?.1,
+        TYPE_HASH_METADATA => {
+            // --- earliest field expiry, then fields with their TTL ahead of each pair
+            let (len, mut next) = parse_length_encoding(buf, pos + 8)?;
+            for _ in 0..len {
+                let (_, after_ttl) = parse_length_encoding(buf, next)?;
+                next = skip_strings_at(buf, after_ttl, 2)?;
+            }
+            next
+        }
+        TYPE_HASH_LISTPACK_EX => parse_rdb_string(buf, pos + 8)?.1,
+        value_type => bail!("Invalid encoding for value: {:x?}", value_type),
+    };
+    // --- fixed size fields are stepped over blindly, make sure they were there
+    ensure!(
+        res <= buf.len(),
+        "Unexpected end of RDB data at offset {}",
+        buf.len()
+    );
+
+    Ok(res)
+}
+
+/// A list, set, sorted set or hash out of the ziplist, listpack or intset Redis saved it
+/// as. Sorted sets and hashes alternate members and scores, fields and values
+fn compact_value(blob: &[u8], value_type: u8) -> Result<RedisValue> {
+    let elements = match value_type {
+        TYPE_SET_INTSET => intset_elements(blob)?,
+        TYPE_LIST_ZIPLIST | TYPE_ZSET_ZIPLIST | TYPE_HASH_ZIPLIST => ziplist_elements(blob)?,
+        _ => listpack_elements(blob)?,
+    };
+    let pairs = || {
+        ensure!(
+            elements.len() % 2 == 0,
+            "Odd number of elements in a {} blob",
+            type_name(value_type)
+        );
+        Ok(elements
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone())))
+    };
+
+    let res = match value_type {
+        TYPE_LIST_ZIPLIST => RedisValue::List(elements.into_iter().collect()),
+        TYPE_SET_INTSET | TYPE_SET_LISTPACK => RedisValue::Set(elements.into_iter().collect()),
+        TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+            let mut zset = SortedSet::default();
+            for (member, score) in pairs()? {
+                let score = str::from_utf8(&score)
+                    .ok()
+                    .and_then(|score| score.parse::<f64>().ok())
+                    .filter(|score| !score.is_nan())
+                    .ok_or_else(|| anyhow!("Invalid score {:?}", score))?;
+                zset.insert(member, score);
+            }
+            RedisValue::SortedSet(zset)
+        }
+        _ => RedisValue::Hash(pairs()?.collect()),
+    };
+
+    Ok(res)
+}
+
+/// Elements of a listpack, integers as their text
+fn listpack_elements(listpack: &[u8]) -> Result<Vec<Bytes>> {
+    let mut reader = ListpackReader {
+        buf: listpack,
+        pos: 6,
+    };
+    let mut res = vec![];
+    while byte_at(listpack, reader.pos)? != LISTPACK_END {
+        res.push(reader.string()?);
+    }
+
+    Ok(res)
+}
+
+/// Elements of a ziplist, the listpack's predecessor: a header of 10 bytes, then each
+/// element behind the length of the previous one, integers as their text
+fn ziplist_elements(ziplist: &[u8]) -> Result<Vec<Bytes>> {
+    let mut res = vec![];
+    let mut pos = 10;
+    loop {
+        let prev_len = byte_at(ziplist, pos)?;
+        if prev_len == ZIPLIST_END {
+            break;
+        }
+        pos += if prev_len == 0xfe { 5 } else { 1 };
+        let encoding = byte_at(ziplist, pos)?;
+        let int = |len: usize| -> Result<i64> {
+            let data = slice_at(ziplist, pos + 1, len)?;
+            let mut raw = [0; 8];
+            raw[..len].copy_from_slice(data);
+            let shift = 64 - 8 * len as u32;
+            Ok(i64::from_le_bytes(raw) << shift >> shift)
+        };
+        let (element, len) = match encoding {
+            0x00..=0x3f => {
+                let len = encoding as usize;
+                (Err(slice_at(ziplist, pos + 1, len)?), 1 + len)
+            }
+            0x40..=0x7f => {
+                let len = (((encoding & 0x3f) as usize) << 8) | byte_at(ziplist, pos + 1)? as usize;
+                (Err(slice_at(ziplist, pos + 2, len)?), 2 + len)
+            }
+            0x80 => {
+                let len = u32::from_be_bytes(
+                    slice_at(ziplist, pos + 1, 4)?
+                        .try_into()
+                        .expect("Should be 4 bytes"),
+                ) as usize;
+                (Err(slice_at(ziplist, pos + 5, len)?), 5 + len)
+            }
+            0xc0 => (Ok(int(2)?), 3),
+            0xd0 => (Ok(int(4)?), 5),
+            0xe0 => (Ok(int(8)?), 9),
+            0xf0 => (Ok(int(3)?), 4),
+            0xfe => (Ok(int(1)?), 2),
+            // --- 4 bit immediates from 1 to 13 standing for 0 to 12
+            0xf1..=0xfd => (Ok((encoding & 0x0f) as i64 - 1), 1),
+            _ => bail!("Invalid ziplist element at offset {}", pos),
+        };
+        res.push(match element {
+            Ok(value) => Bytes::from(value.to_string()),
+            Err(data) => Bytes::copy_from_slice(data),
+        });
+        pos += len;
+    }
+
+    Ok(res)
+}
+
+/// Members of an intset: the width of its integers, their count, then the integers in
+/// ascending order
+fn intset_elements(intset: &[u8]) -> Result<Vec<Bytes>> {
+    let width = u32::from_le_bytes(
+        slice_at(intset, 0, 4)?
+            .try_into()
+            .expect("Should be 4 bytes"),
+    );
+    let len = u32::from_le_bytes(
+        slice_at(intset, 4, 4)?
+            .try_into()
+            .expect("Should be 4 bytes"),
+    );
+    ensure!(
+        matches!(width, 2 | 4 | 8),
+        "Invalid intset encoding {}",
+        width
+    );
+    let width = width as usize;
+    let data = slice_at(intset, 8, width.saturating_mul(len as usize))?;
+
+    let res = data
+        .chunks_exact(width)
+        .map(|raw| {
+            let value = match width {
+                2 => i16::from_le_bytes(raw.try_into().expect("Should be 2 bytes")) as i64,
+                4 => i32::from_le_bytes(raw.try_into().expect("Should be 4 bytes")) as i64,
+                _ => i64::from_le_bytes(raw.try_into().expect("Should be 8 bytes")),
+            };
+            Bytes::from(value.to_string())
+        })
+        .collect();
+
+    Ok(res)
+}
+
+/// Steps over a count of groups of `group` strings
+fn skip_strings(buf: &[u8], pos: usize, group: usize) -> Result<usize> {
+    let (len, next) = parse_length_encoding(buf, pos)?;
+    let res = skip_strings_at(
+        buf,
+        next,
+        len.checked_mul(group)
+            .ok_or_else(|| anyhow!("Invalid length at offset {}", pos))?,
+    )?;
+
+    Ok(res)
+}
+
+fn skip_strings_at(buf: &[u8], mut pos: usize, count: usize) -> Result<usize> {
+    for _ in 0..count {
+        pos = parse_rdb_string(buf, pos)?.1;
+    }
+
+    Ok(pos)
+}
+
+fn skip_lengths(buf: &[u8], mut pos: usize, count: usize) -> Result<usize> {
+    for _ in 0..count {
+        pos = parse_length_encoding(buf, pos)?.1;
+    }
+
+    Ok(pos)
+}
+
+/// Steps over a stream: its listpacks, metadata and consumer groups
+fn skip_stream(buf: &[u8], pos: usize, value_type: u8) -> Result<usize> {
+    // --- listpacks keyed by their master entry ID
+    let mut next = skip_strings(buf, pos, 2)?;
+    // --- length and last ID, then first ID, max deleted ID and entries added since v2
+    next = skip_lengths(buf, next, 3)?;
+    if value_type >= TYPE_STREAM_LISTPACKS_2 {
+        next = skip_lengths(buf, next, 5)?;
+    }
+
+    let (groups, after_groups) = parse_length_encoding(buf, next)?;
+    next = after_groups;
+    for _ in 0..groups {
+        // --- name and last delivered ID, then the entries read since v2
+        next = parse_rdb_string(buf, next)?.1;
+        next = skip_lengths(buf, next, 2)?;
+        if value_type >= TYPE_STREAM_LISTPACKS_2 {
+            next = skip_lengths(buf, next, 1)?;
+        }
+        // --- pending entries: raw 16 byte ID, delivery time and count
+        let (pending, after_pending) = parse_length_encoding(buf, next)?;
+        next = after_pending;
+        for _ in 0..pending {
+            next = skip_lengths(buf, next + 16 + 8, 1)?;
+        }
+        let (consumers, after_consumers) = parse_length_encoding(buf, next)?;
+        next = after_consumers;
+        for _ in 0..consumers {
+            // --- name, seen time, active time since v3, then IDs of its pending entries
+            next = parse_rdb_string(buf, next)?.1 + 8;
+            if value_type >= TYPE_STREAM_LISTPACKS_3 {
+                next += 8;
+            }
+            let (pending, after_pending) = parse_length_encoding(buf, next)?;
+            next = after_pending
+                .checked_add(pending.saturating_mul(16))
+                .ok_or_else(|| anyhow!("Invalid length at offset {}", next))?;
+        }
+    }
+
+    Ok(next)
+}
+
+/// Entries of a stream. Consumer groups aren't supported, a stream with any is left out
+/// the way values of unsupported types are
+fn parse_stream(buf: &[u8], pos: usize, value_type: u8) -> Result<Option<Stream>> {
+    let (nodes, mut next) = parse_length_encoding(buf, pos)?;
+    let mut listpacks = vec![];
+    for _ in 0..nodes {
+        let (master, after_master) = parse_rdb_string(buf, next)?;
+        let (listpack, after_listpack) = parse_rdb_string(buf, after_master)?;
+        let (RedisValue::BulkString(master), RedisValue::BulkString(listpack)) = (master, listpack)
+        else {
+            bail!("Invalid stream listpack at offset {}", next);
+        };
+        ensure!(
+            master.len() == 16,
+            "Invalid stream master ID at offset {}",
+            next
+        );
+        let master = StreamId {
+            ms: u64::from_be_bytes(master[..8].try_into().expect("Should be 8 bytes")),
+            seq: u64::from_be_bytes(master[8..].try_into().expect("Should be 8 bytes")),
+        };
+        listpacks.push((master, listpack));
+        next = after_listpack;
+    }
+    // --- length, then last ID
+    let (_, after_len) = parse_length_encoding(buf, next)?;
+    let (ms, after_ms) = parse_length_encoding(buf, after_len)?;
+    let (seq, mut next) = parse_length_encoding(buf, after_ms)?;
+    let last_id = StreamId {
+        ms: ms as u64,
+        seq: seq as u64,
+    };
+    if value_type >= TYPE_STREAM_LISTPACKS_2 {
+        next = skip_lengths(buf, next, 5)?;
+    }
+    let (groups, _) = parse_length_encoding(buf, next)?;
+    if groups > 0 {
+        return Ok(None);
+    }
+
+    let mut entries = BTreeMap::new();
+    for (master, listpack) in listpacks {
+        parse_stream_listpack(&listpack, master, &mut entries)?;
+    }
+
+    Stream::from_parts(entries, last_id).map(Some)
+}
+
+/// Entries of a stream listpack: a master entry holding the number of entries and the
+/// fields they may share, then each entry with its ID relative to the master one
+fn parse_stream_listpack(
+    listpack: &[u8],
+    master: StreamId,
+    entries: &mut BTreeMap<StreamId, Vec<(Bytes, Bytes)>>,
+) -> Result<()> {
+    let mut reader = ListpackReader {
+        buf: listpack,
+        pos: 6,
+    };
+    let count = reader.integer()? + reader.integer()?;
+    let master_fields = (0..reader.integer()?)
+        .map(|_| reader.string())
+        .collect::<Result<Vec<_>>>()?;
+    // --- master entry terminator
+    reader.integer()?;
+
+    for _ in 0..count {
+        let flags = reader.integer()?;
+        let id = StreamId {
+            ms: master.ms.wrapping_add(reader.integer()? as u64),
+            seq: master.seq.wrapping_add(reader.integer()? as u64),
+        };
+        let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Ok((field.clone(), reader.string()?)))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            (0..reader.integer()?)
+                .map(|_| Ok((reader.string()?, reader.string()?)))
+                .collect::<Result<Vec<_>>>()?
+        };
+        // --- number of elements of the entry, for reading backwards
+        reader.integer()?;
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            entries.insert(id, fields);
+        }
+    }
+    ensure!(
+        byte_at(listpack, reader.pos)? == LISTPACK_END,
+        "Stream listpack not terminated at offset {}",
+        reader.pos
+    );
+
+    Ok(())
+}
+
+/// Stream as Redis 7 saves it: listpacks of up to 100 entries keyed by the ID of their
+/// first one, its length and IDs, then no consumer groups. Entries are written with
+/// their own fields, never relying on those of the master entry
+fn write_stream(buf: &mut Vec<u8>, stream: &Stream) {
+    let entries = stream.entries().collect::<Vec<_>>();
+    let nodes = entries.chunks(STREAM_NODE_MAX_ENTRIES).collect::<Vec<_>>();
+    write_length_encoding(buf, nodes.len());
+    for node in nodes {
+        let master = *node[0].0;
+        let mut master_key = master.ms.to_be_bytes().to_vec();
+        master_key.extend(master.seq.to_be_bytes());
+        write_rdb_string(buf, &master_key);
+
+        let mut listpack = ListpackWriter::default();
+        // --- entries, deleted ones, master fields and the master entry terminator
+        for value in [node.len() as i64, 0, 0, 0] {
+            listpack.integer(value);
+        }
+        for (id, fields) in node {
+            listpack.integer(0);
+            listpack.integer(id.ms.wrapping_sub(master.ms) as i64);
+            listpack.integer(id.seq.wrapping_sub(master.seq) as i64);
+            listpack.integer(fields.len() as i64);
+            for (field, value) in fields.iter() {
+                listpack.string(field);
+                listpack.string(value);
+            }
+            listpack.integer(2 * fields.len() as i64 + 4);
+        }
+        write_rdb_string(buf, &listpack.finish());
+    }
+
+    let first_id = entries.first().map_or(StreamId::MIN, |(id, _)| **id);
+    let last_id = stream.last_id();
+    // --- then first ID, max deleted ID and entries added, none of the entries deleted
+    for value in [
+        stream.len(),
+        last_id.ms as usize,
+        last_id.seq as usize,
+        first_id.ms as usize,
+        first_id.seq as usize,
+        0,
+        0,
+        stream.len(),
+        0,
+    ] {
+        write_length_encoding(buf, value);
+    }
+}
+
+/// Reader of the elements of a listpack, each an integer or a string
+struct ListpackReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+impl ListpackReader<'_> {
+    /// Next element, a string or the text of an integer
+    fn string(&mut self) -> Result<Bytes> {
+        let res = match self.element()? {
+            Ok(value) => Bytes::from(value.to_string()),
+            Err(data) => Bytes::copy_from_slice(data),
+        };
+
+        Ok(res)
+    }
+
+    /// Next element, an integer or a string holding one
+    fn integer(&mut self) -> Result<i64> {
+        let pos = self.pos;
+        let res = match self.element()? {
+            Ok(value) => value,
+            Err(data) => str::from_utf8(data)
+                .ok()
+                .and_then(|text| text.parse().ok())
+                .ok_or_else(|| anyhow!("Expected a listpack integer at offset {}", pos))?,
+        };
+
+        Ok(res)
+    }
+
+    /// Next element, an integer or the data of a string, moving past its back length
+    fn element(&mut self) -> Result<Result<i64, &[u8]>> {
+        let buf = self.buf;
+        let pos = self.pos;
+        let first = byte_at(buf, pos)?;
+        let int = |len: usize| -> Result<i64> {
+            let data = slice_at(buf, pos + 1, len)?;
+            let mut raw = [0; 8];
+            raw[..len].copy_from_slice(data);
+            // --- sign extended from the top bit of the encoded width
+            let shift = 64 - 8 * len as u32;
+            Ok(i64::from_le_bytes(raw) << shift >> shift)
+        };
+        let (res, len) = match first {
+            0x00..=0x7f => (Ok(first as i64), 1),
+            0x80..=0xbf => {
+                let len = (first & 0x3f) as usize;
+                (Err(slice_at(buf, pos + 1, len)?), 1 + len)
+            }
+            0xc0..=0xdf => {
+                let value = (((first & 0x1f) as i64) << 8) | byte_at(buf, pos + 1)? as i64;
+                (Ok(value << 51 >> 51), 2)
+            }
+            0xe0..=0xef => {
+                let len = (((first & 0x0f) as usize) << 8) | byte_at(buf, pos + 1)? as usize;
+                (Err(slice_at(buf, pos + 2, len)?), 2 + len)
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(
+                    slice_at(buf, pos + 1, 4)?
+                        .try_into()
+                        .expect("Should be 4 bytes"),
+                ) as usize;
+                (Err(slice_at(buf, pos + 5, len)?), 5 + len)
+            }
+            0xf1 => (Ok(int(2)?), 3),
+            0xf2 => (Ok(int(3)?), 4),
+            0xf3 => (Ok(int(4)?), 5),
+            0xf4 => (Ok(int(8)?), 9),
+            _ => bail!("Invalid listpack element at offset {}", pos),
+        };
+        self.pos = pos + len + backlen_size(len);
+
+        Ok(res)
+    }
+}
+
+/// Listpack built an element at a time
+#[derive(Default)]
+struct ListpackWriter {
+    elements: Vec<u8>,
+    count: usize,
+}
+impl ListpackWriter {
+    fn integer(&mut self, value: i64) {
+        let start = self.elements.len();
+        match value {
+            0..=0x7f => self.elements.push(value as u8),
+            _ => {
+                self.elements.push(0xf4);
+                self.elements.extend(value.to_le_bytes());
+            }
+        }
+        self.close_element(start);
+    }
+
+    fn string(&mut self, data: &[u8]) {
+        let start = self.elements.len();
+        match data.len() {
+            len @ 0..=0x3f => self.elements.push(0x80 | len as u8),
+            len @ 0x40..=0xfff => self.elements.extend([0xe0 | (len >> 8) as u8, len as u8]),
+            len => {
+                self.elements.push(0xf0);
+                self.elements.extend((len as u32).to_le_bytes());
+            }
+        }
+        self.elements.extend_from_slice(data);
+        self.close_element(start);
+    }
+
+    /// Appends the back length of the element written from `start`, its size 7 bits at
+    /// a time, most significant first
+    fn close_element(&mut self, start: usize) {
+        let len = self.elements.len() - start;
+        let size = backlen_size(len);
+        for i in (0..size).rev() {
+            let bits = ((len >> (7 * i)) & 0x7f) as u8;
+            self.elements
+                .push(if i + 1 < size { bits | 0x80 } else { bits });
+        }
+        self.count += 1;
+    }
+
+    /// The listpack: total size and number of elements, the elements, then the end byte
+    fn finish(self) -> Vec<u8> {
+        let mut res = ((self.elements.len() + 7) as u32).to_le_bytes().to_vec();
+        res.extend((self.count.min(u16::MAX as usize) as u16).to_le_bytes());
+        res.extend(self.elements);
+        res.push(LISTPACK_END);
+
+        res
+    }
+}
+
+/// Bytes the back length of a listpack element of `len` bytes takes
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// 64 bit ID of a module type: its 9 character name then a 10 bit encoding version
+fn module_id(name: &str, encver: u64) -> u64 {
+    let id = name.bytes().fold(0, |id, c| {
+        let index = MODULE_NAME_CHARSET
+            .iter()
+            .position(|valid| *valid == c)
+            .expect("Module names only use the module charset");
+        (id << 6) | index as u64
+    });
+
+    (id << 10) | encver
+}
+
+/// Value of a module type this server has one of its own for, `None` for the others
+fn parse_module_value(buf: &[u8], id: u64, pos: usize) -> Result<Option<RedisValue>> {
+    let encver = id & 0x3ff;
+    let mut data = ModuleData { buf, pos };
+    let res = if id >> 10 == module_id(JSON_MODULE_NAME, 0) >> 10 {
+        ensure!(
+            encver == JSON_MODULE_ENCVER,
+            "Unsupported {} encoding version {}",
+            JSON_MODULE_NAME,
+            encver
+        );
+        RedisValue::Json(data.string()?)
+    } else if id >> 10 == module_id(TIMESERIES_MODULE_NAME, 0) >> 10 {
+        ensure!(
+            encver == TIMESERIES_MODULE_ENCVER,
+            "Unsupported {} encoding version {}",
+            TIMESERIES_MODULE_NAME,
+            encver
+        );
+        RedisValue::TimeSeries(Box::new(parse_timeseries(&mut data)?))
+    } else if id >> 10 == module_id(BLOOM_MODULE_NAME, 0) >> 10 {
+        ensure!(
+            encver == BLOOM_MODULE_ENCVER,
+            "Unsupported {} encoding version {}",
+            BLOOM_MODULE_NAME,
+            encver
+        );
+        RedisValue::Bloom(Box::new(parse_bloom(&mut data)?))
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(res))
+}
+
+/// Layout of `write_timeseries`
+fn parse_timeseries(data: &mut ModuleData) -> Result<TimeSeries> {
+    let retention = data.uint()? as u64;
+    let mut rules = vec![];
+    for _ in 0..data.uint()? {
+        let dest = data.string()?;
+        let aggregation = data.string()?;
+        let Some(aggregation) = str::from_utf8(&aggregation)
+            .ok()
+            .and_then(Aggregation::parse)
+        else {
+            bail!("Invalid time series aggregation at offset {}", data.pos);
+        };
+        let bucket_duration = data.uint()? as u64;
+        ensure!(bucket_duration > 0, "Invalid time series bucket duration");
+        let bucket_start = data.uint()?.checked_sub(1).map(|start| start as u64);
+        rules.push(Rule {
+            dest,
+            aggregation,
+            bucket_duration,
+            bucket_start,
+        });
+    }
+    let count = data.uint()?;
+    let mut samples = Vec::with_capacity(count.min(data.buf.len()));
+    for _ in 0..count {
+        samples.push(Sample {
+            timestamp: data.uint()? as u64,
+            value: data.double()?,
+        });
+    }
+
+    TimeSeries::from_parts(retention, rules, samples)
+}
+
+/// Time series as module data: the retention, the rules (destination, aggregation,
+/// bucket duration, bucket start plus one or 0), then the samples
+fn write_timeseries(buf: &mut Vec<u8>, series: &TimeSeries) {
+    write_module_uint(buf, series.retention as usize);
+    write_module_uint(buf, series.rules.len());
+    for rule in series.rules.iter() {
+        write_module_string(buf, &rule.dest);
+        write_module_string(buf, rule.aggregation.name().as_bytes());
+        write_module_uint(buf, rule.bucket_duration as usize);
+        write_module_uint(buf, rule.bucket_start.map_or(0, |start| start as usize + 1));
+    }
+    write_module_uint(buf, series.samples().len());
+    for sample in series.samples() {
+        write_module_uint(buf, sample.timestamp as usize);
+        write_length_encoding(buf, MODULE_OPCODE_DOUBLE);
+        buf.extend(sample.value.to_le_bytes());
+    }
+}
+
+/// Layout of `write_bloom`
+fn parse_bloom(data: &mut ModuleData) -> Result<BloomFilter> {
+    let error_rate = data.double()?;
+    let expansion = data.uint()? as u32;
+    let mut layers = vec![];
+    for _ in 0..data.uint()? {
+        let hashes = data.uint()? as u32;
+        let capacity = data.uint()? as u64;
+        let count = data.uint()? as u64;
+        let bits = data.string()?.to_vec();
+        layers.push(BloomLayer {
+            bits,
+            hashes,
+            capacity,
+            count,
+        });
+    }
+
+    BloomFilter::from_parts(error_rate, expansion, layers)
+}
+
+/// Bloom filter as module data: the error rate, the expansion, then each layer (number
+/// of hashes, capacity, items added, bits)
+fn write_bloom(buf: &mut Vec<u8>, filter: &BloomFilter) {
+    write_length_encoding(buf, MODULE_OPCODE_DOUBLE);
+    buf.extend(filter.error_rate.to_le_bytes());
+    write_module_uint(buf, filter.expansion as usize);
+    write_module_uint(buf, filter.layers().len());
+    for layer in filter.layers() {
+        write_module_uint(buf, layer.hashes as usize);
+        write_module_uint(buf, layer.capacity as usize);
+        write_module_uint(buf, layer.count as usize);
+        write_module_string(buf, &layer.bits);
+    }
+}
+
+fn write_module_uint(buf: &mut Vec<u8>, value: usize) {
+    write_length_encoding(buf, MODULE_OPCODE_UINT);
+    write_length_encoding(buf, value);
+}
+
+fn write_module_string(buf: &mut Vec<u8>, data: &[u8]) {
+    write_length_encoding(buf, MODULE_OPCODE_STRING);
+    write_rdb_string(buf, data);
+}
+
+/// Reader of the typed values module data is made of, each checked against its opcode
+struct ModuleData<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+impl ModuleData<'_> {
+    fn opcode(&mut self, expected: usize) -> Result<()> {
+        let (opcode, next) = parse_length_encoding(self.buf, self.pos)?;
+        ensure!(
+            opcode == expected,
+            "Unexpected module data opcode {} at offset {}",
+            opcode,
+            self.pos
+        );
+        self.pos = next;
+
+        Ok(())
+    }
+
+    fn uint(&mut self) -> Result<usize> {
+        self.opcode(MODULE_OPCODE_UINT)?;
+        let (res, next) = parse_length_encoding(self.buf, self.pos)?;
+        self.pos = next;
+
+        Ok(res)
+    }
+
+    fn double(&mut self) -> Result<f64> {
+        self.opcode(MODULE_OPCODE_DOUBLE)?;
+        let raw = slice_at(self.buf, self.pos, 8)?
+            .try_into()
+            .expect("Should be 8 bytes");
+        self.pos += 8;
+
+        Ok(f64::from_le_bytes(raw))
+    }
+
+    fn string(&mut self) -> Result<Bytes> {
+        self.opcode(MODULE_OPCODE_STRING)?;
+        let (value, next) = parse_rdb_string(self.buf, self.pos)?;
+        let RedisValue::BulkString(res) = value else {
+            bail!("Invalid module string at offset {}", self.pos);
+        };
+        self.pos = next;
+
+        Ok(res)
+    }
+}
+
+/// Steps over data serialized by a module, a sequence of typed values up to an EOF opcode
+fn skip_module_data(buf: &[u8], mut pos: usize) -> Result<usize> {
+    loop {
+        let (opcode, next) = parse_length_encoding(buf, pos)?;
+        pos = match opcode {
+            MODULE_OPCODE_EOF => return Ok(next),
+            MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => parse_length_encoding(buf, next)?.1,
+            MODULE_OPCODE_FLOAT => slice_at(buf, next, 4).map(|_| next + 4)?,
+            MODULE_OPCODE_DOUBLE => slice_at(buf, next, 8).map(|_| next + 8)?,
+            MODULE_OPCODE_STRING => parse_rdb_string(buf, next)?.1,
+            opcode => bail!("Invalid module data opcode {} at offset {}", opcode, pos),
+        };
+    }
+}
+
+fn parse_length_encoding(buf: &[u8], pos: usize) -> Result<(usize, usize)> {
+    let enconding_byte = byte_at(buf, pos)?;
+    match enconding_byte & LEN_ENCODING_MASK {
+        // --- one byte length
+        0b00000000 => Ok(((enconding_byte & LEN_DECODING_MASK) as usize, pos + 1)),
+        // --- 14 bit length
+        0b01000000 => {
+            let next_byte = byte_at(buf, pos + 1)?;
+            let len = (((enconding_byte & LEN_DECODING_MASK) as usize) << 8) | next_byte as usize;
+            Ok((len, pos + 2))
+        }
+        // --- 8 byte length
+        0b10000000 if enconding_byte == 0x81 => Ok((
+            u64::from_be_bytes(
+                slice_at(buf, pos + 1, 8)?
+                    .try_into()
+                    .expect("Should be an 8 byte slice"),
+            ) as usize,
+            pos + 9,
+        )),
+        // --- 4 byte length
+        0b10000000 if enconding_byte == 0x80 => Ok((
+            u32::from_be_bytes(
+                slice_at(buf, pos + 1, 4)?
+                    .try_into()
+                    .expect("Should be a 4 byte slice"),
+            ) as usize,
+            pos + 5,
+        )),
+        0b10000000 => bail!(
+            "Invalid length encoding {:#x} at offset {}",
+            enconding_byte,
+            pos
+        ),
+        // --- special encodings are for strings, never lengths
+        _ => bail!("Unexpected string encoding at offset {}", pos),
+    }
+}
+
+/// Compares the checksum following the EOF opcode at `end` with the one of the data up
+/// to it. Files without one, from before version 5 or saved with `rdbchecksum no` and
+/// then zeroed, are taken as they are
+fn verify_checksum(buf: &[u8], end: usize) -> Result<()> {
+    let Ok(raw) = slice_at(buf, end, 8) else {
+        return Ok(());
+    };
+    let expected = u64::from_le_bytes(raw.try_into().expect("Should be 8 bytes"));
+    let actual = crc64(&buf[..end]);
+    ensure!(
+        expected == 0 || expected == actual,
+        "Wrong RDB checksum, expected {:016x} but got {:016x}",
+        expected,
+        actual
+    );
+
+    Ok(())
+}
+
+fn crc64(data: &[u8]) -> u64 {
+    data.iter().fold(0, |crc, byte| {
+        CRC64_TABLE[((crc ^ *byte as u64) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use bytes::{Bytes, BytesMut};
+use tokio::{task::JoinSet, time::Instant};
+
+use super::{handler::RedisValue, serde::tokenize};
+use crate::client::RedisClient;
+
+/// A command as it reached the server, one entry of a command log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedCommand {
+    /// unix time in milliseconds
+    pub timestamp: u64,
+    pub client_id: u64,
+    /// the command name followed by its arguments
+    pub args: Vec<Bytes>,
+}
+impl RecordedCommand {
+    /// Log entries are RESP arrays of bulk strings: the timestamp, the client ID and then
+    /// the command itself, so the log reads like an AOF with two extra leading arguments
+    pub fn serialize(&self) -> Result<Bytes> {
+        let entry = [
+            Bytes::from(self.timestamp.to_string()),
+            Bytes::from(self.client_id.to_string()),
+        ]
+        .into_iter()
+        .chain(self.args.iter().cloned())
+        .map(RedisValue::BulkString)
+        .collect();
+
+        RedisValue::Array(entry).serialize()
+    }
+}
+
+/// Appends every command the server accepts to a log file, `--record-commands`. Entries
+/// are buffered and written out by `flush`, which the cron runs
+pub struct CommandRecorder {
+    file: Mutex<BufWriter<File>>,
+}
+impl CommandRecorder {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn record(&self, command: &RecordedCommand) -> Result<()> {
+        let entry = command.serialize()?;
+        self.file.lock().unwrap().write_all(&entry)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.file.lock().unwrap().flush()?;
+
+        Ok(())
+    }
+}
+
+/// Reads back a command log. A truncated last entry, e.g. from a crash mid-write, is
+/// dropped, anything else malformed fails the whole log
+pub fn parse_log(data: Bytes) -> Result<Vec<RecordedCommand>> {
+    let buf = BytesMut::from(&data[..]);
+
+    let mut res = vec![];
+    let mut pos = 0;
+    while let Some(token) = tokenize(&buf, pos)? {
+        let entry_start = pos;
+        pos = token.1;
+        let RedisValue::Array(parts) = RedisValue::from_token(token.0, &data) else {
+            bail!("Log entry at offset {} is not an array", entry_start);
+        };
+        let mut parts = parts.into_iter().map(|part| match part {
+            RedisValue::BulkString(part) => Ok(part),
+            _ => Err(anyhow!(
+                "Log entry at offset {} has a non bulk string part",
+                entry_start
+            )),
+        });
+        let mut number = || -> Result<u64> {
+            let part = parts
+                .next()
+                .ok_or_else(|| anyhow!("Log entry at offset {} is too short", entry_start))??;
+            Ok(std::str::from_utf8(&part)?.parse()?)
+        };
+        let timestamp = number()?;
+        let client_id = number()?;
+        let args = parts.collect::<Result<Vec<_>>>()?;
+        if args.is_empty() {
+            bail!("Log entry at offset {} has no command", entry_start);
+        }
+
+        res.push(RecordedCommand {
+            timestamp,
+            client_id,
+            args,
+        });
+    }
+    if pos < buf.len() {
+        log::warn!("Ignoring the truncated entry at the end of the command log");
+    }
+
+    Ok(res)
+}
+
+/// Outcome of `replay`
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub commands: usize,
+    /// commands the server replied to with an error
+    pub errors: usize,
+}
+
+/// Sends recorded commands to a server, each recorded client over a connection of its
+/// own. Commands keep their original spacing divided by `speed`, a speed of 0 sends them
+/// as fast as the server takes them
+pub async fn replay(
+    commands: Vec<RecordedCommand>,
+    addr: SocketAddr,
+    speed: f64,
+) -> Result<ReplayReport> {
+    let Some(first) = commands.first().map(|command| command.timestamp) else {
+        return Ok(ReplayReport::default());
+    };
+    let mut by_client: HashMap<u64, Vec<RecordedCommand>> = HashMap::new();
+    for command in commands {
+        by_client
+            .entry(command.client_id)
+            .or_default()
+            .push(command);
+    }
+
+    let start = Instant::now();
+    let mut clients = JoinSet::new();
+    for commands in by_client.into_values() {
+        clients.spawn(async move {
+            let mut client = RedisClient::connect(addr).await?;
+            let mut report = ReplayReport::default();
+            for command in commands {
+                if speed > 0.0 {
+                    let offset = command.timestamp.saturating_sub(first) as f64 / speed;
+                    tokio::time::sleep_until(start + Duration::from_secs_f64(offset / 1000.0))
+                        .await;
+                }
+                let reply = client.command(command.args).await?;
+                report.commands += 1;
+                if matches!(reply, RedisValue::SimpleError(_)) {
+                    report.errors += 1;
+                }
+            }
+
+            anyhow::Ok(report)
+        });
+    }
+
+    let mut res = ReplayReport::default();
+    while let Some(report) = clients.join_next().await {
+        let report = report??;
+        res.commands += report.commands;
+        res.errors += report.errors;
+    }
+
+    Ok(res)
+}
@@ -0,0 +1,518 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use mlua::{Function, HookTriggers, IntoLua, Lua, LuaOptions, MultiValue, StdLib, Table, Value};
+use sha1_smol::Sha1;
+use tokio::sync::{mpsc::UnboundedSender, oneshot, watch};
+
+use super::handler::RedisValue;
+
+/// `redis.call` and the helpers around it, on top of `redis.pcall` which hands the
+/// command over to the server
+const PRELUDE: &str = r#"
+redis.call = function(...)
+    local reply = redis.pcall(...)
+    if type(reply) == "table" and reply.err then
+        error(reply)
+    end
+    return reply
+end
+redis.error_reply = function(err)
+    return { err = err }
+end
+redis.status_reply = function(ok)
+    return { ok = ok }
+end
+"#;
+
+/// Locks the globals once the script's own are in place, the way Redis does: a script can
+/// neither create globals nor read ones that don't exist, and can't lift the lock
+const PROTECT_GLOBALS: &str = r#"
+setmetatable(_G, {
+    __index = function(_, name)
+        error("Script attempted to access nonexistent global variable '" .. tostring(name) .. "'", 2)
+    end,
+    __newindex = function(_, name)
+        error("Script attempted to create global variable '" .. tostring(name) .. "'", 2)
+    end,
+    __metatable = false,
+})
+"#;
+
+/// Bytes of memory the interpreter of a script may allocate, past which allocations fail
+/// and the script errors rather than taking the server's memory with it
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Lua instructions a script runs between two looks at its running time and SCRIPT KILL
+const HOOK_INSTRUCTIONS: u32 = 10_000;
+
+const KILLED: &str = "Script killed by user with SCRIPT KILL...";
+
+/// The script running at the moment, if any. Past `busy-reply-threshold` it is busy: other
+/// clients get -BUSY instead of waiting for it, and SCRIPT KILL may stop it
+#[derive(Debug)]
+pub struct RunningScript {
+    running: AtomicBool,
+    /// `busy-reply-threshold` when it started
+    threshold: Mutex<Duration>,
+    busy: watch::Sender<bool>,
+    killed: AtomicBool,
+    /// whether it ran a write, after which killing it would leave half its effects behind
+    wrote: AtomicBool,
+}
+impl Default for RunningScript {
+    fn default() -> Self {
+        Self {
+            running: AtomicBool::new(false),
+            threshold: Mutex::new(Duration::ZERO),
+            busy: watch::Sender::new(false),
+            killed: AtomicBool::new(false),
+            wrote: AtomicBool::new(false),
+        }
+    }
+}
+impl RunningScript {
+    pub fn start(&self, threshold: Duration) {
+        *self.threshold.lock().unwrap() = threshold;
+        self.killed.store(false, Ordering::SeqCst);
+        self.wrote.store(false, Ordering::SeqCst);
+        self.running.store(true, Ordering::SeqCst);
+    }
+
+    pub fn finish(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.busy.send_replace(false);
+    }
+
+    pub fn record_write(&self) {
+        self.wrote.store(true, Ordering::SeqCst);
+    }
+
+    /// Resolves once the running script goes past `busy-reply-threshold`
+    pub async fn overrun(&self) {
+        let mut busy = self.busy.subscribe();
+        // --- the sender lives as long as `self`
+        let _ = busy.wait_for(|busy| *busy).await;
+    }
+
+    /// SCRIPT KILL, refused when no script runs or the one running already wrote
+    pub fn kill(&self) -> Result<(), RedisValue> {
+        if !self.running.load(Ordering::SeqCst) {
+            return Err(RedisValue::SimpleError(Bytes::from_static(
+                b"NOTBUSY No scripts in execution right now.",
+            )));
+        }
+        if self.wrote.load(Ordering::SeqCst) {
+            return Err(RedisValue::SimpleError(Bytes::from_static(
+                b"UNKILLABLE Sorry the script already executed write commands against the \
+                  dataset. You can either wait the script termination or kill the server in a \
+                  hard way using the SHUTDOWN NOSAVE command.",
+            )));
+        }
+        self.killed.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Stops the running script whatever it did, for SHUTDOWN NOSAVE
+    pub fn force_kill(&self) {
+        self.killed.store(true, Ordering::SeqCst);
+    }
+
+    /// Interrupts the script once killed, and marks it busy once it ran for `threshold`
+    fn check(&self, started: Instant, threshold: Duration) -> mlua::Result<()> {
+        if self.killed.load(Ordering::SeqCst) {
+            return Err(mlua::Error::RuntimeError(KILLED.to_string()));
+        }
+        if !*self.busy.borrow() && started.elapsed() >= threshold {
+            log::warn!(
+                "Slow script detected: still in execution after {} milliseconds. You can try \
+                 killing the script using the SCRIPT KILL command.",
+                started.elapsed().as_millis()
+            );
+            self.busy.send_replace(true);
+        }
+
+        Ok(())
+    }
+}
+
+/// Scripts EVAL ran and SCRIPT LOAD loaded, by the SHA1 of their body, for EVALSHA
+#[derive(Debug, Default)]
+pub struct ScriptCache(Mutex<HashMap<String, Bytes>>);
+impl ScriptCache {
+    /// Keeps a script, returning its SHA1
+    pub fn insert(&self, body: Bytes) -> String {
+        let sha = sha1_hex(&body);
+        self.0.lock().unwrap().insert(sha.clone(), body);
+
+        sha
+    }
+
+    /// Body of the script with that SHA1, lowercase hex
+    pub fn get(&self, sha: &str) -> Option<Bytes> {
+        self.0.lock().unwrap().get(sha).cloned()
+    }
+
+    pub fn contains(&self, sha: &str) -> bool {
+        self.0.lock().unwrap().contains_key(sha)
+    }
+
+    /// Forgets every script, SCRIPT FLUSH
+    pub fn flush(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+/// SHA1 of a script body in lowercase hex, the way EVALSHA names it
+pub fn sha1_hex(body: &[u8]) -> String {
+    Sha1::from(body).digest().to_string()
+}
+
+/// Where the writes of a script go with effects replication, as `redis.set_repl` left it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReplTargets {
+    pub aof: bool,
+    pub replicas: bool,
+}
+impl Default for ReplTargets {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+impl ReplTargets {
+    pub const ALL: Self = Self {
+        aof: true,
+        replicas: true,
+    };
+    pub const NONE: Self = Self {
+        aof: false,
+        replicas: false,
+    };
+
+    /// From the `redis.REPL_*` flags, `REPL_AOF` 1 and `REPL_REPLICA` 2
+    fn from_flags(flags: i64) -> Option<Self> {
+        let res = match flags {
+            0..=3 => Self {
+                aof: flags & 1 != 0,
+                replicas: flags & 2 != 0,
+            },
+            _ => return None,
+        };
+
+        Some(res)
+    }
+}
+
+/// A command a script runs through `redis.call` or `redis.pcall`, with where its reply
+/// goes and where its write, if it is one, is replicated to
+pub struct Call {
+    pub args: Vec<Bytes>,
+    pub repl: ReplTargets,
+    pub reply: oneshot::Sender<RedisValue>,
+}
+
+/// Compiles a script without running it, replying the error Redis gives when it doesn't
+pub fn check(body: &[u8]) -> Result<(), RedisValue> {
+    let lua = vm().map_err(internal_error)?;
+    compile(&lua, body)?;
+
+    Ok(())
+}
+
+/// Runs a script in a fresh interpreter, its commands sent to `calls` one at a time and
+/// waited for. Blocks until the script returns or `running` kills it, its value converted
+/// to a reply. `redis.set_repl` is only there with `effects` replication, the script's
+/// writes going out one by one rather than the script itself
+pub fn run(
+    sha: &str,
+    body: &[u8],
+    keys: Vec<Bytes>,
+    argv: Vec<Bytes>,
+    calls: UnboundedSender<Call>,
+    effects: bool,
+    running: Arc<RunningScript>,
+) -> RedisValue {
+    let lua = match vm().and_then(|lua| bind_redis(&lua, calls, effects).map(|()| lua)) {
+        Ok(lua) => lua,
+        Err(e) => return internal_error(e),
+    };
+    let function = match compile(&lua, body) {
+        Ok(function) => function,
+        Err(reply) => return reply,
+    };
+    let started = Instant::now();
+    let threshold = *running.threshold.lock().unwrap();
+    let watched = Arc::clone(&running);
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS),
+        move |_, _| watched.check(started, threshold),
+    );
+
+    let res = match call(&lua, function, keys, argv) {
+        // --- whatever the script made of the interruption, a pcall included
+        _ if running.killed.load(Ordering::SeqCst) => {
+            RedisValue::SimpleError(Bytes::from(format!("ERR {}", KILLED)))
+        }
+        Ok(Ok(reply)) => reply,
+        // --- errors raised by `redis.call` go back as the command replied them
+        Ok(Err(Value::Table(error))) => match error.raw_get::<_, Value>("err") {
+            Ok(Value::String(err)) => {
+                RedisValue::SimpleError(Bytes::copy_from_slice(err.as_bytes()))
+            }
+            _ => run_error(sha, "unknown error"),
+        },
+        Ok(Err(Value::Error(error))) => run_error(sha, &error_message(&error)),
+        Ok(Err(error)) => {
+            let message = match lua.coerce_string(error) {
+                Ok(Some(message)) => message.to_string_lossy().into_owned(),
+                _ => "unknown error".to_string(),
+            };
+            run_error(sha, &message)
+        }
+        Err(e) => internal_error(e),
+    };
+
+    res
+}
+
+/// Interpreter with the libraries scripts may use, nothing reaching the file system or
+/// compiling code at run time, and its memory capped at `MEMORY_LIMIT`
+fn vm() -> mlua::Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+    for name in ["dofile", "loadfile", "loadstring", "load"] {
+        lua.globals().set(name, Value::Nil)?;
+    }
+    lua.set_memory_limit(MEMORY_LIMIT)?;
+
+    Ok(lua)
+}
+
+/// The `redis` table, its `pcall` sending commands to `calls` with the targets
+/// `set_repl` picked
+fn bind_redis(lua: &Lua, calls: UnboundedSender<Call>, effects: bool) -> mlua::Result<()> {
+    let redis = lua.create_table()?;
+    let repl = Arc::new(Mutex::new(ReplTargets::ALL));
+    let targets = Arc::clone(&repl);
+    let pcall = lua.create_function(move |lua, args: MultiValue| {
+        let args = match command_args(args) {
+            Ok(args) => args,
+            Err(message) => return error_table(lua, message),
+        };
+        let (reply, replied) = oneshot::channel();
+        let repl = *targets.lock().unwrap();
+        calls
+            .send(Call { args, repl, reply })
+            .map_err(mlua::Error::external)?;
+        let reply = replied.blocking_recv().map_err(mlua::Error::external)?;
+        to_lua(lua, reply)
+    })?;
+    redis.set("pcall", pcall)?;
+    let set_repl = lua.create_function(move |_, flags: Value| {
+        if !effects {
+            return Err(mlua::Error::RuntimeError(
+                "redis.set_repl() requires command replication mode".to_string(),
+            ));
+        }
+        let targets = match flags {
+            Value::Integer(flags) => ReplTargets::from_flags(flags),
+            Value::Number(flags) if flags.fract() == 0.0 => ReplTargets::from_flags(flags as i64),
+            _ => None,
+        };
+        let Some(targets) = targets else {
+            return Err(mlua::Error::RuntimeError(
+                "Invalid replication flags. Use REPL_AOF, REPL_REPLICA, REPL_ALL or REPL_NONE."
+                    .to_string(),
+            ));
+        };
+        *repl.lock().unwrap() = targets;
+
+        Ok(())
+    })?;
+    redis.set("set_repl", set_repl)?;
+    for (name, flags) in [
+        ("REPL_NONE", 0),
+        ("REPL_AOF", 1),
+        ("REPL_SLAVE", 2),
+        ("REPL_REPLICA", 2),
+        ("REPL_ALL", 3),
+    ] {
+        redis.set(name, flags)?;
+    }
+    redis.set(
+        "sha1hex",
+        lua.create_function(|_, body: mlua::String| Ok(sha1_hex(body.as_bytes())))?,
+    )?;
+    lua.globals().set("redis", redis)?;
+    lua.load(PRELUDE).set_name("@prelude").exec()?;
+
+    Ok(())
+}
+
+fn compile<'lua>(lua: &'lua Lua, body: &[u8]) -> Result<Function<'lua>, RedisValue> {
+    lua.load(body)
+        .set_name("@user_script")
+        .into_function()
+        .map_err(|e| {
+            let message = match e {
+                mlua::Error::SyntaxError { message, .. } => message,
+                e => e.to_string(),
+            };
+            RedisValue::SimpleError(Bytes::from(format!(
+                "ERR Error compiling script (new function): {}",
+                message
+            )))
+        })
+}
+
+/// Calls the script with KEYS and ARGV set and the globals locked, in protected mode so
+/// the value it raises comes back as is
+fn call<'lua>(
+    lua: &'lua Lua,
+    function: Function<'lua>,
+    keys: Vec<Bytes>,
+    argv: Vec<Bytes>,
+) -> mlua::Result<Result<RedisValue, Value<'lua>>> {
+    let globals = lua.globals();
+    globals.set("KEYS", strings(lua, &keys)?)?;
+    globals.set("ARGV", strings(lua, &argv)?)?;
+    let pcall: Function = globals.get("pcall")?;
+    lua.load(PROTECT_GLOBALS).set_name("@protect").exec()?;
+    let mut values = pcall.call::<_, MultiValue>(function)?.into_iter();
+    let ok = matches!(values.next(), Some(Value::Boolean(true)));
+    let value = values.next().unwrap_or(Value::Nil);
+
+    let res = match ok {
+        true => Ok(from_lua(value)),
+        false => Err(value),
+    };
+
+    Ok(res)
+}
+
+fn strings<'lua>(lua: &'lua Lua, values: &[Bytes]) -> mlua::Result<Table<'lua>> {
+    let values = values
+        .iter()
+        .map(|value| lua.create_string(value))
+        .collect::<mlua::Result<Vec<_>>>()?;
+
+    lua.create_sequence_from(values)
+}
+
+/// Arguments of `redis.call`, which must be strings or numbers
+fn command_args(args: MultiValue) -> Result<Vec<Bytes>, &'static str> {
+    if args.is_empty() {
+        return Err("ERR Please specify at least one argument for this redis lib call");
+    }
+
+    args.into_iter()
+        .map(|arg| match arg {
+            Value::String(s) => Ok(Bytes::copy_from_slice(s.as_bytes())),
+            Value::Integer(i) => Ok(Bytes::from(i.to_string())),
+            Value::Number(n) => Ok(Bytes::from(n.to_string())),
+            _ => Err("ERR Lua redis lib command arguments must be strings or integers"),
+        })
+        .collect()
+}
+
+fn error_table<'lua>(lua: &'lua Lua, message: &str) -> mlua::Result<Value<'lua>> {
+    let table = lua.create_table()?;
+    table.set("err", message)?;
+
+    Ok(Value::Table(table))
+}
+
+/// A reply as a script sees it: nils become false, status and error replies tables with
+/// an `ok` or `err` field
+fn to_lua(lua: &Lua, reply: RedisValue) -> mlua::Result<Value<'_>> {
+    let res = match reply {
+        RedisValue::SimpleString(s) => {
+            let table = lua.create_table()?;
+            table.set("ok", lua.create_string(&s)?)?;
+            Value::Table(table)
+        }
+        RedisValue::SimpleError(e) => error_table(lua, &String::from_utf8_lossy(&e))?,
+        RedisValue::BulkString(s)
+        | RedisValue::Json(s)
+        | RedisValue::Double(s)
+        | RedisValue::BigNumber(s) => Value::String(lua.create_string(&s)?),
+        RedisValue::Integer(i) | RedisValue::Counter(i) => i.into_lua(lua)?,
+        RedisValue::Boolean(b) => (b as i64).into_lua(lua)?,
+        RedisValue::Array(values) | RedisValue::Replies(values) => {
+            let values = values
+                .into_iter()
+                .map(|value| to_lua(lua, value))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            Value::Table(lua.create_sequence_from(values)?)
+        }
+        // --- a flat array of fields and values, the way RESP2 sends it
+        RedisValue::Map(pairs) => {
+            let values = pairs
+                .into_iter()
+                .flat_map(|(field, value)| [field, value])
+                .map(|value| to_lua(lua, value))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            Value::Table(lua.create_sequence_from(values)?)
+        }
+        _ => Value::Boolean(false),
+    };
+
+    Ok(res)
+}
+
+/// What a script returned as a reply: numbers are truncated to integers, false and nil
+/// become nil, tables arrays up to their first nil unless they have an `ok` or `err` field
+fn from_lua(value: Value) -> RedisValue {
+    match value {
+        Value::Boolean(true) => RedisValue::Integer(1),
+        Value::Integer(i) => RedisValue::Integer(i),
+        Value::Number(n) => RedisValue::Integer(n as i64),
+        Value::String(s) => RedisValue::BulkString(Bytes::copy_from_slice(s.as_bytes())),
+        Value::Table(table) => {
+            if let Ok(Value::String(err)) = table.raw_get::<_, Value>("err") {
+                return RedisValue::SimpleError(Bytes::copy_from_slice(err.as_bytes()));
+            }
+            if let Ok(Value::String(ok)) = table.raw_get::<_, Value>("ok") {
+                return RedisValue::SimpleString(Bytes::copy_from_slice(ok.as_bytes()));
+            }
+            let values = table
+                .sequence_values::<Value>()
+                .map_while(Result::ok)
+                .map(from_lua)
+                .collect();
+            RedisValue::Array(values)
+        }
+        _ => RedisValue::NullBulkString,
+    }
+}
+
+/// Message of an error raised from Rust, e.g. by `redis.set_repl`, without the traceback
+/// mlua wraps it in
+fn error_message(e: &mlua::Error) -> String {
+    match e {
+        mlua::Error::CallbackError { cause, .. } => error_message(cause),
+        mlua::Error::RuntimeError(message) => message.clone(),
+        e => e.to_string(),
+    }
+}
+
+fn run_error(sha: &str, message: &str) -> RedisValue {
+    RedisValue::SimpleError(Bytes::from(format!(
+        "ERR Error running script (call to f_{}): {}",
+        sha, message
+    )))
+}
+
+fn internal_error(e: mlua::Error) -> RedisValue {
+    RedisValue::SimpleError(Bytes::from(format!("ERR {}", e)))
+}
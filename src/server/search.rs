@@ -0,0 +1,542 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ops::Bound,
+    sync::RwLock,
+};
+
+use anyhow::{bail, ensure, Result};
+use bytes::Bytes;
+use serde_json::Value;
+
+use super::{document::JsonPath, encoding::HashFields, handler::RedisValue, keyspace::Keyspace};
+
+/// How the values of a field are indexed and matched
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    /// JSON numbers, matched by range: `@field:[min max]`
+    Numeric,
+    /// strings, or arrays of them, split on commas and matched case-insensitively:
+    /// `@field:{tag|other}`
+    Tag,
+}
+
+/// Where the values of a field are in a document
+#[derive(Clone, Debug)]
+pub enum FieldSource {
+    /// whatever a path selects in a JSON document
+    Path(JsonPath),
+    /// a field of a hash, numbers and tags written out as text
+    HashField(Bytes),
+}
+
+/// A field of an index, the values it finds in each document
+#[derive(Clone, Debug)]
+pub struct IndexField {
+    /// what queries call the field, the path or hash field itself unless given `AS name`
+    pub name: String,
+    pub source: FieldSource,
+    pub field_type: FieldType,
+}
+
+/// Kind of keys an index covers, keys of the other types are left out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DocumentType {
+    Json,
+    Hash,
+}
+
+/// What FT.CREATE indexes: the JSON documents or hashes under some key prefixes, all of
+/// them without a prefix, and which of their fields
+#[derive(Clone, Debug)]
+pub struct IndexDefinition {
+    pub on: DocumentType,
+    pub prefixes: Vec<Bytes>,
+    pub fields: Vec<IndexField>,
+}
+impl IndexDefinition {
+    /// Parses the arguments of FT.CREATE following the index name:
+    /// `[ON JSON|HASH] [PREFIX count prefix ...] SCHEMA identifier [AS name] NUMERIC|TAG ...`
+    /// where identifiers are JSON paths, or hash fields for `ON HASH`
+    pub fn from_args(args: &[Bytes]) -> Result<Self> {
+        let mut args = args.iter().map(|arg| String::from_utf8_lossy(arg));
+        let mut on = DocumentType::Json;
+        let mut prefixes = vec![];
+        loop {
+            let Some(arg) = args.next() else {
+                bail!("ERR no SCHEMA given");
+            };
+            match arg.to_uppercase().as_str() {
+                "ON" => match args.next().map(|on| on.to_uppercase()).as_deref() {
+                    Some("JSON") => on = DocumentType::Json,
+                    Some("HASH") => on = DocumentType::Hash,
+                    _ => bail!("ERR syntax error"),
+                },
+                "PREFIX" => {
+                    let Some(Ok(count)) = args.next().map(|count| count.parse::<usize>()) else {
+                        bail!("ERR syntax error");
+                    };
+                    for _ in 0..count {
+                        let Some(prefix) = args.next() else {
+                            bail!("ERR syntax error");
+                        };
+                        prefixes.push(Bytes::from(prefix.into_owned()));
+                    }
+                }
+                "SCHEMA" => break,
+                _ => bail!("ERR unknown argument '{}'", arg),
+            }
+        }
+
+        let mut fields: Vec<IndexField> = vec![];
+        let mut args = args.peekable();
+        while let Some(identifier) = args.next() {
+            let mut name = identifier.to_string();
+            if args.next_if(|arg| arg.eq_ignore_ascii_case("AS")).is_some() {
+                let Some(alias) = args.next() else {
+                    bail!("ERR syntax error");
+                };
+                name = alias.into_owned();
+            }
+            let field_type = match args.next().map(|arg| arg.to_uppercase()).as_deref() {
+                Some("NUMERIC") => FieldType::Numeric,
+                Some("TAG") => FieldType::Tag,
+                Some(other) => bail!("ERR unsupported field type '{}'", other),
+                None => bail!("ERR missing type for field '{}'", name),
+            };
+            let source = match on {
+                DocumentType::Json => match JsonPath::parse(&identifier) {
+                    Ok(path) => FieldSource::Path(path),
+                    Err(e) => bail!("ERR {}", e),
+                },
+                DocumentType::Hash => FieldSource::HashField(Bytes::from(identifier.into_owned())),
+            };
+            ensure!(
+                fields.iter().all(|field| field.name != name),
+                "ERR duplicate field '{}'",
+                name
+            );
+            fields.push(IndexField {
+                name,
+                source,
+                field_type,
+            });
+        }
+        ensure!(!fields.is_empty(), "ERR no fields in SCHEMA");
+
+        let res = Self {
+            on,
+            prefixes,
+            fields,
+        };
+
+        Ok(res)
+    }
+
+    fn covers(&self, key: &[u8]) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+}
+
+/// Secondary indexes over JSON documents or hashes, by name. Writes keep them up to date as
+/// they go, they live in memory only and are gone after a restart
+#[derive(Default)]
+pub struct SearchIndexes(RwLock<HashMap<String, SearchIndex>>);
+impl SearchIndexes {
+    /// Adds an index and fills it with the documents already there
+    pub fn create(
+        &self,
+        name: &str,
+        definition: IndexDefinition,
+        main_store: &Keyspace,
+    ) -> Result<()> {
+        let mut indexes = self.0.write().unwrap();
+        ensure!(!indexes.contains_key(name), "ERR Index already exists");
+
+        let mut index = SearchIndex::new(definition);
+        for (key, value) in main_store {
+            index.update(key, value);
+        }
+        indexes.insert(name.to_string(), index);
+
+        Ok(())
+    }
+
+    /// Removes an index, returns whether it existed
+    pub fn drop_index(&self, name: &str) -> bool {
+        self.0.write().unwrap().remove(name).is_some()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut res = self.0.read().unwrap().keys().cloned().collect::<Vec<_>>();
+        res.sort();
+
+        res
+    }
+
+    /// Reindexes a key after a write
+    pub fn update(&self, key: &RedisValue, value: &RedisValue) {
+        if self.0.read().unwrap().is_empty() {
+            return;
+        }
+        for index in self.0.write().unwrap().values_mut() {
+            index.update(key, value);
+        }
+    }
+
+    /// Takes a deleted or expired key out of the indexes
+    pub fn remove(&self, key: &RedisValue) {
+        if self.0.read().unwrap().is_empty() {
+            return;
+        }
+        let RedisValue::BulkString(key) = key else {
+            return;
+        };
+        for index in self.0.write().unwrap().values_mut() {
+            index.remove(key);
+        }
+    }
+
+    /// Indexes a whole new dataset from scratch, e.g. after loading an RDB file
+    pub fn rebuild(&self, main_store: &Keyspace) {
+        for index in self.0.write().unwrap().values_mut() {
+            *index = SearchIndex::new(index.definition.clone());
+            for (key, value) in main_store {
+                index.update(key, value);
+            }
+        }
+    }
+
+    /// Keys of the documents matching a query, sorted. Queries are clauses that all have
+    /// to match, `@field:[min max]` and `@field:{tag|other}`, or `*` for every document.
+    /// Range bounds may be `-inf`/`+inf`, and exclusive with a leading `(`
+    pub fn search(&self, name: &str, query: &str) -> Result<Vec<Bytes>> {
+        let indexes = self.0.read().unwrap();
+        let Some(index) = indexes.get(name) else {
+            bail!("ERR {}: no such index", name);
+        };
+        let clauses = index.parse_query(query)?;
+
+        let res = index.search(&clauses);
+
+        Ok(res)
+    }
+}
+
+/// Number with a total order, the key of numeric field indexes
+#[derive(Clone, Copy, Debug)]
+struct Number(f64);
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Number {}
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Value a document has for a field
+#[derive(Clone, Debug)]
+enum Term {
+    Number(Number),
+    Tag(String),
+}
+
+/// Keys by value, for one field
+enum FieldIndex {
+    Numeric(BTreeMap<Number, BTreeSet<Bytes>>),
+    Tag(HashMap<String, BTreeSet<Bytes>>),
+}
+
+/// Clause of a query, matching the documents with a value of the field in range or
+/// among the tags
+#[derive(Debug)]
+enum Clause {
+    Range {
+        field: usize,
+        min: Bound<Number>,
+        max: Bound<Number>,
+    },
+    Tags {
+        field: usize,
+        tags: Vec<String>,
+    },
+}
+
+struct SearchIndex {
+    definition: IndexDefinition,
+    /// by position of the field in the definition
+    fields: Vec<FieldIndex>,
+    /// terms of each document, by field position, to take it out again
+    documents: HashMap<Bytes, Vec<(usize, Term)>>,
+}
+impl SearchIndex {
+    fn new(definition: IndexDefinition) -> Self {
+        let fields = definition
+            .fields
+            .iter()
+            .map(|field| match field.field_type {
+                FieldType::Numeric => FieldIndex::Numeric(BTreeMap::new()),
+                FieldType::Tag => FieldIndex::Tag(HashMap::new()),
+            })
+            .collect();
+
+        Self {
+            definition,
+            fields,
+            documents: HashMap::new(),
+        }
+    }
+
+    fn update(&mut self, key: &RedisValue, value: &RedisValue) {
+        let RedisValue::BulkString(key) = key else {
+            return;
+        };
+        if !self.definition.covers(key) {
+            return;
+        }
+        self.remove(key);
+        // --- keys of other types or holding invalid documents are simply not indexed
+        let terms = match (self.definition.on, value) {
+            (DocumentType::Json, RedisValue::Json(text)) => {
+                let Ok(document) = serde_json::from_slice::<Value>(text) else {
+                    return;
+                };
+                self.json_terms(&document)
+            }
+            (DocumentType::Hash, RedisValue::Hash(hash)) => self.hash_terms(hash),
+            _ => return,
+        };
+        for (position, term) in terms.iter() {
+            match (&mut self.fields[*position], term) {
+                (FieldIndex::Numeric(index), Term::Number(number)) => {
+                    index.entry(*number).or_default().insert(key.clone());
+                }
+                (FieldIndex::Tag(index), Term::Tag(tag)) => {
+                    index.entry(tag.clone()).or_default().insert(key.clone());
+                }
+                _ => unreachable!("Terms are made after the type of their field"),
+            }
+        }
+        self.documents.insert(key.clone(), terms);
+    }
+
+    fn json_terms(&self, document: &Value) -> Vec<(usize, Term)> {
+        let mut res = vec![];
+        for (position, field) in self.definition.fields.iter().enumerate() {
+            let FieldSource::Path(path) = &field.source else {
+                continue;
+            };
+            for pointer in path.find(document) {
+                match (field.field_type, document.pointer(&pointer)) {
+                    (FieldType::Numeric, Some(Value::Number(number))) => {
+                        if let Some(number) = number.as_f64() {
+                            res.push((position, Term::Number(Number(number))));
+                        }
+                    }
+                    (FieldType::Tag, Some(Value::String(text))) => {
+                        res.extend(tags(text).map(|tag| (position, Term::Tag(tag))));
+                    }
+                    (FieldType::Tag, Some(Value::Array(items))) => {
+                        for text in items.iter().filter_map(Value::as_str) {
+                            res.extend(tags(text).map(|tag| (position, Term::Tag(tag))));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Terms of a hash, fields that aren't numbers where one is expected are left out
+    fn hash_terms(&self, hash: &HashFields) -> Vec<(usize, Term)> {
+        let mut res = vec![];
+        for (position, field) in self.definition.fields.iter().enumerate() {
+            let FieldSource::HashField(name) = &field.source else {
+                continue;
+            };
+            let Some(text) = hash.get(name).map(|value| String::from_utf8_lossy(value)) else {
+                continue;
+            };
+            match field.field_type {
+                FieldType::Numeric => {
+                    if let Ok(number) = text.trim().parse::<f64>() {
+                        if !number.is_nan() {
+                            res.push((position, Term::Number(Number(number))));
+                        }
+                    }
+                }
+                FieldType::Tag => res.extend(tags(&text).map(|tag| (position, Term::Tag(tag)))),
+            }
+        }
+
+        res
+    }
+
+    fn remove(&mut self, key: &Bytes) {
+        let Some(terms) = self.documents.remove(key) else {
+            return;
+        };
+        for (position, term) in terms {
+            match (&mut self.fields[position], term) {
+                (FieldIndex::Numeric(index), Term::Number(number)) => {
+                    if let Some(keys) = index.get_mut(&number) {
+                        keys.remove(key);
+                        if keys.is_empty() {
+                            index.remove(&number);
+                        }
+                    }
+                }
+                (FieldIndex::Tag(index), Term::Tag(tag)) => {
+                    if let Some(keys) = index.get_mut(&tag) {
+                        keys.remove(key);
+                        if keys.is_empty() {
+                            index.remove(&tag);
+                        }
+                    }
+                }
+                _ => unreachable!("Terms are made after the type of their field"),
+            }
+        }
+    }
+
+    fn parse_query(&self, query: &str) -> Result<Vec<Clause>> {
+        let query = query.trim();
+        if query == "*" {
+            return Ok(vec![]);
+        }
+
+        let mut res = vec![];
+        let mut rest = query;
+        while !rest.is_empty() {
+            let Some((name, after)) = rest.strip_prefix('@').and_then(|rest| rest.split_once(':'))
+            else {
+                bail!("ERR syntax error in query '{}'", query);
+            };
+            let Some(field) = self
+                .definition
+                .fields
+                .iter()
+                .position(|field| field.name == name)
+            else {
+                bail!("ERR unknown field '{}'", name);
+            };
+            let (close, field_type) = match after.chars().next() {
+                Some('[') => (']', FieldType::Numeric),
+                Some('{') => ('}', FieldType::Tag),
+                _ => bail!("ERR syntax error in query '{}'", query),
+            };
+            ensure!(
+                self.definition.fields[field].field_type == field_type,
+                "ERR field '{}' can't be queried this way",
+                name
+            );
+            let Some(end) = after.find(close) else {
+                bail!("ERR syntax error in query '{}'", query);
+            };
+            let inner = &after[1..end];
+            res.push(match field_type {
+                FieldType::Numeric => {
+                    let [min, max] = inner.split_whitespace().collect::<Vec<_>>()[..] else {
+                        bail!("ERR numeric ranges take a min and a max: '{}'", inner);
+                    };
+                    Clause::Range {
+                        field,
+                        min: bound(min)?,
+                        max: bound(max)?,
+                    }
+                }
+                FieldType::Tag => Clause::Tags {
+                    field,
+                    tags: inner.split('|').flat_map(tags).collect(),
+                },
+            });
+            rest = after[end + 1..].trim_start();
+        }
+
+        Ok(res)
+    }
+
+    fn search(&self, clauses: &[Clause]) -> Vec<Bytes> {
+        let mut matches = clauses.iter().map(|clause| self.matches(clause));
+        let Some(first) = matches.next() else {
+            let mut res = self.documents.keys().cloned().collect::<Vec<_>>();
+            res.sort();
+            return res;
+        };
+
+        let res = matches.fold(first, |res, other| &res & &other);
+
+        res.into_iter().collect()
+    }
+
+    fn matches(&self, clause: &Clause) -> BTreeSet<Bytes> {
+        match (clause, self.fields.get(clause_field(clause))) {
+            (Clause::Range { min, max, .. }, Some(FieldIndex::Numeric(index))) => {
+                // --- an empty range would make BTreeMap::range panic
+                let empty = match (min, max) {
+                    (Bound::Included(min), Bound::Included(max)) => min > max,
+                    (
+                        Bound::Included(min) | Bound::Excluded(min),
+                        Bound::Included(max) | Bound::Excluded(max),
+                    ) => min >= max,
+                    _ => false,
+                };
+                if empty {
+                    return BTreeSet::new();
+                }
+                index
+                    .range((*min, *max))
+                    .flat_map(|(_, keys)| keys.iter().cloned())
+                    .collect()
+            }
+            (Clause::Tags { tags, .. }, Some(FieldIndex::Tag(index))) => tags
+                .iter()
+                .filter_map(|tag| index.get(tag))
+                .flat_map(|keys| keys.iter().cloned())
+                .collect(),
+            _ => BTreeSet::new(),
+        }
+    }
+}
+
+fn clause_field(clause: &Clause) -> usize {
+    match clause {
+        Clause::Range { field, .. } | Clause::Tags { field, .. } => *field,
+    }
+}
+
+/// Tags of a text: comma separated, trimmed, lowercased
+fn tags(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(',')
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+}
+
+/// Bound of a numeric range: a number, exclusive with a leading `(`, or `-inf`/`+inf`
+fn bound(text: &str) -> Result<Bound<Number>> {
+    let (exclusive, number) = match text.strip_prefix('(') {
+        Some(number) => (true, number),
+        None => (false, text),
+    };
+    let res = match number.to_lowercase().as_str() {
+        "-inf" | "+inf" | "inf" => Bound::Unbounded,
+        number => match number.parse::<f64>() {
+            Ok(number) if !number.is_nan() && exclusive => Bound::Excluded(Number(number)),
+            Ok(number) if !number.is_nan() => Bound::Included(Number(number)),
+            _ => bail!("ERR invalid numeric bound '{}'", text),
+        },
+    };
+
+    Ok(res)
+}
@@ -3,7 +3,10 @@ use core::str;
 use anyhow::{bail, Result};
 use bytes::{Bytes, BytesMut};
 
-use super::handler::RedisValue;
+use super::{
+    handler::RedisValue,
+    split::{split_args, Arg},
+};
 
 /// TOk represents the start index and last index (exclusive)
 /// of the current token in a buffer
@@ -27,27 +30,124 @@ impl<'a> Tok {
 #[derive(PartialEq, Clone, Debug)]
 pub enum RESPRaw {
     SimpleString(Tok),
+    SimpleError(Tok),
+    Integer(i64),
     BulkString(Tok),
+    /// Bulk string of an inline command written with quotes or escapes, so it is no
+    /// longer a slice of the buffer
+    Unescaped(Bytes),
     Array(Vec<RESPRaw>),
     // Since the null bulk string has no encoded data, usize represents
     // the position of the next next token
     NullBulkString(usize),
+    /// "*-1", e.g. the reply of a blocking command that timed out
+    NullArray,
 }
 
 /// Return type of the tokenizer, containing the raw token and the start of the next token
 #[derive(PartialEq, Clone, Debug)]
 pub struct RESPToken(pub RESPRaw, pub usize);
 
+/// Arrays nested deeper than this are rejected instead of recursing further
+const MAX_NESTING_DEPTH: usize = 64;
+
+/// Longest inline command accepted, as in Redis
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+/// Bounds on how much a peer can make us buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProtocolLimits {
+    /// largest bulk string accepted, `proto-max-bulk-len`
+    pub max_bulk_len: usize,
+    /// largest number of elements accepted in an array
+    pub max_multibulk_len: usize,
+    /// largest amount of unparsed data buffered for a client, `client-query-buffer-limit`
+    pub max_query_buffer: usize,
+}
+impl Default for ProtocolLimits {
+    fn default() -> Self {
+        Self {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: 1024 * 1024,
+            max_query_buffer: 1024 * 1024 * 1024,
+        }
+    }
+}
+impl ProtocolLimits {
+    pub fn unbounded() -> Self {
+        Self {
+            max_bulk_len: usize::MAX,
+            max_multibulk_len: usize::MAX,
+            max_query_buffer: usize::MAX,
+        }
+    }
+}
+
+/// Violations that require closing the connection, worded like Redis' own protocol errors
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ProtocolError {
+    #[error("invalid bulk length")]
+    InvalidBulkLength,
+    #[error("invalid multibulk length")]
+    InvalidMultibulkLength,
+    #[error("invalid integer")]
+    InvalidInteger,
+    #[error("expected '{}', got '{}'", *.expected as char, .got.escape_ascii())]
+    UnexpectedType { expected: u8, got: u8 },
+    #[error("unknown type byte '{}'", .0.escape_ascii())]
+    UnknownType(u8),
+    #[error("bulk string not terminated by CRLF")]
+    UnterminatedBulk,
+    #[error("arrays nested deeper than {MAX_NESTING_DEPTH} levels")]
+    NestingTooDeep,
+    #[error("query buffer limit exceeded")]
+    QueryBufferLimit,
+    #[error("too big inline request")]
+    InlineTooBig,
+    #[error("unbalanced quotes in request")]
+    UnbalancedQuotes,
+}
+
+/// Reads the decimal length or integer in a header line
+fn parse_number<T: str::FromStr>(digits: &[u8], err: ProtocolError) -> Result<T> {
+    let res = str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or(err)?;
+
+    Ok(res)
+}
+
+/// Tokenizes the frame at `pos` without any size limits, for trusted peers
 pub fn tokenize(buf: &BytesMut, pos: usize) -> Result<Option<RESPToken>> {
+    tokenize_with_limits(buf, pos, &ProtocolLimits::unbounded())
+}
+
+pub fn tokenize_with_limits(
+    buf: &BytesMut,
+    pos: usize,
+    limits: &ProtocolLimits,
+) -> Result<Option<RESPToken>> {
+    tokenize_nested(buf, pos, 0, limits)
+}
+
+fn tokenize_nested(
+    buf: &BytesMut,
+    pos: usize,
+    depth: usize,
+    limits: &ProtocolLimits,
+) -> Result<Option<RESPToken>> {
     if pos >= buf.len() {
         return Ok(None);
     }
 
     match buf[pos] {
         b'+' => parse_basic_string(buf, pos + 1),
-        b'$' => parse_bulk_string(buf, pos + 1),
-        b'*' => parse_array(buf, pos + 1),
-        _ => anyhow::bail!("Identifier '{}' is not valid", buf[pos].to_string()),
+        b'-' => parse_simple_error(buf, pos + 1),
+        b':' => parse_integer(buf, pos + 1),
+        b'$' => parse_bulk_string(buf, pos + 1, limits),
+        b'*' => parse_array(buf, pos + 1, depth, limits),
+        other => bail!(ProtocolError::UnknownType(other)),
     }
 }
 
@@ -56,25 +156,59 @@ fn parse_basic_string(buf: &BytesMut, pos: usize) -> Result<Option<RESPToken>> {
     Ok(word.map(|(tok, next_post)| RESPToken(RESPRaw::SimpleString(tok), next_post)))
 }
 
-fn parse_bulk_string(buf: &BytesMut, pos: usize) -> Result<Option<RESPToken>> {
+fn parse_simple_error(buf: &BytesMut, pos: usize) -> Result<Option<RESPToken>> {
+    let word = get_next_word(buf, pos);
+    Ok(word.map(|(tok, next_post)| RESPToken(RESPRaw::SimpleError(tok), next_post)))
+}
+
+fn parse_integer(buf: &BytesMut, pos: usize) -> Result<Option<RESPToken>> {
     match get_next_word(buf, pos) {
         Some((tok, next_pos)) => {
-            let len_as_str = str::from_utf8(tok.as_slice(buf))?;
-            let expected_len: i32 = len_as_str.parse()?;
+            let value: i64 = parse_number(tok.as_slice(buf), ProtocolError::InvalidInteger)?;
+            Ok(Some(RESPToken(RESPRaw::Integer(value), next_pos)))
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_bulk_string(
+    buf: &BytesMut,
+    pos: usize,
+    limits: &ProtocolLimits,
+) -> Result<Option<RESPToken>> {
+    match get_next_word(buf, pos) {
+        Some((tok, next_pos)) => {
+            let expected_len: i64 =
+                parse_number(tok.as_slice(buf), ProtocolError::InvalidBulkLength)?;
 
             // --- check for null bulk strings
             if expected_len == -1 {
                 Ok(Some(RESPToken(RESPRaw::NullBulkString(next_pos), next_pos)))
             } else if expected_len >= 0 {
+                // --- refuse before buffering any of the payload
+                if expected_len as usize > limits.max_bulk_len {
+                    bail!(ProtocolError::InvalidBulkLength);
+                }
+
                 let from = next_pos;
-                let to = from + expected_len as usize;
+                let Some(to) = from.checked_add(expected_len as usize) else {
+                    bail!(ProtocolError::InvalidBulkLength);
+                };
+
+                // --- not enough data -> wait for next cycle
+                if to.saturating_add(2) > buf.len() {
+                    return Ok(None);
+                }
+                if &buf[to..to + 2] != b"\r\n" {
+                    bail!(ProtocolError::UnterminatedBulk);
+                }
 
                 Ok(Some(RESPToken(
                     RESPRaw::BulkString(Tok::new(from, to)),
                     to + 2,
                 )))
             } else {
-                bail!("Invalid length found for bulk string: {}", expected_len)
+                bail!(ProtocolError::InvalidBulkLength)
             }
         }
         // --- not enough data -> wait for next cycle
@@ -82,20 +216,37 @@ fn parse_bulk_string(buf: &BytesMut, pos: usize) -> Result<Option<RESPToken>> {
     }
 }
 
-fn parse_array(buf: &BytesMut, pos: usize) -> Result<Option<RESPToken>> {
+fn parse_array(
+    buf: &BytesMut,
+    pos: usize,
+    depth: usize,
+    limits: &ProtocolLimits,
+) -> Result<Option<RESPToken>> {
+    if depth >= MAX_NESTING_DEPTH {
+        bail!(ProtocolError::NestingTooDeep);
+    }
+
     match get_next_word(buf, pos) {
         Some((tok, next_pos)) => {
-            let len_as_str = str::from_utf8(tok.as_slice(buf))?;
-            let expected_arr_len: i32 = len_as_str.parse()?;
+            let expected_arr_len: i64 =
+                parse_number(tok.as_slice(buf), ProtocolError::InvalidMultibulkLength)?;
+
+            if !expected_arr_len.is_negative()
+                && expected_arr_len as usize > limits.max_multibulk_len
+            {
+                bail!(ProtocolError::InvalidMultibulkLength);
+            }
 
             match !expected_arr_len.is_negative() {
                 true => {
                     // used to keep track of next index in vec to scan
                     let mut cur_pos = next_pos;
-                    let mut array: Vec<RESPRaw> = Vec::with_capacity(expected_arr_len as usize);
+                    // --- every element takes at least 3 bytes, don't trust the declared length blindly
+                    let capacity = (expected_arr_len as usize).min(buf.len() / 3);
+                    let mut array: Vec<RESPRaw> = Vec::with_capacity(capacity);
 
                     for _ in 0..expected_arr_len {
-                        match tokenize(buf, cur_pos)? {
+                        match tokenize_nested(buf, cur_pos, depth + 1, limits)? {
                             Some(parsed_tok) => {
                                 cur_pos = parsed_tok.1;
                                 array.push(parsed_tok.0);
@@ -106,13 +257,227 @@ fn parse_array(buf: &BytesMut, pos: usize) -> Result<Option<RESPToken>> {
 
                     Ok(Some(RESPToken(RESPRaw::Array(array), cur_pos)))
                 }
-                false => bail!("Invalid array length: {}", expected_arr_len),
+                false if expected_arr_len == -1 => {
+                    Ok(Some(RESPToken(RESPRaw::NullArray, next_pos)))
+                }
+                false => bail!(ProtocolError::InvalidMultibulkLength),
             }
         }
         None => Ok(None),
     }
 }
 
+/// Incremental parser for a buffer that keeps growing until a whole frame is in. It
+/// remembers the arrays it is filling, the bulk string it waits for and how far it
+/// looked for the end of a line, so each byte is only scanned once however many reads
+/// the frame takes. Positions are relative to the start of the buffer, which must not
+/// change until a frame is returned
+#[derive(Debug, Default)]
+pub struct RespParser {
+    /// start of the next element to parse
+    pos: usize,
+    /// arrays being filled, outermost first, with the number of elements they expect
+    arrays: Vec<(Vec<RESPRaw>, usize)>,
+    /// payload range of a bulk string whose header is parsed, waiting for its data
+    bulk: Option<(usize, usize)>,
+    /// where the search for the CRLF closing the current header line resumes
+    line_scanned: usize,
+}
+impl RespParser {
+    /// Parses on from where the previous call stopped. Returns the frame once complete,
+    /// with the length it takes in the buffer, and starts over for the next one
+    pub fn parse(&mut self, buf: &BytesMut, limits: &ProtocolLimits) -> Result<Option<RESPToken>> {
+        loop {
+            let Some(mut value) = self.parse_element(buf, limits)? else {
+                return Ok(None);
+            };
+
+            // --- a complete element fills the innermost array, maybe completing it too
+            loop {
+                let Some((items, expected)) = self.arrays.last_mut() else {
+                    let res = RESPToken(value, self.pos);
+                    *self = Self::default();
+                    return Ok(Some(res));
+                };
+                items.push(value);
+                if items.len() < *expected {
+                    break;
+                }
+                let (items, _) = self.arrays.pop().expect("Array was just looked at");
+                value = RESPRaw::Array(items);
+            }
+        }
+    }
+
+    /// Next complete element other than a non-empty array, whose header is pushed on
+    /// `arrays` instead. State only moves past what is valid, errors are final
+    fn parse_element(
+        &mut self,
+        buf: &BytesMut,
+        limits: &ProtocolLimits,
+    ) -> Result<Option<RESPRaw>> {
+        loop {
+            if let Some((from, to)) = self.bulk {
+                // --- not enough data -> wait for next cycle
+                if to.saturating_add(2) > buf.len() {
+                    return Ok(None);
+                }
+                if &buf[to..to + 2] != b"\r\n" {
+                    bail!(ProtocolError::UnterminatedBulk);
+                }
+                self.bulk = None;
+                self.pos = to + 2;
+                return Ok(Some(RESPRaw::BulkString(Tok::new(from, to))));
+            }
+
+            if self.pos >= buf.len() {
+                return Ok(None);
+            }
+            let type_byte = buf[self.pos];
+            match type_byte {
+                b'+' | b'-' | b':' | b'$' => {}
+                b'*' if self.arrays.len() >= MAX_NESTING_DEPTH => {
+                    bail!(ProtocolError::NestingTooDeep)
+                }
+                b'*' => {}
+                // --- a frame not starting with a type byte is an inline command, e.g.
+                // --- typed in through telnet
+                _ if self.arrays.is_empty() => return self.parse_inline(buf),
+                other => bail!(ProtocolError::UnknownType(other)),
+            }
+            let Some((tok, next_pos)) = self.next_line(buf) else {
+                return Ok(None);
+            };
+            let line = tok.as_slice(buf);
+
+            let value = match type_byte {
+                b'+' => RESPRaw::SimpleString(tok),
+                b'-' => RESPRaw::SimpleError(tok),
+                b':' => RESPRaw::Integer(parse_number(line, ProtocolError::InvalidInteger)?),
+                b'$' => {
+                    let expected_len: i64 = parse_number(line, ProtocolError::InvalidBulkLength)?;
+                    if expected_len == -1 {
+                        RESPRaw::NullBulkString(next_pos)
+                    } else {
+                        // --- refuse before buffering any of the payload
+                        if expected_len < 0 || expected_len as usize > limits.max_bulk_len {
+                            bail!(ProtocolError::InvalidBulkLength);
+                        }
+                        let Some(to) = next_pos.checked_add(expected_len as usize) else {
+                            bail!(ProtocolError::InvalidBulkLength);
+                        };
+                        self.bulk = Some((next_pos, to));
+                        self.pos = next_pos;
+                        continue;
+                    }
+                }
+                _ => {
+                    let expected_len: i64 =
+                        parse_number(line, ProtocolError::InvalidMultibulkLength)?;
+                    if expected_len == -1 {
+                        self.pos = next_pos;
+                        return Ok(Some(RESPRaw::NullArray));
+                    }
+                    if expected_len < 0 || expected_len as usize > limits.max_multibulk_len {
+                        bail!(ProtocolError::InvalidMultibulkLength);
+                    }
+                    if expected_len > 0 {
+                        // --- every element takes at least 3 bytes, don't trust the declared
+                        // --- length blindly
+                        let capacity = (expected_len as usize).min(buf.len() / 3);
+                        self.arrays
+                            .push((Vec::with_capacity(capacity), expected_len as usize));
+                        self.pos = next_pos;
+                        continue;
+                    }
+                    RESPRaw::Array(vec![])
+                }
+            };
+            self.pos = next_pos;
+
+            return Ok(Some(value));
+        }
+    }
+
+    /// Inline command at `pos`: a line of arguments separated by spaces, ending with LF or
+    /// CRLF. Parsed as the array of bulk strings it stands for, empty for a blank line.
+    /// Arguments can be quoted as in redis-cli, see `split_inline`
+    fn parse_inline(&mut self, buf: &BytesMut) -> Result<Option<RESPRaw>> {
+        let from = self.line_scanned.max(self.pos);
+        let Some(lf) = buf[from..].iter().position(|&b| b == b'\n') else {
+            if buf.len() - self.pos > MAX_INLINE_LEN {
+                bail!(ProtocolError::InlineTooBig);
+            }
+            self.line_scanned = buf.len();
+            return Ok(None);
+        };
+        let end = from + lf;
+        if end - self.pos > MAX_INLINE_LEN {
+            bail!(ProtocolError::InlineTooBig);
+        }
+
+        let args = split_inline(buf, self.pos, end)?;
+        self.line_scanned = 0;
+        self.pos = end + 1;
+
+        Ok(Some(RESPRaw::Array(args)))
+    }
+
+    /// Content of the header line at `pos`, after its type byte, and where the next
+    /// element starts. Remembers how far it looked when the line isn't complete yet
+    fn next_line(&mut self, buf: &BytesMut) -> Option<(Tok, usize)> {
+        let start = self.pos + 1;
+        let from = self.line_scanned.max(start);
+        match buf[from..].windows(2).position(|w| w == b"\r\n") {
+            Some(cr) => {
+                self.line_scanned = 0;
+                Some((Tok::new(start, from + cr), from + cr + 2))
+            }
+            None => {
+                // --- the last byte may be the CR of a CRLF still to come
+                self.line_scanned = buf.len().saturating_sub(1).max(start);
+                None
+            }
+        }
+    }
+}
+
+/// Version of the protocol a connection speaks, RESP2 until it sends HELLO 3
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+impl Protocol {
+    /// The version HELLO takes and replies, 2 or 3
+    pub fn version(&self) -> i64 {
+        match self {
+            Self::Resp2 => 2,
+            Self::Resp3 => 3,
+        }
+    }
+}
+
+/// Arguments of the inline command in `buf[from..end]`, see `split_args`. Those written
+/// without quotes stay slices of the buffer
+fn split_inline(buf: &BytesMut, from: usize, end: usize) -> Result<Vec<RESPRaw>> {
+    let Some(args) = split_args(&buf[from..end]) else {
+        bail!(ProtocolError::UnbalancedQuotes);
+    };
+    let args = args
+        .into_iter()
+        .map(|arg| match arg {
+            Arg::Plain(range) => {
+                RESPRaw::BulkString(Tok::new(from + range.start, from + range.end))
+            }
+            Arg::Quoted(arg) => RESPRaw::Unescaped(Bytes::from(arg)),
+        })
+        .collect();
+
+    Ok(args)
+}
+
 /// Returns the range of the next word
 pub fn get_next_word(buf: &BytesMut, pos: usize) -> Option<(Tok, usize)> {
     // --- end of buffer
@@ -126,20 +491,130 @@ pub fn get_next_word(buf: &BytesMut, pos: usize) -> Option<(Tok, usize)> {
 }
 
 impl RedisValue {
-    pub fn serialize(self) -> Result<String> {
+    /// First byte of the value's RESP encoding
+    pub fn type_byte(&self) -> u8 {
+        match self {
+            RedisValue::SimpleString(_) => b'+',
+            RedisValue::SimpleError(_) => b'-',
+            RedisValue::Integer(_) => b':',
+            RedisValue::NullBulkString
+            | RedisValue::BulkString(_)
+            | RedisValue::Json(_)
+            | RedisValue::Counter(_) => b'$',
+            RedisValue::Map(_) => b'%',
+            RedisValue::Double(_) => b',',
+            RedisValue::Boolean(_) => b'#',
+            RedisValue::BigNumber(_) => b'(',
+            RedisValue::Push(_) => b'>',
+            RedisValue::Array(_)
+            | RedisValue::Replies(_)
+            | RedisValue::NullArray
+            | RedisValue::TimeSeries(_)
+            | RedisValue::Bloom(_)
+            | RedisValue::List(_)
+            | RedisValue::Hash(_)
+            | RedisValue::Set(_)
+            | RedisValue::SortedSet(_)
+            | RedisValue::Stream(_) => b'*',
+        }
+    }
+
+    /// Encodes the value as RESP2, binary-safe for bulk strings
+    pub fn serialize(self) -> Result<Bytes> {
+        self.serialize_as(Protocol::Resp2)
+    }
+
+    /// Encodes the value in the given version of the protocol, RESP3 types turning into
+    /// their RESP2 counterparts for RESP2
+    pub fn serialize_as(self, protocol: Protocol) -> Result<Bytes> {
+        let mut buf = BytesMut::new();
+        self.serialize_into(&mut buf, protocol)?;
+
+        Ok(buf.freeze())
+    }
+
+    fn serialize_into(self, buf: &mut BytesMut, protocol: Protocol) -> Result<()> {
+        let resp3 = protocol == Protocol::Resp3;
         match self {
-            RedisValue::SimpleString(s) => Ok(format!("+{}\r\n", str::from_utf8(&s)?)),
-            RedisValue::BulkString(b) => Ok(format!("${}\r\n{}\r\n", b.len(), str::from_utf8(&b)?)),
-            RedisValue::NullBulkString => Ok(String::from("$-1\r\n")),
-            RedisValue::SimpleError(e) => Ok(format!("-{}\r\n", str::from_utf8(&e)?)),
-            RedisValue::Array(arr) => Ok(format!(
-                "*{}\r\n{}",
-                arr.len(),
-                arr.into_iter()
-                    .map(|m| m.serialize().unwrap())
-                    .collect::<Vec<String>>()
-                    .join("")
-            )),
+            RedisValue::NullBulkString | RedisValue::NullArray if resp3 => {
+                buf.extend_from_slice(b"_\r\n")
+            }
+            RedisValue::Map(pairs) if resp3 => {
+                write_line(buf, b'%', pairs.len().to_string().as_bytes())?;
+                for (key, value) in pairs {
+                    key.serialize_into(buf, protocol)?;
+                    value.serialize_into(buf, protocol)?;
+                }
+            }
+            RedisValue::Map(pairs) => {
+                write_line(buf, b'*', (pairs.len() * 2).to_string().as_bytes())?;
+                for (key, value) in pairs {
+                    key.serialize_into(buf, protocol)?;
+                    value.serialize_into(buf, protocol)?;
+                }
+            }
+            RedisValue::Double(n) if resp3 => write_line(buf, b',', &n)?,
+            RedisValue::BigNumber(n) if resp3 => write_line(buf, b'(', &n)?,
+            RedisValue::Double(n) | RedisValue::BigNumber(n) => {
+                RedisValue::BulkString(n).serialize_into(buf, protocol)?
+            }
+            RedisValue::Boolean(b) if resp3 => write_line(buf, b'#', if b { b"t" } else { b"f" })?,
+            RedisValue::Boolean(b) => {
+                RedisValue::Integer(b as i64).serialize_into(buf, protocol)?
+            }
+            RedisValue::Push(items) => {
+                let prefix = if resp3 { b'>' } else { b'*' };
+                write_line(buf, prefix, items.len().to_string().as_bytes())?;
+                for item in items {
+                    item.serialize_into(buf, protocol)?;
+                }
+            }
+            RedisValue::SimpleString(s) => write_line(buf, b'+', &s)?,
+            RedisValue::SimpleError(e) => write_line(buf, b'-', &e)?,
+            RedisValue::Integer(i) => write_line(buf, b':', i.to_string().as_bytes())?,
+            RedisValue::NullBulkString => buf.extend_from_slice(b"$-1\r\n"),
+            RedisValue::NullArray => buf.extend_from_slice(b"*-1\r\n"),
+            RedisValue::BulkString(b) | RedisValue::Json(b) => {
+                write_line(buf, b'$', b.len().to_string().as_bytes())?;
+                buf.extend_from_slice(&b);
+                buf.extend_from_slice(b"\r\n");
+            }
+            RedisValue::Array(arr) => {
+                write_line(buf, b'*', arr.len().to_string().as_bytes())?;
+                for item in arr {
+                    item.serialize_into(buf, protocol)?;
+                }
+            }
+            RedisValue::Counter(n) => {
+                RedisValue::BulkString(Bytes::from(n.to_string())).serialize_into(buf, protocol)?
+            }
+            RedisValue::Replies(replies) => {
+                for reply in replies {
+                    reply.serialize_into(buf, protocol)?;
+                }
+            }
+            RedisValue::TimeSeries(_) => bail!("Time series can't be sent as is"),
+            RedisValue::Bloom(_) => bail!("Bloom filters can't be sent as is"),
+            RedisValue::List(_) => bail!("Lists can't be sent as is"),
+            RedisValue::Hash(_) => bail!("Hashes can't be sent as is"),
+            RedisValue::Set(_) => bail!("Sets can't be sent as is"),
+            RedisValue::SortedSet(_) => bail!("Sorted sets can't be sent as is"),
+            RedisValue::Stream(_) => bail!("Streams can't be sent as is"),
         }
+
+        Ok(())
     }
 }
+
+/// Writes a single CRLF-terminated line, which can't itself contain CR or LF
+fn write_line(buf: &mut BytesMut, prefix: u8, line: &[u8]) -> Result<()> {
+    if line.iter().any(|&b| b == b'\r' || b == b'\n') {
+        bail!("Simple strings and errors cannot contain CR or LF");
+    }
+
+    buf.extend_from_slice(&[prefix]);
+    buf.extend_from_slice(line);
+    buf.extend_from_slice(b"\r\n");
+
+    Ok(())
+}
@@ -1,224 +1,1777 @@
+use core::str;
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{BufReader, Read},
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
     path::Path,
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock, Weak,
+    },
+    time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Context};
 use bytes::Bytes;
-use tokio::{net::TcpListener, sync::Mutex};
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpStream,
+    signal::unix::{signal, Signal, SignalKind},
+    sync::{broadcast, mpsc, oneshot::error::TryRecvError, Mutex, Notify},
+    task::JoinSet,
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    repl::{
+        master::{ConnectedReplicas, RedisMasterContext},
+        replica::{follow_master, gen_uuid, MasterConnector, ReplicaAnnounce},
+        supervisor::{parse_node_addr, Supervisor, SupervisorConfig, SupervisorHandle},
+        ServerContext,
+    },
+    Args,
+};
 
-use crate::{repl::ServerContext, Args};
+use super::{
+    acl::{AclLog, AclUsers},
+    aof::{AppendFsync, AppendOnlyFile},
+    audit::{AuditCategory, AuditLog, AuditTarget},
+    bigkeys::KeyAnalysis,
+    blocking::BlockedClients,
+    clients::ConnectedClients,
+    clock::{Clock, SystemClock},
+    cluster::ClusterNode,
+    commands::{execute, psync, CommandContext, CommandRenames},
+    config::LiveConfig,
+    connlimit::{ConnectionLimiter, ConnectionLimits, ConnectionPermit, ConnectionRefusal},
+    cron::{MAX_HZ, MIN_HZ},
+    db::Db,
+    encoding::EncodingLimits,
+    events::KeyspaceEvents,
+    eviction::{EvictionPool, KeyAccess},
+    expiry::{ExpireCursor, ExpiryMode, ExpiryTimers},
+    handler::{AsyncStream, RedisConnectionHandler, RedisValue},
+    keyspace::Keyspace,
+    memory::{entry_size, parse_memory_size, DatabaseMemory, MemoryUsage},
+    monitor::Monitors,
+    net::{Accepted, Listeners, SocketOptions},
+    output::{write_limited, ClientClass},
+    persistence::{parse_save_points, SaveState, ShutdownFlags},
+    plugins::CommandRegistry,
+    pubsub::PubSub,
+    rdb::{self, ReplInfo},
+    record::{CommandRecorder, RecordedCommand},
+    scripting::{RunningScript, ScriptCache},
+    search::SearchIndexes,
+    serde::{ProtocolError, ProtocolLimits},
+    session::Session,
+    slowlog::SlowLog,
+    snapshot::{LocalDirStorage, SnapshotStorage},
+    stats::ServerStats,
+    watch::WatchedKeys,
+};
+#[cfg(feature = "chaos")]
+use crate::repl::chaos::FaultInjector;
+#[cfg(feature = "raft")]
+use crate::repl::raft::{RaftConfig, RaftNode};
+#[cfg(feature = "memcached")]
+use crate::server::memcached::ItemFlags;
+#[cfg(any(feature = "memcached", feature = "http"))]
+use crate::server::net::bind_host;
+#[cfg(feature = "tls")]
+use crate::server::tls::{CertificateUser, PeerIdentity, Tls, TlsAuthClients, TlsConfig};
+#[cfg(any(feature = "memcached", feature = "http"))]
+use tokio::net::TcpListener;
 
-use super::handler::RedisValue;
+/// Absolute expire times in ms, by key
+pub type Expires = im::HashMap<RedisValue, u64>;
+pub type RedisMainStore = Arc<Mutex<Keyspace>>;
+pub type RedisExpireStore = Arc<Mutex<Expires>>;
+pub type RedisAccessStore = Arc<Mutex<HashMap<RedisValue, KeyAccess>>>;
+/// Contents of databases, by index
+pub type Dataset = BTreeMap<usize, Db>;
 
-const LEN_ENCODING_MASK: u8 = 0b11000000;
-const LEN_DECODING_MASK: u8 = 0b00111111;
+/// `databases` default, as in Redis
+pub const DEFAULT_DATABASES: usize = 16;
 
-pub type RedisMainStore = Arc<Mutex<HashMap<RedisValue, RedisValue>>>;
-pub type RedisExpireStore = Arc<Mutex<HashMap<RedisValue, u64>>>;
+/// One of the numbered databases SELECT switches between, each a key space of its own
+pub struct Database {
+    /// what SELECT takes to get here. SWAPDB exchanges what two databases hold, never
+    /// their index
+    pub index: usize,
+    pub main_store: RedisMainStore,
+    pub expire_store: RedisExpireStore,
+    /// LRU/LFU metadata, keys without an entry count as just created
+    pub access_store: RedisAccessStore,
+    /// TTL deadlines, only filled in `ExpiryMode::Precise`
+    pub expiry_timers: ExpiryTimers,
+    /// where the active expiry cycle is in its pass over the keys with a TTL
+    pub expire_cursor: ExpireCursor,
+    /// keys `maxmemory-policy` picks from when evicting
+    pub eviction_pool: EvictionPool,
+    /// secondary indexes over the documents and hashes of the database, FT.CREATE's
+    pub search_indexes: SearchIndexes,
+    /// share of the dataset estimate held here, checked against `maxmemory-db`
+    pub memory: DatabaseMemory,
+}
+impl Database {
+    fn new(index: usize, now: u64, memory: Arc<MemoryUsage>) -> Self {
+        Self {
+            index,
+            main_store: Arc::new(Mutex::new(Keyspace::new())),
+            expire_store: Arc::new(Mutex::new(Expires::new())),
+            access_store: Arc::new(Mutex::new(HashMap::new())),
+            expiry_timers: ExpiryTimers::new(now),
+            expire_cursor: ExpireCursor::default(),
+            eviction_pool: EvictionPool::default(),
+            search_indexes: SearchIndexes::default(),
+            memory: DatabaseMemory::new(memory),
+        }
+    }
+}
 pub struct RedisServerConfig {
     pub dir: String,
     pub dbfilename: String,
+    /// number of databases, `databases`
+    pub databases: usize,
+    /// whether writes are logged to the append-only file, `appendonly`
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: AppendFsync,
+    /// what a SIGTERM does with the dataset before exiting, `shutdown-on-sigterm`
+    pub shutdown_on_sigterm: ShutdownFlags,
+    /// same for SIGINT, `shutdown-on-sigint`
+    pub shutdown_on_sigint: ShutdownFlags,
+    /// how long a shutdown waits for lagging replicas, `shutdown-timeout`
+    pub shutdown_timeout: Duration,
+    pub expiry_mode: ExpiryMode,
+    /// address given to the master in place of the connection's, `replica-announce-ip`
+    pub replica_announce_ip: Option<String>,
+    /// port given to the master in place of the listener's, `replica-announce-port`
+    pub replica_announce_port: Option<u16>,
+    /// addresses the client listeners are bound to, `bind`
+    pub bind: Vec<String>,
+    /// path of the unix socket clients may connect to as well, `unixsocket`
+    pub unixsocket: Option<String>,
+    /// permissions of the unix socket file, `unixsocketperm`
+    pub unixsocketperm: Option<u32>,
+    pub socket_options: SocketOptions,
+    pub connection_limits: ConnectionLimits,
+    pub command_renames: CommandRenames,
+    /// whether commands and options of this server's own are on, `--enable-extensions`
+    pub extensions: bool,
+    /// rate of the server cron, in runs per second
+    pub hz: u64,
+    /// failover supervisor to run next to the server, `--supervise-master`
+    pub supervisor: Option<SupervisorConfig>,
+    /// single node cluster mode, `cluster-enabled`
+    pub cluster_enabled: bool,
+    /// Sentinel mode, serving no dataset, `--sentinel`
+    pub sentinel: bool,
+    /// command log to append to, `--record-commands`
+    pub record_commands: Option<String>,
+    /// where audit entries go, `--audit-log`
+    pub audit_log: Option<AuditTarget>,
+    /// what gets audited, `--audit-commands`
+    pub audit_categories: Vec<AuditCategory>,
+    /// entries kept by ACL LOG, `acllog-max-len`
+    pub acllog_max_len: usize,
+    /// file CONFIG RELOAD and SIGHUP read the settings again from
+    pub config_file: Option<String>,
+    /// arguments given at startup, on top of the config file when it is read again
+    pub command_line: Vec<String>,
+    /// what CONFIG SET may change while the server runs
+    pub live: RwLock<LiveConfig>,
+    /// port of the memcached listener, `--memcached-port`
+    #[cfg(feature = "memcached")]
+    pub memcached_port: Option<u16>,
+    /// port of the HTTP gateway, `--http-port`
+    #[cfg(feature = "http")]
+    pub http_port: Option<u16>,
+    /// raft cluster this server is a member of, `--raft`
+    #[cfg(feature = "raft")]
+    pub raft: Option<RaftConfig>,
+    /// libraries registering custom commands, `--load-plugin`
+    #[cfg(feature = "plugins")]
+    pub plugins: Vec<String>,
+    /// TLS port, certificates and replication, `--tls-*`
+    #[cfg(feature = "tls")]
+    pub tls: TlsConfig,
 }
+impl Default for RedisServerConfig {
+    fn default() -> Self {
+        Self {
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            databases: DEFAULT_DATABASES,
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appendfsync: AppendFsync::default(),
+            shutdown_on_sigterm: ShutdownFlags::default(),
+            shutdown_on_sigint: ShutdownFlags::default(),
+            shutdown_timeout: Duration::from_secs(10),
+            expiry_mode: ExpiryMode::default(),
+            replica_announce_ip: None,
+            replica_announce_port: None,
+            bind: vec!["127.0.0.1".to_string()],
+            unixsocket: None,
+            unixsocketperm: None,
+            socket_options: SocketOptions::default(),
+            connection_limits: ConnectionLimits::default(),
+            command_renames: CommandRenames::default(),
+            extensions: false,
+            hz: 10,
+            supervisor: None,
+            cluster_enabled: false,
+            sentinel: false,
+            record_commands: None,
+            audit_log: None,
+            audit_categories: vec![AuditCategory::Write, AuditCategory::Admin],
+            acllog_max_len: 128,
+            config_file: None,
+            command_line: vec![],
+            live: RwLock::default(),
+            #[cfg(feature = "memcached")]
+            memcached_port: None,
+            #[cfg(feature = "http")]
+            http_port: None,
+            #[cfg(feature = "raft")]
+            raft: None,
+            #[cfg(feature = "plugins")]
+            plugins: vec![],
+            #[cfg(feature = "tls")]
+            tls: TlsConfig::default(),
+        }
+    }
+}
+impl RedisServerConfig {
+    pub fn from_args(args: &Args) -> anyhow::Result<Self> {
+        let default = Self::default();
+        let live = LiveConfig::default();
+
+        let res = Self {
+            dir: args.dir.clone().unwrap_or(default.dir),
+            dbfilename: args.dbfilename.clone().unwrap_or(default.dbfilename),
+            databases: match args.databases {
+                Some(0) => bail!("Invalid number of databases: 0"),
+                Some(databases) => databases,
+                None => default.databases,
+            },
+            // --- sentinels have no dataset to log
+            appendonly: args.appendonly.unwrap_or(default.appendonly) && !args.sentinel,
+            appendfilename: args
+                .appendfilename
+                .clone()
+                .unwrap_or(default.appendfilename),
+            appendfsync: match &args.appendfsync {
+                Some(policy) => policy.parse()?,
+                None => default.appendfsync,
+            },
+            shutdown_on_sigterm: match &args.shutdown_on_sigterm {
+                Some(flags) => flags.parse()?,
+                None => default.shutdown_on_sigterm,
+            },
+            shutdown_on_sigint: match &args.shutdown_on_sigint {
+                Some(flags) => flags.parse()?,
+                None => default.shutdown_on_sigint,
+            },
+            shutdown_timeout: args
+                .shutdown_timeout
+                .map_or(default.shutdown_timeout, Duration::from_secs),
+            expiry_mode: match &args.expiry_mode {
+                Some(mode) => mode.parse()?,
+                None => default.expiry_mode,
+            },
+            replica_announce_ip: args.replica_announce_ip.clone(),
+            replica_announce_port: args.replica_announce_port,
+            bind: match args.bind.is_empty() {
+                true => default.bind,
+                false => args.bind.clone(),
+            },
+            unixsocket: args.unixsocket.clone(),
+            unixsocketperm: match &args.unixsocketperm {
+                Some(mode) => Some(
+                    u32::from_str_radix(mode, 8)
+                        .with_context(|| format!("Invalid unixsocketperm: '{}'", mode))?,
+                ),
+                None => None,
+            },
+            socket_options: SocketOptions {
+                keepalive: args
+                    .tcp_keepalive
+                    .unwrap_or(default.socket_options.keepalive),
+                nodelay: args.tcp_nodelay.unwrap_or(default.socket_options.nodelay),
+            },
+            connection_limits: ConnectionLimits {
+                max_total: args
+                    .maxclients
+                    .unwrap_or(default.connection_limits.max_total),
+                max_clients: args
+                    .max_clients_per_ip
+                    .unwrap_or(default.connection_limits.max_clients),
+                max_rate: args
+                    .max_connection_rate_per_ip
+                    .unwrap_or(default.connection_limits.max_rate),
+            },
+            command_renames: CommandRenames::from_pairs(&args.rename_command)?,
+            extensions: args.enable_extensions,
+            hz: args.hz.unwrap_or(default.hz).clamp(MIN_HZ, MAX_HZ),
+            supervisor: match &args.supervise_master {
+                Some(master) => Some(SupervisorConfig {
+                    name: args
+                        .supervise_master_name
+                        .clone()
+                        .unwrap_or(SupervisorConfig::DEFAULT_NAME.to_string()),
+                    master: parse_node_addr(master)?,
+                    replicas: args
+                        .supervise_replica
+                        .iter()
+                        .map(|replica| parse_node_addr(replica))
+                        .collect::<anyhow::Result<_>>()?,
+                    peers: args
+                        .sentinel_peer
+                        .iter()
+                        .map(|peer| parse_node_addr(peer))
+                        .collect::<anyhow::Result<_>>()?,
+                    down_after: Duration::from_millis(
+                        args.down_after_milliseconds
+                            .unwrap_or(SupervisorConfig::DEFAULT_DOWN_AFTER_MS),
+                    ),
+                    quorum: args.quorum.unwrap_or(SupervisorConfig::DEFAULT_QUORUM),
+                }),
+                None => None,
+            },
+            cluster_enabled: args.cluster_enabled,
+            sentinel: args.sentinel,
+            record_commands: args.record_commands.clone(),
+            audit_log: match &args.audit_log {
+                Some(target) => Some(target.parse()?),
+                None => None,
+            },
+            audit_categories: match &args.audit_commands {
+                Some(categories) => AuditCategory::parse_list(categories)?,
+                None => default.audit_categories,
+            },
+            acllog_max_len: args.acllog_max_len.unwrap_or(default.acllog_max_len),
+            config_file: args.config_file.clone(),
+            command_line: args.command_line.clone(),
+            live: RwLock::new(LiveConfig {
+                save_points: match &args.save {
+                    _ if args.sentinel => vec![],
+                    Some(save) => parse_save_points(save)?,
+                    None => live.save_points,
+                },
+                maxmemory: match &args.maxmemory {
+                    Some(maxmemory) => parse_memory_size(maxmemory)?,
+                    None => live.maxmemory,
+                },
+                maxmemory_db: match &args.maxmemory_db {
+                    Some(quotas) => quotas.parse()?,
+                    None => live.maxmemory_db,
+                },
+                maxmemory_policy: match &args.maxmemory_policy {
+                    Some(policy) => policy.parse()?,
+                    None => live.maxmemory_policy,
+                },
+                lfu_log_factor: args.lfu_log_factor.unwrap_or(live.lfu_log_factor),
+                lfu_decay_time: args.lfu_decay_time.unwrap_or(live.lfu_decay_time),
+                // --- a zero timeout would drop the link right away
+                repl_timeout: args
+                    .repl_timeout
+                    .map_or(live.repl_timeout, |secs| Duration::from_secs(secs.max(1))),
+                replica_serve_stale_data: args
+                    .replica_serve_stale_data
+                    .unwrap_or(live.replica_serve_stale_data),
+                replica_read_only: args.replica_read_only.unwrap_or(live.replica_read_only),
+                client_output_buffer_limits: match &args.client_output_buffer_limit {
+                    Some(limits) => limits.parse()?,
+                    None => live.client_output_buffer_limits,
+                },
+                client_fairness_budget: args
+                    .client_fairness_budget
+                    .unwrap_or(live.client_fairness_budget),
+                requirepass: args.requirepass.clone(),
+                notify_keyspace_events: match &args.notify_keyspace_events {
+                    Some(flags) => flags.parse()?,
+                    None => live.notify_keyspace_events,
+                },
+                slowlog_log_slower_than: args
+                    .slowlog_log_slower_than
+                    .unwrap_or(live.slowlog_log_slower_than),
+                slowlog_max_len: args.slowlog_max_len.unwrap_or(live.slowlog_max_len),
+                timeout: args.timeout.unwrap_or(live.timeout),
+                busy_reply_threshold: args
+                    .busy_reply_threshold
+                    .map_or(live.busy_reply_threshold, Duration::from_millis),
+                lua_replicate_commands: args
+                    .lua_replicate_commands
+                    .unwrap_or(live.lua_replicate_commands),
+                encoding_limits: EncodingLimits {
+                    hash_max_listpack_entries: args
+                        .hash_max_listpack_entries
+                        .unwrap_or(live.encoding_limits.hash_max_listpack_entries),
+                    hash_max_listpack_value: args
+                        .hash_max_listpack_value
+                        .unwrap_or(live.encoding_limits.hash_max_listpack_value),
+                    set_max_intset_entries: args
+                        .set_max_intset_entries
+                        .unwrap_or(live.encoding_limits.set_max_intset_entries),
+                    set_max_listpack_entries: args
+                        .set_max_listpack_entries
+                        .unwrap_or(live.encoding_limits.set_max_listpack_entries),
+                    set_max_listpack_value: args
+                        .set_max_listpack_value
+                        .unwrap_or(live.encoding_limits.set_max_listpack_value),
+                    zset_max_listpack_entries: args
+                        .zset_max_listpack_entries
+                        .unwrap_or(live.encoding_limits.zset_max_listpack_entries),
+                    zset_max_listpack_value: args
+                        .zset_max_listpack_value
+                        .unwrap_or(live.encoding_limits.zset_max_listpack_value),
+                    list_max_listpack_size: args
+                        .list_max_listpack_size
+                        .unwrap_or(live.encoding_limits.list_max_listpack_size),
+                },
+            }),
+            #[cfg(feature = "memcached")]
+            memcached_port: args.memcached_port,
+            #[cfg(feature = "http")]
+            http_port: args.http_port,
+            #[cfg(feature = "raft")]
+            raft: match args.raft {
+                true => Some(RaftConfig {
+                    peers: args
+                        .raft_peer
+                        .iter()
+                        .map(|peer| parse_node_addr(peer))
+                        .collect::<anyhow::Result<_>>()?,
+                    // --- without it anyone reaching the port could vote or rewrite the log
+                    secret: args
+                        .raft_secret
+                        .clone()
+                        .filter(|secret| !secret.is_empty())
+                        .ok_or_else(|| anyhow::anyhow!("--raft needs a --raft-secret"))?,
+                    election_timeout: Duration::from_millis(
+                        args.raft_election_timeout
+                            .unwrap_or(RaftConfig::DEFAULT_ELECTION_TIMEOUT_MS),
+                    ),
+                    state_file: args
+                        .raft_state_file
+                        .clone()
+                        .unwrap_or(RaftConfig::DEFAULT_STATE_FILE.to_string()),
+                    snapshot_entries: args
+                        .raft_snapshot_entries
+                        .unwrap_or(RaftConfig::DEFAULT_SNAPSHOT_ENTRIES),
+                }),
+                false => None,
+            },
+            #[cfg(feature = "plugins")]
+            plugins: args.load_plugin.clone(),
+            #[cfg(feature = "tls")]
+            tls: TlsConfig {
+                port: args.tls_port,
+                cert_file: args.tls_cert_file.clone(),
+                key_file: args.tls_key_file.clone(),
+                ca_cert_file: args.tls_ca_cert_file.clone(),
+                replication: args.tls_replication,
+                auth_clients: match &args.tls_auth_clients {
+                    Some(auth_clients) => auth_clients.parse()?,
+                    None => TlsAuthClients::default(),
+                },
+                auth_clients_user: match &args.tls_auth_clients_user {
+                    Some(field) => field.parse()?,
+                    None => CertificateUser::default(),
+                },
+            },
+        };
 
-type RedisServerAux = (
-    RedisMainStore,
-    RedisExpireStore,
-    Option<Arc<RedisServerConfig>>,
-);
+        Ok(res)
+    }
+}
 
 pub struct RedisServer {
-    pub config: Option<Arc<RedisServerConfig>>,
-    pub main_store: RedisMainStore,
-    pub expire_store: RedisExpireStore,
-    /// listener for the client connection
-    pub listener: TcpListener,
+    pub config: Arc<RedisServerConfig>,
+    /// key spaces SELECT picks from, `databases` of them
+    pub databases: Vec<Database>,
+    /// where clients connect, none when running in-process
+    pub listeners: Listeners,
     /// server context holding either master or replica context
-    pub server_context: ServerContext,
+    pub server_context: RwLock<ServerContext>,
+    /// time source for everything expiry related
+    pub clock: Arc<dyn Clock>,
+    /// when the server started, unix time in ms, for INFO's uptime
+    pub started_at: u64,
+    /// bounds on what clients can make the server buffer
+    pub limits: ProtocolLimits,
+    /// wakes up the accept loop once a SHUTDOWN went through
+    pub shutdown_signal: Notify,
+    pub stats: ServerStats,
+    /// dataset estimate of every database together, each keeping its own share as well
+    pub memory: Arc<MemoryUsage>,
+    /// whether expired keys are removed in the background, `DEBUG SET-ACTIVE-EXPIRE`
+    pub active_expire: AtomicBool,
+    /// database the next active expiry cycle starts from
+    pub expire_next_db: AtomicUsize,
+    /// set while a BGSAVE is writing its snapshot
+    pub bgsave_in_progress: Arc<AtomicBool>,
+    /// connections parked in blocking commands
+    pub blocked_clients: BlockedClients,
+    pub save_state: Arc<SaveState>,
+    /// where SAVE and BGSAVE write the dataset and startup reads it from
+    pub snapshot_storage: Arc<dyn SnapshotStorage>,
+    /// open and recent connections of every client IP
+    pub connection_limiter: Arc<ConnectionLimiter>,
+    /// last client ID handed out
+    pub last_client_id: AtomicU64,
+    /// replicas syncing from this server
+    pub replicas: ConnectedReplicas,
+    /// held by propagated writes until they are in the replication stream, and exclusively
+    /// by full resyncs so the snapshot they send matches the offset they send it at
+    pub replication_fence: tokio::sync::RwLock<()>,
+    /// held by commands while they run, and exclusively by EXEC so nothing runs in between
+    /// the commands of a transaction
+    pub exec_lock: tokio::sync::RwLock<()>,
+    /// client connections being served, for CLIENT LIST
+    pub clients: ConnectedClients,
+    /// state of the failover supervisor when one runs, for SENTINEL commands
+    pub supervisor: Option<SupervisorHandle>,
+    /// where accepted commands get logged, when recording
+    pub recorder: Option<CommandRecorder>,
+    /// where writes get logged for durability, with `appendonly`
+    pub aof: Option<AppendOnlyFile>,
+    /// where write and admin commands get audited, when enabled
+    pub audit: Option<AuditLog>,
+    /// users clients authenticate as and what they may run
+    pub acl_users: AclUsers,
+    /// authentication failures and permission denials, for ACL LOG
+    pub acl_log: AclLog,
+    /// commands added by downstream crates and plugins
+    pub custom_commands: CommandRegistry,
+    /// writes, deletions and expirations, for the embedded API
+    pub keyspace_events: KeyspaceEvents,
+    /// keys WATCHed by some connection, for EXEC to tell whether they changed
+    pub watched_keys: WatchedKeys,
+    /// scripts EVALSHA can run
+    pub scripts: ScriptCache,
+    /// the script EVAL or EVALSHA is running, which SCRIPT KILL stops
+    pub running_script: Arc<RunningScript>,
+    /// channels and patterns connections subscribed to, for PUBLISH and supervisor events
+    pub pubsub: Arc<PubSub>,
+    /// connections that sent MONITOR
+    pub monitors: Monitors,
+    /// commands that ran longer than `slowlog-log-slower-than`
+    pub slowlog: SlowLog,
+    /// largest and most accessed keys, MEMORY ANALYZE's
+    pub key_analysis: Arc<KeyAnalysis>,
+    /// replication faults armed by DEBUG, for tests
+    #[cfg(feature = "chaos")]
+    pub faults: FaultInjector,
+    /// listener for memcached clients, when enabled
+    #[cfg(feature = "memcached")]
+    pub memcached_listener: Option<TcpListener>,
+    /// flags memcached clients stored along with their items
+    #[cfg(feature = "memcached")]
+    pub memcached_flags: ItemFlags,
+    /// listener for the HTTP gateway, when enabled
+    #[cfg(feature = "http")]
+    pub http_listener: Option<TcpListener>,
+    /// this server as a cluster node, when cluster mode is enabled
+    pub cluster: Option<ClusterNode>,
+    /// this server's part in the raft cluster, when enabled
+    #[cfg(feature = "raft")]
+    pub raft: Option<Arc<RaftNode>>,
+    /// certificates loaded for the TLS port and the link to the master
+    #[cfg(feature = "tls")]
+    pub tls: Tls,
+    /// datasets being loaded, most commands are refused with -LOADING meanwhile
+    loading: AtomicUsize,
+    /// the server itself, for commands that leave work running in the background
+    this: Weak<RedisServer>,
 }
 impl RedisServer {
     pub async fn init(args: Args) -> anyhow::Result<Arc<Self>> {
-        let dir = args.dir;
-        let dbfilename = args.dbfilename;
-        let port = args.port.unwrap_or(6379);
+        RedisServer::init_with_clock(args, Arc::new(SystemClock)).await
+    }
+
+    /// Same as `init`, but reading time from the given clock (e.g. a `MockClock` in tests)
+    pub async fn init_with_clock(args: Args, clock: Arc<dyn Clock>) -> anyhow::Result<Arc<Self>> {
+        let config = RedisServerConfig::from_args(&args)?;
+        let storage = Arc::new(LocalDirStorage::new(&config.dir, &config.dbfilename));
+
+        RedisServer::init_with_storage(args, clock, storage).await
+    }
+
+    /// Same as `init_with_clock`, but keeping snapshots in the given storage instead of
+    /// `dir`/`dbfilename`
+    pub async fn init_with_storage(
+        args: Args,
+        clock: Arc<dyn Clock>,
+        snapshot_storage: Arc<dyn SnapshotStorage>,
+    ) -> anyhow::Result<Arc<Self>> {
+        let config = RedisServerConfig::from_args(&args)?;
+        let port = args
+            .port
+            .unwrap_or(if config.sentinel { 26379 } else { 6379 });
         let replica_of = args.replicaof;
 
-        // --- set up client listener
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", port))
-            .await
-            .unwrap();
+        let default_limits = ProtocolLimits::default();
+        let limits = ProtocolLimits {
+            max_bulk_len: args
+                .proto_max_bulk_len
+                .unwrap_or(default_limits.max_bulk_len),
+            max_multibulk_len: args
+                .proto_max_multibulk_len
+                .unwrap_or(default_limits.max_multibulk_len),
+            max_query_buffer: args
+                .client_query_buffer_limit
+                .unwrap_or(default_limits.max_query_buffer),
+        };
 
-        // --- master/replica context
-        let server_context = ServerContext::new(replica_of, port).await?;
+        // --- set up client listeners
+        let listeners = Listeners::bind(
+            &config.bind,
+            port as u16,
+            config.unixsocket.as_deref(),
+            config.unixsocketperm,
+        )
+        .await?;
+        #[cfg(feature = "tls")]
+        let tls = Tls::load(&config.tls)?;
+        #[cfg(feature = "tls")]
+        let listeners = match config.tls.port {
+            Some(tls_port) => listeners.with_tls(&config.bind, tls_port).await?,
+            None => listeners,
+        };
+        // --- port 0 asks the OS for an ephemeral port, advertise the one we actually got
+        let port = listeners
+            .local_addr()
+            .map_or(port, |addr| addr.port() as usize);
+        // --- the other listeners are on the first address
+        #[cfg(any(feature = "memcached", feature = "http"))]
+        let host = config
+            .bind
+            .first()
+            .map_or("127.0.0.1", |addr| bind_host(addr));
 
-        // --- init stores or load state from rdb file
-        let (main_store, expire_store, config): RedisServerAux = match (dir, dbfilename) {
-            (Some(dir), Some(dbfilename)) => RedisServer::from_rdbfile(&dir, &dbfilename)?,
-            _ => (
-                Arc::new(Mutex::new(HashMap::new())),
-                Arc::new(Mutex::new(HashMap::new())),
-                None,
-            ),
+        let supervisor = config
+            .supervisor
+            .as_ref()
+            .map(|supervisor| SupervisorHandle::new(supervisor, gen_uuid()));
+        let recorder = match &config.record_commands {
+            Some(path) => Some(CommandRecorder::open(path)?),
+            None => None,
+        };
+        let audit = match &config.audit_log {
+            Some(target) => Some(AuditLog::open(target, config.audit_categories.clone())?),
+            None => None,
+        };
+        let aof = config.appendonly.then(|| {
+            let path = Path::new(&config.dir).join(&config.appendfilename);
+            AppendOnlyFile::new(path, config.appendfsync)
+        });
+        let acl_users = AclUsers::new(config.live.read().unwrap().requirepass.as_deref());
+        let acl_log = AclLog::new(config.acllog_max_len);
+        let connection_limiter = Arc::new(ConnectionLimiter::new(config.connection_limits));
+        let custom_commands = CommandRegistry::default();
+        #[cfg(feature = "plugins")]
+        for path in config.plugins.iter() {
+            custom_commands.load_plugin(path)?;
+        }
+        #[cfg(feature = "memcached")]
+        let memcached_listener = match config.memcached_port {
+            Some(port) => Some(TcpListener::bind((host, port)).await?),
+            None => None,
         };
+        #[cfg(feature = "http")]
+        let http_listener = match config.http_port {
+            Some(port) => Some(TcpListener::bind((host, port)).await?),
+            None => None,
+        };
+        let cluster = config.cluster_enabled.then(ClusterNode::with_random_id);
+        #[cfg(feature = "raft")]
+        let raft = match config.raft.as_ref() {
+            Some(raft) => {
+                let addr = ("127.0.0.1".to_string(), port as u16);
+                let state_file = Path::new(&config.dir).join(&raft.state_file);
+                Some(Arc::new(RaftNode::new(raft.clone(), addr, state_file)?))
+            }
+            None => None,
+        };
+
+        // --- stores start empty and get filled once the dataset is loaded
+        let memory = Arc::new(MemoryUsage::default());
+        let server = Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            databases: (0..config.databases)
+                .map(|index| Database::new(index, clock.now(), memory.clone()))
+                .collect(),
+            config: Arc::new(config),
+            listeners,
+            server_context: RwLock::new(ServerContext::Master(RedisMasterContext::new())),
+            limits,
+            shutdown_signal: Notify::new(),
+            stats: ServerStats::default(),
+            memory,
+            active_expire: AtomicBool::new(true),
+            expire_next_db: AtomicUsize::new(0),
+            bgsave_in_progress: Arc::new(AtomicBool::new(false)),
+            blocked_clients: BlockedClients::default(),
+            started_at: clock.now(),
+            save_state: Arc::new(SaveState::new(clock.now())),
+            snapshot_storage,
+            connection_limiter,
+            last_client_id: AtomicU64::new(0),
+            replicas: ConnectedReplicas::default(),
+            replication_fence: tokio::sync::RwLock::new(()),
+            exec_lock: tokio::sync::RwLock::new(()),
+            clients: ConnectedClients::default(),
+            supervisor,
+            recorder,
+            aof,
+            audit,
+            acl_users,
+            acl_log,
+            custom_commands,
+            keyspace_events: KeyspaceEvents::default(),
+            watched_keys: WatchedKeys::default(),
+            scripts: ScriptCache::default(),
+            running_script: Arc::default(),
+            pubsub: Arc::default(),
+            monitors: Monitors::default(),
+            slowlog: SlowLog::default(),
+            key_analysis: Arc::default(),
+            cluster,
+            #[cfg(feature = "chaos")]
+            faults: FaultInjector::default(),
+            #[cfg(feature = "memcached")]
+            memcached_listener,
+            #[cfg(feature = "memcached")]
+            memcached_flags: ItemFlags::default(),
+            #[cfg(feature = "http")]
+            http_listener,
+            #[cfg(feature = "raft")]
+            raft,
+            #[cfg(feature = "tls")]
+            tls,
+            loading: AtomicUsize::new(0),
+            clock,
+        });
 
-        if server_context.is_master() {
-            log::info!("Redis server running on 127.0.0.1:{}", port);
+        // --- clients may connect while the dataset loads, they get -LOADING until it's in
+        let loading = server.start_loading();
+        let acceptor = tokio::spawn(Arc::clone(&server).accept_while_starting());
+        let started = server.start_up(replica_of, port).await;
+        drop(loading);
+        acceptor.abort();
+        started?;
+
+        Ok(server)
+    }
+
+    /// Loads the dataset and sets up the replication role, the part of `init` clients can
+    /// already connect during
+    async fn start_up(
+        self: &Arc<Self>,
+        replica_of: Option<String>,
+        port: usize,
+    ) -> anyhow::Result<()> {
+        // --- load state from rdb file, sentinels have no dataset
+        let repl_info = match (&self.aof, self.config.sentinel) {
+            (_, true) => None,
+            // --- a raft member starts from its snapshot, its log brings it up to date
+            #[cfg(feature = "raft")]
+            (_, false) if self.raft.is_some() => {
+                let raft = self.raft.as_ref().expect("Checked by the guard");
+                if let Some(data) = raft.snapshot_data()? {
+                    self.load_rdb(data).await?;
+                }
+                None
+            }
+            (Some(aof), false) => self.load_aof(aof).await?,
+            (None, false) => self.load_rdbfile().await?,
+        };
+
+        // --- master/replica context, resuming the replication history of the loaded data
+        let (server_context, master_link) = ServerContext::new(
+            replica_of,
+            ReplicaAnnounce::new(&self.config, port),
+            MasterConnector::new(self),
+            repl_info,
+        )
+        .await?;
+
+        let role = if self.config.sentinel {
+            "sentinel"
+        } else if server_context.is_master() {
+            "server"
         } else {
-            log::info!("Redis replica running on 127.0.0.1:{}", port);
-        }
-
-        Ok(Arc::new(Self {
-            main_store,
-            expire_store,
-            config,
-            listener,
-            server_context,
-        }))
-    }
-
-    fn from_rdbfile(dir: &str, dbfilename: &str) -> anyhow::Result<RedisServerAux> {
-        // --- redis config
-        let config = RedisServerConfig {
-            dir: dir.to_string(),
-            dbfilename: dbfilename.to_string(),
-        };
-
-        // --- open file and read contents into buf
-        let path = Path::new(&dir).join(&dbfilename);
-        let rdbfile = File::open(path);
-        if rdbfile.is_err() {
-            return Ok((
-                Arc::new(Mutex::new(HashMap::new())),
-                Arc::new(Mutex::new(HashMap::new())),
-                Some(Arc::new(config)),
-            ));
-        }
-        let mut buf: Vec<u8> = vec![];
-        let mut reader = BufReader::new(rdbfile.unwrap());
-        reader.read_to_end(&mut buf)?;
-
-        let fb_pos = buf.iter().position(|&b| b == 0xfb).unwrap();
-        let (main_store_size, next_pos) = parse_length_encoding(&buf, fb_pos + 1);
-        let (expire_store_size, mut next_pos) = parse_length_encoding(&buf, next_pos);
-
-        let mut main_store = HashMap::with_capacity(main_store_size);
-        let mut expire_store = HashMap::with_capacity(expire_store_size);
-
-        let mut parsing_complete = false;
-        while next_pos < buf.len() && buf[next_pos] != 0xfe {
-            match buf[next_pos] {
-                0xfc => {
-                    next_pos += 1;
-
-                    let expire_time_in_ms = u64::from_le_bytes(
-                        buf[next_pos..next_pos + 8]
-                            .try_into()
-                            .expect("Should be a slice of length 8"),
-                    );
-                    next_pos += 8;
+            "replica"
+        };
+        for addr in self
+            .listeners
+            .tcp
+            .iter()
+            .filter_map(|l| l.local_addr().ok())
+        {
+            log::info!("Redis {} running on {}", role, addr);
+        }
+        #[cfg(feature = "tls")]
+        for addr in self
+            .listeners
+            .tls
+            .iter()
+            .filter_map(|l| l.local_addr().ok())
+        {
+            log::info!("Redis {} running on {} (TLS)", role, addr);
+        }
+        if let Some(path) = &self.config.unixsocket {
+            log::info!("Redis {} running on {}", role, path);
+        }
+        *self.server_context.write().unwrap() = server_context;
+
+        // --- replicas start from the master's dataset unless they could continue from
+        // --- their own, then follow its command stream
+        if let Some(master_link) = master_link {
+            if let Some(rdb) = master_link.rdb {
+                if let Err(e) = self.load_rdb(rdb).await {
+                    log::error!("Failure loading RDB received from master: {}", e);
+                }
+            }
+            tokio::spawn(follow_master(Arc::clone(self), master_link.handler));
+        }
+
+        Ok(())
+    }
+
+    /// Serves clients that connect while `init` is still loading, until `run` takes over
+    async fn accept_while_starting(self: Arc<Self>) {
+        if self.listeners.is_empty() {
+            return;
+        }
+        loop {
+            match self.listeners.accept().await {
+                Ok(accepted) => self.serve_client(accepted),
+                Err(e) => log::error!("{}", e),
+            }
+        }
+    }
+
+    fn serve_client(self: &Arc<Self>, accepted: Accepted) {
+        let mut session = self.new_session();
+        session.auth_pending = !self.acl_users.default_open();
+        let redis_server = Arc::clone(self);
+        match accepted {
+            Accepted::Tcp(stream, addr) => {
+                let permit = match self.admit(&stream, addr) {
+                    Ok(permit) => permit,
+                    Err(refusal) => return self.refuse(stream, refusal.message()),
+                };
+                session.addr = Some(addr);
+                tokio::spawn(async move {
+                    handle_session(stream, session, redis_server).await;
+                    drop(permit);
+                });
+            }
+            // --- local clients, no address to rate limit by nor TCP options to set
+            Accepted::Unix(stream) => {
+                let permit = match self.connection_limiter.admit_local() {
+                    Ok(permit) => permit,
+                    Err(refusal) => return self.refuse(stream, refusal.message()),
+                };
+                tokio::spawn(async move {
+                    handle_session(stream, session, redis_server).await;
+                    drop(permit);
+                });
+            }
+            #[cfg(feature = "tls")]
+            Accepted::Tls(stream, addr) => {
+                let permit = match self.admit(&stream, addr) {
+                    Ok(permit) => permit,
+                    Err(refusal) => return self.refuse(stream, refusal.message()),
+                };
+                let Some(acceptor) = self.tls.acceptor.clone() else {
+                    return;
+                };
+                session.addr = Some(addr);
+                tokio::spawn(async move {
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => {
+                            let peer = stream.get_ref().1.peer_certificates();
+                            if let Some(peer) = peer.and_then(PeerIdentity::from_certs) {
+                                redis_server.identify(&mut session, &peer);
+                            }
+                            handle_session(stream, session, redis_server).await
+                        }
+                        Err(e) => log::warn!("TLS handshake with {} failed: {}", addr, e),
+                    }
+                    drop(permit);
+                });
+            }
+        }
+        self.stats
+            .total_connections_received
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records who a client certificate says the client is, logging the connection in as
+    /// the ACL user it names with `tls-auth-clients-user`
+    #[cfg(feature = "tls")]
+    fn identify(&self, session: &mut Session, peer: &PeerIdentity) {
+        session.tls_peer = Some(peer.to_string());
+        let field = self.config.tls.auth_clients_user;
+        if let Some(user) = peer.user(field, &self.acl_users) {
+            log::info!(
+                "Client {} authenticated as {} by its certificate",
+                session.client_info(),
+                user
+            );
+            session.user = Some(user);
+            session.auth_pending = false;
+        }
+    }
+
+    /// Counts a TCP connection against the connection limits, setting its socket up if it
+    /// stays under them
+    pub(super) fn admit(
+        &self,
+        stream: &TcpStream,
+        addr: SocketAddr,
+    ) -> Result<ConnectionPermit, ConnectionRefusal> {
+        let permit = self.connection_limiter.admit(addr.ip(), self.clock.now())?;
+        if let Err(e) = self.config.socket_options.apply(stream) {
+            log::warn!("Failure configuring client socket: {}", e);
+        }
+
+        Ok(permit)
+    }
+
+    /// Tells a client over the connection limits why it's dropped with `message`, in its
+    /// protocol, best effort as it may not even read it before the close
+    pub(super) fn refuse(&self, mut stream: impl AsyncStream + 'static, message: Vec<u8>) {
+        self.stats
+            .rejected_connections
+            .fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(async move {
+            let _ = stream.write_all(&message).await;
+        });
+    }
+
+    /// Marks the dataset as being loaded until the guard is dropped
+    pub fn start_loading(&self) -> LoadingGuard<'_> {
+        self.loading.fetch_add(1, Ordering::Relaxed);
+
+        LoadingGuard(&self.loading)
+    }
+
+    /// Whether clients should be told to come back later, `loading` in INFO
+    pub fn is_loading(&self) -> bool {
+        self.loading.load(Ordering::Relaxed) > 0
+    }
+
+    /// Replaces the whole dataset with the contents of an RDB image, clients get -LOADING
+    /// meanwhile. On a replica the master's stream then goes on in the database the image
+    /// says it had selected
+    pub async fn load_rdb(&self, data: Vec<u8>) -> anyhow::Result<()> {
+        let _loading = self.start_loading();
+        let now = self.clock.now();
+        let (dataset, repl_info) =
+            tokio::task::spawn_blocking(move || rdb::parse_with_repl_info(&data, now)).await??;
+        self.replace_dataset(dataset).await?;
+        if let (ServerContext::Replica(replica), Some(repl_info)) =
+            (&*self.server_context.read().unwrap(), repl_info)
+        {
+            let mut backlog = replica.backlog.lock().unwrap();
+            if backlog.stream_db().is_none() {
+                backlog.set_stream_db(Some(repl_info.stream_db));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the dataset from the snapshot storage, returning the replication history it was saved
+    /// with. Missing and corrupt files both leave the dataset empty
+    pub(super) async fn load_rdbfile(&self) -> anyhow::Result<Option<ReplInfo>> {
+        let storage = Arc::clone(&self.snapshot_storage);
+        let Some(buf) = tokio::task::spawn_blocking(move || storage.load()).await?? else {
+            return Ok(None);
+        };
+
+        let now = self.clock.now();
+        let parsed =
+            tokio::task::spawn_blocking(move || rdb::parse_with_repl_info(&buf, now)).await?;
+        match parsed {
+            Ok((dataset, repl_info)) => {
+                self.replace_dataset(dataset).await?;
+                Ok(repl_info)
+            }
+            Err(e) => {
+                log::error!(
+                    "Error while parsing rdbfile: {}. Defaulting to empty stores...",
+                    e
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Swaps in a whole new dataset, e.g. a loaded RDB file, returning the previous one.
+    /// Databases missing from it start empty, one with keys in a database past the
+    /// configured ones is refused
+    pub async fn replace_dataset(&self, mut dataset: Dataset) -> anyhow::Result<Dataset> {
+        if let Some(index) = dataset.keys().next_back() {
+            if *index >= self.databases.len() {
+                bail!(
+                    "The dataset has keys in database {} but only {} databases are configured",
+                    index,
+                    self.databases.len()
+                );
+            }
+        }
+
+        let mut res = Dataset::new();
+        for db in &self.databases {
+            let contents = dataset.remove(&db.index).unwrap_or_default();
+            let previous = self.replace_database(db, contents).await;
+            res.insert(db.index, previous);
+        }
+
+        Ok(res)
+    }
+
+    /// Swaps in new contents for a single database, returning the previous ones
+    pub async fn replace_database(&self, db: &Database, contents: Db) -> Db {
+        let (main_store, expire_store) = contents.into_stores();
+        let mut main_store_lock = db.main_store.lock().await;
+        let mut expire_store_lock = db.expire_store.lock().await;
+        db.memory.shrink(
+            main_store_lock
+                .iter()
+                .map(|(key, value)| entry_size(key, value))
+                .sum(),
+        );
+        db.memory.grow(
+            main_store
+                .iter()
+                .map(|(key, value)| entry_size(key, value))
+                .sum(),
+        );
+        if self.config.expiry_mode == ExpiryMode::Precise {
+            for (key, expire_time) in expire_store.iter() {
+                db.expiry_timers.schedule(key.clone(), *expire_time);
+            }
+        }
+        db.search_indexes.rebuild(&main_store);
+        let res = Db::from_stores(
+            std::mem::replace(&mut *main_store_lock, main_store),
+            std::mem::replace(&mut *expire_store_lock, expire_store),
+        );
+        db.access_store.lock().await.clear();
+        self.watched_keys.touch_db(db.index);
+
+        res
+    }
+
+    /// Exchanges what two databases hold along with their TTL timers (SWAPDB). Clients
+    /// keep the index they selected, now seeing the other data, and search indexes stay
+    /// with their database, indexing what it holds now
+    pub async fn swap_databases(&self, first: usize, second: usize) {
+        if first == second {
+            return;
+        }
+        // --- always locked in index order, as everything locking several databases does
+        let (low, high) = (first.min(second), first.max(second));
+        let (low, high) = (&self.databases[low], &self.databases[high]);
+        let mut low_main = low.main_store.lock().await;
+        let mut low_expire = low.expire_store.lock().await;
+        let mut low_access = low.access_store.lock().await;
+        let mut high_main = high.main_store.lock().await;
+        let mut high_expire = high.expire_store.lock().await;
+        let mut high_access = high.access_store.lock().await;
+        std::mem::swap(&mut *low_main, &mut *high_main);
+        std::mem::swap(&mut *low_expire, &mut *high_expire);
+        std::mem::swap(&mut *low_access, &mut *high_access);
+        low.expiry_timers.swap(&high.expiry_timers);
+        low.memory.swap(&high.memory);
+        low.search_indexes.rebuild(&low_main);
+        high.search_indexes.rebuild(&high_main);
+        low.expire_cursor.reset();
+        high.expire_cursor.reset();
+        low.eviction_pool.reset();
+        high.eviction_pool.reset();
+        self.watched_keys.touch_db(low.index);
+        self.watched_keys.touch_db(high.index);
+    }
+
+    /// Removes a key along with its TTL and access metadata, returning whether it was
+    /// there. A key that already expired counts as missing
+    pub async fn delete_key(&self, db: &Database, key: &RedisValue) -> bool {
+        self.delete_key_if(db, key, |_| true).await
+    }
+
+    /// `delete_key` for a key whose value passes `condition`, checked under the same
+    /// locks as the deletion. Expired keys go regardless
+    pub async fn delete_key_if(
+        &self,
+        db: &Database,
+        key: &RedisValue,
+        condition: impl FnOnce(&RedisValue) -> bool,
+    ) -> bool {
+        let mut main_store = db.main_store.lock().await;
+        let mut expire_store = db.expire_store.lock().await;
+        let expired = expire_store
+            .get(key)
+            .is_some_and(|timestamp| *timestamp < self.clock.now());
+        if !expired && !main_store.get(key).is_some_and(condition) {
+            return false;
+        }
+        expire_store.remove(key);
+        let Some(value) = main_store.remove(key) else {
+            return false;
+        };
+        db.memory.remove_entry(key, &value);
+        db.search_indexes.remove(key);
+        db.access_store.lock().await.remove(key);
+
+        if expired {
+            self.stats.record_expired_key();
+            self.key_changed(db.index, "expired", key);
+            return false;
+        }
+        self.save_state.mark_dirty();
+        self.key_changed(db.index, "del", key);
+
+        true
+    }
+
+    /// Signals a change to a key of a database: keyspace event subscribers hear about it
+    /// and clients watching it get their transaction aborted
+    pub fn key_changed(&self, db: usize, event: &str, key: &RedisValue) {
+        self.keyspace_events.notify(db, event, key);
+        self.watched_keys.touch(db, key);
+        let notifications = self.config.live.read().unwrap().notify_keyspace_events;
+        if let (true, RedisValue::BulkString(key)) = (notifications.enabled(), key) {
+            for (channel, message) in notifications.channels(db, event, key) {
+                self.pubsub.publish(&channel, &message);
+            }
+        }
+    }
+
+    /// Shared handle to the server, `None` only while it is being dropped
+    pub fn handle(&self) -> Option<Arc<Self>> {
+        self.this.upgrade()
+    }
+
+    /// State for a new connection, with its own client ID
+    pub fn new_session(&self) -> Session {
+        Session {
+            id: self.last_client_id.fetch_add(1, Ordering::Relaxed) + 1,
+            connected_at: self.clock.now(),
+            ..Default::default()
+        }
+    }
+
+    /// Address the client listener is bound to
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listeners.local_addr()
+    }
+
+    /// Address the TLS listener is bound to, when there is one
+    #[cfg(feature = "tls")]
+    pub fn tls_addr(&self) -> Option<SocketAddr> {
+        self.listeners.tls_addr()
+    }
+
+    /// Address the memcached listener is bound to, when there is one
+    #[cfg(feature = "memcached")]
+    pub fn memcached_addr(&self) -> Option<SocketAddr> {
+        self.memcached_listener
+            .as_ref()
+            .and_then(|l| l.local_addr().ok())
+    }
+
+    /// Address the HTTP gateway is bound to, when there is one
+    #[cfg(feature = "http")]
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.http_listener
+            .as_ref()
+            .and_then(|l| l.local_addr().ok())
+    }
 
-                    // --- type of the value, for now support only string encoding
-                    if buf[next_pos] != 0 {
-                        log::error!("Invalid encoding for value: {:x?}", buf[next_pos]);
-                        break;
+    /// Creates a master server with empty stores and no listener, for in-process use
+    pub fn in_memory(clock: Arc<dyn Clock>) -> Arc<Self> {
+        let config = RedisServerConfig::default();
+        let snapshot_storage = Arc::new(LocalDirStorage::new(&config.dir, &config.dbfilename));
+        let memory = Arc::new(MemoryUsage::default());
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            databases: (0..config.databases)
+                .map(|index| Database::new(index, clock.now(), memory.clone()))
+                .collect(),
+            config: Arc::new(config),
+            listeners: Listeners::default(),
+            server_context: RwLock::new(ServerContext::Master(RedisMasterContext::new())),
+            limits: ProtocolLimits::default(),
+            shutdown_signal: Notify::new(),
+            stats: ServerStats::default(),
+            memory,
+            active_expire: AtomicBool::new(true),
+            expire_next_db: AtomicUsize::new(0),
+            bgsave_in_progress: Arc::new(AtomicBool::new(false)),
+            blocked_clients: BlockedClients::default(),
+            started_at: clock.now(),
+            save_state: Arc::new(SaveState::new(clock.now())),
+            snapshot_storage,
+            connection_limiter: Arc::default(),
+            last_client_id: AtomicU64::new(0),
+            replicas: ConnectedReplicas::default(),
+            replication_fence: tokio::sync::RwLock::new(()),
+            exec_lock: tokio::sync::RwLock::new(()),
+            clients: ConnectedClients::default(),
+            supervisor: None,
+            recorder: None,
+            aof: None,
+            audit: None,
+            acl_users: AclUsers::new(None),
+            acl_log: AclLog::new(RedisServerConfig::default().acllog_max_len),
+            custom_commands: CommandRegistry::default(),
+            keyspace_events: KeyspaceEvents::default(),
+            watched_keys: WatchedKeys::default(),
+            scripts: ScriptCache::default(),
+            running_script: Arc::default(),
+            pubsub: Arc::default(),
+            monitors: Monitors::default(),
+            slowlog: SlowLog::default(),
+            key_analysis: Arc::default(),
+            cluster: None,
+            #[cfg(feature = "chaos")]
+            faults: FaultInjector::default(),
+            #[cfg(feature = "memcached")]
+            memcached_listener: None,
+            #[cfg(feature = "memcached")]
+            memcached_flags: ItemFlags::default(),
+            #[cfg(feature = "http")]
+            http_listener: None,
+            #[cfg(feature = "raft")]
+            raft: None,
+            #[cfg(feature = "tls")]
+            tls: Tls::default(),
+            loading: AtomicUsize::new(0),
+            clock,
+        })
+    }
+
+    /// Accepts client connections until SHUTDOWN, SIGTERM or SIGINT stops the server, SIGHUP
+    /// reloading the config file
+    pub async fn run(self: Arc<Self>) {
+        let sigterm = signal(SignalKind::terminate()).expect("Failure installing SIGTERM handler");
+        let sigint = signal(SignalKind::interrupt()).expect("Failure installing SIGINT handler");
+        let sighup = signal(SignalKind::hangup()).expect("Failure installing SIGHUP handler");
+
+        self.accept_until(CancellationToken::new(), Some((sigterm, sigint, sighup)))
+            .await
+    }
+
+    /// Accepts client connections until SHUTDOWN or `shutdown` is cancelled, leaving the
+    /// process' signals alone, for servers embedded in another program. Cancelling drops
+    /// the clients without saving the dataset
+    pub async fn serve(self: Arc<Self>, shutdown: CancellationToken) {
+        self.accept_until(shutdown, None).await
+    }
+
+    async fn accept_until(
+        self: Arc<Self>,
+        shutdown: CancellationToken,
+        signals: Option<(Signal, Signal, Signal)>,
+    ) {
+        assert!(
+            !self.listeners.is_empty(),
+            "In-memory servers cannot accept connections"
+        );
+        let (mut sigterm, mut sigint, mut sighup) = match signals {
+            Some((sigterm, sigint, sighup)) => (Some(sigterm), Some(sigint), Some(sighup)),
+            None => (None, None, None),
+        };
+        let mut cron = self.cron_interval();
+        // --- background jobs that live as long as the server loop
+        let mut jobs = JoinSet::new();
+        if let (Some(config), Some(handle)) = (&self.config.supervisor, &self.supervisor) {
+            let period =
+                (config.down_after / 10).clamp(Duration::from_millis(10), Duration::from_secs(1));
+            let supervisor =
+                Supervisor::new(config.clone(), handle.clone(), Arc::clone(&self.pubsub));
+            jobs.spawn(supervisor.run(period));
+        }
+        if self.config.expiry_mode == ExpiryMode::Precise {
+            jobs.spawn(Arc::clone(&self).run_expiry());
+        }
+        #[cfg(feature = "memcached")]
+        jobs.spawn(Arc::clone(&self).run_memcached());
+        #[cfg(feature = "http")]
+        jobs.spawn(Arc::clone(&self).run_http());
+        #[cfg(feature = "raft")]
+        if let Some(raft) = &self.raft {
+            jobs.spawn(Arc::clone(raft).run_elections());
+            jobs.spawn(Arc::clone(raft).apply_committed(Arc::clone(&self)));
+            for peer in 0..raft.peers() {
+                jobs.spawn(Arc::clone(raft).replicate_to(peer));
+            }
+        }
+
+        loop {
+            tokio::select! {
+                accepted = self.listeners.accept() => match accepted {
+                    Ok(accepted) => self.serve_client(accepted),
+                    Err(e) => log::error!("{}", e),
+                },
+                _ = cron.tick() => self.cron().await,
+                _ = self.shutdown_signal.notified() => break,
+                _ = shutdown.cancelled() => {
+                    self.clients.kill(|_| true);
+                    break;
+                }
+                _ = received(&mut sigterm) => {
+                    log::warn!("Received SIGTERM scheduling shutdown...");
+                    match self.shutdown(self.config.shutdown_on_sigterm).await {
+                        Ok(()) => break,
+                        Err(e) => log::error!("Errors trying to shut down the server: {}", e),
+                    }
+                }
+                _ = received(&mut sigint) => {
+                    log::warn!("Received SIGINT scheduling shutdown...");
+                    match self.shutdown(self.config.shutdown_on_sigint).await {
+                        Ok(()) => break,
+                        Err(e) => log::error!("Errors trying to shut down the server: {}", e),
                     }
-                    next_pos += 1;
-
-                    let (key, next) = parse_rdb_string(&buf, next_pos)?;
-                    let (val, next) = parse_rdb_string(&buf, next)?;
-                    next_pos = next;
-
-                    // --- if the key has expired already, skip persisting this
-                    let now = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64;
-                    if expire_time_in_ms < now {
-                        continue;
+                }
+                _ = received(&mut sighup) => {
+                    log::warn!("Received SIGHUP reloading the config file...");
+                    if let Err(e) = self.config_reload() {
+                        log::error!("{}", e);
                     }
+                }
+            }
+        }
+
+        if let Some(path) = &self.config.unixsocket {
+            let _ = std::fs::remove_file(path);
+        }
+        log::info!("Redis is now ready to exit, bye bye...");
+    }
+}
+
+/// Waits for a signal, forever when it isn't handled
+async fn received(signal: &mut Option<Signal>) {
+    match signal {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
 
-                    main_store.insert(key.clone(), val);
-                    expire_store.insert(key, expire_time_in_ms);
-                    next_pos = next
+/// Serves a single client until it disconnects
+pub async fn handle_connection(stream: impl AsyncStream + 'static, redis_server: Arc<RedisServer>) {
+    let mut session = redis_server.new_session();
+    session.auth_pending = !redis_server.acl_users.default_open();
+    handle_session(stream, session, redis_server).await
+}
+
+/// Same as `handle_connection`, for a session already set up with what is known about
+/// the client
+async fn handle_session(
+    stream: impl AsyncStream + 'static,
+    mut session: Session,
+    redis_server: Arc<RedisServer>,
+) {
+    let mut handler = RedisConnectionHandler::with_limits(stream, redis_server.limits);
+    // --- a replica that went through PSYNC is forgotten once its connection ends
+    let _registration = ReplicaRegistration(&redis_server, session.id);
+    let mut killed = redis_server.clients.register(session.summary());
+    let _client = ClientRegistration(&redis_server, session.id);
+    let mut last_read = Instant::now();
+
+    loop {
+        // --- replicas and subscribers are expected to stay silent, like in Redis
+        let idle_timeout = match session.is_replica || session.in_subscribe_mode() {
+            true => 0,
+            false => redis_server.config.live.read().unwrap().timeout,
+        };
+        let frame = tokio::select! {
+            frame = handler.read_frame() => frame,
+            _ = &mut killed => {
+                log::info!("Client {} killed", session.client_info());
+                return;
+            }
+            _ = idle(last_read, idle_timeout) => {
+                log::info!("Closing idle client {}", session.client_info());
+                return;
+            }
+            _ = replication_stream(session.replication_feed.as_deref()) => {
+                match send_replication_stream(&mut handler, &session, &redis_server).await {
+                    Ok(true) => continue,
+                    Ok(false) => return,
+                    Err(e) => {
+                        log::error!("Failure sending the replication stream: {}", e);
+                        return;
+                    }
                 }
-                0xff => {
-                    parsing_complete = true;
-                    break;
+            }
+            message = pubsub_message(session.messages.as_mut()) => {
+                let limit = redis_server
+                    .config
+                    .live
+                    .read()
+                    .unwrap()
+                    .client_output_buffer_limits
+                    .get(ClientClass::Pubsub);
+                let within_limits = match write_limited(&mut handler, message, session.protocol, limit).await {
+                    Ok(within_limits) => within_limits,
+                    Err(e) => return close_after_failure(&session, "writing to client", e),
+                };
+                redis_server.stats.record_traffic(0, handler.take_written());
+                if !within_limits {
+                    log::warn!("Client closed for overcoming of output buffer limits (Pubsub class)");
+                    redis_server
+                        .stats
+                        .client_output_buffer_limit_disconnections
+                        .fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                continue;
+            }
+            line = monitor_line(session.monitor.as_mut()) => {
+                let limit = redis_server
+                    .config
+                    .live
+                    .read()
+                    .unwrap()
+                    .client_output_buffer_limits
+                    .get(ClientClass::Normal);
+                let line = RedisValue::SimpleString(line);
+                let within_limits = match write_limited(&mut handler, line, session.protocol, limit).await {
+                    Ok(within_limits) => within_limits,
+                    Err(e) => return close_after_failure(&session, "writing to client", e),
+                };
+                redis_server.stats.record_traffic(0, handler.take_written());
+                if !within_limits {
+                    log::warn!("Client closed for overcoming of output buffer limits (Normal class)");
+                    redis_server
+                        .stats
+                        .client_output_buffer_limit_disconnections
+                        .fetch_add(1, Ordering::Relaxed);
+                    return;
                 }
-                _ => {
-                    // --- type of the value, for now support only string encoding
-                    if buf[next_pos] != 0 {
-                        log::error!("Invalid encoding for value: {:x?}", buf[next_pos]);
-                        break;
+                continue;
+            }
+        };
+        last_read = Instant::now();
+        // --- a command read along with CLIENT KILL isn't run anymore
+        if !matches!(killed.try_recv(), Err(TryRecvError::Empty)) {
+            log::info!("Client {} killed", session.client_info());
+            return;
+        }
+        let parsed_data = match frame {
+            Ok(frame) => frame.map(|(value, len)| {
+                redis_server.stats.record_traffic(len, 0);
+                value
+            }),
+            Err(e) => match e.downcast::<ProtocolError>() {
+                Ok(e) => return close_with_protocol_error(&mut handler, &session, e).await,
+                // --- I/O failures mean the client is gone, nobody to reply to
+                Err(e) => return close_after_failure(&session, "reading from client", e),
+            },
+        };
+        // --- requests are arrays of bulk strings, empty ones are skipped like Redis does
+        let request_error = match &parsed_data {
+            Some(RedisValue::Array(arr)) if arr.is_empty() => continue,
+            Some(RedisValue::NullArray) => continue,
+            Some(RedisValue::Array(arr)) => arr.iter().find_map(|item| match item {
+                RedisValue::BulkString(_) => None,
+                RedisValue::NullBulkString => Some(ProtocolError::InvalidBulkLength),
+                other => Some(ProtocolError::UnexpectedType {
+                    expected: b'$',
+                    got: other.type_byte(),
+                }),
+            }),
+            Some(other) => Some(ProtocolError::UnexpectedType {
+                expected: b'*',
+                got: other.type_byte(),
+            }),
+            None => None,
+        };
+        if let Some(e) = request_error {
+            return close_with_protocol_error(&mut handler, &session, e).await;
+        }
+        let parsed_request = parsed_data;
+
+        match parsed_request {
+            Some(value) => {
+                let (cmd, args) = value.get_cmd_and_args();
+                // --- a name that isn't UTF-8 can't be a command, it gets the unknown reply
+                let cmd_as_str = String::from_utf8_lossy(&cmd);
+                let mut ctx = CommandContext {
+                    args: &args,
+                    server: &redis_server,
+                    session: &mut session,
+                };
+
+                // --- PSYNC hands the raw connection over to the replication layer
+                let resolved = redis_server
+                    .config
+                    .command_renames
+                    .resolve(&cmd_as_str.to_uppercase())
+                    .map(str::to_string);
+                if resolved.as_deref() == Some("PSYNC") {
+                    if let Err(e) = psync(&mut ctx, &mut handler).await {
+                        return close_after_failure(&session, "serving PSYNC", e);
+                    }
+                    continue;
+                }
+
+                if let Some(recorder) = &redis_server.recorder {
+                    let command = RecordedCommand {
+                        timestamp: redis_server.clock.now(),
+                        client_id: ctx.session.id,
+                        args: [cmd.clone()]
+                            .into_iter()
+                            .chain(args.iter().cloned())
+                            .collect(),
+                    };
+                    if let Err(e) = recorder.record(&command) {
+                        log::error!("Failure recording command: {}", e);
                     }
-                    next_pos += 1;
-
-                    let (key, next) = parse_rdb_string(&buf, next_pos)?;
-                    let (val, next) = parse_rdb_string(&buf, next)?;
-
-                    main_store.insert(key.clone(), val);
-                    next_pos = next
-                }
-            }
-        }
-
-        if !parsing_complete {
-            log::error!("Error while parsing rdbfile. Defaulting to empty stores...");
-            return Ok((
-                Arc::new(Mutex::new(HashMap::new())),
-                Arc::new(Mutex::new(HashMap::new())),
-                Some(Arc::new(config)),
-            ));
-        }
-
-        Ok((
-            Arc::new(Mutex::new(main_store)),
-            Arc::new(Mutex::new(expire_store)),
-            Some(Arc::new(config)),
-        ))
-    }
-}
-
-fn parse_rdb_string(buf: &Vec<u8>, pos: usize) -> Result<(RedisValue, usize)> {
-    let (str_len, next_pos) = parse_length_encoding(buf, pos);
-
-    if next_pos + str_len > buf.len() {
-        return Err(anyhow::anyhow!(
-            "Buffer overflow when parsing string: needed {} bytes but got {}",
-            str_len,
-            buf.len() - next_pos
-        ));
-    }
-    let raw_str = &buf[next_pos..next_pos + str_len];
-    let parsed = RedisValue::BulkString(Bytes::copy_from_slice(raw_str));
-    Ok((parsed, next_pos + str_len))
-}
-
-fn parse_length_encoding(buf: &Vec<u8>, pos: usize) -> (usize, usize) {
-    let enconding_byte = *buf.get(pos).unwrap();
-    match enconding_byte & LEN_ENCODING_MASK {
-        // --- one byte length
-        0b00000000 => ((enconding_byte & LEN_DECODING_MASK) as usize, pos + 1),
-        // --- 14 bit length
-        0b01000000 => unimplemented!("14 bit length encoding not implemented yet"),
-        // --- 4 byte length
-        0b10000000 => (
-            usize::from_le_bytes(
-                buf[pos + 1..pos + 5]
-                    .try_into()
-                    .expect("Should be a 4 byte slice"),
-            ),
-            pos + 5,
-        ),
-        // --- special encoding
-        0b11000000 => unimplemented!("Special encoding length not implemented yet"),
-        _ => panic!(
-            "Unexpected length encoding: '{:08b}'",
-            buf.get(pos).unwrap()
-        ),
+                }
+
+                // --- messages published before the command go out ahead of its reply
+                if let Some(messages) = ctx.session.messages.as_mut() {
+                    while let Ok(message) = messages.try_recv() {
+                        match message.serialize_as(ctx.session.protocol) {
+                            Ok(message) => handler.queue_raw(&message),
+                            Err(e) => log::error!("Failure serializing message: {}", e),
+                        }
+                    }
+                }
+                ctx.session.last_command = Some(cmd_as_str.to_lowercase());
+                ctx.session.last_interaction = redis_server.clock.now();
+                redis_server.clients.update(ctx.session.summary());
+                // --- a failing command costs its reply, not the connection
+                let res = match execute(&cmd_as_str, &mut ctx).await {
+                    Ok(res) => res,
+                    Err(e) => {
+                        log::error!(
+                            "Failure running '{}' for client {}: {}",
+                            cmd_as_str,
+                            session.client_info(),
+                            e
+                        );
+                        error_reply(&e)
+                    }
+                };
+                // --- e.g. the subscriptions it changed show in CLIENT LIST
+                redis_server.clients.update(session.summary());
+                // --- a replica's ACKs go unanswered, its link only carries the stream to it
+                let is_ack = resolved.as_deref() == Some("REPLCONF")
+                    && args
+                        .first()
+                        .is_some_and(|sub| sub.eq_ignore_ascii_case(b"ACK"));
+                if session.is_replica && is_ack {
+                    continue;
+                }
+                let limit = redis_server
+                    .config
+                    .live
+                    .read()
+                    .unwrap()
+                    .client_output_buffer_limits
+                    .get(session.client_class());
+                let within_limits =
+                    match write_limited(&mut handler, res, session.protocol, limit).await {
+                        Ok(within_limits) => within_limits,
+                        Err(e) => return close_after_failure(&session, "writing to client", e),
+                    };
+                redis_server.stats.record_traffic(0, handler.take_written());
+                if !within_limits {
+                    log::warn!(
+                        "Client closed for overcoming of output buffer limits ({:?} class)",
+                        session.client_class()
+                    );
+                    redis_server
+                        .stats
+                        .client_output_buffer_limit_disconnections
+                        .fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                if session.quitting {
+                    break;
+                }
+
+                // --- a busy client, e.g. with a long pipeline, lets the others run now
+                // and then instead of going back to the stores right away
+                session.charge(1);
+                let budget = redis_server
+                    .config
+                    .live
+                    .read()
+                    .unwrap()
+                    .client_fairness_budget;
+                if budget > 0 && session.work_done >= budget {
+                    session.work_done = 0;
+                    redis_server.stats.record_fairness_yield();
+                    tokio::task::yield_now().await;
+                }
+            }
+            None => {
+                break;
+            }
+        };
+    }
+
+    log::info!("Closing connection...");
+}
+
+/// Resolves once there is replication stream to send, never for regular clients
+async fn replication_stream(feed: Option<&Notify>) {
+    match feed {
+        Some(feed) => feed.notified().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves once `timeout` seconds went by since the client last sent something, never
+/// with a 0 timeout
+async fn idle(since: Instant, timeout: u64) {
+    match timeout {
+        0 => std::future::pending().await,
+        timeout => tokio::time::sleep_until(since + Duration::from_secs(timeout)).await,
+    }
+}
+
+/// Next message published for a subscribed connection, never resolves for the others
+async fn pubsub_message(messages: Option<&mut mpsc::UnboundedReceiver<RedisValue>>) -> RedisValue {
+    let message = match messages {
+        Some(messages) => messages.recv().await,
+        None => None,
+    };
+    match message {
+        Some(message) => message,
+        None => std::future::pending().await,
+    }
+}
+
+/// Next command line for a connection in MONITOR mode, never resolves for the others.
+/// Lines a slow monitor missed are skipped
+async fn monitor_line(monitor: Option<&mut broadcast::Receiver<Bytes>>) -> Bytes {
+    let Some(monitor) = monitor else {
+        return std::future::pending().await;
+    };
+    loop {
+        match monitor.recv().await {
+            Ok(line) => return line,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                log::warn!("Monitor fell behind, {} commands not shown", missed);
+            }
+            Err(broadcast::error::RecvError::Closed) => return std::future::pending().await,
+        }
+    }
+}
+
+/// Sends the stream buffered for the replica on this connection. A replica that fell
+/// past the hard `client-output-buffer-limit` is dropped instead, returning false
+async fn send_replication_stream(
+    handler: &mut RedisConnectionHandler,
+    session: &Session,
+    redis_server: &RedisServer,
+) -> anyhow::Result<bool> {
+    let pending = redis_server.replicas.take_pending(session.id);
+    let limit = redis_server
+        .config
+        .live
+        .read()
+        .unwrap()
+        .client_output_buffer_limits
+        .get(ClientClass::Replica);
+    if limit.hard > 0 && pending.len() > limit.hard {
+        log::warn!(
+            "Replica closed for overcoming of output buffer limits ({} bytes pending)",
+            pending.len()
+        );
+        redis_server
+            .stats
+            .client_output_buffer_limit_disconnections
+            .fetch_add(1, Ordering::Relaxed);
+        return Ok(false);
+    }
+    handler.write_raw(&pending).await?;
+    redis_server.stats.record_traffic(0, handler.take_written());
+
+    Ok(true)
+}
+
+/// Tells the client what was wrong with its request before hanging up, the way Redis does
+async fn close_with_protocol_error(
+    handler: &mut RedisConnectionHandler,
+    session: &Session,
+    e: ProtocolError,
+) {
+    log::error!(
+        "Protocol error from client {}, closing connection: {}",
+        session.client_info(),
+        e
+    );
+    let res = RedisValue::SimpleError(Bytes::from(format!("ERR Protocol error: {}", e)));
+    let _ = handler.write(res).await;
+}
+
+/// Logs what ended a connection, nothing being sent back: the client is gone or its
+/// connection in an unknown state
+fn close_after_failure(session: &Session, doing: &str, e: anyhow::Error) {
+    log::error!(
+        "Failure {} for client {}, closing connection: {:#}",
+        doing,
+        session.client_info(),
+        e
+    );
+}
+
+/// Reply to a command that failed, its error kept as is when it already reads like one,
+/// e.g. "ERR ..." or "WRONGTYPE ..."
+fn error_reply(e: &anyhow::Error) -> RedisValue {
+    // --- a simple error ends at the first line break
+    let message = e.to_string().replace(['\r', '\n'], " ");
+    let has_code = message
+        .split_once(' ')
+        .is_some_and(|(code, _)| !code.is_empty() && code.bytes().all(|b| b.is_ascii_uppercase()));
+    let res = match has_code {
+        true => RedisValue::SimpleError(Bytes::from(message)),
+        false => RedisValue::SimpleError(Bytes::from(format!("ERR {}", message))),
+    };
+
+    res
+}
+
+/// Removes the replica served on a connection from `RedisServer::replicas` when dropped
+struct ReplicaRegistration<'a>(&'a RedisServer, u64);
+impl Drop for ReplicaRegistration<'_> {
+    fn drop(&mut self) {
+        self.0.replicas.remove(self.1);
+    }
+}
+
+/// Removes a connection from `RedisServer::clients` when dropped
+struct ClientRegistration<'a>(&'a RedisServer, u64);
+impl Drop for ClientRegistration<'_> {
+    fn drop(&mut self) {
+        self.0.clients.remove(self.1);
+        self.0.watched_keys.unwatch_all(self.1);
+        self.0.pubsub.forget(self.1);
+    }
+}
+
+/// Keeps the server in the loading state, see `RedisServer::start_loading`
+pub struct LoadingGuard<'a>(&'a AtomicUsize);
+impl Drop for LoadingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
     }
 }
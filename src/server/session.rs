@@ -0,0 +1,171 @@
+use std::{collections::BTreeSet, net::SocketAddr, sync::Arc};
+
+use bytes::Bytes;
+use tokio::sync::{broadcast, mpsc, Notify};
+
+use super::{
+    acl::DEFAULT_USER, clients::ClientSummary, handler::RedisValue, output::ClientClass,
+    pubsub::Inbox, scripting::ReplTargets, serde::Protocol,
+};
+
+/// Per-connection state, shared by every command issued on that connection
+#[derive(Debug, Default)]
+pub struct Session {
+    /// unique ID of the connection, as used by `CLIENT UNBLOCK`
+    pub id: u64,
+    /// address of the client, `None` for in-process sessions
+    pub addr: Option<SocketAddr>,
+    /// when the session started, unix time in ms
+    pub connected_at: u64,
+    /// name the connection gave itself, `CLIENT SETNAME`
+    pub name: Option<String>,
+    /// command it ran last, lowercased
+    pub last_command: Option<String>,
+    /// when it last sent a command, unix time in ms
+    pub last_interaction: u64,
+    /// client library the connection announced, `CLIENT SETINFO LIB-NAME`
+    pub lib_name: Option<String>,
+    /// its version, `CLIENT SETINFO LIB-VER`
+    pub lib_ver: Option<String>,
+    /// identity of the certificate the client presented over TLS, its common name or
+    /// first alternative name
+    pub tls_peer: Option<String>,
+    /// user the connection AUTHed as, the default user until then
+    pub user: Option<String>,
+    /// the default user has a password the connection didn't AUTH with yet, only AUTH and
+    /// HELLO may run
+    pub auth_pending: bool,
+    /// the connection sent QUIT, it's closed once the reply went out
+    pub quitting: bool,
+    /// version of the protocol replies go out in, switched by HELLO
+    pub protocol: Protocol,
+    /// database commands run against, switched by SELECT
+    pub db: usize,
+    /// channels the connection SUBSCRIBEd to
+    pub channels: BTreeSet<Bytes>,
+    /// glob patterns it PSUBSCRIBEd to
+    pub patterns: BTreeSet<Bytes>,
+    /// where PUBLISH sends messages for the connection, set up by its first subscription
+    pub inbox: Option<Inbox>,
+    /// messages published for the connection and not sent yet
+    pub messages: Option<mpsc::UnboundedReceiver<RedisValue>>,
+    /// lines of the commands other clients run, once the connection sent MONITOR
+    pub monitor: Option<broadcast::Receiver<Bytes>>,
+    /// exempt from client eviction, `CLIENT NO-EVICT`
+    pub no_evict: bool,
+    /// reads don't update the access metadata of keys, `CLIENT NO-TOUCH`
+    pub no_touch: bool,
+    /// the replication link to our master, whose commands are never refused
+    pub is_master_link: bool,
+    /// replays the append-only file at startup, its commands are never refused nor logged
+    /// and propagated again
+    pub is_aof_client: bool,
+    /// a replica that went through PSYNC on this connection
+    pub is_replica: bool,
+    /// a raft member that proved it knows `raft-secret` with RAFT AUTH, the only kind of
+    /// connection REQUESTVOTE and APPENDENTRIES are taken from
+    pub is_raft_peer: bool,
+    /// port the replica listens on, `REPLCONF listening-port`
+    pub replica_listening_port: Option<u16>,
+    /// address the replica announced, `REPLCONF ip-address`
+    pub replica_announced_ip: Option<String>,
+    /// capabilities the replica announced, `REPLCONF capa`, lowercase
+    pub replica_capa: Vec<String>,
+    /// woken when there is replication stream to send to the replica on this connection
+    pub replication_feed: Option<Arc<Notify>>,
+    /// work done since the connection last gave way to others, `client-fairness-budget`
+    pub work_done: usize,
+    /// commands queued since MULTI, `None` outside of a transaction
+    pub transaction: Option<Transaction>,
+    /// set while EXEC runs the queued commands
+    pub in_exec: bool,
+    /// set while a script runs, its commands going through the usual checks but not the
+    /// exec lock the script holds
+    pub in_script: bool,
+    /// where the write a script's command makes goes, everywhere outside scripts
+    pub repl_targets: ReplTargets,
+    /// keys WATCHed, with their database and the version they had then, see `WatchedKeys`
+    pub watched: Vec<(usize, RedisValue, u64)>,
+    /// writes the running command made for other clients, e.g. the pops of the BLPOPs a
+    /// push served, replicated right after it
+    pub propagate_after: Vec<(&'static str, Vec<Bytes>)>,
+}
+/// What MULTI queued so far
+#[derive(Debug, Default)]
+pub struct Transaction {
+    /// command names, resolved and uppercased, with their arguments
+    pub commands: Vec<(String, Vec<Bytes>)>,
+    /// a command was refused while queueing, EXEC then discards them all
+    pub aborted: bool,
+}
+
+impl Session {
+    /// Number of channels and patterns the connection is subscribed to
+    pub fn subscriptions(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// RESP2 connections with active subscriptions only accept pub/sub commands
+    pub fn in_subscribe_mode(&self) -> bool {
+        self.subscriptions() > 0
+    }
+
+    /// Where PUBLISH should send messages for the connection, set up on first use
+    pub fn inbox(&mut self) -> Inbox {
+        let inbox = self.inbox.get_or_insert_with(|| {
+            let (inbox, messages) = mpsc::unbounded_channel();
+            self.messages = Some(messages);
+            inbox
+        });
+
+        inbox.clone()
+    }
+
+    /// Counts work done by the connection against its fairness budget
+    pub fn charge(&mut self, units: usize) {
+        self.work_done = self.work_done.saturating_add(units);
+    }
+
+    /// Which `client-output-buffer-limit` applies to the connection
+    pub fn client_class(&self) -> ClientClass {
+        if self.is_replica {
+            ClientClass::Replica
+        } else if self.in_subscribe_mode() {
+            ClientClass::Pubsub
+        } else {
+            ClientClass::Normal
+        }
+    }
+
+    /// User the connection runs commands as
+    pub fn user(&self) -> &str {
+        self.user.as_deref().unwrap_or(DEFAULT_USER)
+    }
+
+    /// Short description of the client, as ACL LOG reports it
+    pub fn client_info(&self) -> String {
+        match self.addr {
+            Some(addr) => format!("id={} addr={}", self.id, addr),
+            None => format!("id={}", self.id),
+        }
+    }
+
+    /// What CLIENT LIST and CLIENT INFO report about the connection
+    pub fn summary(&self) -> ClientSummary {
+        ClientSummary {
+            id: self.id,
+            addr: self.addr,
+            connected_at: self.connected_at,
+            name: self.name.clone(),
+            last_command: self.last_command.clone(),
+            last_interaction: self.last_interaction,
+            db: self.db,
+            channels: self.channels.len(),
+            patterns: self.patterns.len(),
+            user: self.user().to_string(),
+            lib_name: self.lib_name.clone(),
+            lib_ver: self.lib_ver.clone(),
+            tls_peer: self.tls_peer.clone(),
+        }
+    }
+}
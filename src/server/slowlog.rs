@@ -0,0 +1,126 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use bytes::Bytes;
+
+use super::{handler::RedisValue, session::Session};
+
+/// Arguments an entry keeps, the last one standing for those left out
+const MAX_ARGS: usize = 32;
+/// Bytes an argument keeps, the rest only counted
+const MAX_ARG_LEN: usize = 128;
+
+#[derive(Clone, Debug)]
+pub struct SlowLogEntry {
+    pub id: u64,
+    /// when the command ran, unix time in seconds
+    pub timestamp: u64,
+    /// how long it ran, in microseconds
+    pub duration: u64,
+    /// the command and its arguments, trimmed to `MAX_ARGS` of at most `MAX_ARG_LEN` bytes
+    pub args: Vec<Bytes>,
+    pub client_addr: String,
+    pub client_name: String,
+}
+impl SlowLogEntry {
+    /// Array of ID, timestamp, duration, arguments, client address and name, as SLOWLOG GET
+    /// replies with
+    pub fn to_value(&self) -> RedisValue {
+        RedisValue::Array(vec![
+            RedisValue::Integer(self.id as i64),
+            RedisValue::Integer(self.timestamp as i64),
+            RedisValue::Integer(self.duration as i64),
+            RedisValue::Array(
+                self.args
+                    .iter()
+                    .cloned()
+                    .map(RedisValue::BulkString)
+                    .collect(),
+            ),
+            RedisValue::BulkString(Bytes::from(self.client_addr.clone())),
+            RedisValue::BulkString(Bytes::from(self.client_name.clone())),
+        ])
+    }
+}
+
+/// Commands that ran longer than `slowlog-log-slower-than`, newest first and capped at
+/// `slowlog-max-len` entries
+#[derive(Debug, Default)]
+pub struct SlowLog {
+    entries: Mutex<VecDeque<SlowLogEntry>>,
+    next_id: Mutex<u64>,
+}
+impl SlowLog {
+    pub fn record(
+        &self,
+        now: u64,
+        duration: u64,
+        cmd: &str,
+        args: &[Bytes],
+        session: &Session,
+        max_len: usize,
+    ) {
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+            *next_id - 1
+        };
+        let total = args.len() + 1;
+        let mut kept = [Bytes::from(cmd.to_lowercase())]
+            .into_iter()
+            .chain(args.iter().cloned())
+            .take(if total > MAX_ARGS {
+                MAX_ARGS - 1
+            } else {
+                MAX_ARGS
+            })
+            .map(|arg| match arg.len() > MAX_ARG_LEN {
+                true => {
+                    let mut trimmed = arg[..MAX_ARG_LEN].to_vec();
+                    let more = format!("... ({} more bytes)", arg.len() - MAX_ARG_LEN);
+                    trimmed.extend_from_slice(more.as_bytes());
+                    Bytes::from(trimmed)
+                }
+                false => arg,
+            })
+            .collect::<Vec<_>>();
+        if total > MAX_ARGS {
+            kept.push(Bytes::from(format!(
+                "... ({} more arguments)",
+                total - MAX_ARGS + 1
+            )));
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_front(SlowLogEntry {
+            id,
+            timestamp: now / 1000,
+            duration,
+            args: kept,
+            client_addr: session
+                .addr
+                .map(|addr| addr.to_string())
+                .unwrap_or_default(),
+            client_name: session.name.clone().unwrap_or_default(),
+        });
+        entries.truncate(max_len);
+    }
+
+    /// Up to `count` of the most recent entries
+    pub fn entries(&self, count: usize) -> Vec<SlowLogEntry> {
+        let entries = self.entries.lock().unwrap();
+
+        entries.iter().take(count).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn reset(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,63 @@
+use std::{io, path::PathBuf, sync::Mutex};
+
+use anyhow::{Context, Result};
+
+/// Where RDB snapshots are saved to and loaded from, swappable for object storage or any
+/// other sink. Calls block, the server makes them from blocking threads
+pub trait SnapshotStorage: Send + Sync {
+    /// The last snapshot stored, `None` when there is none yet
+    fn load(&self) -> Result<Option<Vec<u8>>>;
+
+    /// Replaces the stored snapshot. A failed store must leave the previous one intact
+    fn store(&self, data: &[u8]) -> Result<()>;
+}
+
+/// The `dbfilename` file under `dir`, the default storage
+#[derive(Clone, Debug)]
+pub struct LocalDirStorage {
+    pub dir: PathBuf,
+    pub dbfilename: String,
+}
+impl LocalDirStorage {
+    pub fn new(dir: impl Into<PathBuf>, dbfilename: impl Into<String>) -> Self {
+        Self {
+            dir: dir.into(),
+            dbfilename: dbfilename.into(),
+        }
+    }
+}
+impl SnapshotStorage for LocalDirStorage {
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        let path = self.dir.join(&self.dbfilename);
+        match std::fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed reading the RDB file {:?}", path)),
+        }
+    }
+
+    /// Writes to a temporary file first, renamed over the previous snapshot once complete
+    fn store(&self, data: &[u8]) -> Result<()> {
+        let tmp_path = self.dir.join(format!("temp-{}.rdb", std::process::id()));
+        std::fs::write(&tmp_path, data)
+            .with_context(|| format!("Failed opening the temp RDB file {:?}", tmp_path))?;
+        std::fs::rename(&tmp_path, self.dir.join(&self.dbfilename))?;
+
+        Ok(())
+    }
+}
+
+/// Snapshots kept in memory, for servers that must not touch the disk
+#[derive(Debug, Default)]
+pub struct MemoryStorage(Mutex<Option<Vec<u8>>>);
+impl SnapshotStorage for MemoryStorage {
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+
+    fn store(&self, data: &[u8]) -> Result<()> {
+        *self.0.lock().unwrap() = Some(data.to_vec());
+
+        Ok(())
+    }
+}
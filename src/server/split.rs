@@ -0,0 +1,103 @@
+use std::ops::Range;
+
+/// An argument of a line as `split_args` found it
+#[derive(Debug, PartialEq, Eq)]
+pub enum Arg {
+    /// Written without quotes, the bytes of the line in that range
+    Plain(Range<usize>),
+    /// Written with quotes, its escapes resolved
+    Quoted(Vec<u8>),
+}
+impl Arg {
+    /// Bytes of the argument, `line` being the one it was split from
+    pub fn into_bytes(self, line: &[u8]) -> Vec<u8> {
+        match self {
+            Arg::Plain(range) => line[range].to_vec(),
+            Arg::Quoted(arg) => arg,
+        }
+    }
+}
+
+/// Arguments of a line, split the way Redis' `sdssplitargs` splits inline commands,
+/// redis-cli prompts and config files: double quotes take the escapes `\n \r \t \b \a
+/// \xHH` and a backslash before any other byte, single quotes only `\'`. A quote may
+/// start within an argument, its closing one must end it. `None` when quotes are
+/// unbalanced or a closing one is followed by something else
+pub fn split_args(line: &[u8]) -> Option<Vec<Arg>> {
+    let mut args = vec![];
+    let mut i = 0;
+    loop {
+        while i < line.len() && line[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i == line.len() {
+            break;
+        }
+
+        let start = i;
+        let mut arg = Vec::new();
+        let mut quoted = false;
+        while i < line.len() && !line[i].is_ascii_whitespace() {
+            let quote = line[i];
+            if quote != b'"' && quote != b'\'' {
+                arg.push(quote);
+                i += 1;
+                continue;
+            }
+
+            quoted = true;
+            i += 1;
+            loop {
+                let &byte = line.get(i)?;
+                i += 1;
+                match (quote, byte, line.get(i)) {
+                    (_, byte, _) if byte == quote => break,
+                    (b'"', b'\\', Some(b'x')) => {
+                        let hex = line
+                            .get(i + 1..i + 3)
+                            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                            .and_then(|hex| std::str::from_utf8(hex).ok())
+                            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                        match hex {
+                            Some(byte) => {
+                                arg.push(byte);
+                                i += 3;
+                            }
+                            None => {
+                                arg.push(b'x');
+                                i += 1;
+                            }
+                        }
+                    }
+                    (b'"', b'\\', Some(&escaped)) => {
+                        arg.push(match escaped {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => other,
+                        });
+                        i += 1;
+                    }
+                    (b'\'', b'\\', Some(b'\'')) => {
+                        arg.push(b'\'');
+                        i += 1;
+                    }
+                    (_, byte, _) => arg.push(byte),
+                }
+            }
+            // --- "foo"bar is refused rather than guessed at
+            if line.get(i).is_some_and(|next| !next.is_ascii_whitespace()) {
+                return None;
+            }
+        }
+
+        args.push(match quoted {
+            true => Arg::Quoted(arg),
+            false => Arg::Plain(start..i),
+        });
+    }
+
+    Some(args)
+}
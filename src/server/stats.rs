@@ -0,0 +1,158 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// Samples averaged into `instantaneous_ops_per_sec`, same as Redis
+const OPS_SAMPLES: usize = 16;
+/// ms between two samples of the command rate
+const OPS_SAMPLE_PERIOD: u64 = 100;
+
+/// Server-wide counters reported by INFO stats
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    /// reads that found their key
+    pub keyspace_hits: AtomicU64,
+    /// reads of missing or expired keys
+    pub keyspace_misses: AtomicU64,
+    /// keys removed because their TTL went by
+    pub expired_keys: AtomicU64,
+    /// keys removed to get back under `maxmemory`
+    pub evicted_keys: AtomicU64,
+    /// clients dropped for going over their output buffer limit
+    pub client_output_buffer_limit_disconnections: AtomicU64,
+    /// times a connection went over its fairness budget and gave other clients a turn
+    pub client_fairness_yields: AtomicU64,
+    /// connections turned away by the per-IP limits
+    pub rejected_connections: AtomicU64,
+    /// client connections accepted since startup
+    pub total_connections_received: AtomicU64,
+    /// commands run since startup, including the ones applied from the master
+    pub total_commands_processed: AtomicU64,
+    /// request bytes read from clients
+    pub total_net_input_bytes: AtomicU64,
+    /// reply bytes written to clients
+    pub total_net_output_bytes: AtomicU64,
+    ops_sampler: Mutex<OpsSampler>,
+}
+
+/// Recent command rates, per second, sampled from `total_commands_processed` by the cron
+#[derive(Debug, Default)]
+struct OpsSampler {
+    /// when the last sample was taken and the command count then
+    last: Option<(u64, u64)>,
+    samples: VecDeque<u64>,
+}
+
+impl ServerStats {
+    /// Counts a read lookup as a hit or a miss
+    pub fn record_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.keyspace_hits
+        } else {
+            &self.keyspace_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_expired_key(&self) {
+        self.expired_keys.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_evicted_key(&self) {
+        self.evicted_keys.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_fairness_yield(&self) {
+        self.client_fairness_yields.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_command(&self) {
+        self.total_commands_processed
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the traffic of a client, `input` bytes of requests and `output` of replies
+    pub fn record_traffic(&self, input: usize, output: usize) {
+        self.total_net_input_bytes
+            .fetch_add(input as u64, Ordering::Relaxed);
+        self.total_net_output_bytes
+            .fetch_add(output as u64, Ordering::Relaxed);
+    }
+
+    /// Takes a sample of the command rate, at most once per sample period. Run by the cron
+    pub fn sample_ops(&self, now: u64) {
+        let commands = self.total_commands_processed();
+        let mut sampler = self.ops_sampler.lock().unwrap();
+        if let Some((at, count)) = sampler.last {
+            let elapsed = now.saturating_sub(at);
+            if elapsed < OPS_SAMPLE_PERIOD {
+                return;
+            }
+            let rate = commands.saturating_sub(count) * 1000 / elapsed;
+            if sampler.samples.len() == OPS_SAMPLES {
+                sampler.samples.pop_front();
+            }
+            sampler.samples.push_back(rate);
+        }
+        sampler.last = Some((now, commands));
+    }
+
+    pub fn keyspace_hits(&self) -> u64 {
+        self.keyspace_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn keyspace_misses(&self) -> u64 {
+        self.keyspace_misses.load(Ordering::Relaxed)
+    }
+
+    pub fn expired_keys(&self) -> u64 {
+        self.expired_keys.load(Ordering::Relaxed)
+    }
+
+    pub fn evicted_keys(&self) -> u64 {
+        self.evicted_keys.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn client_output_buffer_limit_disconnections(&self) -> u64 {
+        self.client_output_buffer_limit_disconnections
+            .load(Ordering::Relaxed)
+    }
+
+    pub fn client_fairness_yields(&self) -> u64 {
+        self.client_fairness_yields.load(Ordering::Relaxed)
+    }
+
+    pub fn total_connections_received(&self) -> u64 {
+        self.total_connections_received.load(Ordering::Relaxed)
+    }
+
+    pub fn total_commands_processed(&self) -> u64 {
+        self.total_commands_processed.load(Ordering::Relaxed)
+    }
+
+    pub fn total_net_input_bytes(&self) -> u64 {
+        self.total_net_input_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn total_net_output_bytes(&self) -> u64 {
+        self.total_net_output_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Average of the recent command rate samples
+    pub fn instantaneous_ops_per_sec(&self) -> u64 {
+        let sampler = self.ops_sampler.lock().unwrap();
+        if sampler.samples.is_empty() {
+            return 0;
+        }
+
+        sampler.samples.iter().sum::<u64>() / sampler.samples.len() as u64
+    }
+}
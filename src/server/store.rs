@@ -0,0 +1,70 @@
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{db::Db, handler::RedisValue};
+
+/// What `Store::for_each` calls with each key, its value and expire time
+pub type Visit<'a> = dyn FnMut(&Bytes, &RedisValue, Option<u64>) -> Result<()> + 'a;
+
+/// Where the keys of a database are kept, swappable for an engine holding them elsewhere
+/// than in memory, e.g. an embedded key value store on disk with the hot keys cached in
+/// front of it. Keys are strings, each stored with its value and expire time as one
+/// entry. Calls may block and fail the way the engine's I/O does, the server makes them
+/// from blocking threads
+pub trait Store: Send + Sync {
+    /// Value of a key and its absolute expire time in ms, expired or not
+    fn get(&self, key: &Bytes) -> Result<Option<(RedisValue, Option<u64>)>>;
+
+    /// Sets a key to a value expiring at `expires_at`, never for `None`, replacing both
+    fn insert(&mut self, key: Bytes, value: RedisValue, expires_at: Option<u64>) -> Result<()>;
+
+    /// Removes a key, replying its value and expire time
+    fn remove(&mut self, key: &Bytes) -> Result<Option<(RedisValue, Option<u64>)>>;
+
+    fn len(&self) -> Result<usize>;
+
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Keys with an expire time
+    fn volatile_len(&self) -> Result<usize>;
+
+    /// Hands every key with its value and expire time to `visit`, in no particular order,
+    /// stopping at the first error. Entries are lent rather than given so that an engine
+    /// holding them in memory doesn't copy them
+    fn for_each(&self, visit: &mut Visit<'_>) -> Result<()>;
+}
+
+/// The in memory engine, what the live stores hold
+impl Store for Db {
+    fn get(&self, key: &Bytes) -> Result<Option<(RedisValue, Option<u64>)>> {
+        Ok(Db::get(self, key).map(|(value, expires_at)| (value.clone(), expires_at)))
+    }
+
+    fn insert(&mut self, key: Bytes, value: RedisValue, expires_at: Option<u64>) -> Result<()> {
+        Db::insert(self, key, value, expires_at);
+
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &Bytes) -> Result<Option<(RedisValue, Option<u64>)>> {
+        Ok(Db::remove(self, key))
+    }
+
+    fn len(&self) -> Result<usize> {
+        Ok(Db::len(self))
+    }
+
+    fn volatile_len(&self) -> Result<usize> {
+        Ok(Db::volatile_len(self))
+    }
+
+    fn for_each(&self, visit: &mut Visit<'_>) -> Result<()> {
+        for (key, value, expires_at) in self.iter() {
+            visit(key, value, expires_at)?;
+        }
+
+        Ok(())
+    }
+}
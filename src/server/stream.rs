@@ -0,0 +1,170 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::{Bound, RangeInclusive},
+};
+
+use anyhow::{bail, ensure, Result};
+use bytes::Bytes;
+
+/// ID of a stream entry: the unix time in ms it was added at and a sequence number
+/// telling apart the entries of the same ms
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// "ms-seq", or "ms" alone with the sequence number left to the caller
+    fn parse_parts(arg: &[u8]) -> Option<(u64, Option<&[u8]>)> {
+        let text = std::str::from_utf8(arg).ok()?;
+        let (ms, seq) = match text.split_once('-') {
+            Some((ms, seq)) => (ms, Some(seq.as_bytes())),
+            None => (text, None),
+        };
+
+        Some((ms.parse().ok()?, seq))
+    }
+
+    /// One end of an XRANGE: "-" and "+" for the first and last possible IDs, an "ms"
+    /// alone taking in all of its sequence numbers
+    pub fn parse_bound(arg: &[u8], end: bool) -> Option<Self> {
+        match arg {
+            b"-" => return Some(Self::MIN),
+            b"+" => return Some(Self::MAX),
+            _ => {}
+        }
+        let (ms, seq) = Self::parse_parts(arg)?;
+        let seq = match seq {
+            Some(seq) => std::str::from_utf8(seq).ok()?.parse().ok()?,
+            None if end => u64::MAX,
+            None => 0,
+        };
+
+        Some(Self { ms, seq })
+    }
+}
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// ID XADD is asked to give a new entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NewId {
+    /// "*": the current time, or right after the last entry when the clock is behind it
+    Auto,
+    /// "ms-*": the next sequence number within that ms
+    AutoSeq(u64),
+    /// "ms-seq", or "ms" for "ms-0"
+    Explicit(StreamId),
+}
+impl NewId {
+    pub fn parse(arg: &[u8]) -> Option<Self> {
+        if arg == b"*" {
+            return Some(Self::Auto);
+        }
+        let res = match StreamId::parse_parts(arg)? {
+            (ms, Some(b"*")) => Self::AutoSeq(ms),
+            (ms, Some(seq)) => Self::Explicit(StreamId {
+                ms,
+                seq: std::str::from_utf8(seq).ok()?.parse().ok()?,
+            }),
+            (ms, None) => Self::Explicit(StreamId { ms, seq: 0 }),
+        };
+
+        Some(res)
+    }
+}
+
+/// Entries of XADD in ID order, each a list of field value pairs
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Vec<(Bytes, Bytes)>>,
+    /// ID of the newest entry ever added, new ones have to come after it
+    last_id: StreamId,
+}
+impl Stream {
+    /// Stream read back from a file
+    pub fn from_parts(
+        entries: BTreeMap<StreamId, Vec<(Bytes, Bytes)>>,
+        last_id: StreamId,
+    ) -> Result<Self> {
+        ensure!(
+            entries.keys().next_back().is_none_or(|id| *id <= last_id),
+            "Stream entries past the last ID {}",
+            last_id
+        );
+
+        Ok(Self { entries, last_id })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = (&StreamId, &Vec<(Bytes, Bytes)>)> {
+        self.entries.iter()
+    }
+
+    /// Entries with an ID within the range, oldest first
+    pub fn range(
+        &self,
+        ids: RangeInclusive<StreamId>,
+    ) -> impl Iterator<Item = (&StreamId, &Vec<(Bytes, Bytes)>)> {
+        // --- BTreeMap panics on a range ending before it starts
+        let ids = (ids.start() <= ids.end()).then_some(ids);
+        ids.into_iter().flat_map(|ids| self.entries.range(ids))
+    }
+
+    /// Entries with an ID past the given one, oldest first
+    pub fn after(&self, id: StreamId) -> impl Iterator<Item = (&StreamId, &Vec<(Bytes, Bytes)>)> {
+        self.entries.range((Bound::Excluded(id), Bound::Unbounded))
+    }
+
+    /// Adds an entry, `now` being the current unix time in ms. Refused unless its ID
+    /// comes after the last one
+    pub fn add(&mut self, id: NewId, now: u64, fields: Vec<(Bytes, Bytes)>) -> Result<StreamId> {
+        let last = self.last_id;
+        let id = match id {
+            NewId::Auto if now > last.ms => StreamId { ms: now, seq: 0 },
+            NewId::Auto => match last.seq.checked_add(1) {
+                Some(seq) => StreamId { ms: last.ms, seq },
+                None => match last.ms.checked_add(1) {
+                    Some(ms) => StreamId { ms, seq: 0 },
+                    None => bail!("ERR The stream has exhausted the last possible ID, unable to add more items"),
+                },
+            },
+            // --- 0-* on a new stream gives 0-1, as 0-0 is never a valid ID
+            NewId::AutoSeq(ms) if ms == last.ms => match last.seq.checked_add(1) {
+                Some(seq) => StreamId { ms, seq },
+                None => bail!("ERR The ID specified in XADD is equal or smaller than the target stream top item"),
+            },
+            NewId::AutoSeq(ms) => StreamId { ms, seq: 0 },
+            NewId::Explicit(id) => id,
+        };
+        ensure!(
+            id > last,
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+        );
+        self.entries.insert(id, fields);
+        self.last_id = id;
+
+        Ok(id)
+    }
+}
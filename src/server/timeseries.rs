@@ -0,0 +1,267 @@
+use std::{
+    collections::VecDeque,
+    hash::{Hash, Hasher},
+};
+
+use anyhow::{bail, ensure, Result};
+use bytes::Bytes;
+
+/// A sample of a time series, timestamp in ms
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    pub timestamp: u64,
+    pub value: f64,
+}
+// --- values compare by bits so series can be held by the stores like any other value
+impl PartialEq for Sample {
+    fn eq(&self, other: &Self) -> bool {
+        self.timestamp == other.timestamp && self.value.to_bits() == other.value.to_bits()
+    }
+}
+impl Eq for Sample {}
+impl Hash for Sample {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.timestamp.hash(state);
+        self.value.to_bits().hash(state);
+    }
+}
+
+/// How the samples of a bucket are reduced to one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Aggregation {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    /// max - min
+    Range,
+    Count,
+    First,
+    Last,
+}
+impl Aggregation {
+    pub const ALL: [Aggregation; 8] = [
+        Self::Avg,
+        Self::Sum,
+        Self::Min,
+        Self::Max,
+        Self::Range,
+        Self::Count,
+        Self::First,
+        Self::Last,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|aggregation| aggregation.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Avg => "avg",
+            Self::Sum => "sum",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Range => "range",
+            Self::Count => "count",
+            Self::First => "first",
+            Self::Last => "last",
+        }
+    }
+
+    /// Reduces the values of a bucket, never empty
+    fn apply(&self, values: &[f64]) -> f64 {
+        let min = || values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = || values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        match self {
+            Self::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Self::Sum => values.iter().sum(),
+            Self::Min => min(),
+            Self::Max => max(),
+            Self::Range => max() - min(),
+            Self::Count => values.len() as f64,
+            Self::First => values[0],
+            Self::Last => values[values.len() - 1],
+        }
+    }
+}
+
+/// Downsampling rule: every bucket of the source, once a sample lands past it, is
+/// aggregated into a sample of `dest`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rule {
+    pub dest: Bytes,
+    pub aggregation: Aggregation,
+    /// ms
+    pub bucket_duration: u64,
+    /// start of the bucket being filled, `None` until the first sample
+    pub bucket_start: Option<u64>,
+}
+
+/// Samples in timestamp order. Appending the newest sample is the cheap and common
+/// case, older ones are inserted in place
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TimeSeries {
+    /// how far back from the newest sample samples are kept, in ms, 0 to keep them all
+    pub retention: u64,
+    pub rules: Vec<Rule>,
+    samples: VecDeque<Sample>,
+}
+impl TimeSeries {
+    pub fn new(retention: u64) -> Self {
+        Self {
+            retention,
+            ..Default::default()
+        }
+    }
+
+    /// Series read back from a file, samples in timestamp order
+    pub fn from_parts(retention: u64, rules: Vec<Rule>, samples: Vec<Sample>) -> Result<Self> {
+        ensure!(
+            samples
+                .windows(2)
+                .all(|pair| pair[0].timestamp < pair[1].timestamp),
+            "Time series samples out of order"
+        );
+
+        let res = Self {
+            retention,
+            rules,
+            samples: samples.into(),
+        };
+
+        Ok(res)
+    }
+
+    pub fn samples(&self) -> impl ExactSizeIterator<Item = &Sample> {
+        self.samples.iter()
+    }
+
+    pub fn last(&self) -> Option<Sample> {
+        self.samples.back().copied()
+    }
+
+    /// Adds a sample. Refused when one with the same timestamp is there, or when it is
+    /// already out of the retention window. Returns the samples of the buckets of the
+    /// rules it closed, with the key of the series they go to
+    pub fn add(&mut self, sample: Sample) -> Result<Vec<(Bytes, Sample)>> {
+        match self.samples.back() {
+            None => self.samples.push_back(sample),
+            Some(last) if last.timestamp < sample.timestamp => self.samples.push_back(sample),
+            Some(last) => {
+                ensure!(
+                    self.retention == 0
+                        || sample.timestamp >= last.timestamp.saturating_sub(self.retention),
+                    "ERR TSDB: Timestamp is older than retention"
+                );
+                let pos = self
+                    .samples
+                    .partition_point(|other| other.timestamp < sample.timestamp);
+                if self.samples[pos].timestamp == sample.timestamp {
+                    bail!("ERR TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode");
+                }
+                self.samples.insert(pos, sample);
+            }
+        }
+        // --- closed buckets are aggregated before their samples may fall out of retention
+        let res = self.close_buckets(sample.timestamp);
+        self.trim();
+
+        Ok(res)
+    }
+
+    /// Changes the value of the newest sample
+    pub fn update_last(&mut self, value: f64) {
+        if let Some(last) = self.samples.back_mut() {
+            last.value = value;
+        }
+    }
+
+    /// Samples from `from` to `to`, both included
+    pub fn range(&self, from: u64, to: u64) -> impl Iterator<Item = &Sample> {
+        let start = self
+            .samples
+            .partition_point(|sample| sample.timestamp < from);
+        let end = self
+            .samples
+            .partition_point(|sample| sample.timestamp <= to);
+
+        self.samples.range(start..end.max(start))
+    }
+
+    /// Samples from `from` to `to` reduced per bucket of `bucket_duration` ms, each
+    /// stamped with the start of its bucket. Empty buckets are left out
+    pub fn aggregate(
+        &self,
+        from: u64,
+        to: u64,
+        aggregation: Aggregation,
+        bucket_duration: u64,
+    ) -> Vec<Sample> {
+        let mut res = vec![];
+        let mut values = vec![];
+        let mut bucket = None;
+        for sample in self.range(from, to) {
+            let sample_bucket = sample.timestamp - sample.timestamp % bucket_duration;
+            if bucket.is_some_and(|bucket| bucket != sample_bucket) {
+                res.push(Sample {
+                    timestamp: bucket.unwrap(),
+                    value: aggregation.apply(&values),
+                });
+                values.clear();
+            }
+            bucket = Some(sample_bucket);
+            values.push(sample.value);
+        }
+        if let Some(bucket) = bucket {
+            res.push(Sample {
+                timestamp: bucket,
+                value: aggregation.apply(&values),
+            });
+        }
+
+        res
+    }
+
+    /// Moves the rules on to the bucket of a sample just added
+    fn close_buckets(&mut self, timestamp: u64) -> Vec<(Bytes, Sample)> {
+        let mut res = vec![];
+        for i in 0..self.rules.len() {
+            let rule = &self.rules[i];
+            let bucket = timestamp - timestamp % rule.bucket_duration;
+            match rule.bucket_start {
+                Some(start) if start < bucket => {
+                    let end = start + rule.bucket_duration - 1;
+                    let closed = self.aggregate(start, end, rule.aggregation, rule.bucket_duration);
+                    let dest = rule.dest.clone();
+                    res.extend(closed.into_iter().map(|sample| (dest.clone(), sample)));
+                }
+                // --- late samples don't reopen buckets
+                Some(_) => continue,
+                None => {}
+            }
+            self.rules[i].bucket_start = Some(bucket);
+        }
+
+        res
+    }
+
+    /// Drops the samples out of the retention window
+    fn trim(&mut self) {
+        let Some(newest) = self.samples.back().map(|sample| sample.timestamp) else {
+            return;
+        };
+        if self.retention == 0 {
+            return;
+        }
+        let oldest = newest.saturating_sub(self.retention);
+        while self
+            .samples
+            .front()
+            .is_some_and(|sample| sample.timestamp < oldest)
+        {
+            self.samples.pop_front();
+        }
+    }
+}
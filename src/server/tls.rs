@@ -0,0 +1,287 @@
+use std::{fmt, fs::File, io::BufReader, str::FromStr, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring, pki_types::CertificateDer, server::WebPkiClientVerifier, ClientConfig,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor, TlsConnector,
+};
+use webpki::EndEntityCert;
+
+use super::acl::AclUsers;
+
+/// Whether clients of the TLS port have to present a certificate, `tls-auth-clients`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TlsAuthClients {
+    #[default]
+    No,
+    /// checked when presented
+    Optional,
+    Yes,
+}
+impl TlsAuthClients {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::No => "no",
+            Self::Optional => "optional",
+            Self::Yes => "yes",
+        }
+    }
+}
+impl FromStr for TlsAuthClients {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let res = match s.to_lowercase().as_str() {
+            "no" => Self::No,
+            "optional" => Self::Optional,
+            "yes" => Self::Yes,
+            _ => bail!("tls-auth-clients must be one of no, optional or yes"),
+        };
+
+        Ok(res)
+    }
+}
+
+/// Which name of a client certificate is the ACL user the connection runs as,
+/// `tls-auth-clients-user`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CertificateUser {
+    /// clients AUTH as usual
+    #[default]
+    Off,
+    /// the subject's common name
+    CommonName,
+    /// the first DNS or URI subject alternative name that is a user
+    AltName,
+}
+impl CertificateUser {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::CommonName => "CN",
+            Self::AltName => "SAN",
+        }
+    }
+}
+impl FromStr for CertificateUser {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let res = match s.to_uppercase().as_str() {
+            "OFF" => Self::Off,
+            "CN" => Self::CommonName,
+            "SAN" => Self::AltName,
+            _ => bail!("tls-auth-clients-user must be one of off, CN or SAN"),
+        };
+
+        Ok(res)
+    }
+}
+
+/// Names a verified client certificate carries
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerIdentity {
+    pub common_name: Option<String>,
+    /// DNS and URI subject alternative names
+    pub alt_names: Vec<String>,
+}
+impl PeerIdentity {
+    /// Identity of the end-entity certificate of a chain the handshake verified
+    pub fn from_certs(certs: &[CertificateDer<'_>]) -> Option<Self> {
+        let cert = EndEntityCert::try_from(certs.first()?).ok()?;
+        let alt_names = cert
+            .valid_dns_names()
+            .chain(cert.valid_uri_names())
+            .map(str::to_string)
+            .collect();
+
+        let res = Self {
+            common_name: common_name(cert.subject()),
+            alt_names,
+        };
+
+        Some(res)
+    }
+
+    /// The ACL user the connection runs as without AUTH, an existing and enabled one
+    pub fn user(&self, field: CertificateUser, users: &AclUsers) -> Option<String> {
+        let mut candidates = match field {
+            CertificateUser::Off => return None,
+            CertificateUser::CommonName => self.common_name.iter().collect::<Vec<_>>(),
+            CertificateUser::AltName => self.alt_names.iter().collect(),
+        }
+        .into_iter();
+
+        candidates.find(|name| users.is_enabled(name)).cloned()
+    }
+}
+impl fmt::Display for PeerIdentity {
+    /// The common name, or the first alternative name for certificates without one
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.common_name, self.alt_names.first()) {
+            (Some(name), _) | (None, Some(name)) => f.write_str(name),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Common name in a DER subject, without its outer SEQUENCE: attributes as SETs of
+/// SEQUENCEs of an OID and a string
+fn common_name(mut subject: &[u8]) -> Option<String> {
+    // --- 2.5.4.3
+    const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+    while let Some((_, set, rest)) = der_value(subject) {
+        subject = rest;
+        let Some((_, attribute, _)) = der_value(set) else {
+            continue;
+        };
+        let Some((0x06, oid, value)) = der_value(attribute) else {
+            continue;
+        };
+        if oid == COMMON_NAME {
+            let (_, name, _) = der_value(value)?;
+            return Some(String::from_utf8_lossy(name).into_owned());
+        }
+    }
+
+    None
+}
+
+/// Tag, content and what follows of the DER value at the start of `data`
+fn der_value(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&first, data) = data.split_first()?;
+    let (len, data) = match first {
+        0..=0x7f => (first as usize, data),
+        0x81..=0x84 => {
+            let bytes = (first & 0x7f) as usize;
+            let len = data
+                .get(..bytes)?
+                .iter()
+                .fold(0, |len, &byte| len << 8 | byte as usize);
+            (len, &data[bytes..])
+        }
+        _ => return None,
+    };
+    if data.len() < len {
+        return None;
+    }
+
+    Some((tag, &data[..len], &data[len..]))
+}
+
+/// TLS settings, `--tls-*`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// port taking TLS client connections, next to the plain one, `tls-port`
+    pub port: Option<u16>,
+    /// certificate chain the server presents, PEM, `tls-cert-file`
+    pub cert_file: Option<String>,
+    /// private key of the certificate, PEM, `tls-key-file`
+    pub key_file: Option<String>,
+    /// certificates the master's and those of clients are checked against, PEM,
+    /// `tls-ca-cert-file`
+    pub ca_cert_file: Option<String>,
+    /// whether clients have to present a certificate, `tls-auth-clients`
+    pub auth_clients: TlsAuthClients,
+    /// which name of their certificate clients run as, `tls-auth-clients-user`
+    pub auth_clients_user: CertificateUser,
+    /// whether a replica reaches its master over TLS, `tls-replication`
+    pub replication: bool,
+}
+
+/// Server and client sides of TLS, as configured
+#[derive(Clone, Default)]
+pub struct Tls {
+    /// for connections to `tls-port`
+    pub acceptor: Option<TlsAcceptor>,
+    /// for the link to the master
+    pub connector: Option<TlsConnector>,
+}
+impl Tls {
+    /// Reads the certificates and key the configuration needs
+    pub fn load(config: &TlsConfig) -> Result<Self> {
+        let acceptor = match config.port {
+            Some(_) => {
+                let (Some(cert_file), Some(key_file)) = (&config.cert_file, &config.key_file)
+                else {
+                    bail!("tls-port needs both tls-cert-file and tls-key-file");
+                };
+                let key = rustls_pemfile::private_key(&mut open(key_file)?)?
+                    .with_context(|| format!("No private key in {}", key_file))?;
+                let builder =
+                    ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                        .with_safe_default_protocol_versions()?;
+                let builder = match config.auth_clients {
+                    TlsAuthClients::No => builder.with_no_client_auth(),
+                    auth_clients => {
+                        let Some(ca_cert_file) = &config.ca_cert_file else {
+                            bail!("tls-auth-clients needs tls-ca-cert-file to check clients");
+                        };
+                        let verifier = WebPkiClientVerifier::builder_with_provider(
+                            Arc::new(roots(ca_cert_file)?),
+                            Arc::new(ring::default_provider()),
+                        );
+                        let verifier = match auth_clients {
+                            TlsAuthClients::Optional => verifier.allow_unauthenticated(),
+                            _ => verifier,
+                        };
+                        builder.with_client_cert_verifier(verifier.build()?)
+                    }
+                };
+                let server_config = builder.with_single_cert(read_certs(cert_file)?, key)?;
+                Some(TlsAcceptor::from(Arc::new(server_config)))
+            }
+            None => None,
+        };
+        let connector = match config.replication {
+            true => {
+                let Some(ca_cert_file) = &config.ca_cert_file else {
+                    bail!("tls-replication needs tls-ca-cert-file to check the master");
+                };
+                let client_config =
+                    ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                        .with_safe_default_protocol_versions()?
+                        .with_root_certificates(roots(ca_cert_file)?)
+                        .with_no_client_auth();
+                Some(TlsConnector::from(Arc::new(client_config)))
+            }
+            false => None,
+        };
+
+        let res = Self {
+            acceptor,
+            connector,
+        };
+
+        Ok(res)
+    }
+}
+
+fn open(path: &str) -> Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("Failure opening {}", path))?;
+
+    Ok(BufReader::new(file))
+}
+
+/// Certificates of a PEM file, as trust anchors
+fn roots(path: &str) -> Result<RootCertStore> {
+    let mut res = RootCertStore::empty();
+    for cert in read_certs(path)? {
+        res.add(cert)?;
+    }
+
+    Ok(res)
+}
+
+/// Every certificate of a PEM file, none being an error
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let res = rustls_pemfile::certs(&mut open(path)?).collect::<Result<Vec<_>, _>>()?;
+    anyhow::ensure!(!res.is_empty(), "No certificate in {}", path);
+
+    Ok(res)
+}
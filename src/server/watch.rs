@@ -0,0 +1,63 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use super::handler::RedisValue;
+
+/// A key some client WATCHes: how many times it changed so far, and who watches it
+#[derive(Debug, Default)]
+struct WatchedKey {
+    version: u64,
+    clients: HashSet<u64>,
+}
+
+/// Keys WATCHed by some connection, each with a version bumped whenever the key changes,
+/// for EXEC to compare with the one seen at WATCH time. Keys are by database and name,
+/// keys nobody watches aren't tracked
+#[derive(Debug, Default)]
+pub struct WatchedKeys(Mutex<HashMap<(usize, RedisValue), WatchedKey>>);
+impl WatchedKeys {
+    /// Starts watching a key of a database for a client, returning its current version
+    pub fn watch(&self, client_id: u64, db: usize, key: &RedisValue) -> u64 {
+        let mut keys = self.0.lock().unwrap();
+        let watched = keys.entry((db, key.clone())).or_default();
+        watched.clients.insert(client_id);
+
+        watched.version
+    }
+
+    /// Records a change to a key, nothing to do unless someone watches it
+    pub fn touch(&self, db: usize, key: &RedisValue) {
+        if let Some(watched) = self.0.lock().unwrap().get_mut(&(db, key.clone())) {
+            watched.version += 1;
+        }
+    }
+
+    /// Records a change to every watched key of a database, e.g. when it is flushed or
+    /// swapped with another
+    pub fn touch_db(&self, db: usize) {
+        for ((watched_db, _), watched) in self.0.lock().unwrap().iter_mut() {
+            if *watched_db == db {
+                watched.version += 1;
+            }
+        }
+    }
+
+    /// Whether any of the keys changed since it was watched, at the version given
+    pub fn changed(&self, seen: &[(usize, RedisValue, u64)]) -> bool {
+        let keys = self.0.lock().unwrap();
+        seen.iter().any(|(db, key, version)| {
+            keys.get(&(*db, key.clone()))
+                .is_none_or(|watched| watched.version != *version)
+        })
+    }
+
+    /// Stops watching every key for a client
+    pub fn unwatch_all(&self, client_id: u64) {
+        self.0.lock().unwrap().retain(|_, watched| {
+            watched.clients.remove(&client_id);
+            !watched.clients.is_empty()
+        });
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    hash::{Hash, Hasher},
+    ops::Bound,
+};
+
+use bytes::Bytes;
+
+/// Score of a sorted set member, never NaN
+#[derive(Clone, Copy, Debug)]
+pub struct Score(f64);
+// --- total order so sorted sets can be held by the stores like any other value
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+impl Eq for Score {}
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+impl Hash for Score {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+impl Score {
+    /// -0 is turned into 0, both being the same score
+    fn new(score: f64) -> Self {
+        Self(score + 0.0)
+    }
+}
+
+/// Score as replies show it, the shortest text that reads back as the same number
+pub fn format_score(score: f64) -> Bytes {
+    Bytes::from(score.to_string())
+}
+
+/// Score ZADD takes, infinities included
+pub fn parse_score(arg: &[u8]) -> Option<f64> {
+    std::str::from_utf8(arg)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|score: &f64| !score.is_nan())
+}
+
+/// One end of a score range, as ZRANGEBYSCORE takes them: "1.5", "(1.5" to leave the
+/// score out, "-inf" and "+inf"
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+impl ScoreBound {
+    pub fn parse(arg: &[u8]) -> Option<Self> {
+        let (exclusive, score) = match arg.strip_prefix(b"(") {
+            Some(score) => (true, score),
+            None => (false, arg),
+        };
+        let score = parse_score(score)?;
+
+        Some(Self { score, exclusive })
+    }
+
+    fn below(&self, score: f64) -> bool {
+        self.score < score || (!self.exclusive && self.score == score)
+    }
+
+    fn above(&self, score: f64) -> bool {
+        self.score > score || (!self.exclusive && self.score == score)
+    }
+}
+
+/// Members of ZADD, reachable by name and in score order. Members with the same score
+/// are ordered by name
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SortedSet {
+    scores: BTreeMap<Bytes, Score>,
+    ordered: BTreeSet<(Score, Bytes)>,
+}
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).map(|score| score.0)
+    }
+
+    /// Adds a member or moves it to a new score, returning the one it had
+    pub fn insert(&mut self, member: Bytes, score: f64) -> Option<f64> {
+        let score = Score::new(score);
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(previous, member.clone()));
+        }
+        self.ordered.insert((score, member));
+
+        previous.map(|score| score.0)
+    }
+
+    /// Removes a member, returning its score
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let (member, score) = self.scores.remove_entry(member)?;
+        self.ordered.remove(&(score, member));
+
+        Some(score.0)
+    }
+
+    /// Position of a member in score order, from 0
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let (member, score) = self.scores.get_key_value(member)?;
+        let res = self.ordered.range(..(*score, member.clone())).count();
+
+        Some(res)
+    }
+
+    /// Members and their scores, lowest score first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        self.ordered.iter().map(|(score, member)| (member, score.0))
+    }
+
+    /// Members with a score between `min` and `max`, lowest score first
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> impl Iterator<Item = (&Bytes, f64)> {
+        let start = (Score::new(min.score), Bytes::new());
+        self.ordered
+            .range((Bound::Included(start), Bound::Unbounded))
+            .map(|(score, member)| (member, score.0))
+            .skip_while(move |(_, score)| !min.below(*score))
+            .take_while(move |(_, score)| max.above(*score))
+    }
+}
@@ -0,0 +1,81 @@
+use bytes::Bytes;
+use redis_rust::{
+    alloc::{self, DefaultAllocator, TrackingAllocator},
+    Redis, RedisValue,
+};
+
+#[global_allocator]
+static GLOBAL: TrackingAllocator<DefaultAllocator> = TrackingAllocator::default_allocator();
+
+fn info_field(info: &RedisValue, field: &str) -> usize {
+    let RedisValue::BulkString(info) = info else {
+        panic!("INFO replies with a bulk string");
+    };
+    let prefix = format!("{}:", field);
+    std::str::from_utf8(info)
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn used_memory_follows_real_allocations() {
+    let mut redis = Redis::open_in_memory();
+    let before = alloc::allocated().unwrap();
+
+    let value = Bytes::from(vec![b'x'; 8 * 1024 * 1024]);
+    redis
+        .execute([
+            Bytes::from_static(b"SET"),
+            Bytes::from_static(b"big"),
+            value,
+        ])
+        .await
+        .unwrap();
+    let info = redis.execute(["INFO", "memory"]).await.unwrap();
+    let used = info_field(&info, "used_memory");
+    assert!(used >= before + 8 * 1024 * 1024);
+    assert!(info_field(&info, "used_memory_peak") >= used);
+
+    redis.execute(["SET", "big", "small"]).await.unwrap();
+    assert!(alloc::allocated().unwrap() < used - 4 * 1024 * 1024);
+    assert!(alloc::peak().unwrap() >= used);
+}
+
+#[tokio::test]
+async fn memory_stats_and_purge() {
+    let mut redis = Redis::open_in_memory();
+    redis.execute(["SET", "foo", "bar"]).await.unwrap();
+
+    let RedisValue::Array(stats) = redis.execute(["MEMORY", "STATS"]).await.unwrap() else {
+        panic!("MEMORY STATS replies with an array");
+    };
+    let names = stats
+        .iter()
+        .step_by(2)
+        .map(|name| match name {
+            RedisValue::BulkString(name) => std::str::from_utf8(name).unwrap(),
+            _ => panic!("Stat names are bulk strings"),
+        })
+        .collect::<Vec<_>>();
+    for name in [
+        "peak.allocated",
+        "total.allocated",
+        "dataset.bytes",
+        "rss",
+        "fragmentation",
+    ] {
+        assert!(names.contains(&name), "missing {}", name);
+    }
+
+    let info = redis.execute(["INFO", "memory"]).await.unwrap();
+    assert!(info_field(&info, "used_memory_rss") > 0);
+
+    assert_eq!(
+        redis.execute(["MEMORY", "PURGE"]).await.unwrap(),
+        RedisValue::SimpleString(Bytes::from_static(b"OK"))
+    );
+}
@@ -0,0 +1,232 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{bulk, TestServer};
+use redis_rust::{server::aof, Args, RedisValue};
+
+const SET: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\nb\r\n";
+const MULTI: &[u8] = b"*1\r\n$5\r\nMULTI\r\n";
+const EXEC: &[u8] = b"*1\r\n$4\r\nEXEC\r\n";
+
+#[test]
+fn check_accepts_complete_commands_and_transactions() {
+    let report = aof::check(&[SET, MULTI, SET, EXEC].concat());
+
+    assert!(report.corruption.is_none());
+    assert_eq!(report.commands, 4);
+}
+
+#[test]
+fn check_stops_at_the_first_incomplete_command() {
+    let data = [SET, &SET[..10]].concat();
+    let report = aof::check(&data);
+
+    assert!(report.truncated);
+    assert_eq!(report.corruption.map(|(offset, _)| offset), Some(SET.len()));
+    assert_eq!(report.valid_len, SET.len());
+
+    // --- an open transaction is dropped as a whole
+    let report = aof::check(&[SET, MULTI, SET].concat());
+    assert_eq!(report.corruption.map(|(offset, _)| offset), Some(SET.len()));
+    assert_eq!(report.valid_len, SET.len());
+}
+
+#[test]
+fn check_skips_the_rdb_preamble() {
+    let data = [&include_bytes!("../examples/dump.rdb")[..], SET].concat();
+    let report = aof::check(&data);
+
+    assert!(report.rdb_preamble);
+    assert!(report.corruption.is_none());
+    assert_eq!(report.commands, 1);
+}
+
+#[test]
+fn check_tells_garbage_from_a_truncated_end() {
+    let report = aof::check(&[SET, b"garbage\r\n", SET].concat());
+
+    assert!(!report.truncated);
+    assert_eq!(report.corruption.map(|(offset, _)| offset), Some(SET.len()));
+}
+
+/// Empty directory under the system temp dir, unique to the test
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("redis-rust-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn start_args(dir: &Path, appendonly: bool) -> Args {
+    Args {
+        port: Some(0),
+        dir: Some(dir.to_str().unwrap().to_string()),
+        save: Some(String::new()),
+        appendonly: Some(appendonly),
+        appendfsync: Some("always".to_string()),
+        ..Default::default()
+    }
+}
+
+async fn start_in(dir: &Path, appendonly: bool) -> TestServer {
+    TestServer::start(start_args(dir, appendonly)).await
+}
+
+#[tokio::test]
+async fn writes_are_replayed_after_a_restart() {
+    let dir = temp_dir("aof-replay");
+
+    let server = start_in(&dir, true).await;
+    let mut client = server.client().await;
+    client.set("foo", "bar").await.unwrap();
+    client.set("gone", "soon").await.unwrap();
+    client.command(["DEL", "gone"]).await.unwrap();
+    client.command(["RPUSH", "list", "a", "b"]).await.unwrap();
+    client.command(["INCR", "counter"]).await.unwrap();
+    client
+        .command(["SET", "session", "token", "EX", "600"])
+        .await
+        .unwrap();
+    let RedisValue::BulkString(id) = client.command(["XADD", "s", "*", "f", "v"]).await.unwrap()
+    else {
+        panic!("XADD should reply the new entry ID");
+    };
+    client.command(["PUBLISH", "news", "hello"]).await.unwrap();
+    let mut other = server.client().await;
+    other.command(["SELECT", "4"]).await.unwrap();
+    other.set("foo", "other").await.unwrap();
+    client.set("last", "1").await.unwrap();
+    let RedisValue::BulkString(info) = client.command(["INFO", "persistence"]).await.unwrap()
+    else {
+        panic!("INFO should reply a bulk string");
+    };
+    assert!(String::from_utf8_lossy(&info).contains("aof_enabled:1"));
+    drop(server);
+
+    // --- relative TTLs and generated IDs are logged as they came out
+    let log = std::fs::read(dir.join("appendonly.aof")).unwrap();
+    assert!(log.starts_with(b"REDIS"));
+    assert!(log.windows(4).any(|w| w == b"PXAT"));
+    assert!(log.windows(id.len()).any(|w| w == id));
+    // --- messages only go to replicas
+    assert!(!log.windows(7).any(|w| w == b"PUBLISH"));
+
+    let server = start_in(&dir, true).await;
+    let mut client = server.client().await;
+    assert_eq!(client.command(["GET", "foo"]).await.unwrap(), bulk("bar"));
+    assert_eq!(
+        client.command(["EXISTS", "gone"]).await.unwrap(),
+        RedisValue::Integer(0)
+    );
+    assert_eq!(
+        client.command(["LRANGE", "list", "0", "-1"]).await.unwrap(),
+        RedisValue::Array(vec![bulk("a"), bulk("b")])
+    );
+    assert_eq!(client.command(["GET", "counter"]).await.unwrap(), bulk("1"));
+    let deadline = server.server.databases[0]
+        .expire_store
+        .lock()
+        .await
+        .get(&bulk("session"))
+        .copied();
+    let now = server.server.clock.now();
+    assert!(deadline.is_some_and(|deadline| deadline > now + 590_000 && deadline <= now + 600_000));
+    assert_eq!(
+        client.command(["XRANGE", "s", "-", "+"]).await.unwrap(),
+        RedisValue::Array(vec![RedisValue::Array(vec![
+            RedisValue::BulkString(id),
+            RedisValue::Array(vec![bulk("f"), bulk("v")]),
+        ])])
+    );
+    assert_eq!(client.command(["GET", "last"]).await.unwrap(), bulk("1"));
+    // --- the log switches databases along with the writes
+    client.command(["SELECT", "4"]).await.unwrap();
+    assert_eq!(client.command(["GET", "foo"]).await.unwrap(), bulk("other"));
+    assert_eq!(
+        client.command(["EXISTS", "last"]).await.unwrap(),
+        RedisValue::Integer(0)
+    );
+    // --- replaying doesn't log the commands a second time
+    assert_eq!(std::fs::read(dir.join("appendonly.aof")).unwrap(), log);
+}
+
+#[tokio::test]
+async fn extension_writes_are_replayed_without_extensions() {
+    let dir = temp_dir("aof-extensions");
+
+    let server = TestServer::start(Args {
+        enable_extensions: true,
+        ..start_args(&dir, true)
+    })
+    .await;
+    let mut client = server.client().await;
+    client.set("lock", "a").await.unwrap();
+    client
+        .command(["SET", "lock", "b", "IFEQ", "a"])
+        .await
+        .unwrap();
+    client.set("old", "1").await.unwrap();
+    client.set("gone", "1").await.unwrap();
+    client.command(["DELIFEQ", "gone", "1"]).await.unwrap();
+    drop(server);
+
+    let mut log = std::fs::read(dir.join("appendonly.aof")).unwrap();
+    assert!(!log.windows(4).any(|w| w == b"IFEQ"));
+    // --- logs written before extension writes went out as plain ones still replay
+    log.extend_from_slice(b"*3\r\n$7\r\nDELIFEQ\r\n$3\r\nold\r\n$1\r\n1\r\n");
+    std::fs::write(dir.join("appendonly.aof"), log).unwrap();
+
+    let server = start_in(&dir, true).await;
+    let mut client = server.client().await;
+    assert_eq!(client.command(["GET", "lock"]).await.unwrap(), bulk("b"));
+    assert_eq!(
+        client.command(["EXISTS", "gone", "old"]).await.unwrap(),
+        RedisValue::Integer(0)
+    );
+}
+
+#[tokio::test]
+async fn turning_appendonly_on_starts_from_the_rdb_file() {
+    let dir = temp_dir("aof-from-rdb");
+
+    let server = start_in(&dir, false).await;
+    let mut client = server.client().await;
+    client.set("saved", "1").await.unwrap();
+    client.command(["SAVE"]).await.unwrap();
+    drop(server);
+
+    let server = start_in(&dir, true).await;
+    let mut client = server.client().await;
+    client.set("logged", "2").await.unwrap();
+    drop(server);
+
+    let server = start_in(&dir, true).await;
+    let mut client = server.client().await;
+    assert_eq!(client.command(["GET", "saved"]).await.unwrap(), bulk("1"));
+    assert_eq!(client.command(["GET", "logged"]).await.unwrap(), bulk("2"));
+}
+
+#[tokio::test]
+async fn a_torn_last_write_is_dropped_but_corruption_stops_the_start() {
+    let dir = temp_dir("aof-torn");
+    let path = dir.join("appendonly.aof");
+
+    std::fs::write(&path, [SET, &SET[..10]].concat()).unwrap();
+    let server = start_in(&dir, true).await;
+    let mut client = server.client().await;
+    assert_eq!(client.command(["GET", "a"]).await.unwrap(), bulk("b"));
+    assert_eq!(std::fs::read(&path).unwrap(), SET);
+    drop(server);
+
+    std::fs::write(&path, [SET, b"garbage\r\n", SET].concat()).unwrap();
+    let args = Args {
+        port: Some(0),
+        dir: Some(dir.to_str().unwrap().to_string()),
+        appendonly: Some(true),
+        ..Default::default()
+    };
+    assert!(redis_rust::server::server::RedisServer::init(args)
+        .await
+        .is_err());
+}
@@ -0,0 +1,318 @@
+mod common;
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use common::{bulk, TestServer};
+use redis_rust::{
+    server::{
+        blocking::{BlockedClients, Wakeup},
+        commands::End,
+    },
+    Redis, RedisValue,
+};
+
+fn key(s: &'static str) -> RedisValue {
+    RedisValue::BulkString(Bytes::from_static(s.as_bytes()))
+}
+
+#[tokio::test]
+async fn waiters_on_a_key_are_woken_in_order() {
+    let clients = BlockedClients::default();
+    let first = clients.block(1, 0, vec![key("a")]);
+    let second = clients.block(2, 0, vec![key("b"), key("a")]);
+    assert_eq!(clients.len(), 2);
+
+    assert!(clients.signal_key_ready(0, &key("a")));
+    assert_eq!(first.wait(None).await, Wakeup::KeyReady(key("a")));
+    // --- a woken client leaves every queue it was in
+    assert!(clients.signal_key_ready(0, &key("b")));
+    assert_eq!(second.wait(None).await, Wakeup::KeyReady(key("b")));
+    assert!(!clients.signal_key_ready(0, &key("a")));
+    assert!(clients.is_empty());
+
+    // --- the same key in another database is another key
+    let other_db = clients.block(3, 1, vec![key("a")]);
+    assert!(!clients.signal_key_ready(0, &key("a")));
+    assert!(clients.signal_key_ready(1, &key("a")));
+    assert_eq!(other_db.wait(None).await, Wakeup::KeyReady(key("a")));
+}
+
+#[tokio::test]
+async fn timeouts_unblocks_and_drops_release_the_client() {
+    let clients = BlockedClients::default();
+
+    let timed = clients.block(1, 0, vec![key("a")]);
+    assert_eq!(
+        timed.wait(Some(Duration::from_millis(10))).await,
+        Wakeup::Timeout
+    );
+    assert!(clients.is_empty());
+
+    let unblocked = clients.block(2, 0, vec![]);
+    assert!(clients.unblock(unblocked.id(), true));
+    assert!(!clients.unblock(unblocked.id(), true));
+    assert_eq!(
+        unblocked.wait(None).await,
+        Wakeup::Unblocked { error: true }
+    );
+
+    drop(clients.block(3, 0, vec![key("a")]));
+    assert!(clients.is_empty());
+    assert!(!clients.signal_key_ready(0, &key("a")));
+}
+
+#[tokio::test]
+async fn signaling_all_wakes_every_waiter_on_the_key() {
+    let clients = BlockedClients::default();
+    let first = clients.block(1, 0, vec![key("a")]);
+    let second = clients.block(2, 0, vec![key("a"), key("b")]);
+    let other = clients.block(3, 0, vec![key("b")]);
+
+    assert_eq!(clients.signal_key_ready_all(0, &key("a")), 2);
+    assert_eq!(first.wait(None).await, Wakeup::KeyReady(key("a")));
+    assert_eq!(second.wait(None).await, Wakeup::KeyReady(key("a")));
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients.signal_key_ready_all(0, &key("a")), 0);
+    drop(other);
+}
+
+#[tokio::test]
+async fn writes_wake_clients_blocked_on_the_key() {
+    let mut redis = Redis::open_in_memory();
+    let blocked = redis.server().blocked_clients.block(1, 0, vec![key("foo")]);
+
+    redis.execute(["SET", "foo", "bar"]).await.unwrap();
+    assert_eq!(
+        blocked.wait(Some(Duration::from_secs(1))).await,
+        Wakeup::KeyReady(key("foo"))
+    );
+}
+
+#[tokio::test]
+async fn client_unblock_releases_a_blocked_client() {
+    let server = TestServer::master().await;
+    let blocked = server.server.blocked_clients.block(42, 0, vec![key("foo")]);
+    let mut client = server.client().await;
+
+    assert_eq!(
+        client
+            .command(["CLIENT", "UNBLOCK", "42", "ERROR"])
+            .await
+            .unwrap(),
+        RedisValue::Integer(1)
+    );
+    assert_eq!(blocked.wait(None).await, Wakeup::Unblocked { error: true });
+    assert_eq!(
+        client.command(["CLIENT", "UNBLOCK", "42"]).await.unwrap(),
+        RedisValue::Integer(0)
+    );
+
+    for args in [["UNBLOCK", "nope", "ERROR"], ["UNBLOCK", "42", "LATER"]] {
+        assert!(matches!(
+            client
+                .command(["CLIENT", args[0], args[1], args[2]])
+                .await
+                .unwrap(),
+            RedisValue::SimpleError(_)
+        ));
+    }
+}
+
+#[tokio::test]
+async fn a_new_block_survives_the_previous_one_being_dropped() {
+    let clients = BlockedClients::default();
+    let stale = clients.block(1, 0, vec![key("a")]);
+    let _current = clients.block(1, 0, vec![key("b")]);
+
+    drop(stale);
+    assert_eq!(clients.len(), 1);
+    assert!(clients.signal_key_ready(0, &key("b")));
+}
+
+#[tokio::test]
+async fn xread_block_waits_for_new_entries() {
+    let server = TestServer::master().await;
+    let mut writer = server.client().await;
+    let mut first = server.client().await;
+    let mut second = server.client().await;
+
+    writer
+        .command(["XADD", "s", "1-1", "old", "x"])
+        .await
+        .unwrap();
+    let block = ["XREAD", "BLOCK", "0", "STREAMS", "s", "$"];
+    let writes = async {
+        while server.server.blocked_clients.len() < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        writer
+            .command(["XADD", "s", "2-1", "new", "y"])
+            .await
+            .unwrap()
+    };
+    let (first_reply, second_reply, _) =
+        tokio::join!(first.command(block), second.command(block), writes);
+
+    // --- every reader gets the entry, not just the one blocked first
+    let entry = RedisValue::Array(vec![RedisValue::Array(vec![
+        bulk("s"),
+        RedisValue::Array(vec![RedisValue::Array(vec![
+            bulk("2-1"),
+            RedisValue::Array(vec![bulk("new"), bulk("y")]),
+        ])]),
+    ])]);
+    assert_eq!(first_reply.unwrap(), entry);
+    assert_eq!(second_reply.unwrap(), entry);
+    assert!(server.server.blocked_clients.is_empty());
+
+    let reader = &mut first;
+    assert_eq!(
+        reader
+            .command(["XREAD", "BLOCK", "20", "STREAMS", "s", "$"])
+            .await
+            .unwrap(),
+        RedisValue::NullArray
+    );
+    assert_eq!(
+        reader
+            .command(["XREAD", "STREAMS", "s", "missing", "2-1", "0"])
+            .await
+            .unwrap(),
+        RedisValue::NullArray
+    );
+    assert_eq!(
+        reader
+            .command(["XREAD", "COUNT", "1", "STREAMS", "s", "0"])
+            .await
+            .unwrap(),
+        RedisValue::Array(vec![RedisValue::Array(vec![
+            bulk("s"),
+            RedisValue::Array(vec![RedisValue::Array(vec![
+                bulk("1-1"),
+                RedisValue::Array(vec![bulk("old"), bulk("x")]),
+            ])]),
+        ])])
+    );
+    assert!(matches!(
+        reader.command(["XREAD", "STREAMS", "s"]).await.unwrap(),
+        RedisValue::SimpleError(_)
+    ));
+    assert!(matches!(
+        reader
+            .command(["XREAD", "BLOCK", "-1", "STREAMS", "s", "$"])
+            .await
+            .unwrap(),
+        RedisValue::SimpleError(_)
+    ));
+}
+
+#[tokio::test]
+async fn pops_are_served_longest_waiting_first() {
+    let clients = BlockedClients::default();
+    let first = clients.block_pop(1, 0, vec![key("a")], End::Head);
+    let watcher = clients.block(2, 0, vec![key("a")]);
+    let second = clients.block_pop(3, 0, vec![key("b"), key("a")], End::Tail);
+    let third = clients.block_pop(4, 0, vec![key("a")], End::Head);
+
+    // --- two elements for three poppers, the non popping waiter left alone
+    let mut list = vec![Bytes::from_static(b"x"), Bytes::from_static(b"y")];
+    let served = clients.serve_pops(0, &key("a"), |_| list.pop());
+    assert_eq!(served, vec![End::Head, End::Tail]);
+    assert_eq!(
+        first.wait(None).await,
+        Wakeup::Popped {
+            key: key("a"),
+            element: Bytes::from_static(b"y")
+        }
+    );
+    assert_eq!(
+        second.wait(None).await,
+        Wakeup::Popped {
+            key: key("a"),
+            element: Bytes::from_static(b"x")
+        }
+    );
+    assert_eq!(clients.len(), 2);
+    drop((watcher, third));
+}
+
+#[tokio::test]
+async fn blpop_and_brpop_get_pushed_elements_handed_over() {
+    let server = TestServer::master().await;
+    let mut writer = server.client().await;
+    let mut first = server.client().await;
+    let mut second = server.client().await;
+
+    let blocked = |count| {
+        let server = &server.server;
+        async move {
+            while server.blocked_clients.len() < count {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }
+    };
+    let first_pop = async {
+        let reply = first.command(["BLPOP", "other", "list", "0"]).await;
+        // --- handed over by the push itself, nothing left for anyone else to take
+        (reply, first.command(["LRANGE", "list", "0", "-1"]).await)
+    };
+    let second_pop = async {
+        blocked(1).await;
+        second.command(["BRPOP", "list", "0"]).await
+    };
+    let push = async {
+        blocked(2).await;
+        writer
+            .command(["RPUSH", "list", "a", "b", "c"])
+            .await
+            .unwrap()
+    };
+    let ((first_reply, left), second_reply, pushed) = tokio::join!(first_pop, second_pop, push);
+
+    assert_eq!(pushed, RedisValue::Integer(3));
+    assert_eq!(
+        first_reply.unwrap(),
+        RedisValue::Array(vec![bulk("list"), bulk("a")])
+    );
+    assert_eq!(
+        second_reply.unwrap(),
+        RedisValue::Array(vec![bulk("list"), bulk("c")])
+    );
+    assert_eq!(left.unwrap(), RedisValue::Array(vec![bulk("b")]));
+
+    // --- a list already there is popped right away, keys tried in order
+    writer.command(["RPUSH", "other", "x"]).await.unwrap();
+    assert_eq!(
+        writer
+            .command(["BRPOP", "list", "other", "0"])
+            .await
+            .unwrap(),
+        RedisValue::Array(vec![bulk("list"), bulk("b")])
+    );
+    assert_eq!(
+        writer.command(["EXISTS", "list"]).await.unwrap(),
+        RedisValue::Integer(0)
+    );
+    assert_eq!(
+        writer.command(["BLPOP", "list", "0.02"]).await.unwrap(),
+        RedisValue::NullArray
+    );
+    writer.set("string", "v").await.unwrap();
+    for (args, error) in [
+        (
+            ["BLPOP", "string", "0"],
+            "WRONGTYPE Operation against a key holding the wrong kind of value",
+        ),
+        (["BLPOP", "list", "-1"], "ERR timeout is negative"),
+        (
+            ["BLPOP", "list", "soon"],
+            "ERR timeout is not a float or out of range",
+        ),
+    ] {
+        assert_eq!(
+            writer.command(args).await.unwrap(),
+            RedisValue::SimpleError(error.to_string().into())
+        );
+    }
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use bytes::Bytes;
+use common::{assert_replies, simple, TestServer};
+use redis_rust::{
+    server::{bloom::BloomFilter, db::Db, rdb, server::Dataset},
+    RedisValue,
+};
+
+fn integers(values: &[i64]) -> RedisValue {
+    RedisValue::Array(
+        values
+            .iter()
+            .map(|value| RedisValue::Integer(*value))
+            .collect(),
+    )
+}
+
+fn error(message: &str) -> RedisValue {
+    RedisValue::SimpleError(message.to_string().into())
+}
+
+#[tokio::test]
+async fn items_are_added_and_checked() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["BF.ADD", "seen", "a"], RedisValue::Integer(1)),
+            (&["BF.ADD", "seen", "a"], RedisValue::Integer(0)),
+            (&["BF.MADD", "seen", "b", "a", "c"], integers(&[1, 0, 1])),
+            (&["BF.EXISTS", "seen", "b"], RedisValue::Integer(1)),
+            (&["BF.EXISTS", "seen", "z"], RedisValue::Integer(0)),
+            (&["BF.EXISTS", "missing", "a"], RedisValue::Integer(0)),
+            (&["BF.MEXISTS", "seen", "a", "z", "c"], integers(&[1, 0, 1])),
+            (
+                &["BF.RESERVE", "seen", "0.01", "100"],
+                error("ERR item exists"),
+            ),
+            (
+                &["BF.RESERVE", "other", "1.5", "100"],
+                error("ERR (0 < error rate range < 1)"),
+            ),
+            (
+                &["BF.RESERVE", "other", "0.01", "0"],
+                error("ERR (capacity should be larger than 0)"),
+            ),
+            // --- too large to allocate, refused instead of taking the server down
+            (
+                &["BF.RESERVE", "other", "0.0000000001", "9223372036854775807"],
+                error("ERR insufficient memory to create filter"),
+            ),
+            (&["EXISTS", "other"], RedisValue::Integer(0)),
+            (
+                &["BF.RESERVE", "huge", "0.01", "1", "EXPANSION", "4294967295"],
+                simple("OK"),
+            ),
+            (
+                &["BF.MADD", "huge", "a", "b"],
+                RedisValue::Array(vec![
+                    RedisValue::Integer(1),
+                    error("ERR insufficient memory to create filter"),
+                ]),
+            ),
+            (&["SET", "str", "bar"], simple("OK")),
+            (
+                &["BF.ADD", "str", "a"],
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ),
+            (
+                &["GET", "seen"],
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn filters_scale_unless_told_not_to() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["BF.RESERVE", "fixed", "0.001", "2", "NONSCALING"],
+                simple("OK"),
+            ),
+            (&["BF.MADD", "fixed", "a", "b"], integers(&[1, 1])),
+            (
+                &["BF.MADD", "fixed", "a", "c"],
+                RedisValue::Array(vec![
+                    RedisValue::Integer(0),
+                    error("ERR non scaling filter is full"),
+                ]),
+            ),
+            (
+                &["BF.RESERVE", "growing", "0.001", "2", "EXPANSION", "3"],
+                simple("OK"),
+            ),
+            (&["BF.MADD", "growing", "a", "b", "c"], integers(&[1, 1, 1])),
+            (
+                &["BF.MEXISTS", "growing", "a", "b", "c"],
+                integers(&[1, 1, 1]),
+            ),
+            // --- the third item went to a second layer, three times as large
+            (
+                &["BF.INFO", "growing"],
+                RedisValue::Array(vec![
+                    simple("Capacity"),
+                    RedisValue::Integer(8),
+                    simple("Size"),
+                    RedisValue::Integer(16),
+                    simple("Number of filters"),
+                    RedisValue::Integer(2),
+                    simple("Number of items inserted"),
+                    RedisValue::Integer(3),
+                    simple("Expansion rate"),
+                    RedisValue::Integer(3),
+                ]),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[test]
+fn filters_are_saved_with_all_their_layers() {
+    let mut filter = BloomFilter::new(0.01, 10, 2).unwrap();
+    for i in 0..50 {
+        filter.add(format!("item:{i}").as_bytes()).unwrap();
+    }
+    assert!(filter.layers().len() > 1);
+    let mut contents = Db::new();
+    contents.insert(
+        Bytes::from("seen"),
+        RedisValue::Bloom(Box::new(filter)),
+        None,
+    );
+    let dataset = Dataset::from([(0, contents)]);
+    let image = rdb::serialize(&dataset, None).unwrap();
+
+    let (loaded, _) = rdb::parse_sequential(&image, 0).unwrap();
+    assert_eq!(loaded, dataset);
+    let Some((RedisValue::Bloom(filter), _)) = loaded[&0].get(&Bytes::from("seen")) else {
+        panic!("The filter should be read back");
+    };
+    assert!((0..50).all(|i| filter.contains(format!("item:{i}").as_bytes())));
+}
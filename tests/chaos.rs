@@ -0,0 +1,235 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{bulk, TestServer};
+use redis_rust::{
+    server::{handler::RedisConnectionHandler, rdb::EMPTY_RDB},
+    Args, RedisValue,
+};
+use tokio::{net::TcpListener, sync::mpsc};
+
+const SET: &[u8] = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
+const GETACK: &[u8] = b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
+
+/// Master the test drives by hand: every connection goes through the handshake, the first
+/// one gets a full resync and later ones a partial one. Stream bytes sent on the channel go
+/// to the replica currently connected, frames it sends back come out of the other one
+struct ScriptedMaster {
+    addr: std::net::SocketAddr,
+    stream: mpsc::UnboundedSender<&'static [u8]>,
+    /// PSYNC requests and replies from the replica, in order
+    received: mpsc::UnboundedReceiver<RedisValue>,
+}
+impl ScriptedMaster {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stream, mut to_send) = mpsc::unbounded_channel::<&'static [u8]>();
+        let (sent_back, received) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut full_resync = true;
+            loop {
+                let (conn, _) = listener.accept().await.unwrap();
+                let mut handler = RedisConnectionHandler::new(conn);
+                for reply in ["+PONG\r\n", "+OK\r\n", "+OK\r\n"] {
+                    handler.read_and_parse().await.unwrap();
+                    handler.write_raw(reply.as_bytes()).await.unwrap();
+                }
+                let psync = handler.read_and_parse().await.unwrap().unwrap();
+                sent_back.send(psync).unwrap();
+                let reply = match full_resync {
+                    true => format!(
+                        "+FULLRESYNC {} 0\r\n${}\r\n",
+                        "a".repeat(40),
+                        EMPTY_RDB.len()
+                    )
+                    .into_bytes()
+                    .into_iter()
+                    .chain(EMPTY_RDB.iter().copied())
+                    .collect::<Vec<_>>(),
+                    false => b"+CONTINUE\r\n".to_vec(),
+                };
+                handler.write_raw(&reply).await.unwrap();
+                full_resync = false;
+
+                // --- until the replica hangs up, then wait for it to come back
+                loop {
+                    tokio::select! {
+                        data = to_send.recv() => {
+                            let Some(data) = data else { return };
+                            if handler.write_raw(data).await.is_err() {
+                                break;
+                            }
+                        }
+                        frame = handler.read_and_parse() => match frame {
+                            Ok(Some(frame)) => sent_back.send(frame).unwrap(),
+                            _ => break,
+                        },
+                    }
+                }
+            }
+        });
+
+        Self {
+            addr,
+            stream,
+            received,
+        }
+    }
+
+    fn send(&self, data: &'static [u8]) {
+        self.stream.send(data).unwrap();
+    }
+
+    async fn next_received(&mut self) -> RedisValue {
+        self.received.recv().await.unwrap()
+    }
+}
+
+async fn replica_of(master: &mut ScriptedMaster) -> TestServer {
+    let replica = TestServer::start(Args {
+        port: Some(0),
+        replicaof: Some(format!("{} {}", master.addr.ip(), master.addr.port())),
+        ..Default::default()
+    })
+    .await;
+    master.next_received().await;
+
+    replica
+}
+
+async fn debug(server: &TestServer, args: &[&'static str]) {
+    let reply = server
+        .client()
+        .await
+        .command(["DEBUG"].iter().chain(args).copied())
+        .await
+        .unwrap();
+    assert_eq!(reply, RedisValue::SimpleString("OK".into()), "{:?}", args);
+}
+
+async fn wait_for_link_down(replica: &TestServer) {
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        let RedisValue::BulkString(info) = client.command(["INFO", "replication"]).await.unwrap()
+        else {
+            panic!("INFO replies with a bulk string");
+        };
+        if std::str::from_utf8(&info)
+            .unwrap()
+            .contains("master_link_status:down")
+        {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("The master link never went down");
+}
+
+#[tokio::test]
+async fn link_latency_delays_applying_the_stream() {
+    let mut master = ScriptedMaster::start().await;
+    let replica = replica_of(&mut master).await;
+    let mut client = replica.client().await;
+
+    debug(&replica, &["REPL-LATENCY", "300"]).await;
+    master.send(SET);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(client.get("foo").await.unwrap(), None);
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+}
+
+#[tokio::test]
+async fn ack_delay_holds_back_the_ack() {
+    let mut master = ScriptedMaster::start().await;
+    let replica = replica_of(&mut master).await;
+
+    debug(&replica, &["REPL-ACK-DELAY", "200"]).await;
+    let start = Instant::now();
+    master.send(GETACK);
+    let ack = master.next_received().await;
+
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(
+        ack,
+        RedisValue::Array(vec![bulk("REPLCONF"), bulk("ACK"), bulk("0")])
+    );
+}
+
+#[tokio::test]
+async fn corrupted_stream_drops_the_link_and_partial_resync_recovers() {
+    let mut master = ScriptedMaster::start().await;
+    let replica = replica_of(&mut master).await;
+    let mut client = replica.client().await;
+
+    debug(&replica, &["REPL-CORRUPT", "4"]).await;
+    master.send(SET);
+    wait_for_link_down(&replica).await;
+    assert_eq!(client.get("foo").await.unwrap(), None);
+
+    // --- nothing was applied, the replica resumes from where it was
+    debug(&replica, &["REPL-FAULTS-RESET"]).await;
+    let port = master.addr.port().to_string();
+    client
+        .command(["REPLICAOF".to_string(), "127.0.0.1".to_string(), port])
+        .await
+        .unwrap();
+    assert_eq!(
+        master.next_received().await,
+        RedisValue::Array(vec![bulk("PSYNC"), bulk(&"a".repeat(40)), bulk("1")])
+    );
+    master.send(SET);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+}
+
+#[tokio::test]
+async fn master_link_can_be_dropped_on_demand() {
+    let mut master = ScriptedMaster::start().await;
+    let replica = replica_of(&mut master).await;
+
+    debug(&replica, &["DROP-MASTER-LINK"]).await;
+    wait_for_link_down(&replica).await;
+}
+
+#[tokio::test]
+async fn silent_master_times_out_and_the_replica_reconnects() {
+    let mut master = ScriptedMaster::start().await;
+    let replica = TestServer::start(Args {
+        port: Some(0),
+        replicaof: Some(format!("{} {}", master.addr.ip(), master.addr.port())),
+        repl_timeout: Some(1),
+        ..Default::default()
+    })
+    .await;
+    master.next_received().await;
+    master.send(SET);
+
+    // --- the master goes quiet, after repl-timeout the link drops and comes back on its own
+    let psync = tokio::time::timeout(Duration::from_secs(5), master.next_received())
+        .await
+        .expect("The replica never reconnected");
+    assert_eq!(
+        psync,
+        RedisValue::Array(vec![
+            bulk("PSYNC"),
+            bulk(&"a".repeat(40)),
+            bulk(&(SET.len() + 1).to_string())
+        ])
+    );
+    let mut client = replica.client().await;
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+}
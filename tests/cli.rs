@@ -0,0 +1,156 @@
+mod common;
+
+use std::process::{Output, Stdio};
+
+use common::{bulk, TestServer};
+use redis_rust::server::rdb;
+use tokio::{io::AsyncWriteExt, net::TcpListener, process::Command};
+
+/// Runs the cli against the server with `args`, `stdin` as its input
+async fn cli(server: &TestServer, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .args(["--host", &server.addr.ip().to_string()])
+        .args(["--port", &server.addr.port().to_string()])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failure starting the cli");
+    let mut input = child.stdin.take().unwrap();
+    input.write_all(stdin).await.unwrap();
+    drop(input);
+
+    child.wait_with_output().await.unwrap()
+}
+
+#[tokio::test]
+async fn prompt_lines_are_split_like_redis_cli_and_replies_formatted() {
+    let server = TestServer::master().await;
+
+    let lines = [
+        r#"SET "a key" "tab\there""#,
+        r#"GET 'a key'"#,
+        r#"SET bytes "\x00\xff""#,
+        "GET bytes",
+        "GET missing",
+        r#"SET "unbalanced"#,
+        "RPUSH list one two",
+        "LRANGE list 0 -1",
+        "LRANGE missing 0 -1",
+        "INCR list",
+        "EVAL \"return {1, {'x', 'y'}}\" 0",
+        "",
+        "QUIT",
+        "GET never",
+    ];
+    let output = cli(&server, &[], lines.join("\n").as_bytes()).await;
+
+    assert!(output.status.success(), "{:?}", output);
+    let expected = [
+        "OK",
+        r#""tab\there""#,
+        "OK",
+        r#""\x00\xff""#,
+        "(nil)",
+        "Invalid argument(s)",
+        "(integer) 2",
+        "1) \"one\"\n2) \"two\"",
+        "(empty array)",
+        "(error) WRONGTYPE Operation against a key holding the wrong kind of value",
+        "1) (integer) 1\n2) 1) \"x\"\n   2) \"y\"",
+    ];
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        expected.map(|reply| format!("{}\n", reply)).concat()
+    );
+}
+
+#[tokio::test]
+async fn commands_given_as_arguments_run_once() {
+    let server = TestServer::master().await;
+
+    let output = cli(&server, &["SET", "k", "v"], b"").await;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"OK\n");
+    let output = cli(&server, &["GET", "k"], b"").await;
+    assert_eq!(output.stdout, b"\"v\"\n");
+
+    // --- arguments starting with a hyphen are the command's, not options
+    cli(&server, &["RPUSH", "l", "a", "b"], b"").await;
+    let output = cli(&server, &["LRANGE", "l", "0", "-1"], b"").await;
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"1) \"a\"\n2) \"b\"\n");
+    let output = cli(&server, &["SET", "n", "-5"], b"").await;
+    assert_eq!(output.stdout, b"OK\n");
+}
+
+#[tokio::test]
+async fn pipe_sends_raw_resp_and_counts_the_replies() {
+    let server = TestServer::master().await;
+
+    let input = [
+        "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n",
+        "*2\r\n$4\r\nINCR\r\n$1\r\na\r\n",
+        "*2\r\n$5\r\nLPUSH\r\n$1\r\na\r\n",
+    ]
+    .concat();
+    let output = cli(&server, &["--pipe"], input.as_bytes()).await;
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(output.stdout, b"errors: 1, replies: 3\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("wrong number of arguments for 'lpush'"),
+        "{}",
+        stderr
+    );
+    let mut client = server.client().await;
+    assert_eq!(client.get("a").await.unwrap().as_deref(), Some(&b"2"[..]));
+}
+
+#[tokio::test]
+async fn rdb_saves_the_dataset_of_a_full_sync() {
+    let server = TestServer::master().await;
+    server.client().await.set("foo", "bar").await.unwrap();
+    let path = std::env::temp_dir().join(format!("redis-rust-cli-{}.rdb", std::process::id()));
+
+    let output = cli(&server, &["--rdb", &path.to_string_lossy()], b"").await;
+
+    assert!(output.status.success(), "{:?}", output);
+    let dataset = rdb::parse(&std::fs::read(&path).unwrap(), 0).unwrap();
+    assert_eq!(dataset[&0].get(&"foo".into()), Some((&bulk("bar"), None)));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn errors_after_connecting_are_not_connection_failures() {
+    let server = TestServer::master().await;
+
+    // --- the server goes away in the middle of the session
+    let output = cli(&server, &[], b"SHUTDOWN NOSAVE\nPING\n").await;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Error: "), "{}", stderr);
+
+    // --- nothing listens there, the test server keeps its listener open after SHUTDOWN
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .args(["--host", &addr.ip().to_string()])
+        .args(["--port", &addr.port().to_string()])
+        .arg("PING")
+        .output()
+        .await
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.starts_with(&format!("Could not connect to redis-rust at {}", addr)),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,4170 @@
+mod common;
+
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use common::{assert_replies, bulk, simple, TestServer};
+use redis_rust::{
+    repl::ServerContext,
+    server::{
+        acl::AclDenial,
+        clock::{Clock, MockClock, SystemClock},
+        commands::{execute, CommandContext},
+        connlimit::{ConnectionLimiter, ConnectionLimits, ConnectionRefusal},
+        net::SocketOptions,
+        server::RedisServer,
+        session::Session,
+        stats::ServerStats,
+    },
+    Args, RedisValue,
+};
+
+#[tokio::test]
+async fn set_and_get() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["PING"], simple("PONG")),
+            (&["ECHO", "hey"], bulk("hey")),
+            (&["SET", "foo", "bar"], simple("OK")),
+            (&["GET", "foo"], bulk("bar")),
+            (&["GET", "missing"], RedisValue::NullBulkString),
+            (&["KEYS", "*"], RedisValue::Array(vec![bulk("foo")])),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn databases_are_selected_swapped_and_flushed_apart() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["CONFIG", "GET", "databases"],
+                RedisValue::Array(vec![bulk("databases"), bulk("16")]),
+            ),
+            (&["SELECT", "16"], error("ERR DB index is out of range")),
+            (
+                &["SELECT", "x"],
+                error("ERR value is not an integer or out of range"),
+            ),
+            (&["SET", "a", "0"], simple("OK")),
+            (&["SELECT", "1"], simple("OK")),
+            (&["GET", "a"], RedisValue::NullBulkString),
+            (&["SET", "a", "1"], simple("OK")),
+            (&["SET", "b", "2", "PX", "600000"], simple("OK")),
+            (&["DBSIZE"], RedisValue::Integer(2)),
+            (&["SELECT", "0"], simple("OK")),
+            (&["GET", "a"], bulk("0")),
+            (
+                &["SWAPDB", "0", "16"],
+                error("ERR DB index is out of range"),
+            ),
+            (&["SWAPDB", "x", "0"], error("ERR invalid first DB index")),
+            (&["SWAPDB", "0", "1"], simple("OK")),
+            (&["GET", "a"], bulk("1")),
+            (&["DBSIZE"], RedisValue::Integer(2)),
+        ],
+    )
+    .await;
+    // --- a swap shows on every connection of the databases, TTLs included
+    assert_replies(
+        &mut other,
+        &[
+            (&["GET", "a"], bulk("1")),
+            (&["SELECT", "1"], simple("OK")),
+            (&["GET", "a"], bulk("0")),
+        ],
+    )
+    .await;
+    let RedisValue::Integer(ttl) = client.command(["PTTL", "b"]).await.unwrap() else {
+        panic!("PTTL should reply with an integer");
+    };
+    assert!(ttl > 0);
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["FLUSHDB"], simple("OK")),
+            (&["EXISTS", "a", "b"], RedisValue::Integer(0)),
+            (&["TTL", "b"], RedisValue::Integer(-2)),
+            (&["SELECT", "1"], simple("OK")),
+            (&["GET", "a"], bulk("0")),
+            (&["FLUSHALL", "NOW"], error("ERR syntax error")),
+            (&["FLUSHALL", "ASYNC"], simple("OK")),
+            (&["GET", "a"], RedisValue::NullBulkString),
+            (&["RESET"], simple("RESET")),
+            (&["SET", "a", "0"], simple("OK")),
+            (&["SELECT", "1"], simple("OK")),
+            (&["GET", "a"], RedisValue::NullBulkString),
+        ],
+    )
+    .await;
+    let RedisValue::BulkString(info) = client.command(["INFO", "keyspace"]).await.unwrap() else {
+        panic!("INFO should reply with a bulk string");
+    };
+    assert!(info.ends_with(b"db0:keys=1,expires=0,avg_ttl=0\r\n"));
+}
+
+#[tokio::test]
+async fn move_sends_a_key_to_another_database() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+    assert_replies(
+        &mut client,
+        &[
+            (&["MOVE", "missing", "1"], RedisValue::Integer(0)),
+            (&["SET", "a", "1", "PX", "600000"], simple("OK")),
+            (
+                &["MOVE", "a", "0"],
+                error("ERR source and destination objects are the same"),
+            ),
+            (&["MOVE", "a", "16"], error("ERR DB index is out of range")),
+            (&["MOVE", "a", "2"], RedisValue::Integer(1)),
+            (&["EXISTS", "a"], RedisValue::Integer(0)),
+            (&["SET", "a", "other"], simple("OK")),
+            // --- an existing key in the target stays as it is
+            (&["SELECT", "2"], simple("OK")),
+            (&["MOVE", "a", "0"], RedisValue::Integer(0)),
+            (&["GET", "a"], bulk("1")),
+        ],
+    )
+    .await;
+    let RedisValue::Integer(ttl) = client.command(["PTTL", "a"]).await.unwrap() else {
+        panic!("PTTL should reply with an integer");
+    };
+    assert!(ttl > 0, "the TTL went along with the key");
+}
+
+#[tokio::test]
+async fn rename_copy_randomkey_and_dbsize() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+    assert_replies(
+        &mut client,
+        &[
+            (&["RANDOMKEY"], RedisValue::NullBulkString),
+            (&["DBSIZE"], RedisValue::Integer(0)),
+            (&["RENAME", "a", "b"], error("ERR no such key")),
+            (&["SET", "a", "1", "PX", "600000"], simple("OK")),
+            (&["RPUSH", "l", "x"], RedisValue::Integer(1)),
+            (&["RENAME", "a", "b"], simple("OK")),
+            (&["EXISTS", "a"], RedisValue::Integer(0)),
+            (&["GET", "b"], bulk("1")),
+            (&["RENAMENX", "b", "l"], RedisValue::Integer(0)),
+            (&["RENAMENX", "b", "c"], RedisValue::Integer(1)),
+            (&["RENAME", "c", "l"], simple("OK")),
+            (&["GET", "l"], bulk("1")),
+            (&["RENAME", "l", "l"], simple("OK")),
+            (
+                &["COPY", "l", "l"],
+                error("ERR source and destination objects are the same"),
+            ),
+            (&["COPY", "missing", "d"], RedisValue::Integer(0)),
+            (&["COPY", "l", "d"], RedisValue::Integer(1)),
+            (&["SET", "e", "old"], simple("OK")),
+            (&["COPY", "l", "e"], RedisValue::Integer(0)),
+            (
+                &["COPY", "l", "e", "DB", "0", "REPLACE"],
+                RedisValue::Integer(1),
+            ),
+            (
+                &["COPY", "l", "f", "DB", "16"],
+                error("ERR DB index is out of range"),
+            ),
+            (&["COPY", "l", "f", "DB", "1"], RedisValue::Integer(1)),
+            (&["GET", "e"], bulk("1")),
+            (&["DBSIZE"], RedisValue::Integer(3)),
+            (&["SET", "gone", "x", "PX", "1"], simple("OK")),
+        ],
+    )
+    .await;
+    // --- the TTL went along with the value
+    for key in ["l", "d", "e"] {
+        let RedisValue::Integer(ttl) = client.command(["PTTL", key]).await.unwrap() else {
+            panic!("PTTL should reply with an integer");
+        };
+        assert!(ttl > 0, "{} lost its TTL", key);
+    }
+
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    assert_eq!(
+        client.command(["DBSIZE"]).await.unwrap(),
+        RedisValue::Integer(3)
+    );
+    for _ in 0..20 {
+        let RedisValue::BulkString(key) = client.command(["RANDOMKEY"]).await.unwrap() else {
+            panic!("RANDOMKEY should reply with a key");
+        };
+        assert!([&b"l"[..], b"d", b"e"].contains(&&key[..]), "{:?}", key);
+    }
+
+    // --- COPY to another database takes the TTL there too
+    assert_eq!(client.command(["SELECT", "1"]).await.unwrap(), simple("OK"));
+    assert_eq!(client.command(["GET", "f"]).await.unwrap(), bulk("1"));
+    let RedisValue::Integer(ttl) = client.command(["PTTL", "f"]).await.unwrap() else {
+        panic!("PTTL should reply with an integer");
+    };
+    assert!(ttl > 0, "f lost its TTL");
+}
+
+#[tokio::test]
+async fn scan_calls_return_count_keys_from_their_cursor() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    for i in 0..1000 {
+        client.set(format!("key:{i}"), "x").await.unwrap();
+    }
+
+    let mut cursor = "0".to_string();
+    let mut total = 0;
+    loop {
+        let reply = client
+            .command(["SCAN", &cursor, "COUNT", "50"].map(str::to_string))
+            .await
+            .unwrap();
+        let RedisValue::Array(reply) = reply else {
+            panic!("SCAN should reply with an array");
+        };
+        let [RedisValue::BulkString(next), RedisValue::Array(keys)] = &reply[..] else {
+            panic!("SCAN should reply with a cursor and keys");
+        };
+        total += keys.len();
+        cursor = String::from_utf8(next.to_vec()).unwrap();
+        if cursor == "0" {
+            assert!(keys.len() <= 50);
+            break;
+        }
+        assert_eq!(keys.len(), 50);
+    }
+    assert_eq!(total, 1000);
+}
+
+#[tokio::test]
+async fn keys_and_scan_go_by_the_pattern() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    for i in 0..100 {
+        client.set(format!("user:{i}"), "x").await.unwrap();
+        client.set(format!("other:{i}"), "x").await.unwrap();
+    }
+    let RedisValue::Array(keys) = client.command(["KEYS", "user:?"]).await.unwrap() else {
+        panic!("KEYS should reply with an array");
+    };
+    assert_eq!(keys.len(), 10);
+
+    // --- keys written or deleted halfway through don't make the others come up twice or
+    // --- not at all
+    let mut seen = std::collections::HashSet::new();
+    let mut cursor = "0".to_string();
+    for round in 0.. {
+        let reply = client
+            .command(["SCAN", &cursor, "MATCH", "user:*", "COUNT", "7"].map(str::to_string))
+            .await
+            .unwrap();
+        let RedisValue::Array(reply) = reply else {
+            panic!("SCAN should reply with an array");
+        };
+        let [RedisValue::BulkString(next), RedisValue::Array(keys)] = &reply[..] else {
+            panic!("SCAN should reply with a cursor and keys");
+        };
+        for key in keys {
+            assert!(seen.insert(key.clone()), "{:?} came up twice", key);
+        }
+        client
+            .command(["DEL".to_string(), format!("other:{round}")])
+            .await
+            .unwrap();
+        client.set(format!("new:{round}"), "x").await.unwrap();
+        cursor = String::from_utf8(next.to_vec()).unwrap();
+        if cursor == "0" {
+            break;
+        }
+    }
+    let expected = (0..100)
+        .map(|i| bulk(&format!("user:{i}")))
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(seen, expected);
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["SCAN", "nope"],
+                RedisValue::SimpleError("ERR invalid cursor".into()),
+            ),
+            (
+                &["SCAN", "0", "COUNT", "0"],
+                RedisValue::SimpleError("ERR syntax error".into()),
+            ),
+            (
+                &["SCAN", "0", "MATCH"],
+                RedisValue::SimpleError("ERR syntax error".into()),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn set_with_expiry() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["SET", "foo", "bar", "PX", "100"], simple("OK")),
+            (&["GET", "foo"], bulk("bar")),
+        ],
+    )
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_replies(
+        &mut client,
+        &[(&["GET", "foo"], RedisValue::NullBulkString)],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn set_conditions_and_get() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+    assert_replies(
+        &mut client,
+        &[
+            (&["SET", "foo", "1", "XX"], RedisValue::NullBulkString),
+            (&["SET", "foo", "1", "NX"], simple("OK")),
+            (&["SET", "foo", "2", "NX"], RedisValue::NullBulkString),
+            (&["SET", "foo", "2", "xx", "get"], bulk("1")),
+            (&["SET", "foo", "3", "NX", "GET"], bulk("2")),
+            (&["GET", "foo"], bulk("2")),
+            (&["SET", "new", "1", "GET"], RedisValue::NullBulkString),
+            (&["SET", "foo", "1", "NX", "XX"], error("ERR syntax error")),
+            (
+                &["SET", "foo", "1", "GET", "GET"],
+                error("ERR syntax error"),
+            ),
+            (
+                &["SET", "foo", "1", "EX", "10", "KEEPTTL"],
+                error("ERR syntax error"),
+            ),
+            (&["SET", "foo", "1", "PX"], error("ERR syntax error")),
+            (
+                &["SET", "foo", "1", "EX", "soon"],
+                error("ERR value is not an integer or out of range"),
+            ),
+            (
+                &["SET", "foo", "1", "PX", "0"],
+                error("ERR invalid expire time in 'set' command"),
+            ),
+            (
+                &["SET", "foo", "1", "EX", "9223372036854775807"],
+                error("ERR invalid expire time in 'set' command"),
+            ),
+            (
+                &["SET", "foo", "1", "PX", "9223372036854775807"],
+                error("ERR invalid expire time in 'set' command"),
+            ),
+            (
+                &["SET", "foo", "1", "EX", "9223372036854775"],
+                error("ERR invalid expire time in 'set' command"),
+            ),
+            (&["JSON.SET", "doc", "$", "{}"], simple("OK")),
+            (&["SET", "doc", "1", "NX"], RedisValue::NullBulkString),
+            (
+                &["SET", "doc", "1", "GET"],
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn set_expiry_options_and_keepttl() {
+    let clock = Arc::new(MockClock::new(1_000_000));
+    let server = RedisServer::in_memory(clock.clone());
+    let mut session = server.new_session();
+    let mut set = async |args: &[&'static str]| {
+        let args = args
+            .iter()
+            .map(|arg| bytes::Bytes::from_static(arg.as_bytes()))
+            .collect::<Vec<_>>();
+        let mut ctx = CommandContext {
+            args: &args,
+            server: &server,
+            session: &mut session,
+        };
+        assert_eq!(execute("SET", &mut ctx).await.unwrap(), simple("OK"));
+        server.databases[0]
+            .expire_store
+            .lock()
+            .await
+            .get(&bulk("foo"))
+            .copied()
+    };
+
+    assert_eq!(set(&["foo", "1", "EX", "10"]).await, Some(1_010_000));
+    assert_eq!(set(&["foo", "1", "PX", "10"]).await, Some(1_000_010));
+    assert_eq!(set(&["foo", "1", "EXAT", "2000"]).await, Some(2_000_000));
+    assert_eq!(set(&["foo", "1", "PXAT", "2000"]).await, Some(2_000));
+    assert_eq!(set(&["foo", "1", "EX", "10"]).await, Some(1_010_000));
+    assert_eq!(set(&["foo", "2", "KEEPTTL"]).await, Some(1_010_000));
+    // --- a plain SET drops the TTL
+    assert_eq!(set(&["foo", "3"]).await, None);
+}
+
+#[tokio::test]
+async fn hyperloglogs_count_distinct_elements() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let int = RedisValue::Integer;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["PFADD", "hll", "a", "b", "c", "d", "e", "f", "g"], int(1)),
+            (&["PFCOUNT", "hll"], int(7)),
+            (&["PFADD", "hll", "a", "b"], int(0)),
+            (&["PFADD", "other", "g", "h", "i"], int(1)),
+            (&["PFCOUNT", "hll", "other", "missing"], int(9)),
+            (&["PFCOUNT", "missing"], int(0)),
+            // --- no elements still creates the key
+            (&["PFADD", "empty"], int(1)),
+            (&["PFADD", "empty"], int(0)),
+            (&["PFCOUNT", "empty"], int(0)),
+            (&["PFMERGE", "merged", "hll", "other"], simple("OK")),
+            (&["PFCOUNT", "merged"], int(9)),
+            (&["TYPE", "merged"], simple("string")),
+            (&["SET", "string", "HYLL but not really"], simple("OK")),
+            (
+                &["PFADD", "string", "a"],
+                RedisValue::SimpleError(
+                    "WRONGTYPE Key is not a valid HyperLogLog string value.".into(),
+                ),
+            ),
+            (&["LPUSH", "list", "a"], int(1)),
+            (
+                &["PFCOUNT", "hll", "list"],
+                RedisValue::SimpleError(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+
+    let RedisValue::BulkString(dense) = client.command(["GET", "merged"]).await.unwrap() else {
+        panic!("A HyperLogLog is a string");
+    };
+    assert_eq!(&dense[..4], b"HYLL");
+
+    // --- the estimate stays within a few percent
+    for chunk in (0..10_000).collect::<Vec<_>>().chunks(500) {
+        let mut cmd = vec!["PFADD".to_string(), "big".to_string()];
+        cmd.extend(chunk.iter().map(|i| format!("element:{}", i)));
+        client.command(cmd).await.unwrap();
+    }
+    let RedisValue::Integer(count) = client.command(["PFCOUNT", "big"]).await.unwrap() else {
+        panic!("PFCOUNT replies an integer");
+    };
+    assert!((9_700..=10_300).contains(&count), "{}", count);
+
+    // --- the sparse encoding Redis starts with reads too, an XZERO run over all registers
+    let mut sparse = b"HYLL\x01\x00\x00\x00".to_vec();
+    sparse.extend([0; 8]);
+    sparse.extend([0x7f, 0xff]);
+    client
+        .command([
+            bytes::Bytes::from_static(b"SET"),
+            bytes::Bytes::from_static(b"sparse"),
+            bytes::Bytes::from(sparse),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(client.command(["PFCOUNT", "sparse"]).await.unwrap(), int(0));
+    assert_eq!(
+        client.command(["PFADD", "sparse", "a"]).await.unwrap(),
+        int(1)
+    );
+    assert_eq!(client.command(["PFCOUNT", "sparse"]).await.unwrap(), int(1));
+}
+
+#[tokio::test]
+async fn bitmap_commands() {
+    let server = RedisServer::in_memory(Arc::new(MockClock::new(1_000_000)));
+    let mut session = server.new_session();
+    let mut run = async |cmd: &str, args: &[&'static str]| {
+        let args = args
+            .iter()
+            .map(|arg| bytes::Bytes::from_static(arg.as_bytes()))
+            .collect::<Vec<_>>();
+        let mut ctx = CommandContext {
+            args: &args,
+            server: &server,
+            session: &mut session,
+        };
+        execute(cmd, &mut ctx).await.unwrap()
+    };
+    let int = RedisValue::Integer;
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+
+    // --- setting a bit past the end pads the string with zeros
+    assert_eq!(run("SETBIT", &["bits", "17", "1"]).await, int(0));
+    assert_eq!(
+        run("GET", &["bits"]).await,
+        RedisValue::BulkString(bytes::Bytes::from_static(b"\x00\x00\x40"))
+    );
+    assert_eq!(run("SETBIT", &["bits", "17", "0"]).await, int(1));
+    assert_eq!(run("GETBIT", &["bits", "17"]).await, int(0));
+    assert_eq!(run("GETBIT", &["bits", "1000"]).await, int(0));
+    assert_eq!(run("GETBIT", &["missing", "0"]).await, int(0));
+    assert_eq!(
+        run("SETBIT", &["bits", "-1", "1"]).await,
+        error("ERR bit offset is not an integer or out of range")
+    );
+    assert_eq!(
+        run("SETBIT", &["bits", "0", "2"]).await,
+        error("ERR bit is not an integer or out of range")
+    );
+
+    // --- "foobar" has 4 + 6 bits set in its first two bytes, 26 in all
+    run("SET", &["foo", "foobar"]).await;
+    assert_eq!(run("BITCOUNT", &["foo"]).await, int(26));
+    assert_eq!(run("BITCOUNT", &["foo", "0", "0"]).await, int(4));
+    assert_eq!(run("BITCOUNT", &["foo", "1", "1"]).await, int(6));
+    assert_eq!(run("BITCOUNT", &["foo", "-2", "-1"]).await, int(7));
+    assert_eq!(run("BITCOUNT", &["foo", "5", "30", "BIT"]).await, int(17));
+    assert_eq!(run("BITCOUNT", &["foo", "5", "1"]).await, int(0));
+    assert_eq!(run("BITCOUNT", &["missing"]).await, int(0));
+    assert_eq!(
+        run("BITCOUNT", &["foo", "0"]).await,
+        error("ERR syntax error")
+    );
+    assert_eq!(
+        run("BITCOUNT", &["foo", "0", "1", "WORD"]).await,
+        error("ERR syntax error")
+    );
+
+    run("SET", &["pos", "\x00\x0f\x00"]).await;
+    assert_eq!(run("BITPOS", &["pos", "1"]).await, int(12));
+    assert_eq!(run("BITPOS", &["pos", "0"]).await, int(0));
+    assert_eq!(run("BITPOS", &["pos", "0", "1"]).await, int(8));
+    assert_eq!(run("BITPOS", &["pos", "1", "2"]).await, int(-1));
+    assert_eq!(
+        run("BITPOS", &["pos", "1", "0", "11", "BIT"]).await,
+        int(-1)
+    );
+    assert_eq!(
+        run("BITPOS", &["pos", "1", "-12", "-1", "BIT"]).await,
+        int(12)
+    );
+    // --- past the string are zeros, unless the range has an end
+    run("SET", &["zero", "\x00"]).await;
+    run("BITOP", &["NOT", "full", "zero"]).await;
+    assert_eq!(run("BITPOS", &["full", "0"]).await, int(8));
+    assert_eq!(run("BITPOS", &["full", "0", "0", "-1"]).await, int(-1));
+    assert_eq!(run("BITPOS", &["missing", "1"]).await, int(-1));
+    assert_eq!(run("BITPOS", &["missing", "0"]).await, int(0));
+    assert_eq!(
+        run("BITPOS", &["foo", "2"]).await,
+        error("ERR The bit argument must be 1 or 0.")
+    );
+
+    run("SET", &["a", "\x07\x07"]).await;
+    run("SET", &["b", "\x03"]).await;
+    assert_eq!(run("BITOP", &["AND", "dest", "a", "b"]).await, int(2));
+    assert_eq!(
+        run("GET", &["dest"]).await,
+        RedisValue::BulkString(bytes::Bytes::from_static(b"\x03\x00"))
+    );
+    assert_eq!(
+        run("BITOP", &["or", "dest", "a", "b", "missing"]).await,
+        int(2)
+    );
+    assert_eq!(
+        run("GET", &["dest"]).await,
+        RedisValue::BulkString(bytes::Bytes::from_static(b"\x07\x07"))
+    );
+    assert_eq!(run("BITOP", &["XOR", "dest", "a", "b"]).await, int(2));
+    assert_eq!(
+        run("GET", &["dest"]).await,
+        RedisValue::BulkString(bytes::Bytes::from_static(b"\x04\x07"))
+    );
+    assert_eq!(run("BITOP", &["NOT", "dest", "b"]).await, int(1));
+    assert_eq!(
+        run("GET", &["dest"]).await,
+        RedisValue::BulkString(bytes::Bytes::from_static(b"\xfc"))
+    );
+    assert_eq!(
+        run("BITOP", &["NOT", "dest", "a", "b"]).await,
+        error("ERR BITOP NOT must be called with a single source key.")
+    );
+    // --- nothing to combine deletes the destination
+    assert_eq!(run("BITOP", &["AND", "dest", "missing"]).await, int(0));
+    assert_eq!(run("EXISTS", &["dest"]).await, int(0));
+    run("LPUSH", &["list", "x"]).await;
+    assert_eq!(
+        run("BITOP", &["OR", "dest", "a", "list"]).await,
+        error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
+
+#[tokio::test]
+async fn legacy_set_commands_getex_and_getdel() {
+    let clock = Arc::new(MockClock::new(1_000_000));
+    let server = RedisServer::in_memory(clock.clone());
+    let mut session = server.new_session();
+    let mut run = async |cmd: &str, args: &[&'static str]| {
+        let args = args
+            .iter()
+            .map(|arg| bytes::Bytes::from_static(arg.as_bytes()))
+            .collect::<Vec<_>>();
+        let mut ctx = CommandContext {
+            args: &args,
+            server: &server,
+            session: &mut session,
+        };
+        execute(cmd, &mut ctx).await.unwrap()
+    };
+    let ttl = async || {
+        server.databases[0]
+            .expire_store
+            .lock()
+            .await
+            .get(&bulk("foo"))
+            .copied()
+    };
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+    let wrong_type = error("WRONGTYPE Operation against a key holding the wrong kind of value");
+
+    assert_eq!(run("SETEX", &["foo", "10", "bar"]).await, simple("OK"));
+    assert_eq!(ttl().await, Some(1_010_000));
+    assert_eq!(run("PSETEX", &["foo", "10", "baz"]).await, simple("OK"));
+    assert_eq!(ttl().await, Some(1_000_010));
+    assert_eq!(
+        run("SETEX", &["foo", "0", "bar"]).await,
+        error("ERR invalid expire time in 'setex' command")
+    );
+    assert_eq!(
+        run("PSETEX", &["foo", "9223372036854775807", "bar"]).await,
+        error("ERR invalid expire time in 'psetex' command")
+    );
+    assert_eq!(ttl().await, Some(1_000_010));
+    assert_eq!(
+        run("PSETEX", &["foo", "soon", "bar"]).await,
+        error("ERR value is not an integer or out of range")
+    );
+
+    assert_eq!(
+        run("SETNX", &["foo", "other"]).await,
+        RedisValue::Integer(0)
+    );
+    assert_eq!(run("SETNX", &["new", "v"]).await, RedisValue::Integer(1));
+    assert_eq!(run("GET", &["foo"]).await, bulk("baz"));
+    // --- SETNX doesn't touch the TTL of the key it leaves alone
+    assert_eq!(ttl().await, Some(1_000_010));
+
+    assert_eq!(run("GETEX", &["foo", "EX", "100"]).await, bulk("baz"));
+    assert_eq!(ttl().await, Some(1_100_000));
+    assert_eq!(run("GETEX", &["foo"]).await, bulk("baz"));
+    assert_eq!(ttl().await, Some(1_100_000));
+    assert_eq!(run("GETEX", &["foo", "PERSIST"]).await, bulk("baz"));
+    assert_eq!(ttl().await, None);
+    assert_eq!(
+        run("GETEX", &["foo", "EX", "1", "PERSIST"]).await,
+        error("ERR syntax error")
+    );
+    assert_eq!(
+        run("GETEX", &["foo", "PXAT", "-1"]).await,
+        error("ERR invalid expire time in 'getex' command")
+    );
+    assert_eq!(
+        run("GETEX", &["missing", "EX", "1"]).await,
+        RedisValue::NullBulkString
+    );
+
+    assert_eq!(run("GETDEL", &["foo"]).await, bulk("baz"));
+    assert_eq!(run("GET", &["foo"]).await, RedisValue::NullBulkString);
+    assert_eq!(run("GETDEL", &["foo"]).await, RedisValue::NullBulkString);
+
+    run("RPUSH", &["list", "a"]).await;
+    assert_eq!(run("GETDEL", &["list"]).await, wrong_type);
+    assert_eq!(run("GETEX", &["list", "PERSIST"]).await, wrong_type);
+    assert_eq!(run("EXISTS", &["list"]).await, RedisValue::Integer(1));
+}
+
+#[tokio::test]
+async fn counters_increment_decrement_and_keep_their_ttl() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+    assert_replies(
+        &mut client,
+        &[
+            (&["INCR", "hits"], RedisValue::Integer(1)),
+            (&["INCRBY", "hits", "41"], RedisValue::Integer(42)),
+            (&["DECR", "hits"], RedisValue::Integer(41)),
+            (&["DECRBY", "hits", "-9"], RedisValue::Integer(50)),
+            (&["GET", "hits"], bulk("50")),
+            (&["TYPE", "hits"], simple("string")),
+            (&["SET", "n", "-7", "PX", "600000"], simple("OK")),
+            (&["INCR", "n"], RedisValue::Integer(-6)),
+            (&["INCRBYFLOAT", "n", "0.5"], bulk("-5.5")),
+            (&["INCRBYFLOAT", "n", "1e1"], bulk("4.5")),
+            (&["INCRBYFLOAT", "new", "3"], bulk("3")),
+            (&["INCR", "new"], RedisValue::Integer(4)),
+            (&["SET", "text", "12 "], simple("OK")),
+            (
+                &["INCR", "text"],
+                error("ERR value is not an integer or out of range"),
+            ),
+            (&["SET", "text", "012"], simple("OK")),
+            (
+                &["INCR", "text"],
+                error("ERR value is not an integer or out of range"),
+            ),
+            (
+                &["INCRBY", "hits", "many"],
+                error("ERR value is not an integer or out of range"),
+            ),
+            (
+                &["INCRBYFLOAT", "text", "x"],
+                error("ERR value is not a valid float"),
+            ),
+            (&["SET", "max", "9223372036854775807"], simple("OK")),
+            (
+                &["INCR", "max"],
+                error("ERR increment or decrement would overflow"),
+            ),
+            (
+                &["DECRBY", "hits", "-9223372036854775808"],
+                error("ERR decrement would overflow"),
+            ),
+            (&["SET", "huge", "1.7e308"], simple("OK")),
+            (
+                &["INCRBYFLOAT", "huge", "1.7e308"],
+                error("ERR increment would produce NaN or Infinity"),
+            ),
+            (
+                &["INCRBYFLOAT", "huge", "inf"],
+                error("ERR value is not a valid float"),
+            ),
+            (&["JSON.SET", "doc", "$", "{}"], simple("OK")),
+            (
+                &["INCR", "doc"],
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ),
+        ],
+    )
+    .await;
+
+    // --- counters stay counters, and keep the TTL of the key
+    assert_eq!(
+        server.server.databases[0]
+            .main_store
+            .lock()
+            .await
+            .get(&bulk("hits")),
+        Some(&RedisValue::Counter(50))
+    );
+    assert!(server.server.databases[0]
+        .expire_store
+        .lock()
+        .await
+        .contains_key(&bulk("n")));
+}
+
+#[tokio::test]
+async fn unknown_command_replies_with_error() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    let reply = client.command(["NOPE"]).await.unwrap();
+    assert!(matches!(reply, RedisValue::SimpleError(_)));
+
+    // --- connection is still usable afterwards
+    client.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn commands_with_the_wrong_number_of_arguments_are_refused() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["GET"],
+                RedisValue::SimpleError("ERR wrong number of arguments for 'get' command".into()),
+            ),
+            (
+                &["ECHO"],
+                RedisValue::SimpleError("ERR wrong number of arguments for 'echo' command".into()),
+            ),
+            (
+                &["INCRBY", "n"],
+                RedisValue::SimpleError(
+                    "ERR wrong number of arguments for 'incrby' command".into(),
+                ),
+            ),
+            (
+                &["PING", "a", "b"],
+                RedisValue::SimpleError("ERR wrong number of arguments for 'ping' command".into()),
+            ),
+            (
+                &["getrange", "k", "0"],
+                RedisValue::SimpleError(
+                    "ERR wrong number of arguments for 'getrange' command".into(),
+                ),
+            ),
+            // --- nothing was written and the connection is still usable
+            (
+                &["SET", "k"],
+                RedisValue::SimpleError("ERR wrong number of arguments for 'set' command".into()),
+            ),
+            (&["GET", "k"], RedisValue::NullBulkString),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn clients_share_the_store() {
+    let server = TestServer::master().await;
+    let mut writer = server.client().await;
+    let mut reader = server.client().await;
+
+    writer.set("shared", "value").await.unwrap();
+    assert_eq!(
+        reader.get("shared").await.unwrap().as_deref(),
+        Some(&b"value"[..])
+    );
+}
+
+#[tokio::test]
+async fn ping_with_message() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["PING"], simple("PONG")),
+            (&["PING", "hello world"], bulk("hello world")),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn client_no_evict_and_no_touch() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["CLIENT", "NO-EVICT", "on"], simple("OK")),
+            (&["CLIENT", "NO-TOUCH", "ON"], simple("OK")),
+            (&["CLIENT", "NO-TOUCH", "off"], simple("OK")),
+        ],
+    )
+    .await;
+    assert!(matches!(
+        client
+            .command(["CLIENT", "NO-EVICT", "maybe"])
+            .await
+            .unwrap(),
+        RedisValue::SimpleError(_)
+    ));
+}
+
+#[tokio::test]
+async fn client_setinfo_shows_in_client_list_and_info() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["CLIENT", "SETINFO", "lib-name", "redis-py"], simple("OK")),
+            (&["CLIENT", "SETINFO", "LIB-VER", "5.0.1"], simple("OK")),
+            (
+                &["CLIENT", "SETINFO", "lib-name", "redis py"],
+                RedisValue::SimpleError(
+                    "ERR lib-name cannot contain spaces, newlines or special characters.".into(),
+                ),
+            ),
+            (
+                &["CLIENT", "SETINFO", "lib-flavor", "x"],
+                RedisValue::SimpleError("ERR Unrecognized option 'lib-flavor'".into()),
+            ),
+        ],
+    )
+    .await;
+    let RedisValue::BulkString(info) = client.command(["CLIENT", "INFO"]).await.unwrap() else {
+        panic!("CLIENT INFO should reply with a bulk string");
+    };
+    assert!(info.ends_with(b" lib-name=redis-py lib-ver=5.0.1\n"));
+
+    let RedisValue::BulkString(list) = other.command(["CLIENT", "LIST"]).await.unwrap() else {
+        panic!("CLIENT LIST should reply with a bulk string");
+    };
+    let list = String::from_utf8(list.to_vec()).unwrap();
+    let lines = list.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with(" lib-name=redis-py lib-ver=5.0.1"));
+    assert!(lines[1].ends_with(" lib-name= lib-ver="));
+
+    // --- clients leave the list once disconnected
+    drop(client);
+    let list = loop {
+        let RedisValue::BulkString(list) = other.command(["CLIENT", "LIST"]).await.unwrap() else {
+            panic!("CLIENT LIST should reply with a bulk string");
+        };
+        if list.iter().filter(|b| **b == b'\n').count() == 1 {
+            break list;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert!(list.ends_with(b" lib-name= lib-ver=\n"));
+}
+
+#[tokio::test]
+async fn client_setname_labels_and_client_kill_closes_connections() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let mut victim = server.client().await;
+    let mut blocked = server.client().await;
+
+    assert_replies(
+        &mut victim,
+        &[
+            (&["CLIENT", "GETNAME"], RedisValue::NullBulkString),
+            (&["CLIENT", "SETNAME", "victim"], simple("OK")),
+            (&["CLIENT", "GETNAME"], bulk("victim")),
+            (
+                &["CLIENT", "SETNAME", "two words"],
+                RedisValue::SimpleError(
+                    "ERR Client names cannot contain spaces, newlines or special characters."
+                        .into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+    assert_eq!(
+        blocked
+            .command(["HELLO", "2", "SETNAME", "blocked"])
+            .await
+            .unwrap(),
+        blocked.command(["HELLO"]).await.unwrap()
+    );
+    assert_eq!(
+        blocked.command(["CLIENT", "GETNAME"]).await.unwrap(),
+        bulk("blocked")
+    );
+    let RedisValue::Integer(victim_id) = victim.command(["CLIENT", "ID"]).await.unwrap() else {
+        panic!("CLIENT ID should reply with an integer");
+    };
+
+    let RedisValue::BulkString(list) = client.command(["CLIENT", "LIST"]).await.unwrap() else {
+        panic!("CLIENT LIST should reply with a bulk string");
+    };
+    let list = String::from_utf8(list.to_vec()).unwrap();
+    let line = list
+        .lines()
+        .find(|line| line.starts_with(&format!("id={} ", victim_id)))
+        .unwrap();
+    assert!(line.contains(" name=victim "));
+    assert!(line.contains(" cmd=client "));
+    assert!(line.contains(" user=default "));
+
+    // --- the victim is gone for good, the next command finds the connection closed
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["CLIENT", "KILL", "ID", "0", "SKIPME", "maybe"],
+                RedisValue::SimpleError("ERR syntax error".into()),
+            ),
+            (
+                &["CLIENT", "KILL", "1.2.3.4:5"],
+                RedisValue::SimpleError("ERR No such client".into()),
+            ),
+        ],
+    )
+    .await;
+    let killed = client
+        .command([
+            "CLIENT".to_string(),
+            "KILL".to_string(),
+            "ID".to_string(),
+            victim_id.to_string(),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(killed, RedisValue::Integer(1));
+    assert!(victim.command(["PING"]).await.is_err());
+
+    // --- a blocked client is woken up before its connection closes
+    let pop = async { blocked.command(["BLPOP", "list", "0"]).await };
+    let kill = async {
+        while server.server.blocked_clients.is_empty() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        client
+            .command(["CLIENT", "KILL", "USER", "default"])
+            .await
+            .unwrap()
+    };
+    let (popped, killed) = tokio::join!(pop, kill);
+    assert_eq!(killed, RedisValue::Integer(1));
+    assert_eq!(
+        popped.unwrap(),
+        RedisValue::SimpleError("UNBLOCKED client unblocked via CLIENT UNBLOCK".into())
+    );
+    assert!(blocked.command(["PING"]).await.is_err());
+    assert_eq!(client.command(["PING"]).await.unwrap(), simple("PONG"));
+}
+
+#[tokio::test]
+async fn monitor_streams_the_commands_of_other_clients() {
+    let server = TestServer::master().await;
+    let mut monitor = server.client().await;
+    let mut client = server.client().await;
+
+    assert_eq!(monitor.command(["MONITOR"]).await.unwrap(), simple("OK"));
+    client
+        .command(["SET", "foo", "a \"b\"\n\x01"])
+        .await
+        .unwrap();
+    client.command(["AUTH", "secret"]).await.unwrap();
+    client.ping().await.unwrap();
+
+    let mut lines = vec![];
+    for _ in 0..2 {
+        let RedisValue::SimpleString(line) = monitor.read_reply().await.unwrap() else {
+            panic!("MONITOR should stream simple strings");
+        };
+        lines.push(String::from_utf8(line.to_vec()).unwrap());
+    }
+    let (timestamp, rest) = lines[0].split_once(' ').unwrap();
+    assert!(timestamp.parse::<f64>().is_ok());
+    assert!(rest.starts_with("[0 127.0.0.1:"));
+    assert!(rest.ends_with(r#"] "set" "foo" "a \"b\"\n\x01""#));
+    // --- passwords never show
+    assert!(lines[1].ends_with(r#"] "ping""#));
+}
+
+#[tokio::test]
+async fn slowlog_keeps_the_commands_over_the_threshold() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["SET", "foo", "bar"], simple("OK")),
+            (&["SLOWLOG", "LEN"], RedisValue::Integer(0)),
+            (
+                &["CONFIG", "SET", "slowlog-log-slower-than", "0"],
+                simple("OK"),
+            ),
+            (&["CLIENT", "SETNAME", "slow"], simple("OK")),
+            (
+                &["AUTH", "secret"],
+                RedisValue::SimpleError(
+                    "ERR AUTH <password> called without any password configured for the default user. Are you sure your client is configured correctly?"
+                        .into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+    let keys = (0..40).map(|i| format!("key:{i}"));
+    let long = "x".repeat(130);
+    client
+        .command(["DEL".to_string(), long.clone()].into_iter().chain(keys))
+        .await
+        .unwrap();
+
+    let RedisValue::Array(entries) = client.command(["SLOWLOG", "GET", "1"]).await.unwrap() else {
+        panic!("SLOWLOG GET should reply with an array");
+    };
+    let [RedisValue::Array(entry)] = entries.as_slice() else {
+        panic!(
+            "SLOWLOG GET 1 should reply with one entry, not {:?}",
+            entries
+        );
+    };
+    let [RedisValue::Integer(id), RedisValue::Integer(_), RedisValue::Integer(_), RedisValue::Array(args), RedisValue::BulkString(addr), name] =
+        entry.as_slice()
+    else {
+        panic!("unexpected SLOWLOG entry {:?}", entry);
+    };
+    assert_eq!(*id, 2);
+    assert_eq!(args.len(), 32);
+    assert_eq!(args[0], bulk("del"));
+    assert_eq!(
+        args[1],
+        bulk(&format!("{}... (2 more bytes)", "x".repeat(128)))
+    );
+    assert_eq!(args[31], bulk("... (11 more arguments)"));
+    assert!(addr.starts_with(b"127.0.0.1:"));
+    assert_eq!(*name, bulk("slow"));
+
+    // --- CONFIG SET, CLIENT SETNAME, DEL and SLOWLOG GET, AUTH is never logged
+    assert_replies(
+        &mut client,
+        &[
+            (&["SLOWLOG", "LEN"], RedisValue::Integer(4)),
+            (&["CONFIG", "SET", "slowlog-max-len", "2"], simple("OK")),
+            (&["SLOWLOG", "LEN"], RedisValue::Integer(2)),
+            (&["SLOWLOG", "RESET"], simple("OK")),
+            (&["SLOWLOG", "LEN"], RedisValue::Integer(1)),
+            (
+                &["SLOWLOG", "GET", "-2"],
+                RedisValue::SimpleError("ERR count should be greater than or equal to -1".into()),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn debug_digest_ignores_write_order() {
+    let first = TestServer::master().await;
+    let second = TestServer::master().await;
+    let mut first = first.client().await;
+    let mut second = second.client().await;
+
+    let empty = simple(&"0".repeat(40));
+    assert_replies(&mut first, &[(&["DEBUG", "DIGEST"], empty.clone())]).await;
+    for (key, value) in [("a", "1"), ("b", "2")] {
+        first.set(key, value).await.unwrap();
+    }
+    for (key, value) in [("b", "2"), ("a", "1")] {
+        second.set(key, value).await.unwrap();
+    }
+    let digest = first.command(["DEBUG", "DIGEST"]).await.unwrap();
+    assert_ne!(digest, empty);
+    assert_eq!(second.command(["DEBUG", "DIGEST"]).await.unwrap(), digest);
+
+    second.set("a", "3").await.unwrap();
+    assert_ne!(second.command(["DEBUG", "DIGEST"]).await.unwrap(), digest);
+
+    // --- values alone, whatever key holds them
+    first.set("c", "1").await.unwrap();
+    let RedisValue::Array(digests) = first
+        .command(["DEBUG", "DIGEST-VALUE", "a", "c", "b", "missing"])
+        .await
+        .unwrap()
+    else {
+        panic!("DEBUG DIGEST-VALUE should reply with an array");
+    };
+    assert_eq!(digests.len(), 4);
+    assert_eq!(digests[0], digests[1]);
+    assert_ne!(digests[0], digests[2]);
+    assert_eq!(digests[3], empty);
+}
+
+#[tokio::test]
+async fn debug_object_and_sleep() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+
+    client.set("k", "hello").await.unwrap();
+    let RedisValue::SimpleString(object) = client.command(["DEBUG", "OBJECT", "k"]).await.unwrap()
+    else {
+        panic!("DEBUG OBJECT should reply with a status");
+    };
+    let object = String::from_utf8(object.to_vec()).unwrap();
+    assert!(
+        object.starts_with("Value at:0x0 refcount:1 encoding:embstr serializedlength:6 lru:"),
+        "{}",
+        object
+    );
+    assert!(object.ends_with(" lru_seconds_idle:0"), "{}", object);
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["DEBUG", "OBJECT", "missing"],
+                RedisValue::SimpleError("ERR no such key".into()),
+            ),
+            (
+                &["DEBUG", "SLEEP", "-1"],
+                RedisValue::SimpleError("ERR value is not a valid float".into()),
+            ),
+            (
+                &["DEBUG", "QUICKLIST-PACKED-THRESHOLD", "1mb"],
+                simple("OK"),
+            ),
+            (
+                &["DEBUG", "SET-ACTIVE-EXPIRE", "2"],
+                RedisValue::SimpleError("ERR value is not an integer or out of range".into()),
+            ),
+        ],
+    )
+    .await;
+
+    // --- the sleeper alone waits
+    let start = std::time::Instant::now();
+    let sleep = tokio::spawn(async move { client.command(["DEBUG", "SLEEP", "0.3"]).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        other.get("k").await.unwrap().as_deref(),
+        Some(&b"hello"[..])
+    );
+    assert!(start.elapsed() < Duration::from_millis(300));
+    assert_eq!(sleep.await.unwrap().unwrap(), simple("OK"));
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn del_exists_and_type() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["SET", "a", "1"], simple("OK")),
+            (&["SET", "b", "2", "PX", "600000"], simple("OK")),
+            (&["JSON.SET", "doc", "$", "{}"], simple("OK")),
+            (
+                &["EXISTS", "a", "b", "a", "missing"],
+                RedisValue::Integer(3),
+            ),
+            (&["TYPE", "a"], simple("string")),
+            (&["TYPE", "doc"], simple("ReJSON-RL")),
+            (&["TYPE", "missing"], simple("none")),
+            (&["DEL", "a", "b", "missing", "a"], RedisValue::Integer(2)),
+            (&["EXISTS", "a", "b"], RedisValue::Integer(0)),
+            (&["GET", "b"], RedisValue::NullBulkString),
+            (&["DEL", "doc"], RedisValue::Integer(1)),
+            (&["TYPE", "doc"], simple("none")),
+            (
+                &["DEL"],
+                RedisValue::SimpleError("ERR wrong number of arguments for 'del' command".into()),
+            ),
+        ],
+    )
+    .await;
+
+    // --- the TTL goes along with the key
+    assert!(server.server.databases[0]
+        .expire_store
+        .lock()
+        .await
+        .get(&bulk("b"))
+        .is_none());
+}
+
+#[tokio::test]
+async fn lists_push_pop_and_range() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let wrong_type = RedisValue::SimpleError(
+        "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+    );
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["RPUSH", "list", "b", "c"], RedisValue::Integer(2)),
+            (&["LPUSH", "list", "a", "z"], RedisValue::Integer(4)),
+            (&["TYPE", "list"], simple("list")),
+            (&["LLEN", "list"], RedisValue::Integer(4)),
+            (
+                &["LRANGE", "list", "0", "-1"],
+                RedisValue::Array(vec![bulk("z"), bulk("a"), bulk("b"), bulk("c")]),
+            ),
+            (
+                &["LRANGE", "list", "-3", "1"],
+                RedisValue::Array(vec![bulk("a")]),
+            ),
+            (&["LRANGE", "list", "5", "10"], RedisValue::Array(vec![])),
+            (&["LRANGE", "missing", "0", "-1"], RedisValue::Array(vec![])),
+            (&["LPOP", "list"], bulk("z")),
+            (&["RPOP", "list"], bulk("c")),
+            (
+                &["LPOP", "list", "5"],
+                RedisValue::Array(vec![bulk("a"), bulk("b")]),
+            ),
+            // --- the emptied list is gone
+            (&["EXISTS", "list"], RedisValue::Integer(0)),
+            (&["LLEN", "list"], RedisValue::Integer(0)),
+            (&["RPOP", "list"], RedisValue::NullBulkString),
+            (
+                &["LPOP", "list", "-1"],
+                RedisValue::SimpleError("ERR value is out of range, must be positive".into()),
+            ),
+            (
+                &["LRANGE", "list", "a", "1"],
+                RedisValue::SimpleError("ERR value is not an integer or out of range".into()),
+            ),
+            (
+                &["RPUSH", "list"],
+                RedisValue::SimpleError("ERR wrong number of arguments for 'rpush' command".into()),
+            ),
+            (&["SET", "string", "1"], simple("OK")),
+            (&["LPUSH", "string", "a"], wrong_type.clone()),
+            (&["LLEN", "string"], wrong_type.clone()),
+            (&["RPUSH", "other", "a"], RedisValue::Integer(1)),
+            (&["GET", "other"], wrong_type),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn lists_insert_set_remove_and_trim() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let int = RedisValue::Integer;
+    let array = |items: &[&str]| RedisValue::Array(items.iter().map(|item| bulk(item)).collect());
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["RPUSH", "list", "a", "b", "a", "c", "a"], int(5)),
+            (&["LINSERT", "list", "BEFORE", "b", "x"], int(6)),
+            (&["LINSERT", "list", "after", "c", "y"], int(7)),
+            (&["LINSERT", "list", "AFTER", "nope", "z"], int(-1)),
+            (&["LINSERT", "missing", "AFTER", "a", "z"], int(0)),
+            (
+                &["LINSERT", "list", "AROUND", "a", "z"],
+                error("ERR syntax error"),
+            ),
+            (
+                &["LRANGE", "list", "0", "-1"],
+                array(&["a", "x", "b", "a", "c", "y", "a"]),
+            ),
+            (&["LSET", "list", "1", "X"], simple("OK")),
+            (&["LSET", "list", "-1", "A"], simple("OK")),
+            (&["LSET", "list", "7", "z"], error("ERR index out of range")),
+            (
+                &["LSET", "list", "-8", "z"],
+                error("ERR index out of range"),
+            ),
+            (&["LSET", "missing", "0", "z"], error("ERR no such key")),
+            (
+                &["LRANGE", "list", "0", "-1"],
+                array(&["a", "X", "b", "a", "c", "y", "A"]),
+            ),
+            // --- positive counts remove from the head, negative ones from the tail
+            (&["RPUSH", "rem", "a", "b", "a", "c", "a", "d", "a"], int(7)),
+            (&["LREM", "rem", "1", "a"], int(1)),
+            (
+                &["LRANGE", "rem", "0", "-1"],
+                array(&["b", "a", "c", "a", "d", "a"]),
+            ),
+            (&["LREM", "rem", "-2", "a"], int(2)),
+            (&["LRANGE", "rem", "0", "-1"], array(&["b", "a", "c", "d"])),
+            (&["LREM", "rem", "0", "a"], int(1)),
+            (&["LREM", "rem", "0", "nope"], int(0)),
+            (&["LREM", "missing", "0", "a"], int(0)),
+            (&["LREM", "rem", "0", "b"], int(1)),
+            (&["LREM", "rem", "0", "c"], int(1)),
+            (&["LREM", "rem", "0", "d"], int(1)),
+            (&["EXISTS", "rem"], int(0)),
+            (&["LTRIM", "list", "1", "-2"], simple("OK")),
+            (
+                &["LRANGE", "list", "0", "-1"],
+                array(&["X", "b", "a", "c", "y"]),
+            ),
+            (&["LTRIM", "list", "-3", "100"], simple("OK")),
+            (&["LRANGE", "list", "0", "-1"], array(&["a", "c", "y"])),
+            (&["LTRIM", "list", "2", "1"], simple("OK")),
+            (&["EXISTS", "list"], int(0)),
+            (&["LTRIM", "missing", "0", "1"], simple("OK")),
+            (&["SET", "string", "1"], simple("OK")),
+            (
+                &["LSET", "string", "0", "a"],
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn lmpop_pops_from_the_first_non_empty_list() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let pair = |key: &str, items: &[&str]| {
+        RedisValue::Array(vec![
+            bulk(key),
+            RedisValue::Array(items.iter().map(|item| bulk(item)).collect()),
+        ])
+    };
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["RPUSH", "b", "1", "2", "3"], RedisValue::Integer(3)),
+            (&["LMPOP", "2", "a", "b", "LEFT"], pair("b", &["1"])),
+            (
+                &["LMPOP", "2", "a", "b", "RIGHT", "COUNT", "5"],
+                pair("b", &["3", "2"]),
+            ),
+            (&["EXISTS", "b"], RedisValue::Integer(0)),
+            (&["LMPOP", "2", "a", "b", "LEFT"], RedisValue::NullArray),
+            (
+                &["LMPOP", "0", "a", "LEFT"],
+                error("ERR numkeys should be greater than 0"),
+            ),
+            (&["LMPOP", "3", "a", "b", "LEFT"], error("ERR syntax error")),
+            (&["LMPOP", "1", "a", "UP"], error("ERR syntax error")),
+            (
+                &["LMPOP", "1", "a", "LEFT", "COUNT", "0"],
+                error("ERR count should be greater than 0"),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn lpos_finds_elements_by_rank() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let indexes = |indexes: &[i64]| {
+        RedisValue::Array(indexes.iter().copied().map(RedisValue::Integer).collect())
+    };
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["RPUSH", "l", "a", "b", "c", "1", "2", "3", "c", "c"],
+                RedisValue::Integer(8),
+            ),
+            (&["LPOS", "l", "c"], RedisValue::Integer(2)),
+            (&["LPOS", "l", "missing"], RedisValue::NullBulkString),
+            (&["LPOS", "l", "c", "RANK", "2"], RedisValue::Integer(6)),
+            (&["LPOS", "l", "c", "RANK", "-1"], RedisValue::Integer(7)),
+            (&["LPOS", "l", "c", "COUNT", "2"], indexes(&[2, 6])),
+            (&["LPOS", "l", "c", "COUNT", "0"], indexes(&[2, 6, 7])),
+            (
+                &["LPOS", "l", "c", "RANK", "-2", "COUNT", "0"],
+                indexes(&[6, 2]),
+            ),
+            (
+                &["LPOS", "l", "c", "COUNT", "0", "MAXLEN", "3"],
+                indexes(&[2]),
+            ),
+            (
+                &["LPOS", "l", "c", "RANK", "-1", "MAXLEN", "2"],
+                RedisValue::Integer(7),
+            ),
+            (&["LPOS", "l", "c", "RANK", "4"], RedisValue::NullBulkString),
+            (&["LPOS", "missing", "c", "COUNT", "1"], indexes(&[])),
+            (
+                &["LPOS", "l", "c", "RANK", "0"],
+                error(
+                    "ERR RANK can't be zero: use 1 to start from the first match, 2 from the \
+                     second ... or use negative to start from the end of the list",
+                ),
+            ),
+            (
+                &["LPOS", "l", "c", "COUNT", "-1"],
+                error("ERR COUNT can't be negative"),
+            ),
+            (
+                &["LPOS", "l", "c", "MAXLEN", "-1"],
+                error("ERR MAXLEN can't be negative"),
+            ),
+            (&["LPOS", "l", "c", "RANK"], error("ERR syntax error")),
+            (&["LPOS", "l", "c", "FIRST", "1"], error("ERR syntax error")),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn sort_orders_lists_and_sets() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let array = |items: &[&str]| RedisValue::Array(items.iter().map(|item| bulk(item)).collect());
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["RPUSH", "ids", "3", "1", "10", "2"],
+                RedisValue::Integer(4),
+            ),
+            (&["SORT", "ids"], array(&["1", "2", "3", "10"])),
+            (&["SORT", "ids", "DESC"], array(&["10", "3", "2", "1"])),
+            (&["SORT", "ids", "ALPHA"], array(&["1", "10", "2", "3"])),
+            (&["SORT", "ids", "LIMIT", "1", "2"], array(&["2", "3"])),
+            (
+                &["SORT", "ids", "LIMIT", "-5", "-1"],
+                array(&["1", "2", "3", "10"]),
+            ),
+            (&["SORT", "missing"], array(&[])),
+            (&["SADD", "tags", "b", "c", "a"], RedisValue::Integer(3)),
+            (&["SORT", "tags", "ALPHA", "DESC"], array(&["c", "b", "a"])),
+            (
+                &["SORT", "tags"],
+                error("ERR One or more scores can't be converted into double"),
+            ),
+            // --- BY and GET patterns, through strings and hash fields
+            (
+                &["MSET", "weight_1", "30", "weight_2", "20", "weight_3", "10"],
+                simple("OK"),
+            ),
+            (&["HSET", "user:1", "name", "ann"], RedisValue::Integer(1)),
+            (&["HSET", "user:3", "name", "cid"], RedisValue::Integer(1)),
+            (
+                &["SORT", "ids", "BY", "weight_*"],
+                array(&["10", "3", "2", "1"]),
+            ),
+            (
+                &[
+                    "SORT",
+                    "ids",
+                    "BY",
+                    "weight_*",
+                    "GET",
+                    "#",
+                    "GET",
+                    "user:*->name",
+                ],
+                RedisValue::Array(vec![
+                    bulk("10"),
+                    RedisValue::NullBulkString,
+                    bulk("3"),
+                    bulk("cid"),
+                    bulk("2"),
+                    RedisValue::NullBulkString,
+                    bulk("1"),
+                    bulk("ann"),
+                ]),
+            ),
+            (
+                &["SORT", "ids", "BY", "nosort"],
+                array(&["3", "1", "10", "2"]),
+            ),
+            (
+                &[
+                    "SORT",
+                    "ids",
+                    "BY",
+                    "user:*->name",
+                    "ALPHA",
+                    "LIMIT",
+                    "0",
+                    "3",
+                ],
+                array(&["10", "2", "1"]),
+            ),
+            // --- STORE keeps the result as a list, missing values as empty strings
+            (
+                &["SORT", "ids", "GET", "user:*->name", "STORE", "names"],
+                RedisValue::Integer(4),
+            ),
+            (
+                &["LRANGE", "names", "0", "-1"],
+                array(&["ann", "", "cid", ""]),
+            ),
+            (
+                &["SORT", "missing", "STORE", "names"],
+                RedisValue::Integer(0),
+            ),
+            (&["EXISTS", "names"], RedisValue::Integer(0)),
+            (&["SORT_RO", "ids", "LIMIT", "0", "1"], array(&["1"])),
+            (
+                &["SORT_RO", "ids", "STORE", "names"],
+                error("ERR syntax error"),
+            ),
+            (&["SORT", "ids", "LIMIT", "1"], error("ERR syntax error")),
+            (
+                &["SORT", "ids", "LIMIT", "a", "1"],
+                error("ERR value is not an integer or out of range"),
+            ),
+            (
+                &["SORT", "weight_1"],
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn hashes_set_get_and_delete_fields() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["HSET", "hash", "b", "2", "a", "1", "b", "3"],
+                RedisValue::Integer(2),
+            ),
+            (
+                &["HSET", "hash", "a", "0", "c", "4"],
+                RedisValue::Integer(1),
+            ),
+            (&["TYPE", "hash"], simple("hash")),
+            (&["HLEN", "hash"], RedisValue::Integer(3)),
+            (&["HGET", "hash", "a"], bulk("0")),
+            (&["HGET", "hash", "b"], bulk("3")),
+            (&["HGET", "hash", "missing"], RedisValue::NullBulkString),
+            (&["HEXISTS", "hash", "c"], RedisValue::Integer(1)),
+            (&["HEXISTS", "hash", "d"], RedisValue::Integer(0)),
+            (
+                &["HGETALL", "hash"],
+                RedisValue::Array(vec![
+                    bulk("a"),
+                    bulk("0"),
+                    bulk("b"),
+                    bulk("3"),
+                    bulk("c"),
+                    bulk("4"),
+                ]),
+            ),
+            (
+                &["HDEL", "hash", "a", "missing", "b"],
+                RedisValue::Integer(2),
+            ),
+            (&["HDEL", "hash", "c"], RedisValue::Integer(1)),
+            // --- the emptied hash is gone
+            (&["EXISTS", "hash"], RedisValue::Integer(0)),
+            (&["HGETALL", "hash"], RedisValue::Array(vec![])),
+            (&["HLEN", "hash"], RedisValue::Integer(0)),
+            (&["HDEL", "hash", "a"], RedisValue::Integer(0)),
+            (
+                &["HSET", "hash", "a"],
+                RedisValue::SimpleError("ERR wrong number of arguments for 'hset' command".into()),
+            ),
+            (&["SET", "string", "1"], simple("OK")),
+            (
+                &["HGET", "string", "a"],
+                RedisValue::SimpleError(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn sets_membership_and_algebra() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let members = |names: &[&str]| RedisValue::Array(names.iter().map(|name| bulk(name)).collect());
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["SADD", "a", "x", "y", "z", "x"], RedisValue::Integer(3)),
+            (&["SADD", "a", "x", "w"], RedisValue::Integer(1)),
+            (&["SADD", "b", "y", "z", "v"], RedisValue::Integer(3)),
+            (&["TYPE", "a"], simple("set")),
+            (&["SMEMBERS", "a"], members(&["w", "x", "y", "z"])),
+            (&["SISMEMBER", "a", "w"], RedisValue::Integer(1)),
+            (&["SISMEMBER", "b", "w"], RedisValue::Integer(0)),
+            (&["SINTER", "a", "b"], members(&["y", "z"])),
+            (&["SINTER", "a", "b", "missing"], members(&[])),
+            (
+                &["SUNION", "a", "b", "missing"],
+                members(&["v", "w", "x", "y", "z"]),
+            ),
+            (&["SDIFF", "a", "b"], members(&["w", "x"])),
+            (&["SDIFF", "missing", "a"], members(&[])),
+            (&["SREM", "b", "y", "missing"], RedisValue::Integer(1)),
+            (&["SREM", "b", "z", "v"], RedisValue::Integer(2)),
+            // --- the emptied set is gone
+            (&["EXISTS", "b"], RedisValue::Integer(0)),
+            (&["SMEMBERS", "b"], members(&[])),
+            (
+                &["SINTER"],
+                RedisValue::SimpleError(
+                    "ERR wrong number of arguments for 'sinter' command".into(),
+                ),
+            ),
+            (&["SET", "string", "1"], simple("OK")),
+            (
+                &["SUNION", "a", "string"],
+                RedisValue::SimpleError(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn sets_random_members_and_pops() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let all = ["a", "b", "c", "d", "e"];
+    let members = |reply: RedisValue| {
+        let RedisValue::Array(members) = reply else {
+            panic!("Should reply with an array");
+        };
+        members
+            .into_iter()
+            .map(|member| match member {
+                RedisValue::BulkString(member) => String::from_utf8(member.to_vec()).unwrap(),
+                _ => panic!("Members should be bulk strings"),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    client
+        .command(["SADD", "s"].into_iter().chain(all))
+        .await
+        .unwrap();
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["SMISMEMBER", "s", "a", "x", "e"],
+                RedisValue::Array(vec![
+                    RedisValue::Integer(1),
+                    RedisValue::Integer(0),
+                    RedisValue::Integer(1),
+                ]),
+            ),
+            (
+                &["SMISMEMBER", "missing", "a"],
+                RedisValue::Array(vec![RedisValue::Integer(0)]),
+            ),
+            (&["SRANDMEMBER", "missing"], RedisValue::NullBulkString),
+            (&["SRANDMEMBER", "missing", "3"], RedisValue::Array(vec![])),
+            (&["SPOP", "missing"], RedisValue::NullBulkString),
+            (
+                &["SPOP", "s", "-1"],
+                RedisValue::SimpleError("ERR value is out of range, must be positive".into()),
+            ),
+        ],
+    )
+    .await;
+
+    // --- distinct members up to the size of the set, repeated ones for a negative count
+    let drawn = members(client.command(["SRANDMEMBER", "s", "3"]).await.unwrap());
+    assert_eq!(drawn.len(), 3);
+    assert!(drawn.iter().all(|member| all.contains(&member.as_str())));
+    assert_eq!(drawn.iter().collect::<HashSet<_>>().len(), 3);
+    let mut drawn = members(client.command(["SRANDMEMBER", "s", "10"]).await.unwrap());
+    drawn.sort();
+    assert_eq!(drawn, all);
+    let drawn = members(client.command(["SRANDMEMBER", "s", "-20"]).await.unwrap());
+    assert_eq!(drawn.len(), 20);
+    assert!(drawn.iter().all(|member| all.contains(&member.as_str())));
+    // --- repetitions stop at what a request array may hold
+    assert_eq!(
+        client
+            .command(["SRANDMEMBER", "s", "-9223372036854775807"])
+            .await
+            .unwrap(),
+        RedisValue::SimpleError("ERR value is out of range".into())
+    );
+    let drawn = client
+        .command(["SRANDMEMBER", "s", "9223372036854775807"])
+        .await
+        .unwrap();
+    assert_eq!(members(drawn).len(), all.len());
+
+    let RedisValue::BulkString(popped) = client.command(["SPOP", "s"]).await.unwrap() else {
+        panic!("SPOP should reply with a member");
+    };
+    let mut popped = vec![String::from_utf8(popped.to_vec()).unwrap()];
+    popped.extend(members(client.command(["SPOP", "s", "2"]).await.unwrap()));
+    let mut rest = members(client.command(["SMEMBERS", "s"]).await.unwrap());
+    assert_eq!(popped.len(), 3);
+    rest.extend(popped.iter().cloned());
+    rest.sort();
+    assert_eq!(rest, all);
+    assert_eq!(
+        members(
+            client
+                .command(["SPOP", "s", "9223372036854775807"])
+                .await
+                .unwrap()
+        )
+        .len(),
+        2
+    );
+    assert_eq!(
+        client.command(["EXISTS", "s"]).await.unwrap(),
+        RedisValue::Integer(0)
+    );
+
+    // --- replicas remove the members the master drew
+    let ServerContext::Master(ctx) = server.server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    let stream = ctx.backlog.lock().unwrap().range_from(1).unwrap();
+    let srem = RedisValue::Array(vec![bulk("SREM"), bulk("s"), bulk(&popped[0])])
+        .serialize()
+        .unwrap();
+    assert!(stream.windows(srem.len()).any(|window| window == srem));
+    let spop = RedisValue::Array(vec![bulk("SPOP"), bulk("s")])
+        .serialize()
+        .unwrap();
+    assert!(!stream.windows(spop.len()).any(|window| window == spop));
+}
+
+#[tokio::test]
+async fn sorted_sets_by_rank_and_score() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let members = |names: &[&str]| RedisValue::Array(names.iter().map(|name| bulk(name)).collect());
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["ZADD", "board", "3", "c", "1", "a", "2", "b", "2", "bb"],
+                RedisValue::Integer(4),
+            ),
+            (&["TYPE", "board"], simple("zset")),
+            (
+                &["ZRANGE", "board", "0", "-1"],
+                members(&["a", "b", "bb", "c"]),
+            ),
+            (
+                &["ZRANGE", "board", "-2", "-1", "WITHSCORES"],
+                members(&["bb", "2", "c", "3"]),
+            ),
+            (&["ZRANGE", "board", "5", "10"], members(&[])),
+            (&["ZSCORE", "board", "b"], bulk("2")),
+            (&["ZSCORE", "board", "missing"], RedisValue::NullBulkString),
+            (&["ZRANK", "board", "c"], RedisValue::Integer(3)),
+            (&["ZRANK", "board", "missing"], RedisValue::NullBulkString),
+            // --- moves count with CH only, GT keeps the higher score
+            (
+                &["ZADD", "board", "0.5", "c", "4", "d"],
+                RedisValue::Integer(1),
+            ),
+            (
+                &["ZADD", "board", "CH", "GT", "9", "a", "0", "b"],
+                RedisValue::Integer(1),
+            ),
+            (
+                &["ZADD", "board", "XX", "1", "missing"],
+                RedisValue::Integer(0),
+            ),
+            (&["ZADD", "board", "NX", "7", "c"], RedisValue::Integer(0)),
+            (
+                &["ZRANGE", "board", "0", "-1"],
+                members(&["c", "b", "bb", "d", "a"]),
+            ),
+            (
+                &["ZRANGEBYSCORE", "board", "(0.5", "+inf", "WITHSCORES"],
+                members(&["b", "2", "bb", "2", "d", "4", "a", "9"]),
+            ),
+            (
+                &["ZRANGEBYSCORE", "board", "-inf", "4", "LIMIT", "1", "2"],
+                members(&["b", "bb"]),
+            ),
+            (&["ZRANGEBYSCORE", "board", "(2", "(4"], members(&[])),
+            (
+                &["ZRANGEBYSCORE", "board", "a", "4"],
+                RedisValue::SimpleError("ERR min or max is not a float".into()),
+            ),
+            (
+                &["ZADD", "board", "NX", "XX", "1", "a"],
+                RedisValue::SimpleError(
+                    "ERR XX and NX options at the same time are not compatible".into(),
+                ),
+            ),
+            (
+                &["ZADD", "board", "nan", "a"],
+                RedisValue::SimpleError("ERR value is not a valid float".into()),
+            ),
+            (
+                &["ZADD", "board", "1", "a", "2"],
+                RedisValue::SimpleError("ERR syntax error".into()),
+            ),
+            (
+                &["ZREM", "board", "a", "b", "missing"],
+                RedisValue::Integer(2),
+            ),
+            (&["ZREM", "board", "bb", "c", "d"], RedisValue::Integer(3)),
+            // --- the emptied sorted set is gone
+            (&["EXISTS", "board"], RedisValue::Integer(0)),
+            (&["SET", "string", "1"], simple("OK")),
+            (
+                &["ZSCORE", "string", "a"],
+                RedisValue::SimpleError(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn streams_add_range_and_len() {
+    let clock = Arc::new(MockClock::new(1_000));
+    let server = RedisServer::in_memory(clock.clone());
+    let mut session = server.new_session();
+    let mut run = async |cmd: &str, args: &[&'static str]| {
+        let args = args
+            .iter()
+            .map(|arg| bytes::Bytes::from_static(arg.as_bytes()))
+            .collect::<Vec<_>>();
+        let mut ctx = CommandContext {
+            args: &args,
+            server: &server,
+            session: &mut session,
+        };
+        execute(cmd, &mut ctx).await.unwrap()
+    };
+    let error = |message: &str| RedisValue::SimpleError(bytes::Bytes::from(message.to_string()));
+    let smaller_id =
+        error("ERR The ID specified in XADD is equal or smaller than the target stream top item");
+
+    assert_eq!(run("XADD", &["s", "*", "a", "1"]).await, bulk("1000-0"));
+    assert_eq!(run("XADD", &["s", "*", "b", "2"]).await, bulk("1000-1"));
+    // --- a clock going back doesn't take IDs with it
+    clock.set(500);
+    assert_eq!(run("XADD", &["s", "*", "c", "3"]).await, bulk("1000-2"));
+    assert_eq!(
+        run("XADD", &["s", "1000-*", "d", "4"]).await,
+        bulk("1000-3")
+    );
+    assert_eq!(
+        run("XADD", &["s", "2000", "e", "5", "f", "6"]).await,
+        bulk("2000-0")
+    );
+    assert_eq!(run("XADD", &["s", "2000-0", "x", "0"]).await, smaller_id);
+    assert_eq!(run("XADD", &["s", "1500-*", "x", "0"]).await, smaller_id);
+    assert_eq!(
+        run("XADD", &["s", "0-0", "x", "0"]).await,
+        error("ERR The ID specified in XADD must be greater than 0-0")
+    );
+    assert_eq!(
+        run("XADD", &["s", "1-x", "x", "0"]).await,
+        error("ERR Invalid stream ID specified as stream command argument")
+    );
+    assert_eq!(
+        run("XADD", &["s", "*", "x"]).await,
+        error("ERR wrong number of arguments for 'xadd' command")
+    );
+    assert_eq!(run("XADD", &["new", "0-*", "x", "0"]).await, bulk("0-1"));
+    assert_eq!(run("XLEN", &["s"]).await, RedisValue::Integer(5));
+    assert_eq!(run("XLEN", &["missing"]).await, RedisValue::Integer(0));
+    assert_eq!(run("TYPE", &["s"]).await, simple("stream"));
+
+    let entry = |id: &str, fields: &[&str]| {
+        RedisValue::Array(vec![
+            bulk(id),
+            RedisValue::Array(fields.iter().map(|field| bulk(field)).collect()),
+        ])
+    };
+    assert_eq!(
+        run("XRANGE", &["s", "1000-2", "+"]).await,
+        RedisValue::Array(vec![
+            entry("1000-2", &["c", "3"]),
+            entry("1000-3", &["d", "4"]),
+            entry("2000-0", &["e", "5", "f", "6"]),
+        ])
+    );
+    // --- a bare ms spans all of its sequence numbers
+    assert_eq!(
+        run("XRANGE", &["s", "-", "1000", "COUNT", "2"]).await,
+        RedisValue::Array(vec![
+            entry("1000-0", &["a", "1"]),
+            entry("1000-1", &["b", "2"])
+        ])
+    );
+    assert_eq!(
+        run("XRANGE", &["s", "+", "-"]).await,
+        RedisValue::Array(vec![])
+    );
+    assert_eq!(
+        run("XRANGE", &["missing", "-", "+"]).await,
+        RedisValue::Array(vec![])
+    );
+}
+
+#[tokio::test]
+async fn getrange_and_substr() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["SET", "key", "This is a string"], simple("OK")),
+            (&["GETRANGE", "key", "0", "3"], bulk("This")),
+            (&["GETRANGE", "key", "-3", "-1"], bulk("ing")),
+            (&["GETRANGE", "key", "10", "100"], bulk("string")),
+            (&["SUBSTR", "key", "5", "6"], bulk("is")),
+            (&["SUBSTR", "missing", "0", "-1"], bulk("")),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn multi_key_and_substring_writes() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    let error = |message: &str| RedisValue::SimpleError(message.to_string().into());
+    assert_replies(
+        &mut client,
+        &[
+            (&["SET", "a", "old", "PX", "600000"], simple("OK")),
+            (&["RPUSH", "l", "x"], RedisValue::Integer(1)),
+            (&["MSET", "a", "1", "b", "2"], simple("OK")),
+            (&["PTTL", "a"], RedisValue::Integer(-1)),
+            (
+                &["MSET", "a", "1", "b"],
+                error("ERR wrong number of arguments for 'mset' command"),
+            ),
+            (
+                &["MGET", "a", "l", "missing", "b"],
+                RedisValue::Array(vec![
+                    bulk("1"),
+                    RedisValue::NullBulkString,
+                    RedisValue::NullBulkString,
+                    bulk("2"),
+                ]),
+            ),
+            // --- all or nothing
+            (&["MSETNX", "c", "3", "a", "x"], RedisValue::Integer(0)),
+            (&["EXISTS", "c"], RedisValue::Integer(0)),
+            (&["MSETNX", "c", "3", "d", "4"], RedisValue::Integer(1)),
+            (&["APPEND", "c", "45"], RedisValue::Integer(3)),
+            (&["APPEND", "new", "hi"], RedisValue::Integer(2)),
+            (&["INCR", "c"], RedisValue::Integer(346)),
+            (&["APPEND", "c", "!"], RedisValue::Integer(4)),
+            (&["GET", "c"], bulk("346!")),
+            (
+                &["APPEND", "l", "x"],
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ),
+            (&["STRLEN", "c"], RedisValue::Integer(4)),
+            (&["STRLEN", "missing"], RedisValue::Integer(0)),
+            (&["SETRANGE", "c", "1", "XY"], RedisValue::Integer(4)),
+            (&["GET", "c"], bulk("3XY!")),
+            (&["SETRANGE", "pad", "3", "z"], RedisValue::Integer(4)),
+            (&["GET", "pad"], RedisValue::BulkString("\0\0\0z".into())),
+            (&["SETRANGE", "none", "5", ""], RedisValue::Integer(0)),
+            (&["EXISTS", "none"], RedisValue::Integer(0)),
+            (
+                &["SETRANGE", "c", "-1", "x"],
+                error("ERR offset is out of range"),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn oversized_requests_close_the_connection() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        proto_max_bulk_len: Some(16),
+        proto_max_multibulk_len: Some(4),
+        ..Default::default()
+    })
+    .await;
+
+    let mut client = server.client().await;
+    let reply = client
+        .command(["SET", "key", "a value longer than sixteen bytes"])
+        .await;
+    assert_eq!(
+        reply.unwrap(),
+        RedisValue::SimpleError("ERR Protocol error: invalid bulk length".into())
+    );
+    assert!(client.ping().await.is_err());
+
+    let mut client = server.client().await;
+    let reply = client.command(["ECHO", "1", "2", "3", "4"]).await;
+    assert_eq!(
+        reply.unwrap(),
+        RedisValue::SimpleError("ERR Protocol error: invalid multibulk length".into())
+    );
+}
+
+#[tokio::test]
+async fn frames_split_across_reads() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = TestServer::master().await;
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+
+    for chunk in ["*3\r\n$3\r\nSE", "T\r\n$3\r\nfoo\r\n$3", "\r\nbar\r\n"] {
+        stream.write_all(chunk.as_bytes()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let mut reply = [0; 5];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+OK\r\n");
+}
+
+#[tokio::test]
+async fn pipelined_requests_are_all_answered() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    let mut pipeline = client.pipeline();
+    pipeline
+        .cmd(["SET", "a", "1"])
+        .cmd(["GET", "a"])
+        .cmd(["PING"]);
+    let replies = pipeline.execute().await.unwrap();
+
+    assert_eq!(replies, vec![simple("OK"), bulk("1"), simple("PONG")]);
+}
+
+#[tokio::test]
+async fn busy_clients_give_way_once_over_their_budget() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        client_fairness_budget: Some(10),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    let mut pipeline = client.pipeline();
+    for i in 0..25 {
+        pipeline.cmd(["SET".to_string(), format!("key:{i}"), i.to_string()]);
+    }
+    let replies = pipeline.execute().await.unwrap();
+    assert!(replies.iter().all(|reply| *reply == simple("OK")));
+    // --- 5 commands left from the pipeline, then 25 keys scanned
+    let RedisValue::Array(keys) = client.command(["KEYS", "*"]).await.unwrap() else {
+        panic!("KEYS should reply with an array");
+    };
+    assert_eq!(keys.len(), 25);
+
+    let RedisValue::BulkString(info) = client.command(["INFO", "stats"]).await.unwrap() else {
+        panic!("INFO should reply with a bulk string");
+    };
+    let info = String::from_utf8(info.to_vec()).unwrap();
+    assert!(info.contains("client_fairness_yields:3\r\n"));
+}
+
+#[tokio::test]
+async fn info_stats_counts_keyspace_hits_and_misses() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    client.get("missing").await.unwrap();
+    client.set("foo", "bar").await.unwrap();
+    client.get("foo").await.unwrap();
+    client.command(["GETRANGE", "foo", "0", "1"]).await.unwrap();
+
+    let RedisValue::BulkString(info) = client.command(["INFO", "stats"]).await.unwrap() else {
+        panic!("INFO should reply with a bulk string");
+    };
+    let info = String::from_utf8(info.to_vec()).unwrap();
+    assert!(info.starts_with("# Stats\r\n"));
+    assert!(info.contains("keyspace_hits:2\r\n"));
+    assert!(info.contains("keyspace_misses:1\r\n"));
+}
+
+#[tokio::test]
+async fn info_reports_the_sections_asked_for() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let _other = server.client().await;
+
+    let info = |reply| match reply {
+        RedisValue::BulkString(info) => String::from_utf8(Vec::from(info)).unwrap(),
+        other => panic!("INFO should reply with a bulk string, not {:?}", other),
+    };
+    assert_eq!(
+        info(client.command(["INFO", "keyspace"]).await.unwrap()),
+        "# Keyspace\r\n"
+    );
+    client
+        .command(["SET", "foo", "bar", "EX", "100"])
+        .await
+        .unwrap();
+    client.set("baz", "qux").await.unwrap();
+    let keyspace = info(client.command(["INFO", "KEYSPACE"]).await.unwrap());
+    assert!(keyspace.starts_with("# Keyspace\r\ndb0:keys=2,expires=1,avg_ttl="));
+
+    let sections = info(client.command(["INFO", "server", "clients"]).await.unwrap());
+    assert!(sections.starts_with("# Server\r\nredis_version:"));
+    assert!(sections.contains("\r\nuptime_in_seconds:"));
+    assert!(sections.contains("\r\n# Clients\r\nconnected_clients:2\r\n"));
+    assert!(!sections.contains("# Stats"));
+
+    let all = info(client.command(["INFO"]).await.unwrap());
+    let headers = all
+        .lines()
+        .filter(|line| line.starts_with('#'))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        headers,
+        [
+            "# Server",
+            "# Clients",
+            "# Memory",
+            "# Persistence",
+            "# Stats",
+            "# Replication",
+            "# Cluster",
+            "# Keyspace"
+        ]
+    );
+    assert!(all.contains("\r\n# Cluster\r\ncluster_enabled:0\r\n"));
+    assert_eq!(
+        info(client.command(["INFO", "everything"]).await.unwrap())
+            .lines()
+            .filter(|line| line.starts_with('#'))
+            .count(),
+        8
+    );
+    assert_eq!(info(client.command(["INFO", "bogus"]).await.unwrap()), "");
+}
+
+#[tokio::test]
+async fn info_keyspace_averages_ttls_far_in_the_future() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    // --- their sum overflows u64
+    for key in ["far1", "far2", "far3"] {
+        client.set(key, "v").await.unwrap();
+        client
+            .command(["PEXPIREAT", key, "9000000000000000000"])
+            .await
+            .unwrap();
+    }
+    let RedisValue::BulkString(keyspace) = client.command(["INFO", "keyspace"]).await.unwrap()
+    else {
+        panic!("INFO should reply with a bulk string");
+    };
+    let keyspace = String::from_utf8(keyspace.to_vec()).unwrap();
+    let avg_ttl: u64 = keyspace
+        .trim_end()
+        .rsplit_once("avg_ttl=")
+        .unwrap()
+        .1
+        .parse()
+        .unwrap();
+    assert!(avg_ttl > 8_000_000_000_000_000_000, "{}", keyspace);
+}
+
+#[tokio::test]
+async fn info_stats_counts_connections_commands_and_traffic() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let _other = server.client().await;
+
+    client.ping().await.unwrap();
+    client.set("foo", "bar").await.unwrap();
+
+    let RedisValue::BulkString(info) = client.command(["INFO", "stats"]).await.unwrap() else {
+        panic!("INFO should reply with a bulk string");
+    };
+    let info = String::from_utf8(info.to_vec()).unwrap();
+    assert!(info.contains("total_connections_received:2\r\n"));
+    // --- INFO itself only counts once it ran
+    assert!(info.contains("total_commands_processed:2\r\n"));
+    // --- 14 bytes of PING, 31 of SET and 25 of the INFO request
+    assert!(info.contains("total_net_input_bytes:70\r\n"));
+    // --- "+PONG\r\n" and "+OK\r\n", the INFO reply isn't written yet
+    assert!(info.contains("total_net_output_bytes:12\r\n"));
+    assert!(info.contains("evicted_keys:0\r\n"));
+}
+
+#[test]
+fn instantaneous_ops_average_recent_samples() {
+    let stats = ServerStats::default();
+    stats.sample_ops(1_000);
+    assert_eq!(stats.instantaneous_ops_per_sec(), 0);
+
+    for _ in 0..50 {
+        stats.record_command();
+    }
+    // --- too soon after the previous sample, skipped
+    stats.sample_ops(1_050);
+    assert_eq!(stats.instantaneous_ops_per_sec(), 0);
+    stats.sample_ops(1_100);
+    assert_eq!(stats.instantaneous_ops_per_sec(), 500);
+    stats.sample_ops(1_200);
+    assert_eq!(stats.instantaneous_ops_per_sec(), 250);
+}
+
+#[tokio::test]
+async fn object_freq_tracks_accesses() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        maxmemory_policy: Some("allkeys-lfu".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    // --- the first access past the initial counter always registers
+    assert_replies(
+        &mut client,
+        &[
+            (&["SET", "foo", "bar"], simple("OK")),
+            (&["OBJECT", "FREQ", "foo"], RedisValue::Integer(5)),
+            (&["GET", "foo"], bulk("bar")),
+            (&["OBJECT", "FREQ", "foo"], RedisValue::Integer(6)),
+            (&["OBJECT", "FREQ", "missing"], RedisValue::NullBulkString),
+        ],
+    )
+    .await;
+
+    // --- NO-TOUCH reads leave the counter alone
+    let mut client = server.client().await;
+    client.command(["CLIENT", "NO-TOUCH", "on"]).await.unwrap();
+    for _ in 0..10 {
+        client.get("foo").await.unwrap();
+    }
+    assert_eq!(
+        client.command(["OBJECT", "FREQ", "foo"]).await.unwrap(),
+        RedisValue::Integer(6)
+    );
+}
+
+#[tokio::test]
+async fn object_freq_requires_an_lfu_policy() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    client.set("foo", "bar").await.unwrap();
+    assert!(matches!(
+        client.command(["OBJECT", "FREQ", "foo"]).await.unwrap(),
+        RedisValue::SimpleError(_)
+    ));
+}
+
+#[tokio::test]
+async fn object_and_memory_usage_introspect_values() {
+    let clock = Arc::new(MockClock::new(1_000_000));
+    let server = RedisServer::in_memory(clock.clone());
+    let mut session = server.new_session();
+    let mut run = async |cmd: &str, args: &[&'static str]| {
+        let args = args
+            .iter()
+            .map(|arg| bytes::Bytes::from_static(arg.as_bytes()))
+            .collect::<Vec<_>>();
+        let mut ctx = CommandContext {
+            args: &args,
+            server: &server,
+            session: &mut session,
+        };
+        execute(cmd, &mut ctx).await.unwrap()
+    };
+
+    run("SET", &["counter", "12345"]).await;
+    run("SET", &["short", "bar"]).await;
+    run("SET", &["long", "x".repeat(45).leak()]).await;
+    run("RPUSH", &["list", "a", "b"]).await;
+    // --- past the 8kb of the default list-max-listpack-size
+    let item: &'static str = "x".repeat(64).leak();
+    run("RPUSH", &[&["big"][..], &[item; 129][..]].concat()).await;
+    run("SADD", &["ints", "1", "2"]).await;
+    run("SADD", &["members", "1", "x"]).await;
+    run("HSET", &["hash", "f", "v"]).await;
+    run("ZADD", &["zset", "1", "m"]).await;
+    for (key, encoding) in [
+        ("counter", "int"),
+        ("short", "embstr"),
+        ("long", "raw"),
+        ("list", "listpack"),
+        ("big", "quicklist"),
+        ("ints", "intset"),
+        ("members", "listpack"),
+        ("hash", "listpack"),
+        ("zset", "listpack"),
+    ] {
+        let key: &'static str = key;
+        assert_eq!(
+            run("OBJECT", &["ENCODING", key]).await,
+            bulk(encoding),
+            "encoding of {}",
+            key
+        );
+    }
+    assert_eq!(
+        run("OBJECT", &["ENCODING", "missing"]).await,
+        RedisValue::NullBulkString
+    );
+    assert_eq!(
+        run("OBJECT", &["REFCOUNT", "short"]).await,
+        RedisValue::Integer(1)
+    );
+
+    // --- idle since the last access, reads only
+    clock.set(1_005_000);
+    assert_eq!(
+        run("OBJECT", &["IDLETIME", "short"]).await,
+        RedisValue::Integer(5)
+    );
+    run("GET", &["short"]).await;
+    assert_eq!(
+        run("OBJECT", &["IDLETIME", "short"]).await,
+        RedisValue::Integer(0)
+    );
+
+    let RedisValue::Integer(short) = run("MEMORY", &["USAGE", "short"]).await else {
+        panic!("MEMORY USAGE should reply with an integer");
+    };
+    let RedisValue::Integer(long) = run("MEMORY", &["USAGE", "long", "SAMPLES", "5"]).await else {
+        panic!("MEMORY USAGE should reply with an integer");
+    };
+    // --- 42 more bytes of value, one less of key
+    assert_eq!(long - short, 41);
+    assert_eq!(
+        run("MEMORY", &["USAGE", "missing"]).await,
+        RedisValue::NullBulkString
+    );
+    assert_eq!(
+        run("MEMORY", &["USAGE", "short", "SAMPLES"]).await,
+        RedisValue::SimpleError(bytes::Bytes::from_static(b"ERR syntax error"))
+    );
+}
+
+#[tokio::test]
+async fn sets_leaving_the_intset_encoding_still_find_their_members() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    // --- by value -5, 2, 10, 300 but "-5", "10", "2", "300" as bytes
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["SADD", "s", "2", "10", "-5", "300"],
+                RedisValue::Integer(4),
+            ),
+            (&["OBJECT", "ENCODING", "s"], bulk("intset")),
+            (&["SADD", "s", "a"], RedisValue::Integer(1)),
+            (&["OBJECT", "ENCODING", "s"], bulk("listpack")),
+            (&["SISMEMBER", "s", "2"], RedisValue::Integer(1)),
+            (&["SISMEMBER", "s", "-5"], RedisValue::Integer(1)),
+            (&["SISMEMBER", "s", "300"], RedisValue::Integer(1)),
+            (&["SADD", "s", "10", "-5"], RedisValue::Integer(0)),
+            (&["SREM", "s", "300"], RedisValue::Integer(1)),
+            (
+                &["SMEMBERS", "s"],
+                RedisValue::Array(vec![bulk("-5"), bulk("10"), bulk("2"), bulk("a")]),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn small_collections_outgrow_their_compact_encodings() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let encoding = |key: &'static str| ["OBJECT", "ENCODING", key];
+    // --- one byte over the default *-max-listpack-value
+    let long: &'static str = "x".repeat(65).leak();
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["SADD", "s", "1", "-2", "3"], RedisValue::Integer(3)),
+            (&encoding("s"), bulk("intset")),
+            // --- not written the way Redis writes integers
+            (&["SADD", "s", "007"], RedisValue::Integer(1)),
+            (&encoding("s"), bulk("listpack")),
+            (
+                &["SMEMBERS", "s"],
+                RedisValue::Array(vec![bulk("-2"), bulk("007"), bulk("1"), bulk("3")]),
+            ),
+            (&["SADD", "s", long], RedisValue::Integer(1)),
+            (&encoding("s"), bulk("hashtable")),
+            // --- never back to a compact encoding
+            (&["SREM", "s", long], RedisValue::Integer(1)),
+            (&encoding("s"), bulk("hashtable")),
+            (&["HSET", "h", "f", "v"], RedisValue::Integer(1)),
+            (&encoding("h"), bulk("listpack")),
+            (&["HSET", "h", "f", long], RedisValue::Integer(0)),
+            (&encoding("h"), bulk("hashtable")),
+            (&["HSET", "h", "f", "v"], RedisValue::Integer(0)),
+            (&encoding("h"), bulk("hashtable")),
+            (&["HGET", "h", "f"], bulk("v")),
+        ],
+    )
+    .await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &[
+                    "CONFIG",
+                    "SET",
+                    "hash-max-ziplist-entries",
+                    "2",
+                    "set-max-intset-entries",
+                    "2",
+                    "list-max-listpack-size",
+                    "2",
+                ],
+                simple("OK"),
+            ),
+            (
+                &["CONFIG", "GET", "hash-max-listpack-entries"],
+                RedisValue::Array(vec![bulk("hash-max-listpack-entries"), bulk("2")]),
+            ),
+            (
+                &["HSET", "small", "a", "1", "b", "2"],
+                RedisValue::Integer(2),
+            ),
+            (&encoding("small"), bulk("listpack")),
+            (&["HSET", "small", "c", "3"], RedisValue::Integer(1)),
+            (&encoding("small"), bulk("hashtable")),
+            (&["SADD", "ints", "1", "2", "3"], RedisValue::Integer(3)),
+            (&encoding("ints"), bulk("hashtable")),
+            (&["RPUSH", "list", "a", "b"], RedisValue::Integer(2)),
+            (&encoding("list"), bulk("listpack")),
+            (&["RPUSH", "list", "c"], RedisValue::Integer(3)),
+            (&encoding("list"), bulk("quicklist")),
+            // --- like the others, a list doesn't go back once shrunk
+            (&["RPOP", "list"], bulk("c")),
+            (&encoding("list"), bulk("quicklist")),
+            // --- nor do existing ones change with the limits until written to
+            (&["RPUSH", "other", "a", "b"], RedisValue::Integer(2)),
+            (
+                &["CONFIG", "SET", "list-max-listpack-size", "1"],
+                simple("OK"),
+            ),
+            (&encoding("other"), bulk("listpack")),
+            (&["LSET", "other", "0", "x"], simple("OK")),
+            (&encoding("other"), bulk("quicklist")),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn writes_fail_over_maxmemory_with_noeviction() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        maxmemory: Some("1kb".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    let value = "x".repeat(2048);
+    client.set("big", value.clone()).await.unwrap();
+
+    for cmd in [&["SET", "foo", "bar"][..], &["COPY", "big", "copy"]] {
+        assert_eq!(
+            client.command(cmd.iter().copied()).await.unwrap(),
+            RedisValue::SimpleError(
+                "OOM command not allowed when used memory > 'maxmemory'.".into()
+            )
+        );
+    }
+    // --- reads keep working
+    assert_eq!(
+        client.get("big").await.unwrap().as_deref(),
+        Some(value.as_bytes())
+    );
+}
+
+#[tokio::test]
+async fn allkeys_lru_evicts_the_least_recently_used_key() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        maxmemory: Some("1kb".to_string()),
+        maxmemory_policy: Some("allkeys-lru".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    let value = "x".repeat(200);
+    for key in ["a", "b", "c"] {
+        client.set(key, value.clone()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    client.get("a").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    // --- the write goes through, the next command makes room
+    client.set("d", "x".repeat(300)).await.unwrap();
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["EXISTS", "b"], RedisValue::Integer(0)),
+            (&["EXISTS", "a", "c", "d"], RedisValue::Integer(3)),
+        ],
+    )
+    .await;
+    assert_eq!(server.server.stats.evicted_keys(), 1);
+}
+
+#[tokio::test]
+async fn a_database_over_its_quota_evicts_only_its_own_keys() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        maxmemory_db: Some("1 1kb".to_string()),
+        maxmemory_policy: Some("allkeys-lru".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    // --- database 0 has no quota, however much it holds
+    for key in ["big0", "big1", "big2"] {
+        client.set(key, "x".repeat(2048)).await.unwrap();
+    }
+    client.command(["SELECT", "1"]).await.unwrap();
+    for key in ["a", "b", "c"] {
+        client.set(key, "x".repeat(250)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    // --- the write goes through, the next command makes room
+    client.set("d", "x".repeat(250)).await.unwrap();
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["EXISTS", "a"], RedisValue::Integer(0)),
+            (&["EXISTS", "b", "c", "d"], RedisValue::Integer(3)),
+            (&["SELECT", "0"], RedisValue::SimpleString("OK".into())),
+            (&["EXISTS", "big0", "big1", "big2"], RedisValue::Integer(3)),
+        ],
+    )
+    .await;
+    assert_eq!(server.server.stats.evicted_keys(), 1);
+}
+
+#[tokio::test]
+async fn overwriting_hash_fields_keeps_a_database_under_its_quota() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        maxmemory_db: Some("1 4kb".to_string()),
+        maxmemory_policy: Some("allkeys-lru".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    client.command(["SELECT", "1"]).await.unwrap();
+    client.set("other", "x".repeat(100)).await.unwrap();
+    // --- each write replaces the same 100 bytes, the database's share stays flat
+    let value: &'static str = "x".repeat(100).leak();
+    for _ in 0..200 {
+        client.command(["HSET", "h", "field", value]).await.unwrap();
+    }
+
+    assert_replies(
+        &mut client,
+        &[(&["EXISTS", "other", "h"], RedisValue::Integer(2))],
+    )
+    .await;
+    assert_eq!(server.server.stats.evicted_keys(), 0);
+}
+
+#[tokio::test]
+async fn writes_to_a_database_over_its_quota_fail_with_noeviction() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["CONFIG", "SET", "maxmemory-db", "2 1kb"],
+                RedisValue::SimpleString("OK".into()),
+            ),
+            (
+                &["CONFIG", "GET", "maxmemory-db"],
+                RedisValue::Array(vec![
+                    RedisValue::BulkString("maxmemory-db".into()),
+                    RedisValue::BulkString("2 1024".into()),
+                ]),
+            ),
+            (&["SELECT", "2"], RedisValue::SimpleString("OK".into())),
+        ],
+    )
+    .await;
+    client.set("big", "x".repeat(2048)).await.unwrap();
+    assert_eq!(
+        client.command(["SET", "foo", "bar"]).await.unwrap(),
+        RedisValue::SimpleError("OOM command not allowed when used memory > 'maxmemory'.".into())
+    );
+    // --- MOVE takes the key's size along, leaving room behind
+    assert_replies(
+        &mut client,
+        &[
+            (&["MOVE", "big", "3"], RedisValue::Integer(1)),
+            (
+                &["SET", "foo", "bar"],
+                RedisValue::SimpleString("OK".into()),
+            ),
+            (&["SELECT", "3"], RedisValue::SimpleString("OK".into())),
+            (
+                &["SET", "bar", "baz"],
+                RedisValue::SimpleString("OK".into()),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn volatile_ttl_evicts_the_keys_expiring_first_and_only_those() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        maxmemory: Some("1kb".to_string()),
+        maxmemory_policy: Some("volatile-ttl".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    let value = "x".repeat(150);
+    client.set("keep", "x".repeat(500)).await.unwrap();
+    for (key, ttl) in [("soon", "100000"), ("later", "200000"), ("last", "300000")] {
+        client
+            .command(["SET", key, &value, "PX", ttl].map(str::to_string))
+            .await
+            .unwrap();
+    }
+    assert_replies(
+        &mut client,
+        &[
+            (&["EXISTS", "soon"], RedisValue::Integer(0)),
+            (&["EXISTS", "keep", "later", "last"], RedisValue::Integer(3)),
+        ],
+    )
+    .await;
+    client.set("big", "x".repeat(600)).await.unwrap();
+
+    // --- once the keys with a TTL are gone there is nothing left to evict
+    assert_eq!(
+        client.command(["SET", "foo", "bar"]).await.unwrap(),
+        RedisValue::SimpleError("OOM command not allowed when used memory > 'maxmemory'.".into())
+    );
+    assert_replies(
+        &mut client,
+        &[
+            (&["EXISTS", "later", "last"], RedisValue::Integer(0)),
+            (&["EXISTS", "keep", "big"], RedisValue::Integer(2)),
+        ],
+    )
+    .await;
+    assert_eq!(server.server.stats.evicted_keys(), 3);
+}
+
+#[tokio::test]
+async fn config_get_tcp_keepalive() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        tcp_keepalive: Some(60),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[(
+            &["CONFIG", "GET", "tcp-keepalive"],
+            RedisValue::Array(vec![bulk("tcp-keepalive"), bulk("60")]),
+        )],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn socket_options_set_keepalive_and_nodelay() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    let options = SocketOptions {
+        keepalive: 60,
+        nodelay: true,
+    };
+    options.apply(&stream).unwrap();
+    let socket = socket2::SockRef::from(&stream);
+    assert!(stream.nodelay().unwrap());
+    assert!(socket.keepalive().unwrap());
+    assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(60));
+    assert_eq!(
+        socket.keepalive_interval().unwrap(),
+        Duration::from_secs(20)
+    );
+
+    let options = SocketOptions {
+        keepalive: 0,
+        nodelay: false,
+    };
+    options.apply(&stream).unwrap();
+    assert!(!stream.nodelay().unwrap());
+    assert!(!socket.keepalive().unwrap());
+}
+
+#[tokio::test]
+async fn clients_over_the_per_ip_limit_are_refused() {
+    use tokio::io::AsyncReadExt;
+
+    let server = TestServer::start(Args {
+        port: Some(0),
+        max_clients_per_ip: Some(2),
+        ..Default::default()
+    })
+    .await;
+    let mut first = server.client().await;
+    let mut second = server.client().await;
+    assert_replies(&mut first, &[(&["PING"], simple("PONG"))]).await;
+    assert_replies(&mut second, &[(&["PING"], simple("PONG"))]).await;
+
+    let mut refused = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    let mut reply = String::new();
+    refused.read_to_string(&mut reply).await.unwrap();
+    assert_eq!(reply, "-ERR max number of clients from this IP reached\r\n");
+    assert_eq!(server.server.stats.rejected_connections(), 1);
+
+    // --- a closed connection frees its slot
+    drop(first);
+    let mut third = loop {
+        let mut client = server.client().await;
+        if client.ping().await.is_ok() {
+            break client;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_replies(&mut third, &[(&["PING"], simple("PONG"))]).await;
+}
+
+#[tokio::test]
+async fn clients_over_maxclients_are_refused() {
+    use tokio::io::AsyncReadExt;
+
+    let server = TestServer::start(Args {
+        port: Some(0),
+        maxclients: Some(1),
+        ..Default::default()
+    })
+    .await;
+    let mut first = server.client().await;
+    assert_replies(&mut first, &[(&["PING"], simple("PONG"))]).await;
+
+    let mut refused = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    let mut reply = String::new();
+    refused.read_to_string(&mut reply).await.unwrap();
+    assert_eq!(reply, "-ERR max number of clients reached\r\n");
+    assert_replies(
+        &mut first,
+        &[(
+            &["CONFIG", "GET", "maxclients"],
+            RedisValue::Array(vec![bulk("maxclients"), bulk("1")]),
+        )],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn idle_clients_are_disconnected_after_the_timeout() {
+    use tokio::io::AsyncReadExt;
+
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    assert_replies(
+        &mut client,
+        &[(&["CONFIG", "SET", "timeout", "1"], simple("OK"))],
+    )
+    .await;
+
+    let mut idle = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    let mut subscriber = server.client().await;
+    subscriber.command(["SUBSCRIBE", "news"]).await.unwrap();
+    let started = std::time::Instant::now();
+    let mut rest = vec![];
+    assert_eq!(idle.read_to_end(&mut rest).await.unwrap(), 0);
+    assert!(started.elapsed() >= Duration::from_millis(900));
+
+    // --- subscribers wait for messages, they aren't idle
+    let mut publisher = server.client().await;
+    assert_eq!(
+        publisher.command(["PUBLISH", "news", "hi"]).await.unwrap(),
+        RedisValue::Integer(1)
+    );
+}
+
+#[tokio::test]
+async fn query_buffers_over_the_limit_close_the_connection() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = TestServer::start(Args {
+        port: Some(0),
+        client_query_buffer_limit: Some(1024),
+        ..Default::default()
+    })
+    .await;
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    // --- a bulk string announced within proto-max-bulk-len, never finished
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$100000\r\n")
+        .await
+        .unwrap();
+    let _ = stream.write_all(&[b'x'; 4096]).await;
+
+    let mut reply = vec![];
+    let _ = stream.read_to_end(&mut reply).await;
+    assert!(reply.starts_with(b"-ERR Protocol error"));
+}
+
+#[test]
+fn connection_rate_is_limited_per_ip_and_second() {
+    let limiter = Arc::new(ConnectionLimiter::new(ConnectionLimits {
+        max_total: 0,
+        max_clients: 0,
+        max_rate: 2,
+    }));
+    let ip = "10.0.0.1".parse().unwrap();
+    let other = "10.0.0.2".parse().unwrap();
+
+    let _first = limiter.admit(ip, 1_000).unwrap();
+    let _second = limiter.admit(ip, 1_500).unwrap();
+    assert_eq!(
+        limiter.admit(ip, 1_999).unwrap_err(),
+        ConnectionRefusal::TooFast
+    );
+    assert!(limiter.admit(other, 1_999).is_ok());
+    assert!(limiter.admit(ip, 2_000).is_ok());
+}
+
+#[tokio::test]
+async fn clients_connect_over_a_unix_socket() {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let path = std::env::temp_dir().join(format!("redis-rust-{}.sock", std::process::id()));
+    // --- a leftover socket file from an earlier run is replaced
+    std::fs::write(&path, b"").unwrap();
+    let server = TestServer::start(Args {
+        port: Some(0),
+        unixsocket: Some(path.to_string_lossy().into_owned()),
+        unixsocketperm: Some("700".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o700);
+
+    let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    stream
+        .write_all(b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n")
+        .await
+        .unwrap();
+    let mut reply = [0; 5];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+OK\r\n");
+
+    // --- same dataset as TCP clients
+    let mut client = server.client().await;
+    assert_replies(
+        &mut client,
+        &[
+            (&["GET", "foo"], bulk("bar")),
+            (
+                &["CONFIG", "GET", "unixsocket*"],
+                RedisValue::Array(vec![
+                    bulk("unixsocket"),
+                    bulk(&path.to_string_lossy()),
+                    bulk("unixsocketperm"),
+                    bulk("700"),
+                ]),
+            ),
+        ],
+    )
+    .await;
+    drop(server);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn optional_bind_addresses_may_fail() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        // --- TEST-NET-1 is not a local address, the leading dash lets it fail
+        bind: vec!["127.0.0.1".to_string(), "-192.0.2.1".to_string()],
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+    assert_replies(
+        &mut client,
+        &[(
+            &["CONFIG", "GET", "bind"],
+            RedisValue::Array(vec![bulk("bind"), bulk("127.0.0.1 -192.0.2.1")]),
+        )],
+    )
+    .await;
+
+    let required = RedisServer::init(Args {
+        port: Some(0),
+        bind: vec!["127.0.0.1".to_string(), "192.0.2.1".to_string()],
+        ..Default::default()
+    })
+    .await;
+    assert!(required.is_err());
+}
+
+#[tokio::test]
+async fn replies_over_the_output_buffer_limit_drop_the_client() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        client_output_buffer_limit: Some("normal 1kb 0 0".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    client.set("big", "x".repeat(2048)).await.unwrap();
+    client.set("small", "bar").await.unwrap();
+    assert_eq!(client.command(["GET", "small"]).await.unwrap(), bulk("bar"));
+    assert!(client.command(["GET", "big"]).await.is_err());
+
+    let mut client = server.client().await;
+    let RedisValue::BulkString(info) = client.command(["INFO", "stats"]).await.unwrap() else {
+        panic!("INFO replies with a bulk string");
+    };
+    let info = String::from_utf8(info.to_vec()).unwrap();
+    assert!(info.contains("client_output_buffer_limit_disconnections:1\r\n"));
+    assert_replies(
+        &mut client,
+        &[(
+            &["CONFIG", "GET", "client-output-buffer-limit"],
+            RedisValue::Array(vec![
+                bulk("client-output-buffer-limit"),
+                bulk("normal 1024 0 0 replica 268435456 67108864 60 pubsub 33554432 8388608 60"),
+            ]),
+        )],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn renamed_and_disabled_commands() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        rename_command: ["config", "hidden-config", "DEBUG", ""]
+            .map(String::from)
+            .to_vec(),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    // --- the old names are as unknown as any made up command
+    for (cmd, error) in [
+        (
+            ["CONFIG", "GET", "dir"],
+            "ERR unknown command 'config', with args beginning with: 'GET' 'dir' ",
+        ),
+        (
+            ["config", "GET", "dir"],
+            "ERR unknown command 'config', with args beginning with: 'GET' 'dir' ",
+        ),
+        (
+            ["DEBUG", "CHANGE-REPL-ID", ""],
+            "ERR unknown command 'debug', with args beginning with: 'CHANGE-REPL-ID' '' ",
+        ),
+    ] {
+        assert_eq!(
+            client.command(cmd).await.unwrap(),
+            RedisValue::SimpleError(error.into())
+        );
+    }
+    for name in ["HIDDEN-CONFIG", "hidden-config"] {
+        assert_eq!(
+            client.command([name, "GET", "dbfilename"]).await.unwrap(),
+            RedisValue::Array(vec![bulk("dbfilename"), bulk("dump.rdb")])
+        );
+    }
+    // --- and spoil a transaction like one
+    assert_replies(
+        &mut client,
+        &[
+            (&["MULTI"], simple("OK")),
+            (
+                &["CONFIG", "GET", "dir"],
+                RedisValue::SimpleError(
+                    "ERR unknown command 'config', with args beginning with: 'GET' 'dir' ".into(),
+                ),
+            ),
+            (
+                &["EXEC"],
+                RedisValue::SimpleError(
+                    "EXECABORT Transaction discarded because of previous errors.".into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+    // --- everything else dispatches as usual
+    client.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn malformed_requests_get_protocol_errors() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = TestServer::master().await;
+    let cases: [(&[u8], &str); 7] = [
+        (b"*1\r\n$abc\r\n", "invalid bulk length"),
+        (b"*x\r\n", "invalid multibulk length"),
+        (b"*-5\r\n", "invalid multibulk length"),
+        (b"*1\r\n:1\r\n", "expected '$', got ':'"),
+        (b"+PING\r\n", "expected '*', got '+'"),
+        (b"SET \"abc 1\r\n", "unbalanced quotes in request"),
+        (b"SET 'a'b 1\r\n", "unbalanced quotes in request"),
+    ];
+
+    for (request, error) in cases {
+        let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(
+            String::from_utf8(reply).unwrap(),
+            format!("-ERR Protocol error: {}\r\n", error)
+        );
+    }
+
+    // --- empty requests are skipped without closing the connection
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    stream
+        .write_all(b"*0\r\n*1\r\n$4\r\nPING\r\n")
+        .await
+        .unwrap();
+    let mut reply = [0; 7];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+
+    // --- inline commands, e.g. typed in through telnet, are served like the others
+    stream.write_all(b"ECHO  hey\r\n").await.unwrap();
+    let mut reply = [0; 9];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"$3\r\nhey\r\n");
+    exchange(&mut stream, b"SET \"a b\" \"c d\"\r\n", b"+OK\r\n").await;
+    exchange(&mut stream, b"GET 'a b'\r\n", b"$3\r\nc d\r\n").await;
+
+    // --- so are names that aren't UTF-8, as unknown commands
+    stream
+        .write_all(b"*1\r\n$2\r\n\xff\xfe\r\n*1\r\n$4\r\nPING\r\n")
+        .await
+        .unwrap();
+    let mut reply = Vec::new();
+    while !reply.ends_with(b"+PONG\r\n") {
+        let mut chunk = [0; 64];
+        let read = stream.read(&mut chunk).await.unwrap();
+        assert!(read > 0, "Connection closed after {:?}", reply);
+        reply.extend_from_slice(&chunk[..read]);
+    }
+    assert!(reply.starts_with(b"-"));
+}
+
+/// Sends a raw request and checks the raw reply
+async fn exchange(stream: &mut tokio::net::TcpStream, request: &[u8], expected: &[u8]) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    stream.write_all(request).await.unwrap();
+    let mut reply = vec![0; expected.len()];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(
+        reply.escape_ascii().to_string(),
+        expected.escape_ascii().to_string()
+    );
+}
+
+#[tokio::test]
+async fn hello_switches_the_connection_to_resp3() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    assert_eq!(
+        client.command(["HELLO", "4"]).await.unwrap(),
+        RedisValue::SimpleError("NOPROTO unsupported protocol version".into())
+    );
+    // --- RESP2 gets the map as a flat array
+    let RedisValue::Array(fields) = client.command(["HELLO"]).await.unwrap() else {
+        panic!("HELLO should reply with an array in RESP2");
+    };
+    assert_eq!(fields[..2], [bulk("server"), bulk("redis")]);
+    assert_eq!(fields[4..6], [bulk("proto"), RedisValue::Integer(2)]);
+
+    let mut stream = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    stream.write_all(b"HELLO 3\r\n").await.unwrap();
+    let mut reply = vec![];
+    while !reply.ends_with(b"$7\r\nmodules\r\n*0\r\n") {
+        let mut chunk = [0; 64];
+        let read = stream.read(&mut chunk).await.unwrap();
+        assert!(read > 0, "Connection closed after {:?}", reply);
+        reply.extend_from_slice(&chunk[..read]);
+    }
+    assert!(reply.starts_with(b"%7\r\n$6\r\nserver\r\n$5\r\nredis\r\n"));
+
+    exchange(&mut stream, b"HSET h f v\r\n", b":1\r\n").await;
+    exchange(
+        &mut stream,
+        b"HGETALL h\r\n",
+        b"%1\r\n$1\r\nf\r\n$1\r\nv\r\n",
+    )
+    .await;
+    exchange(&mut stream, b"GET missing\r\n", b"_\r\n").await;
+    exchange(&mut stream, b"ZADD z 1.5 m\r\n", b":1\r\n").await;
+    exchange(&mut stream, b"ZSCORE z m\r\n", b",1.5\r\n").await;
+    // --- members come paired with their scores
+    exchange(&mut stream, b"ZADD z 2 n\r\n", b":1\r\n").await;
+    exchange(
+        &mut stream,
+        b"ZRANGE z 0 -1 WITHSCORES\r\n",
+        b"*2\r\n*2\r\n$1\r\nm\r\n,1.5\r\n*2\r\n$1\r\nn\r\n,2\r\n",
+    )
+    .await;
+    exchange(
+        &mut stream,
+        b"ZRANGEBYSCORE z 2 +inf WITHSCORES\r\n",
+        b"*1\r\n*2\r\n$1\r\nn\r\n,2\r\n",
+    )
+    .await;
+    exchange(&mut stream, b"ZRANGE z 0 0\r\n", b"*1\r\n$1\r\nm\r\n").await;
+    // --- subscribed RESP3 connections get pushes and may still run any command
+    exchange(
+        &mut stream,
+        b"SUBSCRIBE news\r\n",
+        b">3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n",
+    )
+    .await;
+    client.command(["PUBLISH", "news", "hi"]).await.unwrap();
+    exchange(
+        &mut stream,
+        b"GET missing\r\n",
+        b">3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n_\r\n",
+    )
+    .await;
+    exchange(&mut stream, b"HELLO 2\r\n", b"*14\r\n").await;
+}
+
+#[tokio::test]
+async fn subscribe_mode_only_allows_pub_sub_commands() {
+    let server = RedisServer::in_memory(Arc::new(SystemClock));
+    let mut session = Session {
+        channels: [bytes::Bytes::from_static(b"news")].into(),
+        ..Default::default()
+    };
+
+    let args = [bytes::Bytes::from_static(b"foo")];
+    let mut ctx = CommandContext {
+        args: &args,
+        server: &server,
+        session: &mut session,
+    };
+    assert_eq!(
+        execute("get", &mut ctx).await.unwrap(),
+        RedisValue::SimpleError(
+            "ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context".into()
+        )
+    );
+    assert_eq!(
+        execute("PING", &mut ctx).await.unwrap(),
+        RedisValue::Array(vec![bulk("pong"), bulk("foo")])
+    );
+}
+
+/// Stream that counts the writes the server makes on it
+struct CountingStream {
+    inner: tokio::io::DuplexStream,
+    writes: Arc<std::sync::atomic::AtomicUsize>,
+}
+impl tokio::io::AsyncRead for CountingStream {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+impl tokio::io::AsyncWrite for CountingStream {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        self.writes
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        std::pin::Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[tokio::test]
+async fn pipelined_replies_go_out_in_a_single_write() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = RedisServer::in_memory(Arc::new(SystemClock));
+    let (mut client, inner) = tokio::io::duplex(64 * 1024);
+    let writes = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let stream = CountingStream {
+        inner,
+        writes: writes.clone(),
+    };
+    tokio::spawn(redis_rust::server::server::handle_connection(
+        stream, server,
+    ));
+
+    let pipeline = (0..10)
+        .map(|i| format!("*2\r\n$4\r\nECHO\r\n${}\r\n{}\r\n", i.to_string().len(), i))
+        .collect::<String>();
+    client.write_all(pipeline.as_bytes()).await.unwrap();
+
+    let expected = (0..10)
+        .map(|i| format!("${}\r\n{}\r\n", i.to_string().len(), i))
+        .collect::<String>();
+    let mut replies = vec![0; expected.len()];
+    client.read_exact(&mut replies).await.unwrap();
+    assert_eq!(String::from_utf8(replies).unwrap(), expected);
+    assert_eq!(writes.load(std::sync::atomic::Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn acl_log_groups_denials_and_resets() {
+    let clock = Arc::new(MockClock::new(1_000_000));
+    let server = RedisServer::in_memory(clock.clone());
+    let mut session = server.new_session();
+
+    server
+        .acl_log
+        .record(clock.now(), AclDenial::Auth, "AUTH", "alice", &session);
+    clock.advance(Duration::from_secs(1));
+    server
+        .acl_log
+        .record(clock.now(), AclDenial::Auth, "AUTH", "alice", &session);
+    server
+        .acl_log
+        .record(clock.now(), AclDenial::Key, "secret", "bob", &session);
+
+    let mut ctx = CommandContext {
+        args: &[bytes::Bytes::from_static(b"LOG")],
+        server: &server,
+        session: &mut session,
+    };
+    let RedisValue::Array(entries) = execute("ACL", &mut ctx).await.unwrap() else {
+        panic!("ACL LOG should reply with an array");
+    };
+    // --- newest first, the repeated failure counted in the first entry
+    assert_eq!(entries.len(), 2);
+    let RedisValue::Array(newest) = &entries[0] else {
+        panic!("Entries should be arrays");
+    };
+    assert_eq!(
+        newest[..10],
+        [
+            bulk("count"),
+            RedisValue::Integer(1),
+            bulk("reason"),
+            bulk("key"),
+            bulk("context"),
+            bulk("toplevel"),
+            bulk("object"),
+            bulk("secret"),
+            bulk("username"),
+            bulk("bob"),
+        ]
+    );
+    let RedisValue::Array(oldest) = &entries[1] else {
+        panic!("Entries should be arrays");
+    };
+    assert_eq!(oldest[1], RedisValue::Integer(2));
+    assert_eq!(oldest[11], bulk("1.000"));
+    assert_eq!(oldest[13], bulk("id=1"));
+    assert_eq!(oldest[17], RedisValue::Integer(1_000_000));
+    assert_eq!(oldest[19], RedisValue::Integer(1_001_000));
+
+    let args = [
+        bytes::Bytes::from_static(b"LOG"),
+        bytes::Bytes::from_static(b"1"),
+    ];
+    ctx.args = &args;
+    let RedisValue::Array(entries) = execute("ACL", &mut ctx).await.unwrap() else {
+        panic!("ACL LOG should reply with an array");
+    };
+    assert_eq!(entries.len(), 1);
+
+    let args = [
+        bytes::Bytes::from_static(b"LOG"),
+        bytes::Bytes::from_static(b"reset"),
+    ];
+    ctx.args = &args;
+    assert_eq!(execute("ACL", &mut ctx).await.unwrap(), simple("OK"));
+    ctx.args = &args[..1];
+    assert_eq!(
+        execute("ACL", &mut ctx).await.unwrap(),
+        RedisValue::Array(vec![])
+    );
+}
+
+#[tokio::test]
+async fn requirepass_makes_clients_authenticate_first() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        requirepass: Some("secret".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["GET", "k"],
+                RedisValue::SimpleError("NOAUTH Authentication required.".into()),
+            ),
+            (
+                &["AUTH", "wrong"],
+                RedisValue::SimpleError(
+                    "WRONGPASS invalid username-password pair or user is disabled.".into(),
+                ),
+            ),
+            (&["AUTH", "secret"], simple("OK")),
+            (&["SET", "k", "v"], simple("OK")),
+            (&["ACL", "WHOAMI"], bulk("default")),
+            (
+                &["CONFIG", "GET", "requirepass"],
+                RedisValue::Array(vec![bulk("requirepass"), bulk("secret")]),
+            ),
+        ],
+    )
+    .await;
+    let RedisValue::Array(entries) = client.command(["ACL", "LOG"]).await.unwrap() else {
+        panic!("ACL LOG should reply with an array");
+    };
+    let RedisValue::Array(failure) = &entries[0] else {
+        panic!("Entries should be arrays");
+    };
+    assert_eq!(
+        failure[2..10],
+        [
+            bulk("reason"),
+            bulk("auth"),
+            bulk("context"),
+            bulk("toplevel"),
+            bulk("object"),
+            bulk("AUTH"),
+            bulk("username"),
+            bulk("default"),
+        ]
+    );
+
+    // --- HELLO only goes through when it authenticates on the way
+    let mut other = server.client().await;
+    assert!(matches!(
+        other.command(["HELLO", "2"]).await.unwrap(),
+        RedisValue::SimpleError(e) if e.starts_with(b"NOAUTH")
+    ));
+    assert!(matches!(
+        other
+            .command(["HELLO", "2", "AUTH", "default", "secret"])
+            .await
+            .unwrap(),
+        RedisValue::Array(_)
+    ));
+    assert_eq!(other.get("k").await.unwrap(), Some("v".into()));
+}
+
+#[tokio::test]
+async fn acl_users_only_run_the_commands_they_are_allowed() {
+    let server = TestServer::master().await;
+    let mut admin = server.client().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut admin,
+        &[
+            (
+                &["ACL", "SETUSER", "alice", "on", ">pw", "+@read", "+set"],
+                simple("OK"),
+            ),
+            (
+                &["ACL", "SETUSER", "bob", "on", ">pw", "+@bogus"],
+                RedisValue::SimpleError(
+                    "ERR Error in ACL SETUSER modifier '+@bogus': Unknown command or category name in ACL"
+                        .into(),
+                ),
+            ),
+            (&["ACL", "GETUSER", "bob"], RedisValue::NullBulkString),
+            (
+                &["ACL", "LIST"],
+                RedisValue::Array(vec![
+                    bulk("user alice on #1a91d62f7ca67399625a4368a6ab5d4a3baa6073 ~* &* -@all +@read +set"),
+                    bulk("user default on nopass ~* &* +@all"),
+                ]),
+            ),
+            (
+                &["ACL", "GETUSER", "alice"],
+                RedisValue::Array(vec![
+                    bulk("flags"),
+                    RedisValue::Array(vec![bulk("on")]),
+                    bulk("passwords"),
+                    RedisValue::Array(vec![bulk("1a91d62f7ca67399625a4368a6ab5d4a3baa6073")]),
+                    bulk("commands"),
+                    bulk("-@all +@read +set"),
+                    bulk("keys"),
+                    bulk("~*"),
+                    bulk("channels"),
+                    bulk("&*"),
+                ]),
+            ),
+        ],
+    )
+    .await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["AUTH", "alice", "nope"],
+                RedisValue::SimpleError(
+                    "WRONGPASS invalid username-password pair or user is disabled.".into(),
+                ),
+            ),
+            (&["AUTH", "alice", "pw"], simple("OK")),
+            (&["SET", "k", "v"], simple("OK")),
+            (&["GET", "k"], bulk("v")),
+            (
+                &["DEL", "k"],
+                RedisValue::SimpleError(
+                    "NOPERM User alice has no permissions to run the 'del' command".into(),
+                ),
+            ),
+            (
+                &["CONFIG", "GET", "dir"],
+                RedisValue::SimpleError(
+                    "NOPERM User alice has no permissions to run the 'config' command".into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+
+    // --- later rules override earlier ones, and apply to connections already in
+    assert_replies(
+        &mut admin,
+        &[(&["ACL", "SETUSER", "alice", "-set", "+del"], simple("OK"))],
+    )
+    .await;
+    assert_replies(
+        &mut client,
+        &[
+            (&["DEL", "k"], RedisValue::Integer(1)),
+            (
+                &["SET", "k", "v"],
+                RedisValue::SimpleError(
+                    "NOPERM User alice has no permissions to run the 'set' command".into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+    let RedisValue::Array(entries) = admin.command(["ACL", "LOG", "1"]).await.unwrap() else {
+        panic!("ACL LOG should reply with an array");
+    };
+    let RedisValue::Array(denial) = &entries[0] else {
+        panic!("Entries should be arrays");
+    };
+    assert_eq!(denial[3], bulk("command"));
+    assert_eq!(denial[7], bulk("set"));
+    assert_eq!(denial[9], bulk("alice"));
+}
+
+#[tokio::test]
+async fn json_documents_are_read_and_updated_by_path() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["JSON.SET", "doc", "$", r#"{"a":1,"b":{"c":[1,2,3]}}"#],
+                simple("OK"),
+            ),
+            (&["JSON.GET", "doc", "$.a"], bulk("[1]")),
+            (&["JSON.GET", "doc", ".b.c[-1]"], bulk("3")),
+            (&["JSON.GET", "doc", "$.b.c[*]"], bulk("[1,2,3]")),
+            (
+                &["JSON.GET", "doc", "a", "$.b['c'][0]"],
+                bulk(r#"{"$.b['c'][0]":[1],"a":1}"#),
+            ),
+            (
+                &["JSON.GET", "doc", ".missing"],
+                RedisValue::SimpleError("ERR Path '.missing' does not exist".into()),
+            ),
+            (&["JSON.SET", "doc", "$.b.d", r#""new""#], simple("OK")),
+            (
+                &["JSON.SET", "doc", "$.a", "2", "NX"],
+                RedisValue::NullBulkString,
+            ),
+            (&["JSON.SET", "doc", "$.a", "2", "XX"], simple("OK")),
+            (
+                &["JSON.SET", "doc", "$.x.y", "1"],
+                RedisValue::NullBulkString,
+            ),
+            (&["JSON.DEL", "doc", "$.b.c[0]"], RedisValue::Integer(1)),
+            (
+                &["JSON.GET", "doc"],
+                bulk(r#"{"a":2,"b":{"c":[2,3],"d":"new"}}"#),
+            ),
+            (&["JSON.DEL", "doc", "$.b.c[*]"], RedisValue::Integer(2)),
+            (&["JSON.GET", "doc", "$.b.c"], bulk("[[]]")),
+            (&["JSON.DEL", "doc"], RedisValue::Integer(1)),
+            (&["JSON.GET", "doc"], RedisValue::NullBulkString),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn json_commands_check_types_and_paths() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let wrong_type = RedisValue::SimpleError(
+        "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+    );
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["JSON.SET", "doc", "$.a", "1"],
+                RedisValue::SimpleError("ERR new objects must be created at the root".into()),
+            ),
+            (
+                &["JSON.SET", "doc", "$", "[1, 2"],
+                RedisValue::SimpleError("ERR EOF while parsing a list at line 1 column 5".into()),
+            ),
+            (&["JSON.SET", "doc", "$", "{}"], simple("OK")),
+            (
+                &["JSON.GET", "doc", "$..a"],
+                RedisValue::SimpleError("ERR recursive descent is not supported in '$..a'".into()),
+            ),
+            (&["GET", "doc"], wrong_type.clone()),
+            (&["GETRANGE", "doc", "0", "1"], wrong_type.clone()),
+            (&["SET", "str", "bar"], simple("OK")),
+            (&["JSON.GET", "str"], wrong_type.clone()),
+            (&["JSON.SET", "str", "$", "1"], wrong_type.clone()),
+            (&["JSON.DEL", "str"], wrong_type),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn command_describes_the_command_table() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let strings = |values: &[&str]| RedisValue::Array(values.iter().map(|s| simple(s)).collect());
+    let info = |name: &str, arity: i64, flags: &[&str], keys: [i64; 3], categories: &[&str]| {
+        RedisValue::Array(vec![
+            bulk(name),
+            RedisValue::Integer(arity),
+            strings(flags),
+            RedisValue::Integer(keys[0]),
+            RedisValue::Integer(keys[1]),
+            RedisValue::Integer(keys[2]),
+            strings(categories),
+            RedisValue::Array(vec![]),
+            RedisValue::Array(vec![]),
+            RedisValue::Array(vec![]),
+        ])
+    };
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["COMMAND", "INFO", "get", "MSET", "ping", "nosuch"],
+                RedisValue::Array(vec![
+                    info("get", 2, &["readonly"], [1, 1, 1], &["@read"]),
+                    info("mset", -3, &["write", "denyoom"], [1, -1, 2], &["@write"]),
+                    info(
+                        "ping",
+                        -1,
+                        &["loading", "stale"],
+                        [0, 0, 0],
+                        &["@connection"],
+                    ),
+                    RedisValue::NullBulkString,
+                ]),
+            ),
+            (
+                &["COMMAND", "DOCS", "lpos", "nosuch"],
+                RedisValue::Array(vec![
+                    bulk("lpos"),
+                    RedisValue::Array(vec![bulk("group"), bulk("list")]),
+                ]),
+            ),
+            (
+                &["COMMAND", "NOSUCH"],
+                RedisValue::SimpleError("ERR unknown subcommand 'NOSUCH'".into()),
+            ),
+        ],
+    )
+    .await;
+
+    let RedisValue::Array(commands) = client.command(["COMMAND"]).await.unwrap() else {
+        panic!("COMMAND should reply with an array");
+    };
+    assert_eq!(
+        client.command(["COMMAND", "COUNT"]).await.unwrap(),
+        RedisValue::Integer(commands.len() as i64)
+    );
+    // --- extensions are only listed when enabled
+    let names = commands
+        .iter()
+        .map(|command| match command {
+            RedisValue::Array(info) => info[0].clone(),
+            _ => panic!("Commands should be described by arrays"),
+        })
+        .collect::<Vec<_>>();
+    assert!(names.contains(&bulk("command")));
+    assert!(!names.contains(&bulk("delifeq")));
+    let RedisValue::Array(docs) = client.command(["COMMAND", "DOCS"]).await.unwrap() else {
+        panic!("COMMAND DOCS should reply with a map");
+    };
+    assert_eq!(docs.len(), 2 * commands.len());
+}
+
+#[tokio::test]
+async fn lolwut_draws_and_tells_the_version() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    let RedisValue::BulkString(art) = client.command(["LOLWUT", "10", "2", "3"]).await.unwrap()
+    else {
+        panic!("LOLWUT should reply with a bulk string");
+    };
+    let art = String::from_utf8(art.to_vec()).unwrap();
+    assert!(art.ends_with(&format!(
+        "Georg Nees - schotter, plotter on paper, 1968. Redis ver. {}\n",
+        env!("CARGO_PKG_VERSION")
+    )));
+    // --- 10 columns of braille, 3 squares of 8 pixels plus padding down
+    let drawing = art
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    assert_eq!(drawing.len(), 7);
+    assert!(drawing.iter().all(|line| line.chars().count() == 10));
+
+    // --- the default sizes, whatever the version asked for
+    for cmd in [&["LOLWUT"][..], &["LOLWUT", "VERSION", "5"][..]] {
+        let RedisValue::BulkString(art) = client.command(cmd.iter().copied()).await.unwrap() else {
+            panic!("LOLWUT should reply with a bulk string");
+        };
+        let art = String::from_utf8(art.to_vec()).unwrap();
+        let drawing = art
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        assert!(drawing.iter().all(|line| line.chars().count() == 66));
+    }
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["LOLWUT", "VERSION", "x"],
+                RedisValue::SimpleError("ERR value is not an integer or out of range".into()),
+            ),
+            (
+                &["LOLWUT", "1", "1", "1", "1"],
+                RedisValue::SimpleError("ERR syntax error".into()),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn cluster_mode_owns_every_slot() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        cluster_enabled: true,
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+    let port = RedisValue::Integer(server.addr.port() as i64);
+
+    let RedisValue::BulkString(id) = client.command(["CLUSTER", "MYID"]).await.unwrap() else {
+        panic!("CLUSTER MYID should reply with a bulk string");
+    };
+    assert_eq!(id.len(), 40);
+    assert!(id.iter().all(u8::is_ascii_hexdigit));
+    let id = String::from_utf8(id.to_vec()).unwrap();
+    let RedisValue::BulkString(info) = client.command(["CLUSTER", "INFO"]).await.unwrap() else {
+        panic!("CLUSTER INFO should reply with a bulk string");
+    };
+    assert!(info.starts_with(b"cluster_state:ok\r\ncluster_slots_assigned:16384\r\n"));
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["CLUSTER", "SLOTS"],
+                RedisValue::Array(vec![RedisValue::Array(vec![
+                    RedisValue::Integer(0),
+                    RedisValue::Integer(16383),
+                    RedisValue::Array(vec![
+                        bulk("127.0.0.1"),
+                        port.clone(),
+                        bulk(&id),
+                        // --- no hostname to tell about
+                        RedisValue::Array(vec![]),
+                    ]),
+                ])]),
+            ),
+            (&["CLUSTER", "KEYSLOT", "foo"], RedisValue::Integer(12182)),
+            (
+                &["CLUSTER", "KEYSLOT", "123456789"],
+                RedisValue::Integer(12739),
+            ),
+            // --- only the hash tag counts, when there is a non-empty one
+            (
+                &["CLUSTER", "KEYSLOT", "{user1000}.following"],
+                RedisValue::Integer(3443),
+            ),
+            (
+                &["CLUSTER", "KEYSLOT", "{user1000}.followers"],
+                RedisValue::Integer(3443),
+            ),
+            (
+                &["CLUSTER", "KEYSLOT", "foo{}{bar}"],
+                RedisValue::Integer(8363),
+            ),
+            (
+                &["CLUSTER", "KEYSLOT", "foo{{bar}}zap"],
+                RedisValue::Integer(4015),
+            ),
+            (
+                &["CLUSTER", "KEYSLOT"],
+                RedisValue::SimpleError(
+                    "ERR wrong number of arguments for 'cluster|keyslot' command".into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+
+    let RedisValue::Array(shards) = client.command(["CLUSTER", "SHARDS"]).await.unwrap() else {
+        panic!("CLUSTER SHARDS should reply with an array");
+    };
+    assert_eq!(
+        shards,
+        vec![RedisValue::Array(vec![
+            bulk("slots"),
+            RedisValue::Array(vec![RedisValue::Integer(0), RedisValue::Integer(16383)]),
+            bulk("nodes"),
+            RedisValue::Array(vec![RedisValue::Array(vec![
+                bulk("id"),
+                bulk(&id),
+                bulk("port"),
+                port,
+                bulk("ip"),
+                bulk("127.0.0.1"),
+                bulk("endpoint"),
+                bulk("127.0.0.1"),
+                bulk("role"),
+                bulk("master"),
+                bulk("replication-offset"),
+                RedisValue::Integer(0),
+                bulk("health"),
+                bulk("online"),
+            ])]),
+        ])]
+    );
+
+    // --- a server started without the flag knows nothing of clusters
+    let standalone = TestServer::master().await;
+    assert_replies(
+        &mut standalone.client().await,
+        &[(
+            &["CLUSTER", "INFO"],
+            RedisValue::SimpleError("ERR This instance has cluster support disabled".into()),
+        )],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn compare_and_set_extensions() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        enable_extensions: true,
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["SET", "lock", "a", "IFEQ", "b"],
+                RedisValue::NullBulkString,
+            ),
+            (&["GET", "lock"], RedisValue::NullBulkString),
+            (&["SET", "lock", "a"], simple("OK")),
+            (
+                &["SET", "lock", "b", "IFEQ", "x"],
+                RedisValue::NullBulkString,
+            ),
+            (
+                &["SET", "lock", "b", "IFEQ", "a", "PX", "10000"],
+                simple("OK"),
+            ),
+            (&["GET", "lock"], bulk("b")),
+            (&["DELIFEQ", "lock", "a"], RedisValue::Integer(0)),
+            (&["DELIFEQ", "lock", "b"], RedisValue::Integer(1)),
+            (&["GET", "lock"], RedisValue::NullBulkString),
+            (&["DELIFEQ", "lock", "b"], RedisValue::Integer(0)),
+            (&["JSON.SET", "doc", "$", "1"], simple("OK")),
+            (
+                &["SET", "doc", "a", "IFEQ", "1"],
+                RedisValue::SimpleError(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+                ),
+            ),
+            (
+                &["DELIFEQ", "doc", "1"],
+                RedisValue::SimpleError(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn extensions_are_off_by_default() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["SET", "lock", "a", "IFEQ", "b"],
+                RedisValue::SimpleError("ERR syntax error".into()),
+            ),
+            (
+                &["DELIFEQ", "lock", "a"],
+                RedisValue::SimpleError(
+                    "ERR unknown command 'delifeq', with args beginning with: 'lock' 'a' ".into(),
+                ),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn memory_analyze_finds_big_and_hot_keys() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    client.set("medium", "m".repeat(100)).await.unwrap();
+    client.set("big", "b".repeat(1000)).await.unwrap();
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["MEMORY", "BIGKEYS"],
+                RedisValue::SimpleError("ERR no key analysis yet, run MEMORY ANALYZE first".into()),
+            ),
+            (&["SET", "small", "a"], simple("OK")),
+            (&["JSON.SET", "doc", "$", r#"{"a":1}"#], simple("OK")),
+            (&["GET", "small"], bulk("a")),
+            (
+                &["MEMORY", "ANALYZE", "COUNT", "2"],
+                simple("Key analysis started"),
+            ),
+        ],
+    )
+    .await;
+
+    let bigkeys = loop {
+        match client.command(["MEMORY", "BIGKEYS"]).await.unwrap() {
+            RedisValue::Array(bigkeys) => break bigkeys,
+            _ => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+    let ranking = bigkeys
+        .iter()
+        .map(|entry| {
+            let RedisValue::Array(entry) = entry else {
+                panic!("Each big key should be an array");
+            };
+            (entry[0].clone(), entry[1].clone())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ranking,
+        [
+            (bulk("ReJSON-RL"), bulk("doc")),
+            (bulk("string"), bulk("big")),
+            (bulk("string"), bulk("medium")),
+        ]
+    );
+
+    // --- the only key read so far is the hottest
+    let RedisValue::Array(hotkeys) = client.command(["MEMORY", "HOTKEYS"]).await.unwrap() else {
+        panic!("MEMORY HOTKEYS should reply with an array");
+    };
+    assert_eq!(hotkeys.len(), 2);
+    assert_eq!(
+        hotkeys[0],
+        RedisValue::Array(vec![bulk("small"), RedisValue::Integer(6)])
+    );
+}
+
+#[tokio::test]
+async fn expire_ttl_and_persist() {
+    let clock = Arc::new(MockClock::new(10_000));
+    let server = RedisServer::in_memory(clock.clone());
+    let mut session = server.new_session();
+    let mut run = async |cmd: &str, args: &[&'static str]| {
+        let args = args
+            .iter()
+            .map(|arg| bytes::Bytes::from_static(arg.as_bytes()))
+            .collect::<Vec<_>>();
+        let mut ctx = CommandContext {
+            args: &args,
+            server: &server,
+            session: &mut session,
+        };
+        execute(cmd, &mut ctx).await.unwrap()
+    };
+    let int = RedisValue::Integer;
+
+    assert_eq!(run("TTL", &["k"]).await, int(-2));
+    assert_eq!(run("EXPIRE", &["k", "10"]).await, int(0));
+    run("SET", &["k", "v"]).await;
+    assert_eq!(run("TTL", &["k"]).await, int(-1));
+    assert_eq!(run("PERSIST", &["k"]).await, int(0));
+    // --- GT never beats a key without a TTL, LT always does
+    assert_eq!(run("EXPIRE", &["k", "10", "GT"]).await, int(0));
+    assert_eq!(run("EXPIRE", &["k", "10", "XX"]).await, int(0));
+    assert_eq!(run("EXPIRE", &["k", "10", "LT"]).await, int(1));
+    assert_eq!(run("PTTL", &["k"]).await, int(10_000));
+    assert_eq!(run("EXPIRE", &["k", "20", "NX"]).await, int(0));
+    assert_eq!(run("PEXPIRE", &["k", "5000", "GT"]).await, int(0));
+    assert_eq!(run("PEXPIRE", &["k", "5400", "LT"]).await, int(1));
+    // --- seconds are rounded to the closest
+    clock.set(12_000);
+    assert_eq!(run("TTL", &["k"]).await, int(3));
+    assert_eq!(run("EXPIREAT", &["k", "100"]).await, int(1));
+    assert_eq!(run("PEXPIREAT", &["k", "20000"]).await, int(1));
+    assert_eq!(run("PTTL", &["k"]).await, int(8_000));
+    assert_eq!(run("PERSIST", &["k"]).await, int(1));
+    assert_eq!(run("TTL", &["k"]).await, int(-1));
+    assert!(server.databases[0].expire_store.lock().await.is_empty());
+
+    // --- a deadline already past deletes the key
+    assert_eq!(run("PEXPIREAT", &["k", "12000"]).await, int(1));
+    assert_eq!(run("EXISTS", &["k"]).await, int(0));
+    run("SET", &["k", "v", "PX", "100"]).await;
+    clock.set(12_200);
+    assert_eq!(run("PTTL", &["k"]).await, int(-2));
+    assert!(server.databases[0].expire_store.lock().await.is_empty());
+
+    run("SET", &["k", "v"]).await;
+    assert_eq!(
+        run("EXPIRE", &["k", "10", "NX", "GT"]).await,
+        RedisValue::SimpleError(bytes::Bytes::from_static(
+            b"ERR NX and XX, GT or LT options at the same time are not compatible"
+        ))
+    );
+    assert_eq!(
+        run("EXPIRE", &["k", "10", "GT", "LT"]).await,
+        RedisValue::SimpleError(bytes::Bytes::from_static(
+            b"ERR GT and LT options at the same time are not compatible"
+        ))
+    );
+    assert_eq!(
+        run("EXPIRE", &["k", "ten"]).await,
+        RedisValue::SimpleError(bytes::Bytes::from_static(
+            b"ERR value is not an integer or out of range"
+        ))
+    );
+    assert_eq!(
+        run("EXPIRE", &["k", "9223372036854775807"]).await,
+        RedisValue::SimpleError(bytes::Bytes::from_static(
+            b"ERR invalid expire time in 'expire' command"
+        ))
+    );
+}
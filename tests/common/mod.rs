@@ -0,0 +1,185 @@
+#![allow(dead_code)]
+
+use std::{net::SocketAddr, sync::Arc};
+
+use bytes::Bytes;
+use redis_rust::{client::RedisClient, server::server::RedisServer, Args, RedisValue};
+use tokio::task::JoinHandle;
+
+/// Server booted in-process on an ephemeral port, stopped when dropped
+pub struct TestServer {
+    pub server: Arc<RedisServer>,
+    pub addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+impl TestServer {
+    pub async fn master() -> Self {
+        Self::start(Args {
+            port: Some(0),
+            ..Default::default()
+        })
+        .await
+    }
+
+    pub async fn replica_of(master: &TestServer) -> Self {
+        Self::start(Args {
+            port: Some(0),
+            replicaof: Some(format!("{} {}", master.addr.ip(), master.addr.port())),
+            ..Default::default()
+        })
+        .await
+    }
+
+    pub async fn start(args: Args) -> Self {
+        let server = RedisServer::init(args)
+            .await
+            .expect("Failure initializing test server");
+        let addr = server
+            .local_addr()
+            .expect("Test server should listen on TCP");
+        let handle = tokio::spawn(Arc::clone(&server).run());
+
+        Self {
+            server,
+            addr,
+            handle,
+        }
+    }
+
+    pub async fn client(&self) -> RedisClient {
+        RedisClient::connect(self.addr)
+            .await
+            .expect("Failure connecting to test server")
+    }
+}
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+pub fn bulk(s: &str) -> RedisValue {
+    RedisValue::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+}
+
+pub fn simple(s: &str) -> RedisValue {
+    RedisValue::SimpleString(Bytes::copy_from_slice(s.as_bytes()))
+}
+
+/// Runs each command on the client and asserts its reply, e.g. `(&["GET", "k"], bulk("v"))`
+pub async fn assert_replies(client: &mut RedisClient, scenario: &[(&[&'static str], RedisValue)]) {
+    for (cmd, expected) in scenario {
+        let reply = client
+            .command(cmd.iter().copied())
+            .await
+            .expect("Failure running command");
+        assert_eq!(&reply, expected, "unexpected reply for {:?}", cmd);
+    }
+}
+
+/// RDB image laid out like a Redis 7 full sync: a function library, module aux data,
+/// eviction hints, an LZF compressed string, keys of every other type in the encodings
+/// Redis picks for small values and a second database. Only `plain` and `compressed`
+/// (24 'a's) are strings in database 0
+pub fn redis7_rdb() -> Vec<u8> {
+    fn string(s: &[u8]) -> Vec<u8> {
+        [&[s.len() as u8][..], s].concat()
+    }
+    // --- short strings, each closed by its back length
+    fn listpack(elements: &[&[u8]]) -> Vec<u8> {
+        let data = elements
+            .iter()
+            .flat_map(|e| [&[0x80 | e.len() as u8][..], e, &[e.len() as u8 + 1]].concat())
+            .collect::<Vec<_>>();
+        let total = (data.len() + 7) as u32;
+        [
+            &total.to_le_bytes()[..],
+            &(elements.len() as u16).to_le_bytes(),
+            &data,
+            &[0xff],
+        ]
+        .concat()
+    }
+
+    let mut rdb = b"REDIS0011".to_vec();
+    rdb.extend([&[0xfa][..], &string(b"redis-ver"), &string(b"7.2.4")].concat());
+    // --- function library
+    rdb.extend([&[0xf5][..], &string(b"#!lua name=lib")].concat());
+    // --- module aux: module ID, when, when, then an unsigned, a string and a double
+    rdb.extend([0xf7, 5, 2, 2, 2, 7, 5]);
+    rdb.extend(string(b"abc"));
+    rdb.extend([4, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f, 0]);
+    rdb.extend([0xfe, 0, 0xfb, 10, 1]);
+
+    // --- LFU and LRU hints ahead of plain strings
+    rdb.extend([&[0xf9, 3, 0][..], &string(b"plain"), &string(b"v")].concat());
+    rdb.extend([&[0xf8, 10, 0][..], &string(b"compressed")].concat());
+    rdb.extend([0xc3, 5, 24, 0x00, b'a', 0xe0, 14, 0x00]);
+    // --- expire of the list, must not carry over to the next key
+    rdb.extend([0xfc, 0, 0, 0, 0, 0, 0, 0, 0x7f]);
+    rdb.extend(
+        [
+            &[18][..],
+            &string(b"list"),
+            &[1, 2],
+            &string(&listpack(&[b"a", b"b"])),
+        ]
+        .concat(),
+    );
+    rdb.extend(
+        [
+            &[4][..],
+            &string(b"hash"),
+            &[1],
+            &string(b"f"),
+            &string(b"v"),
+        ]
+        .concat(),
+    );
+    rdb.extend(
+        [
+            &[3][..],
+            &string(b"zset"),
+            &[2],
+            &string(b"a"),
+            &string(b"1"),
+        ]
+        .concat(),
+    );
+    rdb.extend([&string(b"b")[..], &[254]].concat());
+    rdb.extend(
+        [
+            &[20][..],
+            &string(b"set"),
+            &string(&listpack(&[b"x", b"y"])),
+        ]
+        .concat(),
+    );
+    // --- intset of 16 bit integers, ziplist with a string and a 4 bit immediate
+    let intset = [2, 0, 0, 0, 2, 0, 0, 0, 0xfe, 0xff, 5, 0];
+    rdb.extend([&[11][..], &string(b"ints"), &string(&intset)].concat());
+    let ziplist = [16, 0, 0, 0, 13, 0, 0, 0, 2, 0, 0, 1, b'f', 3, 0xf8, 0xff];
+    rdb.extend([&[13][..], &string(b"zl"), &string(&ziplist)].concat());
+
+    // --- stream with a consumer group, one pending entry and one consumer
+    rdb.extend(
+        [
+            &[21][..],
+            &string(b"stream"),
+            &[1],
+            &string(&[0; 16]),
+            &string(b"lp"),
+        ]
+        .concat(),
+    );
+    rdb.extend([1, 5, 0, 5, 0, 0, 0, 1]);
+    rdb.extend([&[1][..], &string(b"group"), &[5, 0, 1, 1], &[0; 24], &[1]].concat());
+    rdb.extend([&[1][..], &string(b"consumer"), &[0; 16], &[1], &[0; 16]].concat());
+
+    rdb.extend([0xfe, 1, 0xfb, 1, 0]);
+    rdb.extend([&[0][..], &string(b"other"), &string(b"db")].concat());
+    rdb.push(0xff);
+    rdb.extend([0; 8]);
+
+    rdb
+}
@@ -0,0 +1,149 @@
+//! Runs the command scripts in `tests/compat/` against this server and a real redis-server,
+//! reporting every reply that differs between the two.
+//!
+//! Needs either `redis-server` on PATH or `REDIS_COMPAT_ADDR` pointing at a running instance
+//! (e.g. `docker run -p 6379:6379 redis:7`). Run with `cargo test --test compat -- --ignored`.
+
+mod common;
+
+use std::{
+    fs,
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use common::TestServer;
+use redis_rust::{client::RedisClient, RedisValue};
+
+/// Real redis-server reference instance
+enum Reference {
+    Spawned(Child, SocketAddr),
+    External(SocketAddr),
+}
+impl Reference {
+    fn find() -> Option<Self> {
+        if let Ok(addr) = std::env::var("REDIS_COMPAT_ADDR") {
+            return Some(Self::External(
+                addr.parse().expect("Invalid REDIS_COMPAT_ADDR"),
+            ));
+        }
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .ok()?
+            .port();
+        let child = Command::new("redis-server")
+            .args([
+                "--port",
+                &port.to_string(),
+                "--save",
+                "",
+                "--appendonly",
+                "no",
+            ])
+            .stdout(Stdio::null())
+            .spawn()
+            .ok()?;
+
+        Some(Self::Spawned(
+            child,
+            SocketAddr::from(([127, 0, 0, 1], port)),
+        ))
+    }
+
+    fn addr(&self) -> SocketAddr {
+        match self {
+            Self::Spawned(_, addr) | Self::External(addr) => *addr,
+        }
+    }
+
+    async fn client(&self) -> RedisClient {
+        for _ in 0..50 {
+            if let Ok(client) = RedisClient::connect(self.addr()).await {
+                return client;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("Reference redis-server at {} never came up", self.addr());
+    }
+}
+impl Drop for Reference {
+    fn drop(&mut self) {
+        if let Self::Spawned(child, _) = self {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn same_kind(a: &RedisValue, b: &RedisValue) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+#[tokio::test]
+#[ignore = "needs a real redis-server, run with --ignored"]
+async fn replies_match_real_redis() {
+    let Some(reference) = Reference::find() else {
+        eprintln!("redis-server not found on PATH and REDIS_COMPAT_ADDR unset, skipping");
+        return;
+    };
+
+    let mut scripts: Vec<_> =
+        fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/compat"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+    scripts.sort();
+
+    let mut diffs = vec![];
+    for script in scripts {
+        let server = TestServer::master().await;
+        let mut ours = server.client().await;
+        let mut theirs = reference.client().await;
+        theirs.command(["FLUSHALL"]).await.unwrap();
+
+        let name = script.file_name().unwrap().to_string_lossy().to_string();
+        for (lineno, line) in fs::read_to_string(&script).unwrap().lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (kind_only, cmd) = match line.strip_prefix('?') {
+                Some(cmd) => (true, cmd.trim()),
+                None => (false, line),
+            };
+            let args: Vec<Bytes> = cmd
+                .split_whitespace()
+                .map(|arg| Bytes::copy_from_slice(arg.as_bytes()))
+                .collect();
+
+            let expected = theirs.command(args.clone()).await.unwrap();
+            let actual = ours.command(args).await.unwrap();
+
+            let matches = match kind_only {
+                true => same_kind(&expected, &actual),
+                false => expected == actual,
+            };
+            if !matches {
+                diffs.push(format!(
+                    "{}:{}: `{}`\n  redis:      {:?}\n  redis-rust: {:?}",
+                    name,
+                    lineno + 1,
+                    cmd,
+                    expected,
+                    actual
+                ));
+            }
+        }
+    }
+
+    assert!(
+        diffs.is_empty(),
+        "replies drifted from redis:\n{}",
+        diffs.join("\n")
+    );
+}
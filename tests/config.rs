@@ -0,0 +1,296 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::{assert_replies, bulk, simple, TestServer};
+use redis_rust::{Args, RedisValue};
+
+/// Config file under the system temp dir, unique to the test
+fn config_file(name: &str, contents: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("redis-rust-{}-{}.conf", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn config_files_are_read_with_flags_taking_precedence() {
+    let path = config_file(
+        "flags",
+        r#"
+# comments and blank lines are skipped
+
+port 0
+maxmemory 1mb
+save 900 1
+save 300 10
+notify-keyspace-events "KEA"
+slave-serve-stale-data no
+rename-command DEBUG ""
+enable-extensions yes
+daemonize no
+databases 32
+requirepass "p\xc3\xa9ss\x21"
+"#,
+    );
+    let args = Args::load_from([
+        "redis-rust".to_string(),
+        path.to_string_lossy().into_owned(),
+        "--maxmemory".to_string(),
+        "2mb".to_string(),
+    ])
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(args.port, Some(0));
+    assert_eq!(args.maxmemory.as_deref(), Some("2mb"));
+    assert_eq!(args.save.as_deref(), Some("900 1 300 10"));
+    assert_eq!(args.notify_keyspace_events.as_deref(), Some("KEA"));
+    assert_eq!(args.replica_serve_stale_data, Some(false));
+    assert_eq!(args.rename_command, ["DEBUG", ""]);
+    assert!(args.enable_extensions);
+    assert_eq!(args.requirepass.as_deref(), Some("péss!"));
+
+    let server = TestServer::start(Args {
+        save: Some("".to_string()),
+        requirepass: None,
+        ..args
+    })
+    .await;
+    let mut client = server.client().await;
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["CONFIG", "GET", "maxmemory", "save"],
+                RedisValue::Array(vec![
+                    bulk("maxmemory"),
+                    bulk("2097152"),
+                    bulk("save"),
+                    bulk(""),
+                ]),
+            ),
+            (
+                &["CONFIG", "GET", "*stale-data"],
+                RedisValue::Array(vec![
+                    bulk("replica-serve-stale-data"),
+                    bulk("no"),
+                    bulk("slave-serve-stale-data"),
+                    bulk("no"),
+                ]),
+            ),
+            (
+                &["CONFIG", "GET", "databases"],
+                RedisValue::Array(vec![bulk("databases"), bulk("32")]),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn unbalanced_quotes_in_config_files_are_errors() {
+    let path = config_file("quotes", "requirepass \"secret\n");
+    let loaded = Args::load_from([
+        "redis-rust".to_string(),
+        path.to_string_lossy().into_owned(),
+    ]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(loaded.is_err());
+}
+
+#[tokio::test]
+async fn escapes_in_config_files_making_invalid_utf8_are_errors() {
+    let path = config_file("escapes", "requirepass \"\\xff\"\n");
+    let loaded = Args::load_from([
+        "redis-rust".to_string(),
+        path.to_string_lossy().into_owned(),
+    ]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(loaded.is_err());
+}
+
+#[tokio::test]
+async fn config_reload_applies_what_changed_in_the_file() {
+    let path = config_file("reload", "port 0\nsave \"\"\nmaxmemory 1mb\nhz 10\n");
+    let args = Args::load_from([
+        "redis-rust".to_string(),
+        path.to_string_lossy().into_owned(),
+        "--slowlog-max-len".to_string(),
+        "5".to_string(),
+    ])
+    .unwrap();
+    let server = TestServer::start(args).await;
+    let mut client = server.client().await;
+
+    std::fs::write(
+        &path,
+        "port 0\nsave \"\"\nmaxmemory 2mb\nhz 20\nslowlog-max-len 100\n",
+    )
+    .unwrap();
+    // --- hz takes a restart, the command line still wins over the file
+    assert_replies(
+        &mut client,
+        &[
+            (&["CONFIG", "RELOAD"], simple("OK")),
+            (
+                &["CONFIG", "GET", "maxmemory", "hz", "slowlog-max-len"],
+                RedisValue::Array(vec![
+                    bulk("maxmemory"),
+                    bulk("2097152"),
+                    bulk("hz"),
+                    bulk("10"),
+                    bulk("slowlog-max-len"),
+                    bulk("5"),
+                ]),
+            ),
+        ],
+    )
+    .await;
+
+    // --- a broken file changes nothing
+    std::fs::write(&path, "maxmemory lots\n").unwrap();
+    let reply = client.command(["CONFIG", "RELOAD"]).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(reply, RedisValue::SimpleError(_)));
+    assert_replies(
+        &mut client,
+        &[(
+            &["CONFIG", "GET", "maxmemory"],
+            RedisValue::Array(vec![bulk("maxmemory"), bulk("2097152")]),
+        )],
+    )
+    .await;
+
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    assert_replies(
+        &mut client,
+        &[(
+            &["CONFIG", "RELOAD"],
+            RedisValue::SimpleError("ERR The server is running without a config file".into()),
+        )],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn config_set_changes_live_parameters_all_or_nothing() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["CONFIG", "SET", "maxmemory", "10mb", "lfu-log-factor", "5"],
+                simple("OK"),
+            ),
+            (
+                &["CONFIG", "GET", "maxmemory*"],
+                RedisValue::Array(vec![
+                    bulk("maxmemory"),
+                    bulk("10485760"),
+                    bulk("maxmemory-db"),
+                    bulk(""),
+                    bulk("maxmemory-policy"),
+                    bulk("noeviction"),
+                ]),
+            ),
+            (
+                &["CONFIG", "SET", "maxmemory", "1mb", "maxmemory-policy", "bogus"],
+                RedisValue::SimpleError(
+                    "ERR CONFIG SET failed (possibly related to argument 'maxmemory-policy') - Invalid maxmemory policy: 'bogus'"
+                        .into(),
+                ),
+            ),
+            (
+                &["CONFIG", "GET", "maxmemory"],
+                RedisValue::Array(vec![bulk("maxmemory"), bulk("10485760")]),
+            ),
+            (
+                &["CONFIG", "SET", "dir", "/tmp"],
+                RedisValue::SimpleError(
+                    "ERR CONFIG SET failed (possibly related to argument 'dir') - can't set immutable config"
+                        .into(),
+                ),
+            ),
+            (
+                &["CONFIG", "SET", "nonexistent", "1"],
+                RedisValue::SimpleError(
+                    "ERR Unknown option or number of arguments for CONFIG SET - 'nonexistent'"
+                        .into(),
+                ),
+            ),
+            (
+                &["CONFIG", "SET", "maxmemory"],
+                RedisValue::SimpleError(
+                    "ERR wrong number of arguments for 'config|set' command".into(),
+                ),
+            ),
+            (
+                &["CONFIG", "SET", "slave-serve-stale-data", "no"],
+                simple("OK"),
+            ),
+            (
+                &["CONFIG", "GET", "replica-serve-stale-data"],
+                RedisValue::Array(vec![bulk("replica-serve-stale-data"), bulk("no")]),
+            ),
+            (&["CONFIG", "SET", "requirepass", "pw"], simple("OK")),
+        ],
+    )
+    .await;
+
+    // --- a password set at runtime applies to the connections that come next
+    let mut other = server.client().await;
+    assert_replies(
+        &mut other,
+        &[
+            (
+                &["PING"],
+                RedisValue::SimpleError("NOAUTH Authentication required.".into()),
+            ),
+            (&["AUTH", "pw"], simple("OK")),
+            (&["CONFIG", "SET", "requirepass", ""], simple("OK")),
+        ],
+    )
+    .await;
+    let mut third = server.client().await;
+    assert_eq!(third.command(["PING"]).await.unwrap(), simple("PONG"));
+}
+
+#[tokio::test]
+async fn config_refuses_unknown_subcommands_and_bare_gets() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["CONFIG", "GET"],
+                RedisValue::SimpleError(
+                    "ERR wrong number of arguments for 'config|get' command".into(),
+                ),
+            ),
+            (
+                &["CONFIG", "BOGUS"],
+                RedisValue::SimpleError("ERR unknown subcommand 'BOGUS'".into()),
+            ),
+        ],
+    )
+    .await;
+
+    // --- a subcommand that isn't UTF-8 gets the same error, the connection stays up
+    let mut conn = tokio::net::TcpStream::connect(server.addr).await.unwrap();
+    conn.write_all(b"*2\r\n$6\r\nCONFIG\r\n$1\r\n\xff\r\n*1\r\n$4\r\nPING\r\n")
+        .await
+        .unwrap();
+    let expected = b"-ERR unknown subcommand '\xef\xbf\xbd'\r\n+PONG\r\n";
+    let mut reply = vec![0; expected.len()];
+    conn.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected);
+}
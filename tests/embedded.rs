@@ -0,0 +1,189 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use redis_rust::{
+    server::clock::MockClock, CancellationToken, Redis, RedisClient, RedisServer, RedisValue,
+};
+
+fn bulk(s: &'static str) -> RedisValue {
+    RedisValue::BulkString(Bytes::from_static(s.as_bytes()))
+}
+
+#[tokio::test]
+async fn executes_without_sockets() {
+    let mut redis = Redis::open_in_memory();
+
+    redis.execute(["SET", "foo", "bar"]).await.unwrap();
+    assert_eq!(redis.execute(["GET", "foo"]).await.unwrap(), bulk("bar"));
+}
+
+#[tokio::test]
+async fn clones_are_connections_of_their_own() {
+    let mut redis = Redis::open_in_memory();
+    let mut other = redis.clone();
+
+    // --- a transaction stays with the handle that started it
+    assert_eq!(
+        redis.execute(["MULTI"]).await.unwrap(),
+        RedisValue::SimpleString(Bytes::from_static(b"OK"))
+    );
+    assert_eq!(
+        other.execute(["SET", "foo", "bar"]).await.unwrap(),
+        RedisValue::SimpleString(Bytes::from_static(b"OK"))
+    );
+    redis.execute(["DISCARD"]).await.unwrap();
+
+    // --- nor does a blocked handle hold up the others
+    let mut blocked = redis.clone();
+    let popped = tokio::spawn(async move { blocked.execute(["BLPOP", "list", "0"]).await });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    tokio::time::timeout(
+        Duration::from_secs(1),
+        other.execute(["RPUSH", "list", "a"]),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(
+        tokio::time::timeout(Duration::from_secs(1), popped)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap(),
+        RedisValue::Array(vec![bulk("list"), bulk("a")])
+    );
+}
+
+#[tokio::test]
+async fn expiry_follows_the_injected_clock() {
+    let clock = Arc::new(MockClock::new(1_000));
+    let mut redis = Redis::open_in_memory_with_clock(clock.clone());
+
+    redis
+        .execute(["SET", "foo", "bar", "PX", "100"])
+        .await
+        .unwrap();
+
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(redis.execute(["GET", "foo"]).await.unwrap(), bulk("bar"));
+
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(
+        redis.execute(["GET", "foo"]).await.unwrap(),
+        RedisValue::NullBulkString
+    );
+    assert_eq!(
+        redis.execute(["KEYS", "*"]).await.unwrap(),
+        RedisValue::Array(vec![])
+    );
+}
+
+#[tokio::test]
+async fn registered_commands_run_next_to_the_builtin_ones() {
+    use redis_rust::server::{
+        commands::CommandContext,
+        plugins::{CommandFuture, CustomCommand},
+    };
+
+    /// DBSIZE, counting the keys of the main store
+    struct DbSize;
+    impl CustomCommand for DbSize {
+        fn call<'a>(&'a self, ctx: &'a mut CommandContext<'_>) -> CommandFuture<'a> {
+            Box::pin(async move {
+                let keys = ctx.server.databases[0].main_store.lock().await.len();
+                Ok(RedisValue::Integer(keys as i64))
+            })
+        }
+    }
+
+    let mut redis = Redis::open_in_memory();
+    let commands = &redis.server().custom_commands;
+    commands.register("dbsize", DbSize).unwrap();
+    assert!(commands.register("DBSIZE", DbSize).is_err());
+
+    redis.execute(["SET", "foo", "bar"]).await.unwrap();
+    assert_eq!(
+        redis.execute(["DBSIZE"]).await.unwrap(),
+        RedisValue::Integer(1)
+    );
+}
+
+#[tokio::test]
+async fn startup_fails_on_a_missing_plugin() {
+    use redis_rust::{server::server::RedisServer, Args};
+
+    let res = RedisServer::init(Args {
+        port: Some(0),
+        load_plugin: vec!["/nonexistent/plugin.so".to_string()],
+        ..Default::default()
+    })
+    .await;
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn keyspace_events_follow_writes_and_expirations() {
+    use redis_rust::server::events::KeyspaceEvent;
+
+    let clock = Arc::new(MockClock::new(1_000));
+    let mut redis = Redis::open_in_memory_with_clock(clock.clone());
+    let mut events = redis.keyspace_events();
+
+    redis
+        .execute(["SET", "foo", "bar", "PX", "100"])
+        .await
+        .unwrap();
+    redis.execute(["JSON.SET", "doc", "$", "{}"]).await.unwrap();
+    // --- neither one changes anything
+    redis
+        .execute(["JSON.SET", "doc", "$", "[]", "NX"])
+        .await
+        .unwrap();
+    redis.execute(["TS.ADD", "doc", "1", "1"]).await.unwrap();
+    redis.execute(["JSON.DEL", "doc"]).await.unwrap();
+    clock.advance(Duration::from_millis(101));
+    redis.execute(["GET", "foo"]).await.unwrap();
+
+    let mut received = vec![];
+    while let Ok(KeyspaceEvent { event, key, .. }) = events.try_recv() {
+        received.push((event, key));
+    }
+    assert_eq!(
+        received,
+        [
+            ("set", "foo"),
+            ("json.set", "doc"),
+            ("del", "doc"),
+            ("json.del", "doc"),
+            ("expired", "foo"),
+        ]
+        .map(|(event, key)| (event.to_string(), Bytes::from_static(key.as_bytes())))
+    );
+}
+
+#[tokio::test]
+async fn built_servers_serve_until_cancelled() {
+    let server = RedisServer::builder()
+        .port(0)
+        .in_memory()
+        .build()
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let shutdown = CancellationToken::new();
+    let serving = tokio::spawn(Arc::clone(&server).serve(shutdown.clone()));
+
+    let mut client = RedisClient::connect(addr).await.unwrap();
+    client.set("foo", "bar").await.unwrap();
+    assert_eq!(client.command(["GET", "foo"]).await.unwrap(), bulk("bar"));
+    // --- saving stays in memory
+    assert_eq!(
+        client.command(["SAVE"]).await.unwrap(),
+        RedisValue::SimpleString(Bytes::from_static(b"OK"))
+    );
+
+    shutdown.cancel();
+    serving.await.unwrap();
+    // --- connected clients are dropped along with the server
+    assert!(client.command(["PING"]).await.is_err());
+}
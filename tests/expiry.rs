@@ -0,0 +1,214 @@
+mod common;
+
+use std::{
+    collections::HashSet,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use common::{bulk, TestServer};
+use proptest::prelude::*;
+use redis_rust::{
+    repl::ServerContext,
+    server::{
+        clock::MockClock,
+        commands::{execute, CommandContext},
+        expiry::TimerWheel,
+        server::RedisServer,
+    },
+    Args, RedisValue,
+};
+
+fn key(i: usize) -> RedisValue {
+    RedisValue::BulkString(Bytes::from(i.to_string()))
+}
+
+proptest! {
+    #[test]
+    fn timers_fire_once_their_deadline_is_reached(
+        start in 0u64..1 << 40,
+        steps in prop::collection::vec((prop::collection::vec(0u64..1 << 37, 0..8), 0u64..1 << 24), 1..32),
+    ) {
+        let mut wheel = TimerWheel::new(start);
+        let mut pending = vec![];
+        let mut now = start;
+        for (delays, step) in steps {
+            for delay in delays {
+                let id = pending.len();
+                pending.push(Some(now + delay));
+                wheel.insert(key(id), now + delay);
+            }
+            now += step;
+
+            let fired = wheel.advance(now).into_iter().collect::<HashSet<_>>();
+            let mut expected = HashSet::new();
+            for (id, deadline) in pending.iter_mut().enumerate() {
+                if deadline.is_some_and(|deadline| deadline <= now) {
+                    expected.insert(key(id));
+                    *deadline = None;
+                }
+            }
+            prop_assert_eq!(fired, expected);
+            prop_assert_eq!(wheel.len(), pending.iter().flatten().count());
+        }
+    }
+}
+
+#[test]
+fn far_deadlines_survive_span_boundaries() {
+    let mut wheel = TimerWheel::new(0);
+    let far = (1 << 36) + 5;
+    wheel.insert(key(0), far);
+
+    assert!(wheel.advance((1 << 36) - 1).is_empty());
+    assert!(wheel.advance(far - 1).is_empty());
+    assert_eq!(wheel.advance(far), vec![key(0)]);
+    assert!(wheel.is_empty());
+}
+
+#[tokio::test]
+async fn precise_mode_removes_keys_right_after_they_expire() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        expiry_mode: Some("precise".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    client
+        .command(["SET", "short", "v", "PX", "20"])
+        .await
+        .unwrap();
+    client
+        .command(["SET", "long", "v", "PX", "60000"])
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // --- nobody touched the key, the timer alone removed it
+    let main_store = server.server.databases[0].main_store.lock().await;
+    assert!(!main_store.contains_key(&bulk("short")));
+    assert!(main_store.contains_key(&bulk("long")));
+    drop(main_store);
+    assert_eq!(server.server.stats.expired_keys(), 1);
+}
+
+#[tokio::test]
+async fn lazy_mode_removes_expired_keys_in_the_background() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    client
+        .command(["SET", "short", "v", "PX", "20"])
+        .await
+        .unwrap();
+    client
+        .command(["SET", "long", "v", "PX", "60000"])
+        .await
+        .unwrap();
+
+    // --- nobody touches the key, the cron picks it up within a few ticks
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        if server.server.stats.expired_keys() > 0 {
+            break;
+        }
+    }
+    let main_store = server.server.databases[0].main_store.lock().await;
+    assert!(!main_store.contains_key(&bulk("short")));
+    assert!(main_store.contains_key(&bulk("long")));
+    drop(main_store);
+    assert_eq!(server.server.stats.expired_keys(), 1);
+}
+
+#[tokio::test]
+async fn active_expiry_goes_on_while_keys_keep_expiring_and_tells_replicas() {
+    let clock = Arc::new(MockClock::new(1_000));
+    let server = RedisServer::in_memory(clock.clone());
+    let mut session = server.new_session();
+    for i in 0..100 {
+        let key = Bytes::from(i.to_string());
+        let args = match i {
+            0..50 => vec![key, Bytes::from_static(b"v")],
+            _ => vec![key, Bytes::from_static(b"v"), "PX".into(), "10".into()],
+        };
+        let mut ctx = CommandContext {
+            args: &args,
+            server: &server,
+            session: &mut session,
+        };
+        execute("SET", &mut ctx).await.unwrap();
+    }
+    let ServerContext::Master(master) = server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    let offset = master.backlog.lock().unwrap().offset();
+
+    clock.set(2_000);
+    server.active_expire_cycle().await;
+
+    // --- every round found only expired keys, so the cycle didn't stop before the last
+    assert_eq!(server.databases[0].main_store.lock().await.len(), 50);
+    assert!(server.databases[0].expire_store.lock().await.is_empty());
+    assert_eq!(server.stats.expired_keys(), 50);
+    let stream = master
+        .backlog
+        .lock()
+        .unwrap()
+        .range_from(offset + 1)
+        .unwrap();
+    let mut deleted = (50..100)
+        .map(|i| {
+            RedisValue::Array(vec![bulk("DEL"), key(i)])
+                .serialize()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let mut rest = &stream[..];
+    while !rest.is_empty() {
+        let frame = deleted
+            .iter()
+            .position(|frame| rest.starts_with(frame))
+            .expect("Only DEL of expired keys should be propagated");
+        let frame = deleted.swap_remove(frame);
+        rest = &rest[frame.len()..];
+    }
+    assert!(deleted.is_empty());
+}
+
+#[tokio::test]
+async fn debug_set_active_expire_pauses_background_expiry() {
+    let clock = Arc::new(MockClock::new(1_000));
+    let server = RedisServer::in_memory(clock.clone());
+    let mut session = server.new_session();
+    for (cmd, args) in [
+        ("SET", &["k", "v", "PX", "10"][..]),
+        ("DEBUG", &["SET-ACTIVE-EXPIRE", "0"][..]),
+    ] {
+        let args = args.iter().map(|arg| Bytes::from(*arg)).collect::<Vec<_>>();
+        let mut ctx = CommandContext {
+            args: &args,
+            server: &server,
+            session: &mut session,
+        };
+        execute(cmd, &mut ctx).await.unwrap();
+    }
+
+    clock.set(2_000);
+    server.active_expire_cycle().await;
+    server.expire_due_keys().await;
+
+    // --- the key waits for the cycle to be turned back on
+    assert!(server.databases[0]
+        .main_store
+        .lock()
+        .await
+        .contains_key(&bulk("k")));
+    assert_eq!(server.stats.expired_keys(), 0);
+    server.active_expire.store(true, Ordering::Relaxed);
+    server.active_expire_cycle().await;
+    assert!(server.databases[0].main_store.lock().await.is_empty());
+    assert_eq!(server.stats.expired_keys(), 1);
+}
@@ -0,0 +1,57 @@
+use proptest::prelude::*;
+use redis_rust::server::glob::glob_match;
+
+#[test]
+fn patterns_match_the_way_redis_does() {
+    let cases: &[(&str, &str, bool)] = &[
+        ("*", "", true),
+        ("*", "anything", true),
+        ("h?llo", "hello", true),
+        ("h?llo", "hllo", false),
+        ("h*llo", "hllo", true),
+        ("h*llo", "heeeello", true),
+        ("h*llo", "hello world", false),
+        ("*a*b*", "xxaxxbxx", true),
+        ("*a*b*", "xxbxxaxx", false),
+        ("h[ae]llo", "hallo", true),
+        ("h[ae]llo", "hillo", false),
+        ("h[^e]llo", "hallo", true),
+        ("h[^e]llo", "hello", false),
+        ("h[a-b]llo", "hbllo", true),
+        ("h[b-a]llo", "hbllo", true),
+        ("h[a-b]llo", "hcllo", false),
+        ("h[]llo", "hello", false),
+        (r"h\*llo", "h*llo", true),
+        (r"h\*llo", "hello", false),
+        (r"h[\]]llo", "h]llo", true),
+        (r"trailing\", r"trailing\", true),
+        // --- an unterminated class runs to the end of the pattern
+        ("h[el", "he", true),
+        ("h[el", "hel", false),
+    ];
+    for (pattern, text, expected) in cases {
+        assert_eq!(
+            glob_match(pattern.as_bytes(), text.as_bytes()),
+            *expected,
+            "{} against {}",
+            pattern,
+            text
+        );
+    }
+}
+
+proptest! {
+    #[test]
+    fn escaped_text_matches_itself_only(text in prop::collection::vec(any::<u8>(), 0..16), other in prop::collection::vec(any::<u8>(), 0..16)) {
+        let pattern = text.iter().flat_map(|byte| [b'\\', *byte]).collect::<Vec<_>>();
+        prop_assert!(glob_match(&pattern, &text));
+        prop_assert_eq!(glob_match(&pattern, &other), other == text);
+    }
+
+    #[test]
+    fn stars_stand_for_any_infix(prefix in "[a-z]{0,4}", infix in "[a-z*?]{0,8}", suffix in "[a-z]{0,4}") {
+        let pattern = format!("{}*{}", prefix, suffix);
+        let text = format!("{}{}{}", prefix, infix, suffix);
+        prop_assert!(glob_match(pattern.as_bytes(), text.as_bytes()));
+    }
+}
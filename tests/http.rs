@@ -0,0 +1,239 @@
+mod common;
+
+use common::TestServer;
+use redis_rust::Args;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn start() -> (TestServer, TcpStream) {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        http_port: Some(0),
+        ..Default::default()
+    })
+    .await;
+    let addr = server.server.http_addr().unwrap();
+    let conn = TcpStream::connect(addr).await.unwrap();
+
+    (server, conn)
+}
+
+/// Sends a request over a kept-alive connection, returning the status line and the body
+async fn request(conn: &mut TcpStream, method: &str, target: &str, body: &str) -> (String, String) {
+    let req = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        target,
+        body.len(),
+        body
+    );
+    conn.write_all(req.as_bytes()).await.unwrap();
+
+    let mut res = Vec::new();
+    loop {
+        let text = String::from_utf8_lossy(&res).to_string();
+        if let Some((head, rest)) = text.split_once("\r\n\r\n") {
+            let len: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            if rest.len() >= len {
+                let status = head.lines().next().unwrap().to_string();
+                return (status, rest[..len].to_string());
+            }
+        }
+        let mut buf = [0; 256];
+        let n = conn.read(&mut buf).await.unwrap();
+        assert!(n > 0, "Connection closed after {:?}", text);
+        res.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[tokio::test]
+async fn http_gateway_gets_puts_and_deletes_keys() {
+    let (server, mut conn) = start().await;
+
+    assert_eq!(
+        request(&mut conn, "PUT", "/keys/user%3A1", "hello").await,
+        ("HTTP/1.1 204 No Content".to_string(), String::new())
+    );
+    assert_eq!(
+        request(&mut conn, "GET", "/keys/user%3A1", "").await,
+        ("HTTP/1.1 200 OK".to_string(), "hello".to_string())
+    );
+
+    let mut client = server.client().await;
+    assert_eq!(
+        client.get("user:1").await.unwrap().as_deref(),
+        Some(&b"hello"[..])
+    );
+
+    assert_eq!(
+        request(&mut conn, "DELETE", "/keys/user%3A1", "").await.0,
+        "HTTP/1.1 204 No Content"
+    );
+    assert_eq!(
+        request(&mut conn, "GET", "/keys/user%3A1", "").await.0,
+        "HTTP/1.1 404 Not Found"
+    );
+    assert_eq!(client.get("user:1").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn http_gateway_ttls_and_errors() {
+    let (_server, mut conn) = start().await;
+
+    assert_eq!(
+        request(&mut conn, "PUT", "/keys/short?px=1", "v").await.0,
+        "HTTP/1.1 204 No Content"
+    );
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    assert_eq!(
+        request(&mut conn, "GET", "/keys/short", "").await.0,
+        "HTTP/1.1 404 Not Found"
+    );
+
+    assert_eq!(
+        request(&mut conn, "PUT", "/keys/k?ttl=1", "v").await.0,
+        "HTTP/1.1 400 Bad Request"
+    );
+    assert_eq!(
+        request(&mut conn, "POST", "/keys/k", "v").await.0,
+        "HTTP/1.1 405 Method Not Allowed"
+    );
+    assert_eq!(
+        request(&mut conn, "GET", "/other", "").await.0,
+        "HTTP/1.1 404 Not Found"
+    );
+}
+
+#[tokio::test]
+async fn http_deletes_replicate_and_replicas_refuse_them() {
+    let (master, mut conn) = start().await;
+    let replica = TestServer::start(Args {
+        port: Some(0),
+        http_port: Some(0),
+        replicaof: Some(format!("{} {}", master.addr.ip(), master.addr.port())),
+        ..Default::default()
+    })
+    .await;
+    master.client().await.set("k", "v").await.unwrap();
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        if client.get("k").await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let mut replica_conn = TcpStream::connect(replica.server.http_addr().unwrap())
+        .await
+        .unwrap();
+    let (status, body) = request(&mut replica_conn, "DELETE", "/keys/k", "").await;
+    assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+    assert!(body.starts_with("READONLY"), "{}", body);
+
+    assert_eq!(
+        request(&mut conn, "DELETE", "/keys/k", "").await.0,
+        "HTTP/1.1 204 No Content"
+    );
+    for _ in 0..100 {
+        if client.get("k").await.unwrap().is_none() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("The delete never reached the replica");
+}
+
+#[tokio::test]
+async fn http_gateway_refuses_requests_while_a_password_is_required() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        http_port: Some(0),
+        requirepass: Some("secret".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let addr = server.server.http_addr().unwrap();
+
+    for (method, body) in [("PUT", "v"), ("GET", ""), ("DELETE", "")] {
+        let mut conn = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(
+            request(&mut conn, method, "/keys/k", body).await,
+            (
+                "HTTP/1.1 401 Unauthorized".to_string(),
+                "NOAUTH Authentication required.".to_string()
+            )
+        );
+    }
+
+    // --- without waiting for a body it would never read, closing the connection after
+    let mut conn = TcpStream::connect(addr).await.unwrap();
+    conn.write_all(b"PUT /keys/k HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n")
+        .await
+        .unwrap();
+    let mut res = String::new();
+    conn.read_to_string(&mut res).await.unwrap();
+    assert!(
+        res.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+        "{:?}",
+        res
+    );
+}
+
+#[tokio::test]
+async fn http_gateway_refuses_bodies_past_proto_max_bulk_len() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        http_port: Some(0),
+        proto_max_bulk_len: Some(1024),
+        ..Default::default()
+    })
+    .await;
+    let mut conn = TcpStream::connect(server.server.http_addr().unwrap())
+        .await
+        .unwrap();
+
+    conn.write_all(b"PUT /keys/k HTTP/1.1\r\nContent-Length: 1025\r\n\r\n")
+        .await
+        .unwrap();
+    let mut res = String::new();
+    conn.read_to_string(&mut res).await.unwrap();
+    assert!(
+        res.starts_with("HTTP/1.1 413 Content Too Large\r\n"),
+        "{:?}",
+        res
+    );
+}
+
+#[tokio::test]
+async fn http_clients_count_against_maxclients() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        http_port: Some(0),
+        maxclients: Some(1),
+        ..Default::default()
+    })
+    .await;
+    let addr = server.server.http_addr().unwrap();
+    let mut first = TcpStream::connect(addr).await.unwrap();
+    assert_eq!(
+        request(&mut first, "GET", "/keys/k", "").await.0,
+        "HTTP/1.1 404 Not Found"
+    );
+
+    let mut refused = TcpStream::connect(addr).await.unwrap();
+    let mut res = String::new();
+    refused.read_to_string(&mut res).await.unwrap();
+    assert!(
+        res.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "{:?}",
+        res
+    );
+    assert!(res.ends_with("max number of clients reached"), "{:?}", res);
+}
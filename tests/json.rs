@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use redis_rust::{
+    server::{clock::MockClock, db::Db, json, server::Dataset},
+    Redis, RedisValue,
+};
+
+fn bulk(data: &'static [u8]) -> RedisValue {
+    RedisValue::BulkString(Bytes::from_static(data))
+}
+
+#[test]
+fn dumps_are_sorted_and_binary_safe() {
+    let mut contents = Db::new();
+    contents.insert(Bytes::from_static(b"b"), bulk(b"\xff\x00"), None);
+    contents.insert(Bytes::from_static(b"a"), bulk(b"plain"), Some(5_000));
+    let mut other = Db::new();
+    other.insert(Bytes::from_static(b"a"), bulk(b"other"), None);
+    let dataset = Dataset::from([(3, other), (0, contents)]);
+
+    let dump = json::dump(&dataset).unwrap();
+    let expected = r#"{
+  "version": 1,
+  "keys": [
+    {
+      "key": "a",
+      "type": "string",
+      "value": "plain",
+      "expires_at": 5000
+    },
+    {
+      "key": "b",
+      "type": "string",
+      "value": {
+        "hex": "ff00"
+      }
+    },
+    {
+      "key": "a",
+      "type": "string",
+      "value": "other",
+      "db": 3
+    }
+  ]
+}"#;
+    assert_eq!(dump, expected);
+
+    assert_eq!(json::load(&dump, 1_000).unwrap(), dataset);
+}
+
+#[test]
+fn loading_skips_expired_keys_and_rejects_unknown_input() {
+    let dump = r#"{"version":1,"keys":[
+        {"key":"gone","type":"string","value":"v","expires_at":10},
+        {"key":"kept","type":"string","value":"v"}
+    ]}"#;
+    let dataset = json::load(dump, 20).unwrap();
+    let contents = &dataset[&0];
+    assert_eq!(contents.len(), 1);
+    assert!(contents.get(&Bytes::from_static(b"kept")).is_some());
+    assert_eq!(contents.volatile_len(), 0);
+
+    assert!(json::load(r#"{"version":2,"keys":[]}"#, 0).is_err());
+    assert!(json::load(
+        r#"{"version":1,"keys":[{"key":"k","type":"list","value":"v"}]}"#,
+        0
+    )
+    .is_err());
+    assert!(json::load(
+        r#"{"version":1,"keys":[{"key":{"hex":"f"},"type":"string","value":"v"}]}"#,
+        0
+    )
+    .is_err());
+}
+
+#[tokio::test]
+async fn debug_dump_json_and_load_json_round_trip() {
+    let clock = Arc::new(MockClock::new(1_000));
+    let mut source = Redis::open_in_memory_with_clock(clock.clone());
+    source.execute(["SET", "foo", "bar"]).await.unwrap();
+    source
+        .execute(["SET", "ttl", "v", "PX", "500"])
+        .await
+        .unwrap();
+
+    let RedisValue::BulkString(dump) = source.execute(["DEBUG", "DUMP-JSON"]).await.unwrap() else {
+        panic!("DUMP-JSON replies with a bulk string");
+    };
+    assert!(std::str::from_utf8(&dump)
+        .unwrap()
+        .contains("\"expires_at\": 1500"));
+
+    let mut target = Redis::open_in_memory_with_clock(clock);
+    let load = [
+        Bytes::from_static(b"DEBUG"),
+        Bytes::from_static(b"LOAD-JSON"),
+        dump,
+    ];
+    assert_eq!(
+        target.execute(load).await.unwrap(),
+        RedisValue::SimpleString(Bytes::from_static(b"OK"))
+    );
+    assert_eq!(target.execute(["GET", "foo"]).await.unwrap(), bulk(b"bar"));
+    assert_eq!(target.execute(["GET", "ttl"]).await.unwrap(), bulk(b"v"));
+
+    let invalid = target.execute(["DEBUG", "LOAD-JSON", "{"]).await.unwrap();
+    assert!(matches!(invalid, RedisValue::SimpleError(_)));
+}
+
+#[tokio::test]
+async fn every_value_type_round_trips() {
+    let clock = Arc::new(MockClock::new(1_000));
+    let mut source = Redis::open_in_memory_with_clock(clock.clone());
+    let commands: &[&[&str]] = &[
+        &["SET", "string", "v"],
+        &["INCR", "counter"],
+        &["RPUSH", "list", "a", "b", "a"],
+        &["HSET", "hash", "name", "ada", "lang", "rust"],
+        &["SADD", "set", "y", "x", "1"],
+        &["ZADD", "zset", "1.5", "ada", "+inf", "bob"],
+        &["XADD", "stream", "1-1", "field", "value"],
+        &["TS.ADD", "series", "100", "2.5"],
+        &["BF.ADD", "bloom", "item"],
+        &["JSON.SET", "doc", "$", r#"{"a":[1,2]}"#],
+    ];
+    for command in commands {
+        let reply = source.execute(command.iter().copied()).await.unwrap();
+        assert!(!matches!(reply, RedisValue::SimpleError(_)), "{:?}", reply);
+    }
+
+    let RedisValue::BulkString(dump) = source.execute(["DEBUG", "DUMP-JSON"]).await.unwrap() else {
+        panic!("DUMP-JSON replies with a bulk string");
+    };
+    let text = std::str::from_utf8(&dump).unwrap();
+    for value_type in [
+        "list",
+        "hash",
+        "set",
+        "zset",
+        "stream",
+        "timeseries",
+        "bloom",
+    ] {
+        assert!(
+            text.contains(&format!("\"type\": \"{}\"", value_type)),
+            "{}",
+            text
+        );
+    }
+    assert!(text.contains("\"inf\""), "{}", text);
+
+    let mut target = Redis::open_in_memory_with_clock(clock);
+    let load = [
+        Bytes::from_static(b"DEBUG"),
+        Bytes::from_static(b"LOAD-JSON"),
+        dump,
+    ];
+    assert_eq!(
+        target.execute(load).await.unwrap(),
+        RedisValue::SimpleString(Bytes::from_static(b"OK"))
+    );
+    assert_eq!(
+        target.execute(["DEBUG", "DIGEST"]).await.unwrap(),
+        source.execute(["DEBUG", "DIGEST"]).await.unwrap()
+    );
+    assert_eq!(
+        target.execute(["LRANGE", "list", "0", "-1"]).await.unwrap(),
+        RedisValue::Array(vec![bulk(b"a"), bulk(b"b"), bulk(b"a")])
+    );
+    assert_eq!(
+        target.execute(["ZSCORE", "zset", "bob"]).await.unwrap(),
+        RedisValue::Double(Bytes::from_static(b"inf"))
+    );
+}
@@ -0,0 +1,199 @@
+mod common;
+
+use common::TestServer;
+use redis_rust::Args;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn start() -> (TestServer, TcpStream) {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        memcached_port: Some(0),
+        ..Default::default()
+    })
+    .await;
+    let addr = server.server.memcached_addr().unwrap();
+    let conn = TcpStream::connect(addr).await.unwrap();
+
+    (server, conn)
+}
+
+/// Sends a request and reads until the reply ends the way `until` says
+async fn request(conn: &mut TcpStream, req: &str, until: &str) -> String {
+    conn.write_all(req.as_bytes()).await.unwrap();
+
+    let mut res = Vec::new();
+    while !res.ends_with(until.as_bytes()) {
+        let mut buf = [0; 256];
+        let n = conn.read(&mut buf).await.unwrap();
+        assert!(n > 0, "Connection closed after {:?}", res);
+        res.extend_from_slice(&buf[..n]);
+    }
+
+    String::from_utf8(res).unwrap()
+}
+
+#[tokio::test]
+async fn memcached_clients_share_the_dataset() {
+    let (server, mut conn) = start().await;
+
+    assert_eq!(
+        request(&mut conn, "set greeting 5 0 5\r\nhello\r\n", "\r\n").await,
+        "STORED\r\n"
+    );
+    assert_eq!(
+        request(&mut conn, "get greeting missing\r\n", "END\r\n").await,
+        "VALUE greeting 5 5\r\nhello\r\nEND\r\n"
+    );
+
+    // --- both ways round, what one protocol writes the other reads
+    let mut client = server.client().await;
+    assert_eq!(
+        client.get("greeting").await.unwrap().as_deref(),
+        Some(&b"hello"[..])
+    );
+    client.set("from-redis", "value").await.unwrap();
+    assert_eq!(
+        request(&mut conn, "get from-redis\r\n", "END\r\n").await,
+        "VALUE from-redis 0 5\r\nvalue\r\nEND\r\n"
+    );
+
+    // --- flags belong to the value they were stored with
+    assert_eq!(
+        request(&mut conn, "set overwritten 7 0 1\r\nx\r\n", "\r\n").await,
+        "STORED\r\n"
+    );
+    client.set("overwritten", "y").await.unwrap();
+    assert_eq!(
+        request(&mut conn, "get overwritten\r\n", "END\r\n").await,
+        "VALUE overwritten 0 1\r\ny\r\nEND\r\n"
+    );
+
+    assert_eq!(
+        request(&mut conn, "delete greeting\r\n", "\r\n").await,
+        "DELETED\r\n"
+    );
+    assert_eq!(
+        request(&mut conn, "delete greeting\r\n", "\r\n").await,
+        "NOT_FOUND\r\n"
+    );
+    assert_eq!(client.get("greeting").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn memcached_expiration_and_errors() {
+    let (_server, mut conn) = start().await;
+
+    // --- noreply gets no answer, the next reply is the GET's
+    assert_eq!(
+        request(
+            &mut conn,
+            "set gone 0 -1 1 noreply\r\nx\r\nset kept 0 3600 1\r\ny\r\n",
+            "\r\n"
+        )
+        .await,
+        "STORED\r\n"
+    );
+    assert_eq!(
+        request(&mut conn, "get gone kept\r\n", "END\r\n").await,
+        "VALUE kept 0 1\r\ny\r\nEND\r\n"
+    );
+
+    assert_eq!(
+        request(&mut conn, "incr kept 1\r\n", "\r\n").await,
+        "ERROR\r\n"
+    );
+    assert_eq!(
+        request(&mut conn, "set kept x 0 1\r\n", "\r\n").await,
+        "CLIENT_ERROR bad command line format\r\n"
+    );
+    assert_eq!(
+        request(&mut conn, "set kept 0 0 1\r\ntoolong\r\n", "\r\n").await,
+        "CLIENT_ERROR bad data chunk\r\n"
+    );
+}
+
+#[tokio::test]
+async fn memcached_deletes_replicate_and_replicas_refuse_them() {
+    let (master, mut conn) = start().await;
+    let replica = TestServer::start(Args {
+        port: Some(0),
+        memcached_port: Some(0),
+        replicaof: Some(format!("{} {}", master.addr.ip(), master.addr.port())),
+        ..Default::default()
+    })
+    .await;
+    master.client().await.set("k", "v").await.unwrap();
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        if client.get("k").await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let mut replica_conn = TcpStream::connect(replica.server.memcached_addr().unwrap())
+        .await
+        .unwrap();
+    let reply = request(&mut replica_conn, "delete k\r\n", "\r\n").await;
+    assert!(reply.starts_with("SERVER_ERROR READONLY"), "{}", reply);
+
+    assert_eq!(
+        request(&mut conn, "delete k\r\n", "\r\n").await,
+        "DELETED\r\n"
+    );
+    for _ in 0..100 {
+        if client.get("k").await.unwrap().is_none() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("The delete never reached the replica");
+}
+
+#[tokio::test]
+async fn memcached_refuses_requests_while_a_password_is_required() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        memcached_port: Some(0),
+        requirepass: Some("secret".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut conn = TcpStream::connect(server.server.memcached_addr().unwrap())
+        .await
+        .unwrap();
+
+    for req in ["set k 0 0 1\r\nv\r\n", "get k\r\n", "delete k\r\n"] {
+        assert_eq!(
+            request(&mut conn, req, "\r\n").await,
+            "CLIENT_ERROR unauthenticated\r\n"
+        );
+    }
+    assert!(request(&mut conn, "version\r\n", "\r\n")
+        .await
+        .starts_with("VERSION "));
+}
+
+#[tokio::test]
+async fn memcached_clients_count_against_maxclients() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        memcached_port: Some(0),
+        maxclients: Some(1),
+        ..Default::default()
+    })
+    .await;
+    let addr = server.server.memcached_addr().unwrap();
+    let mut first = TcpStream::connect(addr).await.unwrap();
+    assert!(request(&mut first, "version\r\n", "\r\n")
+        .await
+        .starts_with("VERSION "));
+
+    let mut refused = TcpStream::connect(addr).await.unwrap();
+    let mut reply = String::new();
+    refused.read_to_string(&mut reply).await.unwrap();
+    assert_eq!(reply, "SERVER_ERROR max number of clients reached\r\n");
+}
@@ -0,0 +1,311 @@
+mod common;
+
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+};
+
+use common::{bulk, simple, TestServer};
+use redis_rust::{
+    client::RedisClient,
+    server::{
+        clock::{MockClock, SystemClock},
+        server::RedisServer,
+        snapshot::{MemoryStorage, SnapshotStorage},
+    },
+    Args, RedisValue,
+};
+
+/// Empty directory under the system temp dir, unique to the test
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("redis-rust-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn start_in(dir: &str, save: Option<&str>) -> TestServer {
+    TestServer::start(Args {
+        port: Some(0),
+        dir: Some(dir.to_string()),
+        save: save.map(str::to_string),
+        ..Default::default()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn shutdown_saves_the_dataset() {
+    let dir = temp_dir("shutdown-save");
+    let dir = dir.to_str().unwrap();
+
+    let server = start_in(dir, None).await;
+    let mut client = server.client().await;
+    client.set("foo", "bar").await.unwrap();
+    assert_eq!(client.command(["SHUTDOWN"]).await.unwrap(), simple("OK"));
+    drop(server);
+
+    let server = start_in(dir, None).await;
+    let mut client = server.client().await;
+    assert_eq!(client.command(["GET", "foo"]).await.unwrap(), bulk("bar"));
+}
+
+#[tokio::test]
+async fn shutdown_without_save_points_skips_the_save() {
+    let dir = temp_dir("shutdown-nosave");
+
+    let server = start_in(dir.to_str().unwrap(), Some("")).await;
+    let mut client = server.client().await;
+    client.set("foo", "bar").await.unwrap();
+    assert_eq!(client.command(["SHUTDOWN"]).await.unwrap(), simple("OK"));
+
+    assert!(!dir.join("dump.rdb").exists());
+}
+
+#[tokio::test]
+async fn failed_save_aborts_shutdown_unless_forced() {
+    let dir = temp_dir("shutdown-force").join("missing");
+
+    let server = start_in(dir.to_str().unwrap(), None).await;
+    let mut client = server.client().await;
+    assert!(matches!(
+        client.command(["SHUTDOWN"]).await.unwrap(),
+        RedisValue::SimpleError(_)
+    ));
+    assert_eq!(client.command(["PING"]).await.unwrap(), simple("PONG"));
+
+    assert_eq!(
+        client.command(["SHUTDOWN", "FORCE"]).await.unwrap(),
+        simple("OK")
+    );
+}
+
+#[tokio::test]
+async fn shutdown_closes_every_client_connection() {
+    let dir = temp_dir("shutdown-close");
+
+    let server = start_in(dir.to_str().unwrap(), Some("")).await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+    other.ping().await.unwrap();
+    assert_eq!(
+        client.command(["SHUTDOWN", "NOW"]).await.unwrap(),
+        simple("OK")
+    );
+
+    assert!(client.command(["PING"]).await.is_err());
+    assert!(other.command(["PING"]).await.is_err());
+}
+
+#[tokio::test]
+async fn bgsave_writes_a_point_in_time_snapshot() {
+    let dir = temp_dir("bgsave");
+    let dir = dir.to_str().unwrap();
+
+    let server = start_in(dir, None).await;
+    let mut client = server.client().await;
+    client.set("foo", "bar").await.unwrap();
+
+    // --- writes after the snapshot don't leak into it
+    let dataset = server.server.snapshot().await;
+    client.set("later", "value").await.unwrap();
+    assert_eq!(dataset[&0].len(), 1);
+
+    assert_eq!(
+        client.command(["BGSAVE"]).await.unwrap(),
+        simple("Background saving started")
+    );
+    let dump = std::path::Path::new(dir).join("dump.rdb");
+    while !dump.exists() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    drop(server);
+
+    let server = start_in(dir, None).await;
+    let mut client = server.client().await;
+    assert_eq!(
+        client.command(["GET", "later"]).await.unwrap(),
+        bulk("value")
+    );
+}
+
+#[tokio::test]
+async fn cron_saves_once_a_save_point_is_reached() {
+    let dir = temp_dir("autosave");
+    let dir = dir.to_str().unwrap();
+
+    let server = TestServer::start(Args {
+        port: Some(0),
+        dir: Some(dir.to_string()),
+        save: Some("0 2".to_string()),
+        hz: Some(100),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+    let dump = std::path::Path::new(dir).join("dump.rdb");
+
+    client.set("foo", "bar").await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert!(!dump.exists());
+
+    client.set("baz", "qux").await.unwrap();
+    while !dump.exists() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    while server.server.save_state.dirty.load(Ordering::Relaxed) > 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn info_persistence_tracks_saves() {
+    let dir = temp_dir("info-persistence");
+    let dir = dir.to_str().unwrap();
+
+    let server = start_in(dir, None).await;
+    let persistence = || async {
+        let mut client = server.client().await;
+        match client.command(["INFO", "persistence"]).await.unwrap() {
+            RedisValue::BulkString(info) => String::from_utf8(info.to_vec()).unwrap(),
+            other => panic!("Unexpected INFO reply: {:?}", other),
+        }
+    };
+
+    let info = persistence().await;
+    for field in ["loading:0", "rdb_bgsave_in_progress:0", "aof_enabled:0"] {
+        assert!(info.contains(field), "{} missing from {}", field, info);
+    }
+
+    server.client().await.set("foo", "bar").await.unwrap();
+    assert!(persistence()
+        .await
+        .contains("rdb_changes_since_last_save:1"));
+
+    server.client().await.command(["BGSAVE"]).await.unwrap();
+    while server.server.save_state.dirty.load(Ordering::Relaxed) > 0 {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let info = persistence().await;
+    assert!(info.contains("rdb_changes_since_last_save:0"));
+    assert!(info.contains("rdb_last_bgsave_status:ok"));
+}
+
+#[tokio::test]
+async fn clients_get_loading_errors_until_the_dataset_is_in() {
+    use bytes::Bytes;
+    use redis_rust::{
+        client::RedisClient,
+        server::{
+            db::Db,
+            rdb,
+            server::{Dataset, RedisServer},
+        },
+    };
+
+    let dir = temp_dir("loading");
+    let mut contents = Db::new();
+    for i in 0..200_000 {
+        contents.insert(Bytes::from(format!("key:{}", i)), bulk("value"), None);
+    }
+    let dataset = Dataset::from([(0, contents)]);
+    let image = rdb::serialize(&dataset, None).unwrap();
+    std::fs::write(dir.join("dump.rdb"), image).unwrap();
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let init = tokio::spawn(RedisServer::init(Args {
+        port: Some(port as usize),
+        dir: Some(dir.to_str().unwrap().to_string()),
+        ..Default::default()
+    }));
+    let mut client = loop {
+        if let Ok(client) = RedisClient::connect(("127.0.0.1", port)).await {
+            break client;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    };
+    assert_eq!(client.command(["PING"]).await.unwrap(), simple("PONG"));
+    assert_eq!(
+        client.command(["GET", "key:1"]).await.unwrap(),
+        RedisValue::SimpleError(Bytes::from_static(
+            b"LOADING Redis is loading the dataset in memory"
+        ))
+    );
+
+    // --- connections made while loading carry on once it's done
+    let server = init.await.unwrap().unwrap();
+    assert!(!server.is_loading());
+    assert_eq!(
+        client.command(["GET", "key:1"]).await.unwrap(),
+        bulk("value")
+    );
+}
+
+#[tokio::test]
+async fn snapshots_go_to_a_custom_storage() {
+    let dir = temp_dir("custom-storage");
+    let storage = Arc::new(MemoryStorage::default());
+    // --- a running server and a client connected to it
+    let start = || async {
+        let args = Args {
+            port: Some(0),
+            dir: Some(dir.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        let server = RedisServer::init_with_storage(args, Arc::new(SystemClock), storage.clone())
+            .await
+            .unwrap();
+        let handle = tokio::spawn(Arc::clone(&server).run());
+        let client = RedisClient::connect(server.local_addr().unwrap())
+            .await
+            .unwrap();
+        (handle, client)
+    };
+
+    let (handle, mut client) = start().await;
+    client.set("foo", "bar").await.unwrap();
+    assert_eq!(client.command(["SAVE"]).await.unwrap(), simple("OK"));
+    handle.abort();
+    assert!(storage.load().unwrap().is_some());
+    assert!(!dir.join("dump.rdb").exists());
+
+    let (handle, mut client) = start().await;
+    assert_eq!(client.command(["GET", "foo"]).await.unwrap(), bulk("bar"));
+    handle.abort();
+}
+
+#[tokio::test]
+async fn lastsave_reports_the_last_successful_save() {
+    let dir = temp_dir("lastsave");
+    let clock = Arc::new(MockClock::new(5_000));
+    let args = Args {
+        port: Some(0),
+        dir: Some(dir.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+    let server =
+        RedisServer::init_with_storage(args, clock.clone(), Arc::new(MemoryStorage::default()))
+            .await
+            .unwrap();
+    let handle = tokio::spawn(Arc::clone(&server).run());
+    let mut client = RedisClient::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    // --- the server start counts as the last save until one happens
+    assert_eq!(
+        client.command(["LASTSAVE"]).await.unwrap(),
+        RedisValue::Integer(5)
+    );
+    clock.set(9_500);
+    assert_eq!(client.command(["SAVE"]).await.unwrap(), simple("OK"));
+    assert_eq!(
+        client.command(["LASTSAVE"]).await.unwrap(),
+        RedisValue::Integer(9)
+    );
+    handle.abort();
+}
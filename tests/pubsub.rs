@@ -0,0 +1,239 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{bulk, TestServer};
+use redis_rust::{client::RedisClient, Args, RedisValue};
+
+fn confirmation(kind: &str, channel: Option<&str>, count: i64) -> RedisValue {
+    RedisValue::Array(vec![
+        bulk(kind),
+        channel.map_or(RedisValue::NullBulkString, bulk),
+        RedisValue::Integer(count),
+    ])
+}
+
+async fn next_reply(client: &mut RedisClient) -> RedisValue {
+    tokio::time::timeout(Duration::from_secs(5), client.read_reply())
+        .await
+        .expect("Timed out waiting for a reply")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn subscribers_get_what_is_published_to_their_channels_and_patterns() {
+    let server = TestServer::master().await;
+    let mut subscriber = server.client().await;
+    let mut publisher = server.client().await;
+
+    assert_eq!(
+        subscriber
+            .command(["SUBSCRIBE", "news", "sport"])
+            .await
+            .unwrap(),
+        confirmation("subscribe", Some("news"), 1)
+    );
+    assert_eq!(
+        next_reply(&mut subscriber).await,
+        confirmation("subscribe", Some("sport"), 2)
+    );
+    assert_eq!(
+        subscriber.command(["PSUBSCRIBE", "new?"]).await.unwrap(),
+        confirmation("psubscribe", Some("new?"), 3)
+    );
+
+    assert_eq!(
+        publisher
+            .command(["PUBLISH", "news", "hello"])
+            .await
+            .unwrap(),
+        RedisValue::Integer(2)
+    );
+    assert_eq!(
+        publisher
+            .command(["PUBLISH", "weather", "rain"])
+            .await
+            .unwrap(),
+        RedisValue::Integer(0)
+    );
+    assert_eq!(
+        next_reply(&mut subscriber).await,
+        RedisValue::Array(vec![bulk("message"), bulk("news"), bulk("hello")])
+    );
+    assert_eq!(
+        next_reply(&mut subscriber).await,
+        RedisValue::Array(vec![
+            bulk("pmessage"),
+            bulk("new?"),
+            bulk("news"),
+            bulk("hello")
+        ])
+    );
+
+    // --- subscribed connections only take pub/sub commands
+    assert_eq!(
+        subscriber.command(["GET", "k"]).await.unwrap(),
+        RedisValue::SimpleError(
+            "ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context".into()
+        )
+    );
+    assert_eq!(
+        subscriber.command(["UNSUBSCRIBE"]).await.unwrap(),
+        confirmation("unsubscribe", Some("news"), 2)
+    );
+    assert_eq!(
+        next_reply(&mut subscriber).await,
+        confirmation("unsubscribe", Some("sport"), 1)
+    );
+    assert_eq!(
+        publisher
+            .command(["PUBLISH", "sport", "goal"])
+            .await
+            .unwrap(),
+        RedisValue::Integer(0)
+    );
+    assert_eq!(
+        subscriber.command(["PUNSUBSCRIBE", "new?"]).await.unwrap(),
+        confirmation("punsubscribe", Some("new?"), 0)
+    );
+    assert_eq!(
+        subscriber.command(["UNSUBSCRIBE"]).await.unwrap(),
+        confirmation("unsubscribe", None, 0)
+    );
+    assert_eq!(
+        subscriber.command(["GET", "k"]).await.unwrap(),
+        RedisValue::NullBulkString
+    );
+}
+
+#[tokio::test]
+async fn subscriptions_end_with_the_connection() {
+    let server = TestServer::master().await;
+    let mut publisher = server.client().await;
+    let mut subscriber = server.client().await;
+    subscriber.command(["SUBSCRIBE", "news"]).await.unwrap();
+    assert_eq!(
+        publisher
+            .command(["PUBLISH", "news", "hello"])
+            .await
+            .unwrap(),
+        RedisValue::Integer(1)
+    );
+
+    drop(subscriber);
+    let gone = async {
+        while publisher
+            .command(["PUBLISH", "news", "hello"])
+            .await
+            .unwrap()
+            != RedisValue::Integer(0)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), gone)
+        .await
+        .expect("Subscriber still counted after disconnecting");
+}
+
+#[tokio::test]
+async fn reset_and_quit_get_through_in_subscribe_mode() {
+    let server = TestServer::master().await;
+    let mut publisher = server.client().await;
+    let mut subscriber = server.client().await;
+    subscriber.command(["SUBSCRIBE", "news"]).await.unwrap();
+    subscriber.command(["PSUBSCRIBE", "new?"]).await.unwrap();
+
+    // --- RESET drops every subscription, the connection takes any command again
+    assert_eq!(
+        subscriber.command(["RESET"]).await.unwrap(),
+        RedisValue::SimpleString("RESET".into())
+    );
+    assert_eq!(
+        publisher
+            .command(["PUBLISH", "news", "hello"])
+            .await
+            .unwrap(),
+        RedisValue::Integer(0)
+    );
+    assert_eq!(
+        subscriber.command(["GET", "k"]).await.unwrap(),
+        RedisValue::NullBulkString
+    );
+
+    // --- QUIT is answered, then the connection closes
+    subscriber.command(["SUBSCRIBE", "news"]).await.unwrap();
+    assert_eq!(
+        subscriber.command(["QUIT"]).await.unwrap(),
+        RedisValue::SimpleString("OK".into())
+    );
+    let closed = tokio::time::timeout(Duration::from_secs(5), subscriber.read_reply())
+        .await
+        .expect("Connection still open after QUIT");
+    assert!(closed.is_err());
+}
+
+#[tokio::test]
+async fn keyspace_events_go_out_over_pub_sub_when_enabled() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        notify_keyspace_events: Some("KEA".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut subscriber = server.client().await;
+    let mut client = server.client().await;
+    subscriber
+        .command(["SUBSCRIBE", "__keyspace@0__:k", "__keyevent@0__:del"])
+        .await
+        .unwrap();
+    next_reply(&mut subscriber).await;
+
+    assert_eq!(
+        client
+            .command(["CONFIG", "GET", "notify-keyspace-events"])
+            .await
+            .unwrap(),
+        RedisValue::Array(vec![bulk("notify-keyspace-events"), bulk("AKE")])
+    );
+    client.set("k", "v").await.unwrap();
+    client.set("other", "v").await.unwrap();
+    client.del(["k"]).await.unwrap();
+    for expected in [
+        ["message", "__keyspace@0__:k", "set"],
+        ["message", "__keyspace@0__:k", "del"],
+        ["message", "__keyevent@0__:del", "k"],
+    ] {
+        assert_eq!(
+            next_reply(&mut subscriber).await,
+            RedisValue::Array(expected.into_iter().map(bulk).collect())
+        );
+    }
+}
+
+#[tokio::test]
+async fn only_the_event_classes_asked_for_are_published() {
+    let server = TestServer::start(Args {
+        port: Some(0),
+        notify_keyspace_events: Some("Ex".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut subscriber = server.client().await;
+    let mut client = server.client().await;
+    subscriber
+        .command(["PSUBSCRIBE", "__key*__:*"])
+        .await
+        .unwrap();
+
+    client.command(["SET", "k", "v", "PX", "50"]).await.unwrap();
+    assert_eq!(
+        next_reply(&mut subscriber).await,
+        RedisValue::Array(vec![
+            bulk("pmessage"),
+            bulk("__key*__:*"),
+            bulk("__keyevent@0__:expired"),
+            bulk("k")
+        ])
+    );
+}
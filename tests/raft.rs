@@ -0,0 +1,369 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use common::{bulk, TestServer};
+use redis_rust::{
+    client::RedisClient,
+    repl::raft::{AppendRequest, RaftConfig, RaftNode, VoteRequest},
+    server::server::RedisServerConfig,
+    Args, RedisValue,
+};
+
+/// Directory under the system temp dir, unique to the test and the member
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("redis-rust-raft-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Addresses for the members of a cluster
+fn member_addrs(size: usize) -> Vec<SocketAddr> {
+    (0..size)
+        .map(|_| {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        })
+        .collect()
+}
+
+/// Member of the cluster at `addrs` listening on the address at `member`, keeping its
+/// files in `dir`
+async fn start_member(
+    addrs: &[SocketAddr],
+    member: usize,
+    dir: &Path,
+    snapshot_entries: usize,
+) -> TestServer {
+    let addr = addrs[member];
+    let peers = addrs
+        .iter()
+        .filter(|peer| **peer != addr)
+        .map(|peer| format!("{} {}", peer.ip(), peer.port()))
+        .collect();
+
+    TestServer::start(Args {
+        port: Some(addr.port() as usize),
+        dir: Some(dir.to_string_lossy().into_owned()),
+        raft: true,
+        raft_peer: peers,
+        raft_secret: Some("s3cret".to_string()),
+        raft_election_timeout: Some(200),
+        raft_snapshot_entries: Some(snapshot_entries),
+        ..Default::default()
+    })
+    .await
+}
+
+/// Members of a raft cluster, each knowing all the others
+async fn raft_cluster(size: usize) -> Vec<TestServer> {
+    let addrs = member_addrs(size);
+
+    let mut res = vec![];
+    for (i, addr) in addrs.iter().enumerate() {
+        let dir = temp_dir(&addr.port().to_string());
+        res.push(start_member(&addrs, i, &dir, RaftConfig::DEFAULT_SNAPSHOT_ENTRIES).await);
+    }
+
+    res
+}
+
+/// Waits for a member's dataset to be the same as another's
+async fn wait_for_digest(client: &mut RedisClient, digest: &RedisValue) {
+    for _ in 0..200 {
+        if client.command(["DEBUG", "DIGEST"]).await.unwrap() == *digest {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(client.command(["DEBUG", "DIGEST"]).await.unwrap(), *digest);
+}
+
+async fn raft_state(server: &TestServer) -> Vec<RedisValue> {
+    match server
+        .client()
+        .await
+        .command(["RAFT", "STATE"])
+        .await
+        .unwrap()
+    {
+        RedisValue::Array(state) => state,
+        other => panic!("Unexpected RAFT STATE reply: {:?}", other),
+    }
+}
+
+/// Position of the member all the given ones agree is leading
+async fn wait_for_leader(cluster: &[TestServer], members: &[usize]) -> usize {
+    for _ in 0..200 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let mut leaders = vec![];
+        for i in members {
+            leaders.push(raft_state(&cluster[*i]).await[2].clone());
+        }
+        if leaders.iter().any(|leader| *leader != leaders[0]) {
+            continue;
+        }
+        let leader = members
+            .iter()
+            .find(|i| leaders[0] == bulk(&cluster[**i].addr.to_string()));
+        if let Some(leader) = leader {
+            return *leader;
+        }
+    }
+
+    panic!("No leader was elected");
+}
+
+#[tokio::test]
+async fn writes_are_committed_by_a_majority_and_survive_the_leader() {
+    let cluster = raft_cluster(3).await;
+    let leader = wait_for_leader(&cluster, &[0, 1, 2]).await;
+
+    let mut client = cluster[leader].client().await;
+    client.set("foo", "bar").await.unwrap();
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+
+    // --- followers point clients at the leader
+    let follower = (leader + 1) % 3;
+    assert_eq!(
+        cluster[follower]
+            .client()
+            .await
+            .command(["GET", "foo"])
+            .await
+            .unwrap(),
+        RedisValue::SimpleError(format!("NOTLEADER {}", cluster[leader].addr).into())
+    );
+
+    // --- the two left are still a majority
+    let mut cluster = cluster;
+    drop(cluster.remove(leader));
+    let new_leader = wait_for_leader(&cluster, &[0, 1]).await;
+
+    let mut client = cluster[new_leader].client().await;
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+}
+
+#[tokio::test]
+async fn a_single_node_cluster_leads_itself() {
+    let cluster = raft_cluster(1).await;
+    wait_for_leader(&cluster, &[0]).await;
+
+    let mut client = cluster[0].client().await;
+    client.set("foo", "bar").await.unwrap();
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+}
+
+#[tokio::test]
+async fn every_write_goes_through_the_log() {
+    let cluster = raft_cluster(3).await;
+    let leader = wait_for_leader(&cluster, &[0, 1, 2]).await;
+
+    let mut client = cluster[leader].client().await;
+    for cmd in [
+        &["BF.ADD", "bloom", "a"][..],
+        &["TS.ADD", "series", "1", "10"],
+        &["JSON.SET", "doc", "$", r#"{"a":1}"#],
+        &["SETEX", "expiring", "100", "v"],
+    ] {
+        let reply = client.command(cmd.iter().copied()).await.unwrap();
+        assert!(!matches!(reply, RedisValue::SimpleError(_)), "{:?}", reply);
+    }
+    assert_eq!(
+        client.command(["BLPOP", "list", "0"]).await.unwrap(),
+        RedisValue::SimpleError("ERR 'blpop' is not supported in raft mode".into())
+    );
+
+    // --- followers end up with the same dataset, DEBUG DIGEST runs on each node
+    let digest = client.command(["DEBUG", "DIGEST"]).await.unwrap();
+    for follower in (0..3).filter(|i| *i != leader) {
+        wait_for_digest(&mut cluster[follower].client().await, &digest).await;
+    }
+}
+
+#[tokio::test]
+async fn a_restarted_member_gets_its_dataset_back_from_its_snapshot_and_log() {
+    let addrs = member_addrs(1);
+    let dir = temp_dir("replay");
+    let server = start_member(&addrs, 0, &dir, 5).await;
+    wait_for_leader(std::slice::from_ref(&server), &[0]).await;
+
+    let mut client = server.client().await;
+    for i in 0..12 {
+        client
+            .set(format!("key{}", i), i.to_string())
+            .await
+            .unwrap();
+    }
+    drop(client);
+    drop(server);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    // --- part of the writes went into a snapshot, the rest is only in the log
+    assert!(dir.join("raft.snapshot").exists());
+    assert!(std::fs::metadata(dir.join("raft.log")).unwrap().len() > 0);
+
+    let server = start_member(&addrs, 0, &dir, 5).await;
+    wait_for_leader(std::slice::from_ref(&server), &[0]).await;
+    let mut client = server.client().await;
+    for i in 0..12 {
+        assert_eq!(
+            client.get(format!("key{}", i)).await.unwrap(),
+            Some(Bytes::from(i.to_string()))
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_member_behind_the_compacted_log_gets_the_snapshot() {
+    let addrs = member_addrs(3);
+    let dirs = addrs
+        .iter()
+        .map(|addr| temp_dir(&format!("behind-{}", addr.port())))
+        .collect::<Vec<_>>();
+    // --- two of the three members are a majority, the log is compacted several times
+    // --- before the last one joins
+    let mut cluster = vec![];
+    for (i, dir) in dirs.iter().enumerate().take(2) {
+        cluster.push(start_member(&addrs, i, dir, 5).await);
+    }
+    let leader = wait_for_leader(&cluster, &[0, 1]).await;
+    let mut client = cluster[leader].client().await;
+    for i in 0..20 {
+        client
+            .set(format!("key{}", i), i.to_string())
+            .await
+            .unwrap();
+    }
+    let digest = client.command(["DEBUG", "DIGEST"]).await.unwrap();
+
+    let late = start_member(&addrs, 2, &dirs[2], 5).await;
+    wait_for_digest(&mut late.client().await, &digest).await;
+    assert!(dirs[2].join("raft.snapshot").exists());
+}
+
+#[tokio::test]
+async fn only_members_knowing_the_secret_send_votes_and_entries() {
+    let cluster = raft_cluster(1).await;
+    wait_for_leader(&cluster, &[0]).await;
+    let mut client = cluster[0].client().await;
+
+    let append = [
+        "RAFT",
+        "APPENDENTRIES",
+        "99",
+        "127.0.0.1",
+        "1",
+        "0",
+        "0",
+        "0",
+    ];
+    assert_eq!(
+        client.command(append).await.unwrap(),
+        RedisValue::SimpleError(
+            "NOAUTH Raft members must authenticate with RAFT AUTH first".into()
+        )
+    );
+    assert_eq!(
+        client.command(["RAFT", "AUTH", "guess"]).await.unwrap(),
+        RedisValue::SimpleError("WRONGPASS invalid raft secret".into())
+    );
+    assert_eq!(
+        client
+            .command(["RAFT", "REQUESTVOTE", "99", "intruder", "100", "99"])
+            .await
+            .unwrap(),
+        RedisValue::SimpleError(
+            "NOAUTH Raft members must authenticate with RAFT AUTH first".into()
+        )
+    );
+    // --- the term didn't move, the leader is still leading
+    assert_eq!(raft_state(&cluster[0]).await[0], bulk("leader"));
+
+    assert_eq!(
+        client.command(["RAFT", "AUTH", "s3cret"]).await.unwrap(),
+        RedisValue::SimpleString("OK".into())
+    );
+    assert!(matches!(
+        client.command(append).await.unwrap(),
+        RedisValue::Array(_)
+    ));
+}
+
+#[test]
+fn raft_needs_a_secret() {
+    let args = Args {
+        raft: true,
+        ..Default::default()
+    };
+    assert!(RedisServerConfig::from_args(&args).is_err());
+}
+
+#[test]
+fn a_restarted_node_keeps_its_term_and_vote() {
+    let state_file = temp_dir("restart").join(RaftConfig::DEFAULT_STATE_FILE);
+    let config = RaftConfig {
+        peers: vec![],
+        secret: "s3cret".to_string(),
+        election_timeout: Duration::from_secs(60),
+        state_file: RaftConfig::DEFAULT_STATE_FILE.to_string(),
+        snapshot_entries: RaftConfig::DEFAULT_SNAPSHOT_ENTRIES,
+    };
+    let vote = |candidate: &str| VoteRequest {
+        term: 5,
+        candidate: candidate.to_string(),
+        last_log_index: 0,
+        last_log_term: 0,
+    };
+    let addr = ("127.0.0.1".to_string(), 0);
+
+    let node = RaftNode::new(config.clone(), addr.clone(), state_file.clone()).unwrap();
+    assert_eq!(node.request_vote(vote("a")), (5, true));
+    drop(node);
+
+    let node = RaftNode::new(config, addr, state_file).unwrap();
+    assert_eq!(node.state().1, 5);
+    assert_eq!(node.request_vote(vote("b")), (5, false));
+    assert_eq!(node.request_vote(vote("a")), (5, true));
+}
+
+#[test]
+fn an_entry_length_past_the_end_is_refused() {
+    let args = |argc: &str| {
+        [
+            "1",
+            "127.0.0.1",
+            "6379",
+            "0",
+            "0",
+            "0",
+            "1",
+            "0",
+            argc,
+            "SET",
+            "foo",
+            "bar",
+        ]
+        .map(|arg| Bytes::from(arg.to_string()))
+    };
+
+    let req = AppendRequest::from_args(&args("3")).unwrap();
+    assert_eq!(req.entries.len(), 1);
+    for argc in ["4", &usize::MAX.to_string(), &(usize::MAX - 2).to_string()] {
+        assert!(AppendRequest::from_args(&args(argc)).is_err(), "{}", argc);
+    }
+}
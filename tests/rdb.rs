@@ -0,0 +1,270 @@
+mod common;
+
+use bytes::Bytes;
+use common::bulk;
+use redis_rust::{
+    server::{
+        db::Db,
+        rdb::{self, RdbRecord, ReplInfo},
+        server::Dataset,
+        stream::{NewId, Stream, StreamId},
+        zset::SortedSet,
+    },
+    RedisValue,
+};
+
+const DUMP: &[u8] = include_bytes!("../examples/dump.rdb");
+
+#[test]
+fn check_reports_structure_and_key_counts() {
+    let mut records = vec![];
+    let report = rdb::check(DUMP, |range, record| {
+        records.push((range.start, record.clone()))
+    });
+
+    assert!(report.corruption.is_none());
+    assert_eq!(report.version, Some(11));
+    assert_eq!(report.keys_by_type.get("string"), Some(&1));
+    assert!(matches!(records.first(), Some((9, RdbRecord::Aux { .. }))));
+    assert!(matches!(records.last(), Some((_, RdbRecord::Eof))));
+}
+
+#[test]
+fn check_pinpoints_the_corrupt_record() {
+    // --- cut inside the fourth aux field, which starts at offset 52
+    let report = rdb::check(&DUMP[..60], |_, _| {});
+    assert_eq!(report.corruption.map(|(offset, _)| offset), Some(52));
+
+    // --- anything past the checksum is rejected in strict mode
+    let trailing = [DUMP, b"junk"].concat();
+    let report = rdb::check(&trailing, |_, _| {});
+    assert_eq!(
+        report.corruption.map(|(offset, _)| offset),
+        Some(DUMP.len())
+    );
+    assert!(rdb::parse(&trailing, 0).is_ok());
+}
+
+#[test]
+fn loads_every_type_of_a_redis7_dump() {
+    let rdb = common::redis7_rdb();
+
+    let report = rdb::check(&rdb, |_, _| {});
+    assert!(report.corruption.is_none(), "{:?}", report.corruption);
+    for (value_type, count) in [
+        ("string", 3),
+        ("list", 1),
+        ("hash", 2),
+        ("zset", 1),
+        ("set", 2),
+        ("stream", 1),
+    ] {
+        assert_eq!(report.keys_by_type.get(value_type), Some(&count));
+    }
+
+    let dataset = rdb::parse(&rdb, 0).unwrap();
+    assert_eq!(dataset.keys().copied().collect::<Vec<_>>(), [0, 1]);
+    let other = &dataset[&1];
+    assert_eq!(other.len(), 1);
+    assert!(other.get(&Bytes::from("other")).is_some());
+    let contents = &dataset[&0];
+    let value = |key: &'static str| contents.get(&Bytes::from(key)).map(|(value, _)| value);
+    // --- all but the stream, which has a consumer group
+    assert_eq!(contents.len(), 8);
+    assert_eq!(value("other"), None);
+    assert_eq!(
+        value("hash"),
+        Some(&RedisValue::Hash(
+            [(Bytes::from("f"), Bytes::from("v"))].into()
+        ))
+    );
+    assert_eq!(
+        value("zl"),
+        Some(&RedisValue::Hash(
+            [(Bytes::from("f"), Bytes::from("7"))].into()
+        ))
+    );
+    assert_eq!(
+        value("list"),
+        Some(&RedisValue::List(["a", "b"].map(Bytes::from).into()))
+    );
+    assert_eq!(
+        value("set"),
+        Some(&RedisValue::Set(["x", "y"].map(Bytes::from).into()))
+    );
+    assert_eq!(
+        value("ints"),
+        Some(&RedisValue::Set(["-2", "5"].map(Bytes::from).into()))
+    );
+    let mut zset = SortedSet::default();
+    zset.insert(Bytes::from("a"), 1.0);
+    zset.insert(Bytes::from("b"), f64::INFINITY);
+    assert_eq!(value("zset"), Some(&RedisValue::SortedSet(zset)));
+    assert_eq!(value("compressed"), Some(&bulk(&"a".repeat(24))));
+    assert_eq!(value("plain"), Some(&bulk("v")));
+    assert_eq!(contents.volatile_len(), 1);
+    assert_eq!(
+        contents.get(&Bytes::from("list")),
+        Some((
+            &RedisValue::List(["a", "b"].map(Bytes::from).into()),
+            Some(0x7f << 56)
+        ))
+    );
+}
+
+#[test]
+fn checksums_are_written_and_verified() {
+    // --- the CRC64 Redis computed for it
+    assert!(rdb::parse(DUMP, 0).is_ok());
+
+    let mut corrupt = DUMP.to_vec();
+    let value = corrupt.len() - 12;
+    corrupt[value] = b'L';
+    let report = rdb::check(&corrupt, |_, _| {});
+    let (offset, e) = report.corruption.unwrap();
+    assert_eq!(offset, DUMP.len() - 8);
+    assert!(e.to_string().starts_with("Wrong RDB checksum"), "{}", e);
+    assert!(rdb::parse(&corrupt, 0).is_err());
+    assert!(rdb::parse_parallel(&corrupt, 0, 2).is_err());
+
+    let mut contents = Db::new();
+    contents.insert(Bytes::from("k"), bulk("v"), None);
+    let image = rdb::serialize(&Dataset::from([(0, contents)]), None).unwrap();
+    assert_ne!(image[image.len() - 8..], [0; 8]);
+    assert!(rdb::check(&image, |_, _| {}).corruption.is_none());
+
+    // --- a zeroed checksum wasn't computed, the data is taken as is
+    let unchecked = [&corrupt[..corrupt.len() - 8], &[0; 8]].concat();
+    assert!(rdb::check(&unchecked, |_, _| {}).corruption.is_none());
+}
+
+#[test]
+fn parallel_loading_matches_the_sequential_one() {
+    // --- enough keys for several batches, a third with an expire half of which is past
+    let mut contents = Db::new();
+    for i in 0..10_000 {
+        let expires_at = match i % 3 {
+            0 if i % 2 == 0 => Some(1_000),
+            0 => Some(3_000),
+            _ => None,
+        };
+        let value = bulk(&format!("{}", i).repeat(i % 40));
+        contents.insert(Bytes::from(format!("key:{}", i)), value, expires_at);
+    }
+    let repl_info = ReplInfo {
+        replid: "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_string(),
+        offset: 42,
+        stream_db: 3,
+    };
+    let image = rdb::serialize(&Dataset::from([(0, contents)]), Some(&repl_info)).unwrap();
+
+    let sequential = rdb::parse_sequential(&image, 2_000).unwrap();
+    let parallel = rdb::parse_parallel(&image, 2_000, 4).unwrap();
+    assert_eq!(parallel, sequential);
+    let (dataset, loaded_repl_info) = parallel;
+    assert_eq!(dataset[&0].len(), 10_000 - 1_667);
+    assert_eq!(dataset[&0].volatile_len(), 1_667);
+    assert_eq!(loaded_repl_info, Some(repl_info));
+
+    let rdb = common::redis7_rdb();
+    assert_eq!(
+        rdb::parse_parallel(&rdb, 0, 3).unwrap(),
+        rdb::parse_sequential(&rdb, 0).unwrap()
+    );
+    assert!(rdb::parse_parallel(&image[..image.len() / 2], 2_000, 4).is_err());
+}
+
+#[test]
+fn json_documents_are_saved_as_redisjson_module_values() {
+    let document = RedisValue::Json(Bytes::from_static(br#"{"a":[1,2,{"b":null}]}"#));
+    let mut contents = Db::new();
+    contents.insert(Bytes::from("doc"), document.clone(), Some(5_000));
+    contents.insert(Bytes::from("str"), bulk("bar"), None);
+    let dataset = Dataset::from([(0, contents)]);
+    let image = rdb::serialize(&dataset, None).unwrap();
+
+    let loaded = rdb::parse_sequential(&image, 0).unwrap();
+    assert_eq!(loaded.0, dataset);
+    assert_eq!(rdb::parse_parallel(&image, 0, 2).unwrap(), loaded);
+
+    // --- the module ID RedisJSON registers "ReJSON-RL" with, encoding version 3
+    let module_id = 0x45e2_5238_df91_2c03_u64;
+    assert!(image
+        .windows(9)
+        .any(|window| window[0] == 0x81 && window[1..] == module_id.to_be_bytes()));
+    let report = rdb::check(&image, |_, _| {});
+    assert_eq!(report.keys_by_type.get("module"), Some(&1));
+}
+
+#[test]
+fn collections_are_saved_in_their_plain_encodings() {
+    let list = RedisValue::List(["a", "b", ""].map(Bytes::from).into());
+    let hash = RedisValue::Hash(
+        [("field", "value"), ("other", "")]
+            .map(|(field, value)| (Bytes::from(field), Bytes::from(value)))
+            .into(),
+    );
+    let mut contents = Db::new();
+    contents.insert(Bytes::from("list"), list, None);
+    contents.insert(Bytes::from("hash"), hash, None);
+    let mut zset = SortedSet::default();
+    zset.insert(Bytes::from("low"), f64::NEG_INFINITY);
+    zset.insert(Bytes::from("high"), 1.5);
+    contents.insert(Bytes::from("zset"), RedisValue::SortedSet(zset), None);
+    let mut stream = Stream::default();
+    for (ms, field) in [(5, "a"), (5, "b"), (9, "c")] {
+        let fields = vec![(
+            Bytes::from(field),
+            Bytes::from("x".repeat(ms as usize * 20)),
+        )];
+        stream.add(NewId::AutoSeq(ms), 0, fields).unwrap();
+    }
+    stream.add(NewId::Auto, 0, vec![]).unwrap();
+    contents.insert(Bytes::from("stream"), RedisValue::Stream(stream), None);
+    let mut long_stream = Stream::default();
+    for seq in 1..=250 {
+        let id = NewId::Explicit(StreamId { ms: 1 << 40, seq });
+        long_stream
+            .add(
+                id,
+                0,
+                vec![(Bytes::from("n"), Bytes::from(seq.to_string()))],
+            )
+            .unwrap();
+    }
+    contents.insert(
+        Bytes::from("long stream"),
+        RedisValue::Stream(long_stream),
+        None,
+    );
+    let set = RedisValue::Set(["b", "a"].map(Bytes::from).into());
+    contents.insert(Bytes::from("set"), set, None);
+    let dataset = Dataset::from([(0, contents)]);
+    let image = rdb::serialize(&dataset, None).unwrap();
+
+    let loaded = rdb::parse_sequential(&image, 0).unwrap();
+    assert_eq!(loaded.0, dataset);
+    let report = rdb::check(&image, |_, _| {});
+    assert_eq!(report.keys_by_type.get("list"), Some(&1));
+    assert_eq!(report.keys_by_type.get("hash"), Some(&1));
+    assert_eq!(report.keys_by_type.get("set"), Some(&1));
+    assert_eq!(report.keys_by_type.get("zset"), Some(&1));
+    assert_eq!(report.keys_by_type.get("stream"), Some(&2));
+}
+
+#[test]
+fn every_database_is_saved_behind_its_selectdb() {
+    let mut first = Db::new();
+    first.insert(Bytes::from("k"), bulk("first"), None);
+    let mut other = Db::new();
+    other.insert(Bytes::from("k"), bulk("other"), None);
+    other.insert(Bytes::from("ttl"), bulk("v"), Some(5_000));
+    let dataset = Dataset::from([(0, first), (300, other)]);
+    let image = rdb::serialize(&dataset, None).unwrap();
+
+    let loaded = rdb::parse_sequential(&image, 0).unwrap();
+    assert_eq!(loaded.0, dataset);
+    assert_eq!(rdb::parse_parallel(&image, 0, 2).unwrap(), loaded);
+    let report = rdb::check(&image, |_, _| {});
+    assert_eq!(report.keys_by_type.get("string"), Some(&3));
+}
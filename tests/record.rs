@@ -0,0 +1,139 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use common::{assert_replies, bulk, TestServer};
+use redis_rust::{
+    server::record::{parse_log, replay, RecordedCommand},
+    Args,
+};
+
+#[tokio::test]
+async fn recorded_commands_replay_into_another_server() {
+    let log = std::env::temp_dir().join(format!("redis-rust-record-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&log);
+    let recording = TestServer::start(Args {
+        port: Some(0),
+        record_commands: Some(log.to_str().unwrap().to_string()),
+        ..Default::default()
+    })
+    .await;
+
+    let mut first = recording.client().await;
+    let mut second = recording.client().await;
+    first.command(["SET", "a", "1"]).await.unwrap();
+    second.command(["SET", "b", "2"]).await.unwrap();
+    first.command(["SET", "a", "3"]).await.unwrap();
+    recording.server.recorder.as_ref().unwrap().flush().unwrap();
+
+    let commands = parse_log(Bytes::from(std::fs::read(&log).unwrap())).unwrap();
+    assert_eq!(commands.len(), 3);
+    assert_eq!(commands[0].client_id, commands[2].client_id);
+    assert_ne!(commands[0].client_id, commands[1].client_id);
+    assert_eq!(commands[2].args, ["SET", "a", "3"].map(Bytes::from));
+
+    let target = TestServer::master().await;
+    let report = replay(commands, target.addr, 0.0).await.unwrap();
+    assert_eq!((report.commands, report.errors), (3, 0));
+    assert_replies(
+        &mut target.client().await,
+        &[(&["GET", "a"], bulk("3")), (&["GET", "b"], bulk("2"))],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn replay_keeps_the_recorded_pace() {
+    let target = TestServer::master().await;
+    let command = |timestamp| RecordedCommand {
+        timestamp,
+        client_id: 1,
+        args: vec![Bytes::from_static(b"PING")],
+    };
+    let commands = vec![command(1_000), command(1_400)];
+
+    let start = Instant::now();
+    replay(commands.clone(), target.addr, 2.0).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    let start = Instant::now();
+    replay(commands, target.addr, 0.0).await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(200));
+}
+
+#[test]
+fn truncated_last_entries_are_dropped() {
+    let command = RecordedCommand {
+        timestamp: 1,
+        client_id: 2,
+        args: vec![Bytes::from_static(b"PING")],
+    };
+    let entry = command.serialize().unwrap();
+    let mut log = entry.to_vec();
+    log.extend_from_slice(&entry[..entry.len() - 3]);
+
+    assert_eq!(parse_log(Bytes::from(log)).unwrap(), vec![command]);
+}
+
+#[tokio::test]
+async fn audit_log_records_write_and_admin_commands() {
+    let log = std::env::temp_dir().join(format!("redis-rust-audit-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&log);
+    let server = TestServer::start(Args {
+        port: Some(0),
+        audit_log: Some(log.to_str().unwrap().to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    client.command(["SET", "foo", "secret"]).await.unwrap();
+    client.command(["GET", "foo"]).await.unwrap();
+    client
+        .command(["CONFIG", "GET", "nonexistent"])
+        .await
+        .unwrap();
+    client.command(["DEBUG", "NOPE"]).await.unwrap();
+
+    let entries = std::fs::read_to_string(&log).unwrap();
+    let entries = entries.lines().collect::<Vec<_>>();
+    assert_eq!(entries.len(), 3);
+    assert!(client_addr(entries[0]).starts_with("127.0.0.1:"));
+    let addr = format!("\"addr\":\"{}\"", client_addr(entries[0]));
+    assert!(entries[0].contains("\"command\":\"SET\",\"target\":\"foo\",\"result\":\"ok\""));
+    assert!(entries[0].contains("\"user\":\"default\""));
+    assert!(!entries[0].contains("secret"));
+    assert!(entries[1].contains("\"command\":\"CONFIG\",\"target\":\"GET\""));
+    assert!(entries[2].contains("\"command\":\"DEBUG\",\"target\":\"NOPE\",\"result\":\"error\""));
+    assert!(entries.iter().all(|entry| entry.contains(&addr)));
+}
+
+/// Peer address an audit entry was recorded for
+fn client_addr(entry: &str) -> &str {
+    let start = entry.find("\"addr\":\"").unwrap() + "\"addr\":\"".len();
+    let len = entry[start..].find('"').unwrap();
+    &entry[start..start + len]
+}
+
+#[tokio::test]
+async fn audit_log_only_covers_the_configured_categories() {
+    let log =
+        std::env::temp_dir().join(format!("redis-rust-audit-admin-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&log);
+    let server = TestServer::start(Args {
+        port: Some(0),
+        audit_log: Some(log.to_str().unwrap().to_string()),
+        audit_commands: Some("admin".to_string()),
+        ..Default::default()
+    })
+    .await;
+    let mut client = server.client().await;
+
+    client.command(["SET", "foo", "bar"]).await.unwrap();
+    client.command(["CONFIG", "GET", "dir"]).await.unwrap();
+
+    let entries = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(entries.lines().count(), 1);
+    assert!(entries.contains("\"command\":\"CONFIG\""));
+}
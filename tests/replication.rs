@@ -0,0 +1,1336 @@
+mod common;
+
+use common::{bulk, TestServer};
+use redis_rust::{
+    server::{db::Db, rdb::ReplInfo, server::Dataset},
+    RedisValue,
+};
+
+async fn info(server: &TestServer) -> String {
+    let mut client = server.client().await;
+    match client.command(["INFO", "replication"]).await.unwrap() {
+        RedisValue::BulkString(b) => String::from_utf8(b.to_vec()).unwrap(),
+        other => panic!("Unexpected INFO reply: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn master_reports_its_role() {
+    let master = TestServer::master().await;
+
+    assert!(info(&master).await.contains("role:master"));
+}
+
+#[tokio::test]
+async fn replica_completes_handshake() {
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+
+    assert!(info(&replica).await.contains("role:slave"));
+}
+
+#[tokio::test]
+async fn rdb_payload_arrives_in_chunks() {
+    use bytes::Bytes;
+    use redis_rust::server::handler::RedisConnectionHandler;
+    use tokio::io::AsyncWriteExt;
+
+    let (master, replica) = tokio::io::duplex(64);
+    let mut handler = RedisConnectionHandler::new(replica);
+
+    tokio::spawn(async move {
+        let mut master = master;
+        for chunk in [&b"$1"[..], b"0\r\n0123", b"456789*1\r\n$4\r\nPING\r\n"] {
+            master.write_all(chunk).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    });
+
+    assert_eq!(handler.read_rdb_file().await.unwrap(), b"0123456789");
+    assert_eq!(
+        handler.read_and_parse().await.unwrap(),
+        Some(RedisValue::Array(vec![RedisValue::BulkString(
+            Bytes::from_static(b"PING")
+        )]))
+    );
+}
+
+/// Minimal master that completes the handshake, full-syncs the given RDB image and then
+/// propagates `stream`. The returned task resolves to the first frame the replica sends back
+async fn fake_master(
+    rdb: &'static [u8],
+    stream: &'static [u8],
+) -> (
+    std::net::SocketAddr,
+    tokio::task::JoinHandle<Option<RedisValue>>,
+) {
+    let full_resync = format!("+FULLRESYNC {} 0\r\n${}\r\n", "a".repeat(40), rdb.len());
+
+    fake_master_sending([full_resync.as_bytes(), rdb, stream].concat()).await
+}
+
+/// Same as `fake_master`, answering PSYNC with `sync` as is
+async fn fake_master_sending(
+    sync: Vec<u8>,
+) -> (
+    std::net::SocketAddr,
+    tokio::task::JoinHandle<Option<RedisValue>>,
+) {
+    use redis_rust::server::handler::RedisConnectionHandler;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let task = tokio::spawn(async move {
+        let (conn, _) = listener.accept().await.unwrap();
+        let mut handler = RedisConnectionHandler::new(conn);
+
+        for reply in ["+PONG\r\n", "+OK\r\n", "+OK\r\n"] {
+            handler.read_and_parse().await.unwrap();
+            handler.write_raw(reply.as_bytes()).await.unwrap();
+        }
+        handler.read_and_parse().await.unwrap();
+        handler.write_raw(&sync).await.unwrap();
+
+        // --- keeps the link open until the replica replies or goes away
+        handler.read_and_parse().await.ok().flatten()
+    });
+
+    (addr, task)
+}
+
+async fn start_replica(master_addr: std::net::SocketAddr) -> TestServer {
+    use redis_rust::Args;
+
+    TestServer::start(Args {
+        port: Some(0),
+        replicaof: Some(format!("{} {}", master_addr.ip(), master_addr.port())),
+        ..Default::default()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn replica_loads_the_full_sync_dataset() {
+    let (master_addr, _) = fake_master(include_bytes!("../examples/dump.rdb"), b"").await;
+    let replica = start_replica(master_addr).await;
+
+    let mut client = replica.client().await;
+    assert_eq!(
+        client.get("mykey").await.unwrap().as_deref(),
+        Some(&b"myval"[..])
+    );
+}
+
+#[tokio::test]
+async fn fetch_rdb_saves_the_full_sync_dataset() {
+    use redis_rust::repl::replica::{self, MasterConnector};
+
+    const DUMP: &[u8] = include_bytes!("../examples/dump.rdb");
+    let (master_addr, _) = fake_master(DUMP, b"").await;
+
+    let master_addr = format!("{} {}", master_addr.ip(), master_addr.port());
+    let rdb = replica::fetch_rdb(master_addr, &MasterConnector::default())
+        .await
+        .unwrap();
+    assert_eq!(rdb, DUMP);
+}
+
+#[tokio::test]
+async fn replica_acks_the_processed_offset() {
+    use bytes::Bytes;
+    use redis_rust::server::rdb::EMPTY_RDB;
+
+    const SET: &[u8] = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
+    const PING: &[u8] = b"*1\r\n$4\r\nPING\r\n";
+    const GETACK: &[u8] = b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
+
+    let stream: &'static [u8] = [SET, PING, GETACK].concat().leak();
+    let (master_addr, ack) = fake_master(EMPTY_RDB, stream).await;
+    let replica = start_replica(master_addr).await;
+
+    let expected_offset = (SET.len() + PING.len()).to_string();
+    assert_eq!(
+        ack.await.unwrap(),
+        Some(RedisValue::Array(vec![
+            RedisValue::BulkString(Bytes::from_static(b"REPLCONF")),
+            RedisValue::BulkString(Bytes::from_static(b"ACK")),
+            RedisValue::BulkString(Bytes::from(expected_offset)),
+        ]))
+    );
+
+    // --- the GETACK itself counts once it has been answered
+    let info = info(&replica).await;
+    assert!(info.contains(&format!("slave_repl_offset:{}", stream.len())));
+
+    let mut client = replica.client().await;
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+}
+
+#[tokio::test]
+async fn replica_follows_a_redis7_master() {
+    use bytes::Bytes;
+
+    fn command(args: &[&str]) -> Vec<u8> {
+        let args = args.iter().map(|arg| bulk(arg)).collect();
+        RedisValue::Array(args).serialize().unwrap().to_vec()
+    }
+
+    // --- keepalive newlines around the reply and a diskless transfer closed by a mark
+    let mark = "m".repeat(40);
+    let mut sync = format!(
+        "\n+FULLRESYNC {} 0\r\n\n\n$EOF:{}\r\n",
+        "a".repeat(40),
+        mark
+    )
+    .into_bytes();
+    sync.extend(common::redis7_rdb());
+    sync.extend(mark.as_bytes());
+    for args in [
+        &["SELECT", "0"][..],
+        &["MULTI"],
+        &["SET", "a", "1"],
+        &["SET", "b", "2", "PXAT", "99999999999999"],
+        &["EXEC"],
+        &["SELECT", "1"],
+        &["SET", "other", "x"],
+        &["SELECT", "0"],
+        &["PING"],
+        &["SET", "c", "3"],
+        // --- never completed, none of it gets applied
+        &["MULTI"],
+        &["SET", "d", "4"],
+    ] {
+        sync.extend(command(args));
+    }
+    let (master_addr, ack) = fake_master_sending(sync).await;
+    let replica = start_replica(master_addr).await;
+
+    // --- Redis only starts streaming once the replica ACKs a diskless sync
+    assert_eq!(
+        ack.await.unwrap(),
+        Some(RedisValue::Array(vec![
+            RedisValue::BulkString(Bytes::from_static(b"REPLCONF")),
+            RedisValue::BulkString(Bytes::from_static(b"ACK")),
+            RedisValue::BulkString(Bytes::from_static(b"0")),
+        ]))
+    );
+
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        if client.get("c").await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    for (key, value) in [
+        ("compressed", Some("a".repeat(24))),
+        ("a", Some("1".to_string())),
+        ("b", Some("2".to_string())),
+        ("c", Some("3".to_string())),
+        ("other", None),
+        ("d", None),
+    ] {
+        let got = client.get(key).await.unwrap();
+        assert_eq!(
+            got.as_deref(),
+            value.as_ref().map(|v| v.as_bytes()),
+            "{}",
+            key
+        );
+    }
+    assert_eq!(
+        client.command(["HGET", "hash", "f"]).await.unwrap(),
+        RedisValue::BulkString(Bytes::from_static(b"v"))
+    );
+    // --- both the key of database 1 in the payload and the one written there after
+    client.command(["SELECT", "1"]).await.unwrap();
+    assert_eq!(
+        client.get("other").await.unwrap().as_deref(),
+        Some(&b"x"[..])
+    );
+}
+
+#[tokio::test]
+async fn stale_replica_refuses_reads_once_the_link_is_down() {
+    use redis_rust::{server::rdb::EMPTY_RDB, Args};
+
+    const SET: &[u8] = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
+    let (master_addr, master) = fake_master(EMPTY_RDB, SET).await;
+    let replica = TestServer::start(Args {
+        port: Some(0),
+        replicaof: Some(format!("{} {}", master_addr.ip(), master_addr.port())),
+        replica_serve_stale_data: Some(false),
+        ..Default::default()
+    })
+    .await;
+
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        if client.get("foo").await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+
+    master.abort();
+    for _ in 0..100 {
+        if info(&replica).await.contains("master_link_status:down") {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        client.command(["GET", "foo"]).await.unwrap(),
+        RedisValue::SimpleError(
+            "MASTERDOWN Link with MASTER is down and replica-serve-stale-data is set to 'no'."
+                .into()
+        )
+    );
+    assert!(info(&replica).await.contains("role:slave"));
+    assert_eq!(
+        client
+            .command(["CONFIG", "GET", "replica-serve-stale-data"])
+            .await
+            .unwrap(),
+        RedisValue::Array(vec![bulk("replica-serve-stale-data"), bulk("no")])
+    );
+}
+
+#[tokio::test]
+async fn psync_continues_from_the_backlog() {
+    use bytes::Bytes;
+    use redis_rust::repl::ServerContext;
+
+    const PING: &[u8] = b"*1\r\n$4\r\nPING\r\n";
+
+    let master = TestServer::master().await;
+    let ServerContext::Master(ctx) = master.server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    ctx.backlog.lock().unwrap().feed(&[PING, PING].concat());
+    let replid = ctx.master_replid.clone();
+
+    // --- resuming after the first PING gets only the second one
+    let mut client = master.client().await;
+    let resume_offset = (PING.len() + 1).to_string();
+    assert_eq!(
+        client
+            .command(["PSYNC".to_string(), replid.clone(), resume_offset])
+            .await
+            .unwrap(),
+        RedisValue::SimpleString(Bytes::from(format!("CONTINUE {}", replid)))
+    );
+    assert_eq!(
+        client.read_reply().await.unwrap(),
+        RedisValue::Array(vec![RedisValue::BulkString(Bytes::from_static(b"PING"))])
+    );
+
+    // --- unknown history falls back to a full resync at the current offset
+    let mut client = master.client().await;
+    assert_eq!(
+        client.command(["PSYNC", "?", "-1"]).await.unwrap(),
+        RedisValue::SimpleString(Bytes::from(format!(
+            "FULLRESYNC {} {}",
+            replid,
+            PING.len() * 2
+        )))
+    );
+}
+
+#[test]
+fn backlog_only_serves_the_history_it_still_holds() {
+    use redis_rust::repl::backlog::ReplBacklog;
+
+    let mut backlog = ReplBacklog::new(4);
+    backlog.feed(b"abc");
+    backlog.feed(b"def");
+
+    assert_eq!(backlog.offset(), 6);
+    assert_eq!(backlog.range_from(3).as_deref(), Some(&b"cdef"[..]));
+    assert_eq!(backlog.range_from(7).as_deref(), Some(&b""[..]));
+    assert_eq!(backlog.range_from(2), None);
+    assert_eq!(backlog.range_from(8), None);
+}
+
+#[tokio::test]
+async fn promoted_replica_keeps_its_history_as_replid2() {
+    use bytes::Bytes;
+    use redis_rust::server::rdb::EMPTY_RDB;
+
+    const SET: &[u8] = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
+
+    let (master_addr, _) = fake_master(EMPTY_RDB, SET).await;
+    let replica = start_replica(master_addr).await;
+    let mut client = replica.client().await;
+    while client.get("foo").await.unwrap().is_none() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    assert_eq!(
+        client.command(["REPLICAOF", "NO", "ONE"]).await.unwrap(),
+        RedisValue::SimpleString(Bytes::from_static(b"OK"))
+    );
+    let info = info(&replica).await;
+    let old_replid = "a".repeat(40);
+    assert!(info.contains("role:master"));
+    assert!(info.contains(&format!("master_replid2:{}", old_replid)));
+    assert!(info.contains(&format!("second_repl_offset:{}", SET.len() + 1)));
+
+    // --- a former sibling that applied the same stream resumes without a full resync
+    let RedisValue::SimpleString(reply) = client
+        .command(["PSYNC".to_string(), old_replid, (SET.len() + 1).to_string()])
+        .await
+        .unwrap()
+    else {
+        panic!("PSYNC should reply with a simple string");
+    };
+    let new_replid = info
+        .lines()
+        .find_map(|l| l.strip_prefix("master_replid:"))
+        .unwrap();
+    assert_eq!(reply, format!("CONTINUE {}", new_replid));
+}
+
+#[tokio::test]
+async fn debug_change_repl_id_starts_a_new_history() {
+    let master = TestServer::master().await;
+    let before = info(&master).await;
+
+    let mut client = master.client().await;
+    client.command(["DEBUG", "CHANGE-REPL-ID"]).await.unwrap();
+    let after = info(&master).await;
+
+    let replid = |info: &str| {
+        info.lines()
+            .find_map(|l| l.strip_prefix("master_replid:"))
+            .unwrap()
+            .to_string()
+    };
+    assert_ne!(replid(&before), replid(&after));
+    assert!(after.contains("second_repl_offset:-1"));
+}
+
+#[tokio::test]
+async fn role_reports_the_replication_state() {
+    use bytes::Bytes;
+    use redis_rust::server::rdb::EMPTY_RDB;
+
+    const GETACK: &[u8] = b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
+
+    let replica_role = |master_addr: std::net::SocketAddr, state: &'static str, offset: usize| {
+        RedisValue::Array(vec![
+            RedisValue::BulkString(Bytes::from_static(b"slave")),
+            RedisValue::BulkString(Bytes::from(master_addr.ip().to_string())),
+            RedisValue::Integer(master_addr.port() as i64),
+            RedisValue::BulkString(Bytes::from_static(state.as_bytes())),
+            RedisValue::Integer(offset as i64),
+        ])
+    };
+
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+    assert_eq!(
+        master.client().await.command(["ROLE"]).await.unwrap(),
+        RedisValue::Array(vec![
+            RedisValue::BulkString(Bytes::from_static(b"master")),
+            RedisValue::Integer(0),
+            RedisValue::Array(vec![RedisValue::Array(vec![
+                bulk("127.0.0.1"),
+                RedisValue::BulkString(Bytes::from(replica.addr.port().to_string())),
+                bulk("0"),
+            ])]),
+        ])
+    );
+    assert_eq!(
+        replica.client().await.command(["ROLE"]).await.unwrap(),
+        replica_role(master.addr, "connected", 0)
+    );
+    assert!(info(&replica).await.contains("master_link_status:up"));
+
+    // --- the fake master hangs up once the replica answered the GETACK
+    let (master_addr, ack) = fake_master(EMPTY_RDB, GETACK).await;
+    let replica = start_replica(master_addr).await;
+    ack.await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    assert_eq!(
+        replica.client().await.command(["ROLE"]).await.unwrap(),
+        replica_role(master_addr, "connect", GETACK.len())
+    );
+    assert!(info(&replica).await.contains("master_link_status:down"));
+}
+
+#[tokio::test]
+async fn replica_announces_the_address_it_is_reachable_at() {
+    use redis_rust::Args;
+
+    let master = TestServer::master().await;
+    let _replica = TestServer::start(Args {
+        port: Some(0),
+        replicaof: Some(format!("{} {}", master.addr.ip(), master.addr.port())),
+        replica_announce_ip: Some("10.0.0.5".to_string()),
+        replica_announce_port: Some(7000),
+        ..Default::default()
+    })
+    .await;
+
+    let master_info = info(&master).await;
+    assert!(master_info.contains("connected_slaves:1\r\n"));
+    assert!(master_info.contains("slave0:ip=10.0.0.5,port=7000,state=online,offset=0\r\n"));
+
+    // --- without ip-address the master goes by the connection, and forgets the replica
+    // --- once it hangs up
+    let mut fake_replica = master.client().await;
+    assert_eq!(
+        fake_replica
+            .command([
+                "REPLCONF",
+                "listening-port",
+                "6390",
+                "capa",
+                "eof",
+                "capa",
+                "psync2"
+            ])
+            .await
+            .unwrap(),
+        RedisValue::SimpleString("OK".into())
+    );
+    assert_eq!(
+        fake_replica
+            .command(["REPLCONF", "rdb-filter", "x"])
+            .await
+            .unwrap(),
+        RedisValue::SimpleError("ERR Unrecognized REPLCONF option: rdb-filter".into())
+    );
+    fake_replica
+        .write_raw(b"*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n")
+        .await
+        .unwrap();
+    fake_replica.read_reply().await.unwrap();
+    assert!(info(&master)
+        .await
+        .contains("slave1:ip=127.0.0.1,port=6390,state=online,offset=0\r\n"));
+
+    drop(fake_replica);
+    for _ in 0..100 {
+        if info(&master).await.contains("connected_slaves:1\r\n") {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("The replica that hung up is still listed");
+}
+
+#[tokio::test]
+async fn writes_during_a_full_sync_follow_the_payload() {
+    use redis_rust::server::rdb;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    const SELECT: &[u8] = b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n";
+    const SET: &[u8] = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
+
+    let master = TestServer::master().await;
+    let mut fake_replica = TcpStream::connect(master.addr).await.unwrap();
+    fake_replica
+        .write_all(b"*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n")
+        .await
+        .unwrap();
+    // --- the write lands while the replica hasn't read anything of its sync yet
+    while !info(&master).await.contains("connected_slaves:1\r\n") {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    master.client().await.set("foo", "bar").await.unwrap();
+
+    // --- the payload is the dataset as it was at the offset the sync started from
+    let replid = master
+        .server
+        .server_context
+        .read()
+        .unwrap()
+        .get_master_replid()
+        .to_string();
+    let repl_info = ReplInfo {
+        replid,
+        offset: 0,
+        stream_db: 0,
+    };
+    let payload = rdb::serialize(&Dataset::new(), Some(&repl_info)).unwrap();
+    let header = format!("${}\r\n", payload.len());
+    // --- the stream after a full sync starts by selecting the database of its first write
+    let expected = [header.as_bytes(), &payload, SELECT, SET].concat();
+    let mut received = vec![];
+    while !received.ends_with(&expected) {
+        let mut buf = [0; 512];
+        let n = fake_replica.read(&mut buf).await.unwrap();
+        assert!(n > 0, "Connection closed after {:?}", received);
+        received.extend_from_slice(&buf[..n]);
+    }
+    assert!(received.starts_with(b"+FULLRESYNC "));
+    assert!(info(&master)
+        .await
+        .contains(&format!("master_repl_offset:{}", SELECT.len() + SET.len())));
+}
+
+#[tokio::test]
+async fn full_resync_sends_the_dataset_of_the_master() {
+    let master = TestServer::master().await;
+    let mut client = master.client().await;
+    client.set("foo", "bar").await.unwrap();
+    client
+        .command(["SET", "session", "token", "PX", "600000"])
+        .await
+        .unwrap();
+    client.command(["HSET", "hash", "f", "v"]).await.unwrap();
+    client.command(["SELECT", "3"]).await.unwrap();
+    client.set("foo", "other").await.unwrap();
+    let digest = client.command(["DEBUG", "DIGEST"]).await.unwrap();
+
+    let replica = TestServer::replica_of(&master).await;
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        if client.command(["DEBUG", "DIGEST"]).await.unwrap() == digest {
+            assert_eq!(
+                client.get("foo").await.unwrap().as_deref(),
+                Some(&b"bar"[..])
+            );
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("The replica never got the dataset of its master");
+}
+
+#[tokio::test]
+async fn replica_applies_the_writes_of_its_master() {
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+
+    master.client().await.set("foo", "bar").await.unwrap();
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        if client.get("foo").await.unwrap().is_some() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("The write never reached the replica");
+}
+
+#[tokio::test]
+async fn writes_reach_replicas_in_the_database_they_were_made_in() {
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+
+    let mut client = master.client().await;
+    client.command(["SELECT", "2"]).await.unwrap();
+    client.set("foo", "two").await.unwrap();
+    let mut other = master.client().await;
+    other.set("foo", "zero").await.unwrap();
+    client.command(["SWAPDB", "2", "5"]).await.unwrap();
+    other.set("last", "x").await.unwrap();
+
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        if client.get("last").await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"zero"[..])
+    );
+    client.command(["SELECT", "5"]).await.unwrap();
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"two"[..])
+    );
+    client.command(["SELECT", "2"]).await.unwrap();
+    assert_eq!(client.get("foo").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn replica_refuses_writes_of_its_clients() {
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+    let readonly =
+        || RedisValue::SimpleError("READONLY You can't write against a read only replica.".into());
+
+    let mut client = replica.client().await;
+    assert_eq!(
+        client.command(["SET", "foo", "mine"]).await.unwrap(),
+        readonly()
+    );
+    assert_eq!(
+        client
+            .command(["EVAL", "return redis.call('SET', 'foo', 'mine')", "0"])
+            .await
+            .unwrap(),
+        readonly()
+    );
+    assert_eq!(
+        client
+            .command(["EVAL", "return redis.call('GET', 'foo')", "0"])
+            .await
+            .unwrap(),
+        RedisValue::NullBulkString
+    );
+
+    // --- the master's writes still go through
+    master.client().await.set("foo", "bar").await.unwrap();
+    for _ in 0..100 {
+        if client.get("foo").await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+
+    assert_eq!(
+        client
+            .command(["CONFIG", "SET", "slave-read-only", "no"])
+            .await
+            .unwrap(),
+        RedisValue::SimpleString("OK".into())
+    );
+    assert_eq!(
+        client
+            .command(["CONFIG", "GET", "replica-read-only"])
+            .await
+            .unwrap(),
+        RedisValue::Array(vec![bulk("replica-read-only"), bulk("no")])
+    );
+    assert_eq!(
+        client.command(["SET", "local", "mine"]).await.unwrap(),
+        RedisValue::SimpleString("OK".into())
+    );
+}
+
+#[tokio::test]
+async fn writes_go_to_replicas_with_the_values_the_master_picked() {
+    use redis_rust::repl::ServerContext;
+
+    let master = TestServer::master().await;
+    let mut client = master.client().await;
+    let RedisValue::BulkString(id) = client.command(["XADD", "s", "*", "a", "1"]).await.unwrap()
+    else {
+        panic!("XADD should reply the new entry ID");
+    };
+    client.command(["LPUSH", "l", "x"]).await.unwrap();
+    client
+        .command(["EXPIREAT", "l", "4000000000"])
+        .await
+        .unwrap();
+    let RedisValue::Integer(added) = client.command(["TS.ADD", "ts", "*", "1"]).await.unwrap()
+    else {
+        panic!("TS.ADD should reply the sample timestamp");
+    };
+    let RedisValue::Integer(incremented) = client.command(["TS.INCRBY", "ts", "2"]).await.unwrap()
+    else {
+        panic!("TS.INCRBY should reply the sample timestamp");
+    };
+    client
+        .command(["GETEX", "ts", "PXAT", "4000000000000"])
+        .await
+        .unwrap();
+    client.command(["SETNX", "k", "v"]).await.unwrap();
+    client.command(["GETEX", "k", "PERSIST"]).await.unwrap();
+    client.command(["GETDEL", "k"]).await.unwrap();
+    client
+        .command(["RPUSH", "l2", "a", "b", "c"])
+        .await
+        .unwrap();
+    client
+        .command(["LMPOP", "2", "none", "l2", "RIGHT", "COUNT", "2"])
+        .await
+        .unwrap();
+    client.command(["INCRBYFLOAT", "f", "0.1"]).await.unwrap();
+
+    let ServerContext::Master(ctx) = master.server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    let stream = ctx.backlog.lock().unwrap().range_from(1).unwrap();
+    let expected = [
+        // --- the stream starts by selecting the database of the first write
+        RedisValue::Array(vec![bulk("SELECT"), bulk("0")]),
+        RedisValue::Array(vec![
+            bulk("XADD"),
+            bulk("s"),
+            RedisValue::BulkString(id),
+            bulk("a"),
+            bulk("1"),
+        ]),
+        RedisValue::Array(vec![bulk("LPUSH"), bulk("l"), bulk("x")]),
+        RedisValue::Array(vec![bulk("PEXPIREAT"), bulk("l"), bulk("4000000000000")]),
+        RedisValue::Array(vec![
+            bulk("TS.ADD"),
+            bulk("ts"),
+            bulk(&added.to_string()),
+            bulk("1"),
+        ]),
+        RedisValue::Array(vec![
+            bulk("TS.INCRBY"),
+            bulk("ts"),
+            bulk("2"),
+            bulk("TIMESTAMP"),
+            bulk(&incremented.to_string()),
+        ]),
+        // --- a failed GETEX isn't propagated, the others go out as what they did
+        RedisValue::Array(vec![bulk("SETNX"), bulk("k"), bulk("v")]),
+        RedisValue::Array(vec![bulk("PERSIST"), bulk("k")]),
+        RedisValue::Array(vec![bulk("DEL"), bulk("k")]),
+        RedisValue::Array(vec![
+            bulk("RPUSH"),
+            bulk("l2"),
+            bulk("a"),
+            bulk("b"),
+            bulk("c"),
+        ]),
+        // --- LMPOP as the pop it made
+        RedisValue::Array(vec![bulk("RPOP"), bulk("l2"), bulk("2")]),
+        // --- INCRBYFLOAT as the value it left
+        RedisValue::Array(vec![bulk("SET"), bulk("f"), bulk("0.1"), bulk("KEEPTTL")]),
+    ]
+    .into_iter()
+    .flat_map(|command| command.serialize().unwrap())
+    .collect::<Vec<_>>();
+    assert_eq!(&stream[..], &expected[..]);
+}
+
+#[tokio::test]
+async fn extension_writes_reach_replicas_without_extensions_as_plain_writes() {
+    use redis_rust::{repl::ServerContext, Args};
+
+    let master = TestServer::start(Args {
+        port: Some(0),
+        enable_extensions: true,
+        ..Default::default()
+    })
+    .await;
+    let replica = TestServer::replica_of(&master).await;
+    let mut client = master.client().await;
+    client.set("lock", "a").await.unwrap();
+    client
+        .command(["SET", "lock", "b", "IFEQ", "a"])
+        .await
+        .unwrap();
+    client
+        .command(["SET", "lock", "c", "IFEQ", "a"])
+        .await
+        .unwrap();
+    client
+        .command(["SET", "lock", "d", "GET", "IFEQ", "b"])
+        .await
+        .unwrap();
+    client.command(["DELIFEQ", "lock", "b"]).await.unwrap();
+    client.command(["DELIFEQ", "lock", "d"]).await.unwrap();
+    client.set("last", "x").await.unwrap();
+
+    let ServerContext::Master(ctx) = master.server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    let stream = ctx.backlog.lock().unwrap().range_from(1).unwrap();
+    let expected = [
+        RedisValue::Array(vec![bulk("SELECT"), bulk("0")]),
+        RedisValue::Array(vec![bulk("SET"), bulk("lock"), bulk("a")]),
+        // --- conditions that held go out as plain writes, the others not at all
+        RedisValue::Array(vec![bulk("SET"), bulk("lock"), bulk("b")]),
+        RedisValue::Array(vec![bulk("SET"), bulk("lock"), bulk("d"), bulk("GET")]),
+        RedisValue::Array(vec![bulk("DEL"), bulk("lock")]),
+        RedisValue::Array(vec![bulk("SET"), bulk("last"), bulk("x")]),
+    ]
+    .into_iter()
+    .flat_map(|command| command.serialize().unwrap())
+    .collect::<Vec<_>>();
+    assert_eq!(&stream[..], &expected[..]);
+
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        if client.get("last").await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        client.command(["EXISTS", "lock", "last"]).await.unwrap(),
+        RedisValue::Integer(1)
+    );
+}
+
+#[tokio::test]
+async fn messages_published_on_the_master_reach_subscribers_of_replicas() {
+    use redis_rust::repl::ServerContext;
+
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+    let mut subscriber = replica.client().await;
+    subscriber.command(["SUBSCRIBE", "news"]).await.unwrap();
+
+    let mut publisher = master.client().await;
+    assert_eq!(
+        publisher
+            .command(["PUBLISH", "news", "hello"])
+            .await
+            .unwrap(),
+        RedisValue::Integer(0)
+    );
+    let message = tokio::time::timeout(std::time::Duration::from_secs(5), subscriber.read_reply())
+        .await
+        .expect("The message never reached the replica")
+        .unwrap();
+    assert_eq!(
+        message,
+        RedisValue::Array(vec![bulk("message"), bulk("news"), bulk("hello")])
+    );
+
+    let ServerContext::Master(ctx) = master.server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    let stream = ctx.backlog.lock().unwrap().range_from(1).unwrap();
+    let publish = RedisValue::Array(vec![bulk("PUBLISH"), bulk("news"), bulk("hello")]);
+    assert!(stream.ends_with(&publish.serialize().unwrap()));
+}
+
+#[tokio::test]
+async fn pops_of_blocked_clients_reach_replicas_after_the_push() {
+    use redis_rust::repl::ServerContext;
+
+    let master = TestServer::master().await;
+    let mut blocked = master.client().await;
+    let mut client = master.client().await;
+    let push = async {
+        while master.server.blocked_clients.is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        client.command(["RPUSH", "l", "a", "b"]).await.unwrap();
+        client.command(["BRPOP", "l", "0"]).await.unwrap()
+    };
+    let (_, popped) = tokio::join!(blocked.command(["BLPOP", "l", "0"]), push);
+    assert_eq!(popped, RedisValue::Array(vec![bulk("l"), bulk("b")]));
+
+    let ServerContext::Master(ctx) = master.server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    let stream = ctx.backlog.lock().unwrap().range_from(1).unwrap();
+    let expected = [
+        RedisValue::Array(vec![bulk("SELECT"), bulk("0")]),
+        RedisValue::Array(vec![bulk("RPUSH"), bulk("l"), bulk("a"), bulk("b")]),
+        RedisValue::Array(vec![bulk("LPOP"), bulk("l")]),
+        RedisValue::Array(vec![bulk("RPOP"), bulk("l")]),
+    ]
+    .into_iter()
+    .flat_map(|command| command.serialize().unwrap())
+    .collect::<Vec<_>>();
+    assert_eq!(&stream[..], &expected[..]);
+}
+
+#[tokio::test]
+async fn replica_dataset_digest_matches_its_master() {
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+
+    let mut client = master.client().await;
+    for (key, value) in [("foo", "bar"), ("baz", "qux"), ("foo", "bar2")] {
+        client.set(key, value).await.unwrap();
+    }
+    client
+        .command(["SET", "session", "token", "PX", "600000"])
+        .await
+        .unwrap();
+    client
+        .command(["JSON.SET", "doc", "$", r#"{"a":[1,2]}"#])
+        .await
+        .unwrap();
+    client
+        .command(["XADD", "events", "*", "a", "1"])
+        .await
+        .unwrap();
+    let digest = client.command(["DEBUG", "DIGEST"]).await.unwrap();
+    assert_ne!(digest, RedisValue::SimpleString("0".repeat(40).into()));
+
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        if client.command(["DEBUG", "DIGEST"]).await.unwrap() == digest {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("The replica never caught up with its master");
+}
+
+/// Replica started from a dump.rdb holding `foo` and the given replication history
+async fn start_replica_from_dump(
+    name: &str,
+    master: &TestServer,
+    repl_info: ReplInfo,
+) -> TestServer {
+    use bytes::Bytes;
+    use redis_rust::{server::rdb, Args};
+
+    let dir = std::env::temp_dir().join(format!("redis-rust-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut contents = Db::new();
+    contents.insert(Bytes::from("foo"), bulk("bar"), None);
+    let dataset = Dataset::from([(0, contents)]);
+    let dump = rdb::serialize(&dataset, Some(&repl_info)).unwrap();
+    std::fs::write(dir.join("dump.rdb"), dump).unwrap();
+
+    TestServer::start(Args {
+        port: Some(0),
+        dir: Some(dir.to_str().unwrap().to_string()),
+        replicaof: Some(format!("{} {}", master.addr.ip(), master.addr.port())),
+        ..Default::default()
+    })
+    .await
+}
+
+fn master_replid(info: &str) -> &str {
+    info.lines()
+        .find_map(|line| line.strip_prefix("master_replid:"))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn restarted_replica_continues_from_its_saved_dataset() {
+    let master = TestServer::master().await;
+    let replid = master_replid(&info(&master).await).to_string();
+
+    let repl_info = ReplInfo {
+        replid: replid.clone(),
+        offset: 0,
+        stream_db: 0,
+    };
+    let replica = start_replica_from_dump("resume", &master, repl_info).await;
+
+    // --- a full resync would have replaced the dataset with the master's empty one
+    let mut client = replica.client().await;
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+    assert_eq!(master_replid(&info(&replica).await), replid);
+}
+
+#[tokio::test]
+async fn unknown_saved_history_gets_a_full_resync() {
+    let master = TestServer::master().await;
+
+    let repl_info = ReplInfo {
+        replid: "b".repeat(40),
+        offset: 0,
+        stream_db: 0,
+    };
+    let replica = start_replica_from_dump("no-resume", &master, repl_info).await;
+
+    let mut client = replica.client().await;
+    assert_eq!(client.get("foo").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn restarted_master_keeps_its_replication_id() {
+    use redis_rust::Args;
+
+    let dir =
+        std::env::temp_dir().join(format!("redis-rust-master-restart-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let args = || Args {
+        port: Some(0),
+        dir: Some(dir.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+
+    let master = TestServer::start(args()).await;
+    let replid = master_replid(&info(&master).await).to_string();
+    master.client().await.command(["SAVE"]).await.unwrap();
+    drop(master);
+
+    let master = TestServer::start(args()).await;
+    assert_eq!(master_replid(&info(&master).await), replid);
+}
+
+#[tokio::test]
+async fn supervisor_promotes_a_replica_when_the_master_goes_down() {
+    use redis_rust::Args;
+
+    let node = |server: &TestServer| format!("{} {}", server.addr.ip(), server.addr.port());
+    let master = TestServer::master().await;
+    let replicas = [
+        TestServer::replica_of(&master).await,
+        TestServer::replica_of(&master).await,
+    ];
+    let supervisor = TestServer::start(Args {
+        port: Some(0),
+        sentinel: true,
+        supervise_master: Some(node(&master)),
+        supervise_replica: replicas.iter().map(node).collect(),
+        down_after_milliseconds: Some(500),
+        ..Default::default()
+    })
+    .await;
+    let mut events = supervisor.client().await;
+    events
+        .command(["SUBSCRIBE", "+switch-master"])
+        .await
+        .unwrap();
+
+    // --- a master that stopped serving still accepts connections but never answers
+    let old_master = node(&master);
+    drop(master);
+    let mut roles = vec![];
+    for _ in 0..100 {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        roles = vec![info(&replicas[0]).await, info(&replicas[1]).await];
+        let promoted = roles.iter().position(|info| info.contains("role:master"));
+        if let Some(promoted) = promoted {
+            let follower = &roles[1 - promoted];
+            if follower.contains(&format!("master_port:{}", replicas[promoted].addr.port()))
+                && follower.contains("master_link_status:up")
+            {
+                // --- and subscribers were told
+                let event = format!("mymaster {} {}", old_master, node(&replicas[promoted]));
+                assert_eq!(
+                    events.read_reply().await.unwrap(),
+                    RedisValue::Array(vec![bulk("message"), bulk("+switch-master"), bulk(&event)])
+                );
+                return;
+            }
+        }
+    }
+
+    panic!("No failover happened: {:?}", roles);
+}
+
+#[tokio::test]
+async fn sentinel_mode_answers_clients_looking_for_the_master() {
+    use bytes::Bytes;
+    use redis_rust::Args;
+
+    let master = TestServer::master().await;
+    let sentinel = TestServer::start(Args {
+        port: Some(0),
+        sentinel: true,
+        supervise_master: Some(format!("{} {}", master.addr.ip(), master.addr.port())),
+        ..Default::default()
+    })
+    .await;
+    let mut client = sentinel.client().await;
+    let ip = master.addr.ip().to_string();
+    let port = master.addr.port().to_string();
+    let vote = |leader: &'static str, epoch: i64| {
+        RedisValue::Array(vec![
+            RedisValue::Integer(0),
+            bulk(leader),
+            RedisValue::Integer(epoch),
+        ])
+    };
+
+    assert_eq!(
+        client
+            .command(["SENTINEL", "GET-MASTER-ADDR-BY-NAME", "mymaster"])
+            .await
+            .unwrap(),
+        RedisValue::Array(vec![bulk(&ip), bulk(&port)])
+    );
+    assert_eq!(
+        client
+            .command(["SENTINEL", "GET-MASTER-ADDR-BY-NAME", "other"])
+            .await
+            .unwrap(),
+        RedisValue::NullBulkString
+    );
+    assert_eq!(
+        client.command(["ROLE"]).await.unwrap(),
+        RedisValue::Array(vec![
+            bulk("sentinel"),
+            RedisValue::Array(vec![bulk("mymaster")])
+        ])
+    );
+    assert_eq!(
+        client.command(["GET", "foo"]).await.unwrap(),
+        RedisValue::SimpleError(Bytes::from_static(
+            b"ERR unknown command 'get', with args beginning with: 'foo' "
+        ))
+    );
+
+    // --- one vote per epoch, first come first served
+    for (run_id, epoch, expected) in [
+        ("a", "1", vote("a", 1)),
+        ("b", "1", vote("a", 1)),
+        ("b", "2", vote("b", 2)),
+    ] {
+        let cmd = [
+            "SENTINEL",
+            "IS-MASTER-DOWN-BY-ADDR",
+            &ip,
+            &port,
+            epoch,
+            run_id,
+        ];
+        let cmd = cmd.map(|part| Bytes::from(part.to_string()));
+        assert_eq!(client.command(cmd).await.unwrap(), expected);
+    }
+}
+
+#[tokio::test]
+async fn sentinels_elect_a_leader_and_agree_on_the_new_master() {
+    use redis_rust::Args;
+
+    let node = |addr: std::net::SocketAddr| format!("{} {}", addr.ip(), addr.port());
+    let free_addr = || {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+    let sentinel_addrs = [free_addr(), free_addr()];
+    let sentinel = |addr: std::net::SocketAddr, peer: std::net::SocketAddr| {
+        TestServer::start(Args {
+            port: Some(addr.port() as usize),
+            sentinel: true,
+            supervise_master: Some(node(master.addr)),
+            supervise_replica: vec![node(replica.addr)],
+            sentinel_peer: vec![node(peer)],
+            down_after_milliseconds: Some(300),
+            quorum: Some(2),
+            ..Default::default()
+        })
+    };
+    let sentinels = [
+        sentinel(sentinel_addrs[0], sentinel_addrs[1]).await,
+        sentinel(sentinel_addrs[1], sentinel_addrs[0]).await,
+    ];
+
+    drop(master);
+    let expected = RedisValue::Array(vec![
+        bulk(&replica.addr.ip().to_string()),
+        bulk(&replica.addr.port().to_string()),
+    ]);
+    for _ in 0..200 {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let mut agreed = info(&replica).await.contains("role:master");
+        for sentinel in sentinels.iter() {
+            let cmd = ["SENTINEL", "GET-MASTER-ADDR-BY-NAME", "mymaster"];
+            agreed &= sentinel.client().await.command(cmd).await.unwrap() == expected;
+        }
+        if agreed {
+            return;
+        }
+    }
+
+    panic!("Sentinels didn't agree on the promoted replica");
+}
+
+#[tokio::test]
+async fn replica_reaches_its_master_by_host_name() {
+    use redis_rust::Args;
+
+    let master = TestServer::master().await;
+    // --- "localhost" may resolve to ::1 first, where nothing listens
+    let replica = TestServer::start(Args {
+        port: Some(0),
+        replicaof: Some(format!("localhost {}", master.addr.port())),
+        ..Default::default()
+    })
+    .await;
+
+    let replica_info = info(&replica).await;
+    assert!(replica_info.contains("master_host:localhost\r\n"));
+    assert!(replica_info.contains("master_link_status:up\r\n"));
+
+    master.client().await.set("foo", "bar").await.unwrap();
+    let mut client = replica.client().await;
+    for _ in 0..100 {
+        if client.get("foo").await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        client.get("foo").await.unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+}
+
+#[tokio::test]
+async fn wait_counts_the_replicas_that_acknowledged_the_writes() {
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+    while !info(&master).await.contains("connected_slaves:1\r\n") {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let mut client = master.client().await;
+    client.set("foo", "bar").await.unwrap();
+    assert_eq!(
+        client.command(["WAIT", "1", "0"]).await.unwrap(),
+        RedisValue::Integer(1)
+    );
+    // --- the ACK shows up as the replica's offset
+    assert!(!info(&master).await.contains(",offset=0\r\n"));
+
+    client.set("foo", "baz").await.unwrap();
+    assert_eq!(
+        client.command(["WAIT", "2", "50"]).await.unwrap(),
+        RedisValue::Integer(1)
+    );
+
+    assert_eq!(
+        replica
+            .client()
+            .await
+            .command(["WAIT", "0", "0"])
+            .await
+            .unwrap(),
+        RedisValue::SimpleError("ERR WAIT cannot be used with replica instances.".into())
+    );
+    for args in [["1"].as_slice(), &["x", "0"], &["1", "-1"]] {
+        assert!(matches!(
+            client
+                .command(["WAIT"].iter().chain(args).copied())
+                .await
+                .unwrap(),
+            RedisValue::SimpleError(_)
+        ));
+    }
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_replicas_to_catch_up() {
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+    while !info(&master).await.contains("connected_slaves:1\r\n") {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let mut client = master.client().await;
+    client.set("foo", "bar").await.unwrap();
+    assert_eq!(
+        client.command(["SHUTDOWN", "NOSAVE"]).await.unwrap(),
+        RedisValue::SimpleString("OK".into())
+    );
+    // --- acknowledged before the shutdown went through, no need to wait
+    assert_eq!(
+        replica
+            .client()
+            .await
+            .command(["GET", "foo"])
+            .await
+            .unwrap(),
+        bulk("bar")
+    );
+}
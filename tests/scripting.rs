@@ -0,0 +1,479 @@
+mod common;
+
+use bytes::Bytes;
+use common::{assert_replies, bulk, simple, TestServer};
+use redis_rust::{repl::ServerContext, RedisValue};
+
+fn error(message: &str) -> RedisValue {
+    RedisValue::SimpleError(message.to_string().into())
+}
+
+#[tokio::test]
+async fn eval_runs_scripts_with_keys_and_arguments() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["EVAL", "return 1", "0"], RedisValue::Integer(1)),
+            (
+                &[
+                    "EVAL",
+                    "return {KEYS[1], KEYS[2], ARGV[1]}",
+                    "2",
+                    "a",
+                    "b",
+                    "c",
+                ],
+                RedisValue::Array(vec![bulk("a"), bulk("b"), bulk("c")]),
+            ),
+            (
+                &[
+                    "EVAL",
+                    "return redis.call('SET', KEYS[1], ARGV[1])",
+                    "1",
+                    "k",
+                    "v",
+                ],
+                simple("OK"),
+            ),
+            (
+                &["EVAL", "return redis.call('get', KEYS[1])", "1", "k"],
+                bulk("v"),
+            ),
+            // --- numbers are truncated, false and missing keys become nil, arrays stop at the
+            // first nil
+            (&["EVAL", "return 3.99", "0"], RedisValue::Integer(3)),
+            (
+                &["EVAL", "return redis.call('GET', 'missing')", "0"],
+                RedisValue::NullBulkString,
+            ),
+            (
+                &["EVAL", "return {1, 'two', nil, 4}", "0"],
+                RedisValue::Array(vec![RedisValue::Integer(1), bulk("two")]),
+            ),
+            (
+                &["EVAL", "return redis.call('INCRBY', 'n', 5) + 1", "0"],
+                RedisValue::Integer(6),
+            ),
+            (
+                &["EVAL", "return redis.status_reply('FINE')", "0"],
+                simple("FINE"),
+            ),
+            (
+                &["EVAL", "return redis.error_reply('MY failure')", "0"],
+                error("MY failure"),
+            ),
+            // --- redis.call raises the command's error, redis.pcall returns it
+            (
+                &["EVAL", "return redis.call('INCR', KEYS[1])", "1", "k"],
+                error("ERR value is not an integer or out of range"),
+            ),
+            (
+                &[
+                    "EVAL",
+                    "local reply = redis.pcall('INCR', KEYS[1]) return reply.err",
+                    "1",
+                    "k",
+                ],
+                bulk("ERR value is not an integer or out of range"),
+            ),
+            (
+                &["EVAL", "return redis.sha1hex('')", "0"],
+                bulk("da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            ),
+            (
+                &["EVAL", "return 1", "2", "a"],
+                error("ERR Number of keys can't be greater than number of args"),
+            ),
+            (
+                &["EVAL", "return 1", "-1"],
+                error("ERR Number of keys can't be negative"),
+            ),
+            (
+                &["EVAL", "return redis.call('EXEC')", "0"],
+                error("ERR This Redis command is not allowed from script"),
+            ),
+            (
+                &["EVAL", "return redis.call('MULTI')", "0"],
+                error("ERR This Redis command is not allowed from script"),
+            ),
+        ],
+    )
+    .await;
+
+    let RedisValue::SimpleError(message) = client.command(["EVAL", "return (", "0"]).await.unwrap()
+    else {
+        panic!("A script that doesn't compile should fail");
+    };
+    assert!(message.starts_with(b"ERR Error compiling script"));
+    // --- nothing reaches the file system
+    let script = "return dofile('/etc/passwd')";
+    assert_eq!(
+        client.command(["EVAL", script, "0"]).await.unwrap(),
+        error(&format!(
+            "ERR Error running script (call to f_{}): user_script:1: \
+             Script attempted to access nonexistent global variable 'dofile'",
+            redis_rust::server::scripting::sha1_hex(script.as_bytes())
+        ))
+    );
+    // --- nor compiles code at run time, creates globals or lifts the lock on them
+    for (script, message) in [
+        (
+            "return loadstring('return 1')()",
+            "Script attempted to access nonexistent global variable 'loadstring'",
+        ),
+        (
+            "counter = 1 return counter",
+            "Script attempted to create global variable 'counter'",
+        ),
+        (
+            "setmetatable(_G, nil) counter = 1 return counter",
+            "cannot change a protected metatable",
+        ),
+    ] {
+        assert_eq!(
+            client.command(["EVAL", script, "0"]).await.unwrap(),
+            error(&format!(
+                "ERR Error running script (call to f_{}): user_script:1: {}",
+                redis_rust::server::scripting::sha1_hex(script.as_bytes()),
+                message
+            ))
+        );
+    }
+    // --- and runs out of memory well before the server does
+    let script = "local chunk = string.rep('x', 1024 * 1024) local chunks = {} \
+                  for i = 1, 1024 do chunks[i] = chunk .. i end return #chunks";
+    let RedisValue::SimpleError(message) = client.command(["EVAL", script, "0"]).await.unwrap()
+    else {
+        panic!("A script allocating past the limit should fail");
+    };
+    assert!(message.ends_with(b"not enough memory"), "{:?}", message);
+    assert_eq!(
+        client.command(["EVAL", "return 1", "0"]).await.unwrap(),
+        RedisValue::Integer(1)
+    );
+}
+
+#[tokio::test]
+async fn evalsha_runs_cached_scripts() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let sha = "1fa00e76656cc152ad327c13fe365858fd7be306";
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["EVALSHA", sha, "0"],
+                error("NOSCRIPT No matching script. Please use EVAL."),
+            ),
+            (&["SCRIPT", "LOAD", "return 42"], bulk(sha)),
+            (
+                &[
+                    "SCRIPT",
+                    "EXISTS",
+                    sha,
+                    "ffffffffffffffffffffffffffffffffffffffff",
+                ],
+                RedisValue::Array(vec![RedisValue::Integer(1), RedisValue::Integer(0)]),
+            ),
+            (&["EVALSHA", sha, "0"], RedisValue::Integer(42)),
+            (
+                &["EVALSHA", "1FA00E76656CC152AD327C13FE365858FD7BE306", "0"],
+                RedisValue::Integer(42),
+            ),
+            (&["SCRIPT", "FLUSH"], simple("OK")),
+            (
+                &["SCRIPT", "EXISTS", sha],
+                RedisValue::Array(vec![RedisValue::Integer(0)]),
+            ),
+            // --- EVAL caches what it runs
+            (&["EVAL", "return 42", "0"], RedisValue::Integer(42)),
+            (&["EVALSHA", sha, "0"], RedisValue::Integer(42)),
+            (&["SCRIPT", "FLUSH", "ASYNC"], simple("OK")),
+            (
+                &["SCRIPT", "FLUSH", "LATER"],
+                error("ERR wrong number of arguments for 'script|flush' command"),
+            ),
+        ],
+    )
+    .await;
+
+    let RedisValue::SimpleError(message) = client
+        .command(["SCRIPT", "LOAD", "return ("])
+        .await
+        .unwrap()
+    else {
+        panic!("A script that doesn't compile should not load");
+    };
+    assert!(message.starts_with(b"ERR Error compiling script"));
+}
+
+#[tokio::test]
+async fn other_clients_never_see_a_script_halfway() {
+    let server = TestServer::master().await;
+    let mut writer = server.client().await;
+    let mut reader = server.client().await;
+
+    let reads = tokio::spawn(async move {
+        let mut seen = vec![];
+        for _ in 0..200 {
+            seen.push(reader.get("counter").await.unwrap());
+        }
+        seen
+    });
+    let script = "for i = 1, 100 do redis.call('INCR', KEYS[1]) end";
+    writer
+        .command(["EVAL", script, "1", "counter"])
+        .await
+        .unwrap();
+
+    for value in reads.await.unwrap() {
+        assert!(
+            matches!(value.as_deref(), None | Some(b"100")),
+            "{:?}",
+            value
+        );
+    }
+}
+
+#[tokio::test]
+async fn script_writes_reach_replicas_wrapped_in_multi_and_exec() {
+    let master = TestServer::master().await;
+    let mut client = master.client().await;
+
+    let script = "redis.call('SET', KEYS[1], ARGV[1]) \
+                  redis.call('GET', KEYS[1]) \
+                  return redis.call('INCR', KEYS[1])";
+    client
+        .command(["EVAL", script, "1", "a", "1"])
+        .await
+        .unwrap();
+    // --- reads alone go nowhere
+    client
+        .command(["EVAL", "return redis.call('GET', 'a')", "0"])
+        .await
+        .unwrap();
+    // --- inside a transaction the script's writes join its block
+    client.command(["MULTI"]).await.unwrap();
+    client
+        .command(["EVAL", "return redis.call('INCR', 'a')", "0"])
+        .await
+        .unwrap();
+    client.command(["EXEC"]).await.unwrap();
+
+    let ServerContext::Master(ctx) = master.server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    let stream = ctx.backlog.lock().unwrap().range_from(1).unwrap();
+    let expected = [
+        vec![bulk("SELECT"), bulk("0")],
+        vec![bulk("MULTI")],
+        vec![bulk("SET"), bulk("a"), bulk("1")],
+        vec![bulk("INCR"), bulk("a")],
+        vec![bulk("EXEC")],
+        vec![bulk("MULTI")],
+        vec![bulk("INCR"), bulk("a")],
+        vec![bulk("EXEC")],
+    ]
+    .into_iter()
+    .flat_map(|command| RedisValue::Array(command).serialize().unwrap())
+    .collect::<Vec<_>>();
+    assert_eq!(&stream[..], &expected[..]);
+}
+
+#[tokio::test]
+async fn set_repl_picks_where_script_writes_go() {
+    let master = TestServer::master().await;
+    let mut client = master.client().await;
+
+    let script = "redis.set_repl(redis.REPL_AOF) \
+                  redis.call('SET', 'a', '1') \
+                  redis.set_repl(redis.REPL_NONE) \
+                  redis.call('SET', 'b', '2') \
+                  redis.set_repl(redis.REPL_ALL) \
+                  return redis.call('SET', 'c', '3')";
+    assert_replies(&mut client, &[(&["EVAL", script, "0"], simple("OK"))]).await;
+    let reply = client
+        .command(["EVAL", "redis.set_repl(7)", "0"])
+        .await
+        .unwrap();
+    let RedisValue::SimpleError(message) = reply else {
+        panic!("Should be an error: {:?}", reply);
+    };
+    assert!(String::from_utf8_lossy(&message).contains("Invalid replication flags"));
+
+    let ServerContext::Master(ctx) = master.server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    let stream = ctx.backlog.lock().unwrap().range_from(1).unwrap();
+    let expected = [
+        vec![bulk("SELECT"), bulk("0")],
+        vec![bulk("MULTI")],
+        vec![bulk("SET"), bulk("c"), bulk("3")],
+        vec![bulk("EXEC")],
+    ]
+    .into_iter()
+    .flat_map(|command| RedisValue::Array(command).serialize().unwrap())
+    .collect::<Vec<_>>();
+    assert_eq!(&stream[..], &expected[..]);
+}
+
+#[tokio::test]
+async fn scripts_replicate_verbatim_without_lua_replicate_commands() {
+    let master = TestServer::master().await;
+    let replica = TestServer::replica_of(&master).await;
+    let mut client = master.client().await;
+    let mut replica_client = replica.client().await;
+
+    let script = "return redis.call('INCRBY', KEYS[1], ARGV[1])";
+    let RedisValue::BulkString(sha) = client.command(["SCRIPT", "LOAD", script]).await.unwrap()
+    else {
+        panic!("Should be the script's SHA1");
+    };
+    assert_replies(
+        &mut client,
+        &[(
+            &["CONFIG", "SET", "lua-replicate-commands", "no"],
+            simple("OK"),
+        )],
+    )
+    .await;
+    client
+        .command([
+            Bytes::from_static(b"EVALSHA"),
+            sha,
+            "1".into(),
+            "n".into(),
+            "5".into(),
+        ])
+        .await
+        .unwrap();
+    // --- reads alone go nowhere
+    client
+        .command(["EVAL", "return redis.call('GET', 'n')", "0"])
+        .await
+        .unwrap();
+    let reply = client
+        .command(["EVAL", "redis.set_repl(redis.REPL_ALL)", "0"])
+        .await
+        .unwrap();
+    let RedisValue::SimpleError(message) = reply else {
+        panic!("Should be an error: {:?}", reply);
+    };
+    assert!(String::from_utf8_lossy(&message).contains("requires command replication mode"));
+
+    let ServerContext::Master(ctx) = master.server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    let stream = ctx.backlog.lock().unwrap().range_from(1).unwrap();
+    let expected = [
+        vec![bulk("SELECT"), bulk("0")],
+        vec![bulk("EVAL"), bulk(script), bulk("1"), bulk("n"), bulk("5")],
+    ]
+    .into_iter()
+    .flat_map(|command| RedisValue::Array(command).serialize().unwrap())
+    .collect::<Vec<_>>();
+    assert_eq!(&stream[..], &expected[..]);
+    // --- the replica runs the script itself
+    for _ in 0..100 {
+        if replica_client.get("n").await.unwrap().is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_replies(&mut replica_client, &[(&["GET", "n"], bulk("5"))]).await;
+}
+
+#[tokio::test]
+async fn scripts_running_too_long_get_other_clients_busy_replies_until_killed() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let mut runner = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["SCRIPT", "KILL"],
+                error("NOTBUSY No scripts in execution right now."),
+            ),
+            (&["CONFIG", "SET", "lua-time-limit", "100"], simple("OK")),
+        ],
+    )
+    .await;
+    let script = tokio::spawn(async move {
+        runner
+            .command(["EVAL", "while true do end", "0"])
+            .await
+            .unwrap()
+    });
+    server.server.running_script.overrun().await;
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["GET", "k"],
+                error(
+                    "BUSY Redis is busy running a script. You can only call SCRIPT KILL or \
+                     SHUTDOWN NOSAVE.",
+                ),
+            ),
+            (&["SCRIPT", "KILL"], simple("OK")),
+        ],
+    )
+    .await;
+    assert_eq!(
+        script.await.unwrap(),
+        error("ERR Script killed by user with SCRIPT KILL...")
+    );
+    assert_replies(&mut client, &[(&["GET", "k"], RedisValue::NullBulkString)]).await;
+}
+
+#[tokio::test]
+async fn scripts_that_wrote_can_only_be_stopped_by_shutdown_nosave() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let mut runner = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[(
+            &["CONFIG", "SET", "busy-reply-threshold", "100"],
+            simple("OK"),
+        )],
+    )
+    .await;
+    let script = tokio::spawn(async move {
+        runner
+            .command(["EVAL", "redis.call('SET', 'k', 'v') while true do end", "0"])
+            .await
+    });
+    server.server.running_script.overrun().await;
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["SHUTDOWN"],
+                error(
+                    "BUSY Redis is busy running a script. You can only call SCRIPT KILL or \
+                     SHUTDOWN NOSAVE.",
+                ),
+            ),
+            (
+                &["SCRIPT", "KILL"],
+                error(
+                    "UNKILLABLE Sorry the script already executed write commands against the \
+                     dataset. You can either wait the script termination or kill the server \
+                     in a hard way using the SHUTDOWN NOSAVE command.",
+                ),
+            ),
+        ],
+    )
+    .await;
+    let _ = client.command(["SHUTDOWN", "NOSAVE"]).await;
+    // --- the script is stopped and its client disconnected with the others
+    let _ = script.await.unwrap();
+}
@@ -0,0 +1,254 @@
+mod common;
+
+use common::{assert_replies, bulk, simple, TestServer};
+use redis_rust::RedisValue;
+
+fn keys(keys: &[&str]) -> RedisValue {
+    let mut res = vec![RedisValue::Integer(keys.len() as i64)];
+    res.extend(keys.iter().map(|key| bulk(key)));
+
+    RedisValue::Array(res)
+}
+
+#[tokio::test]
+async fn indexes_follow_the_documents_they_cover() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["JSON.SET", "user:1", "$", r#"{"age":31,"city":"Lisbon"}"#],
+                simple("OK"),
+            ),
+            (
+                &[
+                    "JSON.SET",
+                    "user:2",
+                    "$",
+                    r#"{"age":17,"city":"Porto, Braga"}"#,
+                ],
+                simple("OK"),
+            ),
+            (
+                &["JSON.SET", "other:1", "$", r#"{"age":40,"city":"Lisbon"}"#],
+                simple("OK"),
+            ),
+            (
+                &[
+                    "FT.CREATE",
+                    "users",
+                    "ON",
+                    "JSON",
+                    "PREFIX",
+                    "1",
+                    "user:",
+                    "SCHEMA",
+                    "$.age",
+                    "AS",
+                    "age",
+                    "NUMERIC",
+                    "$.city",
+                    "AS",
+                    "city",
+                    "TAG",
+                ],
+                simple("OK"),
+            ),
+            // --- documents written afterwards are picked up too
+            (
+                &[
+                    "JSON.SET",
+                    "user:3",
+                    "$",
+                    r#"{"age":65,"city":["lisbon","Faro"]}"#,
+                ],
+                simple("OK"),
+            ),
+            (
+                &["FT.SEARCH", "users", "*", "NOCONTENT"],
+                keys(&["user:1", "user:2", "user:3"]),
+            ),
+            (
+                &["FT.SEARCH", "users", "@age:[18 +inf]", "NOCONTENT"],
+                keys(&["user:1", "user:3"]),
+            ),
+            (
+                &["FT.SEARCH", "users", "@age:[-inf (31]", "NOCONTENT"],
+                keys(&["user:2"]),
+            ),
+            (
+                &["FT.SEARCH", "users", "@city:{braga | faro}", "NOCONTENT"],
+                keys(&["user:2", "user:3"]),
+            ),
+            (
+                &[
+                    "FT.SEARCH",
+                    "users",
+                    "@city:{LISBON} @age:[0 50]",
+                    "NOCONTENT",
+                ],
+                keys(&["user:1"]),
+            ),
+            (
+                &["FT.SEARCH", "users", "@age:[60 70]"],
+                RedisValue::Array(vec![
+                    RedisValue::Integer(1),
+                    bulk("user:3"),
+                    RedisValue::Array(vec![
+                        bulk("$"),
+                        bulk(r#"{"age":65,"city":["lisbon","Faro"]}"#),
+                    ]),
+                ]),
+            ),
+            // --- updates and deletions move documents around
+            (&["JSON.SET", "user:1", "$.age", "12"], simple("OK")),
+            (&["JSON.DEL", "user:3"], RedisValue::Integer(1)),
+            (
+                &[
+                    "FT.SEARCH",
+                    "users",
+                    "@age:[0 20]",
+                    "NOCONTENT",
+                    "LIMIT",
+                    "1",
+                    "5",
+                ],
+                RedisValue::Array(vec![RedisValue::Integer(2), bulk("user:2")]),
+            ),
+            (
+                &["FT.SEARCH", "users", "@city:{lisbon}", "NOCONTENT"],
+                keys(&["user:1"]),
+            ),
+            (&["FT._LIST"], RedisValue::Array(vec![bulk("users")])),
+            (&["FT.DROPINDEX", "users"], simple("OK")),
+            (
+                &["FT.SEARCH", "users", "*"],
+                RedisValue::SimpleError("ERR users: no such index".into()),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn hashes_are_indexed_as_they_change() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["HSET", "item:1", "price", "10", "tags", "red,big"],
+                RedisValue::Integer(2),
+            ),
+            (
+                &["JSON.SET", "item:2", "$", r#"{"price":10}"#],
+                simple("OK"),
+            ),
+            (
+                &[
+                    "FT.CREATE",
+                    "items",
+                    "ON",
+                    "HASH",
+                    "PREFIX",
+                    "1",
+                    "item:",
+                    "SCHEMA",
+                    "price",
+                    "NUMERIC",
+                    "tags",
+                    "AS",
+                    "tag",
+                    "TAG",
+                ],
+                simple("OK"),
+            ),
+            // --- JSON documents are left to JSON indexes
+            (
+                &["FT.SEARCH", "items", "@price:[0 20]"],
+                RedisValue::Array(vec![
+                    RedisValue::Integer(1),
+                    bulk("item:1"),
+                    RedisValue::Array(vec![
+                        bulk("price"),
+                        bulk("10"),
+                        bulk("tags"),
+                        bulk("red,big"),
+                    ]),
+                ]),
+            ),
+            (
+                &["HSET", "item:3", "price", "cheap"],
+                RedisValue::Integer(1),
+            ),
+            (&["HSET", "item:4", "tags", "Red"], RedisValue::Integer(1)),
+            (
+                &["FT.SEARCH", "items", "@tag:{red}", "NOCONTENT"],
+                keys(&["item:1", "item:4"]),
+            ),
+            (
+                &["FT.SEARCH", "items", "*", "NOCONTENT"],
+                keys(&["item:1", "item:3", "item:4"]),
+            ),
+            // --- HSET and HDEL move them around, DEL takes them out
+            (&["HSET", "item:1", "price", "30"], RedisValue::Integer(0)),
+            (&["HSET", "item:3", "price", "5"], RedisValue::Integer(0)),
+            (
+                &["FT.SEARCH", "items", "@price:[0 20]", "NOCONTENT"],
+                keys(&["item:3"]),
+            ),
+            (&["HDEL", "item:1", "tags"], RedisValue::Integer(1)),
+            (&["DEL", "item:4"], RedisValue::Integer(1)),
+            (
+                &["FT.SEARCH", "items", "@tag:{red}", "NOCONTENT"],
+                keys(&[]),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn bad_definitions_and_queries_are_refused() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (
+                &["FT.CREATE", "idx", "ON", "XML", "SCHEMA", "name", "TAG"],
+                RedisValue::SimpleError("ERR syntax error".into()),
+            ),
+            (
+                &["FT.CREATE", "idx", "SCHEMA", "$.name", "TEXT"],
+                RedisValue::SimpleError("ERR unsupported field type 'TEXT'".into()),
+            ),
+            (
+                &["FT.CREATE", "idx", "SCHEMA", "$.n", "AS", "n", "NUMERIC"],
+                simple("OK"),
+            ),
+            (
+                &["FT.CREATE", "idx", "SCHEMA", "$.n", "AS", "n", "NUMERIC"],
+                RedisValue::SimpleError("ERR Index already exists".into()),
+            ),
+            (
+                &["FT.SEARCH", "idx", "@n:{a}"],
+                RedisValue::SimpleError("ERR field 'n' can't be queried this way".into()),
+            ),
+            (
+                &["FT.SEARCH", "idx", "@m:[1 2]"],
+                RedisValue::SimpleError("ERR unknown field 'm'".into()),
+            ),
+            (
+                &["FT.SEARCH", "idx", "@n:[1]"],
+                RedisValue::SimpleError("ERR numeric ranges take a min and a max: '1'".into()),
+            ),
+        ],
+    )
+    .await;
+}
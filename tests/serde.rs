@@ -0,0 +1,193 @@
+use bytes::{Bytes, BytesMut};
+use proptest::prelude::*;
+use redis_rust::{
+    server::serde::{tokenize, ProtocolLimits, RespParser},
+    RedisValue,
+};
+
+/// Single-line payload for simple strings and errors
+fn line() -> impl Strategy<Value = Bytes> {
+    "[^\r\n]{0,32}".prop_map(Bytes::from)
+}
+
+fn redis_value() -> impl Strategy<Value = RedisValue> {
+    let leaf = prop_oneof![
+        line().prop_map(RedisValue::SimpleString),
+        line().prop_map(RedisValue::SimpleError),
+        any::<i64>().prop_map(RedisValue::Integer),
+        prop::collection::vec(any::<u8>(), 0..64)
+            .prop_map(|b| RedisValue::BulkString(Bytes::from(b))),
+        Just(RedisValue::NullBulkString),
+        Just(RedisValue::NullArray),
+    ];
+
+    leaf.prop_recursive(4, 64, 8, |inner| {
+        prop::collection::vec(inner, 0..8).prop_map(RedisValue::Array)
+    })
+}
+
+/// Parses the first frame in `buf`, returning it with the number of bytes it took
+fn parse(buf: &BytesMut) -> Option<(RedisValue, usize)> {
+    let tok = tokenize(buf, 0).expect("Valid RESP should tokenize")?;
+    let frame = Bytes::copy_from_slice(&buf[..tok.1]);
+
+    Some((RedisValue::from_token(tok.0, &frame), tok.1))
+}
+
+proptest! {
+    #[test]
+    fn serialize_then_parse_round_trips(value in redis_value()) {
+        let data = BytesMut::from(&value.clone().serialize().unwrap()[..]);
+
+        let (parsed, consumed) = parse(&data).expect("Complete frame should parse");
+        prop_assert_eq!(parsed, value);
+        prop_assert_eq!(consumed, data.len());
+    }
+
+    #[test]
+    fn truncated_frames_wait_for_more_data(value in redis_value(), cut in any::<prop::sample::Index>()) {
+        let data = value.serialize().unwrap();
+        let prefix = BytesMut::from(&data[..cut.index(data.len())]);
+
+        prop_assert_eq!(tokenize(&prefix, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn pipelined_frames_parse_in_order(first in redis_value(), second in redis_value()) {
+        let mut data = BytesMut::from(&first.clone().serialize().unwrap()[..]);
+        data.extend_from_slice(&second.clone().serialize().unwrap());
+
+        let (parsed, consumed) = parse(&data).unwrap();
+        prop_assert_eq!(parsed, first);
+
+        let rest = data.split_off(consumed);
+        let (parsed, _) = parse(&rest).unwrap();
+        prop_assert_eq!(parsed, second);
+    }
+}
+
+#[test]
+fn empty_and_binary_bulk_strings() {
+    for payload in [&b""[..], b"\r\n", b"\x00\xff\xfe", b"$3\r\nfoo"] {
+        let value = RedisValue::BulkString(Bytes::copy_from_slice(payload));
+        let data = BytesMut::from(&value.clone().serialize().unwrap()[..]);
+
+        assert_eq!(parse(&data).map(|(v, _)| v), Some(value));
+    }
+}
+
+#[test]
+fn simple_strings_reject_line_breaks() {
+    assert!(RedisValue::SimpleString(Bytes::from_static(b"a\r\nb"))
+        .serialize()
+        .is_err());
+}
+
+proptest! {
+    #[test]
+    fn frames_fed_in_chunks_parse_as_a_whole(
+        value in redis_value(),
+        chunk in 1..16usize,
+    ) {
+        let data = value.clone().serialize().unwrap();
+        let mut parser = RespParser::default();
+        let mut buf = BytesMut::new();
+
+        let mut token = None;
+        for piece in data.chunks(chunk) {
+            prop_assert!(token.is_none());
+            buf.extend_from_slice(piece);
+            token = parser.parse(&buf, &ProtocolLimits::default()).unwrap();
+        }
+        let token = token.expect("Complete frame should parse");
+        prop_assert_eq!(token.1, data.len());
+        prop_assert_eq!(RedisValue::from_token(token.0, &buf.freeze()), value);
+    }
+}
+
+#[test]
+fn resumed_parser_moves_on_to_the_next_frame() {
+    let mut parser = RespParser::default();
+    let limits = ProtocolLimits::default();
+    let mut buf = BytesMut::from(&b"*2\r\n$3\r\nGET\r\n$3\r\nf"[..]);
+    assert_eq!(parser.parse(&buf, &limits).unwrap(), None);
+
+    buf.extend_from_slice(b"oo\r\n*1\r\n$4\r\nPING\r\n");
+    let token = parser.parse(&buf, &limits).unwrap().unwrap();
+    let frame = buf.split_to(token.1).freeze();
+    assert_eq!(
+        RedisValue::from_token(token.0, &frame),
+        RedisValue::Array(vec![
+            RedisValue::BulkString(Bytes::from_static(b"GET")),
+            RedisValue::BulkString(Bytes::from_static(b"foo")),
+        ])
+    );
+
+    let token = parser.parse(&buf, &limits).unwrap().unwrap();
+    assert_eq!(token.1, buf.len());
+}
+
+#[test]
+fn oversized_bulk_strings_are_refused_before_their_payload() {
+    let mut parser = RespParser::default();
+    let limits = ProtocolLimits {
+        max_bulk_len: 8,
+        ..ProtocolLimits::default()
+    };
+
+    assert!(parser
+        .parse(&BytesMut::from(&b"*1\r\n$9\r\n"[..]), &limits)
+        .is_err());
+}
+
+#[test]
+fn inline_commands_parse_as_arrays_of_their_words() {
+    let mut parser = RespParser::default();
+    let limits = ProtocolLimits::default();
+    let mut buf = BytesMut::from(&b"SET  foo\tbar"[..]);
+    assert_eq!(parser.parse(&buf, &limits).unwrap(), None);
+
+    buf.extend_from_slice(b"\r\nPING\n\r\n");
+    let token = parser.parse(&buf, &limits).unwrap().unwrap();
+    let frame = buf.split_to(token.1).freeze();
+    assert_eq!(
+        RedisValue::from_token(token.0, &frame),
+        RedisValue::Array(vec![
+            RedisValue::BulkString(Bytes::from_static(b"SET")),
+            RedisValue::BulkString(Bytes::from_static(b"foo")),
+            RedisValue::BulkString(Bytes::from_static(b"bar")),
+        ])
+    );
+    let token = parser.parse(&buf, &limits).unwrap().unwrap();
+    let frame = buf.split_to(token.1).freeze();
+    assert_eq!(
+        RedisValue::from_token(token.0, &frame),
+        RedisValue::Array(vec![RedisValue::BulkString(Bytes::from_static(b"PING"))])
+    );
+    // --- a blank line is an empty request
+    let token = parser.parse(&buf, &limits).unwrap().unwrap();
+    assert_eq!(token.0, redis_rust::server::serde::RESPRaw::Array(vec![]));
+    assert_eq!(token.1, buf.len());
+
+    // --- quotes and escapes as redis-cli writes them
+    let buf = BytesMut::from(&b"SET \"a \\\"b\\\"\\x41\\n\" 'it\\'s' k\"\"\r\n"[..]);
+    let token = RespParser::default().parse(&buf, &limits).unwrap().unwrap();
+    assert_eq!(
+        RedisValue::from_token(token.0, &buf.freeze()),
+        RedisValue::Array(vec![
+            RedisValue::BulkString(Bytes::from_static(b"SET")),
+            RedisValue::BulkString(Bytes::from_static(b"a \"b\"A\n")),
+            RedisValue::BulkString(Bytes::from_static(b"it's")),
+            RedisValue::BulkString(Bytes::from_static(b"k")),
+        ])
+    );
+    for unbalanced in [&b"GET \"a\n"[..], b"GET 'a\n", b"GET \"a\"b\n"] {
+        let buf = BytesMut::from(unbalanced);
+        let error = RespParser::default().parse(&buf, &limits).unwrap_err();
+        assert_eq!(error.to_string(), "unbalanced quotes in request");
+    }
+
+    let mut parser = RespParser::default();
+    let endless = BytesMut::from(&vec![b'a'; 64 * 1024 + 1][..]);
+    assert!(parser.parse(&endless, &limits).is_err());
+}
@@ -0,0 +1,38 @@
+use redis_rust::server::split::split_args;
+
+#[test]
+fn lines_split_the_way_sdssplitargs_does() {
+    let cases: &[(&[u8], &[&[u8]])] = &[
+        (b"", &[]),
+        (b"  \t ", &[]),
+        (b"set k v", &[b"set", b"k", b"v"]),
+        (b"  set\tk   v\r\n", &[b"set", b"k", b"v"]),
+        (br#"set k "a b""#, &[b"set", b"k", b"a b"]),
+        (br#""\n\r\t\b\a\"\\""#, &[b"\n\r\t\x08\x07\"\\"]),
+        (br#""\x41\xc3\xa9\xff""#, &[b"A\xc3\xa9\xff"]),
+        // --- an \x without two hex digits is just an x
+        (br#""\xzz" "\x4""#, &[b"xzz", b"x4"]),
+        (br#"'it\'s \n'"#, &[br"it's \n"]),
+        (br#"pre"fix" ''"#, &[b"prefix", b""]),
+    ];
+    for (line, expected) in cases {
+        let args = split_args(line)
+            .unwrap()
+            .into_iter()
+            .map(|arg| arg.into_bytes(line))
+            .collect::<Vec<_>>();
+        assert_eq!(args, *expected, "{:?}", String::from_utf8_lossy(line));
+    }
+}
+
+#[test]
+fn unbalanced_quotes_split_to_nothing() {
+    for line in [&br#"set k "v"#[..], b"set k 'v", br#""a"b"#, b"'a'b"] {
+        assert_eq!(
+            split_args(line),
+            None,
+            "{:?}",
+            String::from_utf8_lossy(line)
+        );
+    }
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use bytes::Bytes;
+use common::{assert_replies, bulk, simple, TestServer};
+use redis_rust::{
+    server::{
+        db::Db,
+        rdb,
+        server::Dataset,
+        timeseries::{Aggregation, Rule, Sample, TimeSeries},
+    },
+    Redis, RedisValue,
+};
+
+fn sample(timestamp: i64, value: &str) -> RedisValue {
+    RedisValue::Array(vec![RedisValue::Integer(timestamp), bulk(value)])
+}
+
+fn samples(samples: &[(i64, &str)]) -> RedisValue {
+    RedisValue::Array(
+        samples
+            .iter()
+            .map(|(timestamp, value)| sample(*timestamp, value))
+            .collect(),
+    )
+}
+
+fn error(message: &str) -> RedisValue {
+    RedisValue::SimpleError(message.to_string().into())
+}
+
+#[tokio::test]
+async fn samples_are_added_and_read_back_by_range() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["TS.ADD", "temp", "1000", "20"], RedisValue::Integer(1000)),
+            (&["TS.ADD", "temp", "1500", "22.5"], RedisValue::Integer(1500)),
+            (&["TS.ADD", "temp", "2100", "19"], RedisValue::Integer(2100)),
+            // --- late samples go in place
+            (&["TS.ADD", "temp", "1200", "21"], RedisValue::Integer(1200)),
+            (
+                &["TS.ADD", "temp", "1200", "30"],
+                error("ERR TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode"),
+            ),
+            (&["TS.GET", "temp"], sample(2100, "19")),
+            (
+                &["TS.RANGE", "temp", "-", "+"],
+                samples(&[(1000, "20"), (1200, "21"), (1500, "22.5"), (2100, "19")]),
+            ),
+            (
+                &["TS.RANGE", "temp", "1100", "2000", "COUNT", "1"],
+                samples(&[(1200, "21")]),
+            ),
+            (
+                &["TS.RANGE", "temp", "-", "+", "AGGREGATION", "avg", "1000"],
+                samples(&[(1000, "21.166666666666668"), (2000, "19")]),
+            ),
+            (
+                &["TS.RANGE", "temp", "-", "+", "AGGREGATION", "max", "1000"],
+                samples(&[(1000, "22.5"), (2000, "19")]),
+            ),
+            (&["TS.INCRBY", "hits", "2", "TIMESTAMP", "10"], RedisValue::Integer(10)),
+            (&["TS.INCRBY", "hits", "3", "TIMESTAMP", "10"], RedisValue::Integer(10)),
+            (&["TS.INCRBY", "hits", "1", "TIMESTAMP", "20"], RedisValue::Integer(20)),
+            (
+                &["TS.INCRBY", "hits", "1", "TIMESTAMP", "15"],
+                error("ERR TSDB: timestamp must be equal to or higher than the maximum existing timestamp"),
+            ),
+            (&["TS.RANGE", "hits", "-", "+"], samples(&[(10, "5"), (20, "6")])),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn retention_and_downsampling_rules() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["TS.CREATE", "raw", "RETENTION", "1000"], simple("OK")),
+            (&["TS.CREATE", "raw"], error("ERR TSDB: key already exists")),
+            (&["TS.CREATE", "per_second"], simple("OK")),
+            (
+                &[
+                    "TS.CREATERULE",
+                    "raw",
+                    "per_second",
+                    "AGGREGATION",
+                    "sum",
+                    "1000",
+                ],
+                simple("OK"),
+            ),
+            (&["TS.ADD", "raw", "100", "1"], RedisValue::Integer(100)),
+            (&["TS.ADD", "raw", "900", "2"], RedisValue::Integer(900)),
+            (&["TS.RANGE", "per_second", "-", "+"], samples(&[])),
+            // --- a sample past the bucket closes it, and pushes 100 out of retention
+            (&["TS.ADD", "raw", "1200", "4"], RedisValue::Integer(1200)),
+            (&["TS.RANGE", "per_second", "-", "+"], samples(&[(0, "3")])),
+            (
+                &["TS.RANGE", "raw", "-", "+"],
+                samples(&[(900, "2"), (1200, "4")]),
+            ),
+            (
+                &["TS.ADD", "raw", "150", "1"],
+                error("ERR TSDB: Timestamp is older than retention"),
+            ),
+            (&["TS.DELETERULE", "raw", "per_second"], simple("OK")),
+            (&["TS.ADD", "raw", "2500", "1"], RedisValue::Integer(2500)),
+            (&["TS.RANGE", "per_second", "-", "+"], samples(&[(0, "3")])),
+            (&["SET", "str", "bar"], simple("OK")),
+            (
+                &["TS.ADD", "str", "1", "1"],
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ),
+            (
+                &["GET", "raw"],
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ),
+        ],
+    )
+    .await;
+}
+
+#[test]
+fn series_are_saved_with_their_rules() {
+    let mut series = TimeSeries::new(60_000);
+    series.rules.push(Rule {
+        dest: "per_minute".into(),
+        aggregation: Aggregation::Avg,
+        bucket_duration: 60_000,
+        bucket_start: Some(0),
+    });
+    for i in 0..100 {
+        series
+            .add(Sample {
+                timestamp: i * 250,
+                value: i as f64 / 3.0,
+            })
+            .unwrap();
+    }
+    let mut contents = Db::new();
+    let raw = RedisValue::TimeSeries(Box::new(series));
+    contents.insert(Bytes::from("raw"), raw, None);
+    let per_minute = RedisValue::TimeSeries(Box::new(TimeSeries::new(0)));
+    contents.insert(Bytes::from("per_minute"), per_minute, None);
+    let dataset = Dataset::from([(0, contents)]);
+    let image = rdb::serialize(&dataset, None).unwrap();
+
+    let (loaded, _) = rdb::parse_sequential(&image, 0).unwrap();
+    assert_eq!(loaded, dataset);
+}
+
+#[tokio::test]
+async fn rules_count_towards_the_memory_of_their_series() {
+    let mut redis = Redis::open_in_memory();
+    let dataset = |info: RedisValue| -> usize {
+        let RedisValue::BulkString(info) = info else {
+            panic!("INFO replies with a bulk string");
+        };
+        std::str::from_utf8(&info)
+            .unwrap()
+            .lines()
+            .find_map(|line| line.strip_prefix("used_memory_dataset:"))
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    let dest: &'static str = "d".repeat(100).leak();
+    redis.execute(["TS.CREATE", "raw"]).await.unwrap();
+    redis.execute(["TS.CREATE", dest]).await.unwrap();
+    let before = dataset(redis.execute(["INFO", "memory"]).await.unwrap());
+
+    let rule = ["TS.CREATERULE", "raw", dest, "AGGREGATION", "sum", "1000"];
+    redis.execute(rule).await.unwrap();
+    let with_rule = dataset(redis.execute(["INFO", "memory"]).await.unwrap());
+    assert!(with_rule >= before + dest.len());
+
+    redis.execute(["TS.DELETERULE", "raw", dest]).await.unwrap();
+    assert_eq!(
+        dataset(redis.execute(["INFO", "memory"]).await.unwrap()),
+        before
+    );
+    redis.execute(["DEL", "raw", dest]).await.unwrap();
+    assert_eq!(dataset(redis.execute(["INFO", "memory"]).await.unwrap()), 0);
+}
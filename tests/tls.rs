@@ -0,0 +1,315 @@
+mod common;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::Bytes;
+use common::{simple, TestServer};
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair,
+};
+use redis_rust::{
+    server::{handler::RedisConnectionHandler, server::RedisServer},
+    Args, RedisValue,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+
+/// Self-signed certificate for 127.0.0.1 and its key, as PEM files under the system temp
+/// dir, unique to the test
+fn certificate(name: &str) -> (PathBuf, PathBuf) {
+    let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+    let dir = std::env::temp_dir();
+    let prefix = format!("redis-rust-{}-{}", name, std::process::id());
+    let cert_file = dir.join(format!("{}.crt", prefix));
+    let key_file = dir.join(format!("{}.key", prefix));
+    std::fs::write(&cert_file, cert.cert.pem()).unwrap();
+    std::fs::write(&key_file, cert.key_pair.serialize_pem()).unwrap();
+
+    (cert_file, key_file)
+}
+
+async fn start_tls(cert_file: &Path, key_file: &Path) -> TestServer {
+    TestServer::start(Args {
+        port: Some(0),
+        tls_port: Some(0),
+        tls_cert_file: Some(cert_file.to_string_lossy().into_owned()),
+        tls_key_file: Some(key_file.to_string_lossy().into_owned()),
+        ..Default::default()
+    })
+    .await
+}
+
+#[tokio::test]
+async fn clients_connect_over_tls() {
+    let (cert_file, key_file) = certificate("clients");
+    let server = start_tls(&cert_file, &key_file).await;
+
+    let mut roots = RootCertStore::empty();
+    let pem = std::fs::read(&cert_file).unwrap();
+    for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let addr = server.server.tls_addr().unwrap();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("127.0.0.1").unwrap(), stream)
+        .await
+        .unwrap();
+    let mut handler = RedisConnectionHandler::new(stream);
+    handler
+        .write(RedisValue::Array(vec![RedisValue::BulkString(
+            Bytes::from_static(b"PING"),
+        )]))
+        .await
+        .unwrap();
+    assert_eq!(
+        handler.read_and_parse().await.unwrap(),
+        Some(simple("PONG"))
+    );
+
+    // --- the plain port keeps working next to the TLS one
+    let mut client = server.client().await;
+    assert_eq!(client.command(["PING"]).await.unwrap(), simple("PONG"));
+
+    std::fs::remove_file(&cert_file).unwrap();
+    std::fs::remove_file(&key_file).unwrap();
+}
+
+#[tokio::test]
+async fn replicas_follow_their_master_over_tls() {
+    let (cert_file, key_file) = certificate("replication");
+    let master = start_tls(&cert_file, &key_file).await;
+    let tls_addr = master.server.tls_addr().unwrap();
+    let replica = TestServer::start(Args {
+        port: Some(0),
+        replicaof: Some(format!("{} {}", tls_addr.ip(), tls_addr.port())),
+        tls_replication: true,
+        tls_ca_cert_file: Some(cert_file.to_string_lossy().into_owned()),
+        ..Default::default()
+    })
+    .await;
+
+    master.client().await.set("foo", "bar").await.unwrap();
+    let mut client = replica.client().await;
+    let mut replicated = None;
+    for _ in 0..100 {
+        replicated = client.get("foo").await.unwrap();
+        if replicated.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(replicated.as_deref(), Some(&b"bar"[..]));
+
+    // --- a master whose certificate isn't trusted is refused
+    let (other_cert, other_key) = certificate("untrusted");
+    let untrusted = RedisServer::init(Args {
+        port: Some(0),
+        replicaof: Some(format!("{} {}", tls_addr.ip(), tls_addr.port())),
+        tls_replication: true,
+        tls_ca_cert_file: Some(other_cert.to_string_lossy().into_owned()),
+        ..Default::default()
+    })
+    .await;
+    assert!(untrusted.is_err());
+
+    for path in [cert_file, key_file, other_cert, other_key] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn tls_port_needs_a_certificate_and_key() {
+    let server = RedisServer::init(Args {
+        port: Some(0),
+        tls_port: Some(0),
+        ..Default::default()
+    })
+    .await;
+
+    assert!(server.is_err());
+}
+
+/// Client certificates signed by a CA of their own, the CA written as a PEM file under the
+/// system temp dir
+struct ClientCa {
+    cert: rcgen::Certificate,
+    key: KeyPair,
+    file: PathBuf,
+}
+impl ClientCa {
+    fn new(name: &str) -> Self {
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "clients CA");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        let file =
+            std::env::temp_dir().join(format!("redis-rust-{}-{}-ca.crt", name, std::process::id()));
+        std::fs::write(&file, cert.pem()).unwrap();
+
+        Self { cert, key, file }
+    }
+
+    /// Certificate and key of a client, named by `common_name` and `alt_names`
+    fn issue(
+        &self,
+        common_name: &str,
+        alt_names: &[&str],
+    ) -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
+        let alt_names = alt_names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        let mut params = CertificateParams::new(alt_names).unwrap();
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+        let key = PrivateKeyDer::try_from(key.serialize_der()).unwrap();
+
+        (vec![cert.der().clone()], key)
+    }
+}
+
+/// Connection to the TLS port trusting `cert_file`, presenting `identity` if any
+async fn connect_tls(
+    server: &TestServer,
+    cert_file: &Path,
+    identity: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+) -> std::io::Result<RedisConnectionHandler> {
+    let mut roots = RootCertStore::empty();
+    let pem = std::fs::read(cert_file).unwrap();
+    for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let config = match identity {
+        Some((chain, key)) => builder.with_client_auth_cert(chain, key).unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+    let stream = TcpStream::connect(server.server.tls_addr().unwrap()).await?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("127.0.0.1").unwrap(), stream)
+        .await?;
+
+    Ok(RedisConnectionHandler::new(stream))
+}
+
+async fn request(handler: &mut RedisConnectionHandler, cmd: &[&str]) -> Option<RedisValue> {
+    let cmd = cmd
+        .iter()
+        .map(|arg| RedisValue::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+        .collect();
+    handler.write(RedisValue::Array(cmd)).await.ok()?;
+
+    handler.read_and_parse().await.ok().flatten()
+}
+
+#[tokio::test]
+async fn client_certificates_name_the_acl_user() {
+    let (cert_file, key_file) = certificate("mtls");
+    let ca = ClientCa::new("mtls");
+    let server = TestServer::start(Args {
+        port: Some(0),
+        tls_port: Some(0),
+        tls_cert_file: Some(cert_file.to_string_lossy().into_owned()),
+        tls_key_file: Some(key_file.to_string_lossy().into_owned()),
+        tls_ca_cert_file: Some(ca.file.to_string_lossy().into_owned()),
+        tls_auth_clients: Some("yes".to_string()),
+        tls_auth_clients_user: Some("CN".to_string()),
+        requirepass: Some("secret".to_string()),
+        ..Default::default()
+    })
+    .await;
+    server
+        .server
+        .acl_users
+        .set_user(
+            "alice",
+            &[
+                "on".to_string(),
+                "nopass".to_string(),
+                "allcommands".to_string(),
+            ],
+        )
+        .unwrap();
+
+    // --- the certificate stands for AUTH
+    let mut alice = connect_tls(&server, &cert_file, Some(ca.issue("alice", &[])))
+        .await
+        .unwrap();
+    assert_eq!(request(&mut alice, &["PING"]).await, Some(simple("PONG")));
+    let Some(RedisValue::BulkString(info)) = request(&mut alice, &["CLIENT", "INFO"]).await else {
+        panic!("CLIENT INFO should reply a bulk string");
+    };
+    let info = String::from_utf8_lossy(&info);
+    assert!(info.contains(" user=alice tls-peer=alice "), "{}", info);
+
+    // --- names that aren't users still have to AUTH
+    let mut bob = connect_tls(&server, &cert_file, Some(ca.issue("bob", &["bob.example"])))
+        .await
+        .unwrap();
+    assert_eq!(
+        request(&mut bob, &["PING"]).await,
+        Some(RedisValue::SimpleError(Bytes::from_static(
+            b"NOAUTH Authentication required."
+        )))
+    );
+
+    // --- no certificate, no connection
+    let refused = match connect_tls(&server, &cert_file, None).await {
+        Ok(mut handler) => request(&mut handler, &["PING"]).await,
+        Err(_) => None,
+    };
+    assert_eq!(refused, None);
+
+    for path in [cert_file, key_file, ca.file] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn tls_auth_clients_needs_a_ca() {
+    let (cert_file, key_file) = certificate("mtls-no-ca");
+    let server = RedisServer::init(Args {
+        port: Some(0),
+        tls_port: Some(0),
+        tls_cert_file: Some(cert_file.to_string_lossy().into_owned()),
+        tls_key_file: Some(key_file.to_string_lossy().into_owned()),
+        tls_auth_clients: Some("optional".to_string()),
+        ..Default::default()
+    })
+    .await;
+
+    assert!(server.is_err());
+    for path in [cert_file, key_file] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
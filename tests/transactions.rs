@@ -0,0 +1,220 @@
+mod common;
+
+use common::{assert_replies, bulk, simple, TestServer};
+use redis_rust::{repl::ServerContext, RedisValue};
+
+fn error(message: &str) -> RedisValue {
+    RedisValue::SimpleError(message.to_string().into())
+}
+
+#[tokio::test]
+async fn exec_runs_the_queued_commands() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    assert_replies(
+        &mut client,
+        &[
+            (&["EXEC"], error("ERR EXEC without MULTI")),
+            (&["DISCARD"], error("ERR DISCARD without MULTI")),
+            (&["MULTI"], simple("OK")),
+            (&["MULTI"], error("ERR MULTI calls can not be nested")),
+            (&["SET", "a", "x"], simple("QUEUED")),
+            (&["INCR", "a"], simple("QUEUED")),
+            (&["SET", "b", "1"], simple("QUEUED")),
+            (&["INCR", "b"], simple("QUEUED")),
+            // --- nothing ran yet
+            (&["GET", "b"], simple("QUEUED")),
+            (
+                &["EXEC"],
+                RedisValue::Array(vec![
+                    simple("OK"),
+                    error("ERR value is not an integer or out of range"),
+                    simple("OK"),
+                    RedisValue::Integer(2),
+                    bulk("2"),
+                ]),
+            ),
+            (&["MULTI"], simple("OK")),
+            (&["SET", "b", "10"], simple("QUEUED")),
+            (&["DISCARD"], simple("OK")),
+            (&["GET", "b"], bulk("2")),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn a_command_refused_while_queueing_aborts_the_transaction() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+
+    // --- DELIFEQ is unknown without --enable-extensions
+    assert_replies(
+        &mut client,
+        &[
+            (&["MULTI"], simple("OK")),
+            (&["SET", "a", "1"], simple("QUEUED")),
+            (
+                &["DELIFEQ", "a", "1"],
+                error("ERR unknown command 'delifeq', with args beginning with: 'a' '1' "),
+            ),
+            (
+                &["EXEC"],
+                error("EXECABORT Transaction discarded because of previous errors."),
+            ),
+            (&["GET", "a"], RedisValue::NullBulkString),
+            // --- so does one with the wrong number of arguments
+            (&["MULTI"], simple("OK")),
+            (&["SET", "a", "1"], simple("QUEUED")),
+            (
+                &["GET"],
+                error("ERR wrong number of arguments for 'get' command"),
+            ),
+            (
+                &["EXEC"],
+                error("EXECABORT Transaction discarded because of previous errors."),
+            ),
+            (&["GET", "a"], RedisValue::NullBulkString),
+            // --- and one that doesn't exist at all
+            (&["MULTI"], simple("OK")),
+            (&["SET", "a", "1"], simple("QUEUED")),
+            (
+                &["NOSUCHCOMMAND", "a"],
+                error("ERR unknown command 'nosuchcommand', with args beginning with: 'a' "),
+            ),
+            (
+                &["EXEC"],
+                error("EXECABORT Transaction discarded because of previous errors."),
+            ),
+            (&["GET", "a"], RedisValue::NullBulkString),
+        ],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn other_clients_never_see_a_transaction_halfway() {
+    let server = TestServer::master().await;
+    let mut writer = server.client().await;
+    let mut reader = server.client().await;
+
+    let reads = tokio::spawn(async move {
+        let mut seen = vec![];
+        for _ in 0..200 {
+            seen.push(reader.get("counter").await.unwrap());
+        }
+        seen
+    });
+    writer.command(["MULTI"]).await.unwrap();
+    for _ in 0..100 {
+        writer.command(["INCR", "counter"]).await.unwrap();
+    }
+    writer.command(["EXEC"]).await.unwrap();
+
+    for value in reads.await.unwrap() {
+        assert!(
+            matches!(value.as_deref(), None | Some(b"100")),
+            "{:?}",
+            value
+        );
+    }
+}
+
+#[tokio::test]
+async fn transactions_reach_replicas_wrapped_in_multi_and_exec() {
+    let master = TestServer::master().await;
+    let mut client = master.client().await;
+
+    client.command(["MULTI"]).await.unwrap();
+    client.command(["SET", "a", "1"]).await.unwrap();
+    client.command(["GET", "a"]).await.unwrap();
+    client.command(["INCR", "a"]).await.unwrap();
+    client.command(["EXEC"]).await.unwrap();
+    // --- reads alone go nowhere
+    client.command(["MULTI"]).await.unwrap();
+    client.command(["GET", "a"]).await.unwrap();
+    client.command(["EXEC"]).await.unwrap();
+    // --- a SELECT inside switches the database of the writes after it, and stays
+    client.command(["MULTI"]).await.unwrap();
+    client.command(["SELECT", "2"]).await.unwrap();
+    client.command(["SET", "b", "2"]).await.unwrap();
+    client.command(["EXEC"]).await.unwrap();
+    assert_eq!(client.command(["GET", "b"]).await.unwrap(), bulk("2"));
+
+    let ServerContext::Master(ctx) = master.server.server_context.read().unwrap().clone() else {
+        panic!("Should be a master");
+    };
+    let stream = ctx.backlog.lock().unwrap().range_from(1).unwrap();
+    let expected = [
+        vec![bulk("SELECT"), bulk("0")],
+        vec![bulk("MULTI")],
+        vec![bulk("SET"), bulk("a"), bulk("1")],
+        vec![bulk("INCR"), bulk("a")],
+        vec![bulk("EXEC")],
+        vec![bulk("MULTI")],
+        vec![bulk("SELECT"), bulk("2")],
+        vec![bulk("SET"), bulk("b"), bulk("2")],
+        vec![bulk("EXEC")],
+    ]
+    .into_iter()
+    .flat_map(|command| RedisValue::Array(command).serialize().unwrap())
+    .collect::<Vec<_>>();
+    assert_eq!(&stream[..], &expected[..]);
+}
+
+#[tokio::test]
+async fn exec_fails_once_a_watched_key_changed() {
+    let server = TestServer::master().await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+
+    client.command(["WATCH", "k", "j"]).await.unwrap();
+    other.set("k", "theirs").await.unwrap();
+    assert_replies(
+        &mut client,
+        &[
+            (&["MULTI"], simple("OK")),
+            (
+                &["WATCH", "k"],
+                error("ERR WATCH inside MULTI is not allowed"),
+            ),
+            (&["SET", "k", "mine"], simple("QUEUED")),
+            (&["EXEC"], RedisValue::NullArray),
+            (&["GET", "k"], bulk("theirs")),
+            // --- EXEC forgets the watched keys, whatever came out of it
+            (&["MULTI"], simple("OK")),
+            (&["SET", "k", "mine"], simple("QUEUED")),
+            (&["EXEC"], RedisValue::Array(vec![simple("OK")])),
+            (&["WATCH", "k"], simple("OK")),
+            (&["UNWATCH"], simple("OK")),
+        ],
+    )
+    .await;
+    other.set("k", "theirs").await.unwrap();
+    assert_replies(
+        &mut client,
+        &[
+            (&["MULTI"], simple("OK")),
+            (&["GET", "k"], simple("QUEUED")),
+            (&["EXEC"], RedisValue::Array(vec![bulk("theirs")])),
+        ],
+    )
+    .await;
+
+    // --- a TTL set by someone else counts as a change too, unrelated keys don't
+    client.command(["WATCH", "k"]).await.unwrap();
+    other.set("unrelated", "x").await.unwrap();
+    client.command(["MULTI"]).await.unwrap();
+    assert_eq!(
+        client.command(["EXEC"]).await.unwrap(),
+        RedisValue::Array(vec![])
+    );
+    client.command(["WATCH", "k"]).await.unwrap();
+    other.command(["EXPIRE", "k", "100"]).await.unwrap();
+    client.command(["MULTI"]).await.unwrap();
+    assert_eq!(
+        client.command(["EXEC"]).await.unwrap(),
+        RedisValue::NullArray
+    );
+}